@@ -0,0 +1,29 @@
+/*!
+Self-extracting executable stub.
+
+Build the stub and bundle it with a PAK file encrypted with the key below:
+
+```text
+cargo build --release --example sfx_stub
+```
+
+Then create the self-extracting executable with `paks::sfx::build`.
+Running it extracts the files to the directory given as its first argument, or the current directory.
+*/
+
+use std::{env, process};
+
+// The key the bundled PAK file is encrypted with.
+const KEY: paks::Key = [13, 42];
+
+fn main() {
+	let dest = env::args().nth(1).unwrap_or_else(|| String::from("."));
+
+	match paks::sfx::extract(&dest, &KEY) {
+		Ok(count) => println!("Extracted {} files to {}", count, dest),
+		Err(err) => {
+			eprintln!("Error: {}", err);
+			process::exit(1);
+		},
+	}
+}
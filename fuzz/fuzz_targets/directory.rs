@@ -0,0 +1,19 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use dataview::Pod;
+
+// Fuzzes the directory functions with arbitrary descriptors.
+// The directory is authenticated by its MAC but may still be crafted by anyone holding the key.
+fuzz_target!(|data: &[u8]| {
+	let dir: Vec<paks::Descriptor> = data.chunks_exact(std::mem::size_of::<paks::Descriptor>()).map(|chunk| {
+		let mut desc = paks::Descriptor::default();
+		desc.as_bytes_mut().copy_from_slice(chunk);
+		desc
+	}).collect();
+
+	let _ = paks::dir::to_string(".", &dir, &paks::dir::Art::ASCII);
+	let _ = paks::dir::find_desc(&dir, b"a/b/c");
+	let _ = paks::dir::extents(&dir);
+	let _ = paks::dir::fsck(&dir, 0x100, 16, &mut String::new());
+	let _ = paks::Directory::from(dir).garbage(0x100);
+});
@@ -0,0 +1,8 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+const KEY: paks::Key = [0x1234, 0x5678];
+
+fuzz_target!(|data: &[u8]| {
+	let _ = paks::read(data, &KEY);
+});
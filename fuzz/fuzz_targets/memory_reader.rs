@@ -0,0 +1,14 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+const KEY: paks::Key = [0x1234, 0x5678];
+
+fuzz_target!(|data: &[u8]| {
+	if let Ok(reader) = paks::MemoryReader::from_bytes(data, &KEY) {
+		for desc in reader.as_ref() {
+			if desc.is_file() {
+				let _ = reader.read_data(desc, &KEY);
+			}
+		}
+	}
+});
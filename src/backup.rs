@@ -0,0 +1,219 @@
+use std::{collections::BTreeMap, convert::TryInto, io, path::Path};
+use crate::*;
+
+impl Directory {
+	/// Path of the hidden file holding the [manifest](Manifest) of a backup, see [`backup`].
+	pub const MANIFEST: &'static [u8] = b".manifest";
+}
+
+/// Content hashes of the files in a backup, see [`backup`].
+///
+/// The hashes are keyed by the key of the PAK file, they reveal nothing about the contents without the key.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Manifest {
+	files: BTreeMap<Vec<u8>, Block>,
+}
+
+impl Manifest {
+	/// Creates an empty manifest, backing up against it exports every file.
+	#[inline]
+	pub fn new() -> Manifest {
+		Manifest::default()
+	}
+
+	/// Reads the manifest stored in the backup PAK file at the given path.
+	pub fn open<P: ?Sized + AsRef<Path>>(path: &P, key: &Key) -> io::Result<Manifest> {
+		Manifest::read(&FileReader::open(path, key)?, key)
+	}
+
+	/// Reads the manifest stored in the backup PAK file.
+	///
+	/// # Errors
+	///
+	/// * [`io::ErrorKind::NotFound`]: The PAK file is not a backup.
+	/// * [`io::ErrorKind::InvalidData`]: The manifest is corrupted.
+	pub fn read(reader: &FileReader, key: &Key) -> io::Result<Manifest> {
+		let desc = match reader.find_file(Directory::MANIFEST) {
+			Some(desc) => desc,
+			None => Err(io::Error::new(io::ErrorKind::NotFound, "not a backup"))?,
+		};
+		match Manifest::decode(&reader.read_data(desc, key)?) {
+			Some(manifest) => Ok(manifest),
+			None => Err(io::Error::new(io::ErrorKind::InvalidData, "invalid manifest"))?,
+		}
+	}
+
+	/// Returns the number of files.
+	#[inline]
+	pub fn len(&self) -> usize {
+		self.files.len()
+	}
+
+	/// Returns `true` if the manifest has no files.
+	#[inline]
+	pub fn is_empty(&self) -> bool {
+		self.files.is_empty()
+	}
+
+	/// Returns the content hash of the file at the given path.
+	#[inline]
+	pub fn hash(&self, path: &[u8]) -> Option<&Block> {
+		self.files.get(path)
+	}
+
+	/// Iterates over the paths and content hashes of the files in order of their path.
+	pub fn iter(&self) -> impl Iterator<Item = (&[u8], &Block)> {
+		self.files.iter().map(|(path, hash)| (path.as_slice(), hash))
+	}
+
+	// Every file is encoded as its path length, path and content hash
+	fn encode(&self) -> Vec<u8> {
+		let mut data = Vec::new();
+		for (path, hash) in &self.files {
+			data.extend_from_slice(&(path.len() as u32).to_le_bytes());
+			data.extend_from_slice(path);
+			data.extend_from_slice(&hash[0].to_le_bytes());
+			data.extend_from_slice(&hash[1].to_le_bytes());
+		}
+		data
+	}
+
+	fn decode(mut data: &[u8]) -> Option<Manifest> {
+		let mut files = BTreeMap::new();
+		while !data.is_empty() {
+			let len = u32::from_le_bytes(data.get(..4)?.try_into().ok()?) as usize;
+			let path = data.get(4..4 + len)?;
+			let hash = data.get(4 + len..4 + len + 16)?;
+			let hash = [u64::from_le_bytes(hash[..8].try_into().ok()?), u64::from_le_bytes(hash[8..].try_into().ok()?)];
+			files.insert(path.to_vec(), hash);
+			data = &data[4 + len + 16..];
+		}
+		Some(Manifest { files })
+	}
+}
+
+// Keyed hash of the contents, the MAC of the contents as a public section
+// The nonce holds the length so contents differing only in their padding hash differently
+pub(crate) fn content_hash(data: &[u8], key: &Key) -> Block {
+	let mut blocks = vec![Block::default(); bytes2blocks(data.len() as u32, BLOCK_SIZE as u32) as usize];
+	as_bytes_mut(&mut blocks)[..data.len()].copy_from_slice(data);
+	let mut section = Section {
+		nonce: [Section::PUBLIC_NONCE, data.len() as u64],
+		..Section::default()
+	};
+	// Public sections are not encrypted, reusing the nonce only computes the same MAC
+	crypt::encrypt_section_with_nonce(&mut blocks, &mut section, key);
+	section.mac
+}
+
+// Hidden files describing the PAK file itself are not backed up
+fn is_backed_up(path: &[u8]) -> bool {
+	if path == Directory::MANIFEST || path == Directory::KEY_SLOTS || path.starts_with(Directory::TRASH) && path.get(Directory::TRASH.len()) == Some(&b'/') {
+		return false;
+	}
+	#[cfg(feature = "parity")]
+	if path == Directory::PARITY {
+		return false;
+	}
+	true
+}
+
+// Copies the file with its contents and metadata into the editor
+fn copy_file(editor: &mut FileEditor, path: &[u8], desc: &Descriptor, data: &[u8], meta: &MetaMap, key: &Key) -> io::Result<()> {
+	let mut edit_file = editor.edit_file(path);
+	edit_file.set_content(desc.content_type, data.len() as u32);
+	edit_file.allocate_data()?.write_data(data, key)?;
+	if !meta.is_empty() {
+		edit_file.set_meta(meta, key)?;
+	}
+	Ok(())
+}
+
+/// Exports the files changed since the previous backup.
+///
+/// Creates a backup PAK file at the dest path holding every file whose contents differ from the previous manifest, or every file without one.
+/// The files keep their content type and [metadata](MetaMap), changes to only the metadata of a file are not detected.
+/// The manifest of all the files in the PAK file, changed or not, is stored in the backup at [`Directory::MANIFEST`] and returned.
+///
+/// The backup is encrypted with the same key, use the manifest of the latest backup as the previous manifest of the next one.
+/// Restore the PAK file from the chain of backups with [`restore`].
+///
+/// # Errors
+///
+/// * [`io::ErrorKind::AlreadyExists`]: A file already exists at the dest path.
+/// * [`io::Error`]: An error encountered reading a file or writing the backup.
+///
+/// # Examples
+///
+/// ```no_run
+/// let ref key = paks::Key::default();
+/// let reader = paks::FileReader::open("saves.pak", key).unwrap();
+///
+/// // Full backup, then only the changes
+/// let manifest = paks::backup(&reader, key, "backup-0.pak", None).unwrap();
+/// let manifest = paks::backup(&reader, key, "backup-1.pak", Some(&manifest)).unwrap();
+///
+/// // Later, pick up where the last backup left off
+/// let manifest = paks::Manifest::open("backup-1.pak", key).unwrap();
+/// ```
+pub fn backup<P: ?Sized + AsRef<Path>>(reader: &FileReader, key: &Key, dest: &P, prev: Option<&Manifest>) -> io::Result<Manifest> {
+	let mut editor = FileEditor::create_new(dest, key)?;
+	let mut manifest = Manifest::new();
+	for (path, desc) in reader.files_under(b"").unwrap_or_default() {
+		if !is_backed_up(&path) {
+			continue;
+		}
+		let data = reader.read_data(&desc, key)?;
+		let hash = content_hash(&data, key);
+		if prev.and_then(|prev| prev.hash(&path)) != Some(&hash) {
+			let meta = reader.read_meta(&desc, key)?;
+			copy_file(&mut editor, &path, &desc, &data, &meta, key)?;
+		}
+		manifest.files.insert(path, hash);
+	}
+	editor.create_file(Directory::MANIFEST, &manifest.encode(), key)?;
+	editor.finish(key)?;
+	Ok(manifest)
+}
+
+/// Restores the PAK file from a chain of backups.
+///
+/// The backups are given oldest first, starting with a full backup followed by the incremental backups made with [`backup`].
+/// The restored PAK file at the dest path holds the files in the manifest of the last backup,
+/// every file is taken from the latest backup holding it and its content hash is verified against the manifest.
+///
+/// Returns the number of files restored.
+///
+/// # Errors
+///
+/// * [`io::ErrorKind::InvalidInput`]: No backups are given.
+/// * [`io::ErrorKind::NotFound`]: The last backup has no manifest.
+/// * [`io::ErrorKind::InvalidData`]: No backup holds the contents of a file in the manifest, a backup is missing from the chain.
+/// * [`io::ErrorKind::AlreadyExists`]: A file already exists at the dest path.
+/// * [`io::Error`]: An error encountered reading the backups or writing the restored PAK file.
+pub fn restore<P: AsRef<Path>, Q: ?Sized + AsRef<Path>>(backups: &[P], key: &Key, dest: &Q) -> io::Result<usize> {
+	let readers = backups.iter().map(|path| FileReader::open(path, key)).collect::<io::Result<Vec<_>>>()?;
+	let manifest = match readers.last() {
+		Some(reader) => Manifest::read(reader, key)?,
+		None => Err(io::ErrorKind::InvalidInput)?,
+	};
+
+	let mut editor = FileEditor::create_new(dest, key)?;
+	'files: for (path, hash) in manifest.iter() {
+		for reader in readers.iter().rev() {
+			let desc = match reader.find_file(path) {
+				Some(desc) => desc,
+				None => continue,
+			};
+			let data = reader.read_data(desc, key)?;
+			if content_hash(&data, key) == *hash {
+				let meta = reader.read_meta(desc, key)?;
+				copy_file(&mut editor, path, desc, &data, &meta, key)?;
+				continue 'files;
+			}
+		}
+		Err(io::Error::new(io::ErrorKind::InvalidData, format!("no backup of {}", String::from_utf8_lossy(path))))?;
+	}
+	editor.finish(key)?;
+	Ok(manifest.len())
+}
@@ -4,44 +4,89 @@ Implements PAKtool's command-line interface.
 
 #![allow(non_snake_case)]
 
-use std::{env, fs, io, io::prelude::*, path, str};
-use dataview::Pod;
+use std::{env, fs, io, io::prelude::*, io::IsTerminal, mem, path::Path, process, str};
+use paks::cli::{self, Error};
 
 fn main() {
 	let args: Vec<_> = env::args().collect();
 	let args: Vec<_> = args.iter().map(|s| &**s).collect();
 
-	match &args[1..] {
-		&[] => print!("{}", HELP_GENERAL),
-		&["help"] => print!("{}", HELP_GENERAL),
-		&[_] => eprintln!("Error invalid syntax, see `PAKtool help`."),
+	let result = match &args[1..] {
+		&[] => help(&[]),
+		&["help"] => help(&[]),
+		&["keygen", ref args @ ..] => keygen(args),
+		&[_] => Err(Error::InvalidInput(String::from("see `PAKtool help`"))),
 		&["help", cmd] => help(&[cmd]),
-		&[_, _] => eprintln!("Error invalid syntax, see `PAKtool help`."),
+		&["key", "split", ref args @ ..] => key_split(args),
+		&["key", "combine", ref args @ ..] => key_combine(args),
+		&["key", "fingerprint", ref args @ ..] => key_fingerprint(args),
+		&[_, _] => Err(Error::InvalidInput(String::from("see `PAKtool help`"))),
 		&[_pak, _key, "help", ref args @ ..] => help(args),
 		&[pak, key, "new", ref args @ ..] => new(pak, key, args),
+		&[pak, key, "overlay", ref args @ ..] => overlay(pak, key, args),
 		&[pak, key, "tree", ref args @ ..] => tree(pak, key, args),
+		&[pak, key, "ls", ref args @ ..] => ls(pak, key, args),
 		&[pak, key, "add", ref args @ ..] => add(pak, key, args),
 		&[pak, key, "copy", ref args @ ..] => copy(pak, key, args),
+		&[pak, key, "import", ref args @ ..] => import(pak, key, args),
 		&[pak, key, "link", ref args @ ..] => link(pak, key, args),
+		&[pak, key, "find", ref args @ ..] => find(pak, key, args),
+		&[pak, key, "grep-names", ref args @ ..] => grep_names(pak, key, args),
+		&[pak, key, "grep", ref args @ ..] => grep(pak, key, args),
 		&[pak, key, "cat", ref args @ ..] => cat(pak, key, args),
+		&[pak, key, "stat", ref args @ ..] => stat(pak, key, args),
 		&[pak, key, "rm", ref args @ ..] => rm(pak, key, args),
+		&[pak, key, "restore", ref args @ ..] => restore(pak, key, args),
 		&[pak, key, "mv", ref args @ ..] => mv(pak, key, args),
+		&[pak, key, "cp", ref args @ ..] => cp(pak, key, args),
+		&[pak, key, "tag", ref args @ ..] => tag(pak, key, args),
+		&[pak, key, "tags", ref args @ ..] => tags(pak, key, args),
+		&[pak, key, "log", ref args @ ..] => log(pak, key, args),
 		&[pak, key, "fsck", ref args @ ..] => fsck(pak, key, args),
+		&[pak, key, "verify", ref args @ ..] => verify(pak, key, args),
 		&[pak, key, "gc", ref args @ ..] => gc(pak, key, args),
+		&[pak, key, "dump", ref args @ ..] => dump(pak, key, args),
 		&[pak, key, "dbg", ref args @ ..] => dbg(pak, key, args),
-		&[_pak, _key, cmd, ..] => eprintln!("Error unknown subcommand: {}", cmd),
+		&[_pak, _key, cmd, ..] => Err(Error::InvalidInput(format!("unknown subcommand: {}", cmd))),
+	};
+
+	if let Err(err) = result {
+		eprintln!("Error[{}]: {}", error_kind(&err), err);
+		process::exit(exit_code(&err));
 	}
 }
 
-fn parse_key(s: &str) -> Option<paks::Key> {
-	match u128::from_str_radix(s, 16) {
-		Ok(val) => {
-			Some([(val & 0xffffffffffffffff) as u64, (val >> 64) as u64])
-		},
-		Err(err) => {
-			eprintln!("Error parsing key argument: {}", err);
-			None
-		},
+/// Returns the error kind printed as `Error[<kind>]: <message>`.
+fn error_kind(err: &Error) -> &'static str {
+	match err {
+		Error::InvalidInput(_) => "syntax",
+		Error::NotFound(_) => "not-found",
+		Error::BadKey(_) => "bad-key",
+		Error::Corrupt(_) => "corrupt",
+		Error::Io(_) => "io",
+	}
+}
+
+/// Returns the process exit code for the error.
+fn exit_code(err: &Error) -> i32 {
+	match err {
+		Error::InvalidInput(_) => 1,
+		Error::NotFound(_) => 2,
+		Error::BadKey(_) => 3,
+		Error::Corrupt(_) => 4,
+		Error::Io(_) => 5,
+	}
+}
+
+/// Keeps the first error while continuing with the remaining work.
+///
+/// Subsequent errors are printed to stderr immediately.
+fn report(result: &mut Result<(), Error>, err: Error) {
+	if result.is_ok() {
+		*result = Err(err);
+	}
+	else {
+		eprintln!("Error[{}]: {}", error_kind(&err), err);
 	}
 }
 
@@ -53,26 +98,55 @@ PAKtool - Copyright (c) 2020-2021 Casper <CasualX@users.noreply.github.com>
 USAGE
     PAKtool help <COMMAND>
     PAKtool <PAKFILE> <KEY> <COMMAND> [..]
+    PAKtool keygen [--hex] [--words] [--qr]
+    PAKtool key <split|combine|fingerprint> [..]
 
 ARGUMENTS
     PAKFILE  Path to a PAK archive to create or edit.
-    KEY      The 128-bit encryption key encoded in hex.
+    KEY      The 128-bit encryption key encoded in hex, see `PAKtool keygen`.
     COMMAND  The subcommand to invoke.
 
 Commands are:
     new      Creates a new empty PAK archive.
+    overlay  Creates a PAK archive with the files changed between two archives.
     tree     Displays the directory of the PAK archive.
-    add      Adds a file to the PAK archive.
+    ls       Lists every path in the PAK archive with its size.
+    add      Adds files to the PAK archive.
     copy     Copies files to the PAK archive.
+    import   Adds the files described by a CSV or TSV listing.
     link     Links the file from alternative paths.
+    find     Finds paths in the PAK archive matching the predicates.
+    grep-names
+             Finds paths in the PAK archive matching a regular expression.
+    grep     Searches the contents of files in the PAK archive.
     cat      Reads files from the PAK archive and writes to stdout.
+    stat     Displays the details of a descriptor.
     rm       Removes paths from the PAK archive.
+    restore  Restores removed paths from the trash.
     mv       Moves files in the PAK archive.
+    cp       Copies files in the PAK archive.
+    tag      Tags files in the PAK archive.
+    tags     Lists the tags or the files with a tag.
+    log      Displays the changelog or logs a change.
     fsck     File system consistency check.
+    verify   Verifies the PAK archive for use as a CI gate.
     gc       Collects garbage left behind by removed files.
+    dump     Prints a hex dump of a file's section.
+    keygen   Generates a random key.
+    key      Splits the key between custodians, combines it and prints its fingerprint.
 
     See `PAKtool help <COMMAND>` for more information on a specific command.
 
+EXIT STATUS
+    0        Success.
+    1        Invalid syntax.
+    2        File or path not found.
+    3        Malformed or incorrect key.
+    4        The PAK archive is corrupted.
+    5        Any other IO error.
+
+    Errors are written to stderr prefixed with `Error[<KIND>]: `.
+
 EXAMPLES
     PAKtool example.pak 0 new
     PAKtool example.pak 0 add a/b/example < tests/data/example.txt
@@ -82,22 +156,39 @@ EXAMPLES
     PAKtool example.pak 0 cat aa/bb/example
 ";
 
-fn help(args: &[&str]) {
+fn help(args: &[&str]) -> Result<(), Error> {
 	let text = match args.first().cloned() {
 		None => HELP_GENERAL,
 		Some("new") => HELP_NEW,
+		Some("overlay") => HELP_OVERLAY,
 		Some("tree") => HELP_TREE,
+		Some("ls") => HELP_LS,
 		Some("add") => HELP_ADD,
 		Some("copy") => HELP_COPY,
+		Some("import") => HELP_IMPORT,
 		Some("link") => HELP_LINK,
+		Some("find") => HELP_FIND,
+		Some("grep-names") => HELP_GREP_NAMES,
+		Some("grep") => HELP_GREP,
 		Some("cat") => HELP_CAT,
+		Some("stat") => HELP_STAT,
 		Some("rm") => HELP_RM,
+		Some("restore") => HELP_RESTORE,
 		Some("mv") => HELP_MV,
+		Some("cp") => HELP_CP,
+		Some("tag") => HELP_TAG,
+		Some("tags") => HELP_TAGS,
+		Some("log") => HELP_LOG,
 		Some("fsck") => HELP_FSCK,
+		Some("verify") => HELP_VERIFY,
 		Some("gc") => HELP_GC,
-		Some(cmd) => return eprintln!("Error unknown subcommand: {}", cmd),
+		Some("dump") => HELP_DUMP,
+		Some("keygen") => HELP_KEYGEN,
+		Some("key") => HELP_KEY,
+		Some(cmd) => return Err(Error::InvalidInput(format!("unknown subcommand: {}", cmd))),
 	};
 	print!("{}", text);
+	Ok(())
 }
 
 //----------------------------------------------------------------
@@ -113,15 +204,49 @@ DESCRIPTION
     If a file with this name already exists it will be overwritten.
 ";
 
-fn new(file: &str, key: &str, _args: &[&str]) {
-	let ref key = match parse_key(key) {
-		Some(key) => key,
-		None => return,
+fn new(file: &str, key: &str, _args: &[&str]) -> Result<(), Error> {
+	let ref key = cli::parse_key(key)?;
+
+	cli::new(file, key)
+}
+
+//----------------------------------------------------------------
+
+const HELP_OVERLAY: &str = "\
+PAKtool overlay
+
+NAME
+    PAKtool-overlay - Creates a PAK archive with the files changed between two archives.
+
+SYNOPSIS
+    PAKtool [..] overlay <BASE> <NEW>
+
+DESCRIPTION
+    Creates a PAK archive containing only the files which are new or changed in the
+    NEW archive compared to the BASE archive, meant to be read before the BASE archive.
+    Files removed from the BASE archive are not expressed by the overlay.
+    If a file with this name already exists it will be overwritten.
+
+ARGUMENTS
+    BASE     Path to the PAK archive the overlay is applied to.
+    NEW      Path to the PAK archive with the updated files.
+             Both archives are opened with the same KEY.
+
+EXAMPLES
+    PAKtool patch.pak 0 overlay game-1.0.pak game-1.1.pak
+";
+
+fn overlay(file: &str, key: &str, args: &[&str]) -> Result<(), Error> {
+	let ref key = cli::parse_key(key)?;
+
+	let (base, new) = match args {
+		&[base, new] => (base, new),
+		_ => return Err(Error::InvalidInput(String::from("expecting a base and new PAK archive"))),
 	};
 
-	if let Err(err) = paks::FileEditor::create_empty(file, key) {
-		eprintln!("Error writing {}: {}", file, err);
-	}
+	let count = cli::overlay(file, key, base, new)?;
+	eprintln!("{} new or changed files", count);
+	Ok(())
 }
 
 //----------------------------------------------------------------
@@ -133,31 +258,63 @@ NAME
     PAKtool-tree - Displays the directory of the PAK archive.
 
 SYNOPSIS
-    PAKtool [..] tree [-au] [PATH]
+    PAKtool [..] tree [-au] [-s] [-L DEPTH] [-P PATTERN] [--dirs-only] [--dot] [PATH]
 
 DESCRIPTION
     Displays the directory of the PAK archive.
 
+    The sizes of directories are the total size of the files they contain.
+    Directories are always displayed, the pattern only filters the files.
+
+    With --dot the directory is written as a Graphviz graph instead,
+    render it with eg. `dot -Tsvg`.
+
 ARGUMENTS
     -a       Display using ASCII art.
     -u       Display using UNICODE art.
+    -s       Display the size in bytes of every entry.
+    -L DEPTH
+             Descend at most DEPTH directories deep.
+    -P PATTERN
+             Display only the files whose name matches the glob PATTERN.
+    --dirs-only
+             Display only the directories.
+    --dot    Output a Graphviz graph in the dot language, -s sizes its nodes.
     PATH     Optional subdirectory to start at.
 ";
 
-fn tree(file: &str, key: &str, mut args: &[&str]) {
-	let ref key = match parse_key(key) {
-		Some(key) => key,
-		None => return,
-	};
+fn tree(file: &str, key: &str, mut args: &[&str]) -> Result<(), Error> {
+	let ref key = cli::parse_key(key)?;
 
 	let mut art = &paks::dir::Art::UNICODE;
+	let mut dot = false;
+	let mut options = paks::dir::FmtOptions::default();
 	while let Some(head) = args.first().cloned() {
 		if head.starts_with("-") {
 			args = &args[1..];
 			match head {
 				"-a" => art = &paks::dir::Art::ASCII,
 				"-u" => art = &paks::dir::Art::UNICODE,
-				_ => eprintln!("Unknown argument: {}", head),
+				"-s" => options.sizes = true,
+				"--dirs-only" => options.dirs_only = true,
+				"--dot" => dot = true,
+				"-L" | "-P" => {
+					let value = match args.first() {
+						Some(value) => *value,
+						None => return Err(Error::InvalidInput(format!("missing value for {}", head))),
+					};
+					args = &args[1..];
+					if head == "-P" {
+						options.pattern = Some(value.as_bytes());
+					}
+					else {
+						match value.parse() {
+							Ok(depth) => options.max_depth = Some(depth),
+							Err(_) => return Err(Error::InvalidInput(format!("invalid depth: {}", value))),
+						}
+					}
+				},
+				_ => return Err(Error::InvalidInput(format!("unknown argument: {}", head))),
 			}
 		}
 		else {
@@ -166,22 +323,52 @@ fn tree(file: &str, key: &str, mut args: &[&str]) {
 	}
 
 	let path = match args {
-		&[path] => Some(path),
-		[..] => None,
+		&[path] => path,
+		[..] => "",
 	};
 
-	let reader = match paks::FileReader::open(file, key) {
-		Ok(reader) => reader,
-		Err(err) => return eprintln!("Error opening {}: {}", file, err),
-	};
+	if dot {
+		print!("{}", cli::tree_dot(file, key, path.as_bytes(), options.sizes)?);
+	}
+	else {
+		println!("{}", cli::tree_string(file, key, path.as_bytes(), art, &options)?);
+	}
+	Ok(())
+}
 
-	let dir = match reader.get_children(path.unwrap_or("").as_bytes()) {
-		Some(path) => path,
-		None => return eprintln!("Error directory not found or is a file: {}", path.unwrap_or("")),
+//----------------------------------------------------------------
+
+const HELP_LS: &str = "\
+PAKtool ls
+
+NAME
+    PAKtool-ls - Lists every path in the PAK archive with its size.
+
+SYNOPSIS
+    PAKtool [..] ls [--csv]
+
+DESCRIPTION
+    Lists every descriptor in directory order with its type, size in bytes and path.
+
+ARGUMENTS
+    --csv    Prints the listing as CSV with the columns path, type, size, blocks and offset.
+             The section's blocks and offset are in units of the PAK archive's block size.
+
+EXAMPLES
+    PAKtool example.pak 0 ls --csv > inventory.csv
+";
+
+fn ls(file: &str, key: &str, args: &[&str]) -> Result<(), Error> {
+	let ref key = cli::parse_key(key)?;
+
+	let csv = match args {
+		&[] => false,
+		&["--csv"] => true,
+		_ => return Err(Error::InvalidInput(String::from("expecting only an optional --csv"))),
 	};
 
-	let root = path.unwrap_or(".");
-	println!("{}", paks::dir::Fmt::new(root, dir, art));
+	print!("{}", cli::ls(file, key, csv)?);
+	Ok(())
 }
 
 //----------------------------------------------------------------
@@ -190,48 +377,75 @@ const HELP_ADD: &str = "\
 PAKtool add
 
 NAME
-    PAKtool-add - Adds a file to the PAK archive.
+    PAKtool-add - Adds files to the PAK archive.
 
 SYNOPSIS
     PAKtool [..] add <PATH> < <CONTENT>
+    PAKtool [..] add [<PATH> <FILE>]..
+    PAKtool [..] add --from-list <MANIFEST>
 
 DESCRIPTION
-    Adds a file to the PAK archive.
+    Adds files to the PAK archive.
+    When only a single PATH is given the file data is read from stdin.
+    Otherwise the arguments are pairs of destination paths and local files.
+    All files are added in a single editing session.
+    If any error happens no changes are written.
 
 ARGUMENTS
     PATH     The destination path in the PAK archive to put the file.
     CONTENT  The file data to write in the PAK archive passed via stdin.
+    FILE     Path to a local file to read the file data from.
+    MANIFEST Path to a text file listing the files to add.
+             Each line contains a destination PATH followed by whitespace and a local FILE.
+             Empty lines and lines starting with `#` are ignored.
 ";
 
-fn add(file: &str, key: &str, args: &[&str]) {
-	let ref key = match parse_key(key) {
-		Some(key) => key,
-		None => return,
-	};
-
-	let path = match args {
-		[path] => path,
-		_ => return eprintln!("Error invalid path: expected exactly 1 argument."),
-	};
-
-	let mut data = Vec::new();
-	match io::stdin().read_to_end(&mut data) {
-		Ok(_) => (),
-		Err(err) => return eprintln!("Error reading stdin: {}", err),
-	};
-
-	let mut edit = match paks::FileEditor::open(file, key) {
-		Ok(edit) => edit,
-		Err(err) => return eprintln!("Error opening {}: {}", file, err),
-	};
-
-	if let Err(err) = edit.create_file(path.as_bytes(), &data, key) {
-		eprintln!("Error creating {}: {}", path, err);
+fn add(file: &str, key: &str, args: &[&str]) -> Result<(), Error> {
+	let ref key = cli::parse_key(key)?;
+
+	// Collect the destination paths and their local files
+	// A local file of None reads the data from stdin
+	let manifest;
+	let mut entries = Vec::new();
+	match args {
+		&[path] => entries.push((path, None)),
+		&["--from-list", list] => {
+			manifest = fs::read_to_string(list).map_err(|err| Error::read(&list, err))?;
+			for line in manifest.lines() {
+				let line = line.trim();
+				if line.is_empty() || line.starts_with("#") {
+					continue;
+				}
+				match line.find(char::is_whitespace) {
+					Some(i) => entries.push((&line[..i], Some(line[i..].trim_start()))),
+					None => return Err(Error::InvalidInput(format!("invalid manifest line: {}", line))),
+				}
+			}
+		},
+		_ if args.len() >= 2 && args.len().is_multiple_of(2) => {
+			for pair in args.chunks(2) {
+				entries.push((pair[0], Some(pair[1])));
+			}
+		},
+		_ => return Err(Error::InvalidInput(String::from("expected a path or pairs of paths and files"))),
 	}
 
-	if let Err(err) = edit.finish(key) {
-		eprintln!("Error writing {}: {}", file, err);
+	// Read the file contents
+	let mut contents = Vec::with_capacity(entries.len());
+	for &(_, local) in &entries {
+		let data = match local {
+			Some(local) => fs::read(local).map_err(|err| Error::read(&local, err))?,
+			None => {
+				let mut data = Vec::new();
+				io::stdin().read_to_end(&mut data).map_err(|err| Error::read(&"stdin", err))?;
+				data
+			},
+		};
+		contents.push(data);
 	}
+
+	let entries: Vec<(&[u8], &[u8])> = entries.iter().zip(&contents).map(|(&(path, _), data)| (path.as_bytes(), &data[..])).collect();
+	cli::add(file, key, &entries)
 }
 
 //----------------------------------------------------------------
@@ -247,67 +461,89 @@ SYNOPSIS
 
 DESCRIPTION
     Copies files to the PAK archive.
+    The files are read and encrypted in parallel using all available cores.
+    If any error happens no changes are written.
 ";
 
-fn copy(file: &str, key: &str, args: &[&str]) {
-	let ref key = match parse_key(key) {
-		Some(key) => key,
-		None => return,
-	};
+fn copy(file: &str, key: &str, args: &[&str]) -> Result<(), Error> {
+	let ref key = cli::parse_key(key)?;
 
-	if args.len() < 1 {
-		return eprintln!("Error invalid syntax: expecting one path followed by many filenames.");
-	}
-	else if args.len() == 1 {
-		return;
+	match args {
+		&[] => Err(Error::InvalidInput(String::from("expecting one path followed by many filenames"))),
+		&[_] => Ok(()),
+		&[base_path, ref files @ ..] => cli::copy(file, key, base_path.as_bytes(), files),
 	}
-	let base_path = args[0];
+}
 
-	let mut edit = match paks::FileEditor::open(file, key) {
-		Ok(edit) => edit,
-		Err(err) => return eprintln!("Error opening {}: {}", file, err),
-	};
+//----------------------------------------------------------------
 
-	let mut dest_path = String::from(base_path);
-	if !dest_path.ends_with("/") {
-		dest_path.push_str("/");
-	}
-	let dest_len = dest_path.len();
+const HELP_IMPORT: &str = "\
+PAKtool import
 
-	for src_path in &args[1..] {
-		let src_path = path::Path::new(src_path);
+NAME
+    PAKtool-import - Adds the files described by a CSV or TSV listing.
 
-		// Read the file contents
-		let data = match fs::read(src_path) {
-			Ok(data) => data,
-			Err(err) => {
-				eprintln!("Error reading {}: {}", src_path.display(), err);
-				continue;
-			},
-		};
+SYNOPSIS
+    PAKtool [..] import [--stripped] <LISTING> [BASE_DIR]
 
-		// Extract the file name
-		let file_name = match src_path.file_name().and_then(|s| s.to_str()) {
-			Some(file_name) => file_name,
-			None => {
-				eprintln!("Error invalid file name: {}", src_path.display());
-				continue;
-			},
-		};
+DESCRIPTION
+    Adds the files described by the listing to the PAK archive.
+    Each line has the columns `source,destination,content_type,flags`, separated by tabs or commas.
+    Empty lines, lines starting with `#` and a header line starting with `source` are ignored.
+    If any error happens no changes are written.
 
-		// Construct destination path
-		dest_path.truncate(dest_len);
-		dest_path.push_str(file_name);
+ARGUMENTS
+    LISTING   Path to the local CSV or TSV file.
+    BASE_DIR  Directory the source paths are relative to.
+              Defaults to the directory containing the listing.
+
+OPTIONS
+    --stripped
+              Replaces the paths by their 64-bit hashes, the paths are not stored in the PAK archive.
+              The PAK archive must be empty or already stripped.
+              Writes the mapping from the path hashes to the destination paths to stdout, one tab separated pair per line.
+
+COLUMNS
+    source        Path to the local file to add.
+    destination   Path of the file in the PAK archive.
+    content_type  Non-zero content type of the file, defaults to 1.
+    flags         Optional flags separated by spaces or `|`:
+                  optional  Skips the line if the source file does not exist.
+                  seal      Seals the destination's parent directory.
 
-		// Write its contents to the PAK archive
-		if let Err(err) = edit.create_file(dest_path.as_bytes(), &data, key) {
-			eprintln!("Error creating {}: {}", dest_path, err);
-		}
-	}
+EXAMPLES
+    PAKtool example.pak 0 import assets.csv
+    PAKtool example.pak 0 import --stripped assets.csv > assets.map
+";
 
-	if let Err(err) = edit.finish(key) {
-		eprintln!("Error writing {}: {}", file, err);
+fn import(file: &str, key: &str, args: &[&str]) -> Result<(), Error> {
+	let ref key = cli::parse_key(key)?;
+
+	let (stripped, args) = match args {
+		&["--stripped", ref args @ ..] => (true, args),
+		_ => (false, args),
+	};
+	let (listing_path, base_dir) = match args {
+		&[listing] => (listing, None),
+		&[listing, base_dir] => (listing, Some(base_dir)),
+		_ => return Err(Error::InvalidInput(String::from("expecting a listing and optional base directory"))),
+	};
+	let listing = fs::read_to_string(listing_path).map_err(|err| Error::read(&listing_path, err))?;
+	let base_dir = match base_dir {
+		Some(base_dir) => Path::new(base_dir),
+		None => Path::new(listing_path).parent().unwrap_or(Path::new("")),
+	};
+
+	if stripped {
+		let mapping = cli::import_stripped(file, key, &listing, base_dir)?;
+		print!("{}", mapping);
+		eprintln!("imported {} files", mapping.lines().count());
+	}
+	else {
+		let imported = cli::import_listing(file, key, &listing, base_dir)?;
+		eprintln!("imported {} files", imported);
 	}
+	Ok(())
 }
 
 //----------------------------------------------------------------
@@ -330,35 +566,172 @@ ARGUMENTS
     DEST     One or more destination paths where to link the SRC.
 ";
 
-fn link(file: &str, key: &str, args: &[&str]) {
-	let ref key = match parse_key(key) {
-		Some(key) => key,
-		None => return,
-	};
+fn link(file: &str, key: &str, args: &[&str]) -> Result<(), Error> {
+	let ref key = cli::parse_key(key)?;
 
 	let (src_path, dest_paths) = match args {
 		&[src, ref dest @ ..] => (src, dest),
-		_ => return eprintln!("Error invalid syntax: expecting a source file"),
+		_ => return Err(Error::InvalidInput(String::from("expecting a source file"))),
 	};
 
-	let mut edit = match paks::FileEditor::open(file, key) {
-		Ok(edit) => edit,
-		Err(err) => return eprintln!("Error opening {}: {}", file, err),
-	};
+	let dest_paths: Vec<&[u8]> = dest_paths.iter().map(|path| path.as_bytes()).collect();
+	cli::link(file, key, src_path.as_bytes(), &dest_paths)
+}
 
-	let src_desc = match edit.find_desc(src_path.as_bytes()) {
-		Some(desc) if desc.is_dir() => return eprintln!("Error file not found: {}", src_path),
-		Some(desc) => *desc,
-		None => return eprintln!("Error file not found: {}", src_path),
-	};
+//----------------------------------------------------------------
 
-	for &dest_path in dest_paths {
-		edit.create_link(dest_path.as_bytes(), &src_desc);
-	}
+const HELP_FIND: &str = "\
+PAKtool find
 
-	if let Err(err) = edit.finish(key) {
-		eprintln!("Error writing {}: {}", file, err);
+NAME
+    PAKtool-find - Finds paths in the PAK archive matching the predicates.
+
+SYNOPSIS
+    PAKtool [..] find [PATH] [--files|--dirs] [--name GLOB] [--type N[,N]..] [--size +N|-N]
+
+DESCRIPTION
+    Prints the paths of the descriptors matching all the predicates, one per line.
+    The paths of directories end with a `/`.
+
+ARGUMENTS
+    PATH         Only descriptors under the directory at this path are matched.
+    --files      Matches only files.
+    --dirs       Matches only directories.
+    --name GLOB  Matches names against the glob pattern.
+                 The wildcard `*` matches any sequence and `?` matches any single character.
+    --type N,..  Matches any of the content types, directories have content type 0.
+    --size +N    Matches files larger than N bytes.
+    --size -N    Matches files smaller than N bytes.
+
+EXAMPLES
+    PAKtool example.pak 0 find textures --name *.png --size +65536
+";
+
+fn find(file: &str, key: &str, args: &[&str]) -> Result<(), Error> {
+	let ref key = cli::parse_key(key)?;
+
+	let mut path = None;
+	let mut types = Vec::new();
+	let mut filter = paks::Filter::Any;
+	let mut args = args.iter().cloned();
+	while let Some(arg) = args.next() {
+		let predicate = match arg {
+			"--files" => paks::Filter::Files,
+			"--dirs" => paks::Filter::Dirs,
+			"--name" => match args.next() {
+				Some(pattern) => paks::Filter::Name(pattern.as_bytes()),
+				None => return Err(Error::InvalidInput(String::from("expecting a glob pattern"))),
+			},
+			"--type" => {
+				let arg = args.next().unwrap_or("");
+				match arg.split(',').map(parse_u32).collect::<Option<Vec<u32>>>() {
+					Some(list) => types = list,
+					None => return Err(Error::InvalidInput(format!("invalid content types: {}", arg))),
+				}
+				continue;
+			},
+			"--size" => {
+				let arg = args.next().unwrap_or("");
+				match (arg.get(..1), arg.get(1..).and_then(parse_u32)) {
+					(Some("+"), Some(size)) => paks::Filter::LargerThan(size),
+					(Some("-"), Some(size)) => paks::Filter::SmallerThan(size),
+					_ => return Err(Error::InvalidInput(format!("invalid size: {}", arg))),
+				}
+			},
+			_ if path.is_none() && !arg.starts_with("-") => {
+				path = Some(arg);
+				paks::Filter::Under(arg.as_bytes())
+			},
+			_ => return Err(Error::InvalidInput(format!("unknown argument: {}", arg))),
+		};
+		filter = filter.and(predicate);
+	}
+	if !types.is_empty() {
+		filter = filter.and(paks::Filter::ContentType(&types));
 	}
+
+	print!("{}", cli::find(file, key, &filter)?);
+	Ok(())
+}
+
+//----------------------------------------------------------------
+
+const HELP_GREP_NAMES: &str = "\
+PAKtool grep-names
+
+NAME
+    PAKtool-grep-names - Finds paths in the PAK archive matching a regular expression.
+
+SYNOPSIS
+    PAKtool [..] grep-names <PATTERN>
+
+DESCRIPTION
+    Prints the paths of the descriptors whose full path matches the regular expression, one per line.
+    The paths of directories end with a `/`.
+
+    Requires PAKtool to be built with the `regex` feature.
+
+ARGUMENTS
+    PATTERN  The regular expression matched against the full paths.
+             Use `^` and `$` to anchor the match to the start and end of the path.
+
+EXAMPLES
+    PAKtool example.pak 0 grep-names \"^levels/level[0-9]+/.*\\.bsp$\"
+";
+
+#[cfg(feature = "regex")]
+fn grep_names(file: &str, key: &str, args: &[&str]) -> Result<(), Error> {
+	let ref key = cli::parse_key(key)?;
+
+	let pattern = match args {
+		&[pattern] => pattern,
+		_ => return Err(Error::InvalidInput(String::from("expecting a single pattern"))),
+	};
+
+	print!("{}", cli::find_regex(file, key, pattern)?);
+	Ok(())
+}
+
+#[cfg(not(feature = "regex"))]
+fn grep_names(_file: &str, _key: &str, _args: &[&str]) -> Result<(), Error> {
+	Err(Error::InvalidInput(String::from("grep-names requires PAKtool to be built with the `regex` feature")))
+}
+
+//----------------------------------------------------------------
+
+const HELP_GREP: &str = "\
+PAKtool grep
+
+NAME
+    PAKtool-grep - Searches the contents of files in the PAK archive.
+
+SYNOPSIS
+    PAKtool [..] grep <PATTERN> [PATH]
+
+DESCRIPTION
+    Decrypts the files one at a time and searches their contents for the pattern.
+    Prints the path and byte offset of every match as `path:offset`, one per line.
+
+ARGUMENTS
+    PATTERN  The text to search for, matched exactly.
+    PATH     Only the file or files under the directory at this path are searched.
+             Searches every file if omitted.
+
+EXAMPLES
+    PAKtool example.pak 0 grep TODO scripts
+";
+
+fn grep(file: &str, key: &str, args: &[&str]) -> Result<(), Error> {
+	let ref key = cli::parse_key(key)?;
+
+	let (pattern, path) = match args {
+		&[pattern] => (pattern, ""),
+		&[pattern, path] => (pattern, path),
+		_ => return Err(Error::InvalidInput(String::from("expecting a pattern and optional path"))),
+	};
+
+	print!("{}", cli::grep(file, key, pattern.as_bytes(), path.as_bytes())?);
+	Ok(())
 }
 
 //----------------------------------------------------------------
@@ -370,43 +743,100 @@ NAME
     PAKtool-cat - Reads files from the PAK archive and writes to stdout.
 
 SYNOPSIS
-    PAKtool [..] cat [PATH]..
+    PAKtool [..] cat [--binary|--hex] [PATH]..
 
 DESCRIPTION
     Reads files from the PAK archive and writes to stdout.
     Each file is read in the order specified and written to stdout one after another.
     If an error happens it is printed and continues to write the rest of the files.
 
+    Files containing NUL bytes are considered binary and are not written to a terminal
+    unless `--binary` is specified. Empty files are reported on stderr.
+
 ARGUMENTS
+    --binary Writes binary files to stdout even if it is a terminal.
+    --hex    Writes a hex and ASCII dump of the files instead.
     PATH     Path to the file in the PAK archive to output.
 ";
 
-fn cat(file: &str, key: &str, args: &[&str]) {
-	let ref key = match parse_key(key) {
-		Some(key) => key,
-		None => return,
-	};
+fn cat(file: &str, key: &str, args: &[&str]) -> Result<(), Error> {
+	let ref key = cli::parse_key(key)?;
 
-	let reader = match paks::FileReader::open(file, key) {
-		Ok(reader) => reader,
-		Err(err) => return eprintln!("Error opening {}: {}", file, err),
+	let (binary, hex, paths) = match args {
+		&["--binary", ref paths @ ..] => (true, false, paths),
+		&["--hex", ref paths @ ..] => (false, true, paths),
+		paths => (false, false, paths),
 	};
+	let terminal = io::stdout().is_terminal();
 
-	for &path in args {
-		match reader.find_file(path.as_bytes()) {
-			Some(file_desc) => {
-				match reader.read_data(&file_desc, key) {
-					Ok(data) => {
-						if let Err(err) = io::stdout().write_all(&data) {
-							eprintln!("Error writing {} to stdout: {}", path, err);
-						}
-					},
-					Err(err) => eprintln!("Error reading {}: {}", path, err),
-				}
+	let mut result = Ok(());
+	for &path in paths {
+		let data = match cli::cat(file, key, path.as_bytes()) {
+			Ok(data) => data,
+			Err(err) => {
+				report(&mut result, err);
+				continue;
 			},
-			None => eprintln!("Error file not found: {}", path),
+		};
+		if data.is_empty() {
+			eprintln!("{}: empty file", path);
+			continue;
+		}
+		let written = if hex {
+			io::stdout().write_all(cli::hex_dump(&data).as_bytes())
+		}
+		else if terminal && !binary && cli::is_binary(&data) {
+			report(&mut result, Error::InvalidInput(format!("{} is a binary file, use --binary or --hex to write it to the terminal", path)));
+			continue;
+		}
+		else {
+			io::stdout().write_all(&data)
+		};
+		if let Err(err) = written {
+			report(&mut result, Error::write(&"stdout", err));
 		}
 	}
+	result
+}
+
+//----------------------------------------------------------------
+
+const HELP_STAT: &str = "\
+PAKtool stat
+
+NAME
+    PAKtool-stat - Displays the details of a descriptor.
+
+SYNOPSIS
+    PAKtool [..] stat [PATH] [--json]
+
+DESCRIPTION
+    Displays the type, size, section location, nonce and MAC of the descriptor,
+    its flags, the number of links sharing its file data, whether it has a meta section
+    and the other paths linking to the same file data.
+
+    Without a path displays the block size, number of entries and high mark of the
+    PAK archive and the provenance of its build: the tool, version, commit and time
+    it was stamped with.
+
+ARGUMENTS
+    PATH     Path to the file or directory in the PAK archive.
+    --json   Prints the details as a single JSON object.
+";
+
+fn stat(file: &str, key: &str, args: &[&str]) -> Result<(), Error> {
+	let ref key = cli::parse_key(key)?;
+
+	let (path, json) = match args {
+		&[] => ("", false),
+		&["--json"] => ("", true),
+		&[path] => (path, false),
+		&[path, "--json"] | &["--json", path] => (path, true),
+		_ => return Err(Error::InvalidInput(String::from("expecting a path and optional --json"))),
+	};
+
+	print!("{}", cli::stat(file, key, path.as_bytes(), json)?);
+	Ok(())
 }
 
 //----------------------------------------------------------------
@@ -418,35 +848,57 @@ NAME
     PAKtool-rm - Removes files from the PAK archive.
 
 SYNOPSIS
-    PAKtool [..] rm [PATH]..
+    PAKtool [..] rm [--soft] [PATH]..
 
 DESCRIPTION
     Removes files from the PAK archive.
+    If any path is not found no changes are written.
 
 ARGUMENTS
+    --soft   Moves the paths to the trash instead, see `PAKtool help restore`.
+             The trash is emptied by garbage collection.
     PATH     Path to the file in the PAK archive to remove.
 ";
 
-fn rm(file: &str, key: &str, args: &[&str]) {
-	let ref key = match parse_key(key) {
-		Some(key) => key,
-		None => return,
-	};
+fn rm(file: &str, key: &str, args: &[&str]) -> Result<(), Error> {
+	let ref key = cli::parse_key(key)?;
 
-	let mut edit = match paks::FileEditor::open(file, key) {
-		Ok(edit) => edit,
-		Err(err) => return eprintln!("Error opening {}: {}", file, err),
-	};
-
-	for &path in args {
-		if edit.remove(path.as_bytes()).is_none() {
-			eprintln!("Unable to remove {}: file not found?", path);
-		}
+	match args {
+		&["--soft", ref args @ ..] => {
+			let paths: Vec<&[u8]> = args.iter().map(|path| path.as_bytes()).collect();
+			cli::trash(file, key, &paths)
+		},
+		_ => {
+			let paths: Vec<&[u8]> = args.iter().map(|path| path.as_bytes()).collect();
+			cli::rm(file, key, &paths)
+		},
 	}
+}
 
-	if let Err(err) = edit.finish(key) {
-		eprintln!("Error writing {}: {}", file, err);
-	}
+//----------------------------------------------------------------
+
+const HELP_RESTORE: &str = "\
+PAKtool restore
+
+NAME
+    PAKtool-restore - Restores removed paths from the trash.
+
+SYNOPSIS
+    PAKtool [..] restore [PATH]..
+
+DESCRIPTION
+    Restores paths removed with `PAKtool rm --soft` from the trash.
+    If any path is not in the trash or already exists no changes are written.
+
+ARGUMENTS
+    PATH     Path in the PAK archive to restore.
+";
+
+fn restore(file: &str, key: &str, args: &[&str]) -> Result<(), Error> {
+	let ref key = cli::parse_key(key)?;
+
+	let paths: Vec<&[u8]> = args.iter().map(|path| path.as_bytes()).collect();
+	cli::restore(file, key, &paths)
 }
 
 //----------------------------------------------------------------
@@ -468,27 +920,157 @@ ARGUMENTS
     DEST     Path to the destination file.
 ";
 
-fn mv(file: &str, key: &str, args: &[&str]) {
-	let ref key = match parse_key(key) {
-		Some(key) => key,
-		None => return,
-	};
+fn mv(file: &str, key: &str, args: &[&str]) -> Result<(), Error> {
+	let ref key = cli::parse_key(key)?;
 
 	let (src_path, dest_path) = match args {
 		&[src_path, dest_path] => (src_path, dest_path),
-		[..] => return eprintln!("Error invalid syntax: expecting exactly two path arguments."),
+		[..] => return Err(Error::InvalidInput(String::from("expecting exactly two path arguments"))),
 	};
 
-	let mut edit = match paks::FileEditor::open(file, key) {
-		Ok(edit) => edit,
-		Err(err) => return eprintln!("Error opening {}: {}", file, err),
+	cli::mv(file, key, src_path.as_bytes(), dest_path.as_bytes())
+}
+
+//----------------------------------------------------------------
+
+const HELP_CP: &str = "\
+PAKtool cp
+
+NAME
+    PAKtool-cp - Copies files in the PAK archive.
+
+SYNOPSIS
+    PAKtool [..] cp <SRC> <DEST> [--link|--deep]
+
+DESCRIPTION
+    Copies the source file to the destination path.
+    Returns file not found error if the SRC path does not exist.
+
+ARGUMENTS
+    SRC      Path to the source file to copy.
+    DEST     Destination path of the copy, replaces any file at the path.
+    --link   The copy links to the file data of SRC, this is the default.
+    --deep   The file data and metadata are re-encrypted into an independent copy.
+";
+
+fn cp(file: &str, key: &str, args: &[&str]) -> Result<(), Error> {
+	let ref key = cli::parse_key(key)?;
+
+	let (src_path, dest_path, deep) = match args {
+		&[src, dest] | &[src, dest, "--link"] => (src, dest, false),
+		&[src, dest, "--deep"] => (src, dest, true),
+		_ => return Err(Error::InvalidInput(String::from("expecting a source and destination path"))),
 	};
 
-	edit.move_file(src_path.as_bytes(), dest_path.as_bytes());
+	cli::cp(file, key, src_path.as_bytes(), dest_path.as_bytes(), deep)
+}
+
+//----------------------------------------------------------------
+
+const HELP_TAG: &str = "\
+PAKtool tag
+
+NAME
+    PAKtool-tag - Tags files in the PAK archive.
+
+SYNOPSIS
+    PAKtool [..] tag [--remove] <TAG> [PATH]..
 
-	if let Err(err) = edit.finish(key) {
-		eprintln!("Error writing {}: {}", file, err);
+DESCRIPTION
+    Adds the tag to the files in the PAK archive.
+    Tags group files independently of the directories they are in and are stored in the metadata of the files.
+    If any path is not found no changes are written.
+
+ARGUMENTS
+    --remove Removes the tag from the files instead.
+    TAG      Name of the tag.
+    PATH     Path to the file in the PAK archive to tag.
+";
+
+fn tag(file: &str, key: &str, args: &[&str]) -> Result<(), Error> {
+	let ref key = cli::parse_key(key)?;
+
+	let (remove, tag, paths) = match args {
+		&["--remove", tag, ref paths @ ..] => (true, tag, paths),
+		&[tag, ref paths @ ..] => (false, tag, paths),
+		[] => return Err(Error::InvalidInput(String::from("expecting a tag and paths"))),
+	};
+
+	let paths: Vec<&[u8]> = paths.iter().map(|path| path.as_bytes()).collect();
+	cli::tag(file, key, tag, &paths, remove)?;
+	Ok(())
+}
+
+//----------------------------------------------------------------
+
+const HELP_TAGS: &str = "\
+PAKtool tags
+
+NAME
+    PAKtool-tags - Lists the tags or the files with a tag.
+
+SYNOPSIS
+    PAKtool [..] tags [TAG]
+
+DESCRIPTION
+    Without a tag lists every tag in the PAK archive with its number of files.
+    With a tag lists the paths of the files with the tag.
+
+ARGUMENTS
+    TAG      Name of the tag.
+";
+
+fn tags(file: &str, key: &str, args: &[&str]) -> Result<(), Error> {
+	let ref key = cli::parse_key(key)?;
+
+	let tag = match args {
+		&[] => None,
+		&[tag] => Some(tag),
+		_ => return Err(Error::InvalidInput(String::from("expecting at most one tag"))),
+	};
+
+	print!("{}", cli::tags(file, key, tag)?);
+	Ok(())
+}
+
+//----------------------------------------------------------------
+
+const HELP_LOG: &str = "\
+PAKtool log
+
+NAME
+    PAKtool-log - Displays the changelog or logs a change.
+
+SYNOPSIS
+    PAKtool [..] log
+    PAKtool [..] log --add <AUTHOR> <MESSAGE>
+
+DESCRIPTION
+    Without arguments lists the changelog of the PAK archive, oldest first, as the
+    date and time in UTC followed by the author and the message.
+    With --add appends an entry timestamped now to the changelog.
+
+    The changelog is the hidden file `.pak/changelog`, encrypted and authenticated
+    like any other file in the PAK archive.
+
+ARGUMENTS
+    AUTHOR   Who made the change.
+    MESSAGE  Description of the change.
+
+EXAMPLES
+    PAKtool example.pak 0 log --add alice \"Update the hero texture\"
+    PAKtool example.pak 0 log
+";
+
+fn log(file: &str, key: &str, args: &[&str]) -> Result<(), Error> {
+	let ref key = cli::parse_key(key)?;
+
+	match args {
+		&[] => print!("{}", cli::log(file, key)?),
+		&["--add", author, message] => cli::log_change(file, key, author, message)?,
+		_ => return Err(Error::InvalidInput(String::from("expecting no arguments or --add with an author and message"))),
 	}
+	Ok(())
 }
 
 //----------------------------------------------------------------
@@ -506,26 +1088,52 @@ DESCRIPTION
     Checks the PAK file's directory for errors.
 ";
 
-fn fsck(file: &str, key: &str, _args: &[&str]) {
-	let ref key = match parse_key(key) {
-		Some(key) => key,
-		None => return,
-	};
+fn fsck(file: &str, key: &str, _args: &[&str]) -> Result<(), Error> {
+	let ref key = cli::parse_key(key)?;
 
-	let reader = match paks::FileReader::open(file, key) {
-		Ok(reader) => reader,
-		Err(err) => return eprintln!("Error opening {}: {}", file, err),
-	};
+	let log = cli::fsck(file, key)?;
+	print!("No errors found!\n{}", log);
+	Ok(())
+}
+
+//----------------------------------------------------------------
+
+const HELP_VERIFY: &str = "\
+PAKtool verify
 
-	let mut log = String::new();
-	let msg = if !reader.fsck(reader.high_mark(), &mut log) {
-		"PAK file contains errors:\n"
+NAME
+    PAKtool-verify - Verifies the PAK archive for use as a CI gate.
+
+SYNOPSIS
+    PAKtool [..] verify [--checksums] [--deep]
+
+DESCRIPTION
+    Checks the directory for errors and audits the nonces for reuse, then prints
+    a summary of every check followed by PASS or FAIL.
+
+    The exit status is 0 if the PAK archive passes verification, 3 if the key is
+    incorrect and 4 if it fails verification or its header is corrupted.
+
+ARGUMENTS
+    --checksums  Verifies the MAC of every file section and meta section.
+    --deep       Verifies the checksums, decodes the metadata of every file, resolves
+                 the dependencies and decodes the backup manifest if present.
+";
+
+fn verify(file: &str, key: &str, args: &[&str]) -> Result<(), Error> {
+	let ref key = cli::parse_key(key)?;
+
+	let (mut checksums, mut deep) = (false, false);
+	for &arg in args {
+		match arg {
+			"--checksums" => checksums = true,
+			"--deep" => deep = true,
+			_ => return Err(Error::InvalidInput(format!("unknown argument: {}", arg))),
+		}
 	}
-	else {
-		"No errors found!\n"
-	};
 
-	print!("{}{}", msg, log);
+	print!("{}", cli::verify(file, key, checksums, deep)?);
+	Ok(())
 }
 
 //----------------------------------------------------------------
@@ -537,55 +1145,269 @@ NAME
     PAKtool-gc - Collects garbage left behind by removed files.
 
 SYNOPSIS
-    PAKtool [..] gc
+    PAKtool [..] gc [--dry-run|--plan|--in-place|--dedup]
 
 DESCRIPTION
     Collects garbage left behind by removed files.
     When files are removed their data is left behind.
     These files are unreadable because their cryptographic nonce is forgotten.
+    The trash is emptied first.
+
+    By default the PAK archive is rewritten in memory and saved as a fresh copy.
+
+ARGUMENTS
+    --dry-run    Reports the number of reclaimable blocks without changing the PAK archive.
+    --plan       Lists the file data moved by --in-place and the bytes it rewrites and
+                 reclaims without changing the PAK archive.
+    --in-place   Moves the file data inside the PAK archive without loading it entirely in memory.
+                 If interrupted the PAK archive may be corrupted.
+    --dedup      Decrypts every file and turns files with the same contents into links
+                 to a single copy of the file data before collecting garbage.
 ";
 
-fn gc(file: &str, key: &str, _args: &[&str]) {
-	let ref key = match parse_key(key) {
-		Some(key) => key,
-		None => return,
-	};
+fn gc(file: &str, key: &str, args: &[&str]) -> Result<(), Error> {
+	let ref key = cli::parse_key(key)?;
 
-	let f = match fs::File::open(file) {
-		Ok(f) => f,
-		Err(err) => return eprintln!("Error opening {}: {}", file, err),
-	};
+	match args {
+		&[] => cli::gc(file, key),
+		&["--dry-run"] => {
+			let garbage = cli::gc_dry_run(file, key)?;
+			println!("{} blocks ({} bytes) reclaimable", garbage, garbage as u64 * mem::size_of::<paks::Block>() as u64);
+			Ok(())
+		},
+		&["--plan"] => {
+			print!("{}", cli::gc_plan(file, key)?);
+			Ok(())
+		},
+		&["--in-place"] => cli::gc_in_place(file, key),
+		&["--dedup"] => {
+			let count = cli::gc_dedup(file, key)?;
+			println!("{} files deduplicated", count);
+			Ok(())
+		},
+		_ => Err(Error::InvalidInput(String::from("expecting --dry-run, --plan, --in-place or --dedup"))),
+	}
+}
 
-	let blocks = match paks::read(f, key) {
-		Ok(blocks) => blocks,
-		Err(err) => return eprintln!("Error reading {}: {}", file, err),
-	};
+//----------------------------------------------------------------
 
-	let mut edit = match paks::MemoryEditor::from_blocks(blocks, key) {
-		Ok(edit) => edit,
-		Err(_) => return eprintln!("Error invalid {}: not a PAK file", file),
+const HELP_DUMP: &str = "\
+PAKtool dump
+
+NAME
+    PAKtool-dump - Prints a hex dump of a file's section.
+
+SYNOPSIS
+    PAKtool [..] dump <PATH> [--raw|--decrypted] [--range A..B]
+
+DESCRIPTION
+    Prints a hex and ASCII dump of the section containing the file's data.
+    Every line contains a single block prefixed with its block index in the PAK archive.
+
+ARGUMENTS
+    PATH         Path to the file in the PAK archive to dump.
+    --raw        Dumps the encrypted section as stored in the PAK archive.
+    --decrypted  Dumps the decrypted section (default).
+                 If the MAC check fails the section is dumped anyway.
+    --range A..B Dumps only the blocks A up to B relative to the start of the section.
+";
+
+fn dump(file: &str, key: &str, args: &[&str]) -> Result<(), Error> {
+	let ref key = cli::parse_key(key)?;
+
+	let mut path = None;
+	let mut raw = false;
+	let mut range = None;
+	let mut args = args.iter().cloned();
+	while let Some(arg) = args.next() {
+		match arg {
+			"--raw" => raw = true,
+			"--decrypted" => raw = false,
+			"--range" => {
+				let arg = args.next().unwrap_or("");
+				range = match arg.find("..").map(|i| (parse_u32(&arg[..i]), parse_u32(&arg[i + 2..]))) {
+					Some((Some(start), Some(end))) => Some(start..end),
+					_ => return Err(Error::InvalidInput(format!("invalid range: {}", arg))),
+				};
+			},
+			_ if path.is_none() && !arg.starts_with("-") => path = Some(arg),
+			_ => return Err(Error::InvalidInput(format!("unknown argument: {}", arg))),
+		}
+	}
+
+	let path = match path {
+		Some(path) => path,
+		None => return Err(Error::InvalidInput(String::from("expecting a path argument"))),
 	};
 
-	edit.gc();
+	print!("{}", cli::dump(file, key, path.as_bytes(), raw, range)?);
+	Ok(())
+}
+
+fn parse_u32(s: &str) -> Option<u32> {
+	if let Some(hex) = s.strip_prefix("0x") {
+		u32::from_str_radix(hex, 16).ok()
+	}
+	else {
+		s.parse().ok()
+	}
+}
+
+//----------------------------------------------------------------
+
+fn dbg(file: &str, key: &str, _args: &[&str]) -> Result<(), Error> {
+	let ref key = cli::parse_key(key)?;
+
+	let reader = paks::FileReader::open(file, key).map_err(|err| Error::open(file.as_ref(), err))?;
 
-	let (data, _) = edit.finish(key);
-	if let Err(err) = fs::write(file, data.as_bytes()) {
-		eprintln!("Error writing {}: {}", file, err);
+	println!("{:#?}", reader);
+	if let Some(info) = reader.provenance(key).map_err(|err| Error::read(&String::from_utf8_lossy(paks::Directory::ARCHIVE_META), err))? {
+		println!("{:#?}", info);
 	}
+	Ok(())
 }
 
 //----------------------------------------------------------------
 
-fn dbg(file: &str, key: &str, _args: &[&str]) {
-	let ref key = match parse_key(key) {
-		Some(key) => key,
-		None => return,
+const HELP_KEYGEN: &str = "\
+PAKtool keygen
+
+NAME
+    PAKtool-keygen - Generates a random key.
+
+SYNOPSIS
+    PAKtool keygen [--hex] [--words] [--qr]
+
+DESCRIPTION
+    Generates a random 128-bit key from the random number generator of the operating
+    system and prints it in the requested formats, in hex if none are given.
+
+    Use a generated key instead of a low-entropy key such as the `0` in the examples.
+    The hex and words formats are both accepted as the KEY argument.
+
+OPTIONS
+    --hex    Prints the key encoded in hex.
+    --words  Prints the key as 16 words, one for every byte.
+    --qr     Prints the key in hex as a QR code for a terminal with a dark background.
+
+EXAMPLES
+    PAKtool keygen --hex --words
+    PAKtool example.pak \"acid acorn ..\" new
+";
+
+fn keygen(args: &[&str]) -> Result<(), Error> {
+	let (mut hex, mut words, mut qr) = (false, false, false);
+	for &arg in args {
+		match arg {
+			"--hex" => hex = true,
+			"--words" => words = true,
+			"--qr" => qr = true,
+			_ => return Err(Error::InvalidInput(format!("unknown argument: {}", arg))),
+		}
+	}
+
+	let ref key = paks::key::generate();
+	if hex || !(words || qr) {
+		println!("{}", cli::format_key(key));
+	}
+	if words {
+		println!("{}", cli::format_key_words(key));
+	}
+	if qr {
+		print!("{}", cli::format_key_qr(key));
+	}
+	Ok(())
+}
+
+//----------------------------------------------------------------
+
+const HELP_KEY: &str = "\
+PAKtool key
+
+NAME
+    PAKtool-key - Splits the key between custodians, combines it and prints its fingerprint.
+
+SYNOPSIS
+    PAKtool key split <N> <K> [KEY]
+    PAKtool key combine [SHARE]..
+    PAKtool key fingerprint [KEY]
+
+DESCRIPTION
+    Splits the key into N shares of which any K combine into the key with Shamir's
+    secret sharing. Fewer than K shares reveal nothing about the key.
+
+    The key and the shares are only read from the arguments or stdin and written to
+    stdout, the full key is never written to disk. Omit the KEY and SHARE arguments
+    to keep them out of the shell history.
+
+    Combining too few shares results in the wrong key, which is only noticed when
+    opening a PAK archive with it.
+
+    The fingerprint is a short public identifier of the key. Opening a PAK archive
+    with the wrong key reports the fingerprint of the key used.
+
+ARGUMENTS
+    N        The number of shares, up to 255.
+    K        The number of shares needed to combine the key.
+    KEY      The 128-bit encryption key encoded in hex, read from stdin if omitted.
+    SHARE    A share as printed by split, read from stdin one per line if omitted.
+
+EXAMPLES
+    PAKtool key split 5 3 < key.txt
+    PAKtool key combine 01-.. 04-.. 05-..
+    PAKtool key fingerprint < key.txt
+";
+
+fn key_split(args: &[&str]) -> Result<(), Error> {
+	let (n, k, key) = match args {
+		&[n, k] => (n, k, None),
+		&[n, k, key] => (n, k, Some(key)),
+		_ => return Err(Error::InvalidInput(String::from("expecting the number of shares and the threshold"))),
+	};
+	let (n, k) = match (n.parse(), k.parse()) {
+		(Ok(n), Ok(k)) => (n, k),
+		_ => return Err(Error::InvalidInput(format!("invalid threshold: {} of {} shares", k, n))),
 	};
 
-	let reader = match paks::FileReader::open(file, key) {
-		Ok(reader) => reader,
-		Err(err) => return eprintln!("Error opening {}: {}", file, err),
+	let ref key = match key {
+		Some(key) => cli::parse_key(key)?,
+		None => {
+			let mut line = String::new();
+			io::stdin().read_line(&mut line).map_err(|err| Error::read(&"stdin", err))?;
+			cli::parse_key(line.trim())?
+		},
+	};
+
+	print!("{}", cli::key_split(key, n, k)?);
+	Ok(())
+}
+
+fn key_fingerprint(args: &[&str]) -> Result<(), Error> {
+	let ref key = match args {
+		&[key] => cli::parse_key(key)?,
+		&[] => {
+			let mut line = String::new();
+			io::stdin().read_line(&mut line).map_err(|err| Error::read(&"stdin", err))?;
+			cli::parse_key(line.trim())?
+		},
+		_ => return Err(Error::InvalidInput(String::from("expecting a single key"))),
+	};
+
+	println!("{}", paks::key::fingerprint(key));
+	Ok(())
+}
+
+fn key_combine(args: &[&str]) -> Result<(), Error> {
+	let key = if args.is_empty() {
+		let mut input = String::new();
+		io::stdin().read_to_string(&mut input).map_err(|err| Error::read(&"stdin", err))?;
+		let shares: Vec<&str> = input.lines().filter(|line| !line.trim().is_empty()).collect();
+		cli::key_combine(&shares)?
+	}
+	else {
+		cli::key_combine(args)?
 	};
 
-	print!("{:#?}", reader.as_ref());
+	println!("{}", cli::format_key(&key));
+	Ok(())
 }
@@ -0,0 +1,150 @@
+use std::{io, str, time::{SystemTime, UNIX_EPOCH}};
+use crate::*;
+
+impl Directory {
+	/// Path of the hidden file holding the changelog, see [`FileEditor::log_change`].
+	pub const CHANGELOG: &'static [u8] = b".pak/changelog";
+}
+
+/// An entry in the changelog of a PAK file, see [`FileEditor::log_change`].
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct ChangeEntry {
+	/// Time of the change in seconds since the Unix epoch.
+	pub time: u64,
+	/// Who made the change.
+	pub author: String,
+	/// Description of the change.
+	pub message: String,
+}
+
+impl ChangeEntry {
+	fn new(author: &str, message: &str) -> ChangeEntry {
+		let time = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs());
+		ChangeEntry { time, author: author.into(), message: message.into() }
+	}
+
+	// Every entry is a line of tab separated fields, tabs and line breaks in the fields are replaced by spaces
+	fn encode(&self, data: &mut Vec<u8>) {
+		let field = |s: &str| s.replace(['\t', '\n', '\r'], " ");
+		data.extend_from_slice(format!("{}\t{}\t{}\n", self.time, field(&self.author), field(&self.message)).as_bytes());
+	}
+
+	// Malformed lines are skipped
+	fn decode(data: &[u8]) -> Vec<ChangeEntry> {
+		String::from_utf8_lossy(data).lines().filter_map(|line| {
+			let mut fields = line.splitn(3, '\t');
+			let time = fields.next()?.parse().ok()?;
+			let author = fields.next()?.into();
+			let message = fields.next()?.into();
+			Some(ChangeEntry { time, author, message })
+		}).collect()
+	}
+}
+
+impl<S: Storage> FileEditor<S> {
+	/// Logs a change in the changelog of the PAK file.
+	///
+	/// The entry is timestamped now and appended to the hidden file [`Directory::CHANGELOG`] when finishing.
+	/// The changelog is encrypted and authenticated like any other file, it records who repacked what and when inside the PAK file.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// let ref key = paks::Key::default();
+	/// let mut editor = paks::FileEditor::open("game.pak", key).unwrap();
+	/// editor.create_file(b"textures/hero.png", b"..", key).unwrap();
+	/// editor.log_change("alice", "Update the hero texture");
+	/// editor.finish(key).unwrap();
+	///
+	/// let reader = paks::FileReader::open("game.pak", key).unwrap();
+	/// let entry = reader.changelog(key).unwrap().pop().unwrap();
+	/// assert_eq!(entry.author, "alice");
+	/// ```
+	pub fn log_change(&mut self, author: &str, message: &str) {
+		self.changelog.push(ChangeEntry::new(author, message));
+		self.dirty = true;
+	}
+
+	// Appends the logged changes to the changelog
+	pub(crate) fn write_changelog(&mut self, key: &Key) -> io::Result<()> {
+		if self.changelog.is_empty() {
+			return Ok(());
+		}
+		let mut data = match self.find_file(Directory::CHANGELOG) {
+			Some(desc) => self.read_data(desc, key)?,
+			None => Vec::new(),
+		};
+		for entry in self.changelog.drain(..) {
+			entry.encode(&mut data);
+		}
+		self.create_file(Directory::CHANGELOG, &data, key)?;
+		Ok(())
+	}
+}
+
+impl MemoryEditor {
+	/// Logs a change in the changelog of the PAK file.
+	///
+	/// See [`FileEditor::log_change`] for more information.
+	pub fn log_change(&mut self, author: &str, message: &str) {
+		self.changelog.push(ChangeEntry::new(author, message));
+	}
+
+	// Appends the logged changes to the changelog, a corrupted changelog is started over
+	pub(crate) fn write_changelog(&mut self, key: &Key) {
+		if self.changelog.is_empty() {
+			return;
+		}
+		let mut data = match self.find_file(Directory::CHANGELOG) {
+			Some(desc) => self.read_data(desc, key).unwrap_or_default(),
+			None => Vec::new(),
+		};
+		for entry in self.changelog.drain(..) {
+			entry.encode(&mut data);
+		}
+		self.create_file(Directory::CHANGELOG, &data, key);
+	}
+}
+
+impl FileReader {
+	/// Decrypts the changelog of the PAK file, see [`FileEditor::log_change`].
+	///
+	/// Returns the entries oldest first, or an empty list if the PAK file has no changelog.
+	pub fn changelog(&self, key: &Key) -> io::Result<Vec<ChangeEntry>> {
+		match self.find_file(Directory::CHANGELOG) {
+			Some(desc) => Ok(ChangeEntry::decode(&self.read_data(desc, key)?)),
+			None => Ok(Vec::new()),
+		}
+	}
+}
+
+impl MemoryReader {
+	/// Decrypts the changelog of the PAK file, see [`FileEditor::log_change`].
+	///
+	/// Returns the entries oldest first, or an empty list if the PAK file has no changelog.
+	pub fn changelog(&self, key: &Key) -> Result<Vec<ChangeEntry>, io::ErrorKind> {
+		match self.find_file(Directory::CHANGELOG) {
+			Some(desc) => Ok(ChangeEntry::decode(&self.read_data(desc, key)?)),
+			None => Ok(Vec::new()),
+		}
+	}
+}
+
+#[test]
+fn test_changelog() {
+	let ref key = Key::default();
+	let mut editor = MemoryEditor::new();
+	editor.log_change("alice", "First\tline\nsecond line");
+	let (blocks, _) = editor.finish(key);
+
+	let mut editor = MemoryEditor::from_blocks(blocks, key).unwrap();
+	editor.log_change("bob", "Fix typo");
+	let (blocks, _) = editor.finish(key);
+
+	let reader = MemoryReader::from_blocks(blocks, key).unwrap();
+	let changelog = reader.changelog(key).unwrap();
+	assert_eq!(changelog.len(), 2);
+	assert_eq!((&*changelog[0].author, &*changelog[0].message), ("alice", "First line second line"));
+	assert_eq!((&*changelog[1].author, &*changelog[1].message), ("bob", "Fix typo"));
+	assert!(changelog[0].time > 0 && changelog[0].time <= changelog[1].time);
+}
@@ -5,7 +5,7 @@ SPECK128/128
 https://nsacyber.github.io/simon-speck/implementations/ImplementationGuide1.1.pdf
 */
 
-const ROUNDS: usize = 32;
+pub const ROUNDS: usize = 32;
 
 macro_rules! R {
 	($x:expr, $y:expr, $k:expr) => {
@@ -46,7 +46,6 @@ pub const fn encrypt(pt: [u64; 2], rk: &[u64; ROUNDS]) -> [u64; 2] {
 	[y, x]
 }
 
-#[allow(dead_code)]
 #[inline(never)]
 pub const fn decrypt(ct: [u64; 2], rk: &[u64; ROUNDS]) -> [u64; 2] {
 	let [mut y, mut x] = ct;
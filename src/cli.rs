@@ -0,0 +1,1117 @@
+/*!
+High level operations on PAK files.
+
+These operations implement the `PAKtool` subcommands and can be reused by other tools without shelling out.
+
+Every operation opens the PAK file, performs its work and writes the changes back.
+Operations which edit the PAK file are all-or-nothing: if any error happens the changes are discarded.
+*/
+
+use std::{fmt, fmt::Write as _, fs, io, io::prelude::*, ops, path::Path, str, thread};
+use dataview::Pod;
+use crate::*;
+
+/// Operation errors.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Error {
+	/// The arguments are invalid.
+	InvalidInput(String),
+	/// A file or path was not found.
+	NotFound(String),
+	/// The key is malformed or incorrect.
+	BadKey(String),
+	/// The PAK file is corrupted.
+	Corrupt(String),
+	/// Any other IO error.
+	Io(String),
+}
+
+impl Error {
+	/// Error opening a PAK file.
+	///
+	/// The key verifier in the header tells the wrong key apart from a corrupted PAK file, see [`key::WrongKey`].
+	pub fn open(file: &Path, err: io::Error) -> Error {
+		match err.kind() {
+			io::ErrorKind::NotFound => Error::NotFound(format!("opening {}: {}", file.display(), err)),
+			io::ErrorKind::InvalidData if err.get_ref().is_some_and(|err| err.is::<key::WrongKey>()) => Error::BadKey(format!("opening {}: {}", file.display(), err)),
+			io::ErrorKind::InvalidData => Error::Corrupt(format!("opening {}: {}", file.display(), err)),
+			io::ErrorKind::UnexpectedEof => Error::Corrupt(format!("opening {}: {}", file.display(), err)),
+			_ => Error::Io(format!("opening {}: {}", file.display(), err)),
+		}
+	}
+
+	/// Error reading a local file or data from a PAK file.
+	pub fn read(path: &dyn fmt::Display, err: io::Error) -> Error {
+		match err.kind() {
+			io::ErrorKind::NotFound => Error::NotFound(format!("reading {}: {}", path, err)),
+			io::ErrorKind::InvalidData => Error::Corrupt(format!("reading {}: {}", path, err)),
+			_ => Error::Io(format!("reading {}: {}", path, err)),
+		}
+	}
+
+	/// Error writing to a file.
+	pub fn write(path: &dyn fmt::Display, err: io::Error) -> Error {
+		Error::Io(format!("writing {}: {}", path, err))
+	}
+
+	/// Returns the error message.
+	pub fn message(&self) -> &str {
+		match self {
+			Error::InvalidInput(msg) => msg,
+			Error::NotFound(msg) => msg,
+			Error::BadKey(msg) => msg,
+			Error::Corrupt(msg) => msg,
+			Error::Io(msg) => msg,
+		}
+	}
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(self.message())
+	}
+}
+
+impl std::error::Error for Error {}
+
+/// Result type for operations.
+pub type Result<T> = std::result::Result<T, Error>;
+
+fn lossy(path: &[u8]) -> String {
+	String::from_utf8_lossy(path).into_owned()
+}
+
+fn open_editor(file: &Path, key: &Key) -> Result<FileEditor> {
+	FileEditor::open(file, key).map_err(|err| Error::open(file, err))
+}
+
+fn open_reader(file: &Path, key: &Key) -> Result<FileReader> {
+	FileReader::open(file, key).map_err(|err| Error::open(file, err))
+}
+
+fn open_metadata(file: &Path, key: &Key) -> Result<FileReader> {
+	FileReader::open_metadata_only(file, key).map_err(|err| Error::open(file, err))
+}
+
+fn finish(file: &Path, edit: FileEditor, key: &Key) -> Result<()> {
+	edit.finish(key).map_err(|err| Error::write(&file.display(), err))
+}
+
+//----------------------------------------------------------------
+
+/// Parses a 128-bit key encoded in hex or as words, see [`format_key_words`].
+pub fn parse_key(s: &str) -> Result<Key> {
+	if s.trim().contains(char::is_whitespace) {
+		return parse_key_words(s);
+	}
+	match u128::from_str_radix(s, 16) {
+		Ok(val) => Ok([(val & 0xffffffffffffffff) as u64, (val >> 64) as u64]),
+		Err(err) => Err(Error::BadKey(format!("parsing key argument: {}", err))),
+	}
+}
+
+fn parse_key_words(s: &str) -> Result<Key> {
+	let mut val = 0u128;
+	let mut count = 0;
+	for word in s.split_whitespace() {
+		let i = match KEY_WORDS.binary_search(&&*word.to_ascii_lowercase()) {
+			Ok(i) => i,
+			Err(_) => return Err(Error::BadKey(format!("parsing key argument: unknown word: {}", word))),
+		};
+		val = val << 8 | i as u128;
+		count += 1;
+	}
+	if count != 16 {
+		return Err(Error::BadKey(format!("parsing key argument: expecting 16 words, found {}", count)));
+	}
+	Ok([(val & 0xffffffffffffffff) as u64, (val >> 64) as u64])
+}
+
+/// Formats the key in hex as parsed by [`parse_key`].
+pub fn format_key(key: &Key) -> String {
+	format!("{:032x}", (key[1] as u128) << 64 | key[0] as u128)
+}
+
+// One word for every byte value in alphabetical order
+static KEY_WORDS: [&str; 256] = [
+	"acid", "acorn", "actor", "adobe", "agent", "alarm", "album", "alien", "alley", "amber", "angle", "apple", "apron", "arena", "arrow", "atlas",
+	"attic", "audio", "award", "bacon", "badge", "bagel", "baker", "bamboo", "banjo", "barn", "basil", "basin", "beach", "beard", "bench", "berry",
+	"bison", "blade", "blaze", "bloom", "board", "boat", "bonus", "boot", "bottle", "brain", "brick", "bridge", "broom", "brush", "bucket", "buffalo",
+	"bugle", "cabin", "cable", "cactus", "camel", "candle", "canoe", "canyon", "carbon", "cargo", "carpet", "castle", "cedar", "chalk", "cherry", "chess",
+	"chief", "cider", "cinema", "citrus", "clock", "cloud", "clover", "coast", "cobra", "comet", "coral", "cotton", "cougar", "crane", "crater", "crayon",
+	"cricket", "crown", "cubic", "curry", "cycle", "daisy", "dancer", "delta", "denim", "desert", "diesel", "dingo", "disco", "dock", "dolphin", "donkey",
+	"dragon", "drum", "eagle", "easel", "echo", "eclipse", "elbow", "elder", "ember", "emerald", "engine", "epoch", "fable", "falcon", "fennel", "ferry",
+	"fiddle", "filter", "flame", "flute", "forest", "fossil", "fountain", "fox", "frost", "galaxy", "garden", "garlic", "gecko", "geyser", "ginger", "glacier",
+	"globe", "goblin", "gold", "gorilla", "granite", "grape", "gravel", "guitar", "hammer", "harbor", "harp", "hazel", "helmet", "hermit", "honey", "hornet",
+	"hotel", "husky", "igloo", "indigo", "iris", "island", "ivory", "jacket", "jaguar", "jasmine", "jelly", "jersey", "jigsaw", "jungle", "kayak", "kernel",
+	"kettle", "kiwi", "koala", "ladder", "lagoon", "lantern", "laser", "lemon", "lentil", "lily", "lizard", "llama", "lobster", "locket", "lotus", "lunar",
+	"magnet", "mango", "maple", "marble", "meadow", "melon", "meteor", "mint", "mirror", "mosaic", "motor", "muffin", "nectar", "needle", "nickel", "noodle",
+	"nutmeg", "oasis", "ocean", "olive", "onion", "opal", "orbit", "orchid", "otter", "oyster", "paddle", "panda", "parrot", "peach", "pebble", "pepper",
+	"piano", "pickle", "pilot", "pine", "planet", "plum", "pocket", "polar", "pony", "poppy", "prism", "puffin", "pumpkin", "quartz", "quill", "rabbit",
+	"radar", "radish", "raven", "ribbon", "river", "robin", "rocket", "rose", "ruby", "saddle", "salmon", "sandal", "satin", "scarf", "shadow", "shark",
+	"shell", "silver", "sketch", "sloth", "socket", "spider", "spruce", "squid", "stable", "statue", "storm", "sugar", "summit", "sunset", "swan", "tablet",
+];
+
+/// Formats the key as 16 words, one for every byte of the hex encoding, as parsed by [`parse_key`].
+///
+/// Words are easier to read out loud and write down without mistakes.
+pub fn format_key_words(key: &Key) -> String {
+	let val = (key[1] as u128) << 64 | key[0] as u128;
+	let words: Vec<&str> = val.to_be_bytes().iter().map(|&byte| KEY_WORDS[byte as usize]).collect();
+	words.join(" ")
+}
+
+/// Renders the key in hex as a QR code for a terminal with a dark background.
+pub fn format_key_qr(key: &Key) -> String {
+	// Uppercase hex fits the compact alphanumeric mode
+	let hex = format_key(key).to_ascii_uppercase();
+	qr::QrCode::encode(&hex).unwrap().render()
+}
+
+/// Splits the key into `n` shares of which any `k` combine into the key.
+///
+/// Returns the shares one per line.
+pub fn key_split(key: &Key, n: u8, k: u8) -> Result<String> {
+	match key::split(key, n, k) {
+		Ok(shares) => Ok(shares.iter().map(|share| format!("{}\n", share)).collect()),
+		Err(_) => Err(Error::InvalidInput(format!("invalid threshold: {} of {} shares", k, n))),
+	}
+}
+
+/// Combines the shares into the key.
+///
+/// The key is not checked, combining too few shares results in the wrong key.
+pub fn key_combine(shares: &[&str]) -> Result<Key> {
+	let shares = shares.iter().map(|share| share.parse().map_err(|_| Error::InvalidInput(format!("invalid share: {}", share.trim()))))
+		.collect::<Result<Vec<key::Share>>>()?;
+	key::combine(&shares).map_err(|_| Error::InvalidInput(String::from("expecting shares with distinct indices")))
+}
+
+/// Creates a new empty PAK file.
+///
+/// If a file with this name already exists it will be overwritten.
+pub fn new<P: ?Sized + AsRef<Path>>(file: &P, key: &Key) -> Result<()> {
+	let file = file.as_ref();
+	FileEditor::create_empty(file, key).map_err(|err| Error::write(&file.display(), err))
+}
+
+/// Renders the directory of the PAK file starting at the given subdirectory.
+///
+/// An empty path renders the entire directory, the options limit the depth, show sizes or filter the entries rendered.
+pub fn tree_string<P: ?Sized + AsRef<Path>>(file: &P, key: &Key, path: &[u8], art: &dir::Art<'static>, options: &dir::FmtOptions) -> Result<String> {
+	let file = file.as_ref();
+	let reader = open_metadata(file, key)?;
+
+	let dir = match reader.get_children(path) {
+		Some(dir) => dir,
+		None => return Err(Error::NotFound(format!("directory not found or is a file: {}", lossy(path)))),
+	};
+
+	let root = if path.is_empty() { String::from(".") } else { lossy(path) };
+	Ok(dir::Fmt::with_options(&root, dir, art, *options).to_string())
+}
+
+/// Renders the directory of the PAK file starting at the given subdirectory as a Graphviz graph.
+///
+/// An empty path renders the entire directory, see [`dir::to_dot`].
+pub fn tree_dot<P: ?Sized + AsRef<Path>>(file: &P, key: &Key, path: &[u8], sizes: bool) -> Result<String> {
+	let file = file.as_ref();
+	let reader = open_metadata(file, key)?;
+
+	let dir = match reader.get_children(path) {
+		Some(dir) => dir,
+		None => return Err(Error::NotFound(format!("directory not found or is a file: {}", lossy(path)))),
+	};
+
+	let root = if path.is_empty() { String::from(".") } else { lossy(path) };
+	Ok(dir::to_dot(&root, dir, sizes))
+}
+
+/// Lists every descriptor in the PAK file.
+///
+/// Each line contains the type, the size in bytes and the path, the paths of directories end with a `/`.
+/// If `csv` is true the listing is formatted as CSV instead, see [`Directory::to_csv`].
+pub fn ls<P: ?Sized + AsRef<Path>>(file: &P, key: &Key, csv: bool) -> Result<String> {
+	let reader = open_metadata(file.as_ref(), key)?;
+
+	if csv {
+		let mut list = Vec::new();
+		reader.to_csv(&mut list).map_err(|err| Error::write(&"listing", err))?;
+		return Ok(String::from_utf8_lossy(&list).into_owned());
+	}
+
+	let mut list = String::new();
+	for (path, desc) in reader.query(&Filter::Any) {
+		if desc.is_dir() {
+			let _ = writeln!(list, "dir  {:>10}  {}/", "-", lossy(&path));
+		}
+		else {
+			let _ = writeln!(list, "file {:>10}  {}", desc.content_size, lossy(&path));
+		}
+	}
+	Ok(list)
+}
+
+/// Finds the descriptors matching the filter.
+///
+/// Returns the paths of the matching descriptors, one per line.
+/// The paths of directories end with a `/`.
+pub fn find<P: ?Sized + AsRef<Path>>(file: &P, key: &Key, filter: &Filter) -> Result<String> {
+	let reader = open_metadata(file.as_ref(), key)?;
+
+	let mut list = String::new();
+	for (path, desc) in reader.query(filter) {
+		list.push_str(&lossy(&path));
+		list.push_str(if desc.is_dir() { "/\n" } else { "\n" });
+	}
+	Ok(list)
+}
+
+/// Lists the paths in the PAK file matching the regular expression.
+///
+/// One path per line, the paths of directories end with a `/`.
+#[cfg(feature = "regex")]
+pub fn find_regex<P: ?Sized + AsRef<Path>>(file: &P, key: &Key, pattern: &str) -> Result<String> {
+	let reader = open_metadata(file.as_ref(), key)?;
+	let found = reader.find_regex(pattern).map_err(|err| Error::InvalidInput(format!("invalid pattern: {}", err)))?;
+
+	let mut list = String::new();
+	for (path, desc) in found {
+		list.push_str(&lossy(&path));
+		list.push_str(if desc.is_dir() { "/\n" } else { "\n" });
+	}
+	Ok(list)
+}
+
+/// Adds files to the PAK file.
+///
+/// Each entry is a destination path in the PAK file and the file data.
+pub fn add<P: ?Sized + AsRef<Path>>(file: &P, key: &Key, entries: &[(&[u8], &[u8])]) -> Result<()> {
+	let file = file.as_ref();
+	let mut edit = open_editor(file, key)?;
+
+	for &(path, data) in entries {
+		edit.create_file(path, data, key).map_err(|err| Error::write(&lossy(path), err))?;
+	}
+
+	finish(file, edit, key)
+}
+
+/// Copies local files into a directory of the PAK file.
+///
+/// Every file keeps its file name and is placed under the destination directory.
+/// The files are read and encrypted on a worker thread per available core, see [`FileEditor::create_files_parallel`].
+pub fn copy<P: ?Sized + AsRef<Path>, Q: AsRef<Path>>(file: &P, key: &Key, dest_dir: &[u8], files: &[Q]) -> Result<()> {
+	let file = file.as_ref();
+	let mut edit = open_editor(file, key)?;
+
+	let mut dest_path = dest_dir.to_vec();
+	if !dest_path.is_empty() && !dest_path.ends_with(b"/") {
+		dest_path.push(b'/');
+	}
+	let dest_len = dest_path.len();
+
+	let mut entries = Vec::new();
+	for src_path in files {
+		let src_path = src_path.as_ref();
+
+		// Extract the file name
+		let file_name = match src_path.file_name().and_then(|s| s.to_str()) {
+			Some(file_name) => file_name,
+			None => return Err(Error::InvalidInput(format!("invalid file name: {}", src_path.display()))),
+		};
+
+		// Construct destination path
+		dest_path.truncate(dest_len);
+		dest_path.extend_from_slice(file_name.as_bytes());
+		entries.push((dest_path.clone(), src_path));
+	}
+
+	// Read the file contents and write them to the PAK file
+	let threads = thread::available_parallelism().map_or(1, |threads| threads.get());
+	edit.create_files_parallel(&entries, threads, key, |&(ref dest_path, src_path)| {
+		match fs::read(src_path) {
+			Ok(data) => Ok((dest_path.clone(), data)),
+			// Keep the path of the file which failed to read
+			Err(err) => Err(io::Error::new(err.kind(), Error::read(&src_path.display(), err))),
+		}
+	}).map_err(|err| match err.get_ref().and_then(|inner| inner.downcast_ref::<Error>()) {
+		Some(inner) => inner.clone(),
+		None => Error::write(&file.display(), err),
+	})?;
+
+	finish(file, edit, key)
+}
+
+/// Imports the files described by a CSV or TSV listing into the PAK file.
+///
+/// Each line of the listing has the columns `source,destination,content_type,flags` where only the first two are required.
+/// Lines containing a tab are split on tabs, otherwise on commas where fields may be quoted.
+/// Empty lines, lines starting with `#` and a header line starting with `source` are ignored.
+///
+/// * The source path is relative to the base directory.
+/// * The destination path is the path of the file in the PAK file.
+/// * The content type defaults to `1` and must not be zero.
+/// * The flags are separated by spaces or `|`:
+///   `optional` skips the line if the source file does not exist,
+///   `seal` [seals](Directory::seal) the destination's parent directory once all files are imported.
+///
+/// Returns the number of files imported.
+pub fn import_listing<P: ?Sized + AsRef<Path>, Q: ?Sized + AsRef<Path>>(file: &P, key: &Key, listing: &str, base_dir: &Q) -> Result<usize> {
+	let file = file.as_ref();
+	let mut edit = open_editor(file, key)?;
+	let imported = import_into(&mut edit, key, listing, base_dir.as_ref())?;
+	finish(file, edit, key)?;
+	Ok(imported.len())
+}
+
+/// Imports the files described by a CSV or TSV listing into the PAK file with [stripped](NamePolicy::stripped) names.
+///
+/// The PAK file must be empty or already stripped, the paths are not stored in the PAK file.
+/// See [`import_listing`] for the format of the listing, the `seal` flag has no effect as stripped PAK files have no directories.
+///
+/// Returns the mapping from the path hashes to the destination paths to keep next to the listing.
+/// Each line holds the path hash in hex and the destination path separated by a tab.
+pub fn import_stripped<P: ?Sized + AsRef<Path>, Q: ?Sized + AsRef<Path>>(file: &P, key: &Key, listing: &str, base_dir: &Q) -> Result<String> {
+	let file = file.as_ref();
+	let mut edit = open_editor(file, key)?;
+	let policy = NamePolicy { stripped: true, ..edit.name_policy() };
+	if !edit.name_policy().stripped && !edit.set_name_policy(policy) {
+		return Err(Error::InvalidInput(format!("{} is not empty, unable to strip the names", file.display())));
+	}
+	let imported = import_into(&mut edit, key, listing, base_dir.as_ref())?;
+	finish(file, edit, key)?;
+
+	let mut mapping = String::new();
+	for path in &imported {
+		let _ = writeln!(mapping, "{:016x}\t{}", policy.path_hash(path), lossy(path));
+	}
+	Ok(mapping)
+}
+
+// Imports the files described by the listing, returns the destination paths
+fn import_into(edit: &mut FileEditor, key: &Key, listing: &str, base_dir: &Path) -> Result<Vec<Vec<u8>>> {
+	let mut imported = Vec::new();
+	let mut seals = Vec::new();
+	for (i, line) in listing.lines().enumerate() {
+		let line = line.trim_end_matches('\r');
+		if line.is_empty() || line.starts_with('#') || (i == 0 && line.starts_with("source")) {
+			continue;
+		}
+		let invalid = |msg: &str| Error::InvalidInput(format!("listing line {}: {}", i + 1, msg));
+
+		let fields = if line.contains('\t') { line.split('\t').map(String::from).collect() } else { csv_fields(line) };
+		let (src_path, dest_path) = match &fields[..] {
+			[src, dest, ..] if !src.is_empty() && !dest.is_empty() => (base_dir.join(src), dest.as_bytes()),
+			_ => return Err(invalid("expecting a source and destination path")),
+		};
+		let content_type = match fields.get(2).map(|field| field.trim()) {
+			None | Some("") => 1,
+			Some(field) => match field.parse() {
+				Ok(0) | Err(_) => return Err(invalid(&format!("invalid content type: {}", field))),
+				Ok(content_type) => content_type,
+			},
+		};
+		let (mut optional, mut seal) = (false, false);
+		for flag in fields.get(3).map_or("", |field| field.as_str()).split(&[' ', '|'][..]) {
+			match flag {
+				"" => (),
+				"optional" => optional = true,
+				"seal" => seal = true,
+				_ => return Err(invalid(&format!("unknown flag: {}", flag))),
+			}
+		}
+
+		let data = match fs::read(&src_path) {
+			Ok(data) => data,
+			Err(err) if optional && err.kind() == io::ErrorKind::NotFound => continue,
+			Err(err) => return Err(Error::read(&src_path.display(), err)),
+		};
+		if !edit.writable(dest_path) {
+			return Err(Error::write(&lossy(dest_path), io::ErrorKind::PermissionDenied.into()));
+		}
+		let mut edit_file = edit.edit_file(dest_path);
+		edit_file.set_content(content_type, data.len() as u32);
+		edit_file.allocate_data().and_then(|edit_file| edit_file.write_data(&data, key)).map_err(|err| Error::write(&lossy(dest_path), err))?;
+		imported.push(dest_path.to_vec());
+
+		if seal {
+			if let Some(i) = dest_path.iter().rposition(|&chr| chr == b'/') {
+				seals.push(dest_path[..i].to_vec());
+			}
+		}
+	}
+
+	for path in &seals {
+		edit.seal(path, true);
+	}
+	Ok(imported)
+}
+
+// Splits the CSV line into its fields, quoted fields may contain commas and escaped quotes
+fn csv_fields(line: &str) -> Vec<String> {
+	let mut fields = vec![String::new()];
+	let mut quoted = false;
+	let mut chars = line.chars().peekable();
+	while let Some(chr) = chars.next() {
+		match chr {
+			'"' if quoted && chars.peek() == Some(&'"') => {
+				chars.next();
+				fields.last_mut().unwrap().push('"');
+			},
+			'"' => quoted = !quoted,
+			',' if !quoted => fields.push(String::new()),
+			chr => fields.last_mut().unwrap().push(chr),
+		}
+	}
+	fields
+}
+
+/// Creates a PAK file containing only the files which are new or changed in the new PAK file compared to the base PAK file.
+///
+/// The overlay is meant to be read before the base PAK file, like a patch or DLC shipped alongside the base game.
+/// A file is changed if its content type or contents differ, every PAK file is opened with the same key.
+/// Files removed from the base PAK file cannot be expressed by the overlay.
+///
+/// If a file with this name already exists it will be overwritten.
+/// Returns the number of files in the overlay.
+pub fn overlay<P: ?Sized + AsRef<Path>, Q: ?Sized + AsRef<Path>, R: ?Sized + AsRef<Path>>(file: &P, key: &Key, base: &Q, new: &R) -> Result<usize> {
+	let file = file.as_ref();
+	let base = open_reader(base.as_ref(), key)?;
+	let new = open_reader(new.as_ref(), key)?;
+
+	// Find the new and changed files
+	let mut changed = Vec::new();
+	for (path, desc) in new.query(&Filter::Files) {
+		let data = new.read_data(desc, key).map_err(|err| Error::read(&lossy(&path), err))?;
+		let unchanged = match base.find_file(&path) {
+			Some(base_desc) if base_desc.content_type == desc.content_type && base_desc.content_size == desc.content_size => {
+				base.read_data(base_desc, key).map_err(|err| Error::read(&lossy(&path), err))? == data
+			},
+			_ => false,
+		};
+		if !unchanged {
+			changed.push((path, desc.content_type, data));
+		}
+	}
+
+	// Write them to the overlay
+	FileEditor::create_empty(file, key).map_err(|err| Error::write(&file.display(), err))?;
+	let mut edit = open_editor(file, key)?;
+	for (path, content_type, data) in &changed {
+		let mut edit_file = edit.edit_file(path);
+		edit_file.set_content(*content_type, data.len() as u32);
+		edit_file.allocate_data().and_then(|edit_file| edit_file.write_data(data, key)).map_err(|err| Error::write(&lossy(path), err))?;
+	}
+
+	finish(file, edit, key)?;
+	Ok(changed.len())
+}
+
+/// Links the source file to alternative destination paths.
+pub fn link<P: ?Sized + AsRef<Path>>(file: &P, key: &Key, src_path: &[u8], dest_paths: &[&[u8]]) -> Result<()> {
+	let file = file.as_ref();
+	let mut edit = open_editor(file, key)?;
+
+	let src_desc = match edit.find_file(src_path) {
+		Some(desc) => *desc,
+		None => return Err(Error::NotFound(format!("file not found: {}", lossy(src_path)))),
+	};
+
+	for &dest_path in dest_paths {
+		edit.create_link(dest_path, &src_desc);
+	}
+
+	finish(file, edit, key)
+}
+
+/// Copies the source file to the destination path.
+///
+/// The copy links to the file data of the source file unless a deep copy is requested.
+/// A deep copy re-encrypts the file data and the metadata in new sections independent of the source file.
+pub fn cp<P: ?Sized + AsRef<Path>>(file: &P, key: &Key, src_path: &[u8], dest_path: &[u8], deep: bool) -> Result<()> {
+	let file = file.as_ref();
+	let mut edit = open_editor(file, key)?;
+
+	let mode = if deep { CopyMode::Deep } else { CopyMode::Link };
+	if let Err(err) = edit.copy_within(src_path, dest_path, mode, key) {
+		return Err(match err.kind() {
+			io::ErrorKind::NotFound => Error::NotFound(format!("file not found: {}", lossy(src_path))),
+			io::ErrorKind::InvalidInput => Error::InvalidInput(format!("destination is a directory: {}", lossy(dest_path))),
+			io::ErrorKind::PermissionDenied => Error::InvalidInput(format!("destination is sealed: {}", lossy(dest_path))),
+			_ => Error::read(&lossy(src_path), err),
+		});
+	}
+
+	finish(file, edit, key)
+}
+
+/// Reads a file from the PAK file.
+pub fn cat<P: ?Sized + AsRef<Path>>(file: &P, key: &Key, path: &[u8]) -> Result<Vec<u8>> {
+	let reader = open_reader(file.as_ref(), key)?;
+
+	let desc = match reader.find_file(path) {
+		Some(desc) => desc,
+		None => return Err(Error::NotFound(format!("file not found: {}", lossy(path)))),
+	};
+
+	reader.read_data(desc, key).map_err(|err| Error::read(&lossy(path), err))
+}
+
+/// Searches the contents of the files at or under the path for the pattern.
+///
+/// Returns the path and byte offset of every match as `path:offset`, one per line.
+/// An empty path searches every file.
+pub fn grep<P: ?Sized + AsRef<Path>>(file: &P, key: &Key, pattern: &[u8], path: &[u8]) -> Result<String> {
+	if pattern.is_empty() {
+		return Err(Error::InvalidInput(String::from("expecting a non-empty pattern")));
+	}
+	let reader = open_reader(file.as_ref(), key)?;
+
+	let mut list = String::new();
+	reader.scan(path, key, |path, data| {
+		let mut offset = 0;
+		while let Some(i) = data[offset..].windows(pattern.len()).position(|window| window == pattern) {
+			list.push_str(&format!("{}:{}\n", lossy(path), offset + i));
+			offset += i + pattern.len();
+		}
+	}).map_err(|err| Error::read(&lossy(path), err))?;
+	Ok(list)
+}
+
+/// Removes paths from the PAK file.
+pub fn rm<P: ?Sized + AsRef<Path>>(file: &P, key: &Key, paths: &[&[u8]]) -> Result<()> {
+	let file = file.as_ref();
+	let mut edit = open_editor(file, key)?;
+
+	for &path in paths {
+		if edit.remove(path).is_none() {
+			return Err(Error::NotFound(format!("unable to remove {}: file not found", lossy(path))));
+		}
+	}
+
+	finish(file, edit, key)
+}
+
+/// Moves paths to the trash of the PAK file.
+///
+/// See [`Directory::soft_remove`] for more information.
+pub fn trash<P: ?Sized + AsRef<Path>>(file: &P, key: &Key, paths: &[&[u8]]) -> Result<()> {
+	let file = file.as_ref();
+	let mut edit = open_editor(file, key)?;
+
+	for &path in paths {
+		if !edit.soft_remove(path) {
+			return Err(Error::NotFound(format!("unable to remove {}: file not found", lossy(path))));
+		}
+	}
+
+	finish(file, edit, key)
+}
+
+/// Restores paths from the trash of the PAK file.
+pub fn restore<P: ?Sized + AsRef<Path>>(file: &P, key: &Key, paths: &[&[u8]]) -> Result<()> {
+	let file = file.as_ref();
+	let mut edit = open_editor(file, key)?;
+
+	for &path in paths {
+		if !edit.restore_trashed(path) {
+			return Err(Error::NotFound(format!("unable to restore {}: not found in the trash or already exists", lossy(path))));
+		}
+	}
+
+	finish(file, edit, key)
+}
+
+/// Moves a file in the PAK file.
+pub fn mv<P: ?Sized + AsRef<Path>>(file: &P, key: &Key, src_path: &[u8], dest_path: &[u8]) -> Result<()> {
+	let file = file.as_ref();
+	let mut edit = open_editor(file, key)?;
+
+	if !edit.move_file(src_path, dest_path) {
+		return Err(Error::NotFound(format!("file not found: {}", lossy(src_path))));
+	}
+
+	finish(file, edit, key)
+}
+
+/// Adds the tag to or removes the tag from files in the PAK file.
+///
+/// See [`FileEditor::tag`] for more information.
+/// Returns the number of files whose tags changed.
+pub fn tag<P: ?Sized + AsRef<Path>>(file: &P, key: &Key, tag: &str, paths: &[&[u8]], remove: bool) -> Result<usize> {
+	let file = file.as_ref();
+	let mut edit = open_editor(file, key)?;
+
+	let mut changed = 0;
+	for &path in paths {
+		let result = if remove { edit.untag(path, tag, key) } else { edit.tag(path, tag, key) };
+		match result {
+			Ok(true) => changed += 1,
+			Ok(false) => (),
+			Err(err) => return Err(match err.kind() {
+				io::ErrorKind::NotFound => Error::NotFound(format!("file not found: {}", lossy(path))),
+				io::ErrorKind::InvalidInput => Error::InvalidInput(format!("unable to tag {}: invalid tag or packed file", lossy(path))),
+				io::ErrorKind::PermissionDenied => Error::InvalidInput(format!("unable to tag {}: sealed", lossy(path))),
+				_ => Error::read(&lossy(path), err),
+			}),
+		}
+	}
+
+	finish(file, edit, key)?;
+	Ok(changed)
+}
+
+/// Lists the tags in the PAK file or the files with the given tag.
+///
+/// Without a tag every tag is listed with its number of files as `tag: count`, one per line.
+/// With a tag the paths of the files with the tag are listed, one per line.
+pub fn tags<P: ?Sized + AsRef<Path>>(file: &P, key: &Key, tag: Option<&str>) -> Result<String> {
+	let reader = open_metadata(file.as_ref(), key)?;
+
+	let mut list = String::new();
+	match tag {
+		Some(tag) => {
+			for (path, _) in reader.find_by_tag(tag) {
+				let _ = writeln!(list, "{}", lossy(&path));
+			}
+		},
+		None => {
+			for tag in reader.tags() {
+				let _ = writeln!(list, "{}: {}", tag, reader.find_by_tag(tag).len());
+			}
+		},
+	}
+	Ok(list)
+}
+
+/// Lists the changelog of the PAK file, oldest first.
+///
+/// Every entry is listed as `date author: message` with the date in UTC, one per line.
+pub fn log<P: ?Sized + AsRef<Path>>(file: &P, key: &Key) -> Result<String> {
+	let reader = open_reader(file.as_ref(), key)?;
+	let changelog = reader.changelog(key).map_err(|err| Error::read(&lossy(Directory::CHANGELOG), err))?;
+
+	let mut list = String::new();
+	for entry in &changelog {
+		let _ = writeln!(list, "{} {}: {}", format_utc(entry.time), entry.author, entry.message);
+	}
+	Ok(list)
+}
+
+/// Logs a change in the changelog of the PAK file.
+pub fn log_change<P: ?Sized + AsRef<Path>>(file: &P, key: &Key, author: &str, message: &str) -> Result<()> {
+	let file = file.as_ref();
+	let mut edit = open_editor(file, key)?;
+	edit.log_change(author, message);
+	finish(file, edit, key)
+}
+
+// Formats seconds since the Unix epoch as `YYYY-MM-DD HH:MM:SS` in UTC
+fn format_utc(time: u64) -> String {
+	let (days, secs) = ((time / 86400) as i64, time % 86400);
+	// Civil from days, see http://howardhinnant.github.io/date_algorithms.html
+	let z = days + 719468;
+	let era = z.div_euclid(146097);
+	let doe = z - era * 146097;
+	let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+	let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+	let mp = (5 * doy + 2) / 153;
+	let day = doy - (153 * mp + 2) / 5 + 1;
+	let month = if mp < 10 { mp + 3 } else { mp - 9 };
+	let year = yoe + era * 400 + (month <= 2) as i64;
+	format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}", year, month, day, secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+/// File system consistency check.
+///
+/// Checks the directory for errors and audits the nonces for reuse.
+///
+/// Returns the log of the check, which is an error if the PAK file contains errors.
+pub fn fsck<P: ?Sized + AsRef<Path>>(file: &P, key: &Key) -> Result<String> {
+	let reader = open_metadata(file.as_ref(), key)?;
+
+	let mut log = String::new();
+	let mut success = reader.fsck(reader.high_mark(), &mut log);
+
+	for reuse in reader.audit_nonces() {
+		let _ = write!(log, "nonce reuse [{:#x}, {:#x}]:", reuse.nonce[0], reuse.nonce[1]);
+		for section in &reuse.sections {
+			let _ = write!(log, " (offset={}, size={})", section.offset, section.size);
+		}
+		log.push('\n');
+		success = false;
+	}
+
+	if !success {
+		return Err(Error::Corrupt(format!("PAK file contains errors:\n{}", log)));
+	}
+
+	Ok(log)
+}
+
+/// Verifies the PAK file for use as a CI gate.
+///
+/// Checks the directory and audits the nonces like [`fsck`].
+/// With `checksums` the MAC of every file section and meta section is verified, see [`FileReader::scrub`].
+/// With `deep` the checksums are verified and the metadata of every file is decoded, its dependencies resolved and the backup manifest decoded if present.
+///
+/// Returns the pass summary, or an error with the fail summary if the PAK file fails verification.
+pub fn verify<P: ?Sized + AsRef<Path>>(file: &P, key: &Key, checksums: bool, deep: bool) -> Result<String> {
+	let file = file.as_ref();
+	let reader = open_reader(file, key)?;
+
+	let mut log = String::new();
+	let mut dir_log = String::new();
+	let mut success = reader.fsck(reader.high_mark(), &mut dir_log);
+	let reuse = reader.audit_nonces();
+	for reuse in &reuse {
+		let _ = write!(dir_log, "nonce reuse [{:#x}, {:#x}]:", reuse.nonce[0], reuse.nonce[1]);
+		for section in &reuse.sections {
+			let _ = write!(dir_log, " (offset={}, size={})", section.offset, section.size);
+		}
+		dir_log.push('\n');
+	}
+	success &= reuse.is_empty();
+	let _ = writeln!(log, "directory: {} ({} entries)", if success { "ok" } else { "FAIL" }, reader.len());
+	log.push_str(&dir_log);
+
+	if checksums || deep {
+		let mut sections = 0usize;
+		let damaged = reader.scrub(key, &ScrubOptions::default(), |_, _| { sections += 1; true })
+			.map_err(|err| Error::read(&file.display(), err))?;
+		// The progress is reported once more when done, unless there is nothing to scrub
+		let sections = sections.saturating_sub(1);
+		let _ = writeln!(log, "checksums: {} ({} sections, {} damaged)", if damaged.is_empty() { "ok" } else { "FAIL" }, sections, damaged.len());
+		for damaged in &damaged {
+			let what = if damaged.meta { "meta section" } else { "section" };
+			let _ = writeln!(log, "damaged {} (offset={}, size={}): {}", what, damaged.section.offset, damaged.section.size,
+				damaged.paths.iter().map(|path| lossy(path)).collect::<Vec<_>>().join(", "));
+		}
+		success &= damaged.is_empty();
+	}
+
+	if deep {
+		let mut errors = String::new();
+		let mut files = 0;
+		for (path, desc) in reader.query(&Filter::Files) {
+			files += 1;
+			if desc.meta_section().is_none() {
+				continue;
+			}
+			if let Err(err) = reader.read_meta(desc, key) {
+				let _ = writeln!(errors, "invalid metadata: {}: {}", lossy(&path), err);
+			}
+			else if let Err(err) = reader.resolve_dependencies(&path, key) {
+				let _ = writeln!(errors, "unresolved dependencies: {}: {}", lossy(&path), err);
+			}
+		}
+		if reader.find_file(Directory::MANIFEST).is_some() {
+			if let Err(err) = Manifest::read(&reader, key) {
+				let _ = writeln!(errors, "invalid manifest: {}", err);
+			}
+		}
+		let _ = writeln!(log, "metadata: {} ({} files)", if errors.is_empty() { "ok" } else { "FAIL" }, files);
+		log.push_str(&errors);
+		success &= errors.is_empty();
+	}
+
+	if !success {
+		return Err(Error::Corrupt(format!("verification failed:\n{}FAIL", log)));
+	}
+	log.push_str("PASS\n");
+	Ok(log)
+}
+
+/// Counts the number of blocks which can be reclaimed by garbage collection.
+pub fn gc_dry_run<P: ?Sized + AsRef<Path>>(file: &P, key: &Key) -> Result<u32> {
+	let reader = open_metadata(file.as_ref(), key)?;
+	// Garbage collection empties the trash
+	let mut directory = Directory::clone(&reader);
+	directory.empty_trash();
+	Ok(directory.garbage(reader.high_mark()))
+}
+
+/// Plans the garbage collection without loading the entire PAK file in memory, see [`FileEditor::plan_gc`].
+///
+/// Lists every extent of file data which moves and the bytes rewritten and reclaimed.
+pub fn gc_plan<P: ?Sized + AsRef<Path>>(file: &P, key: &Key) -> Result<String> {
+	let reader = open_metadata(file.as_ref(), key)?;
+	let plan = reader.plan_gc();
+
+	let mut log = String::new();
+	for extent in plan.moves() {
+		let _ = writeln!(log, "move {}..{} -> {} ({} blocks)", extent.from.start, extent.from.end, extent.to, extent.from.end - extent.from.start);
+	}
+	let _ = writeln!(log, "rewrites {} bytes, reclaims {} bytes", plan.bytes_rewritten(), plan.bytes_reclaimed());
+	Ok(log)
+}
+
+/// Collects garbage left behind by removed files.
+///
+/// The PAK file is rewritten in memory and saved as a fresh copy.
+pub fn gc<P: ?Sized + AsRef<Path>>(file: &P, key: &Key) -> Result<()> {
+	let file = file.as_ref();
+
+	let f = fs::File::open(file).map_err(|err| Error::open(file, err))?;
+	let blocks = read(f, key).map_err(|err| Error::open(file, err))?;
+
+	let mut edit = match MemoryEditor::from_blocks(blocks, key) {
+		Ok(edit) => edit,
+		Err(_) => return Err(Error::Corrupt(format!("invalid {}: not a PAK file", file.display()))),
+	};
+
+	edit.gc();
+
+	let (data, _) = edit.finish(key);
+	fs::write(file, data.as_bytes()).map_err(|err| Error::write(&file.display(), err))
+}
+
+/// Collects garbage after collapsing files with identical contents into links.
+///
+/// The PAK file is rewritten in memory and saved as a fresh copy, see [`MemoryEditor::gc_dedup`].
+/// Returns the number of files rewritten as links.
+pub fn gc_dedup<P: ?Sized + AsRef<Path>>(file: &P, key: &Key) -> Result<usize> {
+	let file = file.as_ref();
+
+	let f = fs::File::open(file).map_err(|err| Error::open(file, err))?;
+	let blocks = read(f, key).map_err(|err| Error::open(file, err))?;
+
+	let mut edit = match MemoryEditor::from_blocks(blocks, key) {
+		Ok(edit) => edit,
+		Err(_) => return Err(Error::Corrupt(format!("invalid {}: not a PAK file", file.display()))),
+	};
+
+	let count = edit.gc_dedup(key);
+
+	let (data, _) = edit.finish(key);
+	fs::write(file, data.as_bytes()).map_err(|err| Error::write(&file.display(), err))?;
+	Ok(count)
+}
+
+/// Collects garbage left behind by removed files without loading the entire PAK file in memory.
+///
+/// See [`FileEditor::gc`] for its consistency guarantees.
+pub fn gc_in_place<P: ?Sized + AsRef<Path>>(file: &P, key: &Key) -> Result<()> {
+	let file = file.as_ref();
+	let mut edit = open_editor(file, key)?;
+	edit.gc().map_err(|err| Error::write(&file.display(), err))?;
+	finish(file, edit, key)
+}
+
+/// Describes the descriptor at the path.
+///
+/// Lists its type, size, section, flags, whether it has a meta section and the other paths linking to the same file data.
+/// If `json` is true the description is formatted as a single JSON object instead.
+///
+/// An empty path describes the PAK file itself, its block size, number of entries, high mark and [provenance](FileEditor::set_provenance).
+pub fn stat<P: ?Sized + AsRef<Path>>(file: &P, key: &Key, path: &[u8], json: bool) -> Result<String> {
+	if path.is_empty() {
+		return stat_archive(file.as_ref(), key, json);
+	}
+	let reader = open_metadata(file.as_ref(), key)?;
+
+	let desc = match reader.find_desc(path) {
+		Some(desc) => *desc,
+		None => return Err(Error::NotFound(format!("path not found: {}", lossy(path)))),
+	};
+	let links: Vec<String> = if desc.is_file() {
+		reader.query(&Filter::Files)
+			.filter(|&(ref link, other)| other.section == desc.section && link.as_slice() != path)
+			.map(|(link, _)| lossy(&link))
+			.collect()
+	}
+	else {
+		Vec::new()
+	};
+	let meta = desc.meta_section().is_some();
+	let nlink = reader.nlink(&desc);
+	let section = desc.section;
+
+	let mut stat = String::new();
+	if json {
+		let _ = write!(stat, "{{\"path\":{},\"type\":\"{}\",\"content_type\":{},\"content_size\":{},",
+			json_string(&lossy(path)), if desc.is_dir() { "dir" } else { "file" }, desc.content_type, desc.content_size);
+		let _ = write!(stat, "\"section\":{{\"offset\":{},\"size\":{},\"nonce\":[\"{:#x}\",\"{:#x}\"],\"mac\":[\"{:#x}\",\"{:#x}\"]}},",
+			section.offset, section.size, section.nonce[0], section.nonce[1], section.mac[0], section.mac[1]);
+		let _ = write!(stat, "\"flags\":{},\"sealed\":{},\"packed\":{},\"pinned\":{},\"public\":{},\"content_offset\":{},\"nlink\":{},\"meta\":{},\"links\":[",
+			desc.flags(), desc.is_sealed(), desc.is_packed(), desc.is_pinned(), desc.is_public(), desc.content_offset(), nlink, meta);
+		for (i, link) in links.iter().enumerate() {
+			if i != 0 {
+				stat.push(',');
+			}
+			stat.push_str(&json_string(link));
+		}
+		stat.push_str("]}\n");
+	}
+	else {
+		let _ = writeln!(stat, "path: {}", lossy(path));
+		if desc.is_dir() {
+			let _ = writeln!(stat, "type: directory");
+			let _ = writeln!(stat, "children: {}", desc.content_size);
+		}
+		else {
+			let _ = writeln!(stat, "type: file (content type {})", desc.content_type);
+			let _ = writeln!(stat, "size: {} bytes", desc.content_size);
+			let _ = writeln!(stat, "section: offset={:#x} size={:#x}", section.offset, section.size);
+			let _ = writeln!(stat, "nonce: [{:#x}, {:#x}]", section.nonce[0], section.nonce[1]);
+			let _ = writeln!(stat, "mac: [{:#x}, {:#x}]", section.mac[0], section.mac[1]);
+			if desc.is_public() {
+				let _ = writeln!(stat, "public: stored as plaintext");
+			}
+			let _ = writeln!(stat, "nlink: {}", nlink);
+		}
+		let _ = write!(stat, "flags: {:#04x}", desc.flags());
+		if desc.is_sealed() {
+			stat.push_str(" (sealed)");
+		}
+		if desc.is_packed() {
+			let _ = write!(stat, " (packed at byte offset {})", desc.content_offset());
+		}
+		if desc.is_pinned() {
+			stat.push_str(" (pinned)");
+		}
+		let _ = writeln!(stat, "\nmeta: {}", if meta { "present" } else { "none" });
+		if desc.is_file() {
+			let _ = writeln!(stat, "links: {}", if links.is_empty() { String::from("none") } else { links.join(", ") });
+		}
+	}
+	Ok(stat)
+}
+
+fn stat_archive(file: &Path, key: &Key, json: bool) -> Result<String> {
+	let reader = open_reader(file, key)?;
+	let provenance = reader.provenance(key).map_err(|err| Error::read(&lossy(Directory::ARCHIVE_META), err))?;
+	let block_size = reader.info().block_size();
+
+	let mut stat = String::new();
+	if json {
+		let _ = write!(stat, "{{\"block_size\":{},\"entries\":{},\"high_mark\":{},\"provenance\":", block_size, reader.len(), reader.high_mark());
+		match provenance {
+			Some(info) => {
+				let _ = write!(stat, "{{\"tool\":{},\"version\":{},\"commit\":{},\"timestamp\":{}}}",
+					json_string(&info.tool), json_string(&info.version), json_string(&info.commit), info.timestamp);
+			},
+			None => stat.push_str("null"),
+		}
+		stat.push_str("}\n");
+	}
+	else {
+		let _ = writeln!(stat, "block size: {} bytes", block_size);
+		let _ = writeln!(stat, "entries: {}", reader.len());
+		let _ = writeln!(stat, "high mark: {} blocks", reader.high_mark());
+		match provenance {
+			Some(info) => {
+				let _ = writeln!(stat, "built by: {} {}", info.tool, info.version);
+				let _ = writeln!(stat, "commit: {}", info.commit);
+				let _ = writeln!(stat, "built at: {}", format_utc(info.timestamp));
+			},
+			None => stat.push_str("provenance: none\n"),
+		}
+	}
+	Ok(stat)
+}
+
+// Quotes and escapes the string as a JSON string
+fn json_string(s: &str) -> String {
+	let mut json = String::from("\"");
+	for chr in s.chars() {
+		match chr {
+			'"' => json.push_str("\\\""),
+			'\\' => json.push_str("\\\\"),
+			chr if (chr as u32) < 0x20 => {
+				let _ = write!(json, "\\u{:04x}", chr as u32);
+			},
+			chr => json.push(chr),
+		}
+	}
+	json.push('"');
+	json
+}
+
+/// Renders a hex and ASCII dump of a file's section.
+///
+/// Every line contains 16 bytes prefixed with their offset in the PAK file in units of 16 bytes.
+/// Larger [block sizes](crate::InfoHeader::block_size) span multiple lines per block.
+/// The range selects the blocks relative to the start of the section, by default the whole section is dumped.
+///
+/// If `raw` is true the encrypted blocks are dumped as stored in the PAK file.
+/// Otherwise the blocks are decrypted, a failed MAC check is noted in the dump instead of returned as an error.
+pub fn dump<P: ?Sized + AsRef<Path>>(file: &P, key: &Key, path: &[u8], raw: bool, range: Option<ops::Range<u32>>) -> Result<String> {
+	let file = file.as_ref();
+	let reader = open_metadata(file, key)?;
+
+	let desc = match reader.find_file(path) {
+		Some(desc) => *desc,
+		None => return Err(Error::NotFound(format!("file not found: {}", lossy(path)))),
+	};
+	let section = desc.section;
+
+	let range = range.unwrap_or(0..section.size);
+	if range.start > range.end || range.end > section.size {
+		return Err(Error::InvalidInput(format!("invalid range {}..{}: section has {} blocks", range.start, range.end, section.size)));
+	}
+
+	// Read the encrypted section
+	let scale = reader.block_size() as usize / BLOCK_SIZE;
+	let mut blocks = vec![Block::default(); section.size as usize * scale];
+	let mut f = fs::File::open(file).map_err(|err| Error::open(file, err))?;
+	f.seek(io::SeekFrom::Start(section.offset as u64 * reader.block_size() as u64)).map_err(|err| Error::read(&lossy(path), err))?;
+	f.read_exact(blocks.as_bytes_mut()).map_err(|err| Error::read(&lossy(path), err))?;
+
+	let mut dump = format!("{}: offset={:#x} size={:#x} content_size={:#x}", lossy(path), section.offset, section.size, desc.content_size);
+	if raw {
+		dump.push_str(" (raw)\n");
+	}
+	else if crypt::decrypt_section(&mut blocks, &section, key) {
+		dump.push_str(" (decrypted)\n");
+	}
+	else {
+		dump.push_str(" (decrypted, MAC check failed!)\n");
+	}
+
+	let start = range.start as usize * scale;
+	for (i, block) in blocks[start..range.end as usize * scale].iter().enumerate() {
+		hex_line(&mut dump, (section.offset as usize * scale + start + i) as u64, block.as_bytes());
+	}
+
+	Ok(dump)
+}
+
+/// Returns if the data looks like binary content which should not be written to a terminal.
+///
+/// Data is considered binary if it contains any NUL bytes.
+pub fn is_binary(data: &[u8]) -> bool {
+	data.contains(&0)
+}
+
+/// Formats a hex and ASCII dump of the data.
+///
+/// Every line contains 16 bytes prefixed with their byte offset in the data.
+/// The lines are laid out like those of [`dump`], which prefixes them with their offset in units of 16 bytes instead.
+pub fn hex_dump(data: &[u8]) -> String {
+	let mut dump = String::new();
+	for (i, bytes) in data.chunks(BLOCK_SIZE).enumerate() {
+		hex_line(&mut dump, (i * BLOCK_SIZE) as u64, bytes);
+	}
+	dump
+}
+
+fn hex_line(dump: &mut String, address: u64, bytes: &[u8]) {
+	let _ = write!(dump, "{:08x} ", address);
+	for byte in bytes {
+		let _ = write!(dump, " {:02x}", byte);
+	}
+	// Align the ASCII column of a short last line
+	for _ in bytes.len()..BLOCK_SIZE {
+		dump.push_str("   ");
+	}
+	dump.push_str("  |");
+	dump.extend(bytes.iter().map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' }));
+	dump.push_str("|\n");
+}
+
+mod qr;
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,226 @@
+// Minimal QR code encoder for printing keys to the terminal.
+// Only version 2 at error correction level M in alphanumeric mode with data mask 0, fitting up to 38 characters.
+
+const SIZE: usize = 25;
+const DATA_CODEWORDS: usize = 28;
+const EC_CODEWORDS: usize = 16;
+const ALPHANUMERIC: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ $%*+-./:";
+
+pub struct QrCode {
+	// Indexed by row then column, true for dark modules
+	modules: [[bool; SIZE]; SIZE],
+	function: [[bool; SIZE]; SIZE],
+}
+
+#[derive(Default)]
+struct Bits {
+	bytes: Vec<u8>,
+	len: usize,
+}
+
+impl Bits {
+	fn push(&mut self, value: u32, n: usize) {
+		for i in (0..n).rev() {
+			if self.len & 7 == 0 {
+				self.bytes.push(0);
+			}
+			if value >> i & 1 != 0 {
+				*self.bytes.last_mut().unwrap() |= 0x80 >> (self.len % 8);
+			}
+			self.len += 1;
+		}
+	}
+}
+
+// Multiplication in GF(2^8) with the QR code polynomial
+fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+	let mut r = 0;
+	while b != 0 {
+		if b & 1 != 0 {
+			r ^= a;
+		}
+		a = (a << 1) ^ if a & 0x80 != 0 { 0x1d } else { 0 };
+		b >>= 1;
+	}
+	r
+}
+
+fn reed_solomon(data: &[u8]) -> [u8; EC_CODEWORDS] {
+	// The generator polynomial is the product of (x - 2^i) for every error correction codeword
+	let mut divisor = [0u8; EC_CODEWORDS];
+	divisor[EC_CODEWORDS - 1] = 1;
+	let mut root = 1;
+	for _ in 0..EC_CODEWORDS {
+		for j in 0..EC_CODEWORDS {
+			divisor[j] = gf_mul(divisor[j], root);
+			if j + 1 < EC_CODEWORDS {
+				divisor[j] ^= divisor[j + 1];
+			}
+		}
+		root = gf_mul(root, 2);
+	}
+
+	let mut rem = [0u8; EC_CODEWORDS];
+	for &byte in data {
+		let factor = byte ^ rem[0];
+		rem.copy_within(1.., 0);
+		rem[EC_CODEWORDS - 1] = 0;
+		for (rem, &coeff) in rem.iter_mut().zip(divisor.iter()) {
+			*rem ^= gf_mul(coeff, factor);
+		}
+	}
+	rem
+}
+
+impl QrCode {
+	// Returns None if the text does not fit or is not in the alphanumeric character set
+	pub fn encode(text: &str) -> Option<QrCode> {
+		let chars = text.bytes().map(|chr| ALPHANUMERIC.iter().position(|&c| c == chr).map(|i| i as u32)).collect::<Option<Vec<_>>>()?;
+		if chars.len() > 38 {
+			return None;
+		}
+
+		// Mode indicator, character count and the characters in pairs
+		let mut bits = Bits::default();
+		bits.push(0b0010, 4);
+		bits.push(chars.len() as u32, 9);
+		for pair in chars.chunks(2) {
+			match *pair {
+				[a, b] => bits.push(a * 45 + b, 11),
+				[a] => bits.push(a, 6),
+				_ => unreachable!(),
+			}
+		}
+		// Terminator, then pad to whole codewords with the alternating pad codewords
+		bits.push(0, usize::min(4, DATA_CODEWORDS * 8 - bits.len));
+		bits.push(0, (8 - bits.len % 8) % 8);
+		let mut codewords = bits.bytes;
+		let mut pads = [0xec, 0x11].iter().cycle();
+		while codewords.len() < DATA_CODEWORDS {
+			codewords.push(*pads.next().unwrap());
+		}
+		let ec = reed_solomon(&codewords);
+		codewords.extend_from_slice(&ec);
+
+		let mut qr = QrCode {
+			modules: [[false; SIZE]; SIZE],
+			function: [[false; SIZE]; SIZE],
+		};
+		qr.draw_function_patterns();
+		qr.draw_codewords(&codewords);
+		Some(qr)
+	}
+
+	fn set_function(&mut self, x: usize, y: usize, dark: bool) {
+		self.modules[y][x] = dark;
+		self.function[y][x] = true;
+	}
+
+	fn draw_function_patterns(&mut self) {
+		for i in 0..SIZE {
+			self.set_function(6, i, i & 1 == 0);
+			self.set_function(i, 6, i & 1 == 0);
+		}
+
+		// Finder patterns with their separators
+		for &(cx, cy) in &[(3, 3), (SIZE - 4, 3), (3, SIZE - 4)] {
+			for dy in -4isize..=4 {
+				for dx in -4isize..=4 {
+					let (x, y) = (cx as isize + dx, cy as isize + dy);
+					if x >= 0 && x < SIZE as isize && y >= 0 && y < SIZE as isize {
+						let dist = isize::max(dx.abs(), dy.abs());
+						self.set_function(x as usize, y as usize, dist != 2 && dist != 4);
+					}
+				}
+			}
+		}
+
+		// The single alignment pattern of version 2
+		for dy in -2isize..=2 {
+			for dx in -2isize..=2 {
+				self.set_function((18 + dx) as usize, (18 + dy) as usize, isize::max(dx.abs(), dy.abs()) != 1);
+			}
+		}
+
+		// Format information of level M with mask 0 and its BCH code
+		let data = 0u32;
+		let mut rem = data;
+		for _ in 0..10 {
+			rem = (rem << 1) ^ ((rem >> 9) * 0x537);
+		}
+		let bits = (data << 10 | rem) ^ 0x5412;
+		let bit = |i: usize| bits >> i & 1 != 0;
+		for i in 0..6 {
+			self.set_function(8, i, bit(i));
+		}
+		self.set_function(8, 7, bit(6));
+		self.set_function(8, 8, bit(7));
+		self.set_function(7, 8, bit(8));
+		for i in 9..15 {
+			self.set_function(14 - i, 8, bit(i));
+		}
+		for i in 0..8 {
+			self.set_function(SIZE - 1 - i, 8, bit(i));
+		}
+		for i in 8..15 {
+			self.set_function(8, SIZE - 15 + i, bit(i));
+		}
+		self.set_function(8, SIZE - 8, true);
+	}
+
+	// Zigzags up and down two columns at a time from the bottom right, skipping the vertical timing pattern
+	fn draw_codewords(&mut self, codewords: &[u8]) {
+		let mut i = 0;
+		let mut right = SIZE - 1;
+		while right >= 1 {
+			if right == 6 {
+				right = 5;
+			}
+			for vert in 0..SIZE {
+				for j in 0..2 {
+					let x = right - j;
+					let upward = (right + 1) & 2 == 0;
+					let y = if upward { SIZE - 1 - vert } else { vert };
+					if !self.function[y][x] {
+						// The remainder bits are light
+						let dark = i < codewords.len() * 8 && codewords[i / 8] >> (7 - i % 8) & 1 != 0;
+						// Mask 0 inverts the modules on a checkerboard
+						self.modules[y][x] = dark ^ ((x + y) & 1 == 0);
+						i += 1;
+					}
+				}
+			}
+			if right < 2 {
+				break;
+			}
+			right -= 2;
+		}
+	}
+
+	// Renders two rows of modules per line with half blocks as light on a dark terminal, surrounded by the quiet zone
+	pub fn render(&self) -> String {
+		const QUIET: usize = 4;
+		let light = |x: usize, y: usize| {
+			if y >= SIZE + 2 * QUIET {
+				return false;
+			}
+			match (x.checked_sub(QUIET), y.checked_sub(QUIET)) {
+				(Some(x), Some(y)) if x < SIZE && y < SIZE => !self.modules[y][x],
+				_ => true,
+			}
+		};
+		let mut s = String::new();
+		for y in (0..SIZE + 2 * QUIET).step_by(2) {
+			for x in 0..SIZE + 2 * QUIET {
+				s.push(match (light(x, y), light(x, y + 1)) {
+					(true, true) => '█',
+					(true, false) => '▀',
+					(false, true) => '▄',
+					(false, false) => ' ',
+				});
+			}
+			s.push('\n');
+		}
+		s
+	}
+}
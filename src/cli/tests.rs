@@ -0,0 +1,486 @@
+use crate::*;
+
+const EXAMPLE: &[u8] = include_str!("../../tests/data/example.txt").as_bytes();
+
+#[test]
+fn test_cli() {
+	let ref key = [3, 4];
+	let file = "cli_test.pak";
+
+	temp_file!(file);
+
+	cli::new(file, key).unwrap();
+	cli::add(file, key, &[(b"a/example", EXAMPLE)]).unwrap();
+	cli::link(file, key, b"a/example", &[b"b/example"]).unwrap();
+	cli::mv(file, key, b"a/example", b"c/example").unwrap();
+	cli::rm(file, key, &[b"a"]).unwrap();
+
+	let tree = cli::tree_string(file, key, b"", &dir::Art::ASCII, &dir::FmtOptions::default()).unwrap();
+	let options = dir::FmtOptions { max_depth: Some(1), sizes: true, ..dir::FmtOptions::default() };
+	let tree_sizes = cli::tree_string(file, key, b"", &dir::Art::ASCII, &options).unwrap();
+	let dot = cli::tree_dot(file, key, b"c", true).unwrap();
+	assert_eq!(cli::cat(file, key, b"c/example").unwrap(), EXAMPLE);
+	assert_eq!(cli::cat(file, key, b"a/example"), Err(cli::Error::NotFound(String::from("file not found: a/example"))));
+	assert!(matches!(cli::cat(file, key, b"x"), Err(cli::Error::NotFound(_))));
+	assert!(matches!(cli::cat(file, &[0, 0], b"b/example"), Err(cli::Error::BadKey(_))));
+
+	// Removing a missing path discards all changes
+	assert!(matches!(cli::rm(file, key, &[b"b/example", b"missing"]), Err(cli::Error::NotFound(_))));
+	assert_eq!(cli::cat(file, key, b"b/example").unwrap(), EXAMPLE);
+
+	assert_eq!(tree, "./\n+- b/\n|  `  example\n|  \n`- c/\n   `  example\n");
+	let size = EXAMPLE.len();
+	assert_eq!(tree_sizes, format!("./\n+- b/ ({0} bytes)\n|  \n`- c/ ({0} bytes)\n", size));
+	let width = format!("{:.2}", 0.75 + (size as f64 + 1.0).log2() / 8.0);
+	assert_eq!(dot, format!("digraph pak {{\n\tnode [fontname=\"monospace\"];\n\
+		\tn0 [label=\"c/\\n{0} bytes\", width={1}, shape=folder];\n\
+		\tn1 [label=\"example\\n{0} bytes\", width={1}, shape=note];\n\tn0 -> n1;\n}}\n", size, width));
+}
+
+#[test]
+fn test_cli_discard() {
+	let ref key = [5, 6];
+	let file = "cli_discard.pak";
+
+	temp_file!(file);
+
+	cli::new(file, key).unwrap();
+	cli::add(file, key, &[(b"example", EXAMPLE)]).unwrap();
+
+	// Failing to copy a missing file must leave the PAK file intact
+	let result = cli::copy(file, key, b"dir", &["tests/data/example.txt", "tests/data/missing.txt"]);
+	let example = cli::cat(file, key, b"example");
+
+	assert!(matches!(result, Err(cli::Error::NotFound(_))));
+	assert_eq!(example.unwrap(), EXAMPLE);
+}
+
+#[test]
+fn test_cli_dump() {
+	let ref key = [7, 8];
+	let file = "cli_dump.pak";
+
+	temp_file!(file);
+
+	cli::new(file, key).unwrap();
+	cli::add(file, key, &[(b"example", b"0123456789abcdefHello")]).unwrap();
+
+	let decrypted = cli::dump(file, key, b"example", false, None);
+	let raw = cli::dump(file, key, b"example", true, Some(1..2));
+	let invalid = cli::dump(file, key, b"example", false, Some(1..3));
+
+	let decrypted = decrypted.unwrap();
+	assert!(decrypted.contains("(decrypted)"));
+	assert!(decrypted.contains("|0123456789abcdef|"));
+	assert!(decrypted.contains("|Hello...........|"));
+	assert_eq!(raw.unwrap().lines().count(), 2);
+	assert!(matches!(invalid, Err(cli::Error::InvalidInput(_))));
+}
+
+#[test]
+fn test_cli_hex_dump() {
+	assert!(!cli::is_binary(b"text\n"));
+	assert!(cli::is_binary(b"bin\0ary"));
+	assert_eq!(cli::hex_dump(b""), "");
+	let dump = cli::hex_dump(b"0123456789abcdefHi\0");
+	assert_eq!(dump.lines().count(), 2);
+	assert!(dump.starts_with("00000000  30 31"));
+	assert!(dump.ends_with("00000010  48 69 00                                         |Hi.|\n"));
+}
+
+#[test]
+fn test_cli_trash() {
+	let ref key = [9, 10];
+	let file = "cli_trash.pak";
+
+	temp_file!(file);
+
+	cli::new(file, key).unwrap();
+	cli::add(file, key, &[(b"a/example", EXAMPLE), (b"a/b/example", EXAMPLE)]).unwrap();
+	cli::trash(file, key, &[b"a"]).unwrap();
+	let trashed = cli::cat(file, key, b"a/example");
+	let reclaimable = cli::gc_dry_run(file, key).unwrap();
+	cli::restore(file, key, &[b"a"]).unwrap();
+	let restored = cli::cat(file, key, b"a/b/example");
+	let missing = cli::restore(file, key, &[b"a"]);
+
+	// Garbage collection empties the trash
+	cli::trash(file, key, &[b"a/b"]).unwrap();
+	cli::gc(file, key).unwrap();
+	let emptied = cli::restore(file, key, &[b"a/b"]);
+	let example = cli::cat(file, key, b"a/example");
+
+	assert!(matches!(trashed, Err(cli::Error::NotFound(_))));
+	assert!(reclaimable > 0);
+	assert_eq!(restored.unwrap(), EXAMPLE);
+	assert!(matches!(missing, Err(cli::Error::NotFound(_))));
+	assert!(matches!(emptied, Err(cli::Error::NotFound(_))));
+	assert_eq!(example.unwrap(), EXAMPLE);
+}
+
+#[test]
+fn test_cli_find() {
+	let ref key = [11, 12];
+	let file = "cli_find.pak";
+
+	temp_file!(file);
+
+	cli::new(file, key).unwrap();
+	cli::add(file, key, &[(b"a/example.txt", EXAMPLE), (b"a/b/small.txt", b"small"), (b"c/example.bin", EXAMPLE)]).unwrap();
+	let txt = cli::find(file, key, &Filter::Name(b"*.txt"));
+	let large = cli::find(file, key, &Filter::Under(b"a").and(Filter::LargerThan(10)));
+	let dirs = cli::find(file, key, &Filter::Dirs.and(!Filter::Under(b"a")));
+
+	assert_eq!(txt.unwrap(), "a/example.txt\na/b/small.txt\n");
+	assert_eq!(large.unwrap(), "a/example.txt\n");
+	assert_eq!(dirs.unwrap(), "a/\nc/\n");
+}
+
+#[cfg(feature = "regex")]
+#[test]
+fn test_cli_find_regex() {
+	let ref key = [13, 14];
+	let file = "cli_find_regex.pak";
+
+	temp_file!(file);
+
+	cli::new(file, key).unwrap();
+	cli::add(file, key, &[(b"a/level1.map", EXAMPLE), (b"a/level12.map", EXAMPLE), (b"b/intro.map", EXAMPLE)]).unwrap();
+	let levels = cli::find_regex(file, key, r"level\d+\.map$");
+	let dirs = cli::find_regex(file, key, "^[ab]$");
+	let invalid = cli::find_regex(file, key, "(");
+
+	assert_eq!(levels.unwrap(), "a/level1.map\na/level12.map\n");
+	assert_eq!(dirs.unwrap(), "a/\nb/\n");
+	assert!(matches!(invalid, Err(cli::Error::InvalidInput(_))));
+}
+
+#[test]
+fn test_cli_grep() {
+	let ref key = [15, 16];
+	let file = "cli_grep.pak";
+
+	temp_file!(file);
+
+	cli::new(file, key).unwrap();
+	cli::add(file, key, &[(b"a/one.txt", b"needle haystack needle"), (b"a/b/two.txt", b"hay"), (b"c/three.txt", b"needle")]).unwrap();
+	let all = cli::grep(file, key, b"needle", b"");
+	let under = cli::grep(file, key, b"needle", b"a");
+	let single = cli::grep(file, key, b"hay", b"a/b/two.txt");
+	let missing = cli::grep(file, key, b"needle", b"missing");
+
+	assert_eq!(all.unwrap(), "a/one.txt:0\na/one.txt:16\nc/three.txt:0\n");
+	assert_eq!(under.unwrap(), "a/one.txt:0\na/one.txt:16\n");
+	assert_eq!(single.unwrap(), "a/b/two.txt:0\n");
+	assert!(matches!(missing, Err(cli::Error::NotFound(_))));
+}
+
+#[test]
+fn test_cli_stat() {
+	let ref key = [17, 18];
+	let file = "cli_stat.pak";
+
+	temp_file!(file);
+
+	cli::new(file, key).unwrap();
+	cli::add(file, key, &[(b"a/example", EXAMPLE)]).unwrap();
+	cli::link(file, key, b"a/example", &[b"b/\"quoted\""]).unwrap();
+	let human = cli::stat(file, key, b"a/example", false);
+	let json = cli::stat(file, key, b"a/example", true);
+	let dir = cli::stat(file, key, b"a", false);
+	let missing = cli::stat(file, key, b"missing", true);
+
+	let human = human.unwrap();
+	assert!(human.contains(&format!("size: {} bytes\n", EXAMPLE.len())));
+	assert!(human.contains("meta: none\n"));
+	assert!(human.contains("nlink: 2\n"));
+	assert!(human.contains("links: b/\"quoted\"\n"));
+	let json = json.unwrap();
+	assert!(json.starts_with("{\"path\":\"a/example\",\"type\":\"file\",\"content_type\":1,"));
+	assert!(json.ends_with(",\"nlink\":2,\"meta\":false,\"links\":[\"b/\\\"quoted\\\"\"]}\n"));
+	assert!(dir.unwrap().contains("type: directory\nchildren: 1\n"));
+	assert!(matches!(missing, Err(cli::Error::NotFound(_))));
+}
+
+#[test]
+fn test_cli_ls() {
+	let ref key = [19, 20];
+	let file = "cli_ls.pak";
+
+	temp_file!(file);
+
+	cli::new(file, key).unwrap();
+	cli::add(file, key, &[(b"a/small.txt", b"small"), (b"b,c", b"")]).unwrap();
+	let list = cli::ls(file, key, false);
+	let csv = cli::ls(file, key, true);
+
+	assert_eq!(list.unwrap(), "dir           -  a/\nfile          5  a/small.txt\nfile          0  b,c\n");
+	let csv = csv.unwrap();
+	let lines: Vec<_> = csv.lines().collect();
+	assert_eq!(lines[0], "path,type,size,blocks,offset");
+	assert_eq!(lines[1], "a,dir,,,");
+	assert!(lines[2].starts_with("a/small.txt,file,5,1,"));
+	assert!(lines[3].starts_with("\"b,c\",file,0,0,"));
+}
+
+#[test]
+fn test_cli_import_listing() {
+	let ref key = [21, 22];
+	let file = "cli_import.pak";
+
+	temp_file!(file);
+	let base_dir = std::path::Path::new("tests/data");
+
+	cli::new(file, key).unwrap();
+	let imported = cli::import_listing(file, key, "source,destination,content_type,flags\n\
+		# comment\n\
+		example.txt,\"a/b,c\",7,seal\n\
+		missing.txt,a/missing,,optional\n\
+		example.txt\tt/tab\n", base_dir);
+	let read = cli::cat(file, key, b"a/b,c");
+	let tab = cli::cat(file, key, b"t/tab");
+	let missing = cli::cat(file, key, b"a/missing");
+	let sealed = cli::import_listing(file, key, "example.txt,a/new", base_dir);
+	let content_type = FileReader::open(file, key).map(|reader| reader.find_file(b"a/b,c").unwrap().content_type);
+	let invalid = cli::import_listing(file, key, "example.txt,x,0", base_dir);
+	let unknown = cli::import_listing(file, key, "example.txt,x,1,nope", base_dir);
+
+	assert_eq!(imported.unwrap(), 2);
+	assert_eq!(read.unwrap(), EXAMPLE);
+	assert_eq!(tab.unwrap(), EXAMPLE);
+	assert!(matches!(missing, Err(cli::Error::NotFound(_))));
+	assert!(sealed.is_err());
+	assert_eq!(content_type.unwrap(), 7);
+	assert!(matches!(invalid, Err(cli::Error::InvalidInput(_))));
+	assert!(matches!(unknown, Err(cli::Error::InvalidInput(_))));
+}
+
+#[test]
+fn test_cli_import_stripped() {
+	let ref key = [21, 23];
+	let (file, plain) = ("cli_import_stripped.pak", "cli_import_stripped_plain.pak");
+
+	temp_file!(file);
+	temp_file!(plain);
+	let base_dir = std::path::Path::new("tests/data");
+
+	cli::new(file, key).unwrap();
+	let mapping = cli::import_stripped(file, key, "example.txt,a/example\nexample.txt,b/example,3\n", base_dir);
+	let more = cli::import_stripped(file, key, "example.txt,c/example", base_dir);
+	let read = cli::cat(file, key, b"b/example");
+	let reader = FileReader::open(file, key);
+	cli::new(plain, key).unwrap();
+	cli::add(plain, key, &[(b"plain", b"plain")]).unwrap();
+	let refused = cli::import_stripped(plain, key, "example.txt,a/example", base_dir);
+
+	let mapping = mapping.unwrap();
+	assert_eq!(mapping.lines().count(), 2);
+	assert!(more.unwrap().ends_with("\tc/example\n"));
+	assert_eq!(read.unwrap(), EXAMPLE);
+	assert!(matches!(refused, Err(cli::Error::InvalidInput(_))));
+
+	let reader = reader.unwrap();
+	assert!(reader.name_policy().stripped);
+	assert_eq!(reader.file_count(), 3);
+	for line in mapping.lines() {
+		let (hash, path) = line.split_once('\t').unwrap();
+		let desc = reader.find_hashed(u64::from_str_radix(hash, 16).unwrap()).unwrap();
+		assert_eq!(reader.find_file(path.as_bytes()), Some(desc));
+	}
+	assert!(reader.find(b"a").is_none());
+}
+
+#[test]
+fn test_cli_overlay() {
+	let ref key = [23, 24];
+	let (base, new, file) = ("cli_overlay_base.pak", "cli_overlay_new.pak", "cli_overlay.pak");
+
+	temp_file!(base);
+	temp_file!(new);
+	temp_file!(file);
+
+	cli::new(base, key).unwrap();
+	cli::add(base, key, &[(b"same", EXAMPLE), (b"changed", b"old"), (b"removed", b"removed")]).unwrap();
+	cli::new(new, key).unwrap();
+	cli::add(new, key, &[(b"same", EXAMPLE), (b"changed", b"new"), (b"dir/added", b"added")]).unwrap();
+	let count = cli::overlay(file, key, base, new);
+	let list = cli::find(file, key, &Filter::Files);
+	let changed = cli::cat(file, key, b"changed");
+
+	assert_eq!(count.unwrap(), 2);
+	assert_eq!(list.unwrap(), "changed\ndir/added\n");
+	assert_eq!(changed.unwrap(), b"new");
+}
+
+#[test]
+fn test_cli_tags() {
+	let ref key = [25, 26];
+	let file = "cli_tags.pak";
+
+	temp_file!(file);
+
+	cli::new(file, key).unwrap();
+	cli::add(file, key, &[(b"ui/button", EXAMPLE), (b"ui/panel", b"panel"), (b"sound", b"sound")]).unwrap();
+	let tagged = cli::tag(file, key, "ui", &[b"ui/button", b"ui/panel", b"ui/button"], false);
+	cli::tag(file, key, "loud", &[b"sound"], false).unwrap();
+	let list = cli::tags(file, key, None);
+	let found = cli::tags(file, key, Some("ui"));
+	let untagged = cli::tag(file, key, "ui", &[b"ui/panel"], true);
+	let found_after = cli::tags(file, key, Some("ui"));
+	let missing = cli::tag(file, key, "ui", &[b"missing"], false);
+	let invalid = cli::tag(file, key, "", &[b"sound"], false);
+	let content = cli::cat(file, key, b"ui/button");
+
+	assert_eq!(tagged.unwrap(), 2);
+	assert_eq!(list.unwrap(), "loud: 1\nui: 2\n");
+	assert_eq!(found.unwrap(), "ui/button\nui/panel\n");
+	assert_eq!(untagged.unwrap(), 1);
+	assert_eq!(found_after.unwrap(), "ui/button\n");
+	assert!(matches!(missing, Err(cli::Error::NotFound(_))));
+	assert!(matches!(invalid, Err(cli::Error::InvalidInput(_))));
+	assert_eq!(content.unwrap(), EXAMPLE);
+}
+
+#[test]
+fn test_cli_cp() {
+	let ref key = [27, 28];
+	let file = "cli_cp.pak";
+
+	temp_file!(file);
+
+	cli::new(file, key).unwrap();
+	cli::add(file, key, &[(b"a", EXAMPLE)]).unwrap();
+	cli::tag(file, key, "ui", &[b"a"], false).unwrap();
+	cli::cp(file, key, b"a", b"link", false).unwrap();
+	cli::cp(file, key, b"a", b"dir/deep", true).unwrap();
+	let link = cli::stat(file, key, b"link", false);
+	let deep = cli::stat(file, key, b"dir/deep", false);
+	let content = cli::cat(file, key, b"dir/deep");
+	let tagged = cli::tags(file, key, Some("ui"));
+	let missing = cli::cp(file, key, b"missing", b"b", false);
+	let dir = cli::cp(file, key, b"a", b"dir", true);
+
+	assert!(link.unwrap().contains("nlink: 2\n"));
+	let deep = deep.unwrap();
+	assert!(deep.contains("nlink: 1\n"));
+	assert!(deep.contains("meta: present\n"));
+	assert_eq!(content.unwrap(), EXAMPLE);
+	assert_eq!(tagged.unwrap(), "a\nlink\ndir/deep\n");
+	assert!(matches!(missing, Err(cli::Error::NotFound(_))));
+	assert!(matches!(dir, Err(cli::Error::InvalidInput(_))));
+}
+
+#[test]
+fn test_cli_key_split() {
+	let key = cli::parse_key("0123456789abcdeffedcba9876543210").unwrap();
+	assert_eq!(cli::format_key(&key), "0123456789abcdeffedcba9876543210");
+
+	let shares = cli::key_split(&key, 3, 2).unwrap();
+	let shares: Vec<&str> = shares.lines().collect();
+	assert_eq!(shares.len(), 3);
+	assert_eq!(cli::key_combine(&[shares[2], shares[0]]).unwrap(), key);
+
+	assert!(matches!(cli::key_split(&key, 2, 3), Err(cli::Error::InvalidInput(_))));
+	assert!(matches!(cli::key_combine(&[shares[1], shares[1]]), Err(cli::Error::InvalidInput(_))));
+	assert!(matches!(cli::key_combine(&["01-xyz"]), Err(cli::Error::InvalidInput(_))));
+}
+
+#[test]
+fn test_cli_keygen() {
+	let key = crate::key::generate();
+	assert_eq!(cli::parse_key(&cli::format_key(&key)).unwrap(), key);
+
+	let words = cli::format_key_words(&key);
+	assert_eq!(words.split(' ').count(), 16);
+	assert_eq!(cli::parse_key(&words.to_uppercase()).unwrap(), key);
+	assert!(matches!(cli::parse_key("acid acorn"), Err(cli::Error::BadKey(_))));
+	assert!(matches!(cli::parse_key("acid xyzzy"), Err(cli::Error::BadKey(_))));
+
+	let qr = cli::format_key_qr(&key);
+	assert_eq!(qr.lines().count(), 17);
+	assert!(qr.lines().all(|line| line.chars().count() == 33));
+}
+
+#[test]
+fn test_cli_log() {
+	let ref key = [5, 6];
+	let file = "cli_log.pak";
+
+	temp_file!(file);
+
+	cli::new(file, key).unwrap();
+	cli::log_change(file, key, "alice", "Initial import").unwrap();
+	cli::log_change(file, key, "bob", "Fix typo").unwrap();
+	let log = cli::log(file, key).unwrap();
+
+	let lines: Vec<&str> = log.lines().collect();
+	assert_eq!(lines.len(), 2);
+	assert!(lines[0].ends_with(" alice: Initial import"));
+	assert!(lines[1].ends_with(" bob: Fix typo"));
+
+	assert_eq!(cli::format_utc(0), "1970-01-01 00:00:00");
+	assert_eq!(cli::format_utc(951782400 + 3723), "2000-02-29 01:02:03");
+}
+
+#[test]
+fn test_cli_stat_archive() {
+	let ref key = [7, 8];
+	let file = "cli_stat_archive.pak";
+
+	temp_file!(file);
+
+	cli::new(file, key).unwrap();
+	let unstamped = cli::stat(file, key, b"", true);
+	// The name policy flags share the field with the block size
+	let mut edit = FileEditor::open(file, key).unwrap();
+	assert!(edit.set_name_policy(NamePolicy { nfc: true, case_fold: true, stripped: false }));
+	edit.set_provenance(&BuildInfo {
+		tool: String::from("assetc"),
+		version: String::from("1.4.2"),
+		commit: String::from("9f0c2e1"),
+		timestamp: 951782400,
+	}, key).unwrap();
+	edit.finish(key).unwrap();
+	let human = cli::stat(file, key, b"", false);
+	let json = cli::stat(file, key, b"", true);
+
+	assert!(unstamped.unwrap().ends_with(",\"provenance\":null}\n"));
+	assert!(json.as_ref().unwrap().starts_with("{\"block_size\":16,"));
+	let human = human.unwrap();
+	assert!(human.starts_with("block size: 16 bytes\n"));
+	assert!(human.contains("built by: assetc 1.4.2\ncommit: 9f0c2e1\nbuilt at: 2000-02-29 00:00:00\n"));
+	assert!(json.unwrap().ends_with(",\"provenance\":{\"tool\":\"assetc\",\"version\":\"1.4.2\",\"commit\":\"9f0c2e1\",\"timestamp\":951782400}}\n"));
+}
+
+#[test]
+fn test_cli_verify() {
+	let ref key = [9, 10];
+	let file = "cli_verify.pak";
+
+	temp_file!(file);
+
+	// Nothing to scrub in an empty PAK file
+	cli::new(file, key).unwrap();
+	assert!(cli::verify(file, key, true, false).unwrap().contains("checksums: ok (0 sections, 0 damaged)\n"));
+
+	cli::add(file, key, &[(b"a/example", EXAMPLE)]).unwrap();
+	let pass = cli::verify(file, key, false, true);
+
+	// Flip a bit in the file data
+	let reader = FileReader::open(file, key).unwrap();
+	let offset = reader.find_file(b"a/example").unwrap().section.offset as usize * reader.info().block_size() as usize;
+	let mut data = std::fs::read(file).unwrap();
+	data[offset] ^= 1;
+	std::fs::write(file, &data).unwrap();
+	let shallow = cli::verify(file, key, false, false);
+	let fail = cli::verify(file, key, true, false);
+
+	let pass = pass.unwrap();
+	assert!(pass.contains("directory: ok (2 entries)\nchecksums: ok (1 sections, 0 damaged)\nmetadata: ok (1 files)\n"));
+	assert!(pass.ends_with("PASS\n"));
+	assert!(shallow.unwrap().ends_with("PASS\n"));
+	match fail {
+		Err(cli::Error::Corrupt(log)) => assert!(log.contains("damaged section") && log.ends_with("a/example\nFAIL")),
+		_ => panic!("expected a corrupt PAK file"),
+	}
+}
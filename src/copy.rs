@@ -0,0 +1,131 @@
+use std::io::{self, ErrorKind};
+use crate::*;
+
+/// How a file is copied, see [`MemoryEditor::copy_within`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum CopyMode {
+	/// The copy shares the file data and metadata of the source file, see [`Directory::create_link`].
+	Link,
+	/// The file data and metadata are re-encrypted into new sections independent of the source file.
+	Deep,
+}
+
+impl Directory {
+	// Checks the copy and returns the handle and descriptor of the source file
+	fn copy_source(&self, src_path: &[u8], dest_path: &[u8]) -> Result<(DescriptorId, Descriptor), ErrorKind> {
+		let (src_id, src_desc) = match self.find(src_path) {
+			Some(id) => match self.get(id) {
+				Some(desc) if desc.is_file() => (id, *desc),
+				_ => return Err(ErrorKind::NotFound),
+			},
+			None => return Err(ErrorKind::NotFound),
+		};
+		if !self.writable(dest_path) {
+			return Err(ErrorKind::PermissionDenied);
+		}
+		if matches!(self.find_desc(dest_path), Some(desc) if desc.is_dir()) {
+			return Err(ErrorKind::InvalidInput);
+		}
+		Ok((src_id, src_desc))
+	}
+}
+
+impl MemoryEditor {
+	/// Copies the file at the src path to the dest path.
+	///
+	/// Any missing parent directories are automatically created and a file at the dest path is replaced.
+	///
+	/// With [`CopyMode::Link`] the copy shares the sections of the source file like [`create_link`](Directory::create_link).
+	/// With [`CopyMode::Deep`] the contents and [metadata](Descriptor::meta_section) are decrypted and encrypted into new sections,
+	/// modifying or [shredding](Self::shred) either file afterwards does not affect the other.
+	/// In both modes the copy has the same content type and [tags](Self::tag) as the source file.
+	///
+	/// # Errors
+	///
+	/// * [`ErrorKind::NotFound`]: The src path is not a file.
+	/// * [`ErrorKind::PermissionDenied`]: The dest path is [sealed](Directory::seal).
+	/// * [`ErrorKind::InvalidInput`]: The dest path is a directory.
+	/// * [`ErrorKind::InvalidData`]: The file data or metadata of a deep copy failed to decrypt with the given key.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use paks::CopyMode;
+	///
+	/// let ref key = paks::Key::default();
+	/// let mut editor = paks::MemoryEditor::new();
+	/// editor.create_file(b"a", b"data", key);
+	/// editor.edit_file(b"a").set_preview(b"preview", key).unwrap();
+	///
+	/// editor.copy_within(b"a", b"link", CopyMode::Link, key).unwrap();
+	/// editor.copy_within(b"a", b"copy", CopyMode::Deep, key).unwrap();
+	///
+	/// let a = *editor.find_file(b"a").unwrap();
+	/// let copy = *editor.find_file(b"copy").unwrap();
+	/// assert_eq!(editor.nlink(&a), 2);
+	/// assert_ne!(copy.section, a.section);
+	/// assert_eq!(editor.read_data(&copy, key).unwrap(), b"data");
+	/// ```
+	pub fn copy_within(&mut self, src_path: &[u8], dest_path: &[u8], mode: CopyMode, key: &Key) -> Result<&Descriptor, ErrorKind> {
+		let (src_id, src_desc) = self.copy_source(src_path, dest_path)?;
+		let dest_id = match mode {
+			CopyMode::Link => self.create_link(dest_path, &src_desc).ok_or(ErrorKind::PermissionDenied)?,
+			CopyMode::Deep => {
+				let data = self.read_data(&src_desc, key)?;
+				let meta = match src_desc.meta_section() {
+					Some(section) => Some(MetaMap::decode(self.read_section(&section, key)?.as_bytes()).ok_or(ErrorKind::InvalidData)?),
+					None => None,
+				};
+				let mut edit_file = self.edit_file(dest_path);
+				edit_file.set_content(src_desc.content_type, src_desc.content_size);
+				edit_file.allocate_data()?.write_data(&data, key);
+				if let Some(meta) = &meta {
+					edit_file.set_meta(meta, key)?;
+				}
+				self.find(dest_path).ok_or(ErrorKind::NotFound)?
+			},
+		};
+		self.copy_tags(src_id, dest_id);
+		self.get(dest_id).ok_or(ErrorKind::NotFound)
+	}
+}
+
+impl<S: Storage> FileEditor<S> {
+	/// Copies the file at the src path to the dest path.
+	///
+	/// See [`MemoryEditor::copy_within`] for more information.
+	pub fn copy_within(&mut self, src_path: &[u8], dest_path: &[u8], mode: CopyMode, key: &Key) -> io::Result<&Descriptor> {
+		let (src_id, src_desc) = self.copy_source(src_path, dest_path)?;
+		let dest_id = match mode {
+			CopyMode::Link => match self.create_link(dest_path, &src_desc) {
+				Some(id) => id,
+				None => Err(io::ErrorKind::PermissionDenied)?,
+			},
+			CopyMode::Deep => {
+				let data = self.read_data(&src_desc, key)?;
+				let meta = match src_desc.meta_section() {
+					Some(section) => match MetaMap::decode(self.read_section(&section, key)?.as_bytes()) {
+						Some(meta) => Some(meta),
+						None => Err(io::ErrorKind::InvalidData)?,
+					},
+					None => None,
+				};
+				let mut edit_file = self.edit_file(dest_path);
+				edit_file.set_content(src_desc.content_type, src_desc.content_size);
+				edit_file.allocate_data()?.write_data(&data, key)?;
+				if let Some(meta) = &meta {
+					edit_file.set_meta(meta, key)?;
+				}
+				match self.find(dest_path) {
+					Some(id) => id,
+					None => Err(io::ErrorKind::NotFound)?,
+				}
+			},
+		};
+		self.copy_tags(src_id, dest_id);
+		match self.get(dest_id) {
+			Some(desc) => Ok(desc),
+			None => Err(io::ErrorKind::NotFound)?,
+		}
+	}
+}
@@ -7,6 +7,8 @@ Bring Your Own Authenticated Encryption based on https://eprint.iacr.org/2019/71
 The chosen cipher is SPECK128/128, the mode of operation is CTR.
 The authentication is CBC-MAC over the ciphertext.
 
+Public sections are marked by their nonce and skip the CTR encryption, the CBC-MAC is computed over the plaintext.
+
 ```text
                                      PLAINTEXT(n)
                                           │
@@ -27,7 +29,7 @@ The authentication is CBC-MAC over the ciphertext.
 ```
 */
 
-use std::slice;
+use std::{cell::Cell, io, ops, slice, sync::atomic::Ordering};
 use crate::*;
 use dataview::Pod;
 
@@ -37,23 +39,78 @@ fn xor(a: Block, b: Block) -> Block {
 fn counter(nonce: Block, i: usize) -> Block {
 	[nonce[0], nonce[1].wrapping_add(i as u64)]
 }
-fn random(blocks: &mut [Block]) {
+pub fn random(blocks: &mut [Block]) {
 	if let Err(_) = getrandom::getrandom(blocks.as_bytes_mut()) {
 		random_error()
 	}
 }
 
+thread_local! {
+	static LAST_NONCE: Cell<Block> = const { Cell::new([0, 0]) };
+}
+
+// Attempts at generating a fresh nonce before the random generator is considered broken
+const NONCE_ATTEMPTS: u32 = 16;
+
+// Generates a random nonce.
+// Guards against a misbehaving random generator returning the same nonce twice in a row.
+fn random_nonce(nonce: &mut Block) {
+	LAST_NONCE.with(|last| {
+		for _ in 0..NONCE_ATTEMPTS {
+			random(slice::from_mut(nonce));
+			metrics::NONCES_GENERATED.fetch_add(1, Ordering::Relaxed);
+			// The public nonce marker is never generated for encrypted sections
+			if *nonce != last.get() && nonce[0] != Section::PUBLIC_NONCE {
+				last.set(*nonce);
+				return;
+			}
+			metrics::NONCE_COLLISIONS.fetch_add(1, Ordering::Relaxed);
+		}
+		random_error()
+	});
+}
+
 #[inline(never)]
 #[cold]
 fn random_error() -> ! {
 	panic!("random unavailable")
 }
 
+// Overwrites the bytes in the range of the section's contents with random data and re-encrypts it.
+// Returns false if the MAC check fails or the range is out of bounds, the blocks are left unchanged.
+pub fn shred_section(blocks: &mut [Block], section: &mut Section, range: ops::Range<usize>, key: &Key) -> bool {
+	let mut plain = blocks.to_vec();
+	if !decrypt_section(&mut plain, section, key) {
+		return false;
+	}
+	let bytes = match plain.as_bytes_mut().get_mut(range) {
+		Some(bytes) => bytes,
+		None => return false,
+	};
+	if getrandom::getrandom(bytes).is_err() {
+		random_error()
+	}
+	encrypt_section(&mut plain, section, key);
+	blocks.copy_from_slice(&plain);
+	true
+}
+
 #[inline(never)]
-pub fn encrypt_section(blocks: &mut [Block], section: &mut Section, &key: &Key) {
+pub fn encrypt_section(blocks: &mut [Block], section: &mut Section, key: &Key) {
 	// Every encryption reinitialize with a random nonce
-	random(slice::from_mut(&mut section.nonce));
+	// Public sections stay public
+	let public = section.is_public();
+	random_nonce(&mut section.nonce);
+	if public {
+		section.nonce[0] = Section::PUBLIC_NONCE;
+	}
+	encrypt_section_with_nonce(blocks, section, key);
+}
 
+// Encrypts with the nonce already in the section.
+// Never reuse a nonce with the same key, only used for known-answer tests.
+pub fn encrypt_section_with_nonce(blocks: &mut [Block], section: &mut Section, &key: &Key) {
+	let span = span!(TRACE, "encrypt_section", offset = section.offset, size = blocks.len());
 	// Derive new keys and nonces and expand the round keys
 	let rk = cipher::expand(key);
 	let rke = cipher::expand(cipher::encrypt(counter(section.nonce, 0), &rk));
@@ -61,37 +118,86 @@ pub fn encrypt_section(blocks: &mut [Block], section: &mut Section, &key: &Key)
 	let ne = cipher::encrypt(counter(section.nonce, 2), &rk);
 	let nm = cipher::encrypt(counter(section.nonce, 3), &rk);
 
+	let public = section.is_public();
 	let mut mac = nm;
 	for i in 0..blocks.len() {
 		let pt = blocks[i];
-		let ct = xor(cipher::encrypt(counter(ne, i), &rke), pt);
+		let ct = if public { pt } else { xor(cipher::encrypt(counter(ne, i), &rke), pt) };
 		mac = cipher::encrypt(xor(mac, ct), &rkm);
 		blocks[i] = ct;
 	}
 	section.mac = mac;
+	span.finish((blocks.len() * BLOCK_SIZE) as u64);
 }
 
 #[inline(never)]
-pub fn decrypt_section(blocks: &mut [Block], section: &Section, &key: &Key) -> bool {
-	// Derive new keys and nonces and expand the round keys
+pub fn decrypt_section(blocks: &mut [Block], section: &Section, key: &Key) -> bool {
+	let span = span!(TRACE, "decrypt_section", offset = section.offset, size = blocks.len());
+	let mut decryptor = Decryptor::new(section, key);
+	decryptor.update(blocks);
+	let success = decryptor.finish();
+	if success {
+		span.finish((blocks.len() * BLOCK_SIZE) as u64);
+	}
+	success
+}
+
+// Computes the MAC of the encrypted blocks without decrypting them
+pub fn verify_mac(blocks: &[Block], section: &Section, &key: &Key) -> bool {
 	let rk = cipher::expand(key);
-	let rke = cipher::expand(cipher::encrypt(counter(section.nonce, 0), &rk));
 	let rkm = cipher::expand(cipher::encrypt(counter(section.nonce, 1), &rk));
-	let ne = cipher::encrypt(counter(section.nonce, 2), &rk);
-	let nm = cipher::encrypt(counter(section.nonce, 3), &rk);
-
-	let mut mac = nm;
-	for i in 0..blocks.len() {
-		let ct = blocks[i];
-		let pt = xor(cipher::encrypt(counter(ne, i), &rke), ct);
+	let mut mac = cipher::encrypt(counter(section.nonce, 3), &rk);
+	for &ct in blocks {
 		mac = cipher::encrypt(xor(mac, ct), &rkm);
-		blocks[i] = pt;
 	}
-
 	// Constant-time comparison of the mac
 	section.mac[0] ^ mac[0] | section.mac[1] ^ mac[1] == 0
 }
 
+// Decrypts a section in consecutive chunks of blocks
+// The MAC is only known after the last chunk, the decrypted chunks must not be trusted before `finish` succeeds
+pub struct Decryptor {
+	rke: [u64; cipher::ROUNDS],
+	rkm: [u64; cipher::ROUNDS],
+	ne: Block,
+	mac: Block,
+	expected: Block,
+	public: bool,
+	// Index of the next block in the section
+	index: usize,
+}
+
+impl Decryptor {
+	pub fn new(section: &Section, &key: &Key) -> Decryptor {
+		// Derive new keys and nonces and expand the round keys
+		let rk = cipher::expand(key);
+		let rke = cipher::expand(cipher::encrypt(counter(section.nonce, 0), &rk));
+		let rkm = cipher::expand(cipher::encrypt(counter(section.nonce, 1), &rk));
+		let ne = cipher::encrypt(counter(section.nonce, 2), &rk);
+		let nm = cipher::encrypt(counter(section.nonce, 3), &rk);
+		Decryptor { rke, rkm, ne, mac: nm, expected: section.mac, public: section.is_public(), index: 0 }
+	}
+
+	// Decrypts the next chunk of the section inplace
+	pub fn update(&mut self, blocks: &mut [Block]) {
+		let mut mac = self.mac;
+		for (i, block) in blocks.iter_mut().enumerate() {
+			let ct = *block;
+			let pt = if self.public { ct } else { xor(cipher::encrypt(counter(self.ne, self.index + i), &self.rke), ct) };
+			mac = cipher::encrypt(xor(mac, ct), &self.rkm);
+			*block = pt;
+		}
+		self.mac = mac;
+		self.index += blocks.len();
+	}
+
+	// Returns whether the MAC of the decrypted chunks is correct
+	pub fn finish(self) -> bool {
+		// Constant-time comparison of the mac
+		self.expected[0] ^ self.mac[0] | self.expected[1] ^ self.mac[1] == 0
+	}
+}
+
 #[test]
 fn test_roundtrip() {
 	let data = [[1, 2], [3, 4], [5, !0]];
@@ -113,16 +219,27 @@ fn test_roundtrip() {
 	assert_eq!(data, blocks);
 }
 
-#[inline]
+// Encrypts the info header with a random nonce carrying the salted key verifier in its upper 32 bits
 pub fn encrypt_header(header: &mut Header, key: &Key) {
 	header.info.version = InfoHeader::VERSION;
-	header.info._unused = 0;
-	let mut section = Section::default();
-	crypt::encrypt_section(header.info.as_mut(), &mut section, key);
+	let mut section = Header::SECTION;
+	random_nonce(&mut section.nonce);
+	section.nonce[1] = section.nonce[1] & 0xffffffff | (key::verifier(key, section.nonce[0]) as u64) << 32;
+	encrypt_section_with_nonce(header.info.as_mut(), &mut section, key);
 	header.nonce = section.nonce;
 	header.mac = section.mac;
 }
 
+// Explains why the header failed to decrypt, the key does not match its verifier or the header is corrupted
+pub fn header_error(header: &Header, key: &Key) -> io::Error {
+	if (header.nonce[1] >> 32) as u32 != key::verifier(key, header.nonce[0]) {
+		io::Error::new(io::ErrorKind::InvalidData, key::WrongKey { fingerprint: key::fingerprint(key) })
+	}
+	else {
+		io::Error::new(io::ErrorKind::InvalidData, "corrupt or unsupported header")
+	}
+}
+
 #[inline]
 pub fn decrypt_header(header: &mut Header, key: &Key) -> bool {
 	let section = Section {
@@ -130,6 +247,14 @@ pub fn decrypt_header(header: &mut Header, key: &Key) -> bool {
 		mac: header.mac,
 		..Header::SECTION
 	};
-	crypt::decrypt_section(header.info.as_mut(), &section, key)
-		&& header.info.version == InfoHeader::VERSION
+	if !crypt::decrypt_section(header.info.as_mut(), &section, key) || header.info.version != InfoHeader::VERSION {
+		return false;
+	}
+	// Older PAK files did not store the block size
+	if header.info.block_size == 0 {
+		header.info.block_size = BLOCK_SIZE as u32;
+	}
+	// Unknown flags change how the directory is interpreted
+	let known_flags = InfoHeader::FLAG_NFC | InfoHeader::FLAG_CASE_FOLD | InfoHeader::FLAG_STRIPPED;
+	InfoHeader::BLOCK_SIZES.contains(&header.info.block_size()) && header.info.flags() & !known_flags == 0
 }
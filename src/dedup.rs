@@ -0,0 +1,102 @@
+use std::{collections::{HashMap, HashSet}, io};
+use crate::*;
+
+// Finds the sections whose plaintext equals that of an earlier section in directory order
+// Returns the duplicate sections mapped to the section holding the same contents
+// Packed files and files whose contents cannot be read are left alone
+fn find_duplicates<F>(directory: &Directory, key: &Key, mut read: F) -> io::Result<HashMap<Section, Section>>
+	where F: FnMut(&Descriptor) -> io::Result<Option<Vec<u8>>>
+{
+	let mut seen = HashSet::new();
+	let mut originals: HashMap<(Block, u32, bool), Vec<&Descriptor>> = HashMap::new();
+	let mut duplicates = HashMap::new();
+	for desc in directory.as_ref() {
+		if !desc.is_file() || desc.is_packed() || desc.section.size == 0 || !seen.insert(desc.section) {
+			continue;
+		}
+		let data = match read(desc)? {
+			Some(data) => data,
+			None => continue,
+		};
+		// The hash is keyed, only confirm the contents of the candidates
+		let candidates = originals.entry((backup::content_hash(&data, key), desc.content_size, desc.section.is_public())).or_default();
+		let mut original = None;
+		for &candidate in candidates.iter() {
+			if read(candidate)?.as_deref() == Some(&data[..]) {
+				original = Some(candidate.section);
+				break;
+			}
+		}
+		match original {
+			Some(section) => { duplicates.insert(desc.section, section); },
+			None => candidates.push(desc),
+		}
+	}
+	Ok(duplicates)
+}
+
+// Rewrites the file descriptors of the duplicate sections as links
+// Returns the number of file descriptors rewritten
+fn relink(directory: &mut Directory, duplicates: &HashMap<Section, Section>) -> usize {
+	let mut count = 0;
+	for desc in directory.descs_mut() {
+		if desc.is_file() && !desc.is_pinned() {
+			if let Some(&section) = duplicates.get(&desc.section) {
+				desc.section = section;
+				count += 1;
+			}
+		}
+	}
+	count
+}
+
+impl MemoryEditor {
+	/// Compacts the referenced data blocks after collapsing files with identical contents.
+	///
+	/// The contents of every file are decrypted and hashed with the key, the ciphertexts differ as every section has its own nonce.
+	/// Files whose contents equal those of an earlier file in directory order are rewritten as [links](Directory::create_link) to its section.
+	/// Their sections are left behind as garbage and reclaimed by [`gc`](Self::gc) at the end.
+	///
+	/// The metadata and content type of every file is kept, packed files, [pinned](Descriptor::is_pinned) files and files which fail to decrypt are not deduplicated.
+	///
+	/// Returns the number of file descriptors rewritten as links.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// let ref key = paks::Key::default();
+	/// let mut editor = paks::MemoryEditor::new();
+	/// editor.create_file(b"levels/1/wall.png", &[7; 1000], key);
+	/// editor.create_file(b"levels/2/wall.png", &[7; 1000], key);
+	///
+	/// assert_eq!(editor.gc_dedup(key), 1);
+	/// assert_eq!(editor.find_file(b"levels/1/wall.png").unwrap().section, editor.find_file(b"levels/2/wall.png").unwrap().section);
+	/// ```
+	pub fn gc_dedup(&mut self, key: &Key) -> usize {
+		// Reading memory fails only for damaged sections
+		let duplicates = find_duplicates(self, key, |desc| Ok(self.read_data(desc, key).ok())).unwrap_or_default();
+		let count = relink(self, &duplicates);
+		self.gc();
+		count
+	}
+}
+
+impl<S: Storage> FileEditor<S> {
+	/// Compacts the referenced data blocks after collapsing files with identical contents.
+	///
+	/// See [`MemoryEditor::gc_dedup`] for more information and [`gc`](Self::gc) for its consistency guarantees.
+	///
+	/// # Errors
+	///
+	/// * [`io::Error`]: An error encountered reading the file data or moving it.
+	pub fn gc_dedup(&mut self, key: &Key) -> io::Result<usize> {
+		let duplicates = find_duplicates(self, key, |desc| match self.read_data(desc, key) {
+			Ok(data) => Ok(Some(data)),
+			Err(err) if matches!(err.kind(), io::ErrorKind::InvalidData | io::ErrorKind::InvalidInput) => Ok(None),
+			Err(err) => Err(err),
+		})?;
+		let count = relink(self, &duplicates);
+		self.gc()?;
+		Ok(count)
+	}
+}
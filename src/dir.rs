@@ -10,9 +10,43 @@ There are two types, directories and files, which share the same [`Descriptor`]
 * File descriptors have their `content_type` non-zero (the interpretation of the value is left to the user) and the `content_size` specifies the size of the file in bytes.
 */
 
-use std::{cmp, fmt, str};
+use std::{borrow::Cow, cmp, fmt, mem, ops, str};
 use crate::*;
 
+fn is_separator(&chr: &u8) -> bool {
+	chr == b'/' || chr == b'\\'
+}
+
+/// Normalizes the path by collapsing repeated separators and stripping leading and trailing separators.
+///
+/// The functions in this module normalize their path arguments, `a//b/` and `a/b` refer to the same descriptor.
+/// Returns an empty path if the path has no components.
+///
+/// # Examples
+///
+/// ```
+/// use paks::dir::normalize;
+///
+/// assert_eq!(&*normalize(b"a/b"), b"a/b");
+/// assert_eq!(&*normalize(b"/a//b\\\\c/"), b"a/b/c");
+/// assert_eq!(&*normalize(b"//"), b"");
+/// ```
+pub fn normalize(path: &[u8]) -> Cow<'_, [u8]> {
+	let is_normal = !path.first().is_some_and(is_separator) && !path.last().is_some_and(is_separator)
+		&& !path.windows(2).any(|pair| is_separator(&pair[0]) && is_separator(&pair[1]));
+	if is_normal {
+		return Cow::Borrowed(path);
+	}
+	let mut normal = Vec::with_capacity(path.len());
+	for name in path.split(is_separator).filter(|name| !name.is_empty()) {
+		if !normal.is_empty() {
+			normal.push(b'/');
+		}
+		normal.extend_from_slice(name);
+	}
+	Cow::Owned(normal)
+}
+
 /// Compares if the next component of the path matches the file descriptor.
 ///
 /// Returns None if the path does not match, otherwise returns the path with the descriptor's name removed.
@@ -141,6 +175,14 @@ pub fn next_sibling(desc: &Descriptor, i: usize, end: usize) -> usize {
 pub fn find_desc<'a>(dir: &'a [Descriptor], path: &[u8]) -> Option<&'a Descriptor> {
 	find(dir, path).get(0)
 }
+/// Finds the index of the descriptor at the given path.
+pub fn find_index(dir: &[Descriptor], path: &[u8]) -> Option<usize> {
+	let found = find(dir, path);
+	if found.is_empty() {
+		return None;
+	}
+	Some((found.as_ptr() as usize - dir.as_ptr() as usize) / mem::size_of::<Descriptor>())
+}
 pub fn find_dir<'a>(dir: &'a [Descriptor], path: &[u8]) -> Option<&'a [Descriptor]> {
 	if path.len() == 0 {
 		Some(dir)
@@ -159,7 +201,9 @@ pub fn find_dir<'a>(dir: &'a [Descriptor], path: &[u8]) -> Option<&'a [Descripto
 /// Returns a slice with length larger than or equal to one if a directory descriptor was found at the given path.
 /// The first entry in the slice is the directory descriptor, the tail are the child descriptors contained within the directory.
 /// These children also contain any subdirectories of the returned directory.
-pub fn find<'a>(dir: &'a [Descriptor], mut path: &[u8]) -> &'a [Descriptor] {
+pub fn find<'a>(dir: &'a [Descriptor], path: &[u8]) -> &'a [Descriptor] {
+	let normal = normalize(path);
+	let mut path = &normal[..];
 	// Reject empty paths
 	if path.len() == 0 {
 		return &dir[..0];
@@ -191,6 +235,220 @@ pub fn find<'a>(dir: &'a [Descriptor], mut path: &[u8]) -> &'a [Descriptor] {
 	return &dir[..0];
 }
 
+/// Iterator over the full paths of the descriptors, see [`paths`].
+#[derive(Clone, Debug)]
+pub struct Paths<'a> {
+	dir: &'a [Descriptor],
+	i: usize,
+	// End index and path length of the enclosing directories
+	stack: Vec<(usize, usize)>,
+	path: Vec<u8>,
+	// Length of the path to keep when advancing
+	base: usize,
+}
+impl<'a> Paths<'a> {
+	// Advances to the next descriptor and returns its full path without allocating
+	pub(crate) fn next_path(&mut self) -> Option<(&[u8], &'a Descriptor)> {
+		// Remove the name of the previous file
+		self.path.truncate(self.base);
+		// Leave the directories which have been fully visited
+		while let Some(&(end, len)) = self.stack.last() {
+			if self.i < end {
+				break;
+			}
+			self.stack.pop();
+			self.path.truncate(len);
+		}
+		let end = self.stack.last().map_or(self.dir.len(), |&(end, _)| end);
+		if self.i >= end {
+			return None;
+		}
+
+		let desc = &self.dir[self.i];
+		let len = self.path.len();
+		self.path.extend_from_slice(desc.name());
+		let path_len = self.path.len();
+		if desc.is_dir() {
+			self.stack.push((next_sibling(desc, self.i, end), len));
+			self.path.push(b'/');
+			self.base = self.path.len();
+		}
+		else {
+			self.base = len;
+		}
+		self.i += 1;
+		Some((&self.path[..path_len], desc))
+	}
+}
+impl<'a> Iterator for Paths<'a> {
+	type Item = Vec<u8>;
+	fn next(&mut self) -> Option<Vec<u8>> {
+		self.next_path().map(|(path, _)| path.to_vec())
+	}
+}
+
+/// Returns the full path of every descriptor in traversal order.
+///
+/// The paths are joined with `/` and yielded in the order of the descriptors, parents before their children.
+/// Zip the paths with the descriptors to visit both.
+///
+/// # Examples
+///
+/// ```
+/// use paks::Descriptor;
+///
+/// let dir = [
+///     Descriptor::dir(b"Foo", 2),
+///     Descriptor::file(b"Bar"),
+///     Descriptor::dir(b"Sub", 0),
+///     Descriptor::file(b"File"),
+/// ];
+///
+/// let paths: Vec<_> = paks::dir::paths(&dir).collect();
+/// assert_eq!(paths, [&b"Foo"[..], b"Foo/Bar", b"Foo/Sub", b"File"]);
+///
+/// for (path, desc) in paks::dir::paths(&dir).zip(&dir) {
+///     assert!(path.ends_with(desc.name()));
+/// }
+/// ```
+pub fn paths(dir: &[Descriptor]) -> Paths<'_> {
+	Paths { dir, i: 0, stack: Vec::new(), path: Vec::new(), base: 0 }
+}
+
+/// Returns the full path of the descriptor at the given index.
+///
+/// Returns `None` if the index is out of bounds.
+pub fn path_of(dir: &[Descriptor], index: usize) -> Option<Vec<u8>> {
+	let mut path = Vec::new();
+	let mut i = 0;
+	let mut end = dir.len();
+	while i < end {
+		let desc = &dir[i];
+		let next_i = next_sibling(desc, i, end);
+		// Descend into the sibling containing the descriptor
+		if index < next_i {
+			path.extend_from_slice(desc.name());
+			if index == i {
+				return Some(path);
+			}
+			path.push(b'/');
+			i += 1;
+			end = next_i;
+			continue;
+		}
+		i = next_i;
+	}
+	None
+}
+
+/// Returns if the descriptor at the given path or any of its parent directories is sealed.
+///
+/// The path does not need to exist, the existing parent directories are checked.
+pub fn is_sealed(dir: &[Descriptor], path: &[u8]) -> bool {
+	let normal = normalize(path);
+	let mut path = &normal[..];
+	let mut i = 0;
+	let mut end = dir.len();
+	while i < end {
+		let desc = &dir[i];
+		let next_i = next_sibling(desc, i, end);
+		if let Some(tail) = name_eq(desc, path) {
+			if desc.is_sealed() {
+				return true;
+			}
+			if tail.is_empty() {
+				return false;
+			}
+			// Continue traversing directory descriptor
+			if desc.is_dir() {
+				path = tail;
+				i += 1;
+				end = next_i;
+				continue;
+			}
+		}
+		i = next_i;
+	}
+	false
+}
+
+/// Traverse the sorted directory with the given path.
+///
+/// Returns the same result as [`find`] if the siblings of every directory are sorted by name, see [`is_sorted`].
+///
+/// The siblings are binary searched by name in place, this speeds up lookups in wide directories.
+/// Locating the siblings around the probed descriptors is still linear in their number but avoids comparing their names.
+pub fn find_sorted<'a>(dir: &'a [Descriptor], path: &[u8]) -> &'a [Descriptor] {
+	let normal = normalize(path);
+	let mut path = &normal[..];
+	// Reject empty paths
+	if path.is_empty() {
+		return &dir[..0];
+	}
+	let mut i = 0;
+	let mut end = dir.len();
+	'outer: loop {
+		// Find the first sibling not sorted before the name
+		let name = component(path);
+		let mut lo = i;
+		let mut hi = end;
+		while lo < hi {
+			// Find the sibling which contains the middle descriptor
+			let mid = lo + (hi - lo) / 2;
+			let mut k = lo;
+			let mut next_k = next_sibling(&dir[k], k, end);
+			while next_k <= mid {
+				k = next_k;
+				next_k = next_sibling(&dir[k], k, end);
+			}
+			if dir[k].name() < name {
+				lo = next_k;
+			}
+			else {
+				hi = k;
+			}
+		}
+
+		// Check all the descriptors with a matching name
+		let mut k = lo;
+		while k < end {
+			let desc = &dir[k];
+			if desc.name() != name {
+				break;
+			}
+			let next_k = next_sibling(desc, k, end);
+			if let Some(tail) = name_eq(desc, path) {
+				// Exactly matching descriptor found
+				if tail.is_empty() {
+					return &dir[k..next_k];
+				}
+				// Continue traversing directory descriptor
+				if desc.is_dir() {
+					path = tail;
+					i = k + 1;
+					end = next_k;
+					continue 'outer;
+				}
+			}
+			k = next_k;
+		}
+
+		// No descriptor with this path found
+		return &dir[..0];
+	}
+}
+
+/// Returns if the siblings of every directory are sorted by name.
+pub fn is_sorted(dir: &[Descriptor]) -> bool {
+	fsck_sorted(dir, &mut NullWrite)
+}
+struct NullWrite;
+impl fmt::Write for NullWrite {
+	fn write_str(&mut self, _s: &str) -> fmt::Result {
+		Ok(())
+	}
+}
+
 /*
 /// Finds a descriptor with the given name in an encrypted directory.
 ///
@@ -260,14 +518,81 @@ impl Art<'static> {
 	};
 }
 
+/// Maximum depth of the paths in the directory, the entries of the root are at depth one.
+///
+/// Rendering the directory is limited to this depth, the editors refuse to create deeper paths.
+pub const MAX_DEPTH: u32 = 31;
+
+/// Returns the depth of the path, the number of its components.
+///
+/// Empty components are not counted, see [`normalize`].
+#[inline]
+pub fn depth(path: &[u8]) -> u32 {
+	path.split(is_separator).filter(|name| !name.is_empty()).count() as u32
+}
+
+/// Options to render the directory, see [`Fmt::with_options`].
+#[derive(Copy, Clone, Debug, Default)]
+pub struct FmtOptions<'a> {
+	/// Maximum depth of the entries rendered, the entries of the root are at depth one.
+	///
+	/// Directories at the maximum depth are rendered without their children. Unlimited if `None`.
+	pub max_depth: Option<u32>,
+	/// Shows the size in bytes of every entry.
+	///
+	/// The size of a directory is the total size of the files it contains.
+	pub sizes: bool,
+	/// Hides the files, rendering only the directories.
+	pub dirs_only: bool,
+	/// Renders only the files whose name matches the glob pattern, see [`Filter::Name`].
+	pub pattern: Option<&'a [u8]>,
+}
+
 pub struct Fmt<'a> {
 	root: &'a str,
 	dir: &'a [Descriptor],
 	art: &'a Art<'static>,
+	options: FmtOptions<'a>,
 }
 impl<'a> Fmt<'a> {
 	pub const fn new(root: &'a str, dir: &'a [Descriptor], art: &'a Art<'static>) -> Fmt<'a> {
-		Fmt { root, dir, art }
+		let options = FmtOptions { max_depth: None, sizes: false, dirs_only: false, pattern: None };
+		Fmt { root, dir, art, options }
+	}
+	/// Renders the directory with the given options.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use paks::Descriptor;
+	/// use paks::dir::{Art, Fmt, FmtOptions};
+	///
+	/// let dir = [
+	///     Descriptor::dir(b"Foo", 3),
+	///     Descriptor::new(b"Bar.txt", 1, 5),
+	///     Descriptor::dir(b"Sub", 1),
+	///     Descriptor::new(b"Baz.bin", 1, 10),
+	///     Descriptor::new(b"File.txt", 1, 20),
+	/// ];
+	///
+	/// let options = FmtOptions { max_depth: Some(1), sizes: true, ..FmtOptions::default() };
+	/// let expected = "./\n+- Foo/ (15 bytes)\n|  \n`  File.txt (20 bytes)\n";
+	/// assert_eq!(expected, Fmt::with_options(".", &dir, &Art::ASCII, options).to_string());
+	///
+	/// let options = FmtOptions { pattern: Some(b"*.txt"), ..FmtOptions::default() };
+	/// let expected = "./\n+- Foo/\n|  |  Bar.txt\n|  |  \n|  `- Sub/\n|  \n`  File.txt\n";
+	/// assert_eq!(expected, Fmt::with_options(".", &dir, &Art::ASCII, options).to_string());
+	///
+	/// let options = FmtOptions { dirs_only: true, ..FmtOptions::default() };
+	/// let expected = "./\n`- Foo/\n   `- Sub/\n";
+	/// assert_eq!(expected, Fmt::with_options(".", &dir, &Art::ASCII, options).to_string());
+	/// ```
+	pub const fn with_options(root: &'a str, dir: &'a [Descriptor], art: &'a Art<'static>, options: FmtOptions<'a>) -> Fmt<'a> {
+		Fmt { root, dir, art, options }
+	}
+	// Is the descriptor rendered?
+	fn is_visible(&self, desc: &Descriptor) -> bool {
+		desc.is_dir() || !self.options.dirs_only && self.options.pattern.is_none_or(|pattern| Filter::Name(pattern).matches(b"", desc))
 	}
 }
 impl<'a> fmt::Display for Fmt<'a> {
@@ -275,7 +600,7 @@ impl<'a> fmt::Display for Fmt<'a> {
 		// Print the root directory
 		f.write_str(self.root)?;
 		f.write_str(if self.root.ends_with("/") { "\n" } else { "/\n" })?;
-		fmt_rec(f, 0, 0, self.dir, self.art)
+		fmt_rec(f, 0, 0, self.dir, self)
 	}
 }
 
@@ -321,19 +646,31 @@ fn fmt_margin<W: fmt::Write>(f: &mut W, margin: u32, depth: u32, art: &Art) -> f
 	}
 	Ok(())
 }
-fn fmt_rec<W: fmt::Write>(f: &mut W, margin: u32, depth: u32, dir: &[Descriptor], art: &Art) -> fmt::Result {
+fn fmt_rec<W: fmt::Write>(f: &mut W, margin: u32, depth: u32, dir: &[Descriptor], fmt: &Fmt) -> fmt::Result {
 	// Max supported nested directories
-	if depth >= 31 {
+	if depth >= u32::min(MAX_DEPTH, fmt.options.max_depth.unwrap_or(u32::MAX)) {
 		return Ok(());
 	}
+	let art = fmt.art;
 
-	let mut was_dir = false;
+	// Collect the visible entries to know which one is last
+	let mut entries = Vec::new();
 	let mut i = 0;
 	while i < dir.len() {
+		// Calculate the next sibling descriptor index
+		let next_i = next_sibling(&dir[i], i, dir.len());
+		if fmt.is_visible(&dir[i]) {
+			entries.push((i, next_i));
+		}
+		i = next_i;
+	}
+
+	let mut was_dir = false;
+	for (k, &(i, next_i)) in entries.iter().enumerate() {
 		let desc = &dir[i];
 
 		// Print some space between directories
-		if i != 0 && (desc.is_dir() || was_dir) {
+		if k != 0 && (desc.is_dir() || was_dir) {
 			fmt_margin(f, margin, depth + 1, art)?;
 			f.write_str("\n")?;
 		}
@@ -342,11 +679,8 @@ fn fmt_rec<W: fmt::Write>(f: &mut W, margin: u32, depth: u32, dir: &[Descriptor]
 		// Print the margin
 		fmt_margin(f, margin, depth, art)?;
 
-		// Calculate the next sibling descriptor index
-		let next_i = next_sibling(desc, i, dir.len());
-
 		// Write the prefix
-		let is_last = dir.len() == next_i;
+		let is_last = k + 1 == entries.len();
 		let prefix = match (is_last, desc.is_dir()) {
 			(true, true) => art.dir_last,
 			(true, false) => art.file_last,
@@ -360,15 +694,96 @@ fn fmt_rec<W: fmt::Write>(f: &mut W, margin: u32, depth: u32, dir: &[Descriptor]
 			Ok(name) => f.write_str(name),
 			Err(_) => f.write_str("err"),
 		}?;
+		if desc.is_dir() {
+			f.write_str("/")?;
+		}
+		if fmt.options.sizes {
+			let size = if desc.is_dir() { files_size(&dir[i + 1..next_i]) } else { desc.content_size as u64 };
+			write!(f, " ({} bytes)", size)?;
+		}
+		f.write_str("\n")?;
 
 		// Print directories recursively
 		if desc.is_dir() {
-			f.write_str("/\n")?;
 			let new_margin = margin | (is_last as u32) << depth;
-			fmt_rec(f, new_margin, depth + 1, &dir[i + 1..next_i], art)?;
+			fmt_rec(f, new_margin, depth + 1, &dir[i + 1..next_i], fmt)?;
+		}
+	}
+	Ok(())
+}
+
+/// Formats the directory structure as a [Graphviz](https://graphviz.org/) graph in the dot language.
+///
+/// Every descriptor is a node connected to its parent directory, the root node is labeled with the given root.
+/// If `sizes` is true the nodes are labeled with the total content size of the files they contain and their width grows with it.
+///
+/// Render the graph with eg. `dot -Tsvg tree.dot -o tree.svg`.
+///
+/// # Examples
+///
+/// ```
+/// use paks::Descriptor;
+///
+/// let dir = [
+///     Descriptor::dir(b"Foo", 1),
+///     Descriptor::new(b"Bar", 1, 5),
+///     Descriptor::new(b"Baz", 1, 0),
+/// ];
+///
+/// let dot = paks::dir::to_dot(".", &dir, false);
+/// assert!(dot.starts_with("digraph pak {\n"));
+/// assert!(dot.contains("\tn1 [label=\"Foo/\", shape=folder];\n\tn0 -> n1;\n"));
+/// assert!(dot.contains("\tn2 [label=\"Bar\", shape=note];\n\tn1 -> n2;\n"));
+/// assert!(dot.contains("\tn0 -> n3;\n"));
+/// ```
+pub fn to_dot(root: &str, dir: &[Descriptor], sizes: bool) -> String {
+	let mut dot = String::from("digraph pak {\n\tnode [fontname=\"monospace\"];\n");
+	let root = if root.ends_with('/') { String::from(root) } else { format!("{}/", root) };
+	let _ = dot_node(&mut dot, 0, &root, true, files_size(dir), sizes);
+	let _ = dot_rec(&mut dot, 0, 1, dir, sizes);
+	dot.push_str("}\n");
+	dot
+}
+// Total content size of the files in the descriptors
+fn files_size(dir: &[Descriptor]) -> u64 {
+	dir.iter().filter(|desc| desc.is_file()).map(|desc| desc.content_size as u64).sum()
+}
+fn dot_node<W: fmt::Write>(f: &mut W, id: usize, name: &str, is_dir: bool, size: u64, sizes: bool) -> fmt::Result {
+	write!(f, "\tn{} [label=\"", id)?;
+	for chr in name.chars() {
+		match chr {
+			'"' | '\\' => write!(f, "\\{}", chr)?,
+			'\n' => f.write_str("\\n")?,
+			_ => f.write_char(chr)?,
+		}
+	}
+	if sizes {
+		// Grow the width with the order of magnitude of the size
+		let width = 0.75 + (size as f64 + 1.0).log2() / 8.0;
+		write!(f, "\\n{} bytes\", width={:.2}", size, width)?;
+	}
+	else {
+		f.write_str("\"")?;
+	}
+	writeln!(f, ", shape={}];", if is_dir { "folder" } else { "note" })
+}
+fn dot_rec<W: fmt::Write>(f: &mut W, parent: usize, base: usize, dir: &[Descriptor], sizes: bool) -> fmt::Result {
+	let mut i = 0;
+	while i < dir.len() {
+		let desc = &dir[i];
+		let next_i = next_sibling(desc, i, dir.len());
+		let id = base + i;
+
+		let name = String::from_utf8_lossy(desc.name());
+		if desc.is_dir() {
+			let children = &dir[i + 1..next_i];
+			dot_node(f, id, &format!("{}/", name), true, files_size(children), sizes)?;
+			writeln!(f, "\tn{} -> n{};", parent, id)?;
+			dot_rec(f, id, id + 1, children, sizes)?;
 		}
 		else {
-			f.write_str("\n")?;
+			dot_node(f, id, &name, false, desc.content_size as u64, sizes)?;
+			writeln!(f, "\tn{} -> n{};", parent, id)?;
 		}
 
 		i = next_i;
@@ -380,12 +795,18 @@ fn fmt_rec<W: fmt::Write>(f: &mut W, margin: u32, depth: u32, dir: &[Descriptor]
 /// Returns the index where `inc` number of descriptors must be inserted.
 ///
 /// Does not care if a descriptor already exists and will suggest to create one with the same name.
-fn dir_inc(dir: &mut Vec<Descriptor>, path: &mut &[u8], inc: i32) -> usize {
+///
+/// If the siblings are sorted by name, new descriptors are inserted in sort order.
+fn dir_inc(dir: &mut [Descriptor], path: &mut &[u8], inc: i32, sorted: bool) -> usize {
 	let mut i = 0;
 	let mut end = dir.len();
 	while i < end {
 		let desc = &mut dir[i];
 		let next_i = next_sibling(desc, i, end);
+		// Passed the place where the descriptor would be if it existed
+		if sorted && desc.name() > component(path) {
+			return i;
+		}
 		// Compare the name of this descriptor with the given path
 		if let Some(tail) = name_eq(desc, *path) {
 			// Found the descriptor matching this name
@@ -412,12 +833,25 @@ fn dir_inc(dir: &mut Vec<Descriptor>, path: &mut &[u8], inc: i32) -> usize {
 	return i;
 }
 
-fn flenck(path: &[u8]) -> i32 {
+// Returns the first component of the path
+pub(crate) fn component(path: &[u8]) -> &[u8] {
+	match path.iter().position(|&c| c == b'/' || c == b'\\') {
+		Some(k) => &path[..k],
+		None => path,
+	}
+}
+
+pub(crate) fn flenck(path: &[u8]) -> i32 {
+	// The descriptor already exists
+	if path.is_empty() {
+		return 0;
+	}
 	let mut components = 0;
 	for i in 0..path.len() {
 		if path[i] == b'/' || path[i] == b'\\' {
+			// A trailing separator does not start a new component
 			if i + 1 == path.len() {
-				return components;
+				return components + 1;
 			}
 			components += 1;
 		}
@@ -429,22 +863,52 @@ fn flenck(path: &[u8]) -> i32 {
 ///
 /// Non-existing sub directories are created as needed.
 /// If a file exists where a directory is expected, a directory with the same name is created as the file.
+///
+/// The path is [normalized](normalize), `a//b/` creates the same descriptor as `a/b`.
+///
+/// # Panics
+///
+/// Panics if the path has no components.
 pub fn create<'a>(dir: &'a mut Vec<Descriptor>, path: &[u8]) -> &'a mut Descriptor {
+	let inserted = insert(dir, path);
+	let i = if inserted.start == inserted.end { inserted.start } else { inserted.end - 1 };
+	&mut dir[i]
+}
+
+/// Creates a new descriptor at the appropriate place given the path.
+///
+/// Returns the range of newly inserted descriptors, the requested descriptor is the last one inserted.
+/// If the range is empty the requested descriptor already exists at the start of the range.
+pub fn insert(dir: &mut Vec<Descriptor>, path: &[u8]) -> ops::Range<usize> {
+	insert_impl(dir, path, false)
+}
+
+/// Creates a new descriptor at the appropriate place given the path in a sorted directory.
+///
+/// New descriptors are inserted such that the siblings remain sorted by name, see [`insert`].
+pub fn insert_sorted(dir: &mut Vec<Descriptor>, path: &[u8]) -> ops::Range<usize> {
+	insert_impl(dir, path, true)
+}
+
+fn insert_impl(dir: &mut Vec<Descriptor>, path: &[u8], sorted: bool) -> ops::Range<usize> {
+	let normal = normalize(path);
+	let path = &normal[..];
+
 	// Dry run to find the index where to insert new descriptors
 	let mut tail = path;
-	let i = dir_inc(dir, &mut tail, 0);
+	let i = dir_inc(dir, &mut tail, 0, sorted);
 
 	// Number of descriptors to add
 	let inc = flenck(tail) as usize;
 
 	// Adding a descriptor which already exists
 	if inc == 0 {
-		return &mut dir[i];
+		return i..i;
 	}
 
 	// Update the parent directories
 	tail = path;
-	let _check = dir_inc(dir, &mut tail, inc as i32);
+	let _check = dir_inc(dir, &mut tail, inc as i32, sorted);
 	debug_assert_eq!(i, _check);
 
 	// Splice new directory descriptors
@@ -460,8 +924,7 @@ pub fn create<'a>(dir: &'a mut Vec<Descriptor>, path: &[u8]) -> &'a mut Descript
 		Descriptor::dir(dir_name, dir_len)
 	}).take(inc));
 
-	// Return the requested descriptor
-	return &mut dir[i + inc - 1];
+	i..i + inc
 }
 
 /// Removes a descriptor at the given path.
@@ -476,32 +939,99 @@ pub fn create<'a>(dir: &'a mut Vec<Descriptor>, path: &[u8]) -> &'a mut Descript
 /// The descriptor is removed and optionally copied to the deleted output argument.
 /// All the direct children of the removed directory are moved to its parent directory.
 pub fn remove(dir: &mut Vec<Descriptor>, path: &[u8]) -> Option<Descriptor> {
+	remove_index(dir, path).map(|(_, desc)| desc)
+}
+
+/// Removes a descriptor at the given path.
+///
+/// Returns the index where the descriptor was removed together with the removed descriptor.
+pub fn remove_index(dir: &mut Vec<Descriptor>, path: &[u8]) -> Option<(usize, Descriptor)> {
+	let normal = normalize(path);
+	let path = &normal[..];
+	// Reject empty paths
+	if path.is_empty() {
+		return None;
+	}
+
 	// Dry run to find the index of the descriptor to remove
 	let mut temp = path;
-	let i = dir_inc(dir, &mut temp, 0);
+	let i = dir_inc(dir, &mut temp, 0, false);
 
 	// Early return if the descriptor wasn't found
-	if i >= dir.len() {
+	// The path is fully consumed only if the descriptor matched exactly
+	if i >= dir.len() || !temp.is_empty() {
 		return None;
 	}
 
 	// Update the parent directories
 	temp = path;
-	let _check = dir_inc(dir, &mut temp, -1);
+	let _check = dir_inc(dir, &mut temp, -1, false);
 	debug_assert_eq!(i, _check);
 
 	// Finally remove the descriptor
-	Some(dir.remove(i))
+	Some((i, dir.remove(i)))
 }
 
-pub fn fsck(dir: &[Descriptor], high_mark: u32, log: &mut dyn fmt::Write) -> bool {
-	fsck_rec(dir, high_mark, None, log)
+/// Calculates the extents of the file data referenced by the directory.
+///
+/// Returns the sorted and non-overlapping block ranges covered by file sections and their meta sections.
+/// Overlapping or adjacent sections (eg. links to the same file) are merged into a single extent.
+/// Sections whose range overflows are ignored.
+///
+/// # Examples
+///
+/// ```
+/// use paks::Descriptor;
+///
+/// let mut dir = [Descriptor::file(b"a"), Descriptor::file(b"b"), Descriptor::file(b"c")];
+/// dir[0].section.offset = 8;
+/// dir[0].section.size = 2;
+/// dir[1].section.offset = 4;
+/// dir[1].section.size = 2;
+/// dir[2].section.offset = 9;
+/// dir[2].section.size = 3;
+///
+/// assert_eq!(paks::dir::extents(&dir), [4..6, 8..12]);
+/// ```
+pub fn extents(dir: &[Descriptor]) -> Vec<ops::Range<u32>> {
+	let mut ranges: Vec<ops::Range<u32>> = dir.iter()
+		.filter(|desc| desc.is_file())
+		.flat_map(|desc| Some(desc.section).into_iter().chain(desc.meta_section()))
+		.filter(|section| section.size != 0)
+		.filter_map(|section| Some(section.offset..section.offset.checked_add(section.size)?))
+		.collect();
+	ranges.sort_unstable_by_key(|range| range.start);
+
+	let mut extents: Vec<ops::Range<u32>> = Vec::new();
+	for range in ranges {
+		match extents.last_mut() {
+			Some(last) if range.start <= last.end => last.end = cmp::max(last.end, range.end),
+			_ => extents.push(range),
+		}
+	}
+	extents
+}
+
+// Extents of the sections of pinned files, these are never relocated
+pub(crate) fn pinned_extents(dir: &[Descriptor]) -> Vec<ops::Range<u32>> {
+	let pinned: Vec<Descriptor> = dir.iter().filter(|desc| desc.is_pinned()).copied().collect();
+	extents(&pinned)
+}
+
+// Whether the range overlaps any of the sorted pinned extents
+pub(crate) fn overlaps_pinned(pinned: &[ops::Range<u32>], range: &ops::Range<u32>) -> bool {
+	let i = pinned.partition_point(|extent| extent.end <= range.start);
+	pinned.get(i).is_some_and(|extent| extent.start < range.end)
+}
+
+pub fn fsck(dir: &[Descriptor], high_mark: u32, block_size: u32, log: &mut dyn fmt::Write) -> bool {
+	fsck_rec(dir, high_mark, block_size, None, log)
 }
 struct FsckParents<'a> {
 	desc: &'a Descriptor,
 	parents: Option<&'a FsckParents<'a>>,
 }
-fn fsck_rec(dir: &[Descriptor], high_mark: u32, parents: Option<&FsckParents>, log: &mut dyn fmt::Write) -> bool {
+fn fsck_rec(dir: &[Descriptor], high_mark: u32, block_size: u32, parents: Option<&FsckParents>, log: &mut dyn fmt::Write) -> bool {
 	let mut success = true;
 	let mut i = 0;
 	while i < dir.len() {
@@ -522,7 +1052,7 @@ fn fsck_rec(dir: &[Descriptor], high_mark: u32, parents: Option<&FsckParents>, l
 
 		if desc.is_file() {
 			// File section overlaps the header
-			if desc.section.offset < Header::BLOCKS_LEN as u32 {
+			if desc.section.offset < header_blocks(block_size) {
 				fsck_error(desc, parents, log, format_args!("invalid file section (offset={}, size={}): overlaps the header", desc.section.offset, desc.section.size));
 				success = false;
 			}
@@ -534,13 +1064,17 @@ fn fsck_rec(dir: &[Descriptor], high_mark: u32, parents: Option<&FsckParents>, l
 			}
 
 			// File section overlaps the directory
-			if desc.section.offset > high_mark - desc.section.size {
+			if desc.section.offset > high_mark.saturating_sub(desc.section.size) {
 				fsck_error(desc, parents, log, format_args!("invalid file section (offset={}, size={}): overlaps the directory", desc.section.offset, desc.section.size));
 				success = false;
 			}
 
 			// File content size larger than its section size
-			if bytes2blocks(desc.content_size) > desc.section.size {
+			let content_blocks = match desc.content_offset().checked_add(desc.content_size) {
+				Some(end) => bytes2blocks(end, block_size),
+				None => u32::MAX,
+			};
+			if content_blocks > desc.section.size {
 				fsck_error(desc, parents, log, format_args!("invalid content size ({}, offset={}, size={}): larger than its section", desc.content_size, desc.section.offset, desc.section.size));
 				success = false;
 			}
@@ -557,13 +1091,44 @@ fn fsck_rec(dir: &[Descriptor], high_mark: u32, parents: Option<&FsckParents>, l
 
 			// Recursively check the directory's children
 			let children = &dir[i..i + desc.content_size as usize];
-			fsck_rec(children, high_mark, Some(&FsckParents { desc, parents }), log);
+			fsck_rec(children, high_mark, block_size, Some(&FsckParents { desc, parents }), log);
 
 			i += desc.content_size as usize;
 		}
 	}
 	return success;
 }
+
+/// File system consistency check for sorted directories.
+///
+/// Checks that the siblings of every directory are sorted by name, returns false if they're not.
+/// The descriptors which are out of order are logged.
+pub fn fsck_sorted(dir: &[Descriptor], log: &mut dyn fmt::Write) -> bool {
+	fsck_sorted_rec(dir, None, log)
+}
+fn fsck_sorted_rec(dir: &[Descriptor], parents: Option<&FsckParents>, log: &mut dyn fmt::Write) -> bool {
+	let mut success = true;
+	let mut prev: Option<&Descriptor> = None;
+	let mut i = 0;
+	while i < dir.len() {
+		let desc = &dir[i];
+		let next_i = next_sibling(desc, i, dir.len());
+
+		if let Some(prev) = prev {
+			if prev.name() > desc.name() {
+				fsck_error(desc, parents, log, format_args!("not sorted: comes after {:?}", String::from_utf8_lossy(prev.name())));
+				success = false;
+			}
+		}
+		if desc.is_dir() {
+			success &= fsck_sorted_rec(&dir[i + 1..next_i], Some(&FsckParents { desc, parents }), log);
+		}
+
+		prev = Some(desc);
+		i = next_i;
+	}
+	success
+}
 #[inline(never)]
 fn fsck_error(desc: &Descriptor, parents: Option<&FsckParents>, log: &mut dyn fmt::Write, args: fmt::Arguments) {
 	fn print_parents(parents: Option<&FsckParents>, log: &mut dyn fmt::Write) {
@@ -667,6 +1232,19 @@ mod tests {
 		assert_eq!(dir, result);
 	}
 
+	#[test]
+	fn test_remove_not_found() {
+		let mut dir = Vec::new();
+		create(&mut dir, b"A/B");
+		create(&mut dir, b"C");
+		let expected = dir.clone();
+
+		assert_eq!(remove(&mut dir, b"A/D"), None);
+		assert_eq!(remove(&mut dir, b"A/B/C"), None);
+		assert_eq!(remove(&mut dir, b""), None);
+		assert_eq!(dir, expected);
+	}
+
 	// #[test]
 	// fn test_find_encrypted() {
 	// 	let mut directory = Directory::from(example_dir());
@@ -1,87 +1,336 @@
-use std::{fmt, slice};
+use std::{collections::BTreeMap, fmt, mem, ops, slice};
 use crate::*;
 
+/// Stable handle to a descriptor in a [`Directory`].
+///
+/// Handles remain valid while descriptors are inserted or removed elsewhere in the directory.
+/// Handles of removed descriptors are never reused by the same directory.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct DescriptorId(u32);
+
 /// Directory editor.
 ///
 /// The directory is a sequence of descriptors encoding a light-weight [TLV structure](https://en.wikipedia.org/wiki/Type-length-value).
-#[derive(Clone, Debug, Default)]
-#[repr(transparent)]
-pub struct Directory(Vec<Descriptor>);
+///
+/// Every descriptor is assigned a [`DescriptorId`] which can be used to access it across edits.
+#[derive(Clone, Debug)]
+pub struct Directory {
+	descs: Vec<Descriptor>,
+	// Handle of the descriptor at the same index
+	ids: Vec<DescriptorId>,
+	// Index of the descriptor for every handle ever allocated, `!0` if removed
+	slots: Vec<u32>,
+	// Keep the siblings sorted by name
+	sorted: bool,
+	// Full paths of the descriptors, discarded on edits
+	paths: Option<PathCache>,
+	// Size in bytes of the blocks addressed by the file sections
+	pub(crate) block_size: u32,
+	// Alignment in bytes of newly allocated file sections
+	pub(crate) align: u32,
+	// Allow modifications under sealed directories
+	ignore_seals: bool,
+	// Number of previous revisions kept of overwritten files
+	revisions: u32,
+	// Files with each tag, see `find_by_tag`
+	tags: BTreeMap<String, Vec<DescriptorId>>,
+	// Normalization of the names on create and lookup
+	names: NamePolicy,
+}
 
 impl AsRef<[Descriptor]> for Directory {
 	#[inline]
 	fn as_ref(&self) -> &[Descriptor] {
-		&self.0
+		&self.descs
 	}
 }
-impl AsMut<[Descriptor]> for Directory {
+impl From<Vec<Descriptor>> for Directory {
 	#[inline]
-	fn as_mut(&mut self) -> &mut [Descriptor] {
-		&mut self.0
+	fn from(dir: Vec<Descriptor>) -> Directory {
+		let ids = (0..dir.len() as u32).map(DescriptorId).collect();
+		let slots = (0..dir.len() as u32).collect();
+		Directory { descs: dir, ids, slots, sorted: false, paths: None, block_size: BLOCK_SIZE as u32, align: 1, ignore_seals: false, revisions: 0, tags: BTreeMap::new(), names: NamePolicy::from_flags(0) }
 	}
 }
-impl From<Vec<Descriptor>> for Directory {
+impl Default for Directory {
 	#[inline]
-	fn from(dir: Vec<Descriptor>) -> Directory {
-		Directory(dir)
+	fn default() -> Directory {
+		Directory::new()
 	}
 }
 impl From<Directory> for Vec<Descriptor> {
 	#[inline]
 	fn from(this: Directory) -> Vec<Descriptor> {
-		this.0
+		this.descs
+	}
+}
+
+impl Directory {
+	// Allocates handles for descriptors inserted at the given range
+	fn insert_ids(&mut self, inserted: ops::Range<usize>) -> Option<DescriptorId> {
+		let start = self.slots.len() as u32;
+		let new_ids = (start..start + inserted.len() as u32).map(DescriptorId);
+		let _ = self.ids.splice(inserted.start..inserted.start, new_ids);
+		self.slots.resize(start as usize + inserted.len(), !0);
+		self.reindex(inserted.start);
+		if inserted.is_empty() { None } else { Some(DescriptorId(self.slots.len() as u32 - 1)) }
+	}
+	// Releases the handle of the descriptor removed at the given index
+	fn remove_id(&mut self, index: usize) -> DescriptorId {
+		let id = self.ids.remove(index);
+		self.slots[id.0 as usize] = !0;
+		self.reindex(index);
+		id
+	}
+	// Updates the slots of the handles starting from the given index
+	// Every edit ends up here, the path cache is discarded
+	fn reindex(&mut self, start: usize) {
+		self.paths = None;
+		for (i, id) in self.ids.iter().enumerate().skip(start) {
+			self.slots[id.0 as usize] = i as u32;
+		}
+	}
+	fn lookup(&self, path: &[u8]) -> &[Descriptor] {
+		let path = &self.names.apply(path)[..];
+		if self.sorted {
+			dir::find_sorted(&self.descs, path)
+		}
+		else {
+			dir::find(&self.descs, path)
+		}
+	}
+	fn insert(&mut self, path: &[u8]) -> ops::Range<usize> {
+		let path = &self.names.apply(path)[..];
+		if self.sorted {
+			dir::insert_sorted(&mut self.descs, path)
+		}
+		else {
+			dir::insert(&mut self.descs, path)
+		}
+	}
+	// Returns the index of the descriptor if it is a reference into this directory
+	fn position(&self, desc: &Descriptor) -> Option<usize> {
+		let offset = (desc as *const Descriptor as usize).wrapping_sub(self.descs.as_ptr() as usize);
+		let index = offset / mem::size_of::<Descriptor>();
+		if index < self.descs.len() && offset.is_multiple_of(mem::size_of::<Descriptor>()) { Some(index) } else { None }
+	}
+	// Removes the descriptor at the path, bypassing the seals
+	fn remove_index(&mut self, path: &[u8]) {
+		let path = &self.names.apply(path)[..];
+		if let Some((i, _)) = dir::remove_index(&mut self.descs, path) {
+			self.remove_id(i);
+		}
+	}
+	// Empty paths and paths deeper than the maximum depth are refused
+	pub(crate) fn invalid_path(path: &[u8]) -> bool {
+		let depth = dir::depth(path);
+		depth == 0 || depth > dir::MAX_DEPTH
+	}
+	// Modifications under sealed directories are refused unless seals are ignored
+	pub(crate) fn writable(&self, path: &[u8]) -> bool {
+		self.ignore_seals || !dir::is_sealed(&self.descs, &self.names.apply(path))
+	}
+	fn index_of(&self, id: DescriptorId) -> Option<usize> {
+		match self.slots.get(id.0 as usize) {
+			Some(&index) if index != !0 => Some(index as usize),
+			_ => None,
+		}
 	}
 }
 
 impl Directory {
 	pub(crate) fn as_blocks(&self) -> &[Block] {
 		unsafe {
-			slice::from_raw_parts(self.0.as_ptr() as *const Block, self.0.len() * Descriptor::BLOCKS_LEN)
+			slice::from_raw_parts(self.descs.as_ptr() as *const Block, self.descs.len() * Descriptor::BLOCKS_LEN)
 		}
 	}
 	pub(crate) fn as_blocks_mut(&mut self) -> &mut [Block] {
 		unsafe {
-			slice::from_raw_parts_mut(self.0.as_mut_ptr() as *mut Block, self.0.len() * Descriptor::BLOCKS_LEN)
+			slice::from_raw_parts_mut(self.descs.as_mut_ptr() as *mut Block, self.descs.len() * Descriptor::BLOCKS_LEN)
 		}
 	}
 
 	/// Returns if there are no files or directories.
 	#[inline]
 	pub fn is_empty(&self) -> bool {
-		self.0.is_empty()
+		self.descs.is_empty()
 	}
 
 	/// Returns the number of [`Descriptor`]s in the directory.
 	#[inline]
 	pub fn len(&self) -> usize {
-		self.0.len()
+		self.descs.len()
+	}
+
+	/// Returns the number of file descriptors.
+	pub fn file_count(&self) -> usize {
+		self.stats().0
+	}
+
+	/// Returns the number of directory descriptors.
+	pub fn dir_count(&self) -> usize {
+		self.stats().1
+	}
+
+	/// Returns the depth of the deepest descriptor, the entries of the root are at depth one.
+	///
+	/// Returns zero if the directory is empty.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// let mut dir = paks::Directory::new();
+	/// dir.create_link(b"a/b/c", &paks::Descriptor::file(b""));
+	/// dir.create_link(b"a/d", &paks::Descriptor::file(b""));
+	/// dir.create_dir(b"e");
+	///
+	/// assert_eq!(dir.file_count(), 2);
+	/// assert_eq!(dir.dir_count(), 3);
+	/// assert_eq!(dir.max_depth(), 3);
+	/// ```
+	pub fn max_depth(&self) -> u32 {
+		self.stats().2
+	}
+
+	// Counts the files, directories and the maximum depth in one pass
+	fn stats(&self) -> (usize, usize, u32) {
+		let (mut files, mut dirs, mut max_depth) = (0, 0, 0);
+		// End index of every parent directory of the current descriptor
+		let mut ends = Vec::new();
+		for (i, desc) in self.descs.iter().enumerate() {
+			while ends.last().is_some_and(|&end| end <= i) {
+				ends.pop();
+			}
+			max_depth = u32::max(max_depth, ends.len() as u32 + 1);
+			if desc.is_dir() {
+				dirs += 1;
+				ends.push(i + 1 + desc.content_size as usize);
+			}
+			else {
+				files += 1;
+			}
+		}
+		(files, dirs, max_depth)
+	}
+
+	/// Finds the handle of a descriptor by its path.
+	#[inline]
+	pub fn find(&self, path: &[u8]) -> Option<DescriptorId> {
+		let i = self.position(self.lookup(path).first()?)?;
+		Some(self.ids[i])
+	}
+
+	/// Gets a descriptor by its handle.
+	///
+	/// Returns `None` if the descriptor was removed.
+	#[inline]
+	pub fn get(&self, id: DescriptorId) -> Option<&Descriptor> {
+		self.index_of(id).map(|i| &self.descs[i])
+	}
+
+	/// Gets a descriptor by its handle.
+	///
+	/// Returns `None` if the descriptor was removed.
+	///
+	/// Changing the `content_size` of a directory descriptor corrupts the directory, see [`iter_files_mut`](Self::iter_files_mut) for safe access to files.
+	#[inline]
+	pub fn get_mut(&mut self, id: DescriptorId) -> Option<&mut Descriptor> {
+		self.paths = None;
+		match self.index_of(id) {
+			Some(i) => Some(&mut self.descs[i]),
+			None => None,
+		}
 	}
 
 	/// Finds a descriptor by its path.
 	#[inline]
 	pub fn find_desc(&self, path: &[u8]) -> Option<&Descriptor> {
-		dir::find_desc(&self.0, path)
+		self.lookup(path).first()
 	}
 
 	/// Finds a file descriptor by its path.
 	#[inline]
 	pub fn find_file(&self, path: &[u8]) -> Option<&Descriptor> {
-		match dir::find_desc(&self.0, path) {
+		match self.lookup(path).first() {
 			Some(desc) if desc.is_file() => Some(desc),
 			_ => None
 		}
 	}
 
+	/// Finds the file descriptors whose section contains the given block index.
+	///
+	/// Multiple descriptors are returned when files are linked or their sections overlap.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// let mut file = paks::Descriptor::file(b"");
+	/// file.section.offset = 0x3A00;
+	/// file.section.size = 0x40;
+	///
+	/// let mut directory = paks::Directory::new();
+	/// directory.create_link(b"foo/bar", &file);
+	///
+	/// let found: Vec<_> = directory.find_by_offset(0x3A20).map(|desc| desc.name()).collect();
+	/// assert_eq!(found, [b"bar"]);
+	/// assert_eq!(directory.find_by_offset(0x3A40).count(), 0);
+	/// ```
+	pub fn find_by_offset(&self, block: u32) -> impl '_ + Iterator<Item = &Descriptor> {
+		self.descs.iter().filter(move |desc| {
+			desc.is_file() && block >= desc.section.offset && block - desc.section.offset < desc.section.size
+		})
+	}
+
+	/// Audits the nonces of all file and [metadata](Descriptor::meta_section) sections for reuse.
+	///
+	/// Encrypting different data with the same nonce is catastrophic for the security of the encryption.
+	/// Linked files which share the exact same section are not considered reuse.
+	/// Empty sections do not use their nonce and are ignored.
+	///
+	/// The other sections (eg. the header and the directory section) are included in the audit.
+	///
+	/// Returns every reused nonce together with the distinct sections encrypted with it.
+	pub fn audit_nonces(&self, others: &[Section]) -> Vec<NonceReuse> {
+		let mut sections: Vec<Section> = self.descs.iter()
+			.filter(|desc| desc.is_file())
+			.map(|desc| desc.section)
+			.chain(self.descs.iter().filter_map(|desc| desc.meta_section()))
+			.chain(others.iter().cloned())
+			.filter(|section| section.size != 0)
+			.collect();
+		sections.sort_unstable_by_key(|section| (section.nonce, section.offset, section.size, section.mac));
+		sections.dedup();
+
+		let mut reuses = Vec::new();
+		let mut i = 0;
+		while i < sections.len() {
+			let mut end = i + 1;
+			while end < sections.len() && sections[end].nonce == sections[i].nonce {
+				end += 1;
+			}
+			if end - i > 1 {
+				reuses.push(NonceReuse { nonce: sections[i].nonce, sections: sections[i..end].to_vec() });
+			}
+			i = end;
+		}
+		reuses
+	}
+
 	/// Gets the child descriptors of the directory at the given path.
 	#[inline]
 	pub fn get_children(&self, path: &[u8]) -> Option<&[Descriptor]> {
-		dir::find_dir(&self.0, path)
+		if path.is_empty() {
+			Some(&self.descs)
+		}
+		else {
+			self.lookup(path).get(1..)
+		}
 	}
 
 	/// Returns a displayable directory.
 	#[inline]
 	pub fn display(&self) -> impl '_ + fmt::Display {
-		dir::Fmt::new(".", &self.0, &dir::Art::UNICODE)
+		dir::Fmt::new(".", &self.descs, &dir::Art::UNICODE)
 	}
 
 	/// File system consistency check.
@@ -90,47 +339,191 @@ impl Directory {
 	/// Detailed information can be found in the log.
 	///
 	/// The high mark is the highest block index that a file section is allowed.
+	///
+	/// If the directory is [sorted](Self::set_sorted) the sort order is checked as well.
 	#[inline]
 	pub fn fsck(&self, high_mark: u32, log: &mut dyn fmt::Write) -> bool {
-		dir::fsck(&self.0, high_mark, log)
+		let success = dir::fsck(&self.descs, high_mark, self.block_size, log);
+		success & (!self.sorted || dir::fsck_sorted(&self.descs, log))
+	}
+
+	/// Counts the number of blocks not referenced by any file descriptor.
+	///
+	/// The high mark is the highest block index that a file section is allowed.
+	/// These blocks can be reclaimed by garbage collection.
+	///
+	/// Padding required by the [data alignment](Self::data_alignment) and blocks left below [pinned](Descriptor::is_pinned) files are not counted as garbage.
+	pub fn garbage(&self, high_mark: u32) -> u32 {
+		let align = self.align_blocks();
+		let pinned = dir::pinned_extents(&self.descs);
+		let mut new_mark = header_blocks(self.block_size);
+		for extent in dir::extents(&self.descs) {
+			// Mirrors the garbage collection which never moves extents up nor moves pinned extents
+			let start = u32::max(new_mark, extent.start);
+			new_mark = if dir::overlaps_pinned(&pinned, &extent) { start } else { u32::min(align_up(new_mark, align), start) };
+			new_mark = new_mark.saturating_add(extent.end - extent.start);
+		}
+		high_mark.saturating_sub(new_mark)
+	}
+
+	/// Returns the ranges of blocks not referenced by any file descriptor.
+	///
+	/// The high mark is the highest block index that a file section is allowed.
+	/// The ranges are sorted and cover the data area between the header and the high mark.
+	///
+	/// Unlike [`garbage`](Self::garbage) padding required by the [data alignment](Self::data_alignment) is included.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// let ref key = paks::Key::default();
+	/// let mut editor = paks::MemoryEditor::new();
+	/// editor.create_file(b"a", &[0; 40], key);
+	/// editor.create_file(b"b", &[0; 40], key);
+	/// let b = editor.find_file(b"b").unwrap().section;
+	/// editor.remove(b"b");
+	///
+	/// assert_eq!(editor.garbage_regions(editor.high_mark()), [b.offset..b.offset + b.size]);
+	/// ```
+	pub fn garbage_regions(&self, high_mark: u32) -> Vec<ops::Range<u32>> {
+		let mut regions = Vec::new();
+		let mut start = header_blocks(self.block_size);
+		for extent in dir::extents(&self.descs) {
+			if extent.start >= high_mark {
+				break;
+			}
+			if extent.start > start {
+				regions.push(start..extent.start);
+			}
+			start = u32::max(start, extent.end);
+		}
+		if high_mark > start {
+			regions.push(start..high_mark);
+		}
+		regions
 	}
 }
 impl Directory {
 	/// Creates a new, empty `Directory` instance.
 	#[inline]
 	pub const fn new() -> Directory {
-		Directory(Vec::new())
+		Directory { descs: Vec::new(), ids: Vec::new(), slots: Vec::new(), sorted: false, paths: None, block_size: BLOCK_SIZE as u32, align: 1, ignore_seals: false, revisions: 0, tags: BTreeMap::new(), names: NamePolicy::from_flags(0) }
 	}
 
-	// For internal use
+	/// Returns the size in bytes of the blocks addressed by the file sections.
+	///
+	/// See [`InfoHeader::block_size`] for more information.
+	#[inline]
+	pub fn block_size(&self) -> u32 {
+		self.block_size
+	}
+
+	/// Returns the alignment in bytes of newly allocated file sections.
+	///
+	/// Editors configure the alignment with `align_data`, the default is no alignment beyond the block size.
+	#[inline]
+	pub fn data_alignment(&self) -> u32 {
+		u32::max(self.align, self.block_size)
+	}
+
+	// Alignment of the file sections in blocks
+	#[inline]
+	pub(crate) fn align_blocks(&self) -> u32 {
+		self.data_alignment() / self.block_size
+	}
+
+	/// Returns if the siblings are kept sorted by name.
 	#[inline]
+	pub fn is_sorted(&self) -> bool {
+		self.sorted
+	}
+
+	/// Keeps the siblings of every directory sorted by name.
+	///
+	/// Lookups in a sorted directory binary search the siblings which speeds up lookups in wide directories.
+	/// New descriptors are inserted in sort order.
+	///
+	/// Enabling this option sorts the directory, descriptors with the same name keep their relative order.
+	/// Disabling this option leaves the directory as is, new descriptors are appended to their parent directory.
+	pub fn set_sorted(&mut self, sorted: bool) {
+		if sorted && !self.sorted {
+			self.sort();
+		}
+		self.sorted = sorted;
+	}
+
+	// Readers use the binary search if the directory happens to be sorted
+	pub(crate) fn detect_sorted(&mut self) {
+		self.sorted = dir::is_sorted(&self.descs);
+	}
+
+	// For internal use
 	pub(crate) fn create(&mut self, path: &[u8]) -> &mut Descriptor {
-		dir::create(&mut self.0, path)
+		self.retain_revision(path);
+		let i = self.create_index(path);
+		&mut self.descs[i]
+	}
+	fn create_index(&mut self, path: &[u8]) -> usize {
+		let inserted = self.insert(path);
+		let i = match self.insert_ids(inserted.clone()) {
+			Some(_) => inserted.end - 1,
+			None => inserted.start,
+		};
+		self.names.stamp(&mut self.descs[i].name, path);
+		i
+	}
+	// Overwriting a directory descriptor moves its children to the parent directory
+	// Returns the index of the descriptor after the children of a sorted directory are sorted in
+	fn clear_children(&mut self, i: usize) -> usize {
+		if self.descs[i].is_dir() && self.descs[i].content_size > 0 {
+			self.descs[i].content_size = 0;
+			if self.sorted {
+				let id = self.ids[i];
+				self.sort();
+				return self.slots[id.0 as usize] as usize;
+			}
+		}
+		i
 	}
 
 	/// Creates a symbolic link from the path to the given file descriptor.
 	///
 	/// Any missing parent directories are automatically created.
 	///
-	/// Does nothing if the given descriptor is not a file descriptor.
-	pub fn create_link(&mut self, path: &[u8], file_desc: &Descriptor) {
-		if file_desc.is_file() {
-			let desc = dir::create(&mut self.0, path);
-			desc.content_size = file_desc.content_size;
-			desc.content_type = file_desc.content_type;
-			desc.section = file_desc.section;
+	/// Returns `None` and does nothing if the given descriptor is not a file descriptor.
+	/// Returns `None` and does nothing if the path is [sealed](Self::seal), empty or deeper than [`dir::MAX_DEPTH`] or its [path hash collides](Self::hash_collides) with another file.
+	pub fn create_link(&mut self, path: &[u8], file_desc: &Descriptor) -> Option<DescriptorId> {
+		if !file_desc.is_file() || !self.writable(path) || Directory::invalid_path(path) || self.hash_collides(path) {
+			return None;
 		}
+		self.retain_revision(path);
+		let i = self.create_index(path);
+		let i = self.clear_children(i);
+		let desc = &mut self.descs[i];
+		desc.content_size = file_desc.content_size;
+		desc.content_type = file_desc.content_type;
+		desc.section = file_desc.section;
+		desc.meta = file_desc.meta;
+		Some(self.ids[i])
 	}
 
 	/// Creates a directory descriptor at the given path.
 	///
 	/// Any missing parent directories are automatically created.
-	#[inline]
-	pub fn create_dir(&mut self, path: &[u8]) {
-		let desc = dir::create(&mut self.0, path);
+	///
+	/// Returns `None` and does nothing if the path is [sealed](Self::seal), empty or deeper than [`dir::MAX_DEPTH`].
+	pub fn create_dir(&mut self, path: &[u8]) -> Option<DescriptorId> {
+		if !self.writable(path) || Directory::invalid_path(path) {
+			return None;
+		}
+		let i = self.create_index(path);
+		let i = self.clear_children(i);
+		let desc = &mut self.descs[i];
 		desc.content_type = 0;
 		desc.content_size = 0;
 		desc.section = Section::default();
+		desc.meta = Section::default();
+		Some(self.ids[i])
 	}
 
 	/// Removes a descriptor at the given path.
@@ -144,9 +537,25 @@ impl Directory {
 	/// Returns `true` if a directory descriptor is found at the given path.
 	/// The descriptor is removed and optionally copied to the deleted output argument.
 	/// All the direct children of the removed directory are moved to its parent directory.
-	#[inline]
+	///
+	/// The handle of the removed descriptor is invalidated.
+	///
+	/// Returns `None` and does nothing if the path is [sealed](Self::seal).
 	pub fn remove(&mut self, path: &[u8]) -> Option<Descriptor> {
-		dir::remove(&mut self.0, path)
+		if !self.writable(path) {
+			return None;
+		}
+		self.remove_stored(&self.names.apply(path))
+	}
+	// Removes the descriptor at the path as stored, the name policy is not applied
+	pub(crate) fn remove_stored(&mut self, path: &[u8]) -> Option<Descriptor> {
+		let (i, deleted) = dir::remove_index(&mut self.descs, path)?;
+		self.remove_id(i);
+		// The children moved to the parent directory need to be sorted in
+		if self.sorted && deleted.is_dir() && deleted.content_size > 0 {
+			self.sort();
+		}
+		Some(deleted)
 	}
 
 	/// Moves a file descriptor from the src path to the given dest path.
@@ -154,28 +563,185 @@ impl Directory {
 	/// Returns `false` if the src path does not exist or is a directory descriptor.
 	/// This method cannot move directory descriptors.
 	///
+	/// Returns `false` if the src or dest path is [sealed](Self::seal) or the dest path is empty or deeper than [`dir::MAX_DEPTH`].
+	///
+	/// Returns `false` if the [path hash](Self::hash_collides) of the dest path collides with another file.
+	///
 	/// Returns `true` if the move was successful.
+	/// The moved descriptor keeps its handle unless a file already existed at the dest path.
 	pub fn move_file(&mut self, src_path: &[u8], dest_path: &[u8]) -> bool {
+		if !self.writable(src_path) || !self.writable(dest_path) || Directory::invalid_path(dest_path) || self.hash_collides(dest_path) {
+			return false;
+		}
 		// Check to make sure it's a file descriptor
 		// Moving directory descriptors like this corrupts the directory
-		match dir::find_desc(&self.0, src_path) {
+		match self.lookup(src_path).first() {
 			Some(src_desc) if src_desc.is_file() => (),
 			_ => return false,
 		}
+		self.retain_revision(dest_path);
 
 		// Delete the descriptor
-		let deleted = match dir::remove(&mut self.0, src_path) {
-			Some(deleted) => deleted,
+		let (src_i, deleted) = match dir::remove_index(&mut self.descs, &self.names.apply(src_path)) {
+			Some(removed) => removed,
 			None => return false,
 		};
+		let id = self.remove_id(src_i);
 
-		let desc = dir::create(&mut self.0, dest_path);
+		// Recreate the descriptor at the destination and transfer its handle
+		let inserted = self.insert(dest_path);
+		let i = if inserted.start == inserted.end {
+			inserted.start
+		}
+		else {
+			self.insert_ids(inserted.start..inserted.end - 1);
+			self.ids.insert(inserted.end - 1, id);
+			self.reindex(inserted.end - 1);
+			inserted.end - 1
+		};
+		let i = self.clear_children(i);
+
+		let desc = &mut self.descs[i];
+		self.names.stamp(&mut desc.name, dest_path);
 		desc.content_type = deleted.content_type;
 		desc.content_size = deleted.content_size;
 		desc.section = deleted.section;
+		desc.meta = deleted.meta;
 		return true;
 	}
+
+	/// Seals or unseals the directory at the given path.
+	///
+	/// Creating, removing or moving descriptors at or under a sealed directory is refused.
+	/// This protects critical paths from accidental modification.
+	/// The seal is stored in the directory descriptor's [flags](Descriptor::flags) and persists in the PAK file.
+	///
+	/// Unsealing the directory or [ignoring the seals](Self::set_ignore_seals) is the explicit override.
+	/// Note that editing the descriptors directly and the editors' `edit_file` ignore the seals.
+	///
+	/// Returns `false` if there is no directory at the given path.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// let mut directory = paks::Directory::new();
+	/// directory.create_link(b"engine/shaders/main", &paks::Descriptor::file(b""));
+	/// assert!(directory.seal(b"engine", true));
+	///
+	/// assert!(directory.is_sealed(b"engine/shaders/main"));
+	/// assert!(directory.remove(b"engine/shaders/main").is_none());
+	/// assert!(directory.create_dir(b"engine/new").is_none());
+	///
+	/// directory.set_ignore_seals(true);
+	/// assert!(directory.remove(b"engine/shaders/main").is_some());
+	/// ```
+	pub fn seal(&mut self, path: &[u8], sealed: bool) -> bool {
+		let i = match self.lookup(path).first() {
+			Some(desc) if desc.is_dir() => self.position(desc),
+			_ => None,
+		};
+		match i {
+			Some(i) => {
+				let desc = &mut self.descs[i];
+				let flags = desc.flags() & !Descriptor::FLAG_SEALED;
+				desc.set_flags(if sealed { flags | Descriptor::FLAG_SEALED } else { flags });
+				true
+			},
+			None => false,
+		}
+	}
+
+	/// Returns if the descriptor at the given path or any of its parent directories is sealed.
+	///
+	/// See [`seal`](Self::seal) for more information.
+	#[inline]
+	pub fn is_sealed(&self, path: &[u8]) -> bool {
+		dir::is_sealed(&self.descs, &self.names.apply(path))
+	}
+
+	/// Ignores the seals of sealed directories.
+	///
+	/// See [`seal`](Self::seal) for more information.
+	#[inline]
+	pub fn set_ignore_seals(&mut self, ignore: bool) {
+		self.ignore_seals = ignore;
+	}
+
+	/// Takes a snapshot of the directory.
+	///
+	/// The directory can be restored to this state later with [`restore`](Self::restore).
+	#[inline]
+	pub fn snapshot(&self) -> Snapshot {
+		Snapshot { descs: self.descs.clone(), ids: self.ids.clone() }
+	}
+
+	/// Restores the directory to the state of the given snapshot.
+	///
+	/// Handles of descriptors in the snapshot are valid again, handles created after the snapshot are invalidated.
+	pub fn restore(&mut self, snapshot: Snapshot) {
+		self.descs = snapshot.descs;
+		self.ids = snapshot.ids;
+		// The snapshot may have been taken from a clone which allocated more handles
+		let slots_len = self.ids.iter().map(|id| id.0 as usize + 1).max().unwrap_or(0);
+		if self.slots.len() < slots_len {
+			self.slots.resize(slots_len, !0);
+		}
+		for slot in &mut self.slots {
+			*slot = !0;
+		}
+		self.reindex(0);
+	}
 }
 
+/// Nonce reuse found by [`Directory::audit_nonces`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct NonceReuse {
+	/// The reused nonce.
+	pub nonce: Block,
+	/// The distinct sections encrypted with this nonce.
+	pub sections: Vec<Section>,
+}
+
+/// Snapshot of a [`Directory`].
+///
+/// See [`Directory::snapshot`] and [`Directory::restore`].
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Snapshot {
+	descs: Vec<Descriptor>,
+	ids: Vec<DescriptorId>,
+}
+
+mod undo;
+pub use self::undo::*;
+
+mod query;
+pub use self::query::*;
+
+mod batch;
+
+mod paths;
+use self::paths::PathCache;
+
+mod shred;
+
+mod revisions;
+
+mod trash;
+
+mod listing;
+
+mod tags;
+
+mod links;
+
+mod files_mut;
+pub use self::files_mut::*;
+
+mod entry;
+pub use self::entry::*;
+
+mod names;
+pub use self::names::*;
+
 #[cfg(test)]
 mod tests;
@@ -0,0 +1,81 @@
+use crate::*;
+
+/// Metadata of a file or directory, see [`Directory::metadata`].
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct EntryMeta {
+	/// Content type of files, zero for directories.
+	pub content_type: u32,
+	/// Size of files in bytes, zero for directories.
+	pub size: u32,
+	/// Bytes occupied by the file section in the PAK file, zero for directories.
+	///
+	/// Packed files and links share their section with other files.
+	pub stored_size: u64,
+	/// The [descriptor flags](Descriptor::flags).
+	pub flags: u8,
+}
+
+impl EntryMeta {
+	/// Is this the metadata of a directory?
+	#[inline]
+	pub fn is_dir(&self) -> bool {
+		self.content_type == 0
+	}
+
+	/// Is this the metadata of a file?
+	#[inline]
+	pub fn is_file(&self) -> bool {
+		self.content_type != 0
+	}
+}
+
+impl Directory {
+	/// Returns if a file or directory exists at the path.
+	#[inline]
+	pub fn exists(&self, path: &[u8]) -> bool {
+		self.find_desc(path).is_some()
+	}
+
+	/// Returns if a directory exists at the path.
+	#[inline]
+	pub fn is_dir(&self, path: &[u8]) -> bool {
+		matches!(self.find_desc(path), Some(desc) if desc.is_dir())
+	}
+
+	/// Returns if a file exists at the path.
+	#[inline]
+	pub fn is_file(&self, path: &[u8]) -> bool {
+		self.find_file(path).is_some()
+	}
+
+	/// Returns the metadata of the file or directory at the path.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// let mut dir = paks::Directory::new();
+	/// dir.create_link(b"sounds/hit.wav", &paks::Descriptor::new(b"", 3, 2000));
+	///
+	/// assert!(dir.exists(b"sounds"));
+	/// assert!(dir.is_dir(b"sounds"));
+	/// assert!(dir.is_file(b"sounds/hit.wav"));
+	/// assert!(!dir.exists(b"sounds/miss.wav"));
+	///
+	/// let meta = dir.metadata(b"sounds/hit.wav").unwrap();
+	/// assert!(meta.is_file());
+	/// assert_eq!((meta.content_type, meta.size), (3, 2000));
+	/// assert_eq!(dir.metadata(b"sounds").unwrap().size, 0);
+	/// ```
+	pub fn metadata(&self, path: &[u8]) -> Option<EntryMeta> {
+		let desc = self.find_desc(path)?;
+		if desc.is_dir() {
+			return Some(EntryMeta { flags: desc.flags(), ..EntryMeta::default() });
+		}
+		Some(EntryMeta {
+			content_type: desc.content_type,
+			size: desc.content_size,
+			stored_size: desc.section.size as u64 * self.block_size as u64,
+			flags: desc.flags(),
+		})
+	}
+}
@@ -0,0 +1,132 @@
+use std::{ops, slice};
+use crate::*;
+
+/// Mutable access to a file descriptor, see [`Directory::iter_files_mut`].
+///
+/// Only the fields of the file descriptor which do not affect the layout of the directory can be changed.
+/// The file cannot be turned into a directory descriptor and its name cannot be changed.
+#[derive(Debug)]
+pub struct FileMut<'a> {
+	desc: &'a mut Descriptor,
+}
+
+impl<'a> ops::Deref for FileMut<'a> {
+	type Target = Descriptor;
+	#[inline]
+	fn deref(&self) -> &Descriptor {
+		self.desc
+	}
+}
+
+impl<'a> FileMut<'a> {
+	/// Sets the content type of the file.
+	///
+	/// Returns `false` and leaves the content type alone if it is zero, the content type of directory descriptors.
+	#[inline]
+	pub fn set_content_type(&mut self, content_type: u32) -> bool {
+		if content_type == 0 {
+			return false;
+		}
+		self.desc.content_type = content_type;
+		true
+	}
+
+	/// Sets the size of the file in bytes.
+	#[inline]
+	pub fn set_content_size(&mut self, content_size: u32) {
+		self.desc.content_size = content_size;
+	}
+
+	/// Sets the section object of the file.
+	#[inline]
+	pub fn set_section(&mut self, section: &Section) {
+		self.desc.section = *section;
+	}
+}
+
+/// Iterator over the file descriptors of a directory, see [`Directory::iter_files_mut`].
+#[derive(Debug)]
+pub struct IterFilesMut<'a> {
+	descs: slice::IterMut<'a, Descriptor>,
+}
+
+impl<'a> Iterator for IterFilesMut<'a> {
+	type Item = FileMut<'a>;
+	fn next(&mut self) -> Option<FileMut<'a>> {
+		self.descs.find(|desc| desc.is_file()).map(|desc| FileMut { desc })
+	}
+}
+
+impl Directory {
+	/// Iterates mutably over the file descriptors in directory order.
+	///
+	/// Directory descriptors are skipped, the structure of the directory cannot be corrupted through the iterator.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// let mut dir = paks::Directory::new();
+	/// dir.create_link(b"a/b", &paks::Descriptor::file(b""));
+	/// dir.create_link(b"c", &paks::Descriptor::file(b""));
+	///
+	/// for mut file in dir.iter_files_mut() {
+	///     assert!(file.set_content_type(7));
+	///     assert!(!file.set_content_type(0));
+	/// }
+	///
+	/// assert_eq!(dir.find_file(b"a/b").unwrap().content_type, 7);
+	/// assert_eq!(dir.find_desc(b"a").unwrap().content_size, 1);
+	/// ```
+	#[inline]
+	pub fn iter_files_mut(&mut self) -> IterFilesMut<'_> {
+		IterFilesMut { descs: self.descs.iter_mut() }
+	}
+
+	/// Finds a file descriptor by its path for editing.
+	///
+	/// Unlike the editors' `edit_file` no descriptor is created, returns `None` if there is no file at the path.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// let mut dir = paks::Directory::new();
+	/// dir.create_link(b"a/b", &paks::Descriptor::file(b""));
+	///
+	/// dir.find_file_mut(b"a/b").unwrap().set_content_size(42);
+	/// assert_eq!(dir.find_file(b"a/b").unwrap().content_size, 42);
+	///
+	/// assert!(dir.find_file_mut(b"a").is_none());
+	/// assert!(dir.find_file_mut(b"a/c").is_none());
+	/// assert!(dir.find(b"a/c").is_none());
+	/// ```
+	#[inline]
+	pub fn find_file_mut(&mut self, path: &[u8]) -> Option<FileMut<'_>> {
+		let i = self.file_index(path)?;
+		Some(FileMut { desc: &mut self.descs[i] })
+	}
+
+	// Finds the file for editing in place by the editors, retaining its revision like `create`
+	pub(crate) fn existing_file_mut(&mut self, path: &[u8]) -> Option<&mut Descriptor> {
+		self.file_index(path)?;
+		self.retain_revision(path);
+		// Retaining the revision may have moved the file
+		let i = self.file_index(path)?;
+		Some(&mut self.descs[i])
+	}
+
+	fn file_index(&self, path: &[u8]) -> Option<usize> {
+		let desc = self.lookup(path).first()?;
+		if !desc.is_file() {
+			return None;
+		}
+		self.position(desc)
+	}
+
+	// Unchecked mutable access to the descriptors for the editors relocating file sections
+	// Changing the name, content type or content size of the descriptors corrupts the directory
+	#[inline]
+	pub(crate) fn descs_mut(&mut self) -> &mut [Descriptor] {
+		self.paths = None;
+		&mut self.descs
+	}
+}
@@ -0,0 +1,50 @@
+use crate::*;
+
+impl Directory {
+	/// Returns the number of file descriptors referencing the section of the file descriptor.
+	///
+	/// [Links](Self::create_link) and [packed files](MemoryEditor::pack_files) share their section with other file descriptors.
+	/// The link count includes the file descriptor itself if it is in this directory.
+	/// Files with an empty section do not share any data and have a link count of one.
+	///
+	/// Returns zero for directory descriptors.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// let ref key = paks::Key::default();
+	/// let mut editor = paks::MemoryEditor::new();
+	/// editor.create_file(b"a", b"data", key);
+	/// let a = *editor.find_file(b"a").unwrap();
+	/// editor.create_link(b"b", &a);
+	///
+	/// assert_eq!(editor.nlink(&a), 2);
+	/// assert_eq!(editor.unlink(b"a").map(|(_, nlink)| nlink), Some(1));
+	/// assert_eq!(editor.unlink(b"b").map(|(_, nlink)| nlink), Some(0));
+	/// assert_eq!(editor.garbage(editor.high_mark()), a.section.size);
+	/// ```
+	pub fn nlink(&self, desc: &Descriptor) -> u32 {
+		if !desc.is_file() {
+			return 0;
+		}
+		if desc.section.size == 0 {
+			return 1;
+		}
+		self.descs.iter().filter(|other| other.is_file() && other.section == desc.section).count() as u32
+	}
+
+	/// Removes the descriptor at the given path and returns it with the number of file descriptors still referencing its section.
+	///
+	/// Removing a link leaves the data of the file in place for the remaining links.
+	/// When the last link is removed the link count is zero and the blocks of the section are garbage,
+	/// they are counted by [`garbage`](Self::garbage) and reclaimed by garbage collection.
+	///
+	/// Directory descriptors are removed as with [`remove`](Self::remove) and have a link count of zero.
+	///
+	/// Returns `None` and does nothing if the path does not exist or is [sealed](Self::seal).
+	pub fn unlink(&mut self, path: &[u8]) -> Option<(Descriptor, u32)> {
+		let deleted = self.remove(path)?;
+		let nlink = if deleted.is_file() && deleted.section.size != 0 { self.nlink(&deleted) } else { 0 };
+		Some((deleted, nlink))
+	}
+}
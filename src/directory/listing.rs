@@ -0,0 +1,63 @@
+use std::io;
+use crate::*;
+
+// Writes the field quoted if it contains a separator, quote or line break
+fn csv_field<W: io::Write>(writer: &mut W, field: &[u8]) -> io::Result<()> {
+	if !field.iter().any(|&chr| matches!(chr, b',' | b'"' | b'\r' | b'\n')) {
+		return writer.write_all(field);
+	}
+	let mut quoted = vec![b'"'];
+	for &chr in field {
+		if chr == b'"' {
+			quoted.push(b'"');
+		}
+		quoted.push(chr);
+	}
+	quoted.push(b'"');
+	writer.write_all(&quoted)
+}
+
+impl Directory {
+	/// Writes the directory listing as CSV.
+	///
+	/// The first line is the header `path,type,size,blocks,offset` followed by a line for every descriptor in directory order.
+	/// The type is either `dir` or `file`, the size is in bytes and the section's size and offset are in blocks.
+	/// The size, blocks and offset of directories are left empty.
+	/// Paths containing a comma, quote or line break are quoted.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// let mut directory = paks::Directory::new();
+	/// directory.create_link(b"sounds/hit.wav", &paks::Descriptor::new(b"", 1, 2000));
+	///
+	/// let mut csv = Vec::new();
+	/// directory.to_csv(&mut csv).unwrap();
+	/// assert_eq!(csv, b"path,type,size,blocks,offset\nsounds,dir,,,\nsounds/hit.wav,file,2000,0,0\n");
+	/// ```
+	pub fn to_csv<W: io::Write>(&self, mut writer: W) -> io::Result<()> {
+		writer.write_all(b"path,type,size,blocks,offset\n")?;
+		for (path, desc) in self.query(&Filter::Any) {
+			csv_field(&mut writer, &path)?;
+			if desc.is_dir() {
+				writer.write_all(b",dir,,,\n")?;
+			}
+			else {
+				writeln!(writer, ",file,{},{},{}", desc.content_size, desc.section.size, desc.section.offset)?;
+			}
+		}
+		Ok(())
+	}
+}
+
+#[test]
+fn test_csv_field() {
+	let field = |field: &[u8]| {
+		let mut csv = Vec::new();
+		csv_field(&mut csv, field).unwrap();
+		String::from_utf8(csv).unwrap()
+	};
+	assert_eq!(field(b"a/b"), "a/b");
+	assert_eq!(field(b"a,b"), "\"a,b\"");
+	assert_eq!(field(b"\"a\"b"), "\"\"\"a\"\"b\"");
+}
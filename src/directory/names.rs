@@ -0,0 +1,224 @@
+use std::{borrow::Cow, ops, str};
+use crate::*;
+
+/// Normalization of the names of the descriptors, see [`Directory::set_name_policy`].
+///
+/// The policy is stored in the [header flags](InfoHeader::flags) so readers look up paths the same way the PAK file was created.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct NamePolicy {
+	/// Normalizes the names to their canonical composition (NFC).
+	///
+	/// Pipelines on macOS produce decomposed names, eg. `e` followed by a combining acute accent instead of `é`.
+	/// Names which are equivalent but differ in their composition or in the order of their combining marks normalize to the same name.
+	pub nfc: bool,
+	/// Folds the names to lowercase.
+	pub case_fold: bool,
+	/// Replaces the paths by their 64-bit hashes, see [`path_hash`](Self::path_hash).
+	///
+	/// Every file is stored in the root directory named by the hash of its path in hex, the paths are not stored in the PAK file.
+	/// Files are still found by path and by hash with [`Directory::find_hashed`], listing the files yields their hashes.
+	/// Stripping the names hides the paths and shrinks the directory, keep the mapping from the paths to the files elsewhere.
+	///
+	/// A second hash of the path is kept with the name, creating a file whose path hash collides with the path of an existing file is refused.
+	///
+	/// Stripped PAK files have no directories, seals nor [trash](Directory::soft_remove).
+	pub stripped: bool,
+}
+
+impl NamePolicy {
+	pub(crate) const fn from_flags(flags: u8) -> NamePolicy {
+		NamePolicy {
+			nfc: flags & InfoHeader::FLAG_NFC != 0,
+			case_fold: flags & InfoHeader::FLAG_CASE_FOLD != 0,
+			stripped: flags & InfoHeader::FLAG_STRIPPED != 0,
+		}
+	}
+
+	pub(crate) fn flags(self) -> u8 {
+		let mut flags = 0;
+		if self.nfc {
+			flags |= InfoHeader::FLAG_NFC;
+		}
+		if self.case_fold {
+			flags |= InfoHeader::FLAG_CASE_FOLD;
+		}
+		if self.stripped {
+			flags |= InfoHeader::FLAG_STRIPPED;
+		}
+		flags
+	}
+
+	/// Applies the policy to the path.
+	///
+	/// Paths which are not valid UTF-8 are left alone.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// let policy = paks::NamePolicy { nfc: true, case_fold: true, stripped: false };
+	/// assert_eq!(&*policy.apply("Cafe\u{301}/Menu".as_bytes()), "caf\u{e9}/menu".as_bytes());
+	///
+	/// let policy = paks::NamePolicy { stripped: true, ..policy };
+	/// assert_eq!(&*policy.apply("Cafe\u{301}/Menu".as_bytes()), format!("{:016x}", policy.path_hash(b"caf\xc3\xa9/menu")).as_bytes());
+	/// ```
+	pub fn apply(self, path: &[u8]) -> Cow<'_, [u8]> {
+		// The root directory keeps its empty path
+		if self.stripped && !dir::normalize(path).is_empty() {
+			return Cow::Owned(hashed_name(self.path_hash(path)).into_bytes());
+		}
+		self.normalize(path)
+	}
+
+	/// Hashes the path with [`runtime::path_hash`] after normalizing it.
+	///
+	/// Stripped PAK files name the files by this hash, see [`stripped`](Self::stripped).
+	pub fn path_hash(self, path: &[u8]) -> u64 {
+		let path = self.normalize(path);
+		runtime::path_hash(&dir::normalize(&path))
+	}
+
+	// Second hash of the path telling apart paths with the same path hash, never zero
+	fn check_hash(self, path: &[u8]) -> u64 {
+		let path = self.normalize(path);
+		let mut hash = 0x84222325cbf29ce4u64;
+		for &byte in dir::normalize(&path).iter().rev() {
+			hash ^= byte as u64;
+			hash = hash.wrapping_mul(0x100000001b3);
+		}
+		u64::max(hash, 1)
+	}
+
+	// Keeps the check hash of the path in the unused bytes of a stripped name
+	pub(crate) fn stamp(self, name: &mut Name, path: &[u8]) {
+		if self.stripped {
+			name.buffer[CHECK_HASH].copy_from_slice(&self.check_hash(path).to_le_bytes());
+		}
+	}
+
+	// Returns whether the stripped name belongs to another path with the same path hash
+	// Names stamped before the check hash existed are zero and never collide
+	pub(crate) fn collides(self, name: &Name, path: &[u8]) -> bool {
+		let mut stamp = [0u8; 8];
+		stamp.copy_from_slice(&name.buffer[CHECK_HASH]);
+		let stamp = u64::from_le_bytes(stamp);
+		self.stripped && stamp != 0 && stamp != self.check_hash(path)
+	}
+
+	fn normalize(self, path: &[u8]) -> Cow<'_, [u8]> {
+		let mut path = match str::from_utf8(path) {
+			Ok(path) if self.nfc || self.case_fold => Cow::Borrowed(path),
+			_ => return Cow::Borrowed(path),
+		};
+		if self.case_fold && path.chars().any(|chr| chr.to_lowercase().ne(Some(chr))) {
+			path = Cow::Owned(path.chars().flat_map(char::to_lowercase).collect());
+		}
+		// Only non-ASCII names can be decomposed
+		if self.nfc && !path.is_ascii() && !unicode::is_composed(&path) {
+			path = Cow::Owned(unicode::compose(&path));
+		}
+		match path {
+			Cow::Borrowed(path) => Cow::Borrowed(path.as_bytes()),
+			Cow::Owned(path) => Cow::Owned(path.into_bytes()),
+		}
+	}
+}
+
+impl Directory {
+	/// Sets the normalization applied to the names when creating and looking up descriptors.
+	///
+	/// Prevents duplicate looking names differing only in their Unicode normalization or case, eg. from macOS and Windows pipelines.
+	/// The policy is stored in the PAK file and applied by the readers.
+	///
+	/// Returns `false` and does nothing if the directory is not empty, the existing names are not normalized.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// let ref key = paks::Key::default();
+	/// let mut editor = paks::MemoryEditor::new();
+	/// assert!(editor.set_name_policy(paks::NamePolicy { nfc: true, case_fold: true, stripped: false }));
+	///
+	/// // Decomposed as on macOS
+	/// editor.create_file("Textures/Cafe\u{301}.png".as_bytes(), b"data", key);
+	/// // Composed as on Windows
+	/// editor.create_file("textures/caf\u{e9}.png".as_bytes(), b"data", key);
+	/// assert_eq!(editor.file_count(), 1);
+	///
+	/// let (blocks, _) = editor.finish(key);
+	/// let reader = paks::MemoryReader::from_blocks(blocks, key).unwrap();
+	/// assert!(reader.find_file("TEXTURES/CAFE\u{301}.PNG".as_bytes()).is_some());
+	/// ```
+	pub fn set_name_policy(&mut self, policy: NamePolicy) -> bool {
+		if !self.descs.is_empty() {
+			return false;
+		}
+		self.names = policy;
+		true
+	}
+
+	// The readers apply the policy stored in the header flags
+	pub(crate) fn set_name_flags(&mut self, flags: u8) {
+		self.names = NamePolicy::from_flags(flags);
+	}
+
+	/// Returns the normalization applied to the names.
+	#[inline]
+	pub fn name_policy(&self) -> NamePolicy {
+		self.names
+	}
+
+	/// Finds the file descriptor by the hash of its path in a [stripped](NamePolicy::stripped) PAK file.
+	///
+	/// The hash is computed with [`NamePolicy::path_hash`], eg. by the tool keeping the mapping from paths to hashes.
+	/// Returns `None` if the names are not stripped.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// let ref key = paks::Key::default();
+	/// let mut editor = paks::MemoryEditor::new();
+	/// let policy = paks::NamePolicy { stripped: true, ..Default::default() };
+	/// assert!(editor.set_name_policy(policy));
+	/// editor.create_file(b"textures/wall.png", b"wall", key);
+	///
+	/// let hash = policy.path_hash(b"textures/wall.png");
+	/// let desc = editor.find_hashed(hash).unwrap();
+	/// assert_eq!(desc.name(), format!("{:016x}", hash).as_bytes());
+	/// assert_eq!(editor.find_file(b"textures/wall.png"), Some(desc));
+	/// ```
+	pub fn find_hashed(&self, hash: u64) -> Option<&Descriptor> {
+		if !self.names.stripped {
+			return None;
+		}
+		let name = hashed_name(hash);
+		let descs = if self.sorted { dir::find_sorted(&self.descs, name.as_bytes()) } else { dir::find(&self.descs, name.as_bytes()) };
+		descs.first().filter(|desc| desc.is_file())
+	}
+
+	/// Returns whether the path hash collides with the path of another file in a [stripped](NamePolicy::stripped) PAK file.
+	///
+	/// Creating a file at the path is refused as it would overwrite the other file.
+	/// Always `false` if the names are not stripped.
+	pub fn hash_collides(&self, path: &[u8]) -> bool {
+		match self.find_hashed(self.names.path_hash(path)) {
+			Some(desc) => self.names.collides(&desc.name, path),
+			None => false,
+		}
+	}
+}
+
+// The check hash follows the hashed name in the name buffer
+const CHECK_HASH: ops::Range<usize> = 16..24;
+
+// Name of the file with the given path hash in stripped PAK files
+fn hashed_name(hash: u64) -> String {
+	format!("{:016x}", hash)
+}
+
+// Parses the path hash from the name of a file in a stripped PAK file
+pub(crate) fn parse_hashed_name(name: &[u8]) -> Option<u64> {
+	if name.len() != 16 {
+		return None;
+	}
+	u64::from_str_radix(str::from_utf8(name).ok()?, 16).ok()
+}
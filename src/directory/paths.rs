@@ -0,0 +1,67 @@
+use std::borrow::Cow;
+use crate::*;
+
+// Full path of every descriptor in the directory
+#[derive(Clone, Debug, Default)]
+pub(super) struct PathCache {
+	buf: Vec<u8>,
+	// Range in the buffer of the path of the descriptor at the same index
+	spans: Vec<(u32, u32)>,
+}
+
+impl PathCache {
+	pub(super) fn new(dir: &[Descriptor]) -> PathCache {
+		let mut cache = PathCache {
+			buf: Vec::new(),
+			spans: Vec::with_capacity(dir.len()),
+		};
+		let mut paths = dir::paths(dir);
+		while let Some((path, _)) = paths.next_path() {
+			let start = cache.buf.len() as u32;
+			cache.buf.extend_from_slice(path);
+			cache.spans.push((start, cache.buf.len() as u32));
+		}
+		cache
+	}
+	pub(super) fn get(&self, index: usize) -> Option<&[u8]> {
+		let &(start, end) = self.spans.get(index)?;
+		Some(&self.buf[start as usize..end as usize])
+	}
+}
+
+impl Directory {
+	/// Precomputes the full path of every descriptor.
+	///
+	/// Speeds up [`path_of`](Self::path_of) at the cost of memory.
+	/// The cache is discarded when the directory is edited.
+	pub fn cache_paths(&mut self) {
+		self.paths = Some(PathCache::new(&self.descs));
+	}
+
+	/// Returns the full path of the descriptor.
+	///
+	/// The descriptor must be a reference into this directory, eg. obtained from [`find_desc`](Self::find_desc).
+	/// Returns `None` if the descriptor is not part of this directory.
+	///
+	/// Without a [path cache](Self::cache_paths) the path is computed by traversing the directory.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// let mut directory = paks::Directory::new();
+	/// directory.create_link(b"textures/ui/icon.png", &paks::Descriptor::file(b""));
+	///
+	/// let desc = directory.find_desc(b"textures/ui/icon.png").unwrap();
+	/// assert_eq!(directory.path_of(desc).as_deref(), Some(&b"textures/ui/icon.png"[..]));
+	///
+	/// let other = paks::Descriptor::file(b"icon.png");
+	/// assert_eq!(directory.path_of(&other), None);
+	/// ```
+	pub fn path_of(&self, desc: &Descriptor) -> Option<Cow<'_, [u8]>> {
+		let index = self.position(desc)?;
+		match &self.paths {
+			Some(paths) => paths.get(index).map(Cow::Borrowed),
+			None => dir::path_of(&self.descs, index).map(Cow::Owned),
+		}
+	}
+}
@@ -0,0 +1,277 @@
+use std::{iter, ops};
+use crate::*;
+
+/// Composable predicate on descriptors, see [`Directory::query`].
+///
+/// Combine filters with [`and`](Self::and), [`or`](Self::or) and negate them with `!`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Filter<'a> {
+	/// Matches every descriptor.
+	Any,
+	/// Matches file descriptors.
+	Files,
+	/// Matches directory descriptors.
+	Dirs,
+	/// Matches file descriptors whose content size is larger than the given number of bytes.
+	LargerThan(u32),
+	/// Matches file descriptors whose content size is smaller than the given number of bytes.
+	SmallerThan(u32),
+	/// Matches descriptors with any of the given content types.
+	///
+	/// Directory descriptors have a content type of zero.
+	ContentType(&'a [u32]),
+	/// Matches descriptors whose name matches the glob pattern.
+	///
+	/// The wildcard `*` matches any sequence of bytes and `?` matches any single byte.
+	Name(&'a [u8]),
+	/// Matches descriptors under the directory at the given path.
+	Under(&'a [u8]),
+	/// Matches descriptors matching both filters.
+	And(Box<Filter<'a>>, Box<Filter<'a>>),
+	/// Matches descriptors matching either filter.
+	Or(Box<Filter<'a>>, Box<Filter<'a>>),
+	/// Matches descriptors not matching the filter.
+	Not(Box<Filter<'a>>),
+}
+
+impl<'a> Filter<'a> {
+	/// Matches descriptors matching both filters.
+	#[inline]
+	pub fn and(self, other: Filter<'a>) -> Filter<'a> {
+		Filter::And(Box::new(self), Box::new(other))
+	}
+
+	/// Matches descriptors matching either filter.
+	#[inline]
+	pub fn or(self, other: Filter<'a>) -> Filter<'a> {
+		Filter::Or(Box::new(self), Box::new(other))
+	}
+
+	/// Returns if the descriptor at the given path matches the filter.
+	pub fn matches(&self, path: &[u8], desc: &Descriptor) -> bool {
+		match self {
+			Filter::Any => true,
+			Filter::Files => desc.is_file(),
+			Filter::Dirs => desc.is_dir(),
+			&Filter::LargerThan(size) => desc.is_file() && desc.content_size > size,
+			&Filter::SmallerThan(size) => desc.is_file() && desc.content_size < size,
+			Filter::ContentType(types) => types.contains(&desc.content_type),
+			Filter::Name(pattern) => glob(pattern, desc.name()),
+			Filter::Under(dir) => {
+				match path.strip_prefix(*dir) {
+					Some(tail) => (dir.is_empty() && !tail.is_empty()) || (tail.len() > 1 && tail[0] == b'/'),
+					None => false,
+				}
+			},
+			Filter::And(a, b) => a.matches(path, desc) && b.matches(path, desc),
+			Filter::Or(a, b) => a.matches(path, desc) || b.matches(path, desc),
+			Filter::Not(a) => !a.matches(path, desc),
+		}
+	}
+}
+
+impl<'a> ops::Not for Filter<'a> {
+	type Output = Filter<'a>;
+	#[inline]
+	fn not(self) -> Filter<'a> {
+		Filter::Not(Box::new(self))
+	}
+}
+
+// Matches the name against the glob pattern
+pub(crate) fn glob(pattern: &[u8], name: &[u8]) -> bool {
+	let (mut p, mut n) = (0, 0);
+	// Position of the last `*` and the name position it is retried from
+	let mut star = None;
+	while n < name.len() {
+		match pattern.get(p) {
+			Some(b'*') => {
+				star = Some((p, n));
+				p += 1;
+			},
+			Some(&chr) if chr == b'?' || chr == name[n] => {
+				p += 1;
+				n += 1;
+			},
+			_ => match star {
+				// Let the `*` match one more byte
+				Some((star_p, star_n)) => {
+					star = Some((star_p, star_n + 1));
+					p = star_p + 1;
+					n = star_n + 1;
+				},
+				None => return false,
+			},
+		}
+	}
+	pattern[p..].iter().all(|&chr| chr == b'*')
+}
+
+/// Iterator over the descriptors matching a filter, see [`Directory::query`].
+#[derive(Clone, Debug)]
+pub struct Query<'a> {
+	paths: dir::Paths<'a>,
+	filter: &'a Filter<'a>,
+}
+
+impl<'a> Iterator for Query<'a> {
+	type Item = (Vec<u8>, &'a Descriptor);
+
+	fn next(&mut self) -> Option<(Vec<u8>, &'a Descriptor)> {
+		loop {
+			let (path, desc) = self.paths.next_path()?;
+			if self.filter.matches(path, desc) {
+				return Some((path.to_vec(), desc));
+			}
+		}
+	}
+}
+
+impl Directory {
+	/// Returns the descriptors matching the filter with their full paths.
+	///
+	/// Descriptors are visited in directory order, parents before their children.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use paks::{Descriptor, Directory, Filter};
+	///
+	/// let mut directory = Directory::new();
+	/// directory.create_link(b"textures/icon.png", &Descriptor::new(b"", 1, 500));
+	/// directory.create_link(b"textures/wall.png", &Descriptor::new(b"", 1, 90000));
+	/// directory.create_link(b"sounds/hit.wav", &Descriptor::new(b"", 2, 20000));
+	///
+	/// let filter = Filter::Name(b"*.png").and(Filter::LargerThan(1000)).or(Filter::ContentType(&[2]));
+	/// let found: Vec<_> = directory.query(&filter).map(|(path, _)| path).collect();
+	/// assert_eq!(found, [&b"textures/wall.png"[..], b"sounds/hit.wav"]);
+	///
+	/// let filter = Filter::Under(b"textures").and(!Filter::Name(b"wall*"));
+	/// assert_eq!(directory.query(&filter).count(), 1);
+	/// ```
+	pub fn query<'a>(&'a self, filter: &'a Filter<'a>) -> Query<'a> {
+		Query { paths: dir::paths(&self.descs), filter }
+	}
+
+	/// Returns the section of every file descriptor with its full path.
+	///
+	/// Intended for replication and backup tools copying the encrypted sections with [`FileReader::raw_blocks`] and verifying them with [`Section::verify_mac`], the contents are never decrypted.
+	/// Sections shared by [links](Self::create_link) are returned once for every path, the meta sections are found with [`Descriptor::meta_section`].
+	///
+	/// # Examples
+	///
+	/// ```
+	/// let ref key = paks::Key::default();
+	/// let mut editor = paks::MemoryEditor::new();
+	/// editor.create_file(b"textures/wall.png", b"wall", key);
+	/// editor.create_file(b"sounds/hit.wav", b"hit", key);
+	/// let (blocks, _) = editor.finish(key);
+	///
+	/// let reader = paks::MemoryReader::from_blocks(blocks, key).unwrap();
+	/// for (path, section) in reader.sections() {
+	///     let raw = reader.raw_blocks(section.offset..section.offset + section.size).unwrap();
+	///     assert!(section.verify_mac(raw, key), "{}", String::from_utf8_lossy(&path));
+	/// }
+	/// ```
+	pub fn sections(&self) -> impl Iterator<Item = (Vec<u8>, Section)> + '_ {
+		let mut paths = dir::paths(&self.descs);
+		iter::from_fn(move || loop {
+			let (path, desc) = paths.next_path()?;
+			if desc.is_file() {
+				return Some((path.to_vec(), desc.section));
+			}
+		})
+	}
+
+	/// Retains only the file descriptors for which the predicate returns `true`.
+	///
+	/// The predicate is called with the full path of every file descriptor.
+	/// Directories left empty by removing their files are removed as well, the seals are ignored.
+	/// Returns the number of file descriptors removed.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// let mut directory = paks::Directory::new();
+	/// directory.create_link(b"editor/gizmo", &paks::Descriptor::file(b""));
+	/// directory.create_link(b"game/player", &paks::Descriptor::file(b""));
+	/// directory.create_dir(b"empty");
+	///
+	/// assert_eq!(directory.retain(|path, _| !path.starts_with(b"editor/")), 1);
+	/// assert!(directory.find(b"editor").is_none());
+	/// assert!(directory.find(b"game/player").is_some());
+	/// assert!(directory.find(b"empty").is_some());
+	/// ```
+	pub fn retain<F: FnMut(&[u8], &Descriptor) -> bool>(&mut self, mut f: F) -> usize {
+		let removed: Vec<Vec<u8>> = self.query(&Filter::Files)
+			.filter(|(path, desc)| !f(path, desc))
+			.map(|(path, _)| path)
+			.collect();
+		for path in &removed {
+			self.remove_index(path);
+			// Remove the parent directories left empty
+			let mut dir = &path[..];
+			while let Some(i) = dir.iter().rposition(|&chr| chr == b'/') {
+				dir = &dir[..i];
+				if !matches!(self.get_children(dir), Some(children) if children.is_empty()) {
+					break;
+				}
+				self.remove_index(dir);
+			}
+		}
+		removed.len()
+	}
+
+	// Lists the file descriptors at or under the path with their full paths
+	// Returns `None` if the path does not exist, an empty path lists every file
+	pub(crate) fn files_under(&self, path: &[u8]) -> Option<Vec<(Vec<u8>, Descriptor)>> {
+		if !path.is_empty() {
+			let desc = self.find_desc(path)?;
+			if desc.is_file() {
+				return Some(vec![(path.to_vec(), *desc)]);
+			}
+		}
+		let filter = Filter::Files.and(Filter::Under(path));
+		Some(self.query(&filter).map(|(path, desc)| (path, *desc)).collect())
+	}
+
+	/// Returns the descriptors whose full path matches the regular expression.
+	///
+	/// The pattern is matched against the full path without a leading or trailing `/`, use `^` and `$` to anchor the match.
+	/// Descriptors are visited in directory order, see [`query`](Self::query).
+	///
+	/// Returns an error if the pattern is not a valid regular expression.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use paks::{Descriptor, Directory};
+	///
+	/// let mut directory = Directory::new();
+	/// directory.create_link(b"levels/level1/map.bsp", &Descriptor::new(b"", 1, 500));
+	/// directory.create_link(b"levels/level12/map.bsp", &Descriptor::new(b"", 1, 500));
+	/// directory.create_link(b"levels/intro/map.bsp", &Descriptor::new(b"", 1, 500));
+	///
+	/// let found: Vec<_> = directory.find_regex(r"^levels/level\d+/.*\.bsp$").unwrap().into_iter().map(|(path, _)| path).collect();
+	/// assert_eq!(found, [&b"levels/level1/map.bsp"[..], b"levels/level12/map.bsp"]);
+	/// ```
+	#[cfg(feature = "regex")]
+	pub fn find_regex(&self, pattern: &str) -> Result<Vec<(Vec<u8>, &Descriptor)>, regex::Error> {
+		let regex = regex::bytes::Regex::new(pattern)?;
+		Ok(self.query(&Filter::Any).filter(|(path, _)| regex.is_match(path)).collect())
+	}
+}
+
+#[test]
+fn test_glob() {
+	assert!(glob(b"", b""));
+	assert!(glob(b"*", b""));
+	assert!(glob(b"*.png", b"icon.png"));
+	assert!(glob(b"*.png", b".png"));
+	assert!(!glob(b"*.png", b"icon.pngx"));
+	assert!(glob(b"a?c*", b"abcdef"));
+	assert!(glob(b"*a*b*", b"xxaxxbxx"));
+	assert!(!glob(b"*a*b", b"xxaxxbxx"));
+	assert!(!glob(b"abc", b"ab"));
+	assert!(glob(b"a**", b"a"));
+}
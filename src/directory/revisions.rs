@@ -0,0 +1,158 @@
+use std::str;
+use crate::*;
+
+// Parses the revision number from the descriptor name
+fn revision(desc: &Descriptor) -> Option<u32> {
+	if !desc.is_file() {
+		return None;
+	}
+	str::from_utf8(desc.name()).ok()?.parse().ok()
+}
+
+// Returns if the path is in the revisions directory
+fn is_revision(path: &[u8]) -> bool {
+	match path.strip_prefix(Directory::REVISIONS) {
+		Some(tail) => tail.is_empty() || tail[0] == b'/',
+		None => false,
+	}
+}
+
+// Path of the directory holding the revisions of the path
+fn revisions_dir(path: &[u8]) -> Vec<u8> {
+	let mut dir = Directory::REVISIONS.to_vec();
+	dir.push(b'/');
+	dir.extend_from_slice(path);
+	dir
+}
+
+impl Directory {
+	/// Path of the hidden directory holding the previous revisions of overwritten files.
+	///
+	/// The revisions of a file are stored at `.rev/<path>/<n>` where `n` counts up from zero.
+	pub const REVISIONS: &'static [u8] = b".rev";
+
+	/// Keeps up to the given number of previous revisions of overwritten files.
+	///
+	/// When a file is overwritten, its previous descriptor is retained under the [revisions](Self::REVISIONS) directory.
+	/// Files are overwritten by [`create_link`](Self::create_link), [`move_file`](Self::move_file) and the editors creating a file at an existing path.
+	/// The oldest revisions are expired once a file has more revisions than the given number.
+	///
+	/// The revisions keep referencing the previous file data which is not reclaimed by garbage collection.
+	/// The default of zero does not keep any revisions, existing revisions are left alone.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// let mut directory = paks::Directory::new();
+	/// directory.set_revisions(2);
+	///
+	/// directory.create_link(b"config", &paks::Descriptor::new(b"", 1, 1));
+	/// directory.create_link(b"config", &paks::Descriptor::new(b"", 1, 2));
+	/// directory.create_link(b"config", &paks::Descriptor::new(b"", 1, 3));
+	///
+	/// let revisions: Vec<_> = directory.revisions(b"config").into_iter().map(|(n, desc)| (n, desc.content_size)).collect();
+	/// assert_eq!(revisions, [(0, 1), (1, 2)]);
+	///
+	/// assert!(directory.restore_revision(b"config", 0));
+	/// assert_eq!(directory.find_file(b"config").unwrap().content_size, 1);
+	/// ```
+	#[inline]
+	pub fn set_revisions(&mut self, keep: u32) {
+		self.revisions = keep;
+	}
+
+	/// Returns the previous revisions of the file at the given path, from oldest to newest.
+	pub fn revisions(&self, path: &[u8]) -> Vec<(u32, &Descriptor)> {
+		let mut revisions: Vec<_> = match self.get_children(&revisions_dir(path)) {
+			Some(children) => children.iter().filter_map(|desc| Some((revision(desc)?, desc))).collect(),
+			None => Vec::new(),
+		};
+		revisions.sort_by_key(|&(n, _)| n);
+		revisions
+	}
+
+	/// Restores the file at the given path to a previous revision.
+	///
+	/// The current file is retained as a new revision if [revisions are kept](Self::set_revisions).
+	/// Returns `false` if the revision does not exist or the file cannot be overwritten.
+	pub fn restore_revision(&mut self, path: &[u8], n: u32) -> bool {
+		let desc = match self.revisions(path).into_iter().find(|&(rev, _)| rev == n) {
+			Some((_, desc)) => *desc,
+			None => return false,
+		};
+		self.create_link(path, &desc).is_some()
+	}
+
+	/// Expires all but the newest given number of revisions of every file.
+	///
+	/// Empty revision directories are removed, returns the number of revisions expired.
+	/// The file data only referenced by the expired revisions can be reclaimed by garbage collection.
+	pub fn expire_revisions(&mut self, keep: u32) -> usize {
+		let mut dirs = Vec::new();
+		self.collect_dirs(Directory::REVISIONS.to_vec(), &mut dirs);
+		let mut expired = 0;
+		// Children are collected after their parents, remove the deepest directories first
+		for dir in dirs.iter().rev() {
+			expired += self.expire(dir, keep);
+			if matches!(self.get_children(dir), Some(children) if children.is_empty()) {
+				self.remove_index(dir);
+			}
+		}
+		expired
+	}
+
+	// Collects the paths of the directories in the subtree
+	fn collect_dirs(&self, path: Vec<u8>, dirs: &mut Vec<Vec<u8>>) {
+		let children = match self.get_children(&path) {
+			Some(children) => children,
+			None => return,
+		};
+		let names: Vec<Vec<u8>> = children.iter()
+			.filter(|desc| desc.is_dir())
+			.map(|desc| desc.name().to_vec())
+			.collect();
+		dirs.push(path.clone());
+		for name in names {
+			let mut child = path.clone();
+			child.push(b'/');
+			child.extend_from_slice(&name);
+			self.collect_dirs(child, dirs);
+		}
+	}
+
+	// Removes the oldest revisions in the revisions directory
+	fn expire(&mut self, dir: &[u8], keep: u32) -> usize {
+		let mut numbers: Vec<u32> = match self.get_children(dir) {
+			Some(children) => children.iter().filter_map(revision).collect(),
+			None => return 0,
+		};
+		numbers.sort_unstable();
+		let excess = numbers.len().saturating_sub(keep as usize);
+		for n in &numbers[..excess] {
+			let mut path = dir.to_vec();
+			path.push(b'/');
+			path.extend_from_slice(n.to_string().as_bytes());
+			self.remove_index(&path);
+		}
+		excess
+	}
+
+	// Retains the file at the path as a new revision before it is overwritten
+	pub(super) fn retain_revision(&mut self, path: &[u8]) {
+		if self.revisions == 0 || is_revision(path) {
+			return;
+		}
+		let desc = match self.lookup(path).first() {
+			Some(desc) if desc.is_file() => *desc,
+			_ => return,
+		};
+		let dir = revisions_dir(path);
+		let n = self.revisions(path).last().map_or(0, |&(n, _)| n + 1);
+		let mut rev_path = dir.clone();
+		rev_path.push(b'/');
+		rev_path.extend_from_slice(n.to_string().as_bytes());
+		let i = self.create_index(&rev_path);
+		self.descs[i] = Descriptor { name: self.descs[i].name, ..desc };
+		self.expire(&dir, self.revisions);
+	}
+}
@@ -0,0 +1,41 @@
+use crate::*;
+
+// Returns if the contents of the file descriptors overlap in their shared section
+fn overlaps(a: &Descriptor, b: &Descriptor) -> bool {
+	let a_start = a.content_offset() as u64;
+	let b_start = b.content_offset() as u64;
+	a.section == b.section && a_start < b_start + b.content_size as u64 && b_start < a_start + a.content_size as u64
+}
+
+impl Directory {
+	// Returns if any file descriptors still reference the section of the file after shredding it
+	// File descriptors whose contents overlap with the file are shredded along with it
+	pub(crate) fn shred_shares(&self, file: &Descriptor) -> bool {
+		self.descs.iter().any(|desc| desc.is_file() && desc.section == file.section && !overlaps(desc, file))
+	}
+
+	// Empties the file descriptor at the path and every file descriptor whose contents overlap with it
+	// The remaining file descriptors sharing its section are assigned the re-encrypted section
+	pub(crate) fn shred_file(&mut self, path: &[u8], file: &Descriptor, section: &Section, key: &Key) {
+		// The emptied files remain readable as empty files
+		let mut empty = Section { offset: file.section.offset, ..Section::default() };
+		crypt::encrypt_section(&mut [], &mut empty, key);
+
+		let index = self.lookup(path).first().and_then(|desc| self.position(desc));
+		for (i, desc) in self.descs.iter_mut().enumerate() {
+			if !desc.is_file() || desc.section != file.section {
+				continue;
+			}
+			if Some(i) == index || overlaps(desc, file) {
+				desc.content_size = 0;
+				desc.section = empty;
+				desc.unpack();
+				// The metadata may describe the shredded contents
+				desc.set_meta_section(&Section::default());
+			}
+			else {
+				desc.section = *section;
+			}
+		}
+	}
+}
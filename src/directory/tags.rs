@@ -0,0 +1,74 @@
+use crate::*;
+
+impl Directory {
+	/// Returns the files with the given tag and their full paths, see [`MemoryEditor::tag`].
+	///
+	/// The tags are indexed when the PAK file is opened and updated by tagging and untagging files.
+	/// Indexing decrypts the meta section of every file with [metadata](Descriptor::meta_section) when the PAK file is opened.
+	///
+	/// Files are returned in directory order.
+	pub fn find_by_tag(&self, tag: &str) -> Vec<(Vec<u8>, &Descriptor)> {
+		let ids = match self.tags.get(tag) {
+			Some(ids) => ids,
+			None => return Vec::new(),
+		};
+		let mut indices: Vec<usize> = ids.iter()
+			.filter_map(|&id| self.index_of(id))
+			// The tags are stored in the metadata, files without metadata lost their tags
+			.filter(|&index| self.descs[index].meta_section().is_some())
+			.collect();
+		indices.sort_unstable();
+		indices.into_iter()
+			.filter_map(|index| Some((dir::path_of(&self.descs, index)?, &self.descs[index])))
+			.collect()
+	}
+
+	/// Returns the tags of the files in the directory in sorted order.
+	pub fn tags(&self) -> Vec<&str> {
+		self.tags.keys()
+			.filter(|tag| !self.find_by_tag(tag).is_empty())
+			.map(String::as_str)
+			.collect()
+	}
+
+	// Indexes the tags of the files with metadata
+	// The callback decrypts the meta section, files whose metadata cannot be read are not indexed
+	pub(crate) fn index_tags<F: FnMut(&Section) -> Option<Vec<Block>>>(&mut self, mut read_section: F) {
+		self.tags.clear();
+		for i in 0..self.descs.len() {
+			let section = match self.descs[i].meta_section() {
+				Some(section) => section,
+				None => continue,
+			};
+			let meta = match read_section(&section).and_then(|blocks| MetaMap::decode(blocks.as_bytes())) {
+				Some(meta) => meta,
+				None => continue,
+			};
+			for tag in meta.tags() {
+				self.tags.entry(String::from(tag)).or_default().push(self.ids[i]);
+			}
+		}
+	}
+
+	// Replaces the indexed tags of the dest file with the tags of the src file
+	pub(crate) fn copy_tags(&mut self, src: DescriptorId, dest: DescriptorId) {
+		for ids in self.tags.values_mut() {
+			ids.retain(|&id| id != dest);
+			if ids.contains(&src) {
+				ids.push(dest);
+			}
+		}
+	}
+
+	// Adds or removes the file from the index of the tag
+	pub(crate) fn index_tag(&mut self, id: DescriptorId, tag: &str, add: bool) {
+		let ids = self.tags.entry(String::from(tag)).or_default();
+		ids.retain(|&other| other != id);
+		if add {
+			ids.push(id);
+		}
+		else if ids.is_empty() {
+			self.tags.remove(tag);
+		}
+	}
+}
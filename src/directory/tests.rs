@@ -15,3 +15,411 @@ fn test_create_remove_create_links() {
 
 	dbg!(directory);
 }
+
+#[test]
+fn test_undo_stack() {
+	let mut directory = Directory::new();
+	let mut undo = UndoStack::new();
+
+	let mut file = Descriptor::file(b"");
+	file.section.offset = 2;
+	file.section.size = 1;
+
+	assert!(undo.create_link(&mut directory, b"a/file", &file));
+	let before_move = directory.snapshot();
+	assert!(undo.move_file(&mut directory, b"a/file", b"b/file"));
+	assert!(!undo.remove(&mut directory, b"a/file"));
+	assert!(undo.remove(&mut directory, b"a"));
+	assert_eq!(undo.edits().count(), 3);
+
+	assert!(undo.undo(&mut directory));
+	assert!(undo.undo(&mut directory));
+	assert_eq!(directory.snapshot(), before_move);
+
+	assert!(undo.redo(&mut directory));
+	assert_eq!(directory.find_file(b"b/file").unwrap().section, file.section);
+	assert!(directory.find_file(b"a/file").is_none());
+
+	// A new edit clears the redo history
+	assert!(undo.create_dir(&mut directory, b"c"));
+	assert!(!undo.can_redo());
+	assert!(!undo.redo(&mut directory));
+}
+
+#[test]
+fn test_undo_limit() {
+	let mut directory = Directory::new();
+	let empty = directory.snapshot();
+	let mut undo = UndoStack::new();
+	undo.set_limit(2);
+
+	assert!(undo.create_dir(&mut directory, b"a"));
+	let first = directory.snapshot();
+	assert!(undo.create_dir(&mut directory, b"a/b"));
+	assert!(undo.create_dir(&mut directory, b"c"));
+	assert_eq!(undo.edits().count(), 2);
+
+	// The oldest edit is forgotten
+	assert!(undo.undo(&mut directory));
+	assert!(undo.undo(&mut directory));
+	assert!(!undo.undo(&mut directory));
+	assert_eq!(directory.snapshot(), first);
+
+	// Redo and undo restore the same descriptors
+	assert!(undo.redo(&mut directory));
+	assert!(undo.redo(&mut directory));
+	assert!(directory.find_desc(b"a/b").is_some());
+	assert!(directory.find_desc(b"c").is_some());
+	assert_ne!(directory.snapshot(), empty);
+}
+
+#[test]
+fn test_audit_nonces() {
+	let mut file = Descriptor::file(b"");
+	file.section = Section { offset: 2, size: 1, nonce: [1, 2], mac: [3, 4] };
+
+	let mut directory = Directory::new();
+	directory.create_link(b"a", &file);
+	directory.create_link(b"b", &file);
+	assert_eq!(directory.audit_nonces(&[]), []);
+
+	// Different section with the same nonce
+	file.section.offset = 3;
+	directory.create_link(b"c", &file);
+	let reuses = directory.audit_nonces(&[]);
+	assert_eq!(reuses.len(), 1);
+	assert_eq!(reuses[0].nonce, [1, 2]);
+	assert_eq!(reuses[0].sections.len(), 2);
+
+	// Empty sections are ignored
+	let empty = Section { offset: 5, size: 0, nonce: [1, 2], mac: [0, 0] };
+	assert_eq!(directory.audit_nonces(&[empty]).len(), 1);
+
+	// The metadata section reuses the nonce of the file data
+	file.section = Section { offset: 10, size: 1, nonce: [5, 6], mac: [7, 8] };
+	file.meta = Section { offset: 11, size: 1, nonce: [5, 6], mac: [9, 10] };
+	directory.create_link(b"d", &file);
+	let reuses = directory.audit_nonces(&[]);
+	assert_eq!(reuses.len(), 2);
+	assert_eq!(reuses[1].nonce, [5, 6]);
+	assert_eq!(reuses[1].sections, [file.section, file.meta]);
+}
+
+#[test]
+fn test_descriptor_ids() {
+	let mut directory = Directory::new();
+
+	let mut file = Descriptor::file(b"");
+	file.section.offset = 2;
+	file.section.size = 1;
+
+	let a = directory.create_link(b"x/a", &file).unwrap();
+	let b = directory.create_link(b"x/b", &file).unwrap();
+	let x = directory.find(b"x").unwrap();
+	assert_eq!(directory.find(b"x/a"), Some(a));
+	assert_eq!(directory.create_link(b"x/a", &file), Some(a));
+	assert_eq!(directory.create_dir(b"dir"), directory.find(b"dir"));
+
+	// Unrelated splices keep the handles valid
+	let c = directory.create_link(b"a/b/c", &file).unwrap();
+	assert_eq!(directory.get(a).unwrap().name(), b"a");
+	assert_eq!(directory.get(b).unwrap().name(), b"b");
+	assert_eq!(directory.get(x).unwrap().name(), b"x");
+	assert!(directory.remove(b"x/a").is_some());
+	assert_eq!(directory.get(a), None);
+	assert_eq!(directory.get(b).unwrap().name(), b"b");
+	assert_eq!(directory.get(c).unwrap().name(), b"c");
+
+	// Moved descriptors keep their handle
+	let before_move = directory.snapshot();
+	assert!(directory.move_file(b"x/b", b"y/z/b"));
+	assert_eq!(directory.find(b"y/z/b"), Some(b));
+	directory.get_mut(b).unwrap().content_type = 7;
+	assert_eq!(directory.find_file(b"y/z/b").unwrap().content_type, 7);
+
+	// Restoring a snapshot invalidates newer handles
+	let y = directory.find(b"y").unwrap();
+	directory.restore(before_move);
+	assert_eq!(directory.get(y), None);
+	assert_eq!(directory.find(b"x/b"), Some(b));
+	assert_eq!(directory.get(b).unwrap().content_type, file.content_type);
+}
+
+#[test]
+fn test_apply_batch() {
+	let mut file = Descriptor::file(b"");
+	file.content_type = 1;
+	file.section.size = 1;
+
+	// Pseudo random edits on a small set of paths to exercise duplicates and missing paths
+	const PATHS: [&[u8]; 8] = [b"a", b"b", b"a/b", b"a/c", b"b/c/d", b"a/b/c", b"c/", b"d"];
+	let mut seed = 0x2545F491u32;
+	let mut edits = Vec::new();
+	for _ in 0..200 {
+		seed ^= seed << 13;
+		seed ^= seed >> 17;
+		seed ^= seed << 5;
+		let path = PATHS[(seed >> 8) as usize % PATHS.len()].to_vec();
+		let dest_path = PATHS[(seed >> 16) as usize % PATHS.len()].to_vec();
+		file.section.offset = seed;
+		edits.push(match seed % 4 {
+			0 => Edit::CreateLink { path, desc: file },
+			1 => Edit::CreateDir { path },
+			2 => Edit::Remove { path },
+			_ => Edit::Move { src_path: path, dest_path },
+		});
+	}
+
+	for len in 0..edits.len() * 2 {
+		let sorted = len >= edits.len();
+		let len = len % edits.len();
+
+		let mut expected = Directory::new();
+		expected.set_sorted(sorted);
+		let expected_applied = edits[..len].iter().filter(|edit| edit.apply(&mut expected)).count();
+
+		let mut directory = Directory::new();
+		directory.set_sorted(sorted);
+		assert_eq!(directory.apply(&edits[..len]), expected_applied);
+		assert_eq!(directory.snapshot(), expected.snapshot());
+		assert!(directory.fsck(!0, &mut String::new()));
+	}
+}
+
+#[test]
+fn test_sorted() {
+	let mut file = Descriptor::file(b"");
+	file.content_type = 1;
+	file.section.offset = Header::BLOCKS_LEN as u32;
+
+	let mut directory = Directory::new();
+	for &path in &[&b"b/z"[..], b"b/a", b"a", b"c/y/x", b"b/m", b"c/a"] {
+		directory.create_link(path, &file);
+	}
+	assert!(!dir::is_sorted(directory.as_ref()));
+	let z = directory.find(b"b/z").unwrap();
+
+	directory.set_sorted(true);
+	assert!(dir::is_sorted(directory.as_ref()));
+	assert!(directory.fsck(!0, &mut String::new()));
+	assert_eq!(directory.find(b"b/z"), Some(z));
+
+	// New descriptors are inserted in sort order
+	directory.create_link(b"b/k", &file);
+	directory.create_link(b"0/0", &file);
+	assert!(directory.move_file(b"c/y/x", b"b/b"));
+	assert!(dir::is_sorted(directory.as_ref()));
+	let names: Vec<_> = directory.get_children(b"b").unwrap().iter().map(|desc| desc.name()).collect();
+	assert_eq!(names, [b"a", b"b", b"k", b"m", b"z"]);
+
+	// Binary search finds the same descriptors
+	for &path in &[&b"a"[..], b"b", b"b/k", b"b/z", b"c/y", b"0/0", b"c/y/x", b"d", b"b/k/a", b""] {
+		let expected = dir::find(directory.as_ref(), path);
+		assert_eq!(dir::find_sorted(directory.as_ref(), path), expected);
+	}
+
+	// Wide directories mixing files and subdirectories
+	for i in 0..40 {
+		let path = if i % 3 == 0 { format!("w/{:02}/f", i) } else { format!("w/{:02}", i) };
+		directory.create_link(path.as_bytes(), &file);
+	}
+	for i in 0..41 {
+		for path in &[format!("w/{:02}", i), format!("w/{:02}/f", i), format!("w/{:02}x", i)] {
+			let expected = dir::find(directory.as_ref(), path.as_bytes());
+			assert_eq!(dir::find_sorted(directory.as_ref(), path.as_bytes()), expected);
+		}
+	}
+
+	// The sort order is checked
+	directory.descs_mut().swap(0, 2);
+	let mut log = String::new();
+	assert!(!directory.fsck(!0, &mut log));
+	assert!(log.contains("not sorted"));
+}
+
+#[test]
+fn test_seal() {
+	let file = Descriptor::file(b"");
+	let mut directory = Directory::new();
+	directory.create_link(b"engine/core/a", &file);
+	directory.create_link(b"game/b", &file);
+	assert!(directory.seal(b"engine/core", true));
+	assert!(!directory.seal(b"game/b", true));
+	assert!(!directory.is_sealed(b"engine"));
+	assert!(directory.is_sealed(b"engine/core"));
+	assert!(directory.is_sealed(b"engine/core/missing"));
+
+	// Modifications under the sealed directory are refused
+	assert_eq!(directory.create_link(b"engine/core/b", &file), None);
+	assert_eq!(directory.create_dir(b"engine/core/c"), None);
+	assert_eq!(directory.remove(b"engine/core"), None);
+	assert!(!directory.move_file(b"engine/core/a", b"game/a"));
+	assert!(!directory.move_file(b"game/b", b"engine/core/b"));
+	let edits = [Edit::Remove { path: b"engine/core/a".to_vec() }, Edit::Remove { path: b"game/b".to_vec() }];
+	assert_eq!(directory.apply(&edits), 1);
+	assert!(directory.find(b"engine/core/a").is_some());
+
+	// Unless explicitly overridden
+	directory.set_ignore_seals(true);
+	assert!(directory.create_link(b"engine/core/b", &file).is_some());
+	directory.set_ignore_seals(false);
+	assert!(directory.seal(b"engine/core", false));
+	assert!(directory.remove(b"engine/core/a").is_some());
+}
+
+#[test]
+fn test_garbage_regions() {
+	let mut directory = Directory::new();
+	let start = header_blocks(directory.block_size());
+	let mut file = Descriptor::file(b"");
+	for &(path, offset, size) in &[(&b"a"[..], 2, 3), (b"b", 4, 2), (b"c", 10, 1), (b"d", 20, 0)] {
+		file.section = Section { offset: start + offset, size, ..Section::default() };
+		directory.create_link(path, &file);
+	}
+	assert_eq!(directory.garbage_regions(start + 15), [start..start + 2, start + 6..start + 10, start + 11..start + 15]);
+	assert_eq!(directory.garbage_regions(start + 11), [start..start + 2, start + 6..start + 10]);
+	assert_eq!(directory.garbage_regions(start + 12).iter().map(|region| region.end - region.start).sum::<u32>(), directory.garbage(start + 12));
+	assert_eq!(Directory::new().garbage_regions(start), []);
+}
+
+#[test]
+fn test_revisions() {
+	let mut directory = Directory::new();
+	directory.create_link(b"a/b", &Descriptor::new(b"", 1, 0));
+	directory.create_link(b"a/b", &Descriptor::new(b"", 1, 1));
+	assert!(directory.find(b".rev").is_none());
+
+	directory.set_revisions(3);
+	for size in 2..7 {
+		directory.create_link(b"a/b", &Descriptor::new(b"", 1, size));
+	}
+	directory.create_link(b"c", &Descriptor::new(b"", 1, 10));
+	assert!(directory.move_file(b"c", b"a/b"));
+	let sizes = |directory: &Directory| directory.revisions(b"a/b").iter().map(|&(n, desc)| (n, desc.content_size)).collect::<Vec<_>>();
+	assert_eq!(sizes(&directory), [(3, 4), (4, 5), (5, 6)]);
+	assert_eq!(directory.find_file(b"a/b").unwrap().content_size, 10);
+
+	assert!(directory.restore_revision(b"a/b", 4));
+	assert!(!directory.restore_revision(b"a/b", 0));
+	assert_eq!(directory.find_file(b"a/b").unwrap().content_size, 5);
+	assert_eq!(sizes(&directory), [(4, 5), (5, 6), (6, 10)]);
+
+	assert_eq!(directory.expire_revisions(1), 2);
+	assert_eq!(sizes(&directory), [(6, 10)]);
+	assert_eq!(directory.expire_revisions(0), 1);
+	assert!(directory.find(b".rev").is_none());
+	assert!(directory.fsck(u32::MAX, &mut String::new()));
+}
+
+#[test]
+fn test_trash() {
+	let mut file = Descriptor::new(b"", 1, 1);
+	file.section = Section { offset: 100, size: 1, ..Section::default() };
+	let mut directory = Directory::new();
+	directory.set_sorted(true);
+	directory.create_link(b"a/b/c", &file);
+	directory.create_link(b"a/d", &file);
+	directory.create_dir(b"a/empty");
+	directory.create_link(b"e", &file);
+	let before = directory.display().to_string();
+
+	assert!(directory.soft_remove(b"a"));
+	assert!(!directory.soft_remove(b"a"));
+	assert!(!directory.soft_remove(b".trash/a"));
+	assert!(directory.find(b"a").is_none());
+	assert_eq!(directory.find_file(b".trash/a/b/c").unwrap().content_size, 1);
+	assert!(directory.find(b".trash/a/empty").is_some());
+	assert!(directory.fsck(u32::MAX, &mut String::new()));
+
+	// Restoring does not overwrite existing paths
+	directory.create_link(b"a", &file);
+	assert!(!directory.restore_trashed(b"a"));
+	directory.remove(b"a");
+	assert!(directory.restore_trashed(b"a"));
+	directory.remove(b".trash");
+	assert_eq!(directory.display().to_string(), before);
+
+	assert!(directory.soft_remove(b"e"));
+	assert!(directory.soft_remove(b"a/b/c"));
+	assert_eq!(directory.empty_trash(), 5);
+	assert!(directory.find(b".trash").is_none());
+	assert!(directory.fsck(u32::MAX, &mut String::new()));
+}
+
+#[test]
+fn test_iter_files_mut() {
+	let mut directory = Directory::new();
+	directory.create_link(b"a/b/c", &Descriptor::file(b""));
+	directory.create_link(b"a/d", &Descriptor::file(b""));
+	directory.create_dir(b"a/empty");
+	let before = directory.display().to_string();
+
+	let section = Section { offset: 1, size: 2, ..Section::default() };
+	let mut count = 0;
+	for mut file in directory.iter_files_mut() {
+		assert!(!file.set_content_type(0));
+		file.set_content_size(100);
+		file.set_section(&section);
+		count += 1;
+	}
+	assert_eq!(count, 2);
+
+	// The directory descriptors are left alone
+	assert_eq!(directory.display().to_string(), before);
+	assert_eq!(directory.find_desc(b"a").unwrap().content_size, 4);
+	assert_eq!(directory.find_file(b"a/d").unwrap().section, section);
+	assert!(directory.fsck(u32::MAX, &mut String::new()));
+}
+
+#[test]
+fn test_max_depth() {
+	let file = Descriptor::file(b"");
+	let mut directory = Directory::new();
+	let deepest = vec![&b"d"[..]; dir::MAX_DEPTH as usize].join(&b'/');
+	assert!(directory.create_link(&deepest, &file).is_some());
+	assert_eq!(directory.max_depth(), dir::MAX_DEPTH);
+	assert_eq!((directory.file_count(), directory.dir_count()), (1, dir::MAX_DEPTH as usize - 1));
+
+	// Deeper paths would not be rendered
+	let too_deep = [&deepest[..], b"/e"].concat();
+	assert!(directory.create_link(&too_deep, &file).is_none());
+	assert!(directory.create_dir(&too_deep).is_none());
+	assert!(!directory.move_file(&deepest, &too_deep));
+	assert_eq!(directory.len(), dir::MAX_DEPTH as usize);
+
+	let mut editor = MemoryEditor::new();
+	assert!(editor.create_file(&too_deep, b"data", &Key::default()).is_none());
+	assert!(editor.is_empty());
+}
+
+#[test]
+fn test_normalize() {
+	let file = Descriptor::file(b"");
+	for &sorted in &[false, true] {
+		let mut directory = Directory::new();
+		directory.set_sorted(sorted);
+		let id = directory.create_link(b"a//b/", &file).unwrap();
+		assert_eq!(directory.create_link(b"/a/b", &file), Some(id));
+		assert_eq!(directory.find(b"a\\b"), Some(id));
+		assert_eq!(directory.len(), 2);
+		assert!(directory.create_dir(b"//").is_none());
+		assert!(directory.create_link(b"", &file).is_none());
+
+		assert!(directory.move_file(b"a//b", b"c//d/"));
+		assert!(directory.find_file(b"c/d").is_some());
+		assert!(directory.remove(b"/c/d/").is_some());
+		assert!(directory.fsck(u32::MAX, &mut String::new()));
+		assert!(directory.is_dir(b"c/"));
+		assert_eq!(directory.len(), 2);
+	}
+
+	// Batches normalize the same way
+	let mut directory = Directory::new();
+	let edits = [
+		Edit::CreateLink { path: b"a//b/".to_vec(), desc: file },
+		Edit::CreateLink { path: b"a/b".to_vec(), desc: file },
+		Edit::CreateDir { path: b"/".to_vec() },
+	];
+	assert_eq!(directory.apply(&edits), 2);
+	assert_eq!(directory.len(), 2);
+}
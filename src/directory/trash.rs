@@ -0,0 +1,125 @@
+use crate::*;
+
+// Path of the descriptor in the trash
+fn trash_path(path: &[u8]) -> Vec<u8> {
+	let mut trash = Directory::TRASH.to_vec();
+	trash.push(b'/');
+	trash.extend_from_slice(path);
+	trash
+}
+
+impl Directory {
+	/// Path of the hidden directory holding the [soft removed](Self::soft_remove) descriptors.
+	pub const TRASH: &'static [u8] = b".trash";
+
+	/// Moves the descriptor at the given path to the [trash](Self::TRASH).
+	///
+	/// Unlike [`remove`](Self::remove) the descriptor can be restored with [`restore_trashed`](Self::restore_trashed).
+	/// Directories are moved to the trash with all their children.
+	/// A descriptor previously trashed at the same path is replaced.
+	///
+	/// The trashed file data is not reclaimed until the trash is emptied, garbage collection empties the trash.
+	///
+	/// Returns `false` and does nothing if the path does not exist, is [sealed](Self::seal), is in the trash or the names are [stripped](NamePolicy::stripped).
+	///
+	/// # Examples
+	///
+	/// ```
+	/// let mut directory = paks::Directory::new();
+	/// directory.create_link(b"a/b", &paks::Descriptor::file(b""));
+	///
+	/// assert!(directory.soft_remove(b"a"));
+	/// assert!(directory.find(b"a/b").is_none());
+	/// assert!(directory.find(b".trash/a/b").is_some());
+	///
+	/// assert!(directory.restore_trashed(b"a"));
+	/// assert!(directory.find(b"a/b").is_some());
+	/// ```
+	pub fn soft_remove(&mut self, path: &[u8]) -> bool {
+		if !self.writable(path) || path.is_empty() || is_trashed(path) || self.names.stripped {
+			return false;
+		}
+		self.move_tree(path, &trash_path(path))
+	}
+
+	/// Restores the descriptor at the given path from the trash.
+	///
+	/// Returns `false` and does nothing if the path is not in the trash, is [sealed](Self::seal) or already exists.
+	pub fn restore_trashed(&mut self, path: &[u8]) -> bool {
+		if !self.writable(path) || path.is_empty() || is_trashed(path) || self.find(path).is_some() {
+			return false;
+		}
+		self.move_tree(&trash_path(path), path)
+	}
+
+	/// Permanently removes every descriptor in the trash.
+	///
+	/// Returns the number of descriptors removed.
+	pub fn empty_trash(&mut self) -> usize {
+		let len = self.subtree(Directory::TRASH).len();
+		self.remove_tree(Directory::TRASH);
+		len
+	}
+
+	// Lists the descriptors in the subtree at the path in order, including the root
+	fn subtree(&self, path: &[u8]) -> Vec<(Vec<u8>, Descriptor)> {
+		let mut entries = Vec::new();
+		if let Some(&desc) = self.lookup(path).first() {
+			entries.push((path.to_vec(), desc));
+			self.subtree_rec(path, &mut entries);
+		}
+		entries
+	}
+	fn subtree_rec(&self, path: &[u8], entries: &mut Vec<(Vec<u8>, Descriptor)>) {
+		let children = match self.get_children(path) {
+			Some(children) => children.to_vec(),
+			None => return,
+		};
+		let mut i = 0;
+		while i < children.len() {
+			let desc = children[i];
+			let mut child = path.to_vec();
+			child.push(b'/');
+			child.extend_from_slice(desc.name());
+			entries.push((child.clone(), desc));
+			if desc.is_dir() {
+				self.subtree_rec(&child, entries);
+			}
+			i = dir::next_sibling(&desc, i, children.len());
+		}
+	}
+
+	// Moves the subtree at the src path to the dest path
+	fn move_tree(&mut self, src_path: &[u8], dest_path: &[u8]) -> bool {
+		let entries = self.subtree(src_path);
+		if entries.is_empty() {
+			return false;
+		}
+		self.remove_tree(dest_path);
+		for (path, desc) in &entries {
+			let mut dest = dest_path.to_vec();
+			dest.extend_from_slice(&path[src_path.len()..]);
+			let i = self.create_index(&dest);
+			if desc.is_file() {
+				self.descs[i] = Descriptor { name: self.descs[i].name, ..*desc };
+			}
+		}
+		self.remove_tree(src_path);
+		true
+	}
+
+	// Removes the subtree at the path
+	fn remove_tree(&mut self, path: &[u8]) {
+		for (path, _) in self.subtree(path).iter().rev() {
+			self.remove_index(path);
+		}
+	}
+}
+
+// Returns if the path is in the trash
+fn is_trashed(path: &[u8]) -> bool {
+	match path.strip_prefix(Directory::TRASH) {
+		Some(tail) => tail.is_empty() || tail[0] == b'/',
+		None => false,
+	}
+}
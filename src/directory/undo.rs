@@ -0,0 +1,259 @@
+use std::collections::VecDeque;
+use crate::*;
+use super::DescriptorId;
+
+/// Recorded directory edit.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Edit {
+	/// See [`Directory::create_link`].
+	CreateLink {
+		path: Vec<u8>,
+		desc: Descriptor,
+	},
+	/// See [`Directory::create_dir`].
+	CreateDir {
+		path: Vec<u8>,
+	},
+	/// See [`Directory::remove`].
+	Remove {
+		path: Vec<u8>,
+	},
+	/// See [`Directory::move_file`].
+	Move {
+		src_path: Vec<u8>,
+		dest_path: Vec<u8>,
+	},
+}
+
+impl Edit {
+	/// Applies the edit to the directory.
+	///
+	/// Returns `false` if the edit did not change the directory.
+	pub fn apply(&self, directory: &mut Directory) -> bool {
+		match self {
+			Edit::CreateLink { path, desc } => directory.create_link(path, desc).is_some(),
+			Edit::CreateDir { path } => directory.create_dir(path).is_some(),
+			Edit::Remove { path } => directory.remove(path).is_some(),
+			Edit::Move { src_path, dest_path } => directory.move_file(src_path, dest_path),
+		}
+	}
+}
+
+// Descriptors replaced by an edit, the descriptors around the range are left unchanged
+#[derive(Clone, Debug)]
+struct Diff {
+	start: usize,
+	// Number of descriptors in the range in the directory
+	len: usize,
+	// Descriptors and their handles swapped into the range
+	descs: Vec<Descriptor>,
+	ids: Vec<DescriptorId>,
+}
+
+impl Diff {
+	// Records the descriptors which differ from the directory before the edit
+	fn new(old: &[Descriptor], old_ids: &[DescriptorId], directory: &Directory) -> Diff {
+		let (new, new_ids) = (&directory.descs, &directory.ids);
+		let same = |i: usize, j: usize| old[i] == new[j] && old_ids[i] == new_ids[j];
+		let prefix = (0..usize::min(old.len(), new.len())).take_while(|&i| same(i, i)).count();
+		let suffix = (1..=usize::min(old.len(), new.len()) - prefix).take_while(|&k| same(old.len() - k, new.len() - k)).count();
+		Diff {
+			start: prefix,
+			len: new.len() - prefix - suffix,
+			descs: old[prefix..old.len() - suffix].to_vec(),
+			ids: old_ids[prefix..old.len() - suffix].to_vec(),
+		}
+	}
+
+	// Swaps the recorded descriptors with the range in the directory, the diff becomes its inverse
+	fn swap(&mut self, directory: &mut Directory) {
+		let range = self.start..self.start + self.len;
+		self.len = self.descs.len();
+		self.descs = directory.descs.splice(range.clone(), self.descs.drain(..)).collect();
+		self.ids = directory.ids.splice(range, self.ids.drain(..)).collect();
+		for id in &self.ids {
+			directory.slots[id.0 as usize] = !0;
+		}
+		directory.reindex(self.start);
+	}
+}
+
+/// Undo stack for directory edits.
+///
+/// Edits are applied to the directory through the undo stack which records them for undo and redo.
+/// Only the descriptors changed by an edit are recorded, the directory must not be edited around the undo stack.
+/// The oldest edits are forgotten past the [limit](Self::set_limit).
+///
+/// Note that only the directory is recorded, the file data referenced by the directory is left alone.
+///
+/// # Examples
+///
+/// ```
+/// let mut directory = paks::Directory::new();
+/// let mut undo = paks::UndoStack::new();
+///
+/// undo.create_dir(&mut directory, b"a/b");
+/// assert!(directory.find_desc(b"a/b").is_some());
+///
+/// assert!(undo.undo(&mut directory));
+/// assert!(directory.is_empty());
+///
+/// assert!(undo.redo(&mut directory));
+/// assert!(directory.find_desc(b"a/b").is_some());
+/// ```
+#[derive(Clone, Debug)]
+pub struct UndoStack {
+	undo: VecDeque<(Edit, Diff)>,
+	redo: Vec<(Edit, Diff)>,
+	limit: usize,
+	// Copy of the directory before an edit, kept around to reuse its allocation
+	scratch_descs: Vec<Descriptor>,
+	scratch_ids: Vec<DescriptorId>,
+}
+
+impl Default for UndoStack {
+	#[inline]
+	fn default() -> UndoStack {
+		UndoStack {
+			undo: VecDeque::new(),
+			redo: Vec::new(),
+			limit: UndoStack::DEFAULT_LIMIT,
+			scratch_descs: Vec::new(),
+			scratch_ids: Vec::new(),
+		}
+	}
+}
+
+impl UndoStack {
+	/// Number of edits which can be undone by default.
+	pub const DEFAULT_LIMIT: usize = 1000;
+
+	/// Creates a new, empty `UndoStack` instance.
+	#[inline]
+	pub fn new() -> UndoStack {
+		UndoStack::default()
+	}
+
+	/// Sets the number of edits which can be undone.
+	///
+	/// The oldest edits past the limit are forgotten.
+	pub fn set_limit(&mut self, limit: usize) {
+		self.limit = limit;
+		while self.undo.len() > limit {
+			self.undo.pop_front();
+		}
+	}
+
+	fn push_undo(&mut self, edit: Edit, diff: Diff) {
+		if self.limit == 0 {
+			return;
+		}
+		if self.undo.len() == self.limit {
+			self.undo.pop_front();
+		}
+		self.undo.push_back((edit, diff));
+	}
+
+	/// Applies the edit and records it.
+	///
+	/// Clears the redo history. Edits which do not change the directory are not recorded.
+	///
+	/// Returns `false` if the edit did not change the directory.
+	pub fn apply(&mut self, directory: &mut Directory, edit: Edit) -> bool {
+		self.scratch_descs.extend_from_slice(&directory.descs);
+		self.scratch_ids.extend_from_slice(&directory.ids);
+		let changed = edit.apply(directory);
+		if changed {
+			let diff = Diff::new(&self.scratch_descs, &self.scratch_ids, directory);
+			self.push_undo(edit, diff);
+			self.redo.clear();
+		}
+		self.scratch_descs.clear();
+		self.scratch_ids.clear();
+		changed
+	}
+
+	/// Creates a symbolic link from the path to the given file descriptor.
+	///
+	/// See [`Directory::create_link`] for more information.
+	#[inline]
+	pub fn create_link(&mut self, directory: &mut Directory, path: &[u8], file_desc: &Descriptor) -> bool {
+		self.apply(directory, Edit::CreateLink { path: path.to_vec(), desc: *file_desc })
+	}
+
+	/// Creates a directory descriptor at the given path.
+	///
+	/// See [`Directory::create_dir`] for more information.
+	#[inline]
+	pub fn create_dir(&mut self, directory: &mut Directory, path: &[u8]) -> bool {
+		self.apply(directory, Edit::CreateDir { path: path.to_vec() })
+	}
+
+	/// Removes a descriptor at the given path.
+	///
+	/// See [`Directory::remove`] for more information.
+	#[inline]
+	pub fn remove(&mut self, directory: &mut Directory, path: &[u8]) -> bool {
+		self.apply(directory, Edit::Remove { path: path.to_vec() })
+	}
+
+	/// Moves a file descriptor from the src path to the given dest path.
+	///
+	/// See [`Directory::move_file`] for more information.
+	#[inline]
+	pub fn move_file(&mut self, directory: &mut Directory, src_path: &[u8], dest_path: &[u8]) -> bool {
+		self.apply(directory, Edit::Move { src_path: src_path.to_vec(), dest_path: dest_path.to_vec() })
+	}
+
+	/// Undoes the last edit.
+	///
+	/// Returns `false` if there is nothing to undo.
+	pub fn undo(&mut self, directory: &mut Directory) -> bool {
+		match self.undo.pop_back() {
+			Some((edit, mut diff)) => {
+				diff.swap(directory);
+				self.redo.push((edit, diff));
+				true
+			},
+			None => false,
+		}
+	}
+
+	/// Redoes the last undone edit.
+	///
+	/// Returns `false` if there is nothing to redo.
+	pub fn redo(&mut self, directory: &mut Directory) -> bool {
+		match self.redo.pop() {
+			Some((edit, mut diff)) => {
+				diff.swap(directory);
+				self.push_undo(edit, diff);
+				true
+			},
+			None => false,
+		}
+	}
+
+	/// Returns the edits which can be undone, the most recent edit last.
+	pub fn edits(&self) -> impl '_ + Iterator<Item = &Edit> {
+		self.undo.iter().map(|(edit, _)| edit)
+	}
+
+	/// Returns if there are any edits to undo.
+	#[inline]
+	pub fn can_undo(&self) -> bool {
+		!self.undo.is_empty()
+	}
+
+	/// Returns if there are any edits to redo.
+	#[inline]
+	pub fn can_redo(&self) -> bool {
+		!self.redo.is_empty()
+	}
+
+	/// Clears the undo and redo history.
+	#[inline]
+	pub fn clear(&mut self) {
+		self.undo.clear();
+		self.redo.clear();
+	}
+}
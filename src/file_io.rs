@@ -4,16 +4,29 @@
 Implements the PAK file format using [`std::fs::File`].
 */
 
-use std::{fs, io, io::prelude::*};
+use std::{fs, io, io::prelude::*, mem, path::Path};
 use crate::*;
 
+// Number of blocks read from a stream at once, the blocks grow by this much at a time
+const READ_CHUNK_BLOCKS: usize = 0x10000;
+
 /// Reads a PAK file from a stream.
 ///
 /// This method reads and decrypts the PAK file header.
-/// If the header is invalid or its MAC check fails, [`io::ErrorKind::InvalidData`] is returned.
+/// If the header is invalid, its MAC check fails or the directory overlaps the header, [`io::ErrorKind::InvalidData`] is returned.
 ///
 /// Then it reads all the blocks in the PAK file as specified by the directory.
-pub fn read<F: Read>(mut file: F, key: &Key) -> io::Result<Vec<Block>> {
+/// If the stream ends before the end of the directory, [`io::ErrorKind::UnexpectedEof`] is returned.
+pub fn read<F: Read>(file: F, key: &Key) -> io::Result<Vec<Block>> {
+	read_with_limits(file, key, &Limits::default())
+}
+
+/// Reads a PAK file from a stream with limits on its size.
+///
+/// The limits are checked after the header is decrypted, before reading the rest of the PAK file.
+/// Exceeding the limits fails with [`io::ErrorKind::OutOfMemory`].
+/// See [`read`] for more information.
+pub fn read_with_limits<F: Read>(mut file: F, key: &Key, limits: &Limits) -> io::Result<Vec<Block>> {
 	// Read the header
 	let mut header = Header::zeroed();
 	file.read_exact(header.as_bytes_mut())?;
@@ -23,69 +36,435 @@ pub fn read<F: Read>(mut file: F, key: &Key) -> io::Result<Vec<Block>> {
 
 	// Decrypt and validate the header
 	if !crypt::decrypt_header(&mut header, key) {
-		return Err(io::Error::from(io::ErrorKind::InvalidData));
+		return Err(crypt::header_error(&header2, key));
 	}
+	limits.check(&header.info)?;
 
 	// Use information from the header to calculate the total size of the PAK file
 	// This code assumes the directory is the very last thing in the PAK file
-	let blocks_len = usize::max(Header::BLOCKS_LEN, header.info.directory.offset as usize + header.info.directory.size as usize * Descriptor::BLOCKS_LEN);
-	let mut blocks = vec![Block::default(); blocks_len];
+	let dir_range = match parse::directory_range(&header.info) {
+		Some(dir_range) => dir_range,
+		None => return Err(io::Error::from(io::ErrorKind::InvalidData)),
+	};
+	let blocks_len = usize::max(Header::BLOCKS_LEN, dir_range.end);
 
 	// Copy the encrypted header into the output since it's already read from the file
-	blocks[..Header::BLOCKS_LEN].copy_from_slice(header2.as_ref());
+	let mut blocks = header2.as_ref().to_vec();
 
 	// Then read the rest of the PAK file
-	file.read_exact(blocks[Header::BLOCKS_LEN..].as_bytes_mut())?;
+	// Grow the blocks as the data arrives so the allocation is bounded by the actual size of the stream
+	// A header claiming more blocks than the stream holds fails with UnexpectedEof
+	while blocks.len() < blocks_len {
+		let start = blocks.len();
+		let chunk = usize::min(blocks_len - start, READ_CHUNK_BLOCKS);
+		blocks.resize(start + chunk, Block::default());
+		file.read_exact(blocks[start..].as_bytes_mut())?;
+	}
 
 	Ok(blocks)
 }
 
+/// IO buffering options.
+///
+/// Tunes the size of the IO requests, eg. for network file systems.
+/// Used by [`FileReader::open_with`], [`FileEditor::open_with`] and [`FileEditor::create_new_with`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct IoOptions {
+	/// Maximum size in bytes of a single read request.
+	///
+	/// Sections are read with a single request if zero, the default.
+	pub read_buf: usize,
+	/// Size in bytes of the write buffer of the [`FileEditor`].
+	///
+	/// See [`FileEditor::set_write_buffer`] for more information.
+	pub write_buf: usize,
+}
+
+impl Default for IoOptions {
+	#[inline]
+	fn default() -> IoOptions {
+		IoOptions {
+			read_buf: 0,
+			write_buf: WRITE_BUFFER_SIZE,
+		}
+	}
+}
+
+/// Reader options.
+///
+/// Hides files from the directory when opening the PAK file, without rewriting it.
+/// Shipping builds can mount a development PAK file while hiding the editor-only files.
+/// Used by [`FileReader::open_with_options`].
+///
+/// The patterns are globs matched against the full paths, the wildcard `*` matches any sequence of bytes including `/` and `?` matches any single byte.
+/// A pattern matching a directory applies to every file under it.
+///
+/// # Examples
+///
+/// ```
+/// let mut options = paks::ReaderOptions::default();
+/// options.hide(b"editor").hide(b"*.psd").only(b"textures").only(b"sounds");
+///
+/// assert!(options.is_visible(b"textures/wall.png"));
+/// assert!(!options.is_visible(b"textures/wall.psd"));
+/// assert!(!options.is_visible(b"editor/gizmo.png"));
+/// assert!(!options.is_visible(b"scripts/main.lua"));
+/// ```
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct ReaderOptions {
+	/// IO buffering options.
+	pub io: IoOptions,
+	/// Maximum size in bytes of a section read into memory.
+	///
+	/// Reading a larger section fails with [`io::ErrorKind::OutOfMemory`] before allocating any memory for it.
+	/// Protects servers from crafted descriptors claiming sections of several GiB. Unlimited if `None`, the default.
+	pub max_section_bytes: Option<u64>,
+	/// Limits on the size of the directory checked when opening the PAK file.
+	pub limits: Limits,
+	hide: Vec<Vec<u8>>,
+	only: Vec<Vec<u8>>,
+}
+
+impl ReaderOptions {
+	/// Hides the files matching the pattern.
+	///
+	/// Hidden files are hidden even if they match an [`only`](Self::only) pattern.
+	pub fn hide(&mut self, pattern: &[u8]) -> &mut ReaderOptions {
+		self.hide.push(pattern.to_vec());
+		self
+	}
+
+	/// Hides the files not matching any of the `only` patterns.
+	///
+	/// Every file is visible unless hidden if there are no `only` patterns.
+	pub fn only(&mut self, pattern: &[u8]) -> &mut ReaderOptions {
+		self.only.push(pattern.to_vec());
+		self
+	}
+
+	/// Returns if the file at the path is visible.
+	pub fn is_visible(&self, path: &[u8]) -> bool {
+		let matches = |patterns: &[Vec<u8>]| {
+			// Match the path and all its parent directories
+			let mut prefixes = path.iter().enumerate().filter(|&(_, &chr)| chr == b'/').map(|(i, _)| &path[..i]).chain(Some(path));
+			prefixes.any(|prefix| patterns.iter().any(|pattern| directory::glob(pattern, prefix)))
+		};
+		(self.only.is_empty() || matches(&self.only)) && !matches(&self.hide)
+	}
+}
+
+// Reads exactly the buffer from the byte offset in the file
+// Requests are split in chunks of read_buf bytes, zero reads with a single request
+fn read_at(file: &fs::File, offset: u64, buf: &mut [u8], read_buf: usize) -> io::Result<()> {
+	if read_buf == 0 {
+		return read_exact_at(file, offset, buf);
+	}
+	let mut offset = offset;
+	for chunk in buf.chunks_mut(read_buf) {
+		read_exact_at(file, offset, chunk)?;
+		offset += chunk.len() as u64;
+	}
+	Ok(())
+}
+
+// Positioned read which does not move the cursor of the file
+// Threads sharing a reader read concurrently without racing on the cursor
+#[cfg(unix)]
+pub(crate) fn read_exact_at(file: &fs::File, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+	std::os::unix::fs::FileExt::read_exact_at(file, buf, offset)
+}
+#[cfg(windows)]
+pub(crate) fn read_exact_at(file: &fs::File, mut offset: u64, mut buf: &mut [u8]) -> io::Result<()> {
+	while !buf.is_empty() {
+		match std::os::windows::fs::FileExt::seek_read(file, buf, offset) {
+			Ok(0) => Err(io::ErrorKind::UnexpectedEof)?,
+			Ok(n) => {
+				buf = &mut buf[n..];
+				offset += n as u64;
+			},
+			Err(err) if err.kind() == io::ErrorKind::Interrupted => (),
+			Err(err) => return Err(err),
+		}
+	}
+	Ok(())
+}
+// Without positioned reads the seek and read are serialised so threads do not race on the cursor
+#[cfg(not(any(unix, windows)))]
+pub(crate) fn read_exact_at(mut file: &fs::File, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+	static CURSOR: std::sync::Mutex<()> = std::sync::Mutex::new(());
+	let _guard = CURSOR.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+	file.seek(io::SeekFrom::Start(offset))?;
+	file.read_exact(buf)
+}
+
+// The base is the byte offset of the PAK file embedded in the file, zero if it is not embedded
 #[inline(always)]
-fn read_header(file: &mut fs::File, key: &Key) -> io::Result<(InfoHeader, Directory)> {
+fn read_header(file: &mut fs::File, base: u64, read_buf: usize, limits: &Limits, key: &Key) -> io::Result<(Header, Directory)> {
+	let span = span!(DEBUG, "open", base);
+
 	// Read the header
 	let mut header = Header::default();
+	file.seek(io::SeekFrom::Start(base))?;
 	file.read_exact(header.as_bytes_mut())?;
 
 	// Decrypt the header and validate
 	if !crypt::decrypt_header(&mut header, key) {
-		Err(io::ErrorKind::InvalidData)?;
+		return Err(crypt::header_error(&header, key));
+	}
+	limits.check(&header.info)?;
+
+	// Validate the directory location against the file size before allocating
+	let dir_range = match parse::directory_range(&header.info) {
+		Some(dir_range) => dir_range,
+		None => Err(io::ErrorKind::InvalidData)?,
+	};
+	if base + dir_range.end as u64 * BLOCK_SIZE as u64 > file.metadata()?.len() {
+		Err(io::ErrorKind::UnexpectedEof)?;
 	}
 
 	// Read the directory
-	file.seek(io::SeekFrom::Start(header.info.directory.offset as u64 * BLOCK_SIZE as u64))?;
-	let mut directory = Directory::from(vec![Descriptor::default(); header.info.directory.size as usize]);
-	file.read_exact(directory.as_mut().as_bytes_mut())?;
+	let mut dir_blocks = vec![Block::default(); dir_range.len()];
+	read_at(file, base + dir_range.start as u64 * BLOCK_SIZE as u64, dir_blocks.as_bytes_mut(), read_buf)?;
 
 	// Decrypt the directory
-	if !crypt::decrypt_section(directory.as_blocks_mut(), &header.info.directory, key) {
+	if !crypt::decrypt_section(&mut dir_blocks, &header.info.directory, key) {
 		Err(io::ErrorKind::InvalidData)?;
 	}
 
-	Ok((header.info, directory))
+	let mut directory = Directory::from(parse::descriptors(&dir_blocks));
+	directory.block_size = header.info.block_size();
+	directory.set_name_flags(header.info.flags());
+	let block_size = header.info.block_size();
+	directory.index_tags(|section| read_section(file, base, read_buf, block_size, section, key).ok());
+	span.finish(((Header::BLOCKS_LEN + dir_blocks.len()) * BLOCK_SIZE) as u64);
+	Ok((header, directory))
 }
 
-fn read_section(mut file: &fs::File, section: &Section, key: &Key) -> io::Result<Vec<Block>> {
+fn read_section(file: &fs::File, base: u64, read_buf: usize, block_size: u32, section: &Section, key: &Key) -> io::Result<Vec<Block>> {
+	let mut blocks = Vec::new();
+	read_section_into(file, base, read_buf, block_size, section, key, &mut blocks)?;
+	Ok(blocks)
+}
+
+// Same as `read_section` reusing the allocation of the blocks
+fn read_section_into(file: &fs::File, base: u64, read_buf: usize, block_size: u32, section: &Section, key: &Key, blocks: &mut Vec<Block>) -> io::Result<()> {
+	let span = span!(DEBUG, "read_section", offset = section.offset, size = section.size);
+
+	// Check the section lies within the file before allocating the memory buffer
+	let file_offset = base + section.offset as u64 * block_size as u64;
+	if file_offset + section.size as u64 * block_size as u64 > file.metadata()?.len() {
+		Err(io::ErrorKind::UnexpectedEof)?;
+	}
+
 	// Read the data to memory buffer
-	let file_offset = section.offset as u64 * BLOCK_SIZE as u64;
-	file.seek(io::SeekFrom::Start(file_offset))?;
-	let mut blocks = vec![Block::default(); section.size as usize];
-	file.read_exact(blocks.as_bytes_mut())?;
+	blocks.clear();
+	blocks.resize(section.size as usize * block_scale(block_size), Block::default());
+	read_at(file, file_offset, blocks.as_bytes_mut(), read_buf)?;
 
 	// Decrypt the data inplace
-	if !crypt::decrypt_section(&mut blocks, section, key) {
+	if !crypt::decrypt_section(blocks, section, key) {
 		Err(io::ErrorKind::InvalidData)?;
 	}
 
-	Ok(blocks)
+	span.finish((blocks.len() * BLOCK_SIZE) as u64);
+	Ok(())
+}
+
+// Size in blocks of the chunks streamed by `FileReader::read_into` and `copy_section_raw`
+const STREAM_BLOCKS: usize = 64 * 1024 / BLOCK_SIZE;
+
+/// Default size in bytes of the [`FileEditor`] write buffer.
+pub const WRITE_BUFFER_SIZE: usize = 64 * 1024;
+
+// Coalesces the writes of consecutive sections into fewer, larger writes
+// Bump allocating new sections makes most writes consecutive
+// Owns the storage of the editor, every access goes through the buffer to flush it first
+pub(crate) struct WriteBuf<S> {
+	pub(crate) storage: S,
+	// Block offset in the storage of the buffered data
+	offset: u64,
+	data: Vec<Block>,
+	// Buffer at most this many bytes, zero disables buffering
+	capacity: usize,
+	// Reused to encrypt the data of small sections, see `take_scratch`
+	scratch: Vec<Block>,
+}
+
+// Scratch allocations up to this many blocks are kept for reuse
+const SCRATCH_BLOCKS: usize = 64 * 1024 / BLOCK_SIZE;
+
+impl<S: Storage> WriteBuf<S> {
+	pub(crate) fn new(storage: S, capacity: usize) -> WriteBuf<S> {
+		WriteBuf { storage, offset: 0, data: Vec::new(), capacity, scratch: Vec::new() }
+	}
+
+	// Returns zeroed blocks to encrypt the data of a section, reusing the previous allocation
+	pub(crate) fn take_scratch(&mut self, len: usize) -> Vec<Block> {
+		let mut blocks = mem::take(&mut self.scratch);
+		blocks.clear();
+		blocks.resize(len, Block::default());
+		blocks
+	}
+
+	// Keeps the blocks for reuse unless they are large
+	pub(crate) fn put_scratch(&mut self, blocks: Vec<Block>) {
+		if blocks.capacity() <= SCRATCH_BLOCKS {
+			self.scratch = blocks;
+		}
+	}
+
+	pub(crate) fn capacity(&self) -> usize {
+		self.capacity
+	}
+
+	// Changing the capacity flushes the buffered data
+	pub(crate) fn set_capacity(&mut self, capacity: usize) -> io::Result<()> {
+		self.flush()?;
+		self.capacity = capacity;
+		Ok(())
+	}
+
+	fn end(&self) -> u64 {
+		self.offset + self.data.len() as u64
+	}
+
+	// Writes the blocks at the block offset in the storage
+	// The blocks are buffered if they continue the buffered data and fit in the buffer
+	pub(crate) fn write(&mut self, offset: u64, blocks: &[Block]) -> io::Result<()> {
+		if !self.data.is_empty() && offset != self.end() {
+			self.flush()?;
+		}
+		if (self.data.len() + blocks.len()) * BLOCK_SIZE > self.capacity {
+			return self.flush_with(offset, blocks);
+		}
+		if self.data.is_empty() {
+			self.offset = offset;
+		}
+		self.data.extend_from_slice(blocks);
+		Ok(())
+	}
+
+	// Writes the buffered data to the storage
+	pub(crate) fn flush(&mut self) -> io::Result<()> {
+		let end = self.end();
+		self.flush_with(end, &[])
+	}
+
+	// Writes the buffered data followed by the tail at the block offset in the storage
+	// The buffered data and the tail are written with a single write if they are consecutive
+	pub(crate) fn flush_with(&mut self, offset: u64, tail: &[Block]) -> io::Result<()> {
+		if !self.data.is_empty() && offset != self.end() {
+			self.storage.write_blocks(self.offset, &self.data)?;
+			self.data.clear();
+		}
+		if self.data.is_empty() {
+			self.offset = offset;
+			if tail.is_empty() {
+				return Ok(());
+			}
+			return self.storage.write_blocks(offset, tail);
+		}
+
+		self.data.extend_from_slice(tail);
+		let result = self.storage.write_blocks(self.offset, &self.data);
+		self.data.clear();
+		result
+	}
+
+	// Reads the blocks at the block offset in the storage after writing the buffered data
+	// Requests are split in chunks of read_buf bytes, zero reads with a single request
+	pub(crate) fn read(&mut self, offset: u64, blocks: &mut [Block], read_buf: usize) -> io::Result<()> {
+		self.flush()?;
+		if read_buf < BLOCK_SIZE {
+			return self.storage.read_blocks(offset, blocks);
+		}
+		let mut offset = offset;
+		for chunk in blocks.chunks_mut(read_buf / BLOCK_SIZE) {
+			self.storage.read_blocks(offset, chunk)?;
+			offset += chunk.len() as u64;
+		}
+		Ok(())
+	}
+
+	// Reads and decrypts the section, the section is checked against the size of the storage before allocating
+	pub(crate) fn read_section(&mut self, read_buf: usize, block_size: u32, section: &Section, key: &Key) -> io::Result<Vec<Block>> {
+		self.flush()?;
+		let scale = block_scale(block_size) as u64;
+		if (section.offset as u64 + section.size as u64) * scale > self.storage.size()? {
+			Err(io::ErrorKind::UnexpectedEof)?;
+		}
+		let mut blocks = vec![Block::default(); section.size as usize * scale as usize];
+		self.read(section.offset as u64 * scale, &mut blocks, read_buf)?;
+		if !crypt::decrypt_section(&mut blocks, section, key) {
+			Err(io::ErrorKind::InvalidData)?;
+		}
+		Ok(blocks)
+	}
+}
+
+// Magic at the very end of a file with an embedded PAK file
+const EMBED_MAGIC: [u8; 8] = *b"PAK1EMBD";
+// The locator is the little endian byte offset of the PAK file followed by the magic
+const LOCATOR_LEN: usize = 16;
+
+/// Writes the locator of a PAK file embedded at the given byte offset.
+///
+/// A PAK file appended to another file (eg. an executable) is found again by [`find_embedded`] if the locator is written right after it.
+/// The locator is not encrypted, it contains a magic and the byte offset of the PAK file.
+pub fn write_locator<W: Write>(mut writer: W, offset: u64) -> io::Result<()> {
+	let mut locator = [0u8; LOCATOR_LEN];
+	locator[..8].copy_from_slice(&offset.to_le_bytes());
+	locator[8..].copy_from_slice(&EMBED_MAGIC);
+	writer.write_all(&locator)
+}
+
+/// Finds the byte offset of the PAK file embedded in the file at the given path.
+///
+/// Returns `None` if the file does not end with a locator written by [`write_locator`].
+/// The embedded PAK file can be opened with [`FileReader::open_at_offset`].
+pub fn find_embedded<P: ?Sized + AsRef<Path>>(path: &P) -> io::Result<Option<u64>> {
+	let mut file = fs::File::open(path)?;
+	let len = file.metadata()?.len();
+	if len < LOCATOR_LEN as u64 {
+		return Ok(None);
+	}
+
+	let mut locator = [0u8; LOCATOR_LEN];
+	file.seek(io::SeekFrom::Start(len - LOCATOR_LEN as u64))?;
+	file.read_exact(&mut locator)?;
+	if locator[8..] != EMBED_MAGIC {
+		return Ok(None);
+	}
+
+	// The PAK file must fit between the offset and the locator
+	let mut offset = [0u8; 8];
+	offset.copy_from_slice(&locator[..8]);
+	let offset = u64::from_le_bytes(offset);
+	if offset > len - LOCATOR_LEN as u64 {
+		return Ok(None);
+	}
+	Ok(Some(offset))
 }
 
 mod reader;
 mod editor;
 mod edit_file;
+mod arc_reader;
+mod key_slots;
+mod gc_plan;
+mod hybrid;
+#[cfg(feature = "memmap2")]
+mod mmap_editor;
 
 pub use self::reader::FileReader;
 pub use self::editor::FileEditor;
 pub use self::edit_file::FileEditFile;
+pub use self::arc_reader::ArcReader;
+pub use self::gc_plan::{ExtentMove, GcPlan};
+pub use self::hybrid::{EditStrategy, HybridOptions, HybridEditor};
+#[cfg(feature = "memmap2")]
+pub use self::mmap_editor::MmapEditor;
 
 #[cfg(test)]
-mod tests;
+#[macro_use]
+pub(crate) mod tests;
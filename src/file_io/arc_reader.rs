@@ -0,0 +1,75 @@
+use std::{fmt, io, ops, path::Path, sync::Arc};
+use crate::*;
+
+/// File reader shared between threads.
+///
+/// Cloning the reader shares the open file and the decrypted directory.
+/// The [`FileReader`] reads with positioned reads, the clones read concurrently without racing on the file cursor.
+///
+/// # Examples
+///
+/// ```no_run
+/// let ref key = paks::Key::default();
+/// let reader = paks::ArcReader::open("game.pak", key).unwrap();
+///
+/// let threads: Vec<_> = ["a", "b"].iter().map(|&path| {
+///     let reader = reader.clone();
+///     let key = *key;
+///     std::thread::spawn(move || {
+///         let desc = reader.find_file(path.as_bytes()).unwrap();
+///         reader.read_data(desc, &key).unwrap()
+///     })
+/// }).collect();
+///
+/// for thread in threads {
+///     println!("{} bytes", thread.join().unwrap().len());
+/// }
+/// ```
+#[derive(Clone)]
+pub struct ArcReader {
+	reader: Arc<FileReader>,
+}
+
+impl ArcReader {
+	/// Opens a PAK file for reading.
+	///
+	/// See [`FileReader::open`] for more information.
+	#[inline]
+	pub fn open<P: ?Sized + AsRef<Path>>(path: &P, key: &Key) -> io::Result<ArcReader> {
+		FileReader::open(path, key).map(ArcReader::from)
+	}
+
+	/// Returns the shared reader.
+	#[inline]
+	pub fn as_arc(&self) -> &Arc<FileReader> {
+		&self.reader
+	}
+}
+
+impl From<FileReader> for ArcReader {
+	#[inline]
+	fn from(reader: FileReader) -> ArcReader {
+		ArcReader { reader: Arc::new(reader) }
+	}
+}
+
+impl From<Arc<FileReader>> for ArcReader {
+	#[inline]
+	fn from(reader: Arc<FileReader>) -> ArcReader {
+		ArcReader { reader }
+	}
+}
+
+impl ops::Deref for ArcReader {
+	type Target = FileReader;
+	#[inline]
+	fn deref(&self) -> &FileReader {
+		&self.reader
+	}
+}
+
+impl fmt::Debug for ArcReader {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		fmt::Debug::fmt(&*self.reader, f)
+	}
+}
@@ -1,14 +1,17 @@
-use std::{fs, io, io::prelude::*};
+use std::{convert::TryFrom, fs, io};
 use crate::*;
 
 /// File file editor.
-pub struct FileEditFile<'a> {
-	pub(super) file: &'a fs::File,
+pub struct FileEditFile<'a, S = fs::File> {
 	pub(super) desc: &'a mut Descriptor,
 	pub(super) high_mark: &'a mut u32,
+	pub(super) wbuf: &'a mut WriteBuf<S>,
+	pub(super) block_size: u32,
+	pub(super) align: u32,
+	pub(super) max_mark: u32,
 }
 
-impl<'a> FileEditFile<'a> {
+impl<'a, S: Storage> FileEditFile<'a, S> {
 	/// Gets the file descriptor as-is.
 	#[inline]
 	pub fn descriptor(&self) -> &Descriptor {
@@ -18,7 +21,7 @@ impl<'a> FileEditFile<'a> {
 	/// Sets the content type and size for this file descriptor.
 	///
 	/// Note that a content type of `0` gets overwritten by a type of `1`.
-	pub fn set_content(&mut self, content_type: u32, content_size: u32) -> &mut FileEditFile<'a> {
+	pub fn set_content(&mut self, content_type: u32, content_size: u32) -> &mut FileEditFile<'a, S> {
 		self.desc.content_type = u32::max(1, content_type); // zero is reserved for directory descriptors...
 		self.desc.content_size = content_size;
 		return self;
@@ -27,8 +30,9 @@ impl<'a> FileEditFile<'a> {
 	/// Assigns an existing section object to this file descriptor.
 	///
 	/// This can be used to make different descriptors point to the same data.
-	pub fn set_section(&mut self, section: &Section) -> &mut FileEditFile<'a> {
+	pub fn set_section(&mut self, section: &Section) -> &mut FileEditFile<'a, S> {
 		self.desc.section = *section;
+		self.desc.unpack();
 		return self;
 	}
 
@@ -37,26 +41,66 @@ impl<'a> FileEditFile<'a> {
 	/// The size allocated is defined by a previous call to [`set_content`](Self::set_content)'s `content_size` argument.
 	///
 	/// The space allocated is logically uninitialized and must be initialized with [`write_data`](Self::write_data) or [`zero_data`](Self::zero_data).
-	pub fn allocate_data(&mut self) -> &mut FileEditFile<'a> {
-		// Simple bump allocate from the file
-		self.desc.section.offset = *self.high_mark;
-		self.desc.section.size = bytes2blocks(self.desc.content_size);
+	///
+	/// Returns [`io::ErrorKind::OutOfMemory`] and allocates nothing if the allocation overflows the high mark or exceeds the [maximum size](FileEditor::set_max_size).
+	pub fn allocate_data(&mut self) -> io::Result<&mut FileEditFile<'a, S>> {
+		let size = bytes2blocks(self.desc.content_size, self.block_size);
+		self.desc.section.offset = self.allocate(size)?;
+		self.desc.section.size = size;
+		// New file data is encrypted unless made public again
+		self.desc.section.nonce = Block::default();
+		self.desc.unpack();
+		Ok(self)
+	}
 
-		// Bump the allocation
-		// FIXME! Overflow??
-		*self.high_mark += self.desc.section.size;
+	/// Stores the file data as plaintext authenticated by its MAC.
+	///
+	/// Public files such as a readme or version number can be read directly from the PAK file by tools without the key, see [`Section::is_public`].
+	/// The directory is still encrypted, locating the file without the key requires knowing its section.
+	///
+	/// Takes effect when the data is written, call after [`allocate_data`](Self::allocate_data) which makes the file data encrypted again.
+	/// Reencrypting or shredding a public file keeps it public.
+	pub fn set_public(&mut self, public: bool) -> &mut FileEditFile<'a, S> {
+		self.desc.section.nonce[0] = if public { Section::PUBLIC_NONCE } else { 0 };
+		self
+	}
 
-		return self;
+	/// Pins the file data to its offset in the PAK file.
+	///
+	/// Garbage collection never relocates the sections of a pinned file, other file data is moved around them.
+	///
+	/// See [`MemoryEditFile::pin`] for more information.
+	pub fn pin(&mut self) -> &mut FileEditFile<'a, S> {
+		self.desc.set_flags(self.desc.flags() | Descriptor::FLAG_PINNED);
+		self
+	}
+
+	/// Unpins the file data, garbage collection may relocate it again.
+	pub fn unpin(&mut self) -> &mut FileEditFile<'a, S> {
+		self.desc.set_flags(self.desc.flags() & !Descriptor::FLAG_PINNED);
+		self
+	}
+
+	// Simple bump allocate from the file and returns the offset of the allocation
+	fn allocate(&mut self, size: u32) -> io::Result<u32> {
+		match bump_allocate(*self.high_mark, size, self.align, self.max_mark) {
+			Some((offset, high_mark)) => {
+				*self.high_mark = high_mark;
+				Ok(offset)
+			},
+			None => Err(io::ErrorKind::OutOfMemory)?,
+		}
 	}
 
 	/// Copies and encrypts the data with the given key into the address specified by this file descriptor.
-	pub fn write_data(&mut self, data: &[u8], key: &Key) -> io::Result<&mut FileEditFile<'a>> {
-		// Seek to this section's file offset
-		let file_offset = self.desc.section.offset as u64 * BLOCK_SIZE as u64;
-		self.file.seek(io::SeekFrom::Start(file_offset))?;
+	///
+	/// The data may be buffered, errors writing the buffered data are returned by a later write or [`FileEditor::finish`].
+	pub fn write_data(&mut self, data: &[u8], key: &Key) -> io::Result<&mut FileEditFile<'a, S>> {
+		// This section's block offset
+		let offset = self.desc.section.offset as u64 * block_scale(self.block_size) as u64;
 
-		// Temp allocation to encrypt the data
-		let mut blocks = vec![Block::default(); self.desc.section.size as usize];
+		// Temp allocation to encrypt the data, reused across writes
+		let mut blocks = self.wbuf.take_scratch(self.desc.section.size as usize * block_scale(self.block_size));
 
 		// Copy the data in the temp allocation
 		let len = usize::min(blocks.as_bytes().len(), data.len());
@@ -66,28 +110,27 @@ impl<'a> FileEditFile<'a> {
 		crypt::encrypt_section(&mut blocks, &mut self.desc.section, key);
 
 		// Write the data to the file
-		let result = self.file.write_all(blocks.as_bytes());
+		let result = self.wbuf.write(offset, &blocks);
 
-		drop(blocks);
+		self.wbuf.put_scratch(blocks);
 		result.map(|()| self)
 	}
 
 	/// Initialize the data with zeroes.
-	pub fn zero_data(&mut self, key: &Key) -> io::Result<&mut FileEditFile<'a>> {
-		// Seek to this section's file offset
-		let file_offset = self.desc.section.offset as u64 * BLOCK_SIZE as u64;
-		self.file.seek(io::SeekFrom::Start(file_offset))?;
+	pub fn zero_data(&mut self, key: &Key) -> io::Result<&mut FileEditFile<'a, S>> {
+		// This section's block offset
+		let offset = self.desc.section.offset as u64 * block_scale(self.block_size) as u64;
 
-		// Temp allocation to encrypt the zeroes
-		let mut blocks = vec![Block::default(); self.desc.section.size as usize];
+		// Temp allocation to encrypt the zeroes, reused across writes
+		let mut blocks = self.wbuf.take_scratch(self.desc.section.size as usize * block_scale(self.block_size));
 
 		// Encrypt the zeroes inplace
 		crypt::encrypt_section(&mut blocks, &mut self.desc.section, key);
 
 		// Write the zeroes to the file
-		let result = self.file.write_all(blocks.as_bytes());
+		let result = self.wbuf.write(offset, &blocks);
 
-		drop(blocks);
+		self.wbuf.put_scratch(blocks);
 		result.map(|()| self)
 	}
 
@@ -103,10 +146,9 @@ impl<'a> FileEditFile<'a> {
 	/// If consistency is important, consider removing & creating the file again instead.
 	pub fn reencrypt_data(&mut self, old_key: &Key, key: &Key) -> io::Result<()> {
 		// Read the file to memory buffer
-		let file_offset = self.desc.section.offset as u64 * BLOCK_SIZE as u64;
-		self.file.seek(io::SeekFrom::Start(file_offset))?;
-		let mut blocks = vec![Block::default(); self.desc.section.size as usize];
-		self.file.read_exact(blocks.as_bytes_mut())?;
+		let offset = self.desc.section.offset as u64 * block_scale(self.block_size) as u64;
+		let mut blocks = vec![Block::default(); self.desc.section.size as usize * block_scale(self.block_size)];
+		self.wbuf.read(offset, &mut blocks, 0)?;
 
 		// Decrypt the data inplace
 		if !crypt::decrypt_section(&mut blocks, &self.desc.section, old_key) {
@@ -118,9 +160,78 @@ impl<'a> FileEditFile<'a> {
 		crypt::encrypt_section(&mut blocks, &mut self.desc.section, key);
 
 		// Write the data back to the file
-		self.file.seek(io::SeekFrom::Start(file_offset))?;
-		self.file.write_all(blocks.as_bytes())?;
+		self.wbuf.write(offset, &blocks)?;
 
 		Ok(())
 	}
+
+	/// Decrypts the [metadata](MetaMap) of the file.
+	///
+	/// Returns an empty key-value store if the file has no metadata.
+	///
+	/// # Errors
+	///
+	/// * [`io::ErrorKind::InvalidData`]: The MAC of the meta section is incorrect or its contents are corrupted.
+	pub fn read_meta(&mut self, key: &Key) -> io::Result<MetaMap> {
+		let section = match self.desc.meta_section() {
+			Some(section) => section,
+			None => return Ok(MetaMap::new()),
+		};
+		let blocks = self.wbuf.read_section(0, self.block_size, &section, key)?;
+		match MetaMap::decode(blocks.as_bytes()) {
+			Some(meta) => Ok(meta),
+			None => Err(io::ErrorKind::InvalidData)?,
+		}
+	}
+
+	/// Allocates, encrypts and assigns a new meta section holding the [metadata](MetaMap).
+	///
+	/// The previous meta section is left behind as garbage, an empty key-value store removes the metadata.
+	/// The metadata is kept when assigning new contents to the file with `allocate_data` or `set_section`.
+	/// The data may be buffered, errors writing the buffered data are returned by a later write or [`FileEditor::finish`].
+	///
+	/// # Errors
+	///
+	/// * [`io::ErrorKind::InvalidInput`]: The file is [packed](Descriptor::is_packed) or the encoded metadata is too large for a meta section.
+	/// * [`io::ErrorKind::OutOfMemory`]: The meta section exceeds the [maximum size](FileEditor::set_max_size).
+	pub fn set_meta(&mut self, meta: &MetaMap, key: &Key) -> io::Result<&mut FileEditFile<'a, S>> {
+		if self.desc.is_packed() {
+			Err(io::ErrorKind::InvalidInput)?;
+		}
+		let data = meta.encode();
+		let size = match u32::try_from(data.len()) {
+			Ok(len) if bytes2blocks(len, self.block_size) <= 0x00ffffff => bytes2blocks(len, self.block_size),
+			_ => Err(io::ErrorKind::InvalidInput)?,
+		};
+
+		let mut section = Section { size, ..Section::default() };
+		if size != 0 {
+			section.offset = self.allocate(size)?;
+
+			let mut blocks = vec![Block::default(); size as usize * block_scale(self.block_size)];
+			blocks.as_bytes_mut()[..data.len()].copy_from_slice(&data);
+			crypt::encrypt_section(&mut blocks, &mut section, key);
+			self.wbuf.write(section.offset as u64 * block_scale(self.block_size) as u64, &blocks)?;
+		}
+		self.desc.set_meta_section(&section);
+		Ok(self)
+	}
+
+	/// Stores a small preview of the file in its [metadata](MetaMap::PREVIEW), eg. a texture thumbnail.
+	///
+	/// See [`MemoryEditFile::set_preview`] for more information.
+	pub fn set_preview(&mut self, preview: &[u8], key: &Key) -> io::Result<&mut FileEditFile<'a, S>> {
+		let mut meta = self.read_meta(key)?;
+		meta.set_bytes(MetaMap::PREVIEW, preview);
+		self.set_meta(&meta, key)
+	}
+
+	/// Records the paths of the files the file depends on in its [metadata](MetaMap::dependencies).
+	///
+	/// See [`MemoryEditFile::set_dependencies`] for more information.
+	pub fn set_dependencies(&mut self, paths: &[&[u8]], key: &Key) -> io::Result<&mut FileEditFile<'a, S>> {
+		let mut meta = self.read_meta(key)?;
+		meta.set_dependencies(paths);
+		self.set_meta(&meta, key)
+	}
 }
@@ -1,30 +1,80 @@
-use std::{fs, io, io::prelude::*, ops, path::Path};
+use std::{collections::HashMap, convert::TryFrom, fmt, fs, io, io::prelude::*, ops, path::Path, sync::{Mutex, MutexGuard, PoisonError}, thread};
 use crate::*;
 use super::*;
 
 /// File editor.
 ///
+/// Edits a PAK file in a file or any other [`Storage`], see [`new_in`](Self::new_in).
+///
 /// # Consistency guarantees
 ///
 /// The implementation makes a reasonable attempt to defend against data loss.
 /// If consistency is super important then consider [`MemoryEditor`] and save a fresh copy when needed.
-pub struct FileEditor {
-	pub(super) file: fs::File,
+pub struct FileEditor<S = fs::File> {
 	pub(super) directory: Directory,
 	pub(super) high_mark: u32,
+	pub(super) read_buf: usize,
+	// The storage behind the write buffer, locked by the readers to flush before reading
+	pub(super) wbuf: Mutex<WriteBuf<S>>,
+	pub(crate) policy: Policy,
+	// Set by every change to be written by finish, see `is_dirty`
+	pub(crate) dirty: bool,
+	// Size in blocks of the storage when opened, restored by abort
+	pub(super) storage_size: u64,
+	pub(super) drop_check: bool,
+	// Logged changes appended to the changelog by finish
+	pub(crate) changelog: Vec<ChangeEntry>,
 }
 
 impl FileEditor {
 	/// Creates a new PAK file, failing if it already exists.
 	#[inline]
 	pub fn create_new<P: ?Sized + AsRef<Path>>(path: &P, key: &Key) -> io::Result<FileEditor> {
-		create_new(path.as_ref(), key)
+		create_new(path.as_ref(), key, &IoOptions::default())
+	}
+
+	/// Creates a new PAK file with the given IO buffering options, failing if it already exists.
+	#[inline]
+	pub fn create_new_with<P: ?Sized + AsRef<Path>>(path: &P, key: &Key, io: &IoOptions) -> io::Result<FileEditor> {
+		create_new(path.as_ref(), key, io)
 	}
 
 	/// Opens an existing PAK file, error if it doesn't exist.
 	#[inline]
 	pub fn open<P: ?Sized + AsRef<Path>>(path: &P, key: &Key) -> io::Result<FileEditor> {
-		open(path.as_ref(), key)
+		open(path.as_ref(), key, &IoOptions::default())
+	}
+
+	/// Opens an existing PAK file with the given IO buffering options, error if it doesn't exist.
+	#[inline]
+	pub fn open_with<P: ?Sized + AsRef<Path>>(path: &P, key: &Key, io: &IoOptions) -> io::Result<FileEditor> {
+		open(path.as_ref(), key, io)
+	}
+
+	/// Reopens the PAK file opened by the reader for editing.
+	///
+	/// The decrypted directory of the reader is reused, only the header is read again to check the PAK file did not change since it was opened.
+	/// The path must be the path the reader was opened with.
+	///
+	/// # Errors
+	///
+	/// * [`io::ErrorKind::InvalidInput`]: The reader was opened at an offset or hides files, see [`FileReader::open_with_options`].
+	/// * [`io::ErrorKind::InvalidData`]: The PAK file at the path is not the PAK file opened by the reader or it was changed since.
+	/// * [`io::Error`]: An error encountered opening or reading the PAK file.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// let ref key = paks::Key::default();
+	/// let reader = paks::FileReader::open("game.pak", key).unwrap();
+	/// if reader.find_file(b"patch.txt").is_none() {
+	///     let mut editor = paks::FileEditor::reopen_rw(reader, "game.pak").unwrap();
+	///     editor.create_file(b"patch.txt", b"patched", key).unwrap();
+	///     editor.finish(key).unwrap();
+	/// }
+	/// ```
+	pub fn reopen_rw<P: ?Sized + AsRef<Path>>(reader: FileReader, path: &P) -> io::Result<FileEditor> {
+		reopen_rw(reader, path.as_ref())
 	}
 
 	/// Creates an empty PAK file, overwrites any file if it already exists.
@@ -41,13 +91,29 @@ impl FileEditor {
 	pub fn read_only<P: ?Sized + AsRef<Path>>(path: &P, key: &Key) -> io::Result<FileEditor> {
 		read_only(path.as_ref(), key)
 	}
+
+	/// Preallocates space in the PAK file for the given number of blocks past the high mark.
+	///
+	/// Growing the file once up-front reduces fragmentation when the final size is roughly known.
+	/// Space which was reserved but not allocated by any file is trimmed again by [`finish`](Self::finish).
+	///
+	/// Note that the file is extended with [`fs::File::set_len`], the file system may not physically allocate the space.
+	pub fn reserve(&mut self, blocks: u32) -> io::Result<()> {
+		let len = (self.high_mark as u64 + blocks as u64) * self.directory.block_size as u64;
+		let file = &self.wbuf.get_mut().unwrap_or_else(PoisonError::into_inner).storage;
+		if file.metadata()?.len() < len {
+			file.set_len(len)?;
+		}
+		Ok(())
+	}
 }
 
 #[inline(never)]
-fn create_new(path: &Path, key: &Key) -> io::Result<FileEditor> {
+fn create_new(path: &Path, key: &Key, io: &IoOptions) -> io::Result<FileEditor> {
 	let mut file = fs::OpenOptions::new().create_new(true).read(true).write(true).open(path)?;
 
 	let mut header = Header::default();
+	header.info.block_size = BLOCK_SIZE as u32;
 	header.info.directory.offset = Header::BLOCKS_LEN as u32;
 	header.info.directory.size = 0;
 	crypt::encrypt_section(&mut [], &mut header.info.directory, key);
@@ -60,24 +126,49 @@ fn create_new(path: &Path, key: &Key) -> io::Result<FileEditor> {
 	// Create the empty FileEditor
 	let directory = Directory::new();
 	let high_mark = Header::BLOCKS_LEN as u32;
-	Ok(FileEditor { file, directory, high_mark })
+	FileEditor::with_storage(file, directory, high_mark, io)
 }
 
 #[inline(never)]
-fn open(path: &Path, key: &Key) -> io::Result<FileEditor> {
+fn open(path: &Path, key: &Key, io: &IoOptions) -> io::Result<FileEditor> {
 	let mut file = fs::OpenOptions::new().read(true).write(true).open(path)?;
 
-	let (info, directory) = read_header(&mut file, key)?;
+	let (header, directory) = read_header(&mut file, 0, io.read_buf, &Limits::default(), key)?;
+	let info = header.info;
 
 	// Initialize the high mark right after the end of the directory
 	// This ensures that in case of failure that the existing directory remains intact
-	let high_mark = info.directory.offset + info.directory.size * InfoHeader::BLOCKS_LEN as u32;
-	Ok(FileEditor { file, directory, high_mark })
+	let high_mark = info.directory.offset + directory_blocks(info.directory.size, info.block_size());
+	FileEditor::with_storage(file, directory, high_mark, io)
+}
+
+#[inline(never)]
+fn reopen_rw(reader: FileReader, path: &Path) -> io::Result<FileEditor> {
+	let FileReader { base, read_buf, mut directory, header, .. } = reader;
+	// Editing a partial directory would drop the hidden files
+	if base != 0 || directory.len() != header.info.directory.size as usize {
+		Err(io::ErrorKind::InvalidInput)?;
+	}
+	let mut file = fs::OpenOptions::new().read(true).write(true).open(path)?;
+
+	// Every finish writes a header with a new nonce
+	let mut current = Header::default();
+	file.read_exact(current.as_bytes_mut())?;
+	if current.nonce != header.nonce || current.mac != header.mac {
+		Err(io::Error::new(io::ErrorKind::InvalidData, "the PAK file changed since it was opened"))?;
+	}
+
+	// Same as opening the PAK file for editing
+	directory.set_sorted(false);
+	let info = header.info;
+	let high_mark = info.directory.offset + directory_blocks(info.directory.size, info.block_size());
+	FileEditor::with_storage(file, directory, high_mark, &IoOptions { read_buf, ..IoOptions::default() })
 }
 
 #[inline(never)]
 fn create_empty(path: &Path, key: &Key) -> io::Result<()> {
 	let mut header = Header::default();
+	header.info.block_size = BLOCK_SIZE as u32;
 	header.info.directory.offset = Header::BLOCKS_LEN as u32;
 	header.info.directory.size = 0;
 	crypt::encrypt_section(&mut [], &mut header.info.directory, key);
@@ -88,45 +179,241 @@ fn create_empty(path: &Path, key: &Key) -> io::Result<()> {
 #[inline(never)]
 fn read_only(path: &Path, key: &Key) -> io::Result<FileEditor> {
 	let mut file = fs::File::open(path)?;
+	let io = IoOptions::default();
 
-	let (info, directory) = read_header(&mut file, key)?;
+	let (header, directory) = read_header(&mut file, 0, io.read_buf, &Limits::default(), key)?;
+	let info = header.info;
 
 	// Initialize the high mark right after the end of the directory
 	// This ensures that in case of failure that the existing directory remains intact
-	let high_mark = u32::max(Header::BLOCKS_LEN as u32, info.directory.offset + info.directory.size * InfoHeader::BLOCKS_LEN as u32);
-	Ok(FileEditor { file, directory, high_mark })
+	let high_mark = u32::max(header_blocks(info.block_size()), info.directory.offset + directory_blocks(info.directory.size, info.block_size()));
+	FileEditor::with_storage(file, directory, high_mark, &io)
+}
+
+impl<S: Storage> FileEditor<S> {
+	/// Creates a new empty PAK file in the storage.
+	///
+	/// Nothing is written to the storage until [`finish`](Self::finish).
+	/// Sections are bump allocated within the [capacity](Storage::capacity) of the storage, allocations which do not fit fail before anything is written.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// let ref key = paks::Key::default();
+	/// let mut blocks = Vec::new();
+	/// let mut editor = paks::FileEditor::new_in(&mut blocks);
+	/// editor.create_file(b"a", b"Hello", key).unwrap();
+	/// editor.finish(key).unwrap();
+	///
+	/// let reader = paks::MemoryReader::from_blocks(blocks, key).unwrap();
+	/// assert_eq!(reader.read_data(reader.find_file(b"a").unwrap(), key).unwrap(), b"Hello");
+	/// ```
+	pub fn new_in(storage: S) -> FileEditor<S> {
+		let directory = Directory::new();
+		let high_mark = header_blocks(directory.block_size);
+		let wbuf = Mutex::new(WriteBuf::new(storage, WRITE_BUFFER_SIZE));
+		FileEditor { directory, high_mark, read_buf: 0, wbuf, policy: Policy::default(), dirty: true, storage_size: 0, drop_check: false, changelog: Vec::new() }
+	}
+
+	/// Opens the PAK file in the storage.
+	///
+	/// If the storage does not contain a PAK file or the encryption key is incorrect, [`io::ErrorKind::InvalidData`] is returned.
+	pub fn open_in(storage: S, key: &Key) -> io::Result<FileEditor<S>> {
+		let mut blocks = [Block::default(); Header::BLOCKS_LEN];
+		storage.read_blocks(0, &mut blocks)?;
+		let header = match parse::header(&blocks, key) {
+			Some(header) => header,
+			None => Err(io::ErrorKind::InvalidData)?,
+		};
+		let info = header.info;
+
+		let dir_range = match parse::directory_range(&info) {
+			Some(dir_range) => dir_range,
+			None => Err(io::ErrorKind::InvalidData)?,
+		};
+		let mut dir_blocks = vec![Block::default(); dir_range.len()];
+		storage.read_blocks(dir_range.start as u64, &mut dir_blocks)?;
+		if !crypt::decrypt_section(&mut dir_blocks, &info.directory, key) {
+			Err(io::ErrorKind::InvalidData)?;
+		}
+		let mut directory = Directory::from(parse::descriptors(&dir_blocks));
+		directory.block_size = info.block_size();
+		directory.set_name_flags(info.flags());
+
+		let high_mark = u32::max(header_blocks(info.block_size()), info.directory.offset + directory_blocks(info.directory.size, info.block_size()));
+		let mut editor = FileEditor::with_storage(storage, directory, high_mark, &IoOptions::default())?;
+		let FileEditor { directory, wbuf, read_buf, .. } = &mut editor;
+		let wbuf = wbuf.get_mut().unwrap_or_else(PoisonError::into_inner);
+		directory.index_tags(|section| wbuf.read_section(*read_buf, info.block_size(), section, key).ok());
+		Ok(editor)
+	}
+
+	fn with_storage(storage: S, directory: Directory, high_mark: u32, io: &IoOptions) -> io::Result<FileEditor<S>> {
+		let storage_size = storage.size()?;
+		let wbuf = Mutex::new(WriteBuf::new(storage, io.write_buf));
+		Ok(FileEditor { directory, high_mark, read_buf: io.read_buf, wbuf, policy: Policy::default(), dirty: false, storage_size, drop_check: false, changelog: Vec::new() })
+	}
+
+	// Locks the write buffer to access the storage
+	pub(super) fn storage(&self) -> MutexGuard<'_, WriteBuf<S>> {
+		self.wbuf.lock().unwrap_or_else(PoisonError::into_inner)
+	}
+
+	// Highest block index allocations may reach, limited by the maximum size and the capacity of the storage
+	pub(crate) fn max_mark(&self) -> u32 {
+		let block_size = self.directory.block_size;
+		let max_mark = self.policy.max_mark(block_size);
+		match self.storage().storage.capacity() {
+			Some(capacity) => {
+				let capacity = u32::try_from(capacity / block_scale(block_size) as u64).unwrap_or(u32::MAX);
+				u32::min(max_mark, capacity)
+			},
+			None => max_mark,
+		}
+	}
 }
 
-impl ops::Deref for FileEditor {
+impl<S> FileEditor<S> {
+	fn summary(&self) -> summary::Summary<'_> {
+		summary::Summary {
+			name: "FileEditor",
+			info: None,
+			directory: &self.directory,
+			high_mark: self.high_mark,
+		}
+	}
+}
+impl<S> fmt::Debug for FileEditor<S> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		fmt::Debug::fmt(&self.summary(), f)
+	}
+}
+impl<S> fmt::Display for FileEditor<S> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		fmt::Display::fmt(&self.summary(), f)
+	}
+}
+
+impl<S> ops::Deref for FileEditor<S> {
 	type Target = Directory;
 	#[inline]
 	fn deref(&self) -> &Directory {
 		&self.directory
 	}
 }
-impl ops::DerefMut for FileEditor {
+impl<S> ops::DerefMut for FileEditor<S> {
 	#[inline]
 	fn deref_mut(&mut self) -> &mut Directory {
+		// Any mutable access may change the directory
+		self.dirty = true;
 		&mut self.directory
 	}
 }
 
-impl FileEditor {
+impl<S> Drop for FileEditor<S> {
+	fn drop(&mut self) {
+		// Don't pile on when already unwinding
+		if !self.dirty || thread::panicking() {
+			return;
+		}
+		#[cfg(feature = "tracing")]
+		tracing::warn!("FileEditor dropped with unsaved changes");
+		if cfg!(debug_assertions) && self.drop_check {
+			panic!("FileEditor dropped with unsaved changes, call finish or abort");
+		}
+	}
+}
+
+impl<S: Storage> FileEditor<S> {
 	/// Highest block index containing file data.
 	#[inline]
 	pub fn high_mark(&self) -> u32 {
 		self.high_mark
 	}
 
+	/// Returns the size in bytes of the write buffer.
+	#[inline]
+	pub fn write_buffer(&self) -> usize {
+		self.storage().capacity()
+	}
+
+	/// Sets the size in bytes of the write buffer, the default is [`WRITE_BUFFER_SIZE`].
+	///
+	/// The data of consecutively allocated files is buffered and written with fewer, larger writes.
+	/// This speeds up packing many small files, a size of zero disables the buffering.
+	///
+	/// Any buffered data is written before changing the size.
+	pub fn set_write_buffer(&mut self, size: usize) -> io::Result<()> {
+		self.wbuf.get_mut().unwrap_or_else(PoisonError::into_inner).set_capacity(size)
+	}
+
+	// Writes the buffered data before reading from the storage
+	pub(super) fn flush(&self) -> io::Result<()> {
+		self.storage().flush()
+	}
+
+	/// Sets the block size of the PAK file.
+	///
+	/// The block size must be one of [`InfoHeader::BLOCK_SIZES`] and can only be changed before any file data is allocated.
+	/// Returns `false` and does nothing otherwise.
+	///
+	/// See [`InfoHeader::block_size`] for more information.
+	pub fn set_block_size(&mut self, block_size: u32) -> bool {
+		let block_size_ok = InfoHeader::BLOCK_SIZES.contains(&block_size);
+		if !block_size_ok || self.high_mark > header_blocks(self.directory.block_size) {
+			return false;
+		}
+		self.directory.block_size = block_size;
+		self.high_mark = header_blocks(block_size);
+		self.dirty = true;
+		true
+	}
+
+	/// Aligns the start of newly allocated file sections to the given number of bytes.
+	///
+	/// Aligned file data enables direct I/O and memory mapping of individual files.
+	/// The alignment must be a power of two, alignments smaller than the [block size](Directory::block_size) have no effect.
+	/// Garbage collection keeps the file sections aligned, the padding is not counted as [garbage](Directory::garbage).
+	///
+	/// Returns `false` and does nothing if the alignment is not a power of two.
+	pub fn align_data(&mut self, bytes: u32) -> bool {
+		if !bytes.is_power_of_two() {
+			return false;
+		}
+		self.directory.align = bytes;
+		true
+	}
+
 	/// Creates a file descriptor at the given path.
 	///
 	/// Any missing parent directories are automatically created.
+	/// [Sealed](Directory::seal) directories are ignored.
 	#[inline]
-	pub fn edit_file(&mut self, path: &[u8]) -> FileEditFile<'_> {
+	pub fn edit_file(&mut self, path: &[u8]) -> FileEditFile<'_, S> {
+		let block_size = self.directory.block_size;
+		let align = self.directory.align_blocks();
+		let max_mark = self.max_mark();
 		let desc = self.directory.create(path);
-		let file = &self.file;
+		self.dirty = true;
 		let high_mark = &mut self.high_mark;
-		FileEditFile { file, desc, high_mark }
+		let wbuf = self.wbuf.get_mut().unwrap_or_else(PoisonError::into_inner);
+		FileEditFile { desc, high_mark, wbuf, block_size, align, max_mark }
+	}
+
+	/// Edits the existing file at the given path.
+	///
+	/// Unlike [`edit_file`](Self::edit_file) nothing is created, returns `None` if there is no file at the path.
+	/// See [`MemoryEditor::edit_existing`] for more information.
+	#[inline]
+	pub fn edit_existing(&mut self, path: &[u8]) -> Option<FileEditFile<'_, S>> {
+		let block_size = self.directory.block_size;
+		let align = self.directory.align_blocks();
+		let max_mark = self.max_mark();
+		let desc = self.directory.existing_file_mut(path)?;
+		self.dirty = true;
+		let high_mark = &mut self.high_mark;
+		let wbuf = self.wbuf.get_mut().unwrap_or_else(PoisonError::into_inner);
+		Some(FileEditFile { desc, high_mark, wbuf, block_size, align, max_mark })
 	}
 
 	/// Creates a file at the given path.
@@ -137,13 +424,113 @@ impl FileEditor {
 	/// Any missing parent directories are automatically created.
 	///
 	/// If the data's len is greater than 4 GiB it is truncated as its size is stored in a `u32`.
+	///
+	/// Returns [`io::ErrorKind::PermissionDenied`] if the path is [sealed](Directory::seal),
+	/// [`io::ErrorKind::InvalidInput`] if the path is empty or deeper than [`dir::MAX_DEPTH`] or the file is rejected by the [insert validator](Self::set_insert_validator),
+	/// [`io::ErrorKind::AlreadyExists`] if the [path hash collides](Directory::hash_collides) with another file
+	/// and [`io::ErrorKind::OutOfMemory`] if the data exceeds the [maximum size](Self::set_max_size).
 	pub fn create_file(&mut self, path: &[u8], data: &[u8], key: &Key) -> io::Result<&Descriptor> {
+		if !self.directory.writable(path) {
+			Err(io::ErrorKind::PermissionDenied)?;
+		}
+		if Directory::invalid_path(path) {
+			Err(io::Error::new(io::ErrorKind::InvalidInput, "path is empty or exceeds the maximum depth"))?;
+		}
+		if self.directory.hash_collides(path) {
+			Err(io::Error::new(io::ErrorKind::AlreadyExists, "path hash collides with another file"))?;
+		}
+		if let Err(message) = self.policy.validate(path, data) {
+			Err(io::Error::new(io::ErrorKind::InvalidInput, message))?;
+		}
+		// Check the allocation before the file descriptor is created
+		let block_size = self.directory.block_size;
+		let size = bytes2blocks(data.len() as u32, block_size);
+		if bump_allocate(self.high_mark, size, self.directory.align_blocks(), self.max_mark()).is_none() {
+			Err(io::ErrorKind::OutOfMemory)?;
+		}
 		let mut edit_file = self.edit_file(path);
 		edit_file.set_content(1, data.len() as u32);
-		edit_file.allocate_data().write_data(data, key)?;
+		edit_file.allocate_data()?.write_data(data, key)?;
 		Ok(edit_file.desc)
 	}
 
+	/// Creates a file at the given path from data encrypted ahead of time.
+	///
+	/// The file is assigned a content_type of `1`.
+	/// See [`create_file`](Self::create_file) for more information.
+	///
+	/// # Errors
+	///
+	/// * [`io::ErrorKind::PermissionDenied`]: The path is [sealed](Directory::seal).
+	/// * [`io::ErrorKind::InvalidInput`]: The path is empty or deeper than [`dir::MAX_DEPTH`] or the data was encrypted for a different [block size](Directory::block_size).
+	/// * [`io::ErrorKind::AlreadyExists`]: The [path hash collides](Directory::hash_collides) with another file.
+	/// * [`io::ErrorKind::OutOfMemory`]: The data exceeds the [maximum size](Self::set_max_size).
+	/// * [`io::Error`]: An error encountered writing the PAK file.
+	pub fn create_encrypted(&mut self, path: &[u8], encrypted: &Encrypted) -> io::Result<&Descriptor> {
+		if !self.directory.writable(path) {
+			Err(io::ErrorKind::PermissionDenied)?;
+		}
+		if Directory::invalid_path(path) {
+			Err(io::Error::new(io::ErrorKind::InvalidInput, "path is empty or exceeds the maximum depth"))?;
+		}
+		if self.directory.hash_collides(path) {
+			Err(io::Error::new(io::ErrorKind::AlreadyExists, "path hash collides with another file"))?;
+		}
+		if encrypted.block_size != self.directory.block_size {
+			Err(io::ErrorKind::InvalidInput)?;
+		}
+		let section = self.import_section_raw(encrypted.blocks.as_bytes(), &encrypted.section.nonce, &encrypted.section.mac)?;
+		let mut edit_file = self.edit_file(path);
+		edit_file.set_content(1, encrypted.content_size).set_section(&section);
+		Ok(edit_file.desc)
+	}
+
+	/// Tags the file at the given path.
+	///
+	/// See [`MemoryEditor::tag`] for more information.
+	pub fn tag(&mut self, path: &[u8], tag: &str, key: &Key) -> io::Result<bool> {
+		self.retag(path, tag, true, key)
+	}
+
+	/// Removes the tag from the file at the given path.
+	///
+	/// Returns `false` and does nothing if the file does not have the tag.
+	/// See [`MemoryEditor::tag`] for more information.
+	pub fn untag(&mut self, path: &[u8], tag: &str, key: &Key) -> io::Result<bool> {
+		self.retag(path, tag, false, key)
+	}
+
+	fn retag(&mut self, path: &[u8], tag: &str, add: bool, key: &Key) -> io::Result<bool> {
+		if tag.is_empty() || tag.contains('\0') {
+			Err(io::ErrorKind::InvalidInput)?;
+		}
+		if !self.directory.writable(path) {
+			Err(io::ErrorKind::PermissionDenied)?;
+		}
+		let id = match self.directory.find(path) {
+			Some(id) if matches!(self.directory.get(id), Some(desc) if desc.is_file()) => id,
+			_ => Err(io::ErrorKind::NotFound)?,
+		};
+		let block_size = self.directory.block_size;
+		let align = self.directory.align_blocks();
+		let max_mark = self.max_mark();
+		let desc = match self.directory.get_mut(id) {
+			Some(desc) => desc,
+			None => Err(io::ErrorKind::NotFound)?,
+		};
+		let high_mark = &mut self.high_mark;
+		let wbuf = self.wbuf.get_mut().unwrap_or_else(PoisonError::into_inner);
+		let mut edit_file = FileEditFile { desc, high_mark, wbuf, block_size, align, max_mark };
+		let mut meta = edit_file.read_meta(key)?;
+		if !meta.retag(tag, add) {
+			return Ok(false);
+		}
+		self.dirty = true;
+		edit_file.set_meta(&meta, key)?;
+		self.directory.index_tag(id, tag, add);
+		Ok(true)
+	}
+
 	/// Decrypts the section.
 	///
 	/// The key is not required to be the same as used to open the PAK file.
@@ -155,7 +542,38 @@ impl FileEditor {
 	/// * [`io::Error`]: An error encountered reading the underlying PAK file.
 	#[inline]
 	pub fn read_section(&self, section: &Section, key: &Key) -> io::Result<Vec<Block>> {
-		read_section(&self.file, section, key)
+		self.storage().read_section(self.read_buf, self.directory.block_size, section, key)
+	}
+
+	/// Imports an encrypted section copied from another PAK file without decrypting it.
+	///
+	/// The data is written as-is and the returned section references it with the given nonce and MAC.
+	/// Assign the section to a file descriptor with [`create_link`](Directory::create_link) or [`FileEditFile::set_section`].
+	/// The data can only be decrypted with the key of the PAK file it was copied from.
+	///
+	/// # Errors
+	///
+	/// * [`io::ErrorKind::InvalidInput`]: The data is not a whole number of blocks of this PAK file's [block size](Directory::block_size).
+	/// * [`io::ErrorKind::OutOfMemory`]: The data exceeds the [maximum size](Self::set_max_size).
+	/// * [`io::Error`]: An error encountered writing the PAK file.
+	pub fn import_section_raw(&mut self, data: &[u8], nonce: &Block, mac: &Block) -> io::Result<Section> {
+		let block_size = self.directory.block_size as usize;
+		if !data.len().is_multiple_of(block_size) || data.len() / block_size > u32::MAX as usize {
+			Err(io::ErrorKind::InvalidInput)?;
+		}
+
+		let size = (data.len() / block_size) as u32;
+		let (offset, high_mark) = match bump_allocate(self.high_mark, size, self.directory.align_blocks(), self.max_mark()) {
+			Some(allocation) => allocation,
+			None => Err(io::ErrorKind::OutOfMemory)?,
+		};
+		let mut blocks = vec![Block::default(); data.len() / BLOCK_SIZE];
+		blocks.as_bytes_mut().copy_from_slice(data);
+		let scale = block_scale(block_size as u32) as u64;
+		self.wbuf.get_mut().unwrap_or_else(PoisonError::into_inner).write(offset as u64 * scale, &blocks)?;
+		self.high_mark = high_mark;
+
+		Ok(Section { offset, size, nonce: *nonce, mac: *mac })
 	}
 
 	/// Decrypts the contents of the given file descriptor.
@@ -166,12 +584,10 @@ impl FileEditor {
 			Err(io::ErrorKind::InvalidInput)?;
 		}
 
-		let blocks = read_section(&self.file, &desc.section, key)?;
+		let blocks = self.read_section(&desc.section, key)?;
 
 		// Figure out which part of the blocks to copy
-		let data = blocks.as_bytes();
-		let len = usize::min(data.len(), desc.content_size as usize);
-		Ok(data[..len].to_vec())
+		Ok(desc.content(blocks.as_bytes()).to_vec())
 	}
 
 	/// Decrypts the contents of the given file descriptor into the dest buffer.
@@ -182,10 +598,11 @@ impl FileEditor {
 			Err(io::ErrorKind::InvalidInput)?;
 		}
 
-		let blocks = read_section(&self.file, &desc.section, key)?;
+		let blocks = self.read_section(&desc.section, key)?;
 
 		// Figure out which part of the blocks to copy
-		let data = match blocks.as_bytes().get(byte_offset..byte_offset + dest.len()) {
+		let start = desc.content_offset() as usize + byte_offset;
+		let data = match blocks.as_bytes().get(start..start + dest.len()) {
 			Some(data) => data,
 			None => Err(io::ErrorKind::InvalidInput)?,
 		};
@@ -196,22 +613,269 @@ impl FileEditor {
 		Ok(())
 	}
 
+	/// Securely erases the contents of the file at the given path.
+	///
+	/// Removing a file only forgets its nonce, leaving the encrypted data behind until it is overwritten.
+	/// Shredding overwrites the data in the PAK file with random bytes, the file descriptor remains with its contents emptied.
+	///
+	/// Links to the file are emptied as well.
+	/// If the file is [packed](Descriptor::is_packed), only its contents are overwritten and the shared section is re-encrypted with the given key.
+	///
+	/// The data is overwritten immediately, not only when the editor is finished.
+	///
+	/// # Errors
+	///
+	/// * [`io::ErrorKind::NotFound`]: No file exists at the given path.
+	/// * [`io::ErrorKind::PermissionDenied`]: The path is [sealed](Directory::seal).
+	/// * [`io::ErrorKind::InvalidInput`]: The file's section is out of bounds.
+	/// * [`io::ErrorKind::InvalidData`]: The file's section is shared and its MAC is incorrect.
+	/// * [`io::Error`]: An error encountered reading or writing the PAK file.
+	pub fn shred(&mut self, path: &[u8], key: &Key) -> io::Result<()> {
+		if !self.directory.writable(path) {
+			Err(io::ErrorKind::PermissionDenied)?;
+		}
+		let file = match self.directory.find_file(path) {
+			Some(desc) => *desc,
+			None => Err(io::ErrorKind::NotFound)?,
+		};
+		let block_size = self.directory.block_size;
+		let mut section = file.section;
+		match section.offset.checked_add(section.size) {
+			Some(end) if end <= self.high_mark => (),
+			_ => Err(io::ErrorKind::InvalidInput)?,
+		}
+		let mut blocks = vec![Block::default(); section.size as usize * block_scale(block_size)];
+		let offset = section.offset as u64 * block_scale(block_size) as u64;
+		let wbuf = self.wbuf.get_mut().unwrap_or_else(PoisonError::into_inner);
+		wbuf.flush()?;
+		if self.directory.shred_shares(&file) {
+			wbuf.read(offset, &mut blocks, self.read_buf)?;
+			let start = file.content_offset() as usize;
+			if !crypt::shred_section(&mut blocks, &mut section, start..start + file.content_size as usize, key) {
+				Err(io::ErrorKind::InvalidData)?;
+			}
+		}
+		else {
+			crypt::random(&mut blocks);
+		}
+		self.dirty = true;
+		wbuf.storage.write_blocks(offset, &blocks)?;
+		wbuf.storage.flush()?;
+		self.directory.shred_file(path, &file, &section, key);
+		Ok(())
+	}
+
+	/// Securely erases the contents of the file at the given path and removes its descriptor.
+	///
+	/// See [`shred`](Self::shred) for more information.
+	pub fn remove_shred(&mut self, path: &[u8], key: &Key) -> io::Result<Descriptor> {
+		self.shred(path, key)?;
+		match self.directory.remove(path) {
+			Some(desc) => Ok(desc),
+			None => Err(io::ErrorKind::NotFound)?,
+		}
+	}
+
+	/// Compacts the referenced data blocks from file descriptors.
+	///
+	/// Removing files only removes their descriptors, leaving unreadable garbage around.
+	/// This method reclaims the space left behind by deleted files by moving the file data towards the start of the PAK file.
+	/// The file data is copied in chunks of at most 1 MiB, large extents are not held in memory at once.
+	/// The [trash](Directory::soft_remove) is emptied first.
+	///
+	/// Any file descriptors with an invalid section object has their section object zeroed.
+	///
+	/// # Consistency guarantees
+	///
+	/// The file data is moved inplace and overwrites the data referenced by the current directory.
+	/// In the case of a failure (forced crash or power loss) before [`finish`](Self::finish) completes the PAK file is corrupted.
+	///
+	/// If consistency is important, consider [`MemoryEditor::gc`] and save a fresh copy instead.
+	pub fn gc(&mut self) -> io::Result<()> {
+		let high_mark = self.high_mark;
+		let block_size = self.directory.block_size;
+		let span = span!(DEBUG, "gc", high_mark);
+		self.dirty = true;
+
+		self.directory.empty_trash();
+
+		// The extents are moved directly in the file
+		self.flush()?;
+
+		// Zero any file sections outside the data area
+		for desc in self.directory.descs_mut() {
+			if desc.is_file() {
+				if !gc_plan::is_valid(&desc.section, block_size, high_mark) {
+					desc.section = Section::default();
+				}
+				if matches!(desc.meta_section(), Some(meta) if !gc_plan::is_valid(&meta, block_size, high_mark)) {
+					desc.set_meta_section(&Section::default());
+				}
+			}
+		}
+
+		// Move every extent down to its planned offset
+		// Extents are never moved up as that may overwrite the next extent before it is moved
+		let plan = GcPlan::new(&self.directory, high_mark);
+		let scale = block_scale(block_size) as u64;
+		let wbuf = self.wbuf.get_mut().unwrap_or_else(PoisonError::into_inner);
+		let mut chunk = Vec::new();
+		for extent in plan.moves() {
+			let len = (extent.from.end - extent.from.start) as u64 * scale;
+			copy_blocks(wbuf, &mut chunk, extent.from.start as u64 * scale, extent.to as u64 * scale, len, self.read_buf)?;
+		}
+
+		// Relocate the file sections and meta sections into their new extents
+		for desc in self.directory.descs_mut() {
+			if desc.is_file() && desc.section.size != 0 {
+				desc.section.offset = plan.relocate(desc.section.offset).unwrap_or(desc.section.offset);
+			}
+			if let Some(mut meta) = desc.meta_section() {
+				meta.offset = plan.relocate(meta.offset).unwrap_or(meta.offset);
+				desc.set_meta_section(&meta);
+			}
+		}
+
+		let new_mark = plan.new_high_mark;
+		self.high_mark = new_mark;
+		span.finish((high_mark - new_mark) as u64 * block_size as u64);
+		Ok(())
+	}
+
+	// Stores the file data in random order, see `set_obfuscation`
+	// The sections are copied past the high mark in random order and compacted by gc which keeps their order
+	pub(crate) fn shuffle_data(&mut self) -> io::Result<()> {
+		let block_size = self.directory.block_size;
+		let align = self.directory.align_blocks();
+		let max_mark = self.max_mark();
+		self.flush()?;
+
+		// Sections shared by multiple file descriptors are copied once, pinned sections stay in place
+		// The key slot table must stay right after the header where `key::unlock` looks for it
+		let high_mark = self.high_mark;
+		let key_slots = self.directory.find_file(Directory::KEY_SLOTS).map(|desc| desc.section);
+		let mut sections: Vec<(u32, u32)> = self.directory.as_ref().iter()
+			.filter(|desc| desc.is_file() && !desc.is_pinned() && Some(desc.section) != key_slots)
+			.flat_map(|desc| Some(desc.section).into_iter().chain(desc.meta_section()))
+			.filter(|section| section.size != 0 && gc_plan::is_valid(section, block_size, high_mark))
+			.map(|section| (section.offset, section.size))
+			.collect();
+		sections.sort_unstable();
+		sections.dedup();
+		shuffle(&mut sections);
+
+		let mut moved = HashMap::new();
+		let scale = block_scale(block_size) as u64;
+		let wbuf = self.wbuf.get_mut().unwrap_or_else(PoisonError::into_inner);
+		let mut chunk = Vec::new();
+		for &(offset, size) in &sections {
+			let (to, end) = match bump_allocate(self.high_mark, size, align, max_mark) {
+				Some(allocation) => allocation,
+				None => Err(io::ErrorKind::OutOfMemory)?,
+			};
+			copy_blocks(wbuf, &mut chunk, offset as u64 * scale, to as u64 * scale, size as u64 * scale, self.read_buf)?;
+			self.high_mark = end;
+			moved.insert((offset, size), to);
+		}
+
+		for desc in self.directory.descs_mut() {
+			if !desc.is_file() || desc.is_pinned() || Some(desc.section) == key_slots {
+				continue;
+			}
+			if let Some(&to) = moved.get(&(desc.section.offset, desc.section.size)) {
+				desc.section.offset = to;
+			}
+			if let Some(mut meta) = desc.meta_section() {
+				if let Some(&to) = moved.get(&(meta.offset, meta.size)) {
+					meta.offset = to;
+					desc.set_meta_section(&meta);
+				}
+			}
+		}
+		self.gc()
+	}
+
+	/// Returns whether the editor has changes to be written by [`finish`](Self::finish).
+	///
+	/// Any mutable access to the directory marks the editor dirty, even if nothing is changed.
+	/// Data appended without changing the directory, eg. by [`import_section_raw`](Self::import_section_raw), does not.
+	#[inline]
+	pub fn is_dirty(&self) -> bool {
+		self.dirty
+	}
+
+	/// Panics in debug builds when the editor is dropped with unsaved changes.
+	///
+	/// Dropping a [dirty](Self::is_dirty) editor without calling [`finish`](Self::finish) or [`abort`](Self::abort) silently loses the changes.
+	/// Enable the check to catch forgotten calls to `finish` during development, release builds never panic.
+	/// With the `tracing` feature a warning is emitted regardless of the check.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// let ref key = paks::Key::default();
+	/// let mut editor = paks::FileEditor::open("game.pak", key).unwrap();
+	/// editor.set_drop_check(cfg!(debug_assertions));
+	/// editor.create_file(b"patch.txt", b"patched", key).unwrap();
+	/// // Panics here in debug builds, finish was not called
+	/// ```
+	#[inline]
+	pub fn set_drop_check(&mut self, enabled: bool) {
+		self.drop_check = enabled;
+	}
+
+	/// Discards the changes and closes the PAK file.
+	///
+	/// Buffered data is dropped and data appended since the PAK file was opened is trimmed, the header and the directory are left alone.
+	///
+	/// Data overwritten in place by [`shred`](Self::shred) and [`gc`](Self::gc) cannot be restored.
+	/// Aborting after garbage collection leaves the directory referencing moved file data, finish the editor instead.
+	pub fn abort(mut self) -> io::Result<()> {
+		self.dirty = false;
+		let storage = &mut self.wbuf.get_mut().unwrap_or_else(PoisonError::into_inner).storage;
+		if storage.size()? > self.storage_size {
+			storage.truncate(self.storage_size)?;
+		}
+		Ok(())
+	}
+
 	/// Finish editing the PAK file.
 	///
 	/// Encrypts and appends the directory to the PAK file.
 	/// Before updating the new header the file is synced to attempt to preserve consistency.
 	/// Finally the header is updated to point to the new directory.
 	///
-	/// Dropping the PAK file without calling `finish` results in any changes being lost.
-	pub fn finish(self, key: &Key) -> io::Result<()> {
-		let FileEditor { mut file, mut directory, high_mark } = self;
+	/// If the editor is not [dirty](Self::is_dirty) nothing is written, the same as [`abort`](Self::abort).
+	///
+	/// Dropping the PAK file without calling `finish` results in any changes being lost, see [`set_drop_check`](Self::set_drop_check).
+	pub fn finish(mut self, key: &Key) -> io::Result<()> {
+		// Spare rewriting the directory and the header with new nonces
+		if !self.dirty {
+			return self.abort();
+		}
+		// The error is returned, failing to finish does not trip the drop check
+		let result = self.write_directory(key);
+		self.dirty = false;
+		result
+	}
+
+	fn write_directory(&mut self, key: &Key) -> io::Result<()> {
+		self.write_changelog(key)?;
+		self.auto_gc()?;
+		self.obfuscate(key)?;
+		#[cfg(feature = "parity")]
+		self.append_parity(key)?;
+		let FileEditor { directory, high_mark, wbuf, .. } = self;
+		let wbuf = wbuf.get_mut().unwrap_or_else(PoisonError::into_inner);
+		let high_mark = *high_mark;
+		let span = span!(DEBUG, "finish", high_mark, entries = directory.len());
 
 		let mut header = Header {
 			nonce: Block::default(),
 			mac: Block::default(),
 			info: InfoHeader {
 				version: InfoHeader::VERSION,
-				_unused: 0,
+				block_size: directory.block_size,
 				directory: Section {
 					offset: high_mark,
 					size: directory.len() as u32,
@@ -220,32 +884,78 @@ impl FileEditor {
 				},
 			},
 		};
+		header.info.set_flags(directory.name_policy().flags());
 
 		// Encrypt the directory
 		crypt::encrypt_section(directory.as_blocks_mut(), &mut header.info.directory, key);
 
 		// Encrypt the header
-		let mut section = Header::SECTION;
-		crypt::encrypt_section(header.info.as_mut(), &mut section, key);
-
-		header.nonce = section.nonce;
-		header.mac = section.mac;
+		crypt::encrypt_header(&mut header, key);
 
-		// Append the directory
-		let dir_offset = high_mark as u64 * BLOCK_SIZE as u64;
-		file.seek(io::SeekFrom::Start(dir_offset))?;
-		file.write_all(directory.as_ref().as_bytes())?;
+		// Append the directory together with any buffered data
+		let dir_offset = high_mark as u64 * block_scale(directory.block_size) as u64;
+		let dir_end = dir_offset + directory.as_blocks().len() as u64;
+		if matches!(wbuf.storage.capacity(), Some(capacity) if dir_end > capacity) {
+			Err(io::ErrorKind::WriteZero)?;
+		}
+		wbuf.flush_with(dir_offset, directory.as_blocks())?;
 
 		// IMPORTANT! In order to prevent corruption:
 		// Ensure that the above write of the directory is synced
 		// If this isn't done then overwriting the header may result in data loss
-		file.sync_data()?;
+		wbuf.storage.flush()?;
 
 		// Finally write the new header
 		// It is assumed that this write is atomic as it's pretty small and at the start of the file
-		file.seek(io::SeekFrom::Start(0))?;
-		file.write_all(header.as_bytes())?;
+		wbuf.storage.write_blocks(0, header.as_ref())?;
+
+		// Trim any data left behind after the directory
+		wbuf.storage.truncate(dir_end)?;
+
+		span.finish((header.as_bytes().len() + directory.as_ref().as_bytes().len()) as u64);
+		Ok(())
+	}
+
+	// Appends the parity of the file data, see `set_parity`
+	#[cfg(feature = "parity")]
+	fn append_parity(&mut self, key: &Key) -> io::Result<()> {
+		// Parity left behind by earlier edits no longer matches the file data
+		self.directory.remove(Directory::PARITY);
+		let options = match self.policy.parity {
+			Some(options) => options,
+			None => return Ok(()),
+		};
+
+		let block_size = self.directory.block_size as u64;
+		let start = header_blocks(self.directory.block_size) as u64 * block_size;
+		let end = self.high_mark as u64 * block_size;
+		let wbuf = self.wbuf.get_mut().unwrap_or_else(PoisonError::into_inner);
+		wbuf.flush()?;
+		let storage = &wbuf.storage;
+		let storage_size = storage.size()?;
+		let data = crate::parity::encode(&options, start, end, |offset, buf| crate::parity::read_storage_padded(storage, storage_size, offset, buf))?;
 
+		let mut edit_file = self.edit_file(Directory::PARITY);
+		edit_file.set_content(1, data.len() as u32);
+		edit_file.allocate_data()?.write_data(&data, key)?;
 		Ok(())
 	}
 }
+
+// Blocks of file data copied at once when moving file data around
+const COPY_CHUNK: u64 = 1024 * 1024 / BLOCK_SIZE as u64;
+
+// Copies the blocks in fixed-size chunks through the given buffer
+// The chunks are copied forward, the destination may overlap the source if it is lower
+pub(super) fn copy_blocks<S: Storage>(wbuf: &mut WriteBuf<S>, chunk: &mut Vec<Block>, from: u64, to: u64, len: u64, read_buf: usize) -> io::Result<()> {
+	debug_assert!(to <= from || to >= from + len);
+	let mut done = 0;
+	while done < len {
+		let n = u64::min(COPY_CHUNK, len - done);
+		chunk.resize(n as usize, Block::default());
+		wbuf.read(from + done, chunk, read_buf)?;
+		wbuf.storage.write_blocks(to + done, chunk)?;
+		done += n;
+	}
+	Ok(())
+}
@@ -0,0 +1,149 @@
+use std::ops;
+use crate::*;
+
+/// An extent of file data and where garbage collection moves it, see [`GcPlan`].
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct ExtentMove {
+	/// Blocks of the extent before garbage collection.
+	pub from: ops::Range<u32>,
+	/// Offset in blocks of the extent after garbage collection.
+	pub to: u32,
+}
+
+impl ExtentMove {
+	/// Returns whether the extent moves.
+	#[inline]
+	pub fn is_moved(&self) -> bool {
+		self.from.start != self.to
+	}
+}
+
+/// Plan of the garbage collection of a PAK file, see [`FileEditor::plan_gc`].
+///
+/// Offsets and sizes are measured in blocks of the PAK file's [block size](InfoHeader::block_size).
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct GcPlan {
+	/// Every extent of file data in order of its offset, including the extents staying in place.
+	///
+	/// An extent is a run of consecutive blocks referenced by file sections and meta sections, the extents are moved in order.
+	/// Extents holding the sections of [pinned](Descriptor::is_pinned) files stay in place.
+	pub extents: Vec<ExtentMove>,
+	/// The high mark before garbage collection.
+	pub high_mark: u32,
+	/// The high mark after garbage collection.
+	pub new_high_mark: u32,
+	/// The block size of the PAK file.
+	pub block_size: u32,
+}
+
+impl GcPlan {
+	// Mirrors the garbage collection of the directory, the trash must be emptied first
+	// Extents are moved down to the lowest free block respecting the data alignment, never up
+	// Extents holding the sections of pinned files are never moved
+	pub(super) fn new(directory: &Directory, high_mark: u32) -> GcPlan {
+		let block_size = directory.block_size();
+		let align = directory.align_blocks();
+
+		// Sections outside the data area are zeroed by garbage collection
+		let descs: Vec<Descriptor> = directory.as_ref().iter().map(|desc| {
+			let mut desc = *desc;
+			if desc.is_file() {
+				if !is_valid(&desc.section, block_size, high_mark) {
+					desc.section = Section::default();
+				}
+				if matches!(desc.meta_section(), Some(meta) if !is_valid(&meta, block_size, high_mark)) {
+					desc.set_meta_section(&Section::default());
+				}
+			}
+			desc
+		}).collect();
+
+		let pinned = dir::pinned_extents(&descs);
+		let mut new_mark = header_blocks(block_size);
+		let mut extents = Vec::new();
+		for extent in dir::extents(&descs) {
+			// Pinned extents stay in place, the extents before them are packed below
+			new_mark = if dir::overlaps_pinned(&pinned, &extent) { extent.start } else { u32::min(align_up(new_mark, align), extent.start) };
+			let len = extent.end - extent.start;
+			extents.push(ExtentMove { from: extent, to: new_mark });
+			new_mark += len;
+		}
+		GcPlan { extents, high_mark, new_high_mark: new_mark, block_size }
+	}
+
+	/// Iterates over the extents which move.
+	pub fn moves(&self) -> impl Iterator<Item = &ExtentMove> {
+		self.extents.iter().filter(|extent| extent.is_moved())
+	}
+
+	/// Returns whether garbage collection changes nothing.
+	#[inline]
+	pub fn is_empty(&self) -> bool {
+		self.new_high_mark == self.high_mark && self.moves().next().is_none()
+	}
+
+	/// Returns the number of bytes of file data read and rewritten by moving the extents.
+	pub fn bytes_rewritten(&self) -> u64 {
+		self.moves().map(|extent| (extent.from.end - extent.from.start) as u64).sum::<u64>() * self.block_size as u64
+	}
+
+	/// Returns the number of bytes reclaimed by lowering the high mark.
+	#[inline]
+	pub fn bytes_reclaimed(&self) -> u64 {
+		self.high_mark.saturating_sub(self.new_high_mark) as u64 * self.block_size as u64
+	}
+
+	/// Returns the offset after garbage collection of the block at the given offset.
+	///
+	/// Returns `None` if the block is garbage, it is not part of any extent.
+	pub fn relocate(&self, offset: u32) -> Option<u32> {
+		let i = self.extents.partition_point(|extent| extent.from.end <= offset);
+		let extent = self.extents.get(i).filter(|extent| extent.from.contains(&offset))?;
+		Some(offset - extent.from.start + extent.to)
+	}
+}
+
+// Whether the section lies in the data area
+pub(super) fn is_valid(section: &Section, block_size: u32, high_mark: u32) -> bool {
+	match section.offset.checked_add(section.size) {
+		Some(end) => section.offset >= header_blocks(block_size) && end <= high_mark,
+		None => false,
+	}
+}
+
+impl<S: Storage> FileEditor<S> {
+	/// Plans the garbage collection without executing it.
+	///
+	/// Returns which extents of file data [`gc`](Self::gc) moves where, the bytes it rewrites and the bytes it reclaims.
+	/// Tools can show the plan to users and schedule expensive garbage collection for idle times.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// let ref key = paks::Key::default();
+	/// let mut editor = paks::FileEditor::open("game.pak", key).unwrap();
+	///
+	/// let plan = editor.plan_gc();
+	/// println!("moving {} bytes reclaims {} bytes", plan.bytes_rewritten(), plan.bytes_reclaimed());
+	/// if plan.bytes_reclaimed() > 64 * 1024 * 1024 {
+	///     editor.gc().unwrap();
+	/// }
+	/// editor.finish(key).unwrap();
+	/// ```
+	pub fn plan_gc(&self) -> GcPlan {
+		let mut directory = Directory::clone(self);
+		directory.empty_trash();
+		GcPlan::new(&directory, self.high_mark())
+	}
+}
+
+impl FileReader {
+	/// Plans the garbage collection of the PAK file without opening it for editing.
+	///
+	/// See [`FileEditor::plan_gc`] for more information.
+	pub fn plan_gc(&self) -> GcPlan {
+		let mut directory = Directory::clone(self);
+		directory.empty_trash();
+		GcPlan::new(&directory, self.high_mark())
+	}
+}
@@ -0,0 +1,168 @@
+use std::{ffi::OsString, fs, io, mem, ops, path::{Path, PathBuf}, sync::PoisonError};
+use crate::*;
+
+/// How a [`HybridEditor`] writes the changes, see [`HybridEditor::strategy`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum EditStrategy {
+	/// The changes are appended to the PAK file and the directory is written in place.
+	///
+	/// Fast, the PAK file is left intact if interrupted but the garbage is not collected.
+	InPlace,
+	/// The PAK file is copied to a staging file next to it, compacted and atomically swapped with the original.
+	///
+	/// Slow, the PAK file is rewritten but never left half compacted.
+	Staged,
+}
+
+/// Size heuristics choosing the [`EditStrategy`] of a [`HybridEditor`].
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct HybridOptions {
+	/// Fraction of the PAK file reclaimable by garbage collection above which the changes are staged.
+	///
+	/// Defaults to a quarter of the PAK file.
+	pub stage_garbage: f64,
+	/// Number of bytes reclaimable by garbage collection below which the changes are always written in place.
+	///
+	/// Defaults to 1 MiB, rewriting small PAK files to reclaim a few bytes is not worth it.
+	pub stage_min_bytes: u64,
+}
+
+impl Default for HybridOptions {
+	#[inline]
+	fn default() -> HybridOptions {
+		HybridOptions {
+			stage_garbage: 0.25,
+			stage_min_bytes: 1024 * 1024,
+		}
+	}
+}
+
+/// File editor choosing between editing in place and staging a compacted copy.
+///
+/// Small additions are appended to the PAK file like the [`FileEditor`] it derefs to.
+/// When finishing the [strategy](Self::strategy) is chosen by the garbage the changes leave behind:
+/// large restructurings removing or replacing much of the file data are staged into a compacted copy which replaces the PAK file atomically.
+///
+/// # Examples
+///
+/// ```no_run
+/// let ref key = paks::Key::default();
+/// let mut editor = paks::HybridEditor::open("game.pak", key, &paks::HybridOptions::default()).unwrap();
+/// editor.remove(b"levels/old");
+/// editor.create_file(b"levels/new/map.bin", b"..", key).unwrap();
+///
+/// let strategy = editor.finish(key).unwrap();
+/// println!("changes written {:?}", strategy);
+/// ```
+pub struct HybridEditor {
+	editor: FileEditor,
+	path: PathBuf,
+	options: HybridOptions,
+}
+
+impl HybridEditor {
+	/// Opens an existing PAK file for editing with the given size heuristics.
+	pub fn open<P: ?Sized + AsRef<Path>>(path: &P, key: &Key, options: &HybridOptions) -> io::Result<HybridEditor> {
+		let editor = FileEditor::open(path, key)?;
+		Ok(HybridEditor { editor, path: path.as_ref().to_path_buf(), options: *options })
+	}
+
+	/// Returns the size heuristics.
+	#[inline]
+	pub fn options(&self) -> &HybridOptions {
+		&self.options
+	}
+
+	/// Returns the strategy finishing the changes made so far would use.
+	pub fn strategy(&self) -> EditStrategy {
+		let plan = self.editor.plan_gc();
+		let reclaimed = plan.high_mark - plan.new_high_mark;
+		if plan.bytes_reclaimed() >= self.options.stage_min_bytes && reclaimed as f64 > self.options.stage_garbage * plan.high_mark as f64 {
+			EditStrategy::Staged
+		}
+		else {
+			EditStrategy::InPlace
+		}
+	}
+
+	/// Returns the path of the staging file, the path of the PAK file with `.staging` appended.
+	pub fn staging_path(&self) -> PathBuf {
+		let mut path = OsString::from(&self.path);
+		path.push(".staging");
+		PathBuf::from(path)
+	}
+
+	/// Finish editing the PAK file with the chosen [strategy](Self::strategy).
+	///
+	/// Staged changes are written to the [staging file](Self::staging_path), compacted with [`FileEditor::gc`] and synced before it is renamed over the PAK file.
+	/// If staging fails the staging file is removed and the PAK file is left as it was opened.
+	///
+	/// Returns the strategy used.
+	pub fn finish(self, key: &Key) -> io::Result<EditStrategy> {
+		if !self.editor.is_dirty() {
+			self.editor.abort()?;
+			return Ok(EditStrategy::InPlace);
+		}
+		match self.strategy() {
+			EditStrategy::InPlace => {
+				self.editor.finish(key)?;
+				Ok(EditStrategy::InPlace)
+			},
+			EditStrategy::Staged => {
+				let staging = self.staging_path();
+				let HybridEditor { mut editor, path, .. } = self;
+				let file_len = editor.storage_size * BLOCK_SIZE as u64;
+				let result = stage(&mut editor, &path, &staging).and_then(|original| {
+					// The editor now writes the staging file, the original is trimmed when done
+					let result = finish_staged(editor, key, &path, &staging);
+					original.set_len(file_len)?;
+					result
+				});
+				if result.is_err() {
+					let _ = fs::remove_file(&staging);
+				}
+				result.map(|_| EditStrategy::Staged)
+			},
+		}
+	}
+
+	/// Discards the changes and closes the PAK file.
+	///
+	/// See [`FileEditor::abort`] for more information.
+	#[inline]
+	pub fn abort(self) -> io::Result<()> {
+		self.editor.abort()
+	}
+}
+
+// Copies the PAK file with the appended data to the staging file and points the editor at it
+// Returns the original file
+fn stage(editor: &mut FileEditor, path: &Path, staging: &Path) -> io::Result<fs::File> {
+	editor.flush()?;
+	let file_len = fs::copy(path, staging)?;
+	let file = fs::OpenOptions::new().read(true).write(true).open(staging)?;
+	editor.storage_size = file_len / BLOCK_SIZE as u64;
+	Ok(mem::replace(&mut editor.wbuf.get_mut().unwrap_or_else(PoisonError::into_inner).storage, file))
+}
+
+fn finish_staged(mut editor: FileEditor, key: &Key, path: &Path, staging: &Path) -> io::Result<()> {
+	editor.gc()?;
+	editor.finish(key)?;
+	// The header must be on disk before the staging file replaces the PAK file
+	fs::File::open(staging)?.sync_all()?;
+	fs::rename(staging, path)
+}
+
+impl ops::Deref for HybridEditor {
+	type Target = FileEditor;
+	#[inline]
+	fn deref(&self) -> &FileEditor {
+		&self.editor
+	}
+}
+impl ops::DerefMut for HybridEditor {
+	#[inline]
+	fn deref_mut(&mut self) -> &mut FileEditor {
+		&mut self.editor
+	}
+}
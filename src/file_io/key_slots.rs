@@ -0,0 +1,181 @@
+use std::{collections::HashMap, io, ops, sync::PoisonError};
+use dataview::Pod;
+use crate::*;
+
+impl Directory {
+	/// Path of the hidden file holding the key slot table, see [`FileEditor::add_key_slot`].
+	pub const KEY_SLOTS: &'static [u8] = b".keyslots";
+}
+
+impl<S: Storage> FileEditor<S> {
+	/// Grants access to the PAK file with another key.
+	///
+	/// The PAK file is encrypted with the master key passed to the editor, every key slot holds the master key encrypted with the key of the slot.
+	/// Keys are granted and revoked without reencrypting the data, open the PAK file with the master key from [`key::unlock`].
+	///
+	/// The key slot table is the hidden public file [`Directory::KEY_SLOTS`] found right after the header.
+	/// It is allocated by the first key slot, file data in its way is moved to the end of the PAK file. Its contents are random where there is no key slot.
+	/// The table is [pinned](Descriptor::is_pinned), garbage collection leaves it in place. Copying the files to another PAK file does not.
+	///
+	/// Returns the index of the key slot.
+	///
+	/// # Errors
+	///
+	/// * [`io::ErrorKind::InvalidInput`]: There is no key slot table and pinned file data is in its way.
+	/// * [`io::ErrorKind::OutOfMemory`]: Every one of the [`MAX_SLOTS`](key::MAX_SLOTS) key slots is taken or the moved file data exceeds the [maximum size](Self::set_max_size).
+	/// * [`io::Error`]: An error encountered reading or writing the key slot table.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// let ref master_key = paks::key::generate();
+	/// let (ref alice, ref bob) = (paks::key::generate(), paks::key::generate());
+	///
+	/// let mut editor = paks::FileEditor::create_new("game.pak", master_key).unwrap();
+	/// editor.add_key_slot(alice, master_key).unwrap();
+	/// let bob_slot = editor.add_key_slot(bob, master_key).unwrap();
+	/// editor.create_file(b"hello", b"Hello, world!", master_key).unwrap();
+	/// editor.finish(master_key).unwrap();
+	///
+	/// // Revoke access for bob
+	/// let ref master_key = paks::key::unlock("game.pak", alice).unwrap();
+	/// let mut editor = paks::FileEditor::open("game.pak", master_key).unwrap();
+	/// editor.remove_key_slot(bob_slot, master_key).unwrap();
+	/// editor.finish(master_key).unwrap();
+	/// ```
+	pub fn add_key_slot(&mut self, slot_key: &Key, key: &Key) -> io::Result<usize> {
+		let (mut table, mut slots) = match self.find_file(Directory::KEY_SLOTS) {
+			Some(desc) => (self.read_data(desc, key)?, key::decode_slots(&self.key_slots_meta(desc, key)?)),
+			None => {
+				self.reserve_key_slots()?;
+				let mut random = vec![Block::default(); key::TABLE_BYTES / BLOCK_SIZE];
+				crypt::random(&mut random);
+				(random.as_bytes().to_vec(), vec![None; key::MAX_SLOTS])
+			},
+		};
+
+		let index = match slots.iter().position(Option::is_none) {
+			Some(index) => index,
+			None => Err(io::ErrorKind::OutOfMemory)?,
+		};
+		key::wrap_slot(&mut table[index * key::SLOT_BLOCKS * BLOCK_SIZE..(index + 1) * key::SLOT_BLOCKS * BLOCK_SIZE], key, slot_key);
+		slots[index] = Some(key::fingerprint(slot_key));
+		self.write_key_slots(&table, &slots, key)?;
+		Ok(index)
+	}
+
+	/// Revokes access to the PAK file with the key in the given key slot.
+	///
+	/// The key slot is overwritten with random data, see [`add_key_slot`](Self::add_key_slot).
+	/// A revoked key which was used to recover the master key before keeps access to the data, reencrypt the PAK file with a new master key to rule this out.
+	///
+	/// # Errors
+	///
+	/// * [`io::ErrorKind::NotFound`]: There is no key in the key slot.
+	/// * [`io::ErrorKind::InvalidInput`]: The key slot is the last one, the master key could no longer be recovered.
+	/// * [`io::Error`]: An error encountered reading or writing the key slot table.
+	pub fn remove_key_slot(&mut self, index: usize, key: &Key) -> io::Result<()> {
+		let desc = match self.find_file(Directory::KEY_SLOTS) {
+			Some(desc) => desc,
+			None => Err(io::ErrorKind::NotFound)?,
+		};
+		let mut table = self.read_data(desc, key)?;
+		let mut slots = key::decode_slots(&self.key_slots_meta(desc, key)?);
+
+		if !matches!(slots.get(index), Some(Some(_))) {
+			Err(io::ErrorKind::NotFound)?;
+		}
+		if slots.iter().filter(|slot| slot.is_some()).count() == 1 {
+			Err(io::Error::new(io::ErrorKind::InvalidInput, "cannot remove the last key slot"))?;
+		}
+
+		let mut random = [Block::default(); key::SLOT_BLOCKS];
+		crypt::random(&mut random);
+		table[index * key::SLOT_BLOCKS * BLOCK_SIZE..(index + 1) * key::SLOT_BLOCKS * BLOCK_SIZE].copy_from_slice(random.as_bytes());
+		slots[index] = None;
+		self.write_key_slots(&table, &slots, key)
+	}
+
+	/// Returns the fingerprints of the keys in the key slots, `None` for the free key slots.
+	///
+	/// Returns an empty list if the PAK file has no key slots, see [`add_key_slot`](Self::add_key_slot).
+	pub fn key_slots(&self, key: &Key) -> io::Result<Vec<Option<key::Fingerprint>>> {
+		match self.find_file(Directory::KEY_SLOTS) {
+			Some(desc) => Ok(key::decode_slots(&self.key_slots_meta(desc, key)?)),
+			None => Ok(Vec::new()),
+		}
+	}
+
+	fn key_slots_meta(&self, desc: &Descriptor, key: &Key) -> io::Result<MetaMap> {
+		let section = match desc.meta_section() {
+			Some(section) => section,
+			None => return Ok(MetaMap::new()),
+		};
+		let blocks = self.read_section(&section, key)?;
+		MetaMap::decode(blocks.as_bytes()).ok_or(io::ErrorKind::InvalidData.into())
+	}
+
+	// Moves the file data in the way of the key slot table to the end of the PAK file
+	fn reserve_key_slots(&mut self) -> io::Result<()> {
+		let table = key::table_extent(self.directory.block_size);
+		let sections = match key::sections_in_table(&self.directory) {
+			Ok(sections) => sections,
+			Err(kind) => Err(io::Error::new(kind, "pinned file data is in the way of the key slot table"))?,
+		};
+		let max_mark = self.max_mark();
+		if table.end > max_mark {
+			Err(io::ErrorKind::OutOfMemory)?;
+		}
+		let align = self.directory.align_blocks();
+		let scale = block_scale(self.directory.block_size) as u64;
+		let mut high_mark = u32::max(self.high_mark, table.end);
+		let mut moved = HashMap::new();
+		let wbuf = self.wbuf.get_mut().unwrap_or_else(PoisonError::into_inner);
+		wbuf.flush()?;
+		let mut chunk = Vec::new();
+		for &(offset, size) in &sections {
+			let (to, end) = match bump_allocate(high_mark, size, align, max_mark) {
+				Some(allocation) => allocation,
+				None => Err(io::ErrorKind::OutOfMemory)?,
+			};
+			super::editor::copy_blocks(wbuf, &mut chunk, offset as u64 * scale, to as u64 * scale, size as u64 * scale, self.read_buf)?;
+			high_mark = end;
+			moved.insert((offset, size), to);
+		}
+		self.high_mark = high_mark;
+		key::relocate_sections(&mut self.directory, &moved);
+		self.dirty = true;
+		Ok(())
+	}
+
+	// Writes the table in place, assigning it the reserved blocks right after the header the first time
+	fn write_key_slots(&mut self, table: &[u8], slots: &[Option<key::Fingerprint>], key: &Key) -> io::Result<()> {
+		let ops::Range { start, end } = key::table_extent(self.directory.block_size);
+		let mut edit_file = match self.edit_existing(Directory::KEY_SLOTS) {
+			Some(edit_file) => edit_file,
+			None => {
+				let mut edit_file = self.edit_file(Directory::KEY_SLOTS);
+				edit_file.set_content(1, key::TABLE_BYTES as u32).set_section(&Section { offset: start, size: end - start, ..Section::default() }).pin();
+				edit_file
+			},
+		};
+		if edit_file.descriptor().section.offset != start {
+			Err(io::Error::new(io::ErrorKind::InvalidData, "key slot table is not after the header"))?;
+		}
+		edit_file.set_public(true).write_data(table, key)?;
+		edit_file.set_meta(&key::encode_slots(slots), key)?;
+		Ok(())
+	}
+}
+
+impl FileReader {
+	/// Returns the fingerprints of the keys in the key slots, `None` for the free key slots.
+	///
+	/// Returns an empty list if the PAK file has no key slots, see [`FileEditor::add_key_slot`].
+	pub fn key_slots(&self, key: &Key) -> io::Result<Vec<Option<key::Fingerprint>>> {
+		match self.find_file(Directory::KEY_SLOTS) {
+			Some(desc) => Ok(key::decode_slots(&self.read_meta(desc, key)?)),
+			None => Ok(Vec::new()),
+		}
+	}
+}
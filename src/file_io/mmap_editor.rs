@@ -0,0 +1,251 @@
+use std::{fmt, fs, io, ops, path::Path, slice};
+use memmap2::MmapMut;
+use crate::*;
+use super::*;
+
+/// Memory mapped file editor.
+///
+/// Maps the PAK file read-write and encrypts the file data directly into the mapping.
+/// This avoids the temporary buffer [`FileEditor`] needs to encrypt the data before writing, which matters for very large files.
+///
+/// Requires the `memmap2` feature.
+///
+/// # Consistency guarantees
+///
+/// Same as the [`FileEditor`], the header is only updated after the directory is flushed to disk.
+/// Modifying the PAK file by other means while it is mapped is undefined behavior.
+pub struct MmapEditor {
+	file: fs::File,
+	map: MmapMut,
+	directory: Directory,
+	high_mark: u32,
+}
+
+impl MmapEditor {
+	/// Opens an existing PAK file, error if it doesn't exist.
+	///
+	/// If the file is not a PAK file or the encryption key is incorrect, [`io::ErrorKind::InvalidData`] is returned.
+	#[inline]
+	pub fn open<P: ?Sized + AsRef<Path>>(path: &P, key: &Key) -> io::Result<MmapEditor> {
+		open(path.as_ref(), key)
+	}
+}
+
+#[inline(never)]
+fn open(path: &Path, key: &Key) -> io::Result<MmapEditor> {
+	let mut file = fs::OpenOptions::new().read(true).write(true).open(path)?;
+
+	let (header, directory) = read_header(&mut file, 0, 0, &Limits::default(), key)?;
+	let info = header.info;
+
+	// Initialize the high mark right after the end of the directory
+	// This ensures that in case of failure that the existing directory remains intact
+	let high_mark = u32::max(header_blocks(info.block_size()), info.directory.offset + directory_blocks(info.directory.size, info.block_size()));
+
+	// Safety: The PAK file must not be modified by other means while mapped
+	let map = unsafe { MmapMut::map_mut(&file)? };
+	Ok(MmapEditor { file, map, directory, high_mark })
+}
+
+impl MmapEditor {
+	fn summary(&self) -> summary::Summary<'_> {
+		summary::Summary {
+			name: "MmapEditor",
+			info: None,
+			directory: &self.directory,
+			high_mark: self.high_mark,
+		}
+	}
+}
+impl fmt::Debug for MmapEditor {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		fmt::Debug::fmt(&self.summary(), f)
+	}
+}
+impl fmt::Display for MmapEditor {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		fmt::Display::fmt(&self.summary(), f)
+	}
+}
+
+impl ops::Deref for MmapEditor {
+	type Target = Directory;
+	#[inline]
+	fn deref(&self) -> &Directory {
+		&self.directory
+	}
+}
+impl ops::DerefMut for MmapEditor {
+	#[inline]
+	fn deref_mut(&mut self) -> &mut Directory {
+		&mut self.directory
+	}
+}
+
+impl MmapEditor {
+	// The mapping as blocks, trailing bytes which do not make up a whole block are excluded
+	fn blocks(&self) -> &[Block] {
+		// Safety: The mapping is page aligned
+		unsafe { slice::from_raw_parts(self.map.as_ptr() as *const Block, self.map.len() / BLOCK_SIZE) }
+	}
+	fn blocks_mut(&mut self) -> &mut [Block] {
+		// Safety: The mapping is page aligned
+		unsafe { slice::from_raw_parts_mut(self.map.as_mut_ptr() as *mut Block, self.map.len() / BLOCK_SIZE) }
+	}
+
+	// Grows the file and remaps it to hold at least the given number of bytes
+	fn grow(&mut self, len: u64) -> io::Result<()> {
+		if (self.map.len() as u64) < len {
+			// Grow geometrically to amortize remapping
+			let len = u64::max(len, self.map.len() as u64 * 2);
+			self.map.flush()?;
+			self.file.set_len(len)?;
+			// Safety: The PAK file must not be modified by other means while mapped
+			self.map = unsafe { MmapMut::map_mut(&self.file)? };
+		}
+		Ok(())
+	}
+
+	/// Highest block index containing file data.
+	#[inline]
+	pub fn high_mark(&self) -> u32 {
+		self.high_mark
+	}
+
+	/// Creates a file at the given path.
+	///
+	/// The file is assigned a content_type of `1`.
+	/// A new section is allocated and the data is encrypted directly into the mapping.
+	///
+	/// Any missing parent directories are automatically created.
+	///
+	/// If the data's len is greater than 4 GiB it is truncated as its size is stored in a `u32`.
+	///
+	/// Returns [`io::ErrorKind::PermissionDenied`] if the path is [sealed](Directory::seal)
+	/// and [`io::ErrorKind::InvalidInput`] if the path is empty or deeper than [`dir::MAX_DEPTH`].
+	pub fn create_file(&mut self, path: &[u8], data: &[u8], key: &Key) -> io::Result<&Descriptor> {
+		if !self.directory.writable(path) {
+			Err(io::ErrorKind::PermissionDenied)?;
+		}
+		if Directory::invalid_path(path) {
+			Err(io::Error::new(io::ErrorKind::InvalidInput, "path is empty or exceeds the maximum depth"))?;
+		}
+		let block_size = self.directory.block_size;
+		let content_size = data.len() as u32;
+
+		// Simple bump allocate from the file
+		let size = bytes2blocks(content_size, block_size);
+		let (offset, high_mark) = match bump_allocate(self.high_mark, size, self.directory.align_blocks(), u32::MAX) {
+			Some(allocation) => allocation,
+			None => Err(io::ErrorKind::OutOfMemory)?,
+		};
+		let mut section = Section { offset, size, ..Section::default() };
+		self.grow(high_mark as u64 * block_size as u64)?;
+		self.high_mark = high_mark;
+
+		// Copy the data into the mapping and encrypt it inplace
+		let blocks = &mut self.blocks_mut()[section.range_usize(block_size)];
+		let len = usize::min(blocks.as_bytes().len(), data.len());
+		blocks.as_bytes_mut()[..len].copy_from_slice(&data[..len]);
+		blocks.as_bytes_mut()[len..].fill(0);
+		crypt::encrypt_section(blocks, &mut section, key);
+
+		let desc = self.directory.create(path);
+		desc.content_type = 1;
+		desc.content_size = content_size;
+		desc.section = section;
+		desc.unpack();
+		Ok(desc)
+	}
+
+	/// Decrypts the section.
+	///
+	/// The key is not required to be the same as used to open the PAK file.
+	///
+	/// # Errors
+	///
+	/// * [`io::ErrorKind::InvalidInput`]: The section is out of bounds.
+	/// * [`io::ErrorKind::InvalidData`]: The file's MAC is incorrect, the file is corrupted.
+	pub fn read_section(&self, section: &Section, key: &Key) -> io::Result<Vec<Block>> {
+		let mut blocks = match self.blocks().get(section.range_usize(self.directory.block_size)) {
+			Some(blocks) => blocks.to_vec(),
+			None => Err(io::ErrorKind::InvalidInput)?,
+		};
+		if !crypt::decrypt_section(&mut blocks, section, key) {
+			Err(io::ErrorKind::InvalidData)?;
+		}
+		Ok(blocks)
+	}
+
+	/// Decrypts the contents of the given file descriptor.
+	///
+	/// See [`read_section`](Self::read_section) for more information.
+	pub fn read_data(&self, desc: &Descriptor, key: &Key) -> io::Result<Vec<u8>> {
+		if !desc.is_file() {
+			Err(io::ErrorKind::InvalidInput)?;
+		}
+
+		let blocks = self.read_section(&desc.section, key)?;
+
+		// Figure out which part of the blocks to copy
+		Ok(desc.content(blocks.as_bytes()).to_vec())
+	}
+
+	/// Finish editing the PAK file.
+	///
+	/// Encrypts the directory into the mapping after the file data and flushes it to disk.
+	/// Finally the header is updated to point to the new directory and the file is trimmed.
+	///
+	/// Dropping the editor without calling `finish` results in the changes being lost.
+	pub fn finish(mut self, key: &Key) -> io::Result<()> {
+		let block_size = self.directory.block_size;
+		let high_mark = self.high_mark;
+		let dir_offset = high_mark as u64 * block_size as u64;
+		let dir_len = self.directory.as_ref().as_bytes().len();
+		self.grow(dir_offset + dir_len as u64)?;
+
+		let mut header = Header {
+			nonce: Block::default(),
+			mac: Block::default(),
+			info: InfoHeader {
+				version: InfoHeader::VERSION,
+				block_size,
+				directory: Section {
+					offset: high_mark,
+					size: self.directory.len() as u32,
+					nonce: Block::default(),
+					mac: Block::default(),
+				},
+			},
+		};
+		header.info.set_flags(self.directory.name_policy().flags());
+
+		// Encrypt the directory into the mapping
+		let start = dir_offset as usize / BLOCK_SIZE;
+		let dir_blocks = dir_len / BLOCK_SIZE;
+		let MmapEditor { file, mut map, directory, .. } = self;
+		{
+			// Safety: The mapping is page aligned
+			let blocks = unsafe { slice::from_raw_parts_mut(map.as_mut_ptr() as *mut Block, map.len() / BLOCK_SIZE) };
+			let blocks = &mut blocks[start..start + dir_blocks];
+			blocks.copy_from_slice(directory.as_blocks());
+			crypt::encrypt_section(blocks, &mut header.info.directory, key);
+		}
+
+		// Encrypt the header
+		crypt::encrypt_header(&mut header, key);
+
+		// IMPORTANT! In order to prevent corruption:
+		// Ensure that the directory is synced before overwriting the header
+		map.flush()?;
+
+		// Finally write the new header
+		map[..header.as_bytes().len()].copy_from_slice(header.as_bytes());
+		map.flush_range(0, header.as_bytes().len())?;
+		drop(map);
+
+		// Trim any data left behind after the directory
+		file.set_len(dir_offset + dir_len as u64)?;
+		Ok(())
+	}
+}
@@ -1,12 +1,18 @@
-use std::{fs, io, path::Path};
+use std::{fmt, fs, io, mem::MaybeUninit, ops, path::Path};
 use crate::*;
 use super::*;
 
 /// File reader.
 pub struct FileReader {
-	pub(super) file: fs::File,
+	// None if opened for metadata only
+	pub(super) file: Option<fs::File>,
+	// Byte offset of the PAK file embedded in the file
+	pub(super) base: u64,
+	pub(super) read_buf: usize,
+	// Refuse to read larger sections, see `ReaderOptions::max_section_bytes`
+	pub(super) max_section_bytes: Option<u64>,
 	pub(super) directory: Directory,
-	pub(super) info: InfoHeader,
+	pub(super) header: Header,
 }
 
 impl FileReader {
@@ -15,17 +21,81 @@ impl FileReader {
 	/// If the file at the given path is not a PAK file or the encryption key is incorrect, [`io::ErrorKind::InvalidData`] is returned.
 	#[inline]
 	pub fn open<P: ?Sized + AsRef<Path>>(path: &P, key: &Key) -> io::Result<FileReader> {
-		open(path.as_ref(), key)
+		open(path.as_ref(), 0, key, &IoOptions::default(), &Limits::default(), false)
+	}
+
+	/// Opens a PAK file for reading with the given IO buffering options.
+	///
+	/// See [`open`](Self::open) for more information.
+	#[inline]
+	pub fn open_with<P: ?Sized + AsRef<Path>>(path: &P, key: &Key, io: &IoOptions) -> io::Result<FileReader> {
+		open(path.as_ref(), 0, key, io, &Limits::default(), false)
+	}
+
+	/// Opens a PAK file for reading with the given options.
+	///
+	/// The files hidden by the options are removed from the directory as if they did not exist.
+	/// See [`open`](Self::open) for more information.
+	pub fn open_with_options<P: ?Sized + AsRef<Path>>(path: &P, key: &Key, options: &ReaderOptions) -> io::Result<FileReader> {
+		let mut reader = open(path.as_ref(), 0, key, &options.io, &options.limits, false)?;
+		reader.max_section_bytes = options.max_section_bytes;
+		reader.directory.retain(|path, _| options.is_visible(path));
+		Ok(reader)
+	}
+
+	/// Opens a PAK file embedded at the given byte offset in the file.
+	///
+	/// Use [`find_embedded`] to find the offset of a PAK file appended to another file.
+	///
+	/// If there is no PAK file at the given offset or the encryption key is incorrect, [`io::ErrorKind::InvalidData`] is returned.
+	#[inline]
+	pub fn open_at_offset<P: ?Sized + AsRef<Path>>(path: &P, offset: u64, key: &Key) -> io::Result<FileReader> {
+		open(path.as_ref(), offset, key, &IoOptions::default(), &Limits::default(), false)
+	}
+
+	/// Opens a PAK file for inspecting its header and directory only.
+	///
+	/// The header and directory are decrypted and authenticated, the file is closed afterwards.
+	/// Intended for tools which list or inspect PAK files without reading the file data.
+	///
+	/// Reading any file data from the returned reader fails with [`io::ErrorKind::Other`].
+	#[inline]
+	pub fn open_metadata_only<P: ?Sized + AsRef<Path>>(path: &P, key: &Key) -> io::Result<FileReader> {
+		open(path.as_ref(), 0, key, &IoOptions::default(), &Limits::default(), true)
 	}
 }
 
 #[inline(never)]
-fn open(path: &Path, key: &Key) -> io::Result<FileReader> {
+fn open(path: &Path, base: u64, key: &Key, io: &IoOptions, limits: &Limits, metadata_only: bool) -> io::Result<FileReader> {
 	let mut file = fs::File::open(path)?;
 
-	let (info, directory) = read_header(&mut file, key)?;
+	let (header, mut directory) = read_header(&mut file, base, io.read_buf, limits, key)?;
+	directory.detect_sorted();
 
-	Ok(FileReader { file, directory, info })
+	let file = if metadata_only { None } else { Some(file) };
+	let read_buf = io.read_buf;
+	Ok(FileReader { file, base, read_buf, max_section_bytes: None, directory, header })
+}
+
+impl FileReader {
+	fn summary(&self) -> summary::Summary<'_> {
+		summary::Summary {
+			name: "FileReader",
+			info: Some(&self.header.info),
+			directory: &self.directory,
+			high_mark: self.high_mark(),
+		}
+	}
+}
+impl fmt::Debug for FileReader {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		fmt::Debug::fmt(&self.summary(), f)
+	}
+}
+impl fmt::Display for FileReader {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		fmt::Display::fmt(&self.summary(), f)
+	}
 }
 
 impl ops::Deref for FileReader {
@@ -40,13 +110,54 @@ impl FileReader {
 	/// Returns the info header.
 	#[inline]
 	pub fn info(&self) -> &InfoHeader {
-		&self.info
+		&self.header.info
 	}
 
 	/// Highest block index containing file data.
 	#[inline]
 	pub fn high_mark(&self) -> u32 {
-		self.info.directory.offset
+		self.header.info.directory.offset
+	}
+
+	/// Byte offset of the PAK file in the underlying file.
+	///
+	/// Zero unless opened with [`open_at_offset`](Self::open_at_offset).
+	#[inline]
+	pub fn offset(&self) -> u64 {
+		self.base
+	}
+
+	/// Returns if the reader was opened with [`open_metadata_only`](Self::open_metadata_only).
+	#[inline]
+	pub fn is_metadata_only(&self) -> bool {
+		self.file.is_none()
+	}
+
+	fn file(&self) -> io::Result<&fs::File> {
+		match &self.file {
+			Some(file) => Ok(file),
+			None => Err(io::Error::other("PAK file opened for metadata only")),
+		}
+	}
+
+	/// Precomputes the full path of every descriptor.
+	///
+	/// See [`Directory::cache_paths`] for more information.
+	#[inline]
+	pub fn cache_paths(&mut self) {
+		self.directory.cache_paths();
+	}
+
+	/// Audits the nonces of the header, the directory and all file sections for reuse.
+	///
+	/// See [`Directory::audit_nonces`] for more information.
+	pub fn audit_nonces(&self) -> Vec<NonceReuse> {
+		let header = Section {
+			nonce: self.header.nonce,
+			mac: self.header.mac,
+			..Header::SECTION
+		};
+		self.directory.audit_nonces(&[header, self.header.info.directory])
 	}
 
 	/// Decrypts the section.
@@ -57,47 +168,294 @@ impl FileReader {
 	///
 	/// * [`io::ErrorKind::InvalidInput`]: The the descriptor is not a file descriptor.
 	/// * [`io::ErrorKind::InvalidData`]: The file's MAC is incorrect, the file is corrupted.
+	/// * [`io::ErrorKind::Other`]: The reader was opened for metadata only.
+	/// * [`io::ErrorKind::OutOfMemory`]: The section is larger than [`ReaderOptions::max_section_bytes`].
+	/// * [`io::ErrorKind::UnexpectedEof`]: The section extends past the end of the PAK file.
 	/// * [`io::Error`]: An error encountered reading the underlying PAK file.
 	#[inline]
 	pub fn read_section(&self, section: &Section, key: &Key) -> io::Result<Vec<Block>> {
-		read_section(&self.file, section, key)
+		self.check_section_size(section)?;
+		read_section(self.file()?, self.base, self.read_buf, self.directory.block_size, section, key)
+	}
+
+	/// Decrypts the section into the given blocks, reusing their allocation.
+	///
+	/// Loading many small files with the same buffer avoids an allocation per file.
+	/// The blocks are resized to the size of the section.
+	///
+	/// See [`read_section`](Self::read_section) for more information.
+	#[inline]
+	pub fn read_section_into(&self, section: &Section, key: &Key, blocks: &mut Vec<Block>) -> io::Result<()> {
+		self.check_section_size(section)?;
+		read_section_into(self.file()?, self.base, self.read_buf, self.directory.block_size, section, key, blocks)
+	}
+
+	fn check_section_size(&self, section: &Section) -> io::Result<()> {
+		match self.max_section_bytes {
+			Some(max) if section.size as u64 * self.directory.block_size as u64 > max => {
+				Err(io::Error::new(io::ErrorKind::OutOfMemory, format!("section of {} blocks exceeds the {} byte limit", section.size, max)))
+			},
+			_ => Ok(()),
+		}
+	}
+
+	/// Writes the encrypted contents of the section without decrypting it.
+	///
+	/// The MAC is not checked. Together with the section's nonce and MAC the data can be imported into another PAK file
+	/// encrypted with the same key with `import_section_raw` without knowing the key.
+	///
+	/// # Errors
+	///
+	/// * [`io::ErrorKind::UnexpectedEof`]: The section extends past the end of the PAK file.
+	/// * [`io::Error`]: An error encountered reading the PAK file or writing to the writer.
+	pub fn copy_section_raw<W: io::Write>(&self, section: &Section, mut writer: W) -> io::Result<()> {
+		let file = self.file()?;
+		let block_size = self.directory.block_size as u64;
+		let mut offset = self.base + section.offset as u64 * block_size;
+		let mut len = section.size as u64 * block_size;
+		// Copy in chunks with positioned reads, the section may be larger than memory
+		let chunk_len = if self.read_buf == 0 { STREAM_BLOCKS * BLOCK_SIZE } else { self.read_buf };
+		let mut buf = vec![0u8; u64::min(len, chunk_len as u64) as usize];
+		while len > 0 {
+			let chunk = &mut buf[..u64::min(len, chunk_len as u64) as usize];
+			read_exact_at(file, offset, chunk)?;
+			writer.write_all(chunk)?;
+			offset += chunk.len() as u64;
+			len -= chunk.len() as u64;
+		}
+		Ok(())
+	}
+
+	/// Reads the range of blocks as stored in the PAK file without decrypting them.
+	///
+	/// The range is measured in blocks of the PAK file's [block size](InfoHeader::block_size), eg. the offset and size of a section from [`sections`](Directory::sections).
+	/// The MAC is not checked, verify it with [`Section::verify_mac`] without ever decrypting the contents.
+	///
+	/// # Errors
+	///
+	/// * [`io::ErrorKind::InvalidInput`]: The range is reversed.
+	/// * [`io::ErrorKind::UnexpectedEof`]: The range extends past the end of the PAK file.
+	/// * [`io::ErrorKind::Other`]: The reader was opened for metadata only.
+	/// * [`io::ErrorKind::OutOfMemory`]: The range is larger than [`ReaderOptions::max_section_bytes`].
+	/// * [`io::Error`]: An error encountered reading the underlying PAK file.
+	pub fn raw_blocks(&self, range: ops::Range<u32>) -> io::Result<Vec<Block>> {
+		if range.start > range.end {
+			Err(io::ErrorKind::InvalidInput)?;
+		}
+		self.check_section_size(&Section { offset: range.start, size: range.end - range.start, ..Section::default() })?;
+		let file = self.file()?;
+		let block_size = self.directory.block_size;
+		let mut blocks = vec![Block::default(); (range.end - range.start) as usize * block_scale(block_size)];
+		read_exact_at(file, self.base + range.start as u64 * block_size as u64, blocks.as_bytes_mut())?;
+		Ok(blocks)
 	}
 
 	/// Decrypts the contents of the given file descriptor.
 	///
 	/// See [`read_section`](Self::read_section) for more information.
+	/// If the MAC is incorrect the error message contains the path of the file.
 	pub fn read_data(&self, desc: &Descriptor, key: &Key) -> io::Result<Vec<u8>> {
 		if !desc.is_file() {
 			Err(io::ErrorKind::InvalidInput)?;
 		}
 
-		let blocks = read_section(&self.file, &desc.section, key)?;
+		let blocks = self.read_file_section(desc, key)?;
 
 		// Figure out which part of the blocks to copy
-		let data = blocks.as_bytes();
-		let len = usize::min(data.len(), desc.content_size as usize);
-		Ok(data[..len].to_vec())
+		Ok(desc.content(blocks.as_bytes()).to_vec())
+	}
+
+	/// Decrypts the contents of the given file descriptor using the scratch blocks.
+	///
+	/// Returns the contents borrowed from the scratch blocks, reusing their allocation avoids an allocation per file.
+	/// See [`MemoryReader::read_data_with`] for more information.
+	pub fn read_data_with<'s>(&self, desc: &Descriptor, key: &Key, scratch: &'s mut Vec<Block>) -> io::Result<&'s [u8]> {
+		if !desc.is_file() {
+			Err(io::ErrorKind::InvalidInput)?;
+		}
+
+		self.read_file_section_into(desc, key, scratch)?;
+
+		// Figure out which part of the blocks to return
+		Ok(desc.content(scratch.as_bytes()))
+	}
+
+	/// Decrypts the contents of the given file descriptor into a shared buffer.
+	///
+	/// See [`MemoryReader::read_data_shared`] for more information.
+	pub fn read_data_shared(&self, desc: &Descriptor, key: &Key) -> io::Result<SharedData> {
+		if !desc.is_file() {
+			Err(io::ErrorKind::InvalidInput)?;
+		}
+
+		let blocks = self.read_file_section(desc, key)?;
+		Ok(SharedData::new(blocks, desc))
+	}
+
+	/// Decrypts the contents of the given file descriptor into [`Bytes`](bytes::Bytes).
+	///
+	/// See [`MemoryReader::read_data_bytes`] for more information.
+	#[cfg(feature = "bytes")]
+	pub fn read_data_bytes(&self, desc: &Descriptor, key: &Key) -> io::Result<bytes::Bytes> {
+		self.read_data_shared(desc, key).map(bytes::Bytes::from_owner)
 	}
 
 	/// Decrypts the contents of the given file descriptor into the dest buffer.
 	///
-	/// See [`read_section`](Self::read_section) for more information.
+	/// The section is decrypted in chunks directly into the dest buffer, the whole section is never held in memory.
+	/// The MAC is only checked after the last chunk, the dest buffer is zeroed if an error is returned so tampered contents are never left behind.
+	///
+	/// See [`read_data`](Self::read_data) for more information.
 	pub fn read_into(&self, desc: &Descriptor, key: &Key, byte_offset: usize, dest: &mut [u8]) -> io::Result<()> {
 		if !desc.is_file() {
 			Err(io::ErrorKind::InvalidInput)?;
 		}
 
-		let blocks = read_section(&self.file, &desc.section, key)?;
+		// Copy the part of the section to its destination
+		let start = desc.content_offset() as usize + byte_offset;
+		let result = self.read_file_range(desc, key, start..start + dest.len(), |offset, data| {
+			dest[offset..offset + data.len()].copy_from_slice(data);
+		});
+		if result.is_err() {
+			dest.fill(0);
+		}
+		result
+	}
 
-		// Figure out which part of the blocks to copy
-		let data = match blocks.as_bytes().get(byte_offset..byte_offset + dest.len()) {
-			Some(data) => data,
+	/// Decrypts the contents of the given file descriptor into uninitialized memory.
+	///
+	/// Returns the contents, initialized at the start of the dest buffer.
+	/// Like [`read_into`](Self::read_into) the section is decrypted in chunks directly into the dest buffer,
+	/// avoiding both the intermediate decrypted section and zeroing the destination of large reads.
+	///
+	/// # Errors
+	///
+	/// * [`io::ErrorKind::InvalidInput`]: The descriptor is not a file descriptor, the dest buffer is shorter than the contents or the contents are out of bounds of the section.
+	///
+	/// See [`read_data`](Self::read_data) for the other errors.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// use std::mem::MaybeUninit;
+	///
+	/// let ref key = paks::Key::default();
+	/// let reader = paks::FileReader::open("game.pak", key).unwrap();
+	/// let desc = reader.find_file(b"music/theme.ogg").unwrap();
+	///
+	/// let mut buf = vec![MaybeUninit::uninit(); desc.content_size as usize];
+	/// let data = reader.read_into_uninit(desc, key, &mut buf).unwrap();
+	/// assert_eq!(data.len(), desc.content_size as usize);
+	/// ```
+	pub fn read_into_uninit<'d>(&self, desc: &Descriptor, key: &Key, dest: &'d mut [MaybeUninit<u8>]) -> io::Result<&'d mut [u8]> {
+		if !desc.is_file() {
+			Err(io::ErrorKind::InvalidInput)?;
+		}
+		let dest = match dest.get_mut(..desc.content_size as usize) {
+			Some(dest) => dest,
 			None => Err(io::ErrorKind::InvalidInput)?,
 		};
 
-		// Copy the data to its destination
-		dest.copy_from_slice(data);
+		let start = desc.content_offset() as usize;
+		let result = self.read_file_range(desc, key, start..start + dest.len(), |offset, data| {
+			uninit_copy(&mut dest[offset..offset + data.len()], data);
+		});
+		// Zero the unauthenticated contents, see `read_into`
+		if let Err(err) = result {
+			dest.fill(MaybeUninit::new(0));
+			return Err(err);
+		}
+
+		// SAFETY: The chunks of the section cover the whole range, every byte of dest was written
+		Ok(unsafe { &mut *(dest as *mut [MaybeUninit<u8>] as *mut [u8]) })
+	}
 
+	/// Decrypts every file at or under the given path and passes its path and contents to the callback.
+	///
+	/// Files are visited in directory order one at a time, an empty path visits every file.
+	/// Intended for searching the contents of many files without holding them all in memory.
+	///
+	/// # Errors
+	///
+	/// * [`io::ErrorKind::NotFound`]: The path does not exist.
+	/// * See [`read_data`](Self::read_data), scanning stops at the first file which cannot be read.
+	pub fn scan<F: FnMut(&[u8], &[u8])>(&self, path: &[u8], key: &Key, mut f: F) -> io::Result<()> {
+		let files = match self.directory.files_under(path) {
+			Some(files) => files,
+			None => Err(io::ErrorKind::NotFound)?,
+		};
+		let mut scratch = Vec::new();
+		for (path, desc) in &files {
+			let data = self.read_data_with(desc, key, &mut scratch)?;
+			f(path, data);
+		}
 		Ok(())
 	}
+
+	fn read_file_section(&self, desc: &Descriptor, key: &Key) -> io::Result<Vec<Block>> {
+		let mut blocks = Vec::new();
+		self.read_file_section_into(desc, key, &mut blocks)?;
+		Ok(blocks)
+	}
+
+	fn read_file_section_into(&self, desc: &Descriptor, key: &Key, blocks: &mut Vec<Block>) -> io::Result<()> {
+		self.check_section_size(&desc.section)?;
+		let result = read_section_into(self.file()?, self.base, self.read_buf, self.directory.block_size, &desc.section, key, blocks);
+		self.mac_failure(desc, result)
+	}
+
+	// Decrypts the file's section in chunks and passes the decrypted bytes in the byte range of the section to the callback with their offset in the range
+	// Only one chunk of the section is held in memory, the MAC is checked after the whole section is decrypted
+	// The bytes passed to the callback are unauthenticated until this returns `Ok`, callers must discard them on error
+	fn read_file_range<F: FnMut(usize, &[u8])>(&self, desc: &Descriptor, key: &Key, range: ops::Range<usize>, mut f: F) -> io::Result<()> {
+		let section = &desc.section;
+		self.check_section_size(section)?;
+		let span = span!(DEBUG, "read_section", offset = section.offset, size = section.size);
+
+		let block_size = self.directory.block_size;
+		let len = section.size as usize * block_scale(block_size);
+		if range.start > range.end || range.end > len * BLOCK_SIZE {
+			Err(io::ErrorKind::InvalidInput)?;
+		}
+
+		let file = self.file()?;
+		let file_offset = self.base + section.offset as u64 * block_size as u64;
+		let mut decryptor = crypt::Decryptor::new(section, key);
+		let mut blocks = vec![Block::default(); usize::min(len, STREAM_BLOCKS)];
+		let mut index = 0;
+		while index < len {
+			let chunk = &mut blocks[..usize::min(len - index, STREAM_BLOCKS)];
+			let chunk_start = index * BLOCK_SIZE;
+			read_at(file, file_offset + chunk_start as u64, chunk.as_bytes_mut(), self.read_buf)?;
+			decryptor.update(chunk);
+
+			// Pass on the part of the chunk in the range
+			let start = usize::max(range.start, chunk_start);
+			let end = usize::min(range.end, chunk_start + chunk.len() * BLOCK_SIZE);
+			if start < end {
+				f(start - range.start, &chunk.as_bytes()[start - chunk_start..end - chunk_start]);
+			}
+			index += chunk.len();
+		}
+
+		if !decryptor.finish() {
+			return self.mac_failure(desc, Err(io::ErrorKind::InvalidData.into()));
+		}
+
+		span.finish((len * BLOCK_SIZE) as u64);
+		Ok(())
+	}
+
+	// Names the file in MAC failures
+	fn mac_failure(&self, desc: &Descriptor, result: io::Result<()>) -> io::Result<()> {
+		match result {
+			Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+				match self.directory.path_of(desc) {
+					Some(path) => Err(io::Error::new(io::ErrorKind::InvalidData, format!("MAC failure in {}", String::from_utf8_lossy(&path)))),
+					None => Err(err),
+				}
+			},
+			result => result,
+		}
+	}
 }
@@ -1,3 +1,4 @@
+use std::io;
 use crate::*;
 
 /// Defer a closure on drop.
@@ -9,7 +10,7 @@ impl<F: FnMut()> Drop for Defer<F> {
 }
 macro_rules! defer {
 	($($body:tt)*) => {
-		let __deferred = Defer(|| { $($body)* });
+		let __deferred = $crate::file_io::tests::Defer(|| { $($body)* });
 	};
 }
 macro_rules! temp_file {
@@ -49,3 +50,1327 @@ fn test_corrupt1() {
 	let example_text = String::from_utf8_lossy(&example_text);
 	assert_eq!(example_text, "abcdefghijklmnopqrstuvwxyzabcdefghijklmnopqrstuvwxyz");
 }
+
+#[test]
+fn test_gc_inplace() {
+	let ref key = Key::default();
+
+	temp_file!("gc_inplace");
+
+	FileEditor::create_empty("gc_inplace", key).unwrap();
+
+	// Step 1: Add some files and remove one of them
+	{
+		let mut edit = FileEditor::open("gc_inplace", key).unwrap();
+		edit.create_file(b"a", &ALPHABET[..20], key).unwrap();
+		edit.create_file(b"b", &ALPHABET[..40], key).unwrap();
+		edit.create_file(b"c", ALPHABET, key).unwrap();
+		let c = *edit.find_file(b"c").unwrap();
+		edit.create_link(b"d", &c);
+		edit.remove(b"b").unwrap();
+		edit.finish(key).unwrap();
+	}
+
+	// Step 2: Collect the garbage
+	{
+		let mut edit = FileEditor::open("gc_inplace", key).unwrap();
+		assert!(edit.garbage(edit.high_mark()) > 0);
+		edit.gc().unwrap();
+		assert_eq!(edit.garbage(edit.high_mark()), 0);
+		edit.finish(key).unwrap();
+	}
+
+	// Step 3: Check the remaining files
+	let reader = FileReader::open("gc_inplace", key).unwrap();
+	assert_eq!(reader.high_mark(), Header::BLOCKS_LEN as u32 + 2 + 4);
+	for &(path, data) in &[(&b"a"[..], &ALPHABET[..20]), (b"c", ALPHABET), (b"d", ALPHABET)] {
+		let desc = reader.find_file(path).unwrap();
+		assert_eq!(reader.read_data(desc, key).unwrap(), data);
+	}
+}
+
+#[test]
+fn test_gc_chunked() {
+	let ref key = Key::default();
+
+	temp_file!("gc_chunked");
+
+	// The large file is copied in several chunks overlapping its old blocks
+	let data: Vec<u8> = (0..3 * 1024 * 1024 + 100).map(|i| (i * 7 + i / 4096) as u8).collect();
+	let mut edit = FileEditor::create_new("gc_chunked", key).unwrap();
+	edit.create_file(b"a", &ALPHABET[..40], key).unwrap();
+	edit.create_file(b"big", &data, key).unwrap();
+	edit.remove(b"a").unwrap();
+	edit.gc().unwrap();
+	assert_eq!(edit.read_data(edit.find_file(b"big").unwrap(), key).unwrap(), data);
+	edit.finish(key).unwrap();
+
+	let reader = FileReader::open("gc_chunked", key).unwrap();
+	assert_eq!(reader.read_data(reader.find_file(b"big").unwrap(), key).unwrap(), data);
+}
+
+#[test]
+fn test_path_of() {
+	let ref key = Key::default();
+
+	temp_file!("path_of");
+
+	{
+		let mut edit = FileEditor::create_new("path_of", key).unwrap();
+		edit.create_file(b"textures/ui/icon.png", ALPHABET, key).unwrap();
+		edit.create_file(b"textures/bg.png", ALPHABET, key).unwrap();
+		edit.finish(key).unwrap();
+	}
+
+	let mut reader = FileReader::open("path_of", key).unwrap();
+	for &cached in &[false, true] {
+		if cached {
+			reader.cache_paths();
+		}
+		for &path in &[&b"textures"[..], b"textures/ui", b"textures/ui/icon.png", b"textures/bg.png"] {
+			let desc = reader.find_desc(path).unwrap();
+			assert_eq!(reader.path_of(desc).as_deref(), Some(path));
+		}
+	}
+
+	// Reading with the wrong key reports the path of the file
+	let desc = reader.find_file(b"textures/ui/icon.png").unwrap();
+	let err = reader.read_data(desc, &[1, 2]).unwrap_err();
+	assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+	assert_eq!(err.to_string(), "MAC failure in textures/ui/icon.png");
+}
+
+#[test]
+fn test_block_size() {
+	let ref key = Key::default();
+
+	temp_file!("block_size");
+
+	{
+		let mut edit = FileEditor::create_new("block_size", key).unwrap();
+		assert!(!edit.set_block_size(32));
+		assert!(edit.set_block_size(256));
+		edit.create_file(b"a", &ALPHABET[..20], key).unwrap();
+		edit.create_file(b"b", ALPHABET, key).unwrap();
+		assert!(!edit.set_block_size(16));
+		edit.remove(b"a").unwrap();
+		edit.finish(key).unwrap();
+	}
+
+	{
+		let mut edit = FileEditor::open("block_size", key).unwrap();
+		assert_eq!(edit.block_size(), 256);
+		assert!(edit.garbage(edit.high_mark()) > 0);
+		edit.gc().unwrap();
+		edit.finish(key).unwrap();
+	}
+
+	let reader = FileReader::open("block_size", key).unwrap();
+	assert_eq!(reader.info().block_size, 256);
+	assert_eq!(reader.high_mark(), 2);
+	assert!(reader.fsck(reader.high_mark(), &mut String::new()));
+	let desc = reader.find_file(b"b").unwrap();
+	assert_eq!(reader.read_data(desc, key).unwrap(), ALPHABET);
+}
+
+#[test]
+fn test_align_data() {
+	let ref key = Key::default();
+
+	temp_file!("align_data");
+
+	let mut edit = FileEditor::create_new("align_data", key).unwrap();
+	assert!(!edit.align_data(3000));
+	assert!(edit.align_data(4096));
+	for &path in &[&b"a"[..], b"b", b"c"] {
+		let desc = edit.create_file(path, ALPHABET, key).unwrap();
+		assert_eq!(desc.section.offset as u64 * 16 % 4096, 0);
+	}
+	edit.remove(b"b").unwrap();
+
+	// Only the removed file and its padding is garbage
+	assert_eq!(edit.garbage(edit.high_mark()), 4096 / 16);
+	edit.gc().unwrap();
+	assert_eq!(edit.garbage(edit.high_mark()), 0);
+	for &path in &[&b"a"[..], b"c"] {
+		let desc = edit.find_file(path).unwrap();
+		assert_eq!(desc.section.offset as u64 * 16 % 4096, 0);
+		assert_eq!(edit.read_data(desc, key).unwrap(), ALPHABET);
+	}
+	edit.finish(key).unwrap();
+}
+
+#[test]
+fn test_reserve() {
+	let ref key = Key::default();
+
+	temp_file!("reserve");
+
+	let mut edit = FileEditor::create_new("reserve", key).unwrap();
+	edit.reserve(100).unwrap();
+	assert_eq!(std::fs::metadata("reserve").unwrap().len(), (edit.high_mark() as u64 + 100) * 16);
+	edit.create_file(b"a", ALPHABET, key).unwrap();
+	edit.finish(key).unwrap();
+
+	// The unused reservation is trimmed
+	let reader = FileReader::open("reserve", key).unwrap();
+	let dir_end = (reader.high_mark() as u64 + Descriptor::BLOCKS_LEN as u64) * 16;
+	assert_eq!(std::fs::metadata("reserve").unwrap().len(), dir_end);
+	let desc = reader.find_file(b"a").unwrap();
+	assert_eq!(reader.read_data(desc, key).unwrap(), ALPHABET);
+}
+
+#[test]
+fn test_edit_existing() {
+	let ref key = Key::default();
+
+	temp_file!("edit_existing");
+
+	let mut edit = FileEditor::create_new("edit_existing", key).unwrap();
+	edit.set_revisions(1);
+	edit.create_file(b"dir/a", b"old", key).unwrap();
+	assert!(edit.edit_existing(b"dir").is_none());
+	assert!(edit.edit_existing(b"dir/b").is_none());
+	assert!(edit.find(b"dir/b").is_none());
+
+	let mut edit_file = edit.edit_existing(b"dir/a").unwrap();
+	edit_file.set_content(1, 3).allocate_data().unwrap().write_data(b"new", key).unwrap();
+	edit.finish(key).unwrap();
+
+	// The previous contents are retained as a revision
+	let reader = FileReader::open("edit_existing", key).unwrap();
+	let desc = reader.find_file(b"dir/a").unwrap();
+	assert_eq!(reader.read_data(desc, key).unwrap(), b"new");
+	assert_eq!(reader.revisions(b"dir/a").len(), 1);
+}
+
+#[test]
+fn test_name_policy() {
+	let ref key = Key::default();
+
+	temp_file!("name_policy");
+
+	let policy = NamePolicy { nfc: true, case_fold: false, stripped: false };
+	let mut edit = FileEditor::create_new("name_policy", key).unwrap();
+	assert!(edit.set_name_policy(policy));
+	edit.create_file("Cafe\u{301}".as_bytes(), ALPHABET, key).unwrap();
+	assert!(!edit.set_name_policy(NamePolicy::default()));
+	edit.finish(key).unwrap();
+
+	let reader = FileReader::open("name_policy", key).unwrap();
+	assert_eq!(reader.info().flags(), InfoHeader::FLAG_NFC);
+	assert_eq!(reader.info().block_size(), 16);
+	assert_eq!(reader.name_policy(), policy);
+	assert!(reader.find_file("Caf\u{e9}".as_bytes()).is_some());
+	assert!(reader.find_file(b"cafe").is_none());
+
+	// The policy is kept when editing again
+	let mut edit = FileEditor::open("name_policy", key).unwrap();
+	edit.create_file("Caf\u{e9}".as_bytes(), b"new", key).unwrap();
+	assert_eq!(edit.file_count(), 1);
+}
+
+#[test]
+fn test_reopen_rw() {
+	let ref key = Key::default();
+
+	temp_file!("reopen_rw");
+
+	let mut edit = FileEditor::create_new("reopen_rw", key).unwrap();
+	edit.create_file(b"a", ALPHABET, key).unwrap();
+	edit.create_file(b"secret/b", ALPHABET, key).unwrap();
+	edit.finish(key).unwrap();
+
+	// Readers hiding files cannot be edited
+	let mut options = ReaderOptions::default();
+	options.hide(b"secret");
+	let reader = FileReader::open_with_options("reopen_rw", key, &options).unwrap();
+	assert_eq!(FileEditor::reopen_rw(reader, "reopen_rw").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+	let reader = FileReader::open("reopen_rw", key).unwrap();
+	let stale = FileReader::open("reopen_rw", key).unwrap();
+	let mut edit = FileEditor::reopen_rw(reader, "reopen_rw").unwrap();
+	edit.create_file(b"c", b"new", key).unwrap();
+	edit.finish(key).unwrap();
+
+	// The PAK file changed since the stale reader was opened
+	assert_eq!(FileEditor::reopen_rw(stale, "reopen_rw").unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+	let reader = FileReader::open("reopen_rw", key).unwrap();
+	assert_eq!(reader.read_data(reader.find_file(b"a").unwrap(), key).unwrap(), ALPHABET);
+	assert_eq!(reader.read_data(reader.find_file(b"c").unwrap(), key).unwrap(), b"new");
+}
+
+#[test]
+fn test_abort() {
+	let ref key = Key::default();
+
+	temp_file!("abort");
+
+	let mut edit = FileEditor::create_new("abort", key).unwrap();
+	edit.create_file(b"a", ALPHABET, key).unwrap();
+	edit.finish(key).unwrap();
+	let original = std::fs::read("abort").unwrap();
+
+	// Finishing without changes leaves the PAK file alone
+	let mut edit = FileEditor::open("abort", key).unwrap();
+	assert!(!edit.is_dirty());
+	assert!(edit.find_file(b"a").is_some());
+	edit.reserve(16).unwrap();
+	assert!(!edit.is_dirty());
+	edit.finish(key).unwrap();
+	assert_eq!(std::fs::read("abort").unwrap(), original);
+
+	// Aborting discards the changes and trims the appended data
+	let mut edit = FileEditor::open("abort", key).unwrap();
+	edit.create_file(b"b", ALPHABET, key).unwrap();
+	assert!(edit.is_dirty());
+	edit.abort().unwrap();
+	assert_eq!(std::fs::read("abort").unwrap(), original);
+
+	let mut edit = FileEditor::open("abort", key).unwrap();
+	edit.remove(b"a");
+	assert!(edit.is_dirty());
+	edit.finish(key).unwrap();
+	let reader = FileReader::open("abort", key).unwrap();
+	assert!(reader.find_file(b"a").is_none());
+}
+
+#[test]
+fn test_high_mark() {
+	let ref key = Key::default();
+
+	temp_file!("high_mark");
+
+	let mut edit = FileEditor::create_new("high_mark", key).unwrap();
+	for name in ["a", "b", "c/d", "c/e"] {
+		edit.create_file(name.as_bytes(), ALPHABET, key).unwrap();
+	}
+	edit.finish(key).unwrap();
+	let original = std::fs::read("high_mark").unwrap();
+
+	// The high mark starts right after the directory at the end of the PAK file
+	let mut edit = FileEditor::open("high_mark", key).unwrap();
+	assert_eq!(edit.high_mark() as usize * BLOCK_SIZE, original.len());
+
+	// New data written before finishing does not overwrite the existing directory
+	edit.create_file(b"f", ALPHABET, key).unwrap();
+	drop(edit);
+	let reader = FileReader::open("high_mark", key).unwrap();
+	assert_eq!(reader.read_data(reader.find_file(b"c/e").unwrap(), key).unwrap(), ALPHABET);
+	assert!(reader.find_file(b"f").is_none());
+}
+
+#[test]
+#[should_panic(expected = "unsaved changes")]
+fn test_drop_check() {
+	let ref key = Key::default();
+
+	temp_file!("drop_check");
+
+	let mut edit = FileEditor::create_new("drop_check", key).unwrap();
+	edit.set_drop_check(true);
+	edit.finish(key).unwrap();
+
+	let mut edit = FileEditor::open("drop_check", key).unwrap();
+	edit.set_drop_check(true);
+	edit.create_file(b"a", ALPHABET, key).unwrap();
+	drop(edit);
+}
+
+#[test]
+fn test_read_data_with() {
+	let ref key = Key::default();
+
+	temp_file!("read_data_with");
+
+	let mut edit = FileEditor::create_new("read_data_with", key).unwrap();
+	edit.create_file(b"big", &[7; 3000], key).unwrap();
+	edit.create_file(b"small", ALPHABET, key).unwrap();
+	edit.finish(key).unwrap();
+
+	let reader = FileReader::open("read_data_with", key).unwrap();
+	let mut scratch = Vec::new();
+	assert_eq!(reader.read_data_with(reader.find_file(b"big").unwrap(), key, &mut scratch).unwrap(), &[7; 3000][..]);
+	let capacity = scratch.capacity();
+	assert_eq!(reader.read_data_with(reader.find_file(b"small").unwrap(), key, &mut scratch).unwrap(), ALPHABET);
+	// The allocation is reused
+	assert_eq!(scratch.capacity(), capacity);
+
+	let desc = reader.find_file(b"small").unwrap();
+	reader.read_section_into(&desc.section, key, &mut scratch).unwrap();
+	assert_eq!(scratch, reader.read_section(&desc.section, key).unwrap());
+}
+
+#[test]
+fn test_read_into_uninit() {
+	use std::mem::MaybeUninit;
+
+	let ref key = Key::default();
+
+	temp_file!("read_into_uninit");
+
+	// Spans several chunks of the streaming reads
+	let data: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+	let mut edit = FileEditor::create_new("read_into_uninit", key).unwrap();
+	edit.create_file(b"big", &data, key).unwrap();
+	edit.finish(key).unwrap();
+
+	let reader = FileReader::open("read_into_uninit", key).unwrap();
+	let desc = reader.find_file(b"big").unwrap();
+
+	let mut buf = vec![MaybeUninit::uninit(); data.len() + 10];
+	assert_eq!(reader.read_into_uninit(desc, key, &mut buf).unwrap(), &data[..]);
+	assert_eq!(reader.read_into_uninit(desc, key, &mut buf[..100]).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+	let mut dest = [0; 1000];
+	reader.read_into(desc, key, 65000, &mut dest).unwrap();
+	assert_eq!(&dest[..], &data[65000..66000]);
+	assert_eq!(reader.read_into(desc, key, data.len(), &mut dest).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+	// The MAC is still checked
+	let wrong_key = &[1, 2];
+	assert_eq!(reader.read_into(desc, wrong_key, 0, &mut dest).unwrap_err().kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn test_read_into_tampered() {
+	use std::{io::prelude::*, mem::MaybeUninit};
+
+	let ref key = Key::default();
+
+	temp_file!("read_into_tampered");
+
+	let mut edit = FileEditor::create_new("read_into_tampered", key).unwrap();
+	edit.create_file(b"a", ALPHABET, key).unwrap();
+	edit.finish(key).unwrap();
+
+	// Flip a byte in the ciphertext of the file
+	let desc = *FileReader::open("read_into_tampered", key).unwrap().find_file(b"a").unwrap();
+	{
+		let mut file = std::fs::OpenOptions::new().read(true).write(true).open("read_into_tampered").unwrap();
+		let offset = desc.section.offset as u64 * BLOCK_SIZE as u64 + 3;
+		let mut byte = [0u8];
+		file.seek(io::SeekFrom::Start(offset)).unwrap();
+		file.read_exact(&mut byte).unwrap();
+		file.seek(io::SeekFrom::Start(offset)).unwrap();
+		file.write_all(&[byte[0] ^ 1]).unwrap();
+	}
+
+	// The tampered plaintext streamed into dest is zeroed
+	let reader = FileReader::open("read_into_tampered", key).unwrap();
+	let mut dest = [0xaa; 26];
+	assert_eq!(reader.read_into(&desc, key, 0, &mut dest).unwrap_err().kind(), io::ErrorKind::InvalidData);
+	assert_eq!(dest, [0; 26]);
+
+	let mut buf = [MaybeUninit::new(0xaa); 52];
+	assert_eq!(reader.read_into_uninit(&desc, key, &mut buf).unwrap_err().kind(), io::ErrorKind::InvalidData);
+	assert!(buf.iter().all(|byte| unsafe { byte.assume_init() } == 0));
+}
+
+#[test]
+fn test_arc_reader() {
+	let ref key = Key::default();
+
+	temp_file!("arc_reader");
+
+	let mut edit = FileEditor::create_new("arc_reader", key).unwrap();
+	for i in 0..8u8 {
+		edit.create_file(&[b'a' + i], &[i; 5000], key).unwrap();
+	}
+	edit.finish(key).unwrap();
+
+	// Concurrent reads do not race on the file cursor
+	let reader = ArcReader::open("arc_reader", key).unwrap();
+	let threads: Vec<_> = (0..8u8).map(|i| {
+		let reader = reader.clone();
+		let key = *key;
+		std::thread::spawn(move || {
+			let desc = reader.find_file(&[b'a' + i]).unwrap();
+			for _ in 0..50 {
+				assert_eq!(reader.read_data(desc, &key).unwrap(), &[i; 5000][..]);
+			}
+		})
+	}).collect();
+	for thread in threads {
+		thread.join().unwrap();
+	}
+}
+
+#[test]
+fn test_extract_all() {
+	let ref key = Key::default();
+
+	temp_file!("extract_all");
+	let out = std::env::temp_dir().join("paks_extract_all");
+	let _ = std::fs::remove_dir_all(&out);
+	defer!(let _ = std::fs::remove_dir_all(&out););
+
+	let mut edit = FileEditor::create_new("extract_all", key).unwrap();
+	for i in 0..20u8 {
+		edit.create_file(format!("dir{}/file{}", i % 3, i).as_bytes(), &[i; 100], key).unwrap();
+	}
+	edit.create_file(b"z/../../escape", b"", key).unwrap();
+	edit.create_file(b"a/../escape", b"", key).unwrap();
+	edit.finish(key).unwrap();
+
+	let reader = FileReader::open("extract_all", key).unwrap();
+	assert_eq!(reader.extract_all(b"dir1", &out, 4, key).unwrap(), 7);
+	assert_eq!(std::fs::read(out.join("dir1/file4")).unwrap(), [4; 100]);
+
+	// The files are extracted to their full path under the destination directory
+	let mut extracted: Vec<_> = std::fs::read_dir(&out).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+	assert_eq!(extracted, ["dir1"]);
+	extracted = std::fs::read_dir(out.join("dir1")).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+	extracted.sort();
+	assert_eq!(extracted, ["file1", "file10", "file13", "file16", "file19", "file4", "file7"]);
+
+	// Errors are collected in directory order
+	let errors = reader.extract_all(b"", &out, 4, key).unwrap_err();
+	let paths: Vec<_> = errors.iter().map(|err| err.path.as_slice()).collect();
+	assert_eq!(paths, [&b"z/../../escape"[..], b"a/../escape"]);
+	assert!(errors.iter().all(|err| err.error.kind() == io::ErrorKind::InvalidData));
+	assert_eq!(std::fs::read(out.join("dir2/file17")).unwrap(), [17; 100]);
+
+	assert_eq!(reader.extract_all(b"missing", &out, 1, key).unwrap_err()[0].error.kind(), io::ErrorKind::NotFound);
+}
+
+#[test]
+fn test_metadata_only() {
+	let ref key = Key::default();
+
+	temp_file!("metadata_only");
+
+	{
+		let mut edit = FileEditor::create_new("metadata_only", key).unwrap();
+		edit.create_file(b"dir/a", ALPHABET, key).unwrap();
+		edit.finish(key).unwrap();
+	}
+
+	let reader = FileReader::open_metadata_only("metadata_only", key).unwrap();
+	assert!(reader.is_metadata_only());
+	assert!(reader.fsck(reader.high_mark(), &mut String::new()));
+	let desc = reader.find_file(b"dir/a").unwrap();
+	assert_eq!(desc.content_size, ALPHABET.len() as u32);
+	assert_eq!(reader.read_data(desc, key).unwrap_err().kind(), io::ErrorKind::Other);
+}
+
+#[test]
+fn test_embedded() {
+	let ref key = Key::default();
+
+	temp_file!("embedded");
+
+	let mut edit = MemoryEditor::new();
+	edit.create_file(b"dir/a", ALPHABET, key);
+	let (blocks, _) = edit.finish(key);
+
+	// Append the PAK file to some unaligned host data
+	let host = b"not a PAK file";
+	let mut bytes = host.to_vec();
+	bytes.extend_from_slice(as_bytes(&blocks));
+	write_locator(&mut bytes, host.len() as u64).unwrap();
+	std::fs::write("embedded", &bytes).unwrap();
+
+	let offset = find_embedded("embedded").unwrap().unwrap();
+	assert_eq!(offset, host.len() as u64);
+	assert!(FileReader::open("embedded", key).is_err());
+
+	let reader = FileReader::open_at_offset("embedded", offset, key).unwrap();
+	assert_eq!(reader.offset(), offset);
+	let desc = reader.find_file(b"dir/a").unwrap();
+	assert_eq!(reader.read_data(desc, key).unwrap(), ALPHABET);
+
+	let mut raw = Vec::new();
+	reader.copy_section_raw(&desc.section, &mut raw).unwrap();
+	assert_eq!(raw, as_bytes(&blocks)[desc.section.offset as usize * 16..][..raw.len()]);
+
+	// Files without a locator
+	std::fs::write("embedded", host).unwrap();
+	assert_eq!(find_embedded("embedded").unwrap(), None);
+}
+
+#[test]
+fn test_write_buffer() {
+	let ref key = Key::default();
+
+	temp_file!("write_buffer");
+
+	let mut edit = FileEditor::create_new("write_buffer", key).unwrap();
+	assert_eq!(edit.write_buffer(), WRITE_BUFFER_SIZE);
+	edit.set_write_buffer(100).unwrap();
+	for i in 0..20 {
+		let path = format!("dir/{}", i);
+		edit.create_file(path.as_bytes(), &ALPHABET[..i * 2], key).unwrap();
+		// Reading flushes the buffered data
+		let desc = *edit.find_file(path.as_bytes()).unwrap();
+		assert_eq!(edit.read_data(&desc, key).unwrap(), &ALPHABET[..i * 2]);
+	}
+	edit.set_write_buffer(0).unwrap();
+	edit.create_file(b"unbuffered", ALPHABET, key).unwrap();
+	edit.finish(key).unwrap();
+
+	let reader = FileReader::open("write_buffer", key).unwrap();
+	for i in 0..20 {
+		let desc = reader.find_file(format!("dir/{}", i).as_bytes()).unwrap();
+		assert_eq!(reader.read_data(desc, key).unwrap(), &ALPHABET[..i * 2]);
+	}
+	let desc = reader.find_file(b"unbuffered").unwrap();
+	assert_eq!(reader.read_data(desc, key).unwrap(), ALPHABET);
+}
+
+#[test]
+fn test_io_options() {
+	let ref key = Key::default();
+
+	temp_file!("io_options");
+
+	let io = IoOptions { read_buf: 32, write_buf: 0 };
+	{
+		let mut edit = FileEditor::create_new_with("io_options", key, &io).unwrap();
+		assert_eq!(edit.write_buffer(), 0);
+		edit.create_file(b"a", ALPHABET, key).unwrap();
+		edit.create_file(b"b", &ALPHABET[..20], key).unwrap();
+		edit.finish(key).unwrap();
+	}
+	{
+		let mut edit = FileEditor::open_with("io_options", key, &io).unwrap();
+		edit.remove(b"a").unwrap();
+		edit.gc().unwrap();
+		edit.finish(key).unwrap();
+	}
+
+	let reader = FileReader::open_with("io_options", key, &io).unwrap();
+	let desc = reader.find_file(b"b").unwrap();
+	assert_eq!(reader.read_data(desc, key).unwrap(), &ALPHABET[..20]);
+	let mut raw = Vec::new();
+	reader.copy_section_raw(&desc.section, &mut raw).unwrap();
+	assert_eq!(raw.len(), desc.section.size as usize * 16);
+}
+
+#[cfg(feature = "memmap2")]
+#[test]
+fn test_mmap_editor() {
+	let ref key = Key::default();
+
+	temp_file!("mmap_editor");
+
+	FileEditor::create_empty("mmap_editor", key).unwrap();
+	{
+		let mut edit = MmapEditor::open("mmap_editor", key).unwrap();
+		edit.create_file(b"a", ALPHABET, key).unwrap();
+		let big = vec![0x5Au8; 100000];
+		edit.create_file(b"dir/big", &big, key).unwrap();
+		let desc = *edit.find_file(b"a").unwrap();
+		assert_eq!(edit.read_data(&desc, key).unwrap(), ALPHABET);
+		edit.finish(key).unwrap();
+	}
+
+	let reader = FileReader::open("mmap_editor", key).unwrap();
+	assert!(reader.fsck(reader.high_mark(), &mut String::new()));
+	let desc = reader.find_file(b"a").unwrap();
+	assert_eq!(reader.read_data(desc, key).unwrap(), ALPHABET);
+	let desc = reader.find_file(b"dir/big").unwrap();
+	assert_eq!(reader.read_data(desc, key).unwrap(), vec![0x5Au8; 100000]);
+	let dir_end = (reader.high_mark() as u64 + 3 * Descriptor::BLOCKS_LEN as u64) * 16;
+	assert_eq!(std::fs::metadata("mmap_editor").unwrap().len(), dir_end);
+}
+
+#[test]
+fn test_chroot() {
+	let ref key = Key::default();
+
+	temp_file!("chroot");
+
+	let mut edit = FileEditor::create_new("chroot", key).unwrap();
+	edit.create_file(b"engine/core", ALPHABET, key).unwrap();
+	assert!(edit.chroot(b"engine/core").is_none());
+	{
+		let mut sub = edit.chroot(b"audio").unwrap();
+		assert_eq!(sub.root(), b"audio");
+		sub.create_file(b"sfx/boom", &ALPHABET[..10], key).unwrap();
+		assert_eq!(sub.create_file(b"", ALPHABET, key).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+		assert!(sub.find_file(b"engine/core").is_none());
+		assert!(!sub.move_file(b"sfx/boom", b""));
+		assert!(sub.remove(b"").is_none());
+		assert_eq!(sub.get_children(b"").unwrap().len(), 2);
+		let desc = *sub.find_file(b"sfx/boom").unwrap();
+		assert_eq!(sub.read_data(&desc, key).unwrap(), &ALPHABET[..10]);
+		assert!(sub.create_link(b"sfx/bang", &desc).is_some());
+	}
+
+	// Descriptors from outside the scope are refused
+	let core = *edit.find_file(b"engine/core").unwrap();
+	{
+		let mut sub = edit.chroot(b"audio").unwrap();
+		assert_eq!(sub.read_data(&core, key).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+		assert!(sub.create_link(b"core", &core).is_none());
+	}
+	assert!(edit.find_file(b"audio/sfx/boom").is_some());
+
+	// Scoping an existing directory leaves its children alone
+	assert_eq!(edit.chroot(b"audio").unwrap().get_children(b"").unwrap().len(), 3);
+	assert!(edit.find_file(b"audio/sfx/boom").is_some());
+	edit.finish(key).unwrap();
+}
+
+#[test]
+fn test_pack_files() {
+	let ref key = Key::default();
+
+	temp_file!("pack_files");
+
+	let mut edit = MemoryEditor::new();
+	edit.create_file(b"a", &ALPHABET[..3], key).unwrap();
+	edit.create_file(b"b", &ALPHABET[3..20], key).unwrap();
+	edit.create_file(b"large", ALPHABET, key).unwrap();
+	let b = *edit.find_file(b"b").unwrap();
+	edit.create_link(b"dir/c", &b);
+	let high_mark = edit.high_mark();
+
+	assert_eq!(edit.pack_files(20, key), 3);
+	assert_eq!(edit.pack_files(20, key), 0);
+	edit.gc();
+	assert!(edit.high_mark() < high_mark);
+	assert!(!edit.find_file(b"large").unwrap().is_packed());
+	let (blocks, _) = edit.finish(key);
+	std::fs::write("pack_files", as_bytes(&blocks)).unwrap();
+
+	// Packed files are read from their shared section
+	let reader = FileReader::open("pack_files", key).unwrap();
+	assert!(reader.fsck(reader.high_mark(), &mut String::new()));
+	for &(path, data) in &[(&b"a"[..], &ALPHABET[..3]), (b"b", &ALPHABET[3..20]), (b"dir/c", &ALPHABET[3..20]), (b"large", ALPHABET)] {
+		let desc = reader.find_file(path).unwrap();
+		assert_eq!(reader.read_data(desc, key).unwrap(), data);
+	}
+	let mut dest = [0; 4];
+	reader.read_into(reader.find_file(b"b").unwrap(), key, 2, &mut dest).unwrap();
+	assert_eq!(dest, ALPHABET[5..9]);
+
+	// Overwriting a packed file gives it its own section
+	let mut edit = FileEditor::open("pack_files", key).unwrap();
+	edit.create_file(b"a", b"new", key).unwrap();
+	let a = *edit.find_file(b"a").unwrap();
+	assert!(!a.is_packed());
+	assert_eq!(edit.read_data(&a, key).unwrap(), b"new");
+	assert_eq!(edit.read_data(edit.find_file(b"b").unwrap(), key).unwrap(), &ALPHABET[3..20]);
+}
+
+#[test]
+fn test_shred() {
+	let ref key = Key::default();
+
+	temp_file!("shred");
+
+	let mut edit = MemoryEditor::new();
+	edit.create_file(b"secret", &ALPHABET[..40], key).unwrap();
+	edit.create_file(b"a", &ALPHABET[..3], key).unwrap();
+	edit.create_file(b"b", &ALPHABET[3..20], key).unwrap();
+	edit.create_file(b"c", &ALPHABET[20..30], key).unwrap();
+	let secret = *edit.find_file(b"secret").unwrap();
+	edit.create_link(b"link", &secret);
+	assert_eq!(edit.pack_files(20, key), 3);
+	let (blocks, _) = edit.finish(key);
+	std::fs::write("shred", as_bytes(&blocks)).unwrap();
+
+	let mut edit = FileEditor::open("shred", key).unwrap();
+	let packed = edit.find_file(b"b").unwrap().section;
+	assert_eq!(edit.shred(b"missing", key).unwrap_err().kind(), io::ErrorKind::NotFound);
+
+	// Links are shredded along with the file
+	edit.shred(b"secret", key).unwrap();
+	assert_eq!(edit.find_file(b"secret").unwrap().content_size, 0);
+	assert_eq!(edit.read_data(edit.find_file(b"link").unwrap(), key).unwrap(), b"");
+
+	// Other files sharing the packed section remain readable
+	let b = edit.remove_shred(b"b", key).unwrap();
+	assert_eq!(b.content_size, 0);
+	assert!(edit.find_file(b"b").is_none());
+	assert_eq!(edit.read_data(edit.find_file(b"a").unwrap(), key).unwrap(), &ALPHABET[..3]);
+	assert_eq!(edit.read_data(edit.find_file(b"c").unwrap(), key).unwrap(), &ALPHABET[20..30]);
+	assert_eq!(edit.find_file(b"c").unwrap().section.offset, packed.offset);
+	assert_ne!(edit.find_file(b"c").unwrap().section.nonce, packed.nonce);
+	edit.finish(key).unwrap();
+
+	// The plaintext is gone from the PAK file
+	let reader = FileReader::open("shred", key).unwrap();
+	assert!(reader.fsck(reader.high_mark(), &mut String::new()));
+	assert_eq!(reader.read_data(reader.find_file(b"c").unwrap(), key).unwrap(), &ALPHABET[20..30]);
+	let mut data = vec![Block::default(); packed.size as usize];
+	assert!(!crypt::decrypt_section(&mut data, &packed, key));
+	let data = std::fs::read("shred").unwrap();
+	let range = secret.section.range_usize(BLOCK_SIZE as u32);
+	let mut blocks = vec![Block::default(); range.len()];
+	blocks.as_bytes_mut().copy_from_slice(&data[range.start * BLOCK_SIZE..range.end * BLOCK_SIZE]);
+	assert!(!crypt::decrypt_section(&mut blocks, &secret.section, key));
+}
+
+#[test]
+fn test_insert_validator() {
+	let ref key = Key::default();
+
+	temp_file!("insert_validator");
+
+	let mut edit = FileEditor::create_new("insert_validator", key).unwrap();
+	edit.create_file(b"a.txt", ALPHABET, key).unwrap();
+	edit.set_insert_validator(Some(|path, _| if path.ends_with(b".txt") { Ok(()) } else { Err(String::from("not a text file")) }));
+	let err = edit.create_file(b"b.exe", ALPHABET, key).unwrap_err();
+	assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+	assert_eq!(err.to_string(), "not a text file");
+	edit.create_file(b"c.txt", ALPHABET, key).unwrap();
+	assert!(edit.find_file(b"b.exe").is_none());
+
+	let violations = edit.take_violations();
+	assert_eq!(violations, [Violation { path: b"b.exe".to_vec(), message: String::from("not a text file") }]);
+	assert!(edit.violations().is_empty());
+	edit.finish(key).unwrap();
+}
+
+#[test]
+fn test_reader_options() {
+	let ref key = Key::default();
+
+	temp_file!("reader_options");
+
+	let mut edit = FileEditor::create_new("reader_options", key).unwrap();
+	for &path in &[&b"editor/gizmo.png"[..], b"textures/wall.png", b"textures/wall.psd", b"textures/src/wall.psd", b"sounds/hit.wav"] {
+		edit.create_file(path, ALPHABET, key).unwrap();
+	}
+	edit.finish(key).unwrap();
+
+	let mut options = ReaderOptions::default();
+	options.hide(b"*.psd").hide(b"editor");
+	let reader = FileReader::open_with_options("reader_options", key, &options).unwrap();
+	assert!(reader.find(b"editor").is_none());
+	assert!(reader.find(b"textures/src").is_none());
+	assert!(reader.find_file(b"textures/wall.psd").is_none());
+	assert_eq!(reader.read_data(reader.find_file(b"textures/wall.png").unwrap(), key).unwrap(), ALPHABET);
+	assert!(reader.find_file(b"sounds/hit.wav").is_some());
+
+	options.only(b"sounds");
+	let reader = FileReader::open_with_options("reader_options", key, &options).unwrap();
+	let files: Vec<_> = reader.query(&Filter::Files).map(|(path, _)| path).collect();
+	assert_eq!(files, [b"sounds/hit.wav"]);
+
+	// Sections larger than the limit are refused
+	let options = ReaderOptions { max_section_bytes: Some(64), ..ReaderOptions::default() };
+	let mut reader = FileReader::open_with_options("reader_options", key, &options).unwrap();
+	let mut desc = *reader.find_file(b"sounds/hit.wav").unwrap();
+	assert_eq!(reader.read_data(&desc, key).unwrap(), ALPHABET);
+	desc.section.size = 0x10000000;
+	assert_eq!(reader.read_data(&desc, key).unwrap_err().kind(), io::ErrorKind::OutOfMemory);
+	assert_eq!(reader.read_section(&desc.section, key).unwrap_err().kind(), io::ErrorKind::OutOfMemory);
+	// Sections past the end of the file are refused before allocating them
+	reader.max_section_bytes = None;
+	assert_eq!(reader.read_section(&desc.section, key).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+
+	// Directories larger than the limit are refused before reading them
+	let options = ReaderOptions { limits: Limits { max_directory_entries: Some(5), ..Limits::default() }, ..ReaderOptions::default() };
+	assert_eq!(FileReader::open_with_options("reader_options", key, &options).err().unwrap().kind(), io::ErrorKind::OutOfMemory);
+	let options = ReaderOptions { limits: Limits { max_directory_entries: Some(9), max_blocks: Some(1000) }, ..ReaderOptions::default() };
+	assert!(FileReader::open_with_options("reader_options", key, &options).is_ok());
+}
+
+#[test]
+fn test_preview() {
+	let ref key = Key::default();
+
+	temp_file!("preview");
+
+	let mut edit = FileEditor::create_new("preview", key).unwrap();
+	edit.create_file(b"garbage", ALPHABET, key).unwrap();
+	edit.create_file(b"a", ALPHABET, key).unwrap();
+	edit.edit_file(b"a").set_preview(b"preview", key).unwrap();
+	edit.remove(b"garbage").unwrap();
+	edit.finish(key).unwrap();
+
+	// Garbage collection moves the meta sections along with the file sections
+	let mut edit = FileEditor::open("preview", key).unwrap();
+	edit.gc().unwrap();
+	assert_eq!(edit.garbage(edit.high_mark()), 0);
+	edit.finish(key).unwrap();
+
+	let reader = FileReader::open("preview", key).unwrap();
+	let a = reader.find_file(b"a").unwrap();
+	assert_eq!(reader.read_data(a, key).unwrap(), ALPHABET);
+	assert_eq!(reader.read_preview(a, key).unwrap().unwrap(), b"preview");
+
+	// Assigning new contents keeps the metadata
+	let mut edit = FileEditor::open("preview", key).unwrap();
+	edit.create_file(b"a", b"new", key).unwrap();
+	assert!(edit.find_file(b"a").unwrap().meta_section().is_some());
+}
+
+#[test]
+fn test_max_size() {
+	let ref key = Key::default();
+
+	temp_file!("max_size");
+
+	let mut edit = FileEditor::create_new("max_size", key).unwrap();
+	edit.set_max_size(Some(1024));
+	edit.create_file(b"a", ALPHABET, key).unwrap();
+	let high_mark = edit.high_mark();
+	assert_eq!(edit.create_file(b"b", &[0; 1024], key).unwrap_err().kind(), io::ErrorKind::OutOfMemory);
+	assert!(edit.find(b"b").is_none());
+	assert_eq!(edit.high_mark(), high_mark);
+
+	let mut edit_file = edit.edit_file(b"a");
+	edit_file.set_content(1, 1024);
+	assert_eq!(edit_file.allocate_data().err().map(|err| err.kind()), Some(io::ErrorKind::OutOfMemory));
+	edit.edit_file(b"a").set_content(1, ALPHABET.len() as u32);
+	edit.finish(key).unwrap();
+
+	let reader = FileReader::open("max_size", key).unwrap();
+	assert_eq!(reader.read_data(reader.find_file(b"a").unwrap(), key).unwrap(), ALPHABET);
+}
+
+#[test]
+fn test_scrub() {
+	let ref key = Key::default();
+
+	temp_file!("scrub");
+
+	let mut edit = FileEditor::create_new("scrub", key).unwrap();
+	edit.create_file(b"good", ALPHABET, key).unwrap();
+	let bad = *edit.create_file(b"bad", ALPHABET, key).unwrap();
+	edit.create_link(b"link", &bad);
+	edit.edit_file(b"good").set_preview(b"preview", key).unwrap();
+	edit.finish(key).unwrap();
+
+	// Flip a byte in the data of the bad file
+	{
+		use std::io::prelude::*;
+		let mut file = std::fs::OpenOptions::new().read(true).write(true).open("scrub").unwrap();
+		let offset = bad.section.offset as u64 * BLOCK_SIZE as u64 + 3;
+		let mut byte = [0u8];
+		file.seek(io::SeekFrom::Start(offset)).unwrap();
+		file.read_exact(&mut byte).unwrap();
+		file.seek(io::SeekFrom::Start(offset)).unwrap();
+		file.write_all(&[byte[0] ^ 1]).unwrap();
+	}
+
+	let reader = FileReader::open("scrub", key).unwrap();
+	let mut calls = Vec::new();
+	let damaged = reader.scrub(key, &ScrubOptions::default(), |done, total| { calls.push((done, total)); true }).unwrap();
+	assert_eq!(damaged, [Damaged { paths: vec![b"bad".to_vec(), b"link".to_vec()], section: bad.section, meta: false, error: io::ErrorKind::InvalidData }]);
+	// Three distinct sections including the meta section of the good file
+	assert_eq!(calls.len(), 4);
+	assert_eq!(calls[3].0, calls[3].1);
+
+	// Stopping early returns the damage found so far
+	let damaged = reader.scrub(key, &ScrubOptions { max_bytes_per_sec: Some(1 << 30) }, |_, _| false).unwrap();
+	assert!(damaged.is_empty());
+}
+
+#[test]
+fn test_repair_from() {
+	let ref key = Key::default();
+
+	temp_file!("repair_primary");
+	temp_file!("repair_mirror");
+
+	let mut edit = FileEditor::create_new("repair_primary", key).unwrap();
+	edit.create_file(b"good", ALPHABET, key).unwrap();
+	let bad = *edit.create_file(b"bad", ALPHABET, key).unwrap();
+	edit.finish(key).unwrap();
+	std::fs::copy("repair_primary", "repair_mirror").unwrap();
+
+	// Nothing to repair
+	assert!(crate::repair_from("repair_primary", "repair_mirror", key).unwrap().is_empty());
+
+	// Flip a byte in the data of the bad file in both copies
+	let corrupt = |path: &str| {
+		use std::io::prelude::*;
+		let mut file = std::fs::OpenOptions::new().read(true).write(true).open(path).unwrap();
+		let offset = bad.section.offset as u64 * BLOCK_SIZE as u64 + 5;
+		let mut byte = [0u8];
+		file.seek(io::SeekFrom::Start(offset)).unwrap();
+		file.read_exact(&mut byte).unwrap();
+		file.seek(io::SeekFrom::Start(offset)).unwrap();
+		file.write_all(&[byte[0] ^ 1]).unwrap();
+	};
+	corrupt("repair_primary");
+
+	let repaired = crate::repair_from("repair_primary", "repair_mirror", key).unwrap();
+	assert_eq!(repaired.len(), 1);
+	assert_eq!(repaired[0].section, bad.section);
+	let reader = FileReader::open("repair_primary", key).unwrap();
+	assert_eq!(reader.read_data(&bad, key).unwrap(), ALPHABET);
+	assert_eq!(std::fs::read("repair_primary").unwrap(), std::fs::read("repair_mirror").unwrap());
+
+	// A mirror without an intact copy leaves the primary alone
+	corrupt("repair_primary");
+	corrupt("repair_mirror");
+	let err = crate::repair_from("repair_primary", "repair_mirror", key).unwrap_err();
+	assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+	assert_eq!(std::fs::read("repair_primary").unwrap(), std::fs::read("repair_mirror").unwrap());
+}
+
+#[test]
+fn test_backup() {
+	let ref key = Key::default();
+
+	temp_file!("backup");
+	temp_file!("backup-0");
+	temp_file!("backup-1");
+	temp_file!("backup-restored");
+
+	{
+		let mut edit = FileEditor::create_new("backup", key).unwrap();
+		edit.create_file(b"a", ALPHABET, key).unwrap();
+		edit.create_file(b"dir/b", b"unchanged", key).unwrap();
+		edit.finish(key).unwrap();
+	}
+	let full = backup(&FileReader::open("backup", key).unwrap(), key, "backup-0", None).unwrap();
+	assert_eq!(full.len(), 2);
+
+	{
+		let mut edit = FileEditor::open("backup", key).unwrap();
+		edit.create_file(b"a", b"changed", key).unwrap();
+		edit.create_file(b"c", b"new", key).unwrap();
+		edit.finish(key).unwrap();
+	}
+	let incr = backup(&FileReader::open("backup", key).unwrap(), key, "backup-1", Some(&full)).unwrap();
+	assert_eq!(incr, Manifest::open("backup-1", key).unwrap());
+	assert_eq!(incr.hash(b"dir/b"), full.hash(b"dir/b"));
+	assert_ne!(incr.hash(b"a"), full.hash(b"a"));
+
+	// Only the changed files are exported
+	let reader = FileReader::open("backup-1", key).unwrap();
+	assert!(reader.find_file(b"dir/b").is_none());
+	assert_eq!(reader.read_data(reader.find_file(b"c").unwrap(), key).unwrap(), b"new");
+
+	// Restoring requires the whole chain
+	assert_eq!(restore(&["backup-1"], key, "backup-restored").unwrap_err().kind(), io::ErrorKind::InvalidData);
+	let _ = std::fs::remove_file("backup-restored");
+	assert_eq!(restore(&["backup-0", "backup-1"], key, "backup-restored").unwrap(), 3);
+	let reader = FileReader::open("backup-restored", key).unwrap();
+	assert_eq!(reader.read_data(reader.find_file(b"a").unwrap(), key).unwrap(), b"changed");
+	assert_eq!(reader.read_data(reader.find_file(b"dir/b").unwrap(), key).unwrap(), b"unchanged");
+	assert!(reader.find_file(Directory::MANIFEST).is_none());
+}
+
+#[test]
+fn test_wrong_key() {
+	let ref key = [1, 2];
+
+	temp_file!("wrong_key");
+	FileEditor::create_empty("wrong_key", key).unwrap();
+
+	let err = FileReader::open("wrong_key", &[3, 4]).unwrap_err();
+	assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+	let wrong_key = err.get_ref().and_then(|err| err.downcast_ref::<crate::key::WrongKey>()).unwrap();
+	assert_eq!(wrong_key.fingerprint, crate::key::fingerprint(&[3, 4]));
+
+	// Corrupting the info header with the right key is not reported as the wrong key
+	let mut data = std::fs::read("wrong_key").unwrap();
+	data[40] ^= 1;
+	std::fs::write("wrong_key", &data).unwrap();
+	let err = FileReader::open("wrong_key", key).unwrap_err();
+	assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+	assert!(!err.get_ref().unwrap().is::<crate::key::WrongKey>());
+	assert!(matches!(cli::cat("wrong_key", key, b"a"), Err(cli::Error::Corrupt(_))));
+}
+
+#[test]
+fn test_key_slots() {
+	let ref master_key = [1, 2];
+	let (ref alice, ref bob, ref eve) = ([3, 4], [5, 6], [7, 8]);
+
+	temp_file!("key_slots");
+	temp_file!("key_slots2");
+	temp_file!("key_slots3");
+
+	{
+		let mut edit = FileEditor::create_new("key_slots", master_key).unwrap();
+		assert_eq!(edit.add_key_slot(alice, master_key).unwrap(), 0);
+		assert_eq!(edit.add_key_slot(bob, master_key).unwrap(), 1);
+		edit.create_file(b"hello", b"Hello, world!", master_key).unwrap();
+		edit.finish(master_key).unwrap();
+	}
+
+	assert_eq!(crate::key::unlock("key_slots", alice).unwrap(), *master_key);
+	assert_eq!(crate::key::unlock("key_slots", bob).unwrap(), *master_key);
+	assert_eq!(crate::key::unlock("key_slots", master_key).unwrap(), *master_key);
+	let err = crate::key::unlock("key_slots", eve).unwrap_err();
+	assert!(err.get_ref().unwrap().is::<crate::key::WrongKey>());
+
+	{
+		let mut edit = FileEditor::open("key_slots", master_key).unwrap();
+		assert_eq!(edit.add_key_slot(eve, master_key).unwrap(), 2);
+		edit.remove_key_slot(1, master_key).unwrap();
+		assert_eq!(edit.remove_key_slot(1, master_key).unwrap_err().kind(), io::ErrorKind::NotFound);
+		edit.gc().unwrap();
+		edit.finish(master_key).unwrap();
+	}
+
+	let reader = FileReader::open("key_slots", master_key).unwrap();
+	let fingerprints = reader.key_slots(master_key).unwrap();
+	assert_eq!(&fingerprints[..3], [Some(crate::key::fingerprint(alice)), None, Some(crate::key::fingerprint(eve))]);
+	assert_eq!(reader.read_data(reader.find_file(b"hello").unwrap(), master_key).unwrap(), b"Hello, world!");
+	assert!(crate::key::unlock("key_slots", bob).is_err());
+	assert_eq!(crate::key::unlock("key_slots", eve).unwrap(), *master_key);
+
+	// The file data in the way of the key slot table is moved
+	{
+		let mut edit = FileEditor::create_new("key_slots2", master_key).unwrap();
+		edit.create_file(b"hello", b"Hello, world!", master_key).unwrap();
+		edit.finish(master_key).unwrap();
+	}
+	{
+		let mut edit = FileEditor::open("key_slots2", master_key).unwrap();
+		edit.add_key_slot(alice, master_key).unwrap();
+		edit.gc().unwrap();
+		edit.finish(master_key).unwrap();
+	}
+	assert_eq!(crate::key::unlock("key_slots2", alice).unwrap(), *master_key);
+	let reader = FileReader::open("key_slots2", master_key).unwrap();
+	assert!(reader.find_file(Directory::KEY_SLOTS).unwrap().is_pinned());
+	assert_eq!(reader.read_data(reader.find_file(b"hello").unwrap(), master_key).unwrap(), b"Hello, world!");
+
+	// Unless it is pinned
+	let mut edit = FileEditor::create_new("key_slots3", master_key).unwrap();
+	edit.edit_file(b"pinned").set_content(1, 5).allocate_data().unwrap().pin().write_data(b"fixed", master_key).unwrap();
+	assert_eq!(edit.add_key_slot(alice, master_key).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+	edit.abort().unwrap();
+}
+
+#[test]
+fn test_raw_sections() {
+	let ref key = Key::default();
+
+	temp_file!("raw_sections");
+
+	{
+		let mut edit = FileEditor::create_new("raw_sections", key).unwrap();
+		edit.create_file(b"a", ALPHABET, key).unwrap();
+		edit.create_file(b"dir/b", b"hello", key).unwrap();
+		edit.finish(key).unwrap();
+	}
+
+	let reader = FileReader::open("raw_sections", key).unwrap();
+	let sections: Vec<_> = reader.sections().collect();
+	assert_eq!(sections.iter().map(|(path, _)| &path[..]).collect::<Vec<_>>(), [&b"a"[..], b"dir/b"]);
+	for (_, section) in &sections {
+		let mut raw = reader.raw_blocks(section.offset..section.offset + section.size).unwrap();
+		assert!(section.verify_mac(&raw, key));
+		assert!(!section.verify_mac(&raw, &[1, 2]));
+		raw[0][0] ^= 1;
+		assert!(!section.verify_mac(&raw, key));
+	}
+	let end = reader.high_mark();
+	assert_eq!(reader.raw_blocks(end..end + 1000).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn test_gc_dedup() {
+	let ref key = Key::default();
+
+	temp_file!("gc_dedup");
+
+	let mut edit = FileEditor::create_new("gc_dedup", key).unwrap();
+	edit.create_file(b"a", ALPHABET, key).unwrap();
+	edit.create_file(b"b", b"unique", key).unwrap();
+	edit.create_file(b"c", ALPHABET, key).unwrap();
+	let desc = *edit.find_file(b"c").unwrap();
+	edit.create_link(b"d", &desc);
+	edit.edit_file(b"c").set_dependencies(&[b"b"], key).unwrap();
+	let high_mark = edit.high_mark();
+
+	assert_eq!(edit.gc_dedup(key).unwrap(), 2);
+	assert!(edit.high_mark() < high_mark);
+	let section = edit.find_file(b"a").unwrap().section;
+	assert_eq!(edit.find_file(b"c").unwrap().section, section);
+	assert_eq!(edit.find_file(b"d").unwrap().section, section);
+	assert_ne!(edit.find_file(b"b").unwrap().section, section);
+	edit.finish(key).unwrap();
+
+	let reader = FileReader::open("gc_dedup", key).unwrap();
+	for path in [&b"a"[..], b"c", b"d"] {
+		assert_eq!(reader.read_data(reader.find_file(path).unwrap(), key).unwrap(), ALPHABET);
+	}
+	assert_eq!(reader.read_data(reader.find_file(b"b").unwrap(), key).unwrap(), b"unique");
+	assert_eq!(reader.read_meta(reader.find_file(b"c").unwrap(), key).unwrap().dependencies(), [b"b"]);
+}
+
+#[test]
+fn test_auto_gc() {
+	let ref key = Key::default();
+
+	temp_file!("auto_gc");
+
+	let mut edit = FileEditor::create_new("auto_gc", key).unwrap();
+	edit.set_auto_gc_threshold(Some(0.5));
+	edit.create_file(b"a", ALPHABET, key).unwrap();
+	edit.create_file(b"b", ALPHABET, key).unwrap();
+	edit.remove(b"a");
+	edit.finish(key).unwrap();
+	// Less than half of the PAK file is garbage
+	let reader = FileReader::open("auto_gc", key).unwrap();
+	assert_ne!(reader.garbage(reader.high_mark()), 0);
+
+	let runs = metrics::auto_gc_runs();
+	let mut edit = FileEditor::open("auto_gc", key).unwrap();
+	edit.set_auto_gc_threshold(Some(0.5));
+	edit.create_file(b"c", b"small", key).unwrap();
+	edit.remove(b"b");
+	edit.finish(key).unwrap();
+	assert!(metrics::auto_gc_runs() > runs);
+
+	let reader = FileReader::open("auto_gc", key).unwrap();
+	assert_eq!(reader.garbage(reader.high_mark()), 0);
+	assert_eq!(reader.read_data(reader.find_file(b"c").unwrap(), key).unwrap(), b"small");
+}
+
+#[test]
+fn test_plan_gc() {
+	let ref key = Key::default();
+
+	temp_file!("plan_gc");
+
+	let mut edit = FileEditor::create_new("plan_gc", key).unwrap();
+	edit.create_file(b"a", ALPHABET, key).unwrap();
+	edit.create_file(b"b", ALPHABET, key).unwrap();
+	edit.create_file(b"c", b"hello", key).unwrap();
+	let a = edit.find_file(b"a").unwrap().section;
+	let c = edit.find_file(b"c").unwrap().section;
+	edit.remove(b"b");
+
+	let plan = edit.plan_gc();
+	assert_eq!(plan.extents.len(), 2);
+	assert_eq!(plan.moves().count(), 1);
+	assert_eq!(plan.relocate(a.offset), Some(a.offset));
+	assert_eq!(plan.relocate(a.offset + a.size), None);
+	assert_eq!(plan.bytes_rewritten(), c.size as u64 * plan.block_size as u64);
+	assert!(plan.bytes_reclaimed() > 0);
+
+	edit.gc().unwrap();
+	assert_eq!(edit.high_mark(), plan.new_high_mark);
+	assert_eq!(Some(edit.find_file(b"c").unwrap().section.offset), plan.relocate(c.offset));
+	assert!(edit.plan_gc().is_empty());
+	edit.finish(key).unwrap();
+
+	let reader = FileReader::open("plan_gc", key).unwrap();
+	assert_eq!(reader.read_data(reader.find_file(b"c").unwrap(), key).unwrap(), b"hello");
+}
+
+#[test]
+fn test_hybrid_editor() {
+	let ref key = Key::default();
+	let options = HybridOptions { stage_min_bytes: 0, ..HybridOptions::default() };
+
+	temp_file!("hybrid");
+
+	let mut edit = FileEditor::create_new("hybrid", key).unwrap();
+	edit.create_file(b"big", &[42; 4000], key).unwrap();
+	edit.create_file(b"small", b"small", key).unwrap();
+	edit.finish(key).unwrap();
+
+	// Small additions are appended in place
+	let mut edit = HybridEditor::open("hybrid", key, &options).unwrap();
+	edit.create_file(b"added", b"added", key).unwrap();
+	assert_eq!(edit.strategy(), EditStrategy::InPlace);
+	assert_eq!(edit.finish(key).unwrap(), EditStrategy::InPlace);
+
+	// Removing most of the file data stages a compacted copy
+	let len = std::fs::metadata("hybrid").unwrap().len();
+	let mut edit = HybridEditor::open("hybrid", key, &options).unwrap();
+	edit.remove(b"big");
+	let staging = edit.staging_path();
+	assert_eq!(edit.finish(key).unwrap(), EditStrategy::Staged);
+	assert!(!staging.exists());
+	assert!(std::fs::metadata("hybrid").unwrap().len() < len / 2);
+
+	let reader = FileReader::open("hybrid", key).unwrap();
+	assert!(reader.find_file(b"big").is_none());
+	assert_eq!(reader.garbage(reader.high_mark()), 0);
+	assert_eq!(reader.read_data(reader.find_file(b"small").unwrap(), key).unwrap(), b"small");
+	assert_eq!(reader.read_data(reader.find_file(b"added").unwrap(), key).unwrap(), b"added");
+}
+
+#[test]
+fn test_pinned_gc() {
+	let ref key = Key::default();
+
+	temp_file!("pinned_gc");
+
+	let mut edit = FileEditor::create_new("pinned_gc", key).unwrap();
+	edit.create_file(b"a", ALPHABET, key).unwrap();
+	edit.create_file(b"index", b"index", key).unwrap();
+	edit.create_file(b"b", ALPHABET, key).unwrap();
+	edit.create_file(b"c", b"hello", key).unwrap();
+	edit.edit_file(b"index").pin();
+	let index = edit.find_file(b"index").unwrap().section;
+	edit.remove(b"a");
+	edit.remove(b"b");
+
+	// The pinned extent stays in place and the gap below it is not garbage
+	let plan = edit.plan_gc();
+	assert_eq!(plan.relocate(index.offset), Some(index.offset));
+	assert_eq!(plan.new_high_mark, index.offset + index.size + edit.find_file(b"c").unwrap().section.size);
+	assert_eq!(edit.garbage(edit.high_mark()), plan.high_mark - plan.new_high_mark);
+
+	edit.gc().unwrap();
+	assert_eq!(edit.find_file(b"index").unwrap().section, index);
+	assert_eq!(edit.find_file(b"c").unwrap().section.offset, index.offset + index.size);
+	edit.finish(key).unwrap();
+
+	let reader = FileReader::open("pinned_gc", key).unwrap();
+	let desc = reader.find_file(b"index").unwrap();
+	assert!(desc.is_pinned());
+	assert_eq!(desc.section, index);
+	assert_eq!(reader.read_data(desc, key).unwrap(), b"index");
+	assert_eq!(reader.read_data(reader.find_file(b"c").unwrap(), key).unwrap(), b"hello");
+}
+
+#[test]
+fn test_obfuscation() {
+	let ref key = Key::default();
+
+	temp_file!("obfuscation");
+
+	let mut edit = FileEditor::create_new("obfuscation", key).unwrap();
+	edit.set_obfuscation(Some(ObfuscationOptions { shuffle: true, decoys: 2, decoy_max_size: 100 }));
+	let paths: Vec<Vec<u8>> = (0..20).map(|i| format!("file{:02}", i).into_bytes()).collect();
+	for path in &paths {
+		edit.create_file(path, path, key).unwrap();
+	}
+	edit.create_file(b"index", b"index", key).unwrap();
+	edit.edit_file(b"index").pin();
+	let index = edit.find_file(b"index").unwrap().section;
+	edit.finish(key).unwrap();
+
+	let reader = FileReader::open("obfuscation", key).unwrap();
+	assert_eq!(reader.files_under(Directory::DECOYS).unwrap().len(), 2);
+	assert_eq!(reader.find_file(b"index").unwrap().section, index);
+	let offsets: Vec<u32> = paths.iter().map(|path| reader.find_file(path).unwrap().section.offset).collect();
+	assert!(offsets.windows(2).any(|pair| pair[0] > pair[1]));
+	for path in &paths {
+		assert_eq!(reader.read_data(reader.find_file(path).unwrap(), key).unwrap(), *path);
+	}
+	assert!(reader.fsck(reader.high_mark(), &mut String::new()));
+	assert_eq!(reader.garbage(reader.high_mark()), 0);
+}
+
+#[test]
+fn test_obfuscation_key_slots() {
+	let ref master_key = [1, 2];
+	let ref alice = [3, 4];
+
+	temp_file!("obfuscation_key_slots");
+
+	let mut edit = FileEditor::create_new("obfuscation_key_slots", master_key).unwrap();
+	edit.add_key_slot(alice, master_key).unwrap();
+	let key_slots = edit.find_file(Directory::KEY_SLOTS).unwrap().section;
+	for i in 0..20 {
+		edit.create_file(format!("file{:02}", i).as_bytes(), b"data", master_key).unwrap();
+	}
+	edit.set_obfuscation(Some(ObfuscationOptions { shuffle: true, decoys: 2, decoy_max_size: 100 }));
+	edit.finish(master_key).unwrap();
+
+	// The key slot table stays right after the header
+	let reader = FileReader::open("obfuscation_key_slots", master_key).unwrap();
+	assert_eq!(reader.find_file(Directory::KEY_SLOTS).unwrap().section, key_slots);
+	assert_eq!(crate::key::unlock("obfuscation_key_slots", alice).unwrap(), *master_key);
+}
@@ -0,0 +1,444 @@
+/*!
+Generating, identifying and splitting keys.
+
+## Fingerprints
+
+The [fingerprint] of a key is a short public identifier, eg. to label keys in a keyring or tell which key a team member is using.
+Opening a PAK file with the wrong key fails with [`WrongKey`] reporting the fingerprint of the key used.
+
+## Key slots
+
+A PAK file encrypted with a master key grants access to several keys with key slots, see [`FileEditor::add_key_slot`](crate::FileEditor::add_key_slot).
+The master key is recovered from a key slot with [`unlock`].
+
+## Splitting keys between custodians
+
+The key is split into `n` shares with [Shamir's secret sharing](https://en.wikipedia.org/wiki/Shamir%27s_secret_sharing), any `k` of them combine into the key.
+Fewer than `k` shares reveal nothing about the key.
+
+```
+let key = [0x0123456789abcdef, 0xfedcba9876543210];
+let shares = paks::key::split(&key, 5, 3).unwrap();
+
+// Any three custodians recover the key
+assert_eq!(paks::key::combine(&[shares[4], shares[0], shares[2]]).unwrap(), key);
+
+// Shares are written down as text
+let share: paks::key::Share = shares[1].to_string().parse().unwrap();
+assert_eq!(share, shares[1]);
+```
+*/
+
+use std::{collections::HashMap, convert::TryInto, error, fmt, fs, io, io::prelude::*, io::ErrorKind, ops, path::Path, str};
+use dataview::Pod;
+use crate::*;
+
+/// Short public identifier of a key, see [`fingerprint`].
+///
+/// Formatted as 8 hex digits.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Fingerprint(pub u32);
+
+impl fmt::Display for Fingerprint {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{:08x}", self.0)
+	}
+}
+
+/// Returns the fingerprint of the key.
+///
+/// The fingerprint is derived with the cipher, it reveals no more about the key than checking a guessed key against a PAK file already does.
+/// Different keys can share a fingerprint, it identifies keys between people and is not a substitute for the key.
+///
+/// # Examples
+///
+/// ```
+/// let key = [0x0123456789abcdef, 0xfedcba9876543210];
+/// let fingerprint = paks::key::fingerprint(&key);
+/// assert_eq!(fingerprint.to_string().len(), 8);
+/// assert_ne!(fingerprint, paks::key::fingerprint(&[0, 0]));
+/// ```
+pub fn fingerprint(key: &Key) -> Fingerprint {
+	let rk = cipher::expand(*key);
+	Fingerprint(cipher::encrypt([u64::from_le_bytes(*b"FINGERPR"), 0], &rk)[0] as u32)
+}
+
+// Verifier of the key salted with the first half of the header nonce
+// The header nonce holds it so the readers tell a wrong key from a corrupted header, the salt keeps PAK files with the same key unlinkable
+pub(crate) fn verifier(key: &Key, salt: u64) -> u32 {
+	let rk = cipher::expand(*key);
+	cipher::encrypt([u64::from_le_bytes(*b"VERIFIER"), salt], &rk)[0] as u32
+}
+
+/// Error opening a PAK file with the wrong key.
+///
+/// Opening a PAK file with [`FileReader`] or [`FileEditor`] fails with [`io::ErrorKind::InvalidData`] wrapping this error
+/// when the key does not match the verifier in the header. A corrupted header opened with the right key fails with a different message instead.
+///
+/// PAK files written before the verifier existed and files which are not PAK files at all always report the wrong key.
+///
+/// # Examples
+///
+/// ```no_run
+/// let err = paks::FileReader::open("game.pak", &[0, 0]).unwrap_err();
+/// if let Some(wrong_key) = err.get_ref().and_then(|err| err.downcast_ref::<paks::key::WrongKey>()) {
+///     eprintln!("game.pak is not encrypted with key {}", wrong_key.fingerprint);
+/// }
+/// ```
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct WrongKey {
+	/// Fingerprint of the key used to open the PAK file.
+	pub fingerprint: Fingerprint,
+}
+
+impl fmt::Display for WrongKey {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "incorrect key {} or not a PAK file", self.fingerprint)
+	}
+}
+
+impl error::Error for WrongKey {}
+
+/// Number of key slots in the key slot table, see [`FileEditor::add_key_slot`](crate::FileEditor::add_key_slot).
+pub const MAX_SLOTS: usize = 8;
+
+// Every slot is the nonce, the MAC and the master key encrypted with the key of the slot
+pub(crate) const SLOT_BLOCKS: usize = 3;
+pub(crate) const TABLE_BYTES: usize = MAX_SLOTS * SLOT_BLOCKS * BLOCK_SIZE;
+
+// The metadata of the table lists the fingerprints of the keys in the occupied slots
+pub(crate) const META_SLOTS: &str = "paks.key_slots";
+
+// The key slot table follows the header where `unlock` finds it without the master key
+pub(crate) fn table_extent(block_size: u32) -> ops::Range<u32> {
+	let start = header_blocks(block_size);
+	start..start + bytes2blocks(TABLE_BYTES as u32, block_size)
+}
+
+// Lists the sections in the way of the key slot table, they are moved before the table is allocated
+// Pinned file data cannot be moved
+pub(crate) fn sections_in_table(directory: &Directory) -> Result<Vec<(u32, u32)>, ErrorKind> {
+	let table = table_extent(directory.block_size);
+	let mut sections = Vec::new();
+	for desc in directory.as_ref().iter().filter(|desc| desc.is_file()) {
+		for section in Some(desc.section).into_iter().chain(desc.meta_section()) {
+			if section.size != 0 && section.offset < table.end && section.offset.saturating_add(section.size) > table.start {
+				if desc.is_pinned() {
+					return Err(ErrorKind::InvalidInput);
+				}
+				sections.push((section.offset, section.size));
+			}
+		}
+	}
+	sections.sort_unstable();
+	sections.dedup();
+	Ok(sections)
+}
+
+// Points the file descriptors at the new offsets of the moved sections
+pub(crate) fn relocate_sections(directory: &mut Directory, moved: &HashMap<(u32, u32), u32>) {
+	for desc in directory.descs_mut().iter_mut().filter(|desc| desc.is_file()) {
+		if let Some(&to) = moved.get(&(desc.section.offset, desc.section.size)) {
+			desc.section.offset = to;
+		}
+		if let Some(mut meta) = desc.meta_section() {
+			if let Some(&to) = moved.get(&(meta.offset, meta.size)) {
+				meta.offset = to;
+				desc.set_meta_section(&meta);
+			}
+		}
+	}
+}
+
+pub(crate) fn wrap_slot(slot: &mut [u8], master_key: &Key, key: &Key) {
+	let mut blocks = [*master_key];
+	let mut section = Section { size: 1, ..Section::default() };
+	crypt::encrypt_section(&mut blocks, &mut section, key);
+	slot.copy_from_slice([section.nonce, section.mac, blocks[0]].as_bytes());
+}
+
+fn unwrap_slot(slot: &[u8], key: &Key) -> Option<Key> {
+	let mut blocks = [Block::default(); SLOT_BLOCKS];
+	blocks.as_bytes_mut().copy_from_slice(slot);
+	let section = Section { offset: 0, size: 1, nonce: blocks[0], mac: blocks[1] };
+	let mut master_key = [blocks[2]];
+	if !crypt::decrypt_section(&mut master_key, &section, key) {
+		return None;
+	}
+	Some(master_key[0])
+}
+
+pub(crate) fn decode_slots(meta: &MetaMap) -> Vec<Option<Fingerprint>> {
+	let mut slots = vec![None; MAX_SLOTS];
+	if let Some(value) = meta.get_bytes(META_SLOTS) {
+		for (slot, entry) in slots.iter_mut().zip(value.chunks_exact(5)) {
+			if entry[0] != 0 {
+				*slot = Some(Fingerprint(u32::from_le_bytes([entry[1], entry[2], entry[3], entry[4]])));
+			}
+		}
+	}
+	slots
+}
+
+pub(crate) fn encode_slots(slots: &[Option<Fingerprint>]) -> MetaMap {
+	let mut value = Vec::new();
+	for slot in slots {
+		value.push(slot.is_some() as u8);
+		value.extend_from_slice(&slot.map_or(0, |fingerprint| fingerprint.0).to_le_bytes());
+	}
+	let mut meta = MetaMap::new();
+	meta.set_bytes(META_SLOTS, &value);
+	meta
+}
+
+/// Recovers the master key of a PAK file from its key slots.
+///
+/// The key slot table is found without the master key right after the header, see [`FileEditor::add_key_slot`](crate::FileEditor::add_key_slot).
+/// The master key is checked against the header before it is returned, the key itself is returned if it is the master key.
+///
+/// # Errors
+///
+/// * [`io::ErrorKind::InvalidData`]: No key slot opens with the key, wrapping [`WrongKey`].
+/// * [`io::Error`]: An error encountered reading the PAK file.
+///
+/// # Examples
+///
+/// ```no_run
+/// let ref alice = [0x1234, 0x5678];
+/// let ref master_key = paks::key::unlock("game.pak", alice).unwrap();
+/// let reader = paks::FileReader::open("game.pak", master_key).unwrap();
+/// ```
+pub fn unlock<P: ?Sized + AsRef<Path>>(path: &P, key: &Key) -> io::Result<Key> {
+	// The table follows the header rounded up to the block size
+	let max_size = InfoHeader::BLOCK_SIZES.iter().map(|&block_size| header_blocks(block_size) as usize * block_size as usize).max().unwrap_or(0) + TABLE_BYTES;
+	let mut data = Vec::with_capacity(max_size);
+	fs::File::open(path)?.take(max_size as u64).read_to_end(&mut data)?;
+	unlock_bytes(&data, key)
+}
+
+/// Recovers the master key of a PAK file in memory from its key slots.
+///
+/// Only the start of the PAK file holding the header and the key slot table is needed.
+/// See [`unlock`] for more information.
+///
+/// # Examples
+///
+/// ```
+/// let ref master_key = paks::key::generate();
+/// let ref alice = paks::key::generate();
+///
+/// let mut editor = paks::MemoryEditor::new();
+/// editor.create_file(b"hello", b"Hello, world!", master_key);
+/// editor.add_key_slot(alice, master_key).unwrap();
+/// let (blocks, _) = editor.finish(master_key);
+///
+/// let bytes = paks::as_bytes(&blocks);
+/// assert_eq!(paks::key::unlock_bytes(bytes, alice).unwrap(), *master_key);
+/// ```
+pub fn unlock_bytes(data: &[u8], key: &Key) -> io::Result<Key> {
+	let mut header = Header::default();
+	match data.get(..header.as_bytes().len()) {
+		Some(bytes) => header.as_bytes_mut().copy_from_slice(bytes),
+		None => Err(io::ErrorKind::UnexpectedEof)?,
+	}
+	let mut direct = header;
+	if crypt::decrypt_header(&mut direct, key) {
+		return Ok(*key);
+	}
+
+	for &block_size in &InfoHeader::BLOCK_SIZES {
+		let start = header_blocks(block_size) as usize * block_size as usize;
+		let table = match data.get(start..start + TABLE_BYTES) {
+			Some(table) => table,
+			None => continue,
+		};
+		for slot in table.chunks_exact(SLOT_BLOCKS * BLOCK_SIZE) {
+			if let Some(master_key) = unwrap_slot(slot, key) {
+				let mut header = header;
+				if crypt::decrypt_header(&mut header, &master_key) && header.info.block_size() == block_size {
+					return Ok(master_key);
+				}
+			}
+		}
+	}
+
+	Err(io::Error::new(io::ErrorKind::InvalidData, WrongKey { fingerprint: fingerprint(key) }))
+}
+
+/// A share of a split key, see [`split`].
+///
+/// Formatted as its index and value in hex separated by a dash, eg. `01-00112233445566778899aabbccddeeff`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Share {
+	/// The non-zero index of the share.
+	pub index: u8,
+	/// The value of the share.
+	pub value: [u8; 16],
+}
+
+impl fmt::Display for Share {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{:02x}-", self.index)?;
+		for byte in &self.value {
+			write!(f, "{:02x}", byte)?;
+		}
+		Ok(())
+	}
+}
+
+impl str::FromStr for Share {
+	type Err = ErrorKind;
+	fn from_str(s: &str) -> Result<Share, ErrorKind> {
+		let (index, hex) = match s.trim().split_once('-') {
+			Some((index, hex)) if index.len() == 2 && hex.len() == 32 => (index, hex),
+			_ => return Err(ErrorKind::InvalidData),
+		};
+		let index = u8::from_str_radix(index, 16).map_err(|_| ErrorKind::InvalidData)?;
+		let mut value = [0u8; 16];
+		for (i, byte) in value.iter_mut().enumerate() {
+			*byte = hex.get(i * 2..i * 2 + 2).and_then(|hex| u8::from_str_radix(hex, 16).ok()).ok_or(ErrorKind::InvalidData)?;
+		}
+		if index == 0 {
+			return Err(ErrorKind::InvalidData);
+		}
+		Ok(Share { index, value })
+	}
+}
+
+// Multiplication in GF(2^8) with the AES polynomial
+fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+	let mut r = 0;
+	while b != 0 {
+		if b & 1 != 0 {
+			r ^= a;
+		}
+		a = (a << 1) ^ if a & 0x80 != 0 { 0x1b } else { 0 };
+		b >>= 1;
+	}
+	r
+}
+
+// The inverse is a^254 as the multiplicative group has order 255
+fn gf_inv(a: u8) -> u8 {
+	let mut r = 1;
+	for _ in 0..254 {
+		r = gf_mul(r, a);
+	}
+	r
+}
+
+/// Generates a random key from the random number generator of the operating system.
+///
+/// # Examples
+///
+/// ```
+/// let key = paks::key::generate();
+/// assert_ne!(key, paks::key::generate());
+/// ```
+pub fn generate() -> Key {
+	let mut key = [Key::default()];
+	crypt::random(&mut key);
+	key[0]
+}
+
+fn key_bytes(key: &Key) -> [u8; 16] {
+	let mut bytes = [0u8; 16];
+	bytes[..8].copy_from_slice(&key[0].to_le_bytes());
+	bytes[8..].copy_from_slice(&key[1].to_le_bytes());
+	bytes
+}
+
+/// Splits the key into `n` shares of which any `k` combine into the key.
+///
+/// Every byte of the key is the constant term of its own random polynomial of degree `k - 1`, the shares are the polynomials evaluated at their index.
+///
+/// # Errors
+///
+/// * [`ErrorKind::InvalidInput`]: The threshold `k` is zero or greater than the number of shares `n`.
+pub fn split(key: &Key, n: u8, k: u8) -> Result<Vec<Share>, ErrorKind> {
+	if k == 0 || k > n {
+		return Err(ErrorKind::InvalidInput);
+	}
+
+	// The random coefficients of the polynomials, one block per degree
+	let mut coeffs = vec![Block::default(); k as usize - 1];
+	crypt::random(&mut coeffs);
+
+	let secret = key_bytes(key);
+	let shares = (1..=n).map(|index| {
+		let mut value = [0u8; 16];
+		for (i, byte) in value.iter_mut().enumerate() {
+			// Horner's method from the highest degree down to the secret
+			let mut y = 0;
+			for coeff in coeffs.iter().rev() {
+				y = gf_mul(y, index) ^ coeff.as_bytes()[i];
+			}
+			*byte = gf_mul(y, index) ^ secret[i];
+		}
+		Share { index, value }
+	}).collect();
+
+	// Any coefficient recovers the key together with k - 1 shares
+	coeffs.iter_mut().for_each(|coeff| *coeff = Block::default());
+	Ok(shares)
+}
+
+/// Combines the shares into the key.
+///
+/// Combining fewer shares than the threshold the key was split with, or shares of different keys, results in the wrong key.
+/// There is no way to tell without trying the key, eg. by opening a PAK file with it.
+///
+/// # Errors
+///
+/// * [`ErrorKind::InvalidInput`]: There are no shares or an index is repeated or zero.
+pub fn combine(shares: &[Share]) -> Result<Key, ErrorKind> {
+	if shares.is_empty() || shares.iter().enumerate().any(|(i, share)| share.index == 0 || shares[..i].iter().any(|other| other.index == share.index)) {
+		return Err(ErrorKind::InvalidInput);
+	}
+
+	// Lagrange interpolation at zero, subtraction is xor in GF(2^8)
+	let mut secret = [0u8; 16];
+	for share in shares {
+		let mut num = 1;
+		let mut den = 1;
+		for other in shares {
+			if other.index != share.index {
+				num = gf_mul(num, other.index);
+				den = gf_mul(den, other.index ^ share.index);
+			}
+		}
+		let basis = gf_mul(num, gf_inv(den));
+		for (byte, &y) in secret.iter_mut().zip(share.value.iter()) {
+			*byte ^= gf_mul(y, basis);
+		}
+	}
+
+	Ok([u64::from_le_bytes(secret[..8].try_into().unwrap()), u64::from_le_bytes(secret[8..].try_into().unwrap())])
+}
+
+#[test]
+fn test_split() {
+	let key = [0x0123456789abcdef, 0xfedcba9876543210];
+	let shares = split(&key, 5, 3).unwrap();
+	assert_eq!(shares.len(), 5);
+	for a in 0..5 {
+		for b in 0..5 {
+			for c in 0..5 {
+				if a != b && b != c && a != c {
+					assert_eq!(combine(&[shares[a], shares[b], shares[c]]).unwrap(), key);
+				}
+			}
+		}
+	}
+	assert_ne!(combine(&shares[..2]).unwrap(), key);
+	assert_eq!(combine(&split(&key, 1, 1).unwrap()).unwrap(), key);
+
+	assert_eq!(split(&key, 2, 3), Err(ErrorKind::InvalidInput));
+	assert_eq!(split(&key, 2, 0), Err(ErrorKind::InvalidInput));
+	assert_eq!(combine(&[shares[0], shares[0]]), Err(ErrorKind::InvalidInput));
+	assert_eq!(combine(&[]), Err(ErrorKind::InvalidInput));
+
+	assert_eq!("00-00112233445566778899aabbccddeeff".parse::<Share>(), Err(ErrorKind::InvalidData));
+	assert_eq!("01-0011".parse::<Share>(), Err(ErrorKind::InvalidData));
+	assert_eq!("01-00112233445566778899aabbccddeeff".parse::<Share>().unwrap().to_string(), "01-00112233445566778899aabbccddeeff");
+}
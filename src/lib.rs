@@ -13,6 +13,8 @@ The smallest addressable unit of the file format is a [`Block`], the entire file
 
 Addresses and sizes as referenced by [`Section`] objects, their 32-bit address and length fields reference blocks, not byte offsets.
 This limits the file format to a maximum of 64 GiB, individual files are limited to a maximum 4 GiB each.
+A PAK file may opt into a larger [block size](InfoHeader::block_size) of 64 or 256 bytes, raising the maximum to 256 GiB or 1 TiB.
+Sections always start and end at a block boundary, the encryption itself still operates on 16 byte [`Block`]s.
 
 The [`InfoHeader`] contains a section object referencing the [`Directory`].
 
@@ -23,7 +25,8 @@ The individual files' data are stored in between the header and the directory in
 When files are removed their data isn't immediately reclaimed leaving behind gaps.
 An explicit garbage collection can rewrite the PAK file to reclaim this unused space.
 
-The encryption SPECK128/128 and authentication CBC-MAC are not optional or configurable.
+The encryption SPECK128/128 and authentication CBC-MAC are not configurable.
+Individual files may opt out of the encryption and be stored as [public](Section::is_public) plaintext, they are still authenticated.
 These operations are performed on a per-file basis, the whole PAK file does not need to be checked beforehand.
 
 Getting started
@@ -87,6 +90,14 @@ let data = reader.read_data(desc, key).unwrap();
 // If the PAK file was tampered with without knowing the key,
 // reading the file will fail with an error
 ```
+
+### Sharing between threads
+
+The readers, the editors and the [`Directory`] are `Send` and `Sync`.
+Reading only borrows the readers immutably, the [`FileReader`] uses positioned reads so threads sharing a reader do not race on the file cursor.
+
+Clones of a [`MemoryReader`] share the PAK file in memory, wrap a [`FileReader`] in an [`ArcReader`] to share it the same way.
+Editing requires exclusive access, share a [`MemoryReader`] from [`MemoryEditor::into_reader`] once done editing.
 */
 
 use std::{fmt, mem, ops, str};
@@ -108,8 +119,22 @@ use dataview::Pod;
 // 	};
 // }
 
+#[macro_use]
+mod trace;
+
 mod cipher;
 mod crypt;
+mod parse;
+pub use self::parse::Limits;
+mod summary;
+mod unicode;
+
+pub mod metrics;
+
+pub mod key;
+
+mod selftest;
+pub use self::selftest::*;
 
 // The API exposed by the directory module is unstable but has to be public for paktool and friends
 #[doc(hidden)]
@@ -123,12 +148,78 @@ pub use self::directory::*;
 // pub use self::memory_reader::MemoryReader;
 // pub use self::memory_editor::{MemoryEditor, MemoryEditFile};
 
+#[macro_use]
 mod file_io;
 pub use self::file_io::*;
 
 mod memory;
 pub use self::memory::*;
 
+mod scoped;
+pub use self::scoped::*;
+
+mod parallel;
+pub use self::parallel::*;
+
+mod policy;
+pub use self::policy::*;
+
+mod meta;
+pub use self::meta::*;
+
+mod copy;
+pub use self::copy::*;
+
+mod dedup;
+
+mod backup;
+pub use self::backup::*;
+
+mod changelog;
+pub use self::changelog::*;
+
+mod provenance;
+pub use self::provenance::*;
+
+mod nested;
+
+mod storage;
+pub use self::storage::*;
+
+mod scrub;
+pub use self::scrub::*;
+
+mod obfuscate;
+pub use self::obfuscate::*;
+
+#[cfg(feature = "parity")]
+mod parity;
+#[cfg(feature = "parity")]
+pub use self::parity::*;
+
+pub mod cli;
+
+pub mod sfx;
+
+pub mod runtime;
+
+mod model;
+pub use self::model::{ArchiveModel, ModelEvent, ModelNode, NodeId};
+
+// Sharing between threads is part of the API, see the crate documentation
+const _: fn() = || {
+	fn send_sync<T: Send + Sync>() {}
+	send_sync::<Directory>();
+	send_sync::<FileReader>();
+	send_sync::<ArcReader>();
+	send_sync::<FileEditor>();
+	send_sync::<MemoryReader>();
+	send_sync::<MemoryEditor>();
+	send_sync::<SharedData>();
+	#[cfg(feature = "memmap2")]
+	send_sync::<MmapEditor>();
+};
+
 /// Block primitive.
 ///
 /// A block is the smallest addressable unit of which the PAK file is made.
@@ -146,6 +237,8 @@ const BLOCK_SIZE: usize = mem::size_of::<Block>();
 /// Section object.
 ///
 /// A section object defines a location in the PAK file and its cryptographic nonce and MAC.
+///
+/// Offsets and sizes are measured in blocks of the PAK file's [block size](InfoHeader::block_size).
 #[derive(Copy, Clone, Default, Eq, PartialEq, Hash)]
 #[repr(C)]
 pub struct Section {
@@ -160,8 +253,31 @@ pub struct Section {
 }
 
 impl Section {
-	fn range_usize(&self) -> ops::Range<usize> {
-		self.offset as usize..(self.offset.wrapping_add(self.size)) as usize
+	/// First half of the nonce of public sections, see [`is_public`](Self::is_public).
+	pub const PUBLIC_NONCE: u64 = u64::from_le_bytes(*b"PUBLIC\0\0");
+
+	/// Is this a public section?
+	///
+	/// Public sections are stored as plaintext authenticated by their MAC, see [`MemoryEditFile::set_public`].
+	/// Their contents can be read directly from the PAK file without the key, the key is still required to verify the MAC.
+	#[inline]
+	pub fn is_public(&self) -> bool {
+		self.nonce[0] == Section::PUBLIC_NONCE
+	}
+
+	/// Verifies the MAC of the encrypted contents of the section without decrypting them.
+	///
+	/// The blocks are the raw contents of the section as stored in the PAK file, eg. from [`FileReader::raw_blocks`].
+	/// The key is required to compute the MAC, but the plaintext is never produced.
+	#[inline]
+	pub fn verify_mac(&self, blocks: &[Block], key: &Key) -> bool {
+		crypt::verify_mac(blocks, self, key)
+	}
+
+	// Range of `Block`s covered by the section in a PAK file with the given block size
+	fn range_usize(&self, block_size: u32) -> ops::Range<usize> {
+		let scale = block_scale(block_size);
+		self.offset as usize * scale..(self.offset.wrapping_add(self.size)) as usize * scale
 	}
 }
 
@@ -178,8 +294,53 @@ impl fmt::Debug for Section {
 
 unsafe impl Pod for Section {}
 
-fn bytes2blocks(byte_size: u32) -> u32 {
-	if byte_size == 0 { 0 } else { (byte_size - 1) / BLOCK_SIZE as u32 + 1 }
+fn bytes2blocks(byte_size: u32, block_size: u32) -> u32 {
+	if byte_size == 0 { 0 } else { (byte_size - 1) / block_size + 1 }
+}
+
+// Number of `Block`s in a block of the given block size
+#[inline]
+fn block_scale(block_size: u32) -> usize {
+	block_size as usize / BLOCK_SIZE
+}
+
+// Rounds the block index up to a multiple of the alignment in blocks
+#[inline]
+fn align_up(index: u32, align: u32) -> u32 {
+	index.saturating_add(align - 1) / align * align
+}
+
+// Copies the bytes into uninitialized memory of the same length
+#[inline]
+fn uninit_copy(dest: &mut [std::mem::MaybeUninit<u8>], src: &[u8]) {
+	for (dest, &byte) in dest.iter_mut().zip(src) {
+		*dest = std::mem::MaybeUninit::new(byte);
+	}
+}
+
+// Bump allocates the given number of blocks after the high mark
+// Returns the offset of the allocation and the new high mark, `None` if it overflows or ends past the max mark
+#[inline]
+fn bump_allocate(high_mark: u32, size: u32, align: u32, max_mark: u32) -> Option<(u32, u32)> {
+	let offset = align_up(high_mark, align);
+	let end = offset.checked_add(size)?;
+	if end > max_mark {
+		return None;
+	}
+	Some((offset, end))
+}
+
+// Number of blocks occupied by the header
+#[inline]
+fn header_blocks(block_size: u32) -> u32 {
+	bytes2blocks(mem::size_of::<Header>() as u32, block_size)
+}
+
+// Number of blocks occupied by a directory with the given number of descriptors
+#[inline]
+fn directory_blocks(dir_size: u32, block_size: u32) -> u32 {
+	let bytes = dir_size as u64 * mem::size_of::<Descriptor>() as u64;
+	bytes.div_ceil(block_size as u64) as u32
 }
 
 //----------------------------------------------------------------
@@ -190,7 +351,14 @@ fn bytes2blocks(byte_size: u32) -> u32 {
 pub struct InfoHeader {
 	/// Version info value, should be equal to [`VERSION`](Self::VERSION).
 	pub version: u32,
-	pub _unused: u32,
+	/// Size in bytes of the blocks, one of [`BLOCK_SIZES`](Self::BLOCK_SIZES).
+	///
+	/// Larger blocks trade addressing granularity for a larger maximum PAK file size.
+	/// 16 byte blocks cap the PAK file at 64 GiB, 256 byte blocks at 1 TiB.
+	/// PAK files created before this field existed store zero, which means 16 byte blocks.
+	///
+	/// The upper 8 bits hold the header [flags](Self::flags), see [`block_size`](Self::block_size()).
+	pub block_size: u32,
 	/// The section object describing the location of the directory.
 	///
 	/// Special note: the section size specifies the number of `Descriptors` not the number of blocks.
@@ -203,12 +371,45 @@ impl InfoHeader {
 	/// Note that this PAK library is endian sensitive.
 	/// When inspecting PAK files on a machine with incorrect endianness the version check will fail.
 	pub const VERSION: u32 = u32::from_ne_bytes(*b"PAK1");
+
+	/// Supported block sizes in bytes.
+	pub const BLOCK_SIZES: [u32; 3] = [16, 64, 256];
+
+	/// Flag of PAK files composing the names of the descriptors, see [`NamePolicy::nfc`].
+	pub const FLAG_NFC: u8 = 0x01;
+
+	/// Flag of PAK files folding the names of the descriptors to lowercase, see [`NamePolicy::case_fold`].
+	pub const FLAG_CASE_FOLD: u8 = 0x02;
+
+	/// Flag of PAK files replacing the paths by their hashes, see [`NamePolicy::stripped`].
+	pub const FLAG_STRIPPED: u8 = 0x04;
+
+	/// Gets the size in bytes of the blocks without the flags.
+	#[inline]
+	pub fn block_size(&self) -> u32 {
+		self.block_size & 0x00ffffff
+	}
+
+	/// Gets the header flags.
+	///
+	/// The flags are stored in the upper 8 bits of the `block_size`.
+	#[inline]
+	pub fn flags(&self) -> u8 {
+		(self.block_size >> 24) as u8
+	}
+
+	/// Sets the header flags.
+	#[inline]
+	pub fn set_flags(&mut self, flags: u8) {
+		self.block_size = self.block_size & 0x00ffffff | (flags as u32) << 24;
+	}
 }
 
 impl fmt::Debug for InfoHeader {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
 		f.debug_struct("InfoHeader")
 			.field("version", &self.version)
+			.field("block_size", &self.block_size)
 			.field("directory", &self.directory)
 			.finish()
 	}
@@ -258,7 +459,10 @@ pub struct Descriptor {
 	pub section: Section,
 	/// The name of the descriptor, see [`name`](Self::name).
 	pub name: Name,
-	/// Extra meta section object, unused for now.
+	/// Meta section object holding the [metadata](MetaMap) of the file, see [`meta_section`](Self::meta_section).
+	///
+	/// The upper 8 bits of its size hold the descriptor [flags](Self::flags), the lower 24 bits the size of the meta section.
+	/// Packed file descriptors store their [content offset](Self::content_offset) in its offset and have no meta section.
 	pub meta: Section,
 }
 
@@ -304,6 +508,116 @@ impl Descriptor {
 	pub fn is_file(&self) -> bool {
 		self.content_type != 0
 	}
+
+	/// Content type of file descriptors holding a nested PAK file, see [`MemoryReader::open_nested`].
+	pub const CONTENT_TYPE_PAK: u32 = u32::from_le_bytes(*b"PAK\0");
+
+	/// Is this a file descriptor holding a nested PAK file?
+	#[inline]
+	pub fn is_nested(&self) -> bool {
+		self.content_type == Descriptor::CONTENT_TYPE_PAK
+	}
+
+	/// Is this a file descriptor stored as plaintext, see [`Section::is_public`]?
+	#[inline]
+	pub fn is_public(&self) -> bool {
+		self.is_file() && self.section.is_public()
+	}
+
+	/// Flag of sealed directory descriptors, see [`Directory::seal`].
+	pub const FLAG_SEALED: u8 = 0x01;
+
+	/// Gets the descriptor flags.
+	///
+	/// The flags are stored in the upper 8 bits of the size of the `meta` section.
+	#[inline]
+	pub fn flags(&self) -> u8 {
+		(self.meta.size >> 24) as u8
+	}
+
+	/// Sets the descriptor flags.
+	#[inline]
+	pub fn set_flags(&mut self, flags: u8) {
+		self.meta.size = self.meta.size & 0x00ffffff | (flags as u32) << 24;
+	}
+
+	/// Is this a sealed directory descriptor?
+	#[inline]
+	pub fn is_sealed(&self) -> bool {
+		self.is_dir() && self.flags() & Descriptor::FLAG_SEALED != 0
+	}
+
+	/// Flag of file descriptors packed into a shared section, see [`MemoryEditor::pack_files`].
+	pub const FLAG_PACKED: u8 = 0x02;
+
+	/// Is this a file descriptor packed into a shared section?
+	#[inline]
+	pub fn is_packed(&self) -> bool {
+		self.is_file() && self.flags() & Descriptor::FLAG_PACKED != 0
+	}
+
+	/// Flag of pinned file descriptors whose section is never relocated, see [`MemoryEditFile::pin`].
+	pub const FLAG_PINNED: u8 = 0x04;
+
+	/// Is this a pinned file descriptor?
+	#[inline]
+	pub fn is_pinned(&self) -> bool {
+		self.is_file() && self.flags() & Descriptor::FLAG_PINNED != 0
+	}
+
+	/// Flag of decoy file descriptors, see [`MemoryEditor::set_obfuscation`].
+	pub const FLAG_DECOY: u8 = 0x08;
+
+	/// Is this a decoy file descriptor?
+	#[inline]
+	pub fn is_decoy(&self) -> bool {
+		self.is_file() && self.flags() & Descriptor::FLAG_DECOY != 0
+	}
+
+	/// Byte offset of the file contents in its decrypted section.
+	///
+	/// Zero unless the file is [packed](Self::is_packed).
+	#[inline]
+	pub fn content_offset(&self) -> u32 {
+		if self.is_packed() { self.meta.offset } else { 0 }
+	}
+
+	/// Gets the meta section object holding the [metadata](MetaMap) of the file.
+	///
+	/// Returns `None` if this is not a file descriptor, the file is packed or it has no metadata.
+	#[inline]
+	pub fn meta_section(&self) -> Option<Section> {
+		let size = self.meta.size & 0x00ffffff;
+		if !self.is_file() || self.is_packed() || size == 0 {
+			return None;
+		}
+		Some(Section { size, ..self.meta })
+	}
+
+	// Assigns the meta section object keeping the flags
+	// The size of the meta section must fit in 24 bits
+	fn set_meta_section(&mut self, section: &Section) {
+		let flags = self.flags();
+		self.meta = *section;
+		self.set_flags(flags);
+	}
+
+	// Extracts the file contents from its decrypted section
+	fn content<'a>(&self, data: &'a [u8]) -> &'a [u8] {
+		let start = usize::min(data.len(), self.content_offset() as usize);
+		let end = usize::min(data.len(), start + self.content_size as usize);
+		&data[start..end]
+	}
+
+	// Forgets the file is packed when it is assigned its own section
+	// Packed files store their content offset instead of metadata, the metadata of other files is kept
+	fn unpack(&mut self) {
+		if self.is_packed() {
+			let flags = self.flags() & !Descriptor::FLAG_PACKED;
+			self.meta = Section::default();
+			self.set_flags(flags);
+		}
+	}
 }
 
 impl fmt::Debug for Descriptor {
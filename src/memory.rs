@@ -1,60 +1,129 @@
-use std::slice;
+use std::{io, ops};
 use std::io::ErrorKind;
 use crate::*;
 
 // Decrypts and authenticates a section.
 // Returns an error if the section range or MAC is incorrect.
-fn read_section(blocks: &[Block], section: &Section, key: &Key) -> Result<Vec<Block>, ErrorKind> {
-	let blocks = match blocks.get(section.range_usize()) {
+fn read_section(blocks: &[Block], block_size: u32, section: &Section, key: &Key) -> Result<Vec<Block>, ErrorKind> {
+	let mut dest = Vec::new();
+	read_section_into(blocks, block_size, section, key, &mut dest)?;
+	Ok(dest)
+}
+
+// Same as `read_section` reusing the allocation of the destination
+fn read_section_into(blocks: &[Block], block_size: u32, section: &Section, key: &Key, dest: &mut Vec<Block>) -> Result<(), ErrorKind> {
+	let span = span!(DEBUG, "read_section", offset = section.offset, size = section.size);
+
+	let blocks = match blocks.get(section.range_usize(block_size)) {
 		Some(blocks) => blocks,
 		None => return Err(ErrorKind::InvalidInput),
 	};
 
-	let mut blocks = blocks.to_vec();
-	if !crypt::decrypt_section(&mut blocks, section, key) {
+	dest.clear();
+	dest.extend_from_slice(blocks);
+	if !crypt::decrypt_section(dest, section, key) {
 		return Err(ErrorKind::InvalidData);
 	}
 
-	Ok(blocks)
+	span.finish((dest.len() * BLOCK_SIZE) as u64);
+	Ok(())
+}
+
+// Size in blocks of the chunks decrypted by `read_section_range`
+const STREAM_BLOCKS: usize = 16 * 1024 / BLOCK_SIZE;
+
+// Decrypts the section in chunks and passes the decrypted bytes in the byte range of the section to the callback with their offset in the range
+// The MAC is checked before decrypting, the callback only sees authenticated plaintext
+fn read_section_range<F: FnMut(usize, &[u8])>(blocks: &[Block], block_size: u32, section: &Section, key: &Key, range: ops::Range<usize>, mut f: F) -> Result<(), ErrorKind> {
+	let span = span!(DEBUG, "read_section", offset = section.offset, size = section.size);
+
+	let blocks = match blocks.get(section.range_usize(block_size)) {
+		Some(blocks) => blocks,
+		None => return Err(ErrorKind::InvalidInput),
+	};
+	if range.start > range.end || range.end > blocks.len() * BLOCK_SIZE {
+		return Err(ErrorKind::InvalidInput);
+	}
+	// The section is in memory, authenticate it before passing on any plaintext
+	if !crypt::verify_mac(blocks, section, key) {
+		return Err(ErrorKind::InvalidData);
+	}
+
+	let mut decryptor = crypt::Decryptor::new(section, key);
+	let mut chunk = [Block::default(); STREAM_BLOCKS];
+	for (i, src) in blocks.chunks(STREAM_BLOCKS).enumerate() {
+		let chunk = &mut chunk[..src.len()];
+		chunk.copy_from_slice(src);
+		decryptor.update(chunk);
+
+		// Pass on the part of the chunk in the range
+		let chunk_start = i * STREAM_BLOCKS * BLOCK_SIZE;
+		let start = usize::max(range.start, chunk_start);
+		let end = usize::min(range.end, chunk_start + chunk.len() * BLOCK_SIZE);
+		if start < end {
+			f(start - range.start, &chunk.as_bytes()[start - chunk_start..end - chunk_start]);
+		}
+	}
+
+	if !decryptor.finish() {
+		return Err(ErrorKind::InvalidData);
+	}
+
+	span.finish((blocks.len() * BLOCK_SIZE) as u64);
+	Ok(())
+}
+
+// Writes the encrypted blocks of the section as-is.
+fn copy_section_raw(blocks: &[Block], block_size: u32, section: &Section, writer: &mut dyn io::Write) -> io::Result<()> {
+	let blocks = match blocks.get(section.range_usize(block_size)) {
+		Some(blocks) => blocks,
+		None => Err(io::ErrorKind::InvalidInput)?,
+	};
+	writer.write_all(blocks.as_bytes())
 }
 
 // Decrypts and authenticates the header and the directory.
 // Returns an the original blocks on any bounds errors or MAC checks fail.
 fn from_blocks(mut blocks: Vec<Block>, key: &Key) -> Result<(Vec<Block>, Directory), Vec<Block>> {
-	// The blocks must contain at least space for the header ref$1
-	if blocks.len() < Header::BLOCKS_LEN {
-		return Err(blocks);
-	}
+	let span = span!(DEBUG, "open", len = blocks.len());
 
 	// Decrypt the header
-	let mut header: Header = blocks.as_data_view_mut().copy(0);
-	if !crypt::decrypt_header(&mut header, key) {
-		// MAC is incorrect!
-		return Err(blocks);
-	}
+	// The blocks must contain at least space for the header ref$1
+	let header = match parse::header(&blocks, key) {
+		Some(header) => header,
+		// Not enough blocks or the MAC is incorrect!
+		None => return Err(blocks),
+	};
 
 	// Extract the directory
-	let dir_start = header.info.directory.offset as usize;
-	let dir_end = dir_start + header.info.directory.size as usize * Descriptor::BLOCKS_LEN;
-	let dir_blocks = match blocks.get_mut(dir_start..dir_end) {
-		Some(dir_blocks) => dir_blocks,
+	let dir_range = match parse::directory_range(&header.info) {
+		Some(dir_range) => dir_range,
+		None => return Err(blocks),
+	};
+	let (dir_start, dir_end) = (dir_range.start, dir_range.end);
+	let mut dir_blocks = match blocks.get(dir_range) {
+		Some(dir_blocks) => dir_blocks.to_vec(),
 		None => return Err(blocks),
 	};
 
 	// Decrypt the directory
-	crypt::decrypt_section(dir_blocks, &header.info.directory, key);
+	if !crypt::decrypt_section(&mut dir_blocks, &header.info.directory, key) {
+		return Err(blocks);
+	}
 
-	// Reinterpret the directory
-	let dir = unsafe {
-		slice::from_raw_parts(dir_blocks.as_ptr() as *const Descriptor, header.info.directory.size as usize)
-	};
-	let directory = Directory::from(dir.to_vec());
+	// Copy the descriptors out of the directory
+	let mut directory = Directory::from(parse::descriptors(&dir_blocks));
+	directory.block_size = header.info.block_size();
+	directory.set_name_flags(header.info.flags());
+	directory.index_tags(|section| read_section(&blocks, header.info.block_size(), section, key).ok());
 
-	// Truncate the blocks to trim the directory
-	if blocks.len() == dir_end {
+	// Truncate the blocks to trim the directory, including the padding of its last block
+	let scale = block_scale(header.info.block_size());
+	if blocks.len() >= dir_end && blocks.len() - dir_end < scale {
 		blocks.truncate(dir_start);
 	}
 
+	span.finish((dir_blocks.len() * BLOCK_SIZE) as u64);
 	Ok((blocks, directory))
 }
 
@@ -72,10 +141,15 @@ pub fn as_bytes_mut(blocks: &mut [Block]) -> &mut [u8] {
 mod reader;
 mod editor;
 mod edit_file;
+mod layout;
+mod shared;
+mod key_slots;
 
 pub use self::reader::*;
 pub use self::editor::*;
 pub use self::edit_file::*;
+pub use self::layout::*;
+pub use self::shared::*;
 
 #[cfg(test)]
 mod tests;
@@ -1,3 +1,4 @@
+use std::{convert::TryFrom, io::ErrorKind};
 use crate::*;
 
 /// Memory file editor.
@@ -7,6 +8,9 @@ use crate::*;
 pub struct MemoryEditFile<'a> {
 	pub(super) desc: &'a mut Descriptor,
 	pub(super) blocks: &'a mut Vec<Block>,
+	pub(super) block_size: u32,
+	pub(super) align: u32,
+	pub(super) max_mark: u32,
 }
 
 impl<'a> MemoryEditFile<'a> {
@@ -30,6 +34,7 @@ impl<'a> MemoryEditFile<'a> {
 	/// This can be used to make different descriptors point to the same file contents.
 	pub fn set_section(&mut self, section: &Section) -> &mut MemoryEditFile<'a> {
 		self.desc.section = *section;
+		self.desc.unpack();
 		return self;
 	}
 
@@ -38,21 +43,92 @@ impl<'a> MemoryEditFile<'a> {
 	/// The size allocated is defined by a previous call to `set_content`'s content_size argument.
 	///
 	/// The space allocated is logically uninitialized and must be initialized with a call to `write_data` or `init_zero`.
-	pub fn allocate_data(&mut self) -> &mut MemoryEditFile<'a> {
-		let size = bytes2blocks(self.desc.content_size);
-
-		// Simple bump allocate from the blocks Vec
-		self.desc.section.offset = self.blocks.len() as u32;
+	///
+	/// Returns [`ErrorKind::OutOfMemory`] and allocates nothing if the allocation overflows the high mark or exceeds the [maximum size](MemoryEditor::set_max_size).
+	pub fn allocate_data(&mut self) -> Result<&mut MemoryEditFile<'a>, ErrorKind> {
+		let size = bytes2blocks(self.desc.content_size, self.block_size);
+		self.desc.section.offset = self.allocate(size)?;
 		self.desc.section.size = size;
+		// New file data is encrypted unless made public again
+		self.desc.section.nonce = Block::default();
+		self.desc.unpack();
+		Ok(self)
+	}
 
-		// In the case of overflow... Do nothing?
-		// Writing data into the allocation will fail
-		if let Some(new_len) = self.blocks.len().checked_add(size as usize) {
-			// Should be overwritten by `write_data` or `zero_data`
-			self.blocks.resize(new_len, Block::default());
-		}
+	/// Stores the file data as plaintext authenticated by its MAC.
+	///
+	/// Public files such as a readme or version number can be read directly from the PAK file by tools without the key, see [`Section::is_public`].
+	/// The directory is still encrypted, locating the file without the key requires knowing its section.
+	///
+	/// Takes effect when the data is written, call after [`allocate_data`](Self::allocate_data) which makes the file data encrypted again.
+	/// Reencrypting or shredding a public file keeps it public.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// let ref key = paks::Key::default();
+	/// let mut editor = paks::MemoryEditor::new();
+	///
+	/// let mut edit_file = editor.edit_file(b"version.txt");
+	/// edit_file.set_content(1, 5).allocate_data().unwrap().set_public(true).write_data(b"1.0.2", key);
+	/// let desc = *edit_file.descriptor();
+	/// assert!(desc.is_public());
+	///
+	/// // The contents are stored as plaintext
+	/// let (blocks, _) = editor.finish(key);
+	/// let offset = desc.section.offset as usize * 16;
+	/// assert_eq!(&paks::as_bytes(&blocks)[offset..offset + 5], b"1.0.2");
+	/// ```
+	pub fn set_public(&mut self, public: bool) -> &mut MemoryEditFile<'a> {
+		self.desc.section.nonce[0] = if public { Section::PUBLIC_NONCE } else { 0 };
+		self
+	}
 
-		return self;
+	/// Pins the file data to its offset in the PAK file.
+	///
+	/// Garbage collection and compaction never relocate the sections of a pinned file, other file data is moved around them.
+	/// Pin assets referenced by absolute offset from outside the PAK file, eg. by a memory mapped runtime index.
+	/// Pinned files are not [packed](MemoryEditor::pack_files) or [deduplicated](MemoryEditor::gc_dedup).
+	///
+	/// The pin is recorded in the descriptor flags and kept when the file data is reallocated, which does move the file data.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// let ref key = paks::Key::default();
+	/// let mut editor = paks::MemoryEditor::new();
+	/// editor.create_file(b"garbage", &[0; 100], key);
+	/// editor.create_file(b"index.bin", b"runtime index", key);
+	/// editor.edit_file(b"index.bin").pin();
+	/// let section = editor.find_file(b"index.bin").unwrap().section;
+	///
+	/// editor.remove(b"garbage");
+	/// editor.gc();
+	/// assert_eq!(editor.find_file(b"index.bin").unwrap().section, section);
+	/// ```
+	pub fn pin(&mut self) -> &mut MemoryEditFile<'a> {
+		self.desc.set_flags(self.desc.flags() | Descriptor::FLAG_PINNED);
+		self
+	}
+
+	/// Unpins the file data, garbage collection may relocate it again.
+	pub fn unpin(&mut self) -> &mut MemoryEditFile<'a> {
+		self.desc.set_flags(self.desc.flags() & !Descriptor::FLAG_PINNED);
+		self
+	}
+
+	// Allocates the given number of blocks and returns their offset
+	fn allocate(&mut self, size: u32) -> Result<u32, ErrorKind> {
+		let scale = block_scale(self.block_size);
+
+		// Simple bump allocate from the blocks Vec
+		let high_mark = u32::try_from(self.blocks.len().div_ceil(scale)).map_err(|_| ErrorKind::OutOfMemory)?;
+		let (offset, end) = bump_allocate(high_mark, size, self.align, self.max_mark).ok_or(ErrorKind::OutOfMemory)?;
+		let new_len = (end as usize).checked_mul(scale).ok_or(ErrorKind::OutOfMemory)?;
+
+		// Should be overwritten by `write_data` or `zero_data`
+		self.blocks.resize(new_len, Block::default());
+		Ok(offset)
 	}
 
 	/// Copies and encrypts the data with the given key into the address specified by this file descriptor.
@@ -61,7 +137,7 @@ impl<'a> MemoryEditFile<'a> {
 	///
 	/// This method assumes the section is correctly initialized (either through `set_section` or `allocate`).
 	pub fn write_data(&mut self, data: &[u8], key: &Key) -> &mut MemoryEditFile<'a> {
-		let blocks = &mut self.blocks[self.desc.section.range_usize()];
+		let blocks = &mut self.blocks[self.desc.section.range_usize(self.block_size)];
 
 		// Copy the data into the allocation
 		let len = usize::min(blocks.as_bytes().len(), data.len());
@@ -75,7 +151,7 @@ impl<'a> MemoryEditFile<'a> {
 
 	/// Initialize the data with zeroes.
 	pub fn zero_data(&mut self, key: &Key) -> &mut MemoryEditFile<'a> {
-		let blocks = &mut self.blocks[self.desc.section.range_usize()];
+		let blocks = &mut self.blocks[self.desc.section.range_usize(self.block_size)];
 
 		// Zero the data
 		blocks.fill(Block::default());
@@ -94,7 +170,7 @@ impl<'a> MemoryEditFile<'a> {
 	///
 	/// This method assumes the section is correctly initialized (either through `set_section` or `allocate`).
 	pub fn reencrypt_data(&mut self, old_key: &Key, key: &Key) {
-		let blocks = &mut self.blocks[self.desc.section.range_usize()];
+		let blocks = &mut self.blocks[self.desc.section.range_usize(self.block_size)];
 
 		let old_mac = self.desc.section.mac;
 
@@ -107,4 +183,82 @@ impl<'a> MemoryEditFile<'a> {
 			self.desc.section.mac = old_mac;
 		}
 	}
+
+	/// Decrypts the [metadata](MetaMap) of the file.
+	///
+	/// Returns an empty key-value store if the file has no metadata.
+	///
+	/// # Errors
+	///
+	/// * [`ErrorKind::InvalidInput`]: The meta section is out of bounds.
+	/// * [`ErrorKind::InvalidData`]: The MAC of the meta section is incorrect or its contents are corrupted.
+	pub fn read_meta(&self, key: &Key) -> Result<MetaMap, ErrorKind> {
+		let section = match self.desc.meta_section() {
+			Some(section) => section,
+			None => return Ok(MetaMap::new()),
+		};
+		let blocks = super::read_section(self.blocks, self.block_size, &section, key)?;
+		MetaMap::decode(blocks.as_bytes()).ok_or(ErrorKind::InvalidData)
+	}
+
+	/// Allocates, encrypts and assigns a new meta section holding the [metadata](MetaMap).
+	///
+	/// The previous meta section is left behind as garbage, an empty key-value store removes the metadata.
+	/// The metadata is kept when assigning new contents to the file with `allocate_data` or `set_section`.
+	///
+	/// # Errors
+	///
+	/// * [`ErrorKind::InvalidInput`]: The file is [packed](Descriptor::is_packed) or the encoded metadata is too large for a meta section.
+	/// * [`ErrorKind::OutOfMemory`]: The meta section does not fit in the PAK file or exceeds the [maximum size](MemoryEditor::set_max_size).
+	pub fn set_meta(&mut self, meta: &MetaMap, key: &Key) -> Result<&mut MemoryEditFile<'a>, ErrorKind> {
+		if self.desc.is_packed() {
+			return Err(ErrorKind::InvalidInput);
+		}
+		let data = meta.encode();
+		let size = match u32::try_from(data.len()) {
+			Ok(len) if bytes2blocks(len, self.block_size) <= 0x00ffffff => bytes2blocks(len, self.block_size),
+			_ => return Err(ErrorKind::InvalidInput),
+		};
+
+		let mut section = Section { size, ..Section::default() };
+		if size != 0 {
+			section.offset = self.allocate(size)?;
+			let blocks = match self.blocks.get_mut(section.range_usize(self.block_size)) {
+				Some(blocks) => blocks,
+				None => return Err(ErrorKind::OutOfMemory),
+			};
+			blocks.as_bytes_mut()[..data.len()].copy_from_slice(&data);
+			crypt::encrypt_section(blocks, &mut section, key);
+		}
+		self.desc.set_meta_section(&section);
+		Ok(self)
+	}
+
+	/// Stores a small preview of the file in its [metadata](MetaMap::PREVIEW), eg. a texture thumbnail.
+	///
+	/// Asset browsers can read the preview without decrypting the file contents, see [`MemoryReader::read_preview`].
+	/// The other metadata of the file is kept.
+	///
+	/// # Errors
+	///
+	/// See [`read_meta`](Self::read_meta) and [`set_meta`](Self::set_meta).
+	pub fn set_preview(&mut self, preview: &[u8], key: &Key) -> Result<&mut MemoryEditFile<'a>, ErrorKind> {
+		let mut meta = self.read_meta(key)?;
+		meta.set_bytes(MetaMap::PREVIEW, preview);
+		self.set_meta(&meta, key)
+	}
+
+	/// Records the paths of the files the file depends on in its [metadata](MetaMap::dependencies).
+	///
+	/// Engines can prefetch everything a file needs, see [`MemoryReader::resolve_dependencies`].
+	/// The other metadata of the file is kept.
+	///
+	/// # Errors
+	///
+	/// See [`read_meta`](Self::read_meta) and [`set_meta`](Self::set_meta).
+	pub fn set_dependencies(&mut self, paths: &[&[u8]], key: &Key) -> Result<&mut MemoryEditFile<'a>, ErrorKind> {
+		let mut meta = self.read_meta(key)?;
+		meta.set_dependencies(paths);
+		self.set_meta(&meta, key)
+	}
 }
@@ -1,14 +1,17 @@
-use std::ops;
+use std::{collections::HashMap, fmt, ops, sync::Arc};
 use crate::*;
 use super::*;
 
 /// Memory editor.
 ///
 /// This implementation keeps the entire PAK file in memory.
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub struct MemoryEditor {
 	pub(super) blocks: Vec<Block>,
 	pub(super) directory: Directory,
+	pub(crate) policy: Policy,
+	// Logged changes appended to the changelog by finish
+	pub(crate) changelog: Vec<ChangeEntry>,
 }
 
 impl MemoryEditor {
@@ -17,7 +20,18 @@ impl MemoryEditor {
 		// The blocks must contain at least space for the header ref$1
 		let blocks = vec![Block::default(); Header::BLOCKS_LEN];
 		let directory = Directory::from(Vec::new());
-		MemoryEditor { blocks, directory }
+		MemoryEditor { blocks, directory, policy: Policy::default(), changelog: Vec::new() }
+	}
+
+	/// Creates a new `MemoryEditor` instance with room for the given number of blocks.
+	///
+	/// Reserving the expected size of the PAK file up-front avoids reallocating the blocks as files are added.
+	/// The capacity includes the header and the directory appended by [`finish`](Self::finish).
+	pub fn with_capacity(capacity: usize) -> MemoryEditor {
+		let mut blocks = Vec::with_capacity(usize::max(Header::BLOCKS_LEN, capacity));
+		blocks.resize(Header::BLOCKS_LEN, Block::default());
+		let directory = Directory::from(Vec::new());
+		MemoryEditor { blocks, directory, policy: Policy::default(), changelog: Vec::new() }
 	}
 
 	/// Parses the bytes as the PAK file format for editing.
@@ -44,14 +58,79 @@ impl MemoryEditor {
 		blocks.as_bytes_mut()[..bytes.len()].copy_from_slice(bytes);
 
 		match from_blocks(blocks, key) {
-			Ok((blocks, directory)) => Ok(MemoryEditor { blocks, directory }),
-			Err(_) => unimplemented!(),
+			Ok((blocks, directory)) => Ok(MemoryEditor { blocks, directory, policy: Policy::default(), changelog: Vec::new() }),
+			Err(_) => Err(ErrorKind::InvalidData),
 		}
 	}
 
 	/// Parses the blocks as the PAK file format for editing.
 	pub fn from_blocks(blocks: Vec<Block>, key: &Key) -> Result<MemoryEditor, Vec<Block>> {
-		from_blocks(blocks, key).map(|(blocks, directory)| MemoryEditor { blocks, directory })
+		from_blocks(blocks, key).map(|(blocks, directory)| MemoryEditor { blocks, directory, policy: Policy::default(), changelog: Vec::new() })
+	}
+
+	/// Edits the PAK file opened by the reader.
+	///
+	/// The decrypted directory and the blocks of the reader are reused, nothing is parsed or decrypted again.
+	/// They are copied if shared with clones of the reader.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// let ref key = paks::Key::default();
+	/// let mut editor = paks::MemoryEditor::new();
+	/// editor.create_file(b"a", b"data", key);
+	/// let (blocks, _) = editor.finish(key);
+	///
+	/// // Inspect then edit
+	/// let reader = paks::MemoryReader::from_blocks(blocks, key).unwrap();
+	/// assert!(reader.find_file(b"b").is_none());
+	/// let mut editor = paks::MemoryEditor::from_reader(reader);
+	/// editor.create_file(b"b", b"more", key);
+	///
+	/// // And back for reading without encrypting the directory
+	/// let reader = editor.into_reader();
+	/// let desc = reader.find_file(b"b").unwrap();
+	/// assert_eq!(reader.read_data(desc, key).unwrap(), b"more");
+	/// ```
+	pub fn from_reader(reader: MemoryReader) -> MemoryEditor {
+		let MemoryReader { blocks, directory } = reader;
+		// Copied only if shared with clones of the reader
+		let blocks = Arc::try_unwrap(blocks).unwrap_or_else(|blocks| (*blocks).clone());
+		let mut directory = Arc::try_unwrap(directory).unwrap_or_else(|directory| (*directory).clone());
+		// Same as parsing the blocks for editing
+		directory.set_sorted(false);
+		MemoryEditor { blocks, directory, policy: Policy::default(), changelog: Vec::new() }
+	}
+
+	/// Reads the PAK file being edited.
+	///
+	/// The directory and the blocks are reused without finishing, the pending changes are visible to the reader.
+	/// Nothing done by [`finish`](Self::finish) is applied, eg. no parity is appended.
+	pub fn into_reader(self) -> MemoryReader {
+		let MemoryEditor { blocks, mut directory, .. } = self;
+		directory.detect_sorted();
+		MemoryReader { blocks: Arc::new(blocks), directory: Arc::new(directory) }
+	}
+}
+
+impl MemoryEditor {
+	fn summary(&self) -> summary::Summary<'_> {
+		summary::Summary {
+			name: "MemoryEditor",
+			info: None,
+			directory: &self.directory,
+			high_mark: self.high_mark(),
+		}
+	}
+}
+impl fmt::Debug for MemoryEditor {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		fmt::Debug::fmt(&self.summary(), f)
+	}
+}
+impl fmt::Display for MemoryEditor {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		fmt::Display::fmt(&self.summary(), f)
 	}
 }
 
@@ -72,16 +151,80 @@ impl MemoryEditor {
 	/// Highest block index containing file data.
 	#[inline]
 	pub fn high_mark(&self) -> u32 {
-		self.blocks.len() as u32
+		let scale = block_scale(self.directory.block_size);
+		self.blocks.len().div_ceil(scale) as u32
+	}
+
+	/// Sets the block size of the PAK file.
+	///
+	/// The block size must be one of [`InfoHeader::BLOCK_SIZES`] and can only be changed before any file data is allocated.
+	/// Returns `false` and does nothing otherwise.
+	///
+	/// See [`InfoHeader::block_size`] for more information.
+	pub fn set_block_size(&mut self, block_size: u32) -> bool {
+		let block_size_ok = InfoHeader::BLOCK_SIZES.contains(&block_size);
+		if !block_size_ok || self.high_mark() > header_blocks(self.directory.block_size) {
+			return false;
+		}
+		self.directory.block_size = block_size;
+		self.blocks.resize(header_blocks(block_size) as usize * block_scale(block_size), Block::default());
+		true
+	}
+
+	/// Aligns the start of newly allocated file sections to the given number of bytes.
+	///
+	/// Aligned file data enables direct I/O and memory mapping of individual files.
+	/// The alignment must be a power of two, alignments smaller than the [block size](Directory::block_size) have no effect.
+	/// Garbage collection keeps the file sections aligned, the padding is not counted as [garbage](Directory::garbage).
+	///
+	/// Returns `false` and does nothing if the alignment is not a power of two.
+	pub fn align_data(&mut self, bytes: u32) -> bool {
+		if !bytes.is_power_of_two() {
+			return false;
+		}
+		self.directory.align = bytes;
+		true
 	}
 
 	/// Creates a file descriptor at the given path.
 	///
 	/// Any missing parent directories are automatically created.
+	/// [Sealed](Directory::seal) directories are ignored.
 	pub fn edit_file(&mut self, path: &[u8]) -> MemoryEditFile<'_> {
+		let block_size = self.directory.block_size;
+		let align = self.directory.align_blocks();
+		let max_mark = self.policy.max_mark(block_size);
 		let desc = self.directory.create(path);
 		let blocks = &mut self.blocks;
-		MemoryEditFile { blocks, desc }
+		MemoryEditFile { blocks, desc, block_size, align, max_mark }
+	}
+
+	/// Edits the existing file at the given path.
+	///
+	/// Unlike [`edit_file`](Self::edit_file) nothing is created, returns `None` if there is no file at the path.
+	/// [Sealed](Directory::seal) directories are ignored.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// let ref key = paks::Key::default();
+	/// let mut editor = paks::MemoryEditor::new();
+	/// editor.create_file(b"config", b"old", key);
+	///
+	/// editor.edit_existing(b"config").unwrap().set_content(1, 3).allocate_data().unwrap().write_data(b"new", key);
+	/// assert!(editor.edit_existing(b"missing").is_none());
+	/// assert!(editor.find(b"missing").is_none());
+	///
+	/// let desc = editor.find_file(b"config").unwrap();
+	/// assert_eq!(editor.read_data(desc, key).unwrap(), b"new");
+	/// ```
+	pub fn edit_existing(&mut self, path: &[u8]) -> Option<MemoryEditFile<'_>> {
+		let block_size = self.directory.block_size;
+		let align = self.directory.align_blocks();
+		let max_mark = self.policy.max_mark(block_size);
+		let desc = self.directory.existing_file_mut(path)?;
+		let blocks = &mut self.blocks;
+		Some(MemoryEditFile { blocks, desc, block_size, align, max_mark })
 	}
 
 	/// Creates a file at the given path.
@@ -92,19 +235,247 @@ impl MemoryEditor {
 	/// Any missing parent directories are automatically created.
 	///
 	/// If the data's len is greater than 4 GiB it is truncated as its size is stored in a `u32`.
-	pub fn create_file(&mut self, path: &[u8], data: &[u8], key: &Key) -> &Descriptor {
+	///
+	/// Returns `None` and does nothing if the path is [sealed](Directory::seal), empty or deeper than [`dir::MAX_DEPTH`],
+	/// the [path hash collides](Directory::hash_collides) with another file,
+	/// the file is rejected by the [insert validator](Self::set_insert_validator) or the data exceeds the [maximum size](Self::set_max_size).
+	pub fn create_file(&mut self, path: &[u8], data: &[u8], key: &Key) -> Option<&Descriptor> {
+		if !self.directory.writable(path) || Directory::invalid_path(path) || self.directory.hash_collides(path) || self.policy.validate(path, data).is_err() {
+			return None;
+		}
+		// Check the allocation before the file descriptor is created
+		let block_size = self.directory.block_size;
+		let size = bytes2blocks(data.len() as u32, block_size);
+		bump_allocate(self.high_mark(), size, self.directory.align_blocks(), self.policy.max_mark(block_size))?;
 		let mut edit_file = self.edit_file(path);
 		edit_file.set_content(1, data.len() as u32);
-		edit_file.allocate_data().write_data(data, key);
-		edit_file.desc
+		edit_file.allocate_data().ok()?.write_data(data, key);
+		Some(edit_file.desc)
+	}
+
+	/// Creates a file at the given path from data encrypted ahead of time.
+	///
+	/// The file is assigned a content_type of `1`.
+	/// See [`create_file`](Self::create_file) for more information.
+	///
+	/// # Errors
+	///
+	/// * [`ErrorKind::PermissionDenied`]: The path is [sealed](Directory::seal).
+	/// * [`ErrorKind::InvalidInput`]: The path is empty or deeper than [`dir::MAX_DEPTH`] or the data was encrypted for a different [block size](Directory::block_size).
+	/// * [`ErrorKind::AlreadyExists`]: The [path hash collides](Directory::hash_collides) with another file.
+	/// * [`ErrorKind::OutOfMemory`]: The data exceeds the [maximum size](Self::set_max_size).
+	pub fn create_encrypted(&mut self, path: &[u8], encrypted: &Encrypted) -> Result<&Descriptor, ErrorKind> {
+		if !self.directory.writable(path) {
+			return Err(ErrorKind::PermissionDenied);
+		}
+		if Directory::invalid_path(path) || encrypted.block_size != self.directory.block_size {
+			return Err(ErrorKind::InvalidInput);
+		}
+		if self.directory.hash_collides(path) {
+			return Err(ErrorKind::AlreadyExists);
+		}
+		let section = self.import_section_raw(encrypted.blocks.as_bytes(), &encrypted.section.nonce, &encrypted.section.mac)?;
+		let mut edit_file = self.edit_file(path);
+		edit_file.set_content(1, encrypted.content_size).set_section(&section);
+		Ok(edit_file.desc)
+	}
+
+	/// Tags the file at the given path.
+	///
+	/// Tags group files orthogonally to the path hierarchy, find the tagged files with [`Directory::find_by_tag`].
+	/// The tags are stored in the [metadata](MetaMap::tags) of the file, the previous meta section is left behind as garbage.
+	///
+	/// Returns `false` and does nothing if the file already has the tag.
+	///
+	/// # Errors
+	///
+	/// * [`ErrorKind::NotFound`]: There is no file at the given path.
+	/// * [`ErrorKind::PermissionDenied`]: The path is [sealed](Directory::seal).
+	/// * [`ErrorKind::InvalidInput`]: The tag is empty or contains a zero byte, or the file is [packed](Descriptor::is_packed).
+	/// * [`ErrorKind::InvalidData`]: The metadata of the file is corrupted.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// let ref key = paks::Key::default();
+	/// let mut editor = paks::MemoryEditor::new();
+	/// editor.create_file(b"menu/button.png", b"button", key);
+	/// editor.create_file(b"hud/health.png", b"health", key);
+	/// editor.create_file(b"level/wall.png", b"wall", key);
+	/// editor.tag(b"menu/button.png", "ui", key).unwrap();
+	/// editor.tag(b"hud/health.png", "ui", key).unwrap();
+	///
+	/// let (blocks, _) = editor.finish(key);
+	/// let reader = paks::MemoryReader::from_blocks(blocks, key).unwrap();
+	/// let ui: Vec<_> = reader.find_by_tag("ui").into_iter().map(|(path, _)| path).collect();
+	/// assert_eq!(ui, [&b"menu/button.png"[..], b"hud/health.png"]);
+	/// ```
+	pub fn tag(&mut self, path: &[u8], tag: &str, key: &Key) -> Result<bool, ErrorKind> {
+		self.retag(path, tag, true, key)
+	}
+
+	/// Removes the tag from the file at the given path.
+	///
+	/// Returns `false` and does nothing if the file does not have the tag.
+	/// See [`tag`](Self::tag) for more information.
+	pub fn untag(&mut self, path: &[u8], tag: &str, key: &Key) -> Result<bool, ErrorKind> {
+		self.retag(path, tag, false, key)
+	}
+
+	fn retag(&mut self, path: &[u8], tag: &str, add: bool, key: &Key) -> Result<bool, ErrorKind> {
+		if tag.is_empty() || tag.contains('\0') {
+			return Err(ErrorKind::InvalidInput);
+		}
+		if !self.directory.writable(path) {
+			return Err(ErrorKind::PermissionDenied);
+		}
+		let id = match self.directory.find(path) {
+			Some(id) if matches!(self.directory.get(id), Some(desc) if desc.is_file()) => id,
+			_ => return Err(ErrorKind::NotFound),
+		};
+		let block_size = self.directory.block_size;
+		let align = self.directory.align_blocks();
+		let desc = self.directory.get_mut(id).ok_or(ErrorKind::NotFound)?;
+		let max_mark = self.policy.max_mark(block_size);
+		let mut edit_file = MemoryEditFile { desc, blocks: &mut self.blocks, block_size, align, max_mark };
+		let mut meta = edit_file.read_meta(key)?;
+		if !meta.retag(tag, add) {
+			return Ok(false);
+		}
+		edit_file.set_meta(&meta, key)?;
+		self.directory.index_tag(id, tag, add);
+		Ok(true)
+	}
+
+	/// Packs small files into shared sections.
+	///
+	/// Every file is encrypted in its own section, costing a nonce and MAC and padding its contents to a whole block.
+	/// Packing coalesces the contents of files up to `max_size` bytes into sections of up to [`PACKED_SECTION_SIZE`](Self::PACKED_SECTION_SIZE) bytes.
+	/// The packed files share the section and store the offset of their contents, see [`Descriptor::content_offset`].
+	///
+	/// The files must be encrypted with the given key, files which fail to decrypt and files with [metadata](Descriptor::meta_section), [public](Descriptor::is_public) and [pinned](Descriptor::is_pinned) files are left unpacked.
+	/// Returns the number of files packed.
+	/// The sections left behind are garbage, reclaim them with [`gc`](Self::gc).
+	///
+	/// # Examples
+	///
+	/// ```
+	/// let ref key = paks::Key::default();
+	/// let mut editor = paks::MemoryEditor::new();
+	/// editor.create_file(b"a", b"Hello", key);
+	/// editor.create_file(b"b", b"World", key);
+	///
+	/// assert_eq!(editor.pack_files(16, key), 2);
+	/// editor.gc();
+	///
+	/// let a = editor.find_file(b"a").unwrap();
+	/// let b = editor.find_file(b"b").unwrap();
+	/// assert_eq!(a.section, b.section);
+	/// assert_eq!(editor.read_data(b, key).unwrap(), b"World");
+	/// ```
+	pub fn pack_files(&mut self, max_size: u32, key: &Key) -> usize {
+		let block_size = self.directory.block_size;
+
+		// Concatenate the contents of the small files
+		// Files sharing a section are packed once
+		let mut packs: Vec<Vec<u8>> = vec![Vec::new()];
+		let mut packed = HashMap::new();
+		let mut assigned = Vec::new();
+		for (i, desc) in self.directory.as_ref().iter().enumerate() {
+			// Packed files have no room for a meta section
+			if !desc.is_file() || desc.is_packed() || desc.is_pinned() || desc.is_public() || desc.meta_section().is_some() || desc.content_size == 0 || desc.content_size > max_size {
+				continue;
+			}
+			if let Some(&pos) = packed.get(&desc.section) {
+				assigned.push((i, pos));
+				continue;
+			}
+			let blocks = match read_section(&self.blocks, block_size, &desc.section, key) {
+				Ok(blocks) => blocks,
+				Err(_) => continue,
+			};
+			let data = desc.content(blocks.as_bytes());
+			let last = &packs[packs.len() - 1];
+			if !last.is_empty() && last.len() + data.len() > MemoryEditor::PACKED_SECTION_SIZE as usize {
+				packs.push(Vec::new());
+			}
+			let pack = packs.len() - 1;
+			let pos = (pack, packs[pack].len() as u32);
+			packs[pack].extend_from_slice(data);
+			packed.insert(desc.section, pos);
+			assigned.push((i, pos));
+		}
+
+		if assigned.is_empty() {
+			return 0;
+		}
+
+		// Encrypt the packs in new sections
+		let mut sections = Vec::with_capacity(packs.len());
+		for data in &packs {
+			let mut edit_file = MemoryEditFile {
+				blocks: &mut self.blocks,
+				desc: &mut Descriptor::file(b""),
+				block_size,
+				align: self.directory.align_blocks(),
+				max_mark: self.policy.max_mark(block_size),
+			};
+			match edit_file.set_content(1, data.len() as u32).allocate_data() {
+				Ok(edit_file) => edit_file.write_data(data, key),
+				// The packs exceed the maximum size, leave the files unpacked
+				Err(_) => return 0,
+			};
+			sections.push(edit_file.desc.section);
+		}
+
+		let descs = self.directory.descs_mut();
+		for &(i, (pack, offset)) in &assigned {
+			let desc = &mut descs[i];
+			desc.section = sections[pack];
+			desc.meta.offset = offset;
+			desc.set_flags(desc.flags() | Descriptor::FLAG_PACKED);
+		}
+		assigned.len()
 	}
 
+	/// Maximum size in bytes of the sections created by [`pack_files`](Self::pack_files).
+	///
+	/// Reading a packed file decrypts and authenticates the entire section.
+	pub const PACKED_SECTION_SIZE: u32 = 64 * 1024;
+
 	/// Decrypts the section.
 	///
 	/// The key is not required to be the same as used to open the PAK file.
 	#[inline]
 	pub fn read_section(&self, section: &Section, key: &Key) -> Result<Vec<Block>, ErrorKind> {
-		read_section(&self.blocks, section, key)
+		read_section(&self.blocks, self.directory.block_size, section, key)
+	}
+
+	/// Imports an encrypted section copied from another PAK file without decrypting it.
+	///
+	/// The data is stored as-is and the returned section references it with the given nonce and MAC.
+	/// Assign the section to a file descriptor with [`create_link`](Directory::create_link) or [`MemoryEditFile::set_section`].
+	/// The data can only be decrypted with the key of the PAK file it was copied from.
+	///
+	/// # Errors
+	///
+	/// * [`ErrorKind::InvalidInput`]: The data is not a whole number of blocks of this PAK file's [block size](Directory::block_size).
+	/// * [`ErrorKind::OutOfMemory`]: The data exceeds the [maximum size](Self::set_max_size).
+	pub fn import_section_raw(&mut self, data: &[u8], nonce: &Block, mac: &Block) -> Result<Section, ErrorKind> {
+		let block_size = self.directory.block_size as usize;
+		if !data.len().is_multiple_of(block_size) || data.len() / block_size > u32::MAX as usize {
+			return Err(ErrorKind::InvalidInput);
+		}
+		let scale = block_scale(self.directory.block_size);
+
+		let size = (data.len() / block_size) as u32;
+		let max_mark = self.policy.max_mark(self.directory.block_size);
+		let (offset, _) = bump_allocate(self.high_mark(), size, self.directory.align_blocks(), max_mark).ok_or(ErrorKind::OutOfMemory)?;
+		let start = offset as usize * scale;
+		self.blocks.resize(start + data.len() / BLOCK_SIZE, Block::default());
+		self.blocks[start..].as_bytes_mut().copy_from_slice(data);
+
+		Ok(Section { offset, size, nonce: *nonce, mac: *mac })
 	}
 
 	/// Decrypts the contents of the given file descriptor.
@@ -120,12 +491,10 @@ impl MemoryEditor {
 			return Err(ErrorKind::InvalidInput);
 		}
 
-		let blocks = read_section(&self.blocks, &desc.section, key)?;
+		let blocks = read_section(&self.blocks, self.directory.block_size, &desc.section, key)?;
 
 		// Figure out which part of the blocks to copy
-		let data = blocks.as_bytes();
-		let len = usize::min(data.len(), desc.content_size as usize);
-		Ok(data[..len].to_vec())
+		Ok(desc.content(blocks.as_bytes()).to_vec())
 	}
 
 	/// Decrypts the contents of the given file descriptor into the dest buffer.
@@ -141,10 +510,11 @@ impl MemoryEditor {
 			return Err(ErrorKind::InvalidInput);
 		}
 
-		let blocks = read_section(&self.blocks, &desc.section, key)?;
+		let blocks = read_section(&self.blocks, self.directory.block_size, &desc.section, key)?;
 
 		// Figure out which part of the blocks to copy
-		let data = match blocks.as_bytes().get(byte_offset..byte_offset + dest.len()) {
+		let start = desc.content_offset() as usize + byte_offset;
+		let data = match blocks.as_bytes().get(start..start + dest.len()) {
 			Some(data) => data,
 			None => return Err(ErrorKind::InvalidInput),
 		};
@@ -155,46 +525,219 @@ impl MemoryEditor {
 		Ok(())
 	}
 
+	/// Securely erases the contents of the file at the given path.
+	///
+	/// Removing a file only forgets its nonce, leaving the encrypted data behind until it is overwritten.
+	/// Shredding overwrites the data with random bytes, the file descriptor remains with its contents emptied.
+	///
+	/// Links to the file are emptied as well.
+	/// If the file is [packed](Descriptor::is_packed), only its contents are overwritten and the shared section is re-encrypted with the given key.
+	///
+	/// # Errors
+	///
+	/// * [`ErrorKind::NotFound`]: No file exists at the given path.
+	/// * [`ErrorKind::PermissionDenied`]: The path is [sealed](Directory::seal).
+	/// * [`ErrorKind::InvalidInput`]: The file's section is out of bounds.
+	/// * [`ErrorKind::InvalidData`]: The file's section is shared and its MAC is incorrect.
+	pub fn shred(&mut self, path: &[u8], key: &Key) -> Result<(), ErrorKind> {
+		if !self.directory.writable(path) {
+			return Err(ErrorKind::PermissionDenied);
+		}
+		let file = match self.directory.find_file(path) {
+			Some(desc) => *desc,
+			None => return Err(ErrorKind::NotFound),
+		};
+		let mut section = file.section;
+		let blocks = match self.blocks.get_mut(section.range_usize(self.directory.block_size)) {
+			Some(blocks) => blocks,
+			None => return Err(ErrorKind::InvalidInput),
+		};
+		if self.directory.shred_shares(&file) {
+			let start = file.content_offset() as usize;
+			if !crypt::shred_section(blocks, &mut section, start..start + file.content_size as usize, key) {
+				return Err(ErrorKind::InvalidData);
+			}
+		}
+		else {
+			crypt::random(blocks);
+		}
+		self.directory.shred_file(path, &file, &section, key);
+		Ok(())
+	}
+
+	/// Securely erases the contents of the file at the given path and removes its descriptor.
+	///
+	/// See [`shred`](Self::shred) for more information.
+	pub fn remove_shred(&mut self, path: &[u8], key: &Key) -> Result<Descriptor, ErrorKind> {
+		self.shred(path, key)?;
+		self.directory.remove(path).ok_or(ErrorKind::NotFound)
+	}
+
 	/// Compacts the referenced data blocks from file descriptors.
 	///
 	/// Removing files only removes their descriptors, leaving unreadable garbage around.
 	/// The cryptographic nonce has been erased making it no longer possible to recover the file data.
 	/// This method reclaims the space left behind by deleted files.
+	/// The [trash](Directory::soft_remove) is emptied first.
 	///
 	/// Any file descriptors with an invalid section object has their section object zeroed.
 	pub fn gc(&mut self) {
-		let mut blocks = vec![Block::default(); Header::BLOCKS_LEN];
-
-		for desc in self.directory.as_mut() {
-			if desc.is_file() {
-				let offset = blocks.len();
-				if let Some(data) = self.blocks.get(desc.section.range_usize()) {
-					blocks.extend_from_slice(data);
-					desc.section.offset = offset as u32;
+		self.directory.empty_trash();
+		let order = Layout::Directory.order(&self.directory);
+		self.compact(&order);
+	}
+
+	/// Compacts the referenced data blocks from file descriptors in the given layout.
+	///
+	/// See [`gc`](Self::gc) and [`Layout`] for more information.
+	pub fn gc_with_layout(&mut self, layout: Layout) {
+		self.directory.cache_paths();
+		let order = layout.order(&self.directory);
+		self.compact(&order);
+	}
+
+	/// Reorders the file data to match the access trace.
+	///
+	/// The access trace is the ordered list of paths of the files as loaded at runtime, eg. recorded while profiling the application.
+	/// The file data is compacted with [`Layout::Trace`] and the expected number of seeks is reported before and after reordering.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// let ref key = paks::Key::default();
+	/// let mut editor = paks::MemoryEditor::new();
+	/// editor.create_file(b"c", b"c", key);
+	/// editor.create_file(b"b", b"b", key);
+	/// editor.create_file(b"a", b"a", key);
+	///
+	/// let report = editor.apply_trace(&[b"a", b"b", b"c"]);
+	/// assert_eq!(report.seeks_before, 3);
+	/// assert_eq!(report.seeks_after, 1);
+	/// ```
+	pub fn apply_trace(&mut self, trace: &[&[u8]]) -> SeekReport {
+		let (accesses, seeks_before, distance_before) = layout::simulate(&self.directory, trace);
+		self.gc_with_layout(Layout::Trace(trace));
+		let (_, seeks_after, distance_after) = layout::simulate(&self.directory, trace);
+		SeekReport { accesses, seeks_before, seeks_after, distance_before, distance_after }
+	}
+
+	// Compacts the data of the file descriptors at the given indices in the given order
+	// Pinned extents are copied in place first, the other sections are allocated around them
+	fn compact(&mut self, order: &[usize]) {
+		let span = span!(DEBUG, "gc", high_mark = self.high_mark());
+		let block_size = self.directory.block_size;
+		let align = self.directory.align_blocks();
+		let scale = block_scale(block_size);
+		let header = header_blocks(block_size);
+		let mut blocks = vec![Block::default(); header as usize * scale];
+
+		let old_blocks = &self.blocks;
+		let mut pinned = dir::pinned_extents(self.directory.as_ref());
+		pinned.retain(|extent| extent.start >= header && extent.end as usize * scale <= old_blocks.len());
+		for extent in &pinned {
+			let range = extent.start as usize * scale..extent.end as usize * scale;
+			blocks.resize(usize::max(blocks.len(), range.end), Block::default());
+			blocks[range.clone()].copy_from_slice(&old_blocks[range]);
+		}
+
+		// Sections shared by multiple file descriptors are copied once
+		let mut moved = HashMap::new();
+		let mut next = header;
+		let mut copy = |section: &Section| -> Option<u32> {
+			if let Some(&offset) = moved.get(&(section.offset, section.size)) {
+				return Some(offset);
+			}
+			// Sections inside pinned extents stay in place
+			let end = section.offset as u64 + section.size as u64;
+			let i = pinned.partition_point(|extent| extent.end <= section.offset);
+			if section.size != 0 && pinned.get(i).is_some_and(|extent| extent.start <= section.offset && end <= extent.end as u64) {
+				return Some(section.offset);
+			}
+			let data = old_blocks.get(section.range_usize(block_size))?;
+			// Pad the blocks to keep the file data aligned and skip over the pinned extents
+			let mut offset = next;
+			loop {
+				if !data.is_empty() {
+					offset = align_up(offset, align);
 				}
-				else {
-					// Not much to do when we find an invalid descriptor...
-					desc.section = Section::default();
+				let i = pinned.partition_point(|extent| extent.end <= offset);
+				match pinned.get(i) {
+					Some(extent) if extent.start < offset + section.size => offset = extent.end,
+					_ => break,
 				}
 			}
+			next = offset + section.size;
+			let range = offset as usize * scale..next as usize * scale;
+			blocks.resize(usize::max(blocks.len(), range.end), Block::default());
+			blocks[range].copy_from_slice(data);
+			moved.insert((section.offset, section.size), offset);
+			Some(offset)
+		};
+		let descs = self.directory.descs_mut();
+		for &i in order {
+			let desc = &mut descs[i];
+			match copy(&desc.section) {
+				Some(offset) => desc.section.offset = offset,
+				// Not much to do when we find an invalid descriptor...
+				None => desc.section = Section::default(),
+			}
+			// The metadata follows the file contents
+			if let Some(mut meta) = desc.meta_section() {
+				match copy(&meta) {
+					Some(offset) => meta.offset = offset,
+					None => meta = Section::default(),
+				}
+				desc.set_meta_section(&meta);
+			}
 		}
 
+		span.finish((self.blocks.len().saturating_sub(blocks.len()) * BLOCK_SIZE) as u64);
 		self.blocks = blocks;
 	}
 
+	/// Finish editing the PAK file with the file data stored in the given layout.
+	///
+	/// The file data is compacted in the order of the layout, reclaiming any garbage like [`gc`](Self::gc).
+	/// See [`finish`](Self::finish) for more information.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// let ref key = paks::Key::default();
+	/// let mut editor = paks::MemoryEditor::new();
+	/// editor.create_file(b"level2/map", b"map2", key);
+	/// editor.create_file(b"level1/map", b"map1", key);
+	///
+	/// let (_, directory) = editor.finish_with_layout(paks::Layout::Groups(&[b"level1", b"level2"]), key);
+	/// let level1 = directory.find_file(b"level1/map").unwrap();
+	/// let level2 = directory.find_file(b"level2/map").unwrap();
+	/// assert!(level1.section.offset < level2.section.offset);
+	/// ```
+	pub fn finish_with_layout(mut self, layout: Layout, key: &Key) -> (Vec<Block>, Directory) {
+		self.gc_with_layout(layout);
+		self.finish(key)
+	}
+
 	/// Finish editing the PAK file.
 	///
 	/// Initializes the header, encrypts the directory and appends it to the blocks.
 	/// Returns the encrypted PAK file and the unencrypted directory for inspection.
-	pub fn finish(self, key: &Key) -> (Vec<Block>, Directory) {
-		let MemoryEditor { mut blocks, directory } = self;
+	pub fn finish(mut self, key: &Key) -> (Vec<Block>, Directory) {
+		self.write_changelog(key);
+		self.auto_gc();
+		self.obfuscate(key);
+		#[cfg(feature = "parity")]
+		self.append_parity(key);
+		let MemoryEditor { mut blocks, directory, .. } = self;
+		let block_size = directory.block_size;
+		let name_flags = directory.name_policy().flags();
+		let scale = block_scale(block_size);
+		let span = span!(DEBUG, "finish", high_mark = blocks.len() / scale, entries = directory.len());
 
 		{
-			// Ensure enough room for the header ref$1
-			if blocks.len() < Header::BLOCKS_LEN {
-				let padding = &[[0, 0]; Header::BLOCKS_LEN];
-				blocks.extend_from_slice(&padding[..Header::BLOCKS_LEN - blocks.len()]);
-			}
+			// Ensure enough room for the header and pad to a whole block
+			let high_mark = u32::max(header_blocks(block_size), blocks.len().div_ceil(scale) as u32);
+			blocks.resize(high_mark as usize * scale, Block::default());
 
 			// Keep track if the highest block index before the directory starts
 			let high_mark = blocks.len();
@@ -215,27 +758,64 @@ impl MemoryEditor {
 				mac: Block::default(),
 				info: InfoHeader {
 					version: InfoHeader::VERSION,
-					_unused: 0,
+					block_size,
 					directory: Section {
-						offset: high_mark as u32,
+						offset: (high_mark / scale) as u32,
 						size: dir_size as u32,
 						nonce: Block::default(),
 						mac: Block::default(),
 					},
 				},
 			};
+			header.info.set_flags(name_flags);
 
 			// Encrypt the directory
 			crypt::encrypt_section(directory, &mut header.info.directory, key);
 
 			// Encrypt the header
-			let mut section = Header::SECTION;
-			crypt::encrypt_section(header.info.as_mut(), &mut section, key);
-
-			header.nonce = section.nonce;
-			header.mac = section.mac;
+			crypt::encrypt_header(header, key);
 		}
 
+		span.finish((Header::BLOCKS_LEN + directory.len() * Descriptor::BLOCKS_LEN) as u64 * BLOCK_SIZE as u64);
 		(blocks, directory)
 	}
 }
+
+impl MemoryEditor {
+	// Appends the parity of the file data, see `set_parity`
+	#[cfg(feature = "parity")]
+	fn append_parity(&mut self, key: &Key) {
+		// Parity left behind by earlier edits no longer matches the file data
+		self.directory.remove(Directory::PARITY);
+		let options = match self.policy.parity {
+			Some(options) => options,
+			None => return,
+		};
+
+		let block_size = self.directory.block_size;
+		let start = header_blocks(block_size) as u64 * block_size as u64;
+		let end = self.high_mark() as u64 * block_size as u64;
+		let bytes = self.blocks.as_bytes();
+		let data = match crate::parity::encode(&options, start, end, |offset, buf| {
+			// The last block is zero padded by finish
+			let src = bytes.get(offset as usize..).unwrap_or_default();
+			let len = usize::min(buf.len(), src.len());
+			buf[..len].copy_from_slice(&src[..len]);
+			buf[len..].fill(0);
+			Ok(())
+		}) {
+			Ok(data) => data,
+			Err(_) => return,
+		};
+
+		let size = bytes2blocks(data.len() as u32, block_size);
+		if bump_allocate(self.high_mark(), size, self.directory.align_blocks(), self.policy.max_mark(block_size)).is_none() {
+			return;
+		}
+		let mut edit_file = self.edit_file(Directory::PARITY);
+		edit_file.set_content(1, data.len() as u32);
+		if let Ok(edit_file) = edit_file.allocate_data() {
+			edit_file.write_data(&data, key);
+		}
+	}
+}
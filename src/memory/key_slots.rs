@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use crate::*;
+
+impl MemoryEditor {
+	/// Grants access to the PAK file with another key.
+	///
+	/// See [`FileEditor::add_key_slot`] for more information.
+	///
+	/// Returns the index of the key slot.
+	///
+	/// # Errors
+	///
+	/// * [`ErrorKind::InvalidInput`]: There is no key slot table and pinned file data is in its way.
+	/// * [`ErrorKind::OutOfMemory`]: Every one of the [`MAX_SLOTS`](key::MAX_SLOTS) key slots is taken or the moved file data exceeds the [maximum size](Self::set_max_size).
+	/// * [`ErrorKind::InvalidData`]: The key slot table fails to decrypt.
+	pub fn add_key_slot(&mut self, slot_key: &Key, key: &Key) -> Result<usize, ErrorKind> {
+		let (mut table, mut slots) = match self.find_file(Directory::KEY_SLOTS) {
+			Some(desc) => (self.read_data(desc, key)?, key::decode_slots(&self.key_slots_meta(desc, key)?)),
+			None => {
+				self.reserve_key_slots()?;
+				let mut random = vec![Block::default(); key::TABLE_BYTES / BLOCK_SIZE];
+				crypt::random(&mut random);
+				(random.as_bytes().to_vec(), vec![None; key::MAX_SLOTS])
+			},
+		};
+
+		let index = match slots.iter().position(Option::is_none) {
+			Some(index) => index,
+			None => return Err(ErrorKind::OutOfMemory),
+		};
+		key::wrap_slot(&mut table[index * key::SLOT_BLOCKS * BLOCK_SIZE..(index + 1) * key::SLOT_BLOCKS * BLOCK_SIZE], key, slot_key);
+		slots[index] = Some(key::fingerprint(slot_key));
+		self.write_key_slots(&table, &slots, key)?;
+		Ok(index)
+	}
+
+	/// Revokes access to the PAK file with the key in the given key slot.
+	///
+	/// See [`FileEditor::remove_key_slot`] for more information.
+	///
+	/// # Errors
+	///
+	/// * [`ErrorKind::NotFound`]: There is no key in the key slot.
+	/// * [`ErrorKind::InvalidInput`]: The key slot is the last one, the master key could no longer be recovered.
+	/// * [`ErrorKind::InvalidData`]: The key slot table fails to decrypt.
+	pub fn remove_key_slot(&mut self, index: usize, key: &Key) -> Result<(), ErrorKind> {
+		let desc = match self.find_file(Directory::KEY_SLOTS) {
+			Some(desc) => desc,
+			None => return Err(ErrorKind::NotFound),
+		};
+		let mut table = self.read_data(desc, key)?;
+		let mut slots = key::decode_slots(&self.key_slots_meta(desc, key)?);
+
+		if !matches!(slots.get(index), Some(Some(_))) {
+			return Err(ErrorKind::NotFound);
+		}
+		if slots.iter().filter(|slot| slot.is_some()).count() == 1 {
+			return Err(ErrorKind::InvalidInput);
+		}
+
+		let mut random = [Block::default(); key::SLOT_BLOCKS];
+		crypt::random(&mut random);
+		table[index * key::SLOT_BLOCKS * BLOCK_SIZE..(index + 1) * key::SLOT_BLOCKS * BLOCK_SIZE].copy_from_slice(random.as_bytes());
+		slots[index] = None;
+		self.write_key_slots(&table, &slots, key)
+	}
+
+	/// Returns the fingerprints of the keys in the key slots, `None` for the free key slots.
+	///
+	/// Returns an empty list if the PAK file has no key slots, see [`add_key_slot`](Self::add_key_slot).
+	pub fn key_slots(&self, key: &Key) -> Result<Vec<Option<key::Fingerprint>>, ErrorKind> {
+		match self.find_file(Directory::KEY_SLOTS) {
+			Some(desc) => Ok(key::decode_slots(&self.key_slots_meta(desc, key)?)),
+			None => Ok(Vec::new()),
+		}
+	}
+
+	fn key_slots_meta(&self, desc: &Descriptor, key: &Key) -> Result<MetaMap, ErrorKind> {
+		let section = match desc.meta_section() {
+			Some(section) => section,
+			None => return Ok(MetaMap::new()),
+		};
+		let blocks = self.read_section(&section, key)?;
+		MetaMap::decode(blocks.as_bytes()).ok_or(ErrorKind::InvalidData)
+	}
+
+	// Moves the file data in the way of the key slot table to the end of the PAK file
+	fn reserve_key_slots(&mut self) -> Result<(), ErrorKind> {
+		let block_size = self.directory.block_size;
+		let table = key::table_extent(block_size);
+		let sections = key::sections_in_table(&self.directory)?;
+		let max_mark = self.policy.max_mark(block_size);
+		if table.end > max_mark {
+			return Err(ErrorKind::OutOfMemory);
+		}
+		let align = self.directory.align_blocks();
+		let scale = block_scale(block_size);
+		let mut high_mark = u32::max(self.high_mark(), table.end);
+		let mut moved = HashMap::new();
+		for &(offset, size) in &sections {
+			let (to, end) = match bump_allocate(high_mark, size, align, max_mark) {
+				Some(allocation) => allocation,
+				None => return Err(ErrorKind::OutOfMemory),
+			};
+			let from = offset as usize * scale..(offset + size) as usize * scale;
+			if from.end > self.blocks.len() {
+				return Err(ErrorKind::InvalidInput);
+			}
+			self.blocks.resize(end as usize * scale, Block::default());
+			self.blocks.copy_within(from, to as usize * scale);
+			high_mark = end;
+			moved.insert((offset, size), to);
+		}
+		self.blocks.resize(usize::max(self.blocks.len(), high_mark as usize * scale), Block::default());
+		key::relocate_sections(&mut self.directory, &moved);
+		Ok(())
+	}
+
+	// Writes the table in place, assigning it the reserved blocks right after the header the first time
+	fn write_key_slots(&mut self, table: &[u8], slots: &[Option<key::Fingerprint>], key: &Key) -> Result<(), ErrorKind> {
+		let extent = key::table_extent(self.directory.block_size);
+		let mut edit_file = match self.edit_existing(Directory::KEY_SLOTS) {
+			Some(edit_file) => edit_file,
+			None => {
+				let mut edit_file = self.edit_file(Directory::KEY_SLOTS);
+				edit_file.set_content(1, key::TABLE_BYTES as u32).set_section(&Section { offset: extent.start, size: extent.end - extent.start, ..Section::default() }).pin();
+				edit_file
+			},
+		};
+		if edit_file.descriptor().section.offset != extent.start {
+			return Err(ErrorKind::InvalidData);
+		}
+		edit_file.set_public(true).write_data(table, key);
+		edit_file.set_meta(&key::encode_slots(slots), key)?;
+		Ok(())
+	}
+}
+
+impl MemoryReader {
+	/// Returns the fingerprints of the keys in the key slots, `None` for the free key slots.
+	///
+	/// Returns an empty list if the PAK file has no key slots, see [`MemoryEditor::add_key_slot`].
+	pub fn key_slots(&self, key: &Key) -> Result<Vec<Option<key::Fingerprint>>, ErrorKind> {
+		match self.find_file(Directory::KEY_SLOTS) {
+			Some(desc) => Ok(key::decode_slots(&self.read_meta(desc, key)?)),
+			None => Ok(Vec::new()),
+		}
+	}
+}
@@ -0,0 +1,115 @@
+use std::{collections::HashMap, fmt};
+use crate::*;
+
+/// Physical ordering of the file data.
+///
+/// Storing files in the order they are loaded avoids seeking on HDD and optical media.
+/// See [`MemoryEditor::finish_with_layout`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Layout<'a> {
+	/// Files are stored in the order of their descriptors in the directory.
+	Directory,
+	/// Files are grouped by the directories or files at the given paths, in the order of the groups.
+	///
+	/// A file belongs to the first group whose path equals the file's path or one of its parent directories.
+	/// Files not part of any group are stored last.
+	/// Within a group the files are stored in directory order.
+	Groups(&'a [&'a [u8]]),
+	/// Files are stored from smallest to largest.
+	Size,
+	/// Files are stored in the order they are first accessed in the access trace.
+	///
+	/// The access trace is the ordered list of paths of the files as loaded at runtime.
+	/// Files not accessed are stored last, in directory order.
+	Trace(&'a [&'a [u8]]),
+	/// Files are stored in random order.
+	///
+	/// Hides the order of the files from the layout of the encrypted file data, see [`MemoryEditor::set_obfuscation`].
+	Shuffle,
+}
+
+impl<'a> Layout<'a> {
+	// Returns the indices of the file descriptors in the order their data is stored
+	pub(super) fn order(&self, directory: &Directory) -> Vec<usize> {
+		let descs = directory.as_ref();
+		let mut order: Vec<usize> = (0..descs.len()).filter(|&i| descs[i].is_file()).collect();
+		match *self {
+			Layout::Directory => (),
+			Layout::Groups(groups) => {
+				order.sort_by_cached_key(|&i| {
+					let path = directory.path_of(&descs[i]).unwrap_or_default();
+					groups.iter().position(|&group| is_under(&path, group)).unwrap_or(groups.len())
+				});
+			},
+			Layout::Size => order.sort_by_key(|&i| descs[i].section.size),
+			Layout::Trace(trace) => {
+				let mut first = HashMap::new();
+				for (index, &path) in trace.iter().enumerate() {
+					first.entry(path).or_insert(index);
+				}
+				order.sort_by_cached_key(|&i| {
+					let path = directory.path_of(&descs[i]).unwrap_or_default();
+					first.get(&*path).copied().unwrap_or(trace.len())
+				});
+			},
+			Layout::Shuffle => shuffle(&mut order),
+		}
+		order
+	}
+}
+
+// Returns if the path equals the parent or is one of its children
+fn is_under(path: &[u8], parent: &[u8]) -> bool {
+	match path.strip_prefix(parent) {
+		Some(tail) => tail.is_empty() || tail[0] == b'/',
+		None => false,
+	}
+}
+
+/// Expected seeks when loading the files in the order of an access trace.
+///
+/// See [`MemoryEditor::apply_trace`].
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct SeekReport {
+	/// Number of accesses of files in the PAK file.
+	pub accesses: usize,
+	/// Number of seeks before reordering the file data.
+	pub seeks_before: usize,
+	/// Number of seeks after reordering the file data.
+	pub seeks_after: usize,
+	/// Total seek distance in blocks before reordering the file data.
+	pub distance_before: u64,
+	/// Total seek distance in blocks after reordering the file data.
+	pub distance_after: u64,
+}
+
+impl fmt::Display for SeekReport {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let reduction = if self.seeks_before != 0 { 100.0 - self.seeks_after as f64 * 100.0 / self.seeks_before as f64 } else { 0.0 };
+		write!(f, "{} accesses: {} seeks -> {} seeks ({:.1}% fewer), seek distance {} -> {} blocks",
+			self.accesses, self.seeks_before, self.seeks_after, reduction, self.distance_before, self.distance_after)
+	}
+}
+
+// Simulates loading the files in the order of the access trace
+// Returns the number of accesses, seeks and the total seek distance in blocks
+pub(super) fn simulate(directory: &Directory, trace: &[&[u8]]) -> (usize, usize, u64) {
+	let align = directory.align_blocks();
+	let mut head = None;
+	let (mut accesses, mut seeks, mut distance) = (0, 0, 0);
+	for &path in trace {
+		let section = match directory.find_file(path) {
+			Some(desc) => desc.section,
+			None => continue,
+		};
+		accesses += 1;
+		// Reading on past the alignment padding does not need a seek
+		let sequential = matches!(head, Some(head) if section.offset >= head && section.offset <= align_up(head, align));
+		if !sequential {
+			seeks += 1;
+			distance += head.map_or(0, |head: u32| head.abs_diff(section.offset) as u64);
+		}
+		head = Some(section.offset + section.size);
+	}
+	(accesses, seeks, distance)
+}
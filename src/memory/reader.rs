@@ -1,13 +1,16 @@
-use std::ops;
+use std::{io, mem, mem::MaybeUninit, ops, sync::Arc};
 use crate::*;
 use super::*;
 
 /// Memory reader.
 ///
 /// This implementation keeps the entire PAK file in memory.
+///
+/// The blocks and the directory are reference counted, cloning the reader shares them without copying.
+#[derive(Clone)]
 pub struct MemoryReader {
-	blocks: Vec<Block>,
-	directory: Directory,
+	pub(super) blocks: Arc<Vec<Block>>,
+	pub(super) directory: Arc<Directory>,
 }
 
 impl MemoryReader {
@@ -17,32 +20,72 @@ impl MemoryReader {
 	///
 	/// The reader has specific alignment requirements for the buffer.
 	/// For this reason the entire byte array will be copied to an internal buffer.
+	/// The header and the directory bounds are validated before the bytes are copied.
 	///
 	/// # Errors
 	///
 	/// * [`ErrorKind::InvalidInput`]: Bytes length is not a multiple of the block size.
-	/// * [`ErrorKind::InvalidData`]: Incorrect version info or authentication checks failed.
+	/// * [`ErrorKind::InvalidData`]: Incorrect version info, the directory overlaps the header or authentication checks failed.
+	/// * [`ErrorKind::UnexpectedEof`]: The bytes end before the end of the directory.
+	#[inline]
 	pub fn from_bytes(bytes: &[u8], key: &Key) -> Result<MemoryReader, ErrorKind> {
+		MemoryReader::from_bytes_with_limits(bytes, key, &Limits::default())
+	}
+
+	/// Parses the bytes as the PAK file format for reading with limits on its size.
+	///
+	/// The limits are checked after the header is authenticated, before the bytes are copied to the internal buffer.
+	///
+	/// # Errors
+	///
+	/// * [`ErrorKind::OutOfMemory`]: The bytes or the directory claimed by the header exceed the limits.
+	///
+	/// See [`from_bytes`](Self::from_bytes) for the other errors.
+	pub fn from_bytes_with_limits(bytes: &[u8], key: &Key, limits: &Limits) -> Result<MemoryReader, ErrorKind> {
 		// The input bytes must be a multiple of the BLOCK_SIZE or this is nonsense
-		if bytes.len() % BLOCK_SIZE != 0 {
+		if !bytes.len().is_multiple_of(BLOCK_SIZE) {
 			return Err(ErrorKind::InvalidInput);
 		}
+		let blocks_len = bytes.len() / BLOCK_SIZE;
+		if matches!(limits.max_blocks, Some(max) if blocks_len as u64 > max) {
+			return Err(ErrorKind::OutOfMemory);
+		}
+
+		// Authenticate the header on its own before copying all the bytes
+		let mut header = Header::default();
+		match bytes.get(..mem::size_of::<Header>()) {
+			Some(header_bytes) => header.as_bytes_mut().copy_from_slice(header_bytes),
+			None => return Err(ErrorKind::UnexpectedEof),
+		}
+		if !crypt::decrypt_header(&mut header, key) {
+			return Err(ErrorKind::InvalidData);
+		}
+		limits.check(&header.info)?;
+
+		// Validate the directory bounds against the actual size
+		match parse::directory_range(&header.info) {
+			Some(dir_range) if dir_range.end <= blocks_len => (),
+			Some(_) => return Err(ErrorKind::UnexpectedEof),
+			None => return Err(ErrorKind::InvalidData),
+		}
 
 		// Allocate enough space to hold the blocks equivalent
 		// This is necessary as internal operations have alignment requirements
 		// Copy the input into these blocks
-		let mut blocks = vec![Block::default(); bytes.len() / BLOCK_SIZE];
-		blocks.as_bytes_mut()[..bytes.len()].copy_from_slice(bytes);
+		let mut blocks = vec![Block::default(); blocks_len];
+		blocks.as_bytes_mut().copy_from_slice(bytes);
 
-		match from_blocks(blocks, key) {
-			Ok((blocks, directory)) => Ok(MemoryReader { blocks, directory }),
-			Err(_) => return Err(ErrorKind::InvalidData),
+		match MemoryReader::from_blocks(blocks, key) {
+			Ok(reader) => Ok(reader),
+			Err(_) => Err(ErrorKind::InvalidData),
 		}
 	}
 
 	/// Parses the blocks as the PAK file format for reading.
 	pub fn from_blocks(blocks: Vec<Block>, key: &Key) -> Result<MemoryReader, Vec<Block>> {
-		from_blocks(blocks, key).map(|(blocks, directory)| MemoryReader { blocks, directory })
+		let (blocks, mut directory) = from_blocks(blocks, key)?;
+		directory.detect_sorted();
+		Ok(MemoryReader { blocks: Arc::new(blocks), directory: Arc::new(directory) })
 	}
 }
 
@@ -55,12 +98,58 @@ impl ops::Deref for MemoryReader {
 }
 
 impl MemoryReader {
+	/// Precomputes the full path of every descriptor.
+	///
+	/// The directory is copied first if it is shared with clones of the reader.
+	/// See [`Directory::cache_paths`] for more information.
+	#[inline]
+	pub fn cache_paths(&mut self) {
+		Arc::make_mut(&mut self.directory).cache_paths();
+	}
+
 	/// Decrypts and authenticates the section.
 	///
 	/// The key is not required to be the same as used to open the PAK file.
 	#[inline]
 	pub fn read_section(&self, section: &Section, key: &Key) -> Result<Vec<Block>, ErrorKind> {
-		read_section(&self.blocks, section, key)
+		read_section(&self.blocks, self.directory.block_size, section, key)
+	}
+
+	/// Decrypts the section into the given blocks, reusing their allocation.
+	///
+	/// The blocks are resized to the size of the section.
+	/// See [`read_section`](Self::read_section) for more information.
+	#[inline]
+	pub fn read_section_into(&self, section: &Section, key: &Key, blocks: &mut Vec<Block>) -> Result<(), ErrorKind> {
+		read_section_into(&self.blocks, self.directory.block_size, section, key, blocks)
+	}
+
+	/// Writes the encrypted contents of the section without decrypting it.
+	///
+	/// The MAC is not checked. Together with the section's nonce and MAC the data can be imported into another PAK file
+	/// encrypted with the same key with `import_section_raw` without knowing the key.
+	///
+	/// # Errors
+	///
+	/// * [`io::ErrorKind::InvalidInput`]: The section is out of bounds.
+	/// * [`io::Error`]: An error encountered writing to the writer.
+	pub fn copy_section_raw<W: io::Write>(&self, section: &Section, mut writer: W) -> io::Result<()> {
+		copy_section_raw(&self.blocks, self.directory.block_size, section, &mut writer)
+	}
+
+	/// Returns the range of blocks as stored in the PAK file without decrypting them.
+	///
+	/// See [`FileReader::raw_blocks`] for more information.
+	///
+	/// # Errors
+	///
+	/// * [`ErrorKind::InvalidInput`]: The range is reversed or out of bounds.
+	pub fn raw_blocks(&self, range: ops::Range<u32>) -> Result<&[Block], ErrorKind> {
+		if range.start > range.end {
+			return Err(ErrorKind::InvalidInput);
+		}
+		let section = Section { offset: range.start, size: range.end - range.start, ..Section::default() };
+		self.blocks.get(section.range_usize(self.directory.block_size)).ok_or(ErrorKind::InvalidInput)
 	}
 
 	/// Decrypts the contents of the given file descriptor.
@@ -76,17 +165,69 @@ impl MemoryReader {
 			return Err(ErrorKind::InvalidInput);
 		}
 
-		let blocks = read_section(&self.blocks, &desc.section, key)?;
+		let blocks = read_section(&self.blocks, self.directory.block_size, &desc.section, key)?;
 
 		// Figure out which part of the blocks to copy
-		let data = blocks.as_bytes();
-		let len = usize::min(data.len(), desc.content_size as usize);
-		Ok(data[..len].to_vec())
+		Ok(desc.content(blocks.as_bytes()).to_vec())
+	}
+
+	/// Decrypts the contents of the given file descriptor using the scratch blocks.
+	///
+	/// Returns the contents borrowed from the scratch blocks.
+	/// Unlike [`read_data`](Self::read_data) nothing is allocated once the scratch blocks are large enough, loading many small files reuses the same allocation.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// let ref key = paks::Key::default();
+	/// let mut editor = paks::MemoryEditor::new();
+	/// editor.create_file(b"a", b"first", key);
+	/// editor.create_file(b"b", b"second", key);
+	/// let (blocks, _) = editor.finish(key);
+	///
+	/// let reader = paks::MemoryReader::from_blocks(blocks, key).unwrap();
+	/// let mut scratch = Vec::new();
+	/// for (path, expected) in [(b"a", &b"first"[..]), (b"b", &b"second"[..])].iter() {
+	///     let data = reader.read_data_with(reader.find_file(*path).unwrap(), key, &mut scratch).unwrap();
+	///     assert_eq!(data, *expected);
+	/// }
+	/// ```
+	pub fn read_data_with<'s>(&self, desc: &Descriptor, key: &Key, scratch: &'s mut Vec<Block>) -> Result<&'s [u8], ErrorKind> {
+		if !desc.is_file() {
+			return Err(ErrorKind::InvalidInput);
+		}
+
+		read_section_into(&self.blocks, self.directory.block_size, &desc.section, key, scratch)?;
+
+		// Figure out which part of the blocks to return
+		Ok(desc.content(scratch.as_bytes()))
+	}
+
+	/// Decrypts every file at or under the given path and passes its path and contents to the callback.
+	///
+	/// Files are visited in directory order one at a time, an empty path visits every file.
+	///
+	/// # Errors
+	///
+	/// * [`ErrorKind::NotFound`]: The path does not exist.
+	/// * See [`read_data`](Self::read_data), scanning stops at the first file which cannot be read.
+	pub fn scan<F: FnMut(&[u8], &[u8])>(&self, path: &[u8], key: &Key, mut f: F) -> Result<(), ErrorKind> {
+		let files = match self.directory.files_under(path) {
+			Some(files) => files,
+			None => return Err(ErrorKind::NotFound),
+		};
+		let mut scratch = Vec::new();
+		for (path, desc) in &files {
+			let data = self.read_data_with(desc, key, &mut scratch)?;
+			f(path, data);
+		}
+		Ok(())
 	}
 
 	/// Decrypts the contents of the given file descriptor into the dest buffer.
 	///
 	/// The key is not required to be the same as used to open the PAK file.
+	/// The section is decrypted in chunks directly into the dest buffer after its MAC is checked, the dest buffer is left unchanged if an error is returned.
 	///
 	/// # Notes
 	///
@@ -97,17 +238,48 @@ impl MemoryReader {
 			return Err(ErrorKind::InvalidInput);
 		}
 
-		let blocks = read_section(&self.blocks, &desc.section, key)?;
+		// Copy the part of the section to its destination
+		let start = desc.content_offset() as usize + byte_offset;
+		read_section_range(&self.blocks, self.directory.block_size, &desc.section, key, start..start + dest.len(), |offset, data| {
+			dest[offset..offset + data.len()].copy_from_slice(data);
+		})
+	}
 
-		// Figure out which part of the blocks to copy
-		let data = match blocks.as_bytes().get(byte_offset..byte_offset + dest.len()) {
-			Some(data) => data,
+	/// Decrypts the contents of the given file descriptor into uninitialized memory.
+	///
+	/// Returns the contents, initialized at the start of the dest buffer.
+	/// See [`FileReader::read_into_uninit`] for more information.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use std::mem::MaybeUninit;
+	///
+	/// let ref key = paks::Key::default();
+	/// let mut editor = paks::MemoryEditor::new();
+	/// editor.create_file(b"a", b"contents", key);
+	/// let (blocks, _) = editor.finish(key);
+	///
+	/// let reader = paks::MemoryReader::from_blocks(blocks, key).unwrap();
+	/// let mut buf = [MaybeUninit::uninit(); 16];
+	/// let data = reader.read_into_uninit(reader.find_file(b"a").unwrap(), key, &mut buf).unwrap();
+	/// assert_eq!(data, b"contents");
+	/// ```
+	pub fn read_into_uninit<'d>(&self, desc: &Descriptor, key: &Key, dest: &'d mut [MaybeUninit<u8>]) -> Result<&'d mut [u8], ErrorKind> {
+		if !desc.is_file() {
+			return Err(ErrorKind::InvalidInput);
+		}
+		let dest = match dest.get_mut(..desc.content_size as usize) {
+			Some(dest) => dest,
 			None => return Err(ErrorKind::InvalidInput),
 		};
 
-		// Copy the data to its destination
-		dest.copy_from_slice(data);
+		let start = desc.content_offset() as usize;
+		read_section_range(&self.blocks, self.directory.block_size, &desc.section, key, start..start + dest.len(), |offset, data| {
+			uninit_copy(&mut dest[offset..offset + data.len()], data);
+		})?;
 
-		Ok(())
+		// SAFETY: The chunks of the section cover the whole range, every byte of dest was written
+		Ok(unsafe { &mut *(dest as *mut [MaybeUninit<u8>] as *mut [u8]) })
 	}
 }
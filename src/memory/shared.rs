@@ -0,0 +1,128 @@
+use std::{fmt, ops, sync::Arc};
+use crate::*;
+use super::*;
+
+/// Decrypted file contents shared by reference counting, see [`MemoryReader::read_data_shared`].
+///
+/// Cloning and [slicing](Self::slice) share the decrypted blocks instead of copying them.
+#[derive(Clone)]
+pub struct SharedData {
+	blocks: Arc<Vec<Block>>,
+	start: usize,
+	end: usize,
+}
+
+impl SharedData {
+	// Shares the contents of the file in its decrypted section
+	pub(crate) fn new(blocks: Vec<Block>, desc: &Descriptor) -> SharedData {
+		let bytes = blocks.as_bytes();
+		let start = usize::min(bytes.len(), desc.content_offset() as usize);
+		let end = start + desc.content(bytes).len();
+		SharedData { blocks: Arc::new(blocks), start, end }
+	}
+
+	/// Returns the bytes in the given range of the contents, sharing the decrypted blocks.
+	///
+	/// # Panics
+	///
+	/// Panics if the range is out of bounds.
+	pub fn slice(&self, range: ops::Range<usize>) -> SharedData {
+		assert!(range.start <= range.end && range.end <= self.len(), "range out of bounds");
+		SharedData {
+			blocks: self.blocks.clone(),
+			start: self.start + range.start,
+			end: self.start + range.end,
+		}
+	}
+
+	/// Copies the contents into a new vector.
+	#[inline]
+	pub fn to_vec(&self) -> Vec<u8> {
+		(**self).to_vec()
+	}
+}
+
+impl ops::Deref for SharedData {
+	type Target = [u8];
+	#[inline]
+	fn deref(&self) -> &[u8] {
+		&self.blocks.as_bytes()[self.start..self.end]
+	}
+}
+
+impl AsRef<[u8]> for SharedData {
+	#[inline]
+	fn as_ref(&self) -> &[u8] {
+		self
+	}
+}
+
+impl fmt::Debug for SharedData {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		fmt::Debug::fmt(&**self, f)
+	}
+}
+
+impl PartialEq<[u8]> for SharedData {
+	#[inline]
+	fn eq(&self, other: &[u8]) -> bool {
+		**self == *other
+	}
+}
+
+impl MemoryReader {
+	/// Decrypts the contents of the given file descriptor into a shared buffer.
+	///
+	/// Unlike [`read_data`](Self::read_data) the contents are not copied out of the decrypted section.
+	/// The result can be cloned and sliced cheaply and handed to other threads.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// let ref key = paks::Key::default();
+	/// let mut editor = paks::MemoryEditor::new();
+	/// editor.create_file(b"greeting", b"Hello, world!", key);
+	/// let (blocks, _) = editor.finish(key);
+	///
+	/// let reader = paks::MemoryReader::from_blocks(blocks, key).unwrap();
+	/// let data = reader.read_data_shared(reader.find_file(b"greeting").unwrap(), key).unwrap();
+	/// let world = data.slice(7..12);
+	/// assert_eq!(&*world, b"world");
+	///
+	/// // Readers are cloned without copying the PAK file
+	/// let shared = reader.clone();
+	/// std::thread::spawn(move || {
+	///     assert!(shared.find_file(b"greeting").is_some());
+	/// }).join().unwrap();
+	/// ```
+	pub fn read_data_shared(&self, desc: &Descriptor, key: &Key) -> Result<SharedData, ErrorKind> {
+		if !desc.is_file() {
+			return Err(ErrorKind::InvalidInput);
+		}
+
+		let blocks = read_section(&self.blocks, self.directory.block_size, &desc.section, key)?;
+		Ok(SharedData::new(blocks, desc))
+	}
+
+	/// Decrypts the contents of the given file descriptor into [`Bytes`](bytes::Bytes).
+	///
+	/// The bytes reference the decrypted section without copying the contents out of it.
+	/// See [`read_data_shared`](Self::read_data_shared) for more information.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// let ref key = paks::Key::default();
+	/// let mut editor = paks::MemoryEditor::new();
+	/// editor.create_file(b"index.html", b"<h1>Hello</h1>", key);
+	/// let (blocks, _) = editor.finish(key);
+	///
+	/// let reader = paks::MemoryReader::from_blocks(blocks, key).unwrap();
+	/// let body = reader.read_data_bytes(reader.find_file(b"index.html").unwrap(), key).unwrap();
+	/// assert_eq!(body, &b"<h1>Hello</h1>"[..]);
+	/// ```
+	#[cfg(feature = "bytes")]
+	pub fn read_data_bytes(&self, desc: &Descriptor, key: &Key) -> Result<bytes::Bytes, ErrorKind> {
+		self.read_data_shared(desc, key).map(bytes::Bytes::from_owner)
+	}
+}
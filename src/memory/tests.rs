@@ -31,3 +31,715 @@ fn test_simple() {
 	let example = reader.read_data(desc, key).expect("failed to read example");
 	assert_eq!(example, EXAMPLE);
 }
+
+#[test]
+fn test_malformed() {
+	let ref key = [1, 2];
+
+	let mut edit = MemoryEditor::new();
+	edit.create_file(b"example", EXAMPLE, key);
+	let (blocks, _) = edit.finish(key);
+	let bytes = as_bytes(&blocks);
+
+	// Truncated input is rejected
+	for len in (0..bytes.len()).step_by(BLOCK_SIZE) {
+		assert!(MemoryReader::from_bytes(&bytes[..len], key).is_err());
+		assert!(crate::read(&bytes[..len], key).is_err());
+	}
+
+	// Tampering with the directory is detected
+	let mut tampered = blocks.clone();
+	let last = tampered.len() - 1;
+	tampered[last][0] ^= 1;
+	assert!(MemoryReader::from_blocks(tampered, key).is_err());
+
+	// The original is fine
+	assert!(crate::read(bytes, key).is_ok());
+	assert!(MemoryReader::from_bytes(bytes, key).is_ok());
+}
+
+#[test]
+fn test_display() {
+	let ref key = [1, 2];
+
+	let mut edit = MemoryEditor::new();
+	edit.create_file(b"dir/example", EXAMPLE, key);
+	let text = edit.to_string();
+	assert!(text.starts_with("MemoryEditor\n"));
+	assert!(text.contains("entries:   2 (1 files, 1 directories)"));
+	assert!(format!("{:?}", edit).contains("garbage: 0"));
+}
+
+#[test]
+fn test_block_size() {
+	let ref key = [1, 2];
+
+	for &block_size in &InfoHeader::BLOCK_SIZES {
+		let mut edit = MemoryEditor::new();
+		assert!(edit.set_block_size(block_size));
+		edit.create_file(b"a", &EXAMPLE[..1], key);
+		edit.create_file(b"dir/example", EXAMPLE, key);
+		let (blocks, _) = edit.finish(key);
+
+		let reader = MemoryReader::from_blocks(blocks.clone(), key).expect("failed to read");
+		assert_eq!(reader.block_size(), block_size);
+		assert!(reader.fsck(!0, &mut String::new()));
+		let desc = reader.find_file(b"dir/example").unwrap();
+		assert_eq!(desc.section.size, (EXAMPLE.len() as u32).div_ceil(block_size));
+		assert_eq!(reader.read_data(desc, key).unwrap(), EXAMPLE);
+		assert_eq!(crate::read(as_bytes(&blocks), key).unwrap(), blocks);
+
+		// Editing keeps the block size
+		let mut edit = MemoryEditor::from_blocks(blocks, key).expect("failed to edit");
+		assert!(!edit.set_block_size(16) || block_size == 16);
+		edit.remove(b"a");
+		edit.gc();
+		let (blocks, _) = edit.finish(key);
+		let reader = MemoryReader::from_blocks(blocks, key).expect("failed to read");
+		let desc = reader.find_file(b"dir/example").unwrap();
+		assert_eq!(reader.read_data(desc, key).unwrap(), EXAMPLE);
+	}
+}
+
+#[test]
+fn test_align_data() {
+	let ref key = [1, 2];
+
+	let mut edit = MemoryEditor::new();
+	assert!(edit.set_block_size(64));
+	assert!(edit.align_data(1024));
+	edit.create_file(b"a", &EXAMPLE[..1], key);
+	edit.create_file(b"b", &EXAMPLE[..1], key);
+	edit.create_file(b"c", EXAMPLE, key);
+	edit.remove(b"b");
+	edit.gc();
+	assert_eq!(edit.garbage(edit.high_mark()), 0);
+
+	let (blocks, _) = edit.finish(key);
+	let reader = MemoryReader::from_blocks(blocks, key).expect("failed to read");
+	for &(path, data) in &[(&b"a"[..], &EXAMPLE[..1]), (b"c", EXAMPLE)] {
+		let desc = reader.find_file(path).unwrap();
+		assert_eq!(desc.section.offset % (1024 / 64), 0);
+		assert_eq!(reader.read_data(desc, key).unwrap(), data);
+	}
+}
+
+#[test]
+fn test_section_raw() {
+	let ref key = [1, 2];
+
+	let mut edit = MemoryEditor::new();
+	edit.create_file(b"example", EXAMPLE, key);
+	let (blocks, _) = edit.finish(key);
+	let reader = MemoryReader::from_blocks(blocks, key).expect("failed to read");
+	let src = *reader.find_file(b"example").unwrap();
+
+	// Copy the encrypted section to another PAK file with a different block size
+	let mut data = Vec::new();
+	reader.copy_section_raw(&src.section, &mut data).unwrap();
+	let mut edit = MemoryEditor::new();
+	assert!(edit.set_block_size(64));
+	assert_eq!(edit.import_section_raw(&data, &src.section.nonce, &src.section.mac), Err(std::io::ErrorKind::InvalidInput));
+	assert!(edit.set_block_size(16));
+	let section = edit.import_section_raw(&data, &src.section.nonce, &src.section.mac).unwrap();
+	edit.edit_file(b"copy").set_content(src.content_type, src.content_size).set_section(&section);
+	let (blocks, _) = edit.finish(key);
+
+	let reader = MemoryReader::from_blocks(blocks, key).expect("failed to read");
+	let desc = reader.find_file(b"copy").unwrap();
+	assert_eq!(reader.read_data(desc, key).unwrap(), EXAMPLE);
+}
+
+#[test]
+fn test_seal_persists() {
+	let ref key = Key::default();
+	let mut edit = MemoryEditor::new();
+	edit.create_file(b"engine/a", b"a", key).unwrap();
+	assert!(edit.seal(b"engine", true));
+	assert!(edit.create_file(b"engine/b", b"b", key).is_none());
+	let (blocks, _) = edit.finish(key);
+
+	let reader = MemoryReader::from_blocks(blocks, key).unwrap();
+	assert!(reader.find_desc(b"engine").unwrap().is_sealed());
+}
+
+#[test]
+fn test_finish_with_layout() {
+	let ref key = Key::default();
+	let mut edit = MemoryEditor::new();
+	edit.create_file(b"b/large", &[1; 300], key).unwrap();
+	edit.create_file(b"a/small", &[2; 10], key).unwrap();
+	edit.create_file(b"c/medium", &[3; 100], key).unwrap();
+	edit.remove(b"c/medium");
+	edit.create_file(b"c/medium", &[3; 100], key).unwrap();
+
+	let offsets = |layout| {
+		let (blocks, _) = edit.clone().finish_with_layout(layout, key);
+		let reader = MemoryReader::from_blocks(blocks, key).unwrap();
+		for (path, data) in [(&b"a/small"[..], &[2; 10][..]), (b"b/large", &[1; 300]), (b"c/medium", &[3; 100])] {
+			assert_eq!(reader.read_data(reader.find_file(path).unwrap(), key).unwrap(), data);
+		}
+		[&b"a/small"[..], b"b/large", b"c/medium"].map(|path| reader.find_file(path).unwrap().section.offset)
+	};
+
+	let [a, b, c] = offsets(Layout::Size);
+	assert!(a < c && c < b);
+	let [a, b, c] = offsets(Layout::Groups(&[b"c", b"a/small"]));
+	assert!(c < a && a < b);
+	let [a, b, c] = offsets(Layout::Directory);
+	assert!(b < a && a < c);
+}
+
+#[test]
+fn test_apply_trace() {
+	let ref key = Key::default();
+	let mut edit = MemoryEditor::new();
+	for path in [&b"d"[..], b"c", b"b", b"a"] {
+		edit.create_file(path, path, key).unwrap();
+	}
+
+	// Repeated and unknown paths in the trace
+	let trace = [&b"a"[..], b"missing", b"b", b"c", b"a"];
+	let report = edit.apply_trace(&trace);
+	assert_eq!(report.accesses, 4);
+	assert_eq!(report.seeks_before, 4);
+	assert_eq!(report.seeks_after, 2);
+	assert!(report.distance_after < report.distance_before);
+
+	// The file not in the trace is stored last
+	let offset = |path| edit.find_file(path).unwrap().section.offset;
+	assert!(offset(b"a") < offset(b"b") && offset(b"b") < offset(b"c") && offset(b"c") < offset(b"d"));
+
+	let (blocks, _) = edit.finish(key);
+	let reader = MemoryReader::from_blocks(blocks, key).unwrap();
+	assert_eq!(reader.read_data(reader.find_file(b"d").unwrap(), key).unwrap(), b"d");
+}
+
+#[test]
+fn test_shred() {
+	let ref key = Key::default();
+	let mut edit = MemoryEditor::new();
+	edit.create_file(b"dir/secret", EXAMPLE, key).unwrap();
+	let section = edit.find_file(b"dir/secret").unwrap().section;
+
+	edit.seal(b"dir", true);
+	assert_eq!(edit.shred(b"dir/secret", key), Err(std::io::ErrorKind::PermissionDenied));
+	edit.seal(b"dir", false);
+
+	edit.remove_shred(b"dir/secret", key).unwrap();
+	assert!(edit.find_file(b"dir/secret").is_none());
+	assert_eq!(edit.read_section(&section, key), Err(std::io::ErrorKind::InvalidData));
+	assert_eq!(edit.shred(b"dir/secret", key), Err(std::io::ErrorKind::NotFound));
+}
+
+#[test]
+fn test_create_files_parallel() {
+	let ref key = [3, 4];
+	let items: Vec<u32> = (0..100).collect();
+
+	let mut edit = MemoryEditor::new();
+	let created = edit.create_files_parallel(&items, 4, key, |&i| {
+		Ok((format!("dir{}/file{}", i % 3, i).into_bytes(), EXAMPLE[..i as usize].to_vec()))
+	});
+	assert_eq!(created.unwrap(), 100);
+
+	// Files are created in the order of the items
+	let mut offset = 0;
+	for &i in &items {
+		let desc = *edit.find_file(format!("dir{}/file{}", i % 3, i).as_bytes()).unwrap();
+		assert!(desc.section.offset >= offset);
+		offset = desc.section.offset;
+		assert_eq!(edit.read_data(&desc, key).unwrap(), &EXAMPLE[..i as usize]);
+	}
+
+	// Stops at the first error
+	let created = edit.create_files_parallel(&items, 4, key, |&i| match i {
+		50 => Err(std::io::ErrorKind::NotFound.into()),
+		_ => Ok((format!("more/file{}", i).into_bytes(), Vec::new())),
+	});
+	assert_eq!(created.unwrap_err().kind(), std::io::ErrorKind::NotFound);
+	assert_eq!(edit.get_children(b"more").unwrap().len(), 50);
+
+	// Encrypted for a different block size
+	let encrypted = Encrypted::new(EXAMPLE, 64, key);
+	assert_eq!(edit.create_encrypted(b"wrong", &encrypted).unwrap_err(), std::io::ErrorKind::InvalidInput);
+	let mut edit = MemoryEditor::new();
+	assert!(edit.set_block_size(64));
+	let desc = *edit.create_encrypted(b"right", &encrypted).unwrap();
+	assert_eq!(edit.read_data(&desc, key).unwrap(), EXAMPLE);
+}
+
+#[test]
+fn test_budgets() {
+	let ref key = [5, 6];
+
+	let mut edit = MemoryEditor::new();
+	edit.set_budget(b"", 100);
+	edit.set_budget(b"a", 32);
+	edit.create_file(b"a/x", &EXAMPLE[..20], key);
+	assert_eq!(edit.check_budgets(), Ok(()));
+
+	// Links share their section and are counted once
+	let x = *edit.find_file(b"a/x").unwrap();
+	edit.create_link(b"a/y", &x);
+	edit.create_link(b"b/y", &x);
+	assert_eq!(edit.check_budgets(), Ok(()));
+
+	edit.create_file(b"b/z", &EXAMPLE[..100], key);
+	edit.create_file(b"a/z", &EXAMPLE[..1], key);
+	let exceeded = edit.check_budgets().unwrap_err();
+	assert_eq!(exceeded, [
+		BudgetExceeded { path: Vec::new(), budget: 100, used: 160 },
+		BudgetExceeded { path: b"a".to_vec(), budget: 32, used: 48 },
+	]);
+	assert_eq!(exceeded[0].to_string(), ".: 160 bytes exceeds the budget of 100 bytes by 60 bytes");
+
+	// Setting a budget again replaces it
+	edit.set_budget(b"", 1000);
+	edit.set_budget(b"a", 48);
+	assert_eq!(edit.check_budgets(), Ok(()));
+}
+
+#[test]
+fn test_limits() {
+	let ref key = Key::default();
+	let mut edit = MemoryEditor::new();
+	edit.create_file(b"a", b"a", key);
+	edit.create_file(b"b/c", b"c", key);
+	let (blocks, _) = edit.finish(key);
+	let bytes = as_bytes(&blocks);
+
+	// Three descriptors: `a`, `b` and `b/c`
+	let limits = Limits { max_directory_entries: Some(3), max_blocks: Some(blocks.len() as u64) };
+	assert!(MemoryReader::from_bytes_with_limits(bytes, key, &limits).is_ok());
+	assert!(crate::read_with_limits(bytes, key, &limits).is_ok());
+
+	let limits = Limits { max_directory_entries: Some(2), ..Limits::default() };
+	assert_eq!(MemoryReader::from_bytes_with_limits(bytes, key, &limits).err(), Some(std::io::ErrorKind::OutOfMemory));
+	assert_eq!(crate::read_with_limits(bytes, key, &limits).unwrap_err().kind(), std::io::ErrorKind::OutOfMemory);
+
+	let limits = Limits { max_blocks: Some(blocks.len() as u64 - 1), ..Limits::default() };
+	assert_eq!(MemoryReader::from_bytes_with_limits(bytes, key, &limits).err(), Some(std::io::ErrorKind::OutOfMemory));
+	assert_eq!(crate::read_with_limits(bytes, key, &limits).unwrap_err().kind(), std::io::ErrorKind::OutOfMemory);
+
+	// The wrong key is still reported as invalid data
+	let limits = Limits { max_directory_entries: Some(0), ..Limits::default() };
+	assert_eq!(MemoryReader::from_bytes_with_limits(bytes, &[1, 2], &limits).err(), Some(std::io::ErrorKind::InvalidData));
+}
+
+#[test]
+fn test_truncated() {
+	let ref key = Key::default();
+	let mut edit = MemoryEditor::new();
+	edit.create_file(b"example", EXAMPLE, key);
+	let (blocks, _) = edit.finish(key);
+	let bytes = as_bytes(&blocks);
+
+	// Truncated input is reported as such before copying the bytes
+	let len = bytes.len() - BLOCK_SIZE;
+	assert_eq!(MemoryReader::from_bytes(&bytes[..len], key).err(), Some(std::io::ErrorKind::UnexpectedEof));
+	assert_eq!(MemoryReader::from_bytes(&bytes[..BLOCK_SIZE], key).err(), Some(std::io::ErrorKind::UnexpectedEof));
+	assert_eq!(crate::read(&bytes[..len], key).unwrap_err().kind(), std::io::ErrorKind::UnexpectedEof);
+
+	// A stream longer than the PAK file is only read up to the end of the directory
+	let mut longer = bytes.to_vec();
+	longer.extend_from_slice(&[0; BLOCK_SIZE]);
+	assert_eq!(crate::read(&longer[..], key).unwrap(), blocks);
+}
+
+#[test]
+fn test_preview() {
+	let ref key = Key::default();
+	let mut edit = MemoryEditor::new();
+	edit.create_file(b"a", EXAMPLE, key);
+	edit.create_file(b"garbage", EXAMPLE, key);
+	edit.create_file(b"b", b"b", key);
+	edit.create_file(b"c", b"c", key);
+
+	let mut meta = MetaMap::new();
+	meta.set_str("tool.author", "me");
+	let mut edit_file = edit.edit_file(b"a");
+	edit_file.set_meta(&meta, key).unwrap();
+	edit_file.set_preview(b"preview", key).unwrap();
+	let desc = *edit_file.descriptor();
+	assert!(desc.meta_section().is_some());
+	edit.create_link(b"link", &desc);
+
+	// Files with metadata are not packed and garbage collection moves the meta sections
+	assert_eq!(edit.pack_files(64, key), 2);
+	edit.remove(b"garbage");
+	edit.gc();
+	let (blocks, _) = edit.finish(key);
+
+	let reader = MemoryReader::from_blocks(blocks, key).unwrap();
+	let a = reader.find_file(b"a").unwrap();
+	assert_eq!(reader.read_data(a, key).unwrap(), EXAMPLE);
+	assert_eq!(reader.read_preview(a, key).unwrap().unwrap(), b"preview");
+	assert_eq!(reader.read_meta(a, key).unwrap().get_str("tool.author"), Some("me"));
+	assert_eq!(reader.read_preview(reader.find_file(b"link").unwrap(), key).unwrap().unwrap(), b"preview");
+	assert_eq!(reader.read_preview(reader.find_file(b"b").unwrap(), key).unwrap(), None);
+	assert!(reader.read_meta(a, &[1, 2]).is_err());
+}
+
+#[test]
+fn test_nlink() {
+	let ref key = Key::default();
+	let mut edit = MemoryEditor::new();
+	edit.create_file(b"a", EXAMPLE, key);
+	edit.create_file(b"b", b"b", key);
+	let a = *edit.find_file(b"a").unwrap();
+	edit.create_link(b"dir/link", &a);
+	assert_eq!(edit.nlink(&a), 2);
+	assert_eq!(edit.nlink(edit.find_desc(b"dir").unwrap()), 0);
+
+	// Removing the original keeps the data alive for the link
+	assert_eq!(edit.unlink(b"a").map(|(_, nlink)| nlink), Some(1));
+	assert_eq!(edit.garbage(edit.high_mark()), 0);
+	edit.gc();
+
+	let link = *edit.find_file(b"dir/link").unwrap();
+	assert_eq!(edit.read_data(&link, key).unwrap(), EXAMPLE);
+	assert_eq!(edit.nlink(&link), 1);
+
+	// Removing the last link frees its blocks
+	assert_eq!(edit.unlink(b"dir/link").map(|(_, nlink)| nlink), Some(0));
+	assert_eq!(edit.garbage(edit.high_mark()), link.section.size);
+	assert!(edit.unlink(b"dir/link").is_none());
+}
+
+#[test]
+fn test_copy_within() {
+	let ref key = Key::default();
+	let mut edit = MemoryEditor::new();
+	edit.create_file(b"a", EXAMPLE, key);
+	edit.create_file(b"dest", b"old", key);
+	edit.create_dir(b"dir");
+	edit.edit_file(b"a").set_content(7, EXAMPLE.len() as u32);
+	edit.tag(b"a", "ui", key).unwrap();
+	edit.tag(b"dest", "old", key).unwrap();
+
+	// Copies share the tags of the source file without reopening
+	let link = *edit.copy_within(b"a", b"link", CopyMode::Link, key).unwrap();
+	let deep = *edit.copy_within(b"a", b"dest", CopyMode::Deep, key).unwrap();
+	assert_eq!(link.content_type, 7);
+	assert_eq!(deep.content_type, 7);
+	assert_eq!(edit.nlink(&link), 2);
+	assert_eq!(edit.nlink(&deep), 1);
+	let ui: Vec<_> = edit.find_by_tag("ui").into_iter().map(|(path, _)| path).collect();
+	assert_eq!(ui, [&b"a"[..], b"dest", b"link"]);
+	assert!(edit.find_by_tag("old").is_empty());
+
+	// Deep copies are independent of the source file
+	edit.shred(b"a", key).unwrap();
+	assert_eq!(edit.read_data(&deep, key).unwrap(), EXAMPLE);
+	let meta = MetaMap::decode(edit.read_section(&deep.meta_section().unwrap(), key).unwrap().as_bytes()).unwrap();
+	assert_eq!(meta.tags(), ["ui"]);
+
+	assert_eq!(edit.copy_within(b"missing", b"b", CopyMode::Link, key).err(), Some(std::io::ErrorKind::NotFound));
+	assert_eq!(edit.copy_within(b"dir", b"b", CopyMode::Deep, key).err(), Some(std::io::ErrorKind::NotFound));
+	assert_eq!(edit.copy_within(b"dest", b"dir", CopyMode::Deep, key).err(), Some(std::io::ErrorKind::InvalidInput));
+	edit.seal(b"dir", true);
+	assert_eq!(edit.copy_within(b"dest", b"dir/b", CopyMode::Link, key).err(), Some(std::io::ErrorKind::PermissionDenied));
+}
+
+#[test]
+fn test_nested() {
+	let ref key = Key::default();
+	let ref inner_key = [3, 4];
+	let mut inner = MemoryEditor::new();
+	inner.create_file(b"example", EXAMPLE, inner_key);
+	let (blocks, _) = inner.finish(inner_key);
+	let bytes = as_bytes(&blocks);
+
+	let mut edit = MemoryEditor::new();
+	edit.create_file(b"nested.pak", bytes, key);
+	edit.create_file(b"plain.pak", bytes, key);
+	edit.edit_file(b"nested.pak").set_content(Descriptor::CONTENT_TYPE_PAK, bytes.len() as u32);
+	let (blocks, _) = edit.finish(key);
+
+	let reader = MemoryReader::from_blocks(blocks, key).unwrap();
+	let nested = reader.find_file(b"nested.pak").unwrap();
+	assert!(nested.is_nested());
+	let inner = reader.open_nested(nested, key, inner_key).unwrap();
+	assert_eq!(inner.read_data(inner.find_file(b"example").unwrap(), inner_key).unwrap(), EXAMPLE);
+	assert_eq!(reader.open_nested(nested, key, key).err(), Some(std::io::ErrorKind::InvalidData));
+	assert_eq!(reader.open_nested(reader.find_file(b"plain.pak").unwrap(), key, inner_key).err(), Some(std::io::ErrorKind::InvalidInput));
+}
+
+#[test]
+fn test_max_size() {
+	let ref key = Key::default();
+	let mut edit = MemoryEditor::new();
+	edit.set_max_size(Some(1024));
+	assert_eq!(edit.max_size(), Some(1024));
+
+	assert!(edit.create_file(b"a", &[1; 500], key).is_some());
+	let high_mark = edit.high_mark();
+	assert!(edit.create_file(b"b", &[2; 600], key).is_none());
+	assert!(edit.find(b"b").is_none());
+	assert_eq!(edit.high_mark(), high_mark);
+
+	let mut edit_file = edit.edit_file(b"c");
+	edit_file.set_content(1, 600);
+	assert_eq!(edit_file.allocate_data().err(), Some(std::io::ErrorKind::OutOfMemory));
+	assert_eq!(edit.high_mark(), high_mark);
+	assert_eq!(edit.import_section_raw(&[0; 608], &Block::default(), &Block::default()).err(), Some(std::io::ErrorKind::OutOfMemory));
+	let mut meta = MetaMap::new();
+	meta.set_bytes("big", &[0; 600]);
+	assert_eq!(edit.edit_file(b"a").set_meta(&meta, key).err(), Some(std::io::ErrorKind::OutOfMemory));
+
+	// Lifting the maximum size allows the allocation
+	edit.set_max_size(None);
+	assert!(edit.create_file(b"b", &[2; 600], key).is_some());
+	assert_eq!(edit.read_data(edit.find_file(b"a").unwrap(), key).unwrap(), [1; 500]);
+}
+
+#[test]
+fn test_public() {
+	let ref key = Key::default();
+	let ref other_key = [1, 2];
+	let mut edit = MemoryEditor::new();
+	edit.create_file(b"secret", EXAMPLE, key);
+	{
+		let mut edit_file = edit.edit_file(b"readme");
+		edit_file.set_content(1, EXAMPLE.len() as u32).allocate_data().unwrap().set_public(true).write_data(EXAMPLE, key);
+		edit_file.reencrypt_data(key, key);
+	}
+	let readme = *edit.find_file(b"readme").unwrap();
+	assert!(readme.is_public());
+	assert!(!edit.find_file(b"secret").unwrap().is_public());
+	assert_eq!(edit.pack_files(1 << 20, key), 1);
+
+	let (mut blocks, _) = edit.finish(key);
+	let offset = readme.section.offset as usize * BLOCK_SIZE;
+	assert_eq!(&blocks.as_bytes()[offset..offset + EXAMPLE.len()], EXAMPLE);
+
+	// The MAC still requires the key
+	let reader = MemoryReader::from_blocks(blocks.clone(), key).unwrap();
+	assert_eq!(reader.read_data(&readme, key).unwrap(), EXAMPLE);
+	assert_eq!(reader.read_data(&readme, other_key), Err(std::io::ErrorKind::InvalidData));
+
+	// Tampering with the plaintext is detected
+	blocks.as_bytes_mut()[offset] ^= 1;
+	let reader = MemoryReader::from_blocks(blocks, key).unwrap();
+	assert_eq!(reader.read_data(&readme, key), Err(std::io::ErrorKind::InvalidData));
+
+	// Overwriting a public file encrypts the new data
+	let mut edit = MemoryEditor::new();
+	edit.edit_file(b"readme").set_content(1, 5).allocate_data().unwrap().set_public(true).write_data(b"hello", key);
+	let desc = *edit.create_file(b"readme", b"world", key).unwrap();
+	assert!(!desc.is_public());
+	assert_eq!(edit.read_data(&desc, key).unwrap(), b"world");
+}
+
+#[test]
+fn test_shared() {
+	let ref key = [1, 2];
+
+	let mut edit = MemoryEditor::new();
+	edit.create_file(b"example", EXAMPLE, key);
+	let (blocks, _) = edit.finish(key);
+
+	let mut reader = MemoryReader::from_blocks(blocks, key).unwrap();
+	let clone = reader.clone();
+	// Caching the paths copies the shared directory
+	reader.cache_paths();
+
+	let desc = clone.find_file(b"example").unwrap();
+	let data = clone.read_data_shared(desc, key).unwrap();
+	assert_eq!(*data, *EXAMPLE);
+	assert_eq!(*data.slice(4..10), EXAMPLE[4..10]);
+	assert!(data.slice(0..0).is_empty());
+
+	// Editing a shared reader leaves its clones alone
+	let mut edit = MemoryEditor::from_reader(reader);
+	edit.remove(b"example");
+	assert!(edit.into_reader().find(b"example").is_none());
+	assert_eq!(clone.read_data(desc, key).unwrap(), EXAMPLE);
+}
+
+#[test]
+fn test_pinned() {
+	let ref key = [3, 4];
+
+	let mut edit = MemoryEditor::new();
+	edit.create_file(b"garbage", &[0; 200], key);
+	edit.create_file(b"small", b"small", key);
+	edit.create_file(b"index", EXAMPLE, key);
+	edit.create_file(b"large", &[1; 300], key);
+	edit.edit_file(b"index").pin();
+	let index = *edit.find_file(b"index").unwrap();
+	assert!(index.is_pinned());
+
+	// Pinned files stay in place while the other files are moved around them
+	edit.remove(b"garbage");
+	edit.gc_with_layout(Layout::Groups(&[b"small", b"large"]));
+	assert_eq!(edit.find_file(b"index").unwrap().section, index.section);
+	let small = edit.find_file(b"small").unwrap().section;
+	assert!(small.offset < index.section.offset);
+	let large = edit.find_file(b"large").unwrap().section;
+	assert!(large.offset >= index.section.offset + index.section.size);
+
+	// Pinned files are not packed
+	assert_eq!(edit.pack_files(4096, key), 2);
+	assert!(!edit.find_file(b"index").unwrap().is_packed());
+
+	let (blocks, _) = edit.finish(key);
+	let reader = MemoryReader::from_blocks(blocks, key).unwrap();
+	let desc = reader.find_file(b"index").unwrap();
+	assert!(desc.is_pinned());
+	assert_eq!(desc.section, index.section);
+	assert_eq!(reader.read_data(desc, key).unwrap(), EXAMPLE);
+	assert_eq!(reader.read_data(reader.find_file(b"small").unwrap(), key).unwrap(), b"small");
+	assert_eq!(reader.read_data(reader.find_file(b"large").unwrap(), key).unwrap(), &[1; 300][..]);
+}
+
+#[test]
+fn test_obfuscation() {
+	let ref key = [5, 6];
+	let options = ObfuscationOptions { shuffle: true, decoys: 3, decoy_max_size: 100 };
+
+	let mut edit = MemoryEditor::new();
+	edit.set_obfuscation(Some(options));
+	let paths: Vec<Vec<u8>> = (0..20).map(|i| format!("file{:02}", i).into_bytes()).collect();
+	for path in &paths {
+		edit.create_file(path, path, key);
+	}
+	let (blocks, _) = edit.finish(key);
+
+	let reader = MemoryReader::from_blocks(blocks, key).unwrap();
+	assert_eq!(reader.files_under(Directory::DECOYS).unwrap().len(), 3);
+	let offsets: Vec<u32> = paths.iter().map(|path| reader.find_file(path).unwrap().section.offset).collect();
+	assert!(offsets.windows(2).any(|pair| pair[0] > pair[1]));
+	for path in &paths {
+		assert_eq!(reader.read_data(reader.find_file(path).unwrap(), key).unwrap(), *path);
+	}
+
+	// The decoys are replaced when finishing again and removed when disabled
+	let mut edit = MemoryEditor::from_reader(reader);
+	edit.set_obfuscation(Some(ObfuscationOptions { shuffle: false, ..options }));
+	let (blocks, _) = edit.finish(key);
+	let reader = MemoryReader::from_blocks(blocks, key).unwrap();
+	assert_eq!(reader.files_under(Directory::DECOYS).unwrap().len(), 3);
+	let (_, directory) = MemoryEditor::from_reader(reader).finish(key);
+	assert!(directory.files_under(Directory::DECOYS).is_none());
+	assert_eq!(directory.file_count(), 20);
+}
+
+#[test]
+fn test_obfuscation_stripped() {
+	let ref key = [5, 6];
+	let options = ObfuscationOptions { shuffle: true, decoys: 3, decoy_max_size: 100 };
+
+	let mut edit = MemoryEditor::new();
+	edit.set_name_policy(NamePolicy { stripped: true, ..NamePolicy::default() });
+	edit.set_obfuscation(Some(options));
+	edit.create_file(b"a", b"a", key);
+	edit.create_file(b"b", b"b", key);
+	let (blocks, _) = edit.finish(key);
+
+	// The decoys are found by their flag and replaced when finishing again
+	let reader = MemoryReader::from_blocks(blocks, key).unwrap();
+	assert_eq!(reader.file_count(), 5);
+	let mut edit = MemoryEditor::from_reader(reader);
+	edit.set_obfuscation(Some(options));
+	let (blocks, _) = edit.finish(key);
+	let reader = MemoryReader::from_blocks(blocks, key).unwrap();
+	assert_eq!(reader.file_count(), 5);
+	assert_eq!(reader.query(&Filter::Files).filter(|(_, desc)| desc.is_decoy()).count(), 3);
+	assert_eq!(reader.read_data(reader.find_file(b"a").unwrap(), key).unwrap(), b"a");
+
+	// And removed when disabled
+	let (_, directory) = MemoryEditor::from_reader(reader).finish(key);
+	assert_eq!(directory.file_count(), 2);
+}
+
+#[test]
+fn test_overwrite_keeps_meta() {
+	let ref key = [7, 8];
+
+	let mut edit = MemoryEditor::new();
+	edit.create_file(b"a", b"old", key);
+	edit.tag(b"a", "ui", key).unwrap();
+	edit.create_file(b"a", b"new contents", key);
+	let (blocks, _) = edit.finish(key);
+
+	let reader = MemoryReader::from_blocks(blocks, key).unwrap();
+	let desc = reader.find_file(b"a").unwrap();
+	assert_eq!(reader.read_data(desc, key).unwrap(), b"new contents");
+	assert_eq!(reader.read_meta(desc, key).unwrap().tags(), ["ui"]);
+	assert_eq!(reader.find_by_tag("ui").len(), 1);
+}
+
+#[test]
+fn test_read_into_tampered() {
+	let ref key = [9, 10];
+
+	let mut edit = MemoryEditor::new();
+	edit.create_file(b"a", EXAMPLE, key);
+	let (mut blocks, _) = edit.finish(key);
+	let desc = *MemoryReader::from_blocks(blocks.clone(), key).unwrap().find_file(b"a").unwrap();
+
+	// Flip a byte in the ciphertext of the file, dest is left untouched
+	blocks.as_bytes_mut()[desc.section.offset as usize * BLOCK_SIZE + 3] ^= 1;
+	let reader = MemoryReader::from_blocks(blocks, key).unwrap();
+	let mut dest = [0xaa; 100];
+	assert_eq!(reader.read_into(&desc, key, 0, &mut dest), Err(std::io::ErrorKind::InvalidData));
+	assert_eq!(dest, [0xaa; 100]);
+}
+
+#[test]
+fn test_key_slots() {
+	let ref master_key = [1, 2];
+	let (ref alice, ref bob) = ([3, 4], [5, 6]);
+
+	// The file data in the way of the key slot table is moved, also with larger blocks
+	for &block_size in &InfoHeader::BLOCK_SIZES {
+		let mut edit = MemoryEditor::new();
+		edit.set_block_size(block_size);
+		edit.create_file(b"a", EXAMPLE, master_key);
+		edit.create_file(b"b", b"small", master_key);
+		edit.tag(b"b", "ui", master_key).unwrap();
+		assert_eq!(edit.add_key_slot(alice, master_key).unwrap(), 0);
+		assert_eq!(edit.add_key_slot(bob, master_key).unwrap(), 1);
+		edit.remove_key_slot(0, master_key).unwrap();
+		assert_eq!(edit.remove_key_slot(1, master_key), Err(std::io::ErrorKind::InvalidInput));
+		edit.gc_with_layout(Layout::Groups(&[b"b", b"a"]));
+		let (blocks, _) = edit.finish(master_key);
+
+		assert!(crate::key::unlock_bytes(blocks.as_bytes(), alice).is_err());
+		assert_eq!(crate::key::unlock_bytes(blocks.as_bytes(), bob).unwrap(), *master_key);
+		let reader = MemoryReader::from_blocks(blocks, master_key).unwrap();
+		assert_eq!(reader.key_slots(master_key).unwrap()[..2], [None, Some(crate::key::fingerprint(bob))]);
+		assert_eq!(reader.read_data(reader.find_file(b"a").unwrap(), master_key).unwrap(), EXAMPLE);
+		assert_eq!(reader.read_data(reader.find_file(b"b").unwrap(), master_key).unwrap(), b"small");
+		assert_eq!(reader.read_meta(reader.find_file(b"b").unwrap(), master_key).unwrap().tags(), ["ui"]);
+	}
+}
+
+#[test]
+fn test_stripped_collision() {
+	let ref key = [11, 12];
+
+	let mut edit = MemoryEditor::new();
+	edit.set_name_policy(NamePolicy { stripped: true, ..NamePolicy::default() });
+	edit.create_file(b"a/b", b"first", key).unwrap();
+	// Overwriting the file at the same path is fine
+	edit.create_file(b"a/b", b"second", key).unwrap();
+	assert!(!edit.hash_collides(b"a/b"));
+
+	// Pretend the file was created by another path with the same path hash
+	let id = edit.find(b"a/b").unwrap();
+	edit.get_mut(id).unwrap().name.buffer[16] ^= 1;
+	assert!(edit.hash_collides(b"a/b"));
+	assert!(edit.create_file(b"a/b", b"third", key).is_none());
+	let desc = *edit.find_file(b"a/b").unwrap();
+	assert_eq!(edit.create_link(b"a/b", &desc), None);
+	assert!(!edit.move_file(b"c", b"a/b"));
+
+	// The check hash is kept in the PAK file
+	let (blocks, _) = edit.finish(key);
+	let reader = MemoryReader::from_blocks(blocks, key).unwrap();
+	assert!(reader.hash_collides(b"a/b"));
+	assert_eq!(reader.read_data(reader.find_file(b"a/b").unwrap(), key).unwrap(), b"second");
+}
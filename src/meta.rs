@@ -0,0 +1,490 @@
+use std::{collections::HashMap, convert::TryInto, io, str};
+use crate::*;
+
+// Type tags of the values, zero ends the encoded entries
+const TAG_END: u8 = 0;
+const TAG_U32: u8 = 1;
+const TAG_U64: u8 = 2;
+const TAG_BYTES: u8 = 3;
+const TAG_STR: u8 = 4;
+
+/// Key-value store for the meta section of a file.
+///
+/// Different tools can store their own fields in the same meta section without clobbering each other's fields.
+/// Prefix the keys with the name of the tool to avoid collisions, eg. `texconv.mips`.
+///
+/// The entries are encoded as a sequence of type tag, key and value triples.
+/// Each entry starts with its type tag byte, followed by the key length as a `u16`, the key, the value length as a `u32` and the value.
+/// Integers are stored in little endian and a zero type tag ends the entries, the zero padding of the section ends them implicitly.
+/// Entries with unknown type tags are kept as-is so they survive being decoded and encoded again by older tools.
+///
+/// # Examples
+///
+/// ```
+/// let mut meta = paks::MetaMap::new();
+/// meta.set_u32("texconv.width", 256);
+/// meta.set_str("texconv.format", "bc7");
+/// meta.set_bytes("audio.loop", &[1, 2, 3]);
+///
+/// let meta = paks::MetaMap::decode(&meta.encode()).unwrap();
+/// assert_eq!(meta.get_u32("texconv.width"), Some(256));
+/// assert_eq!(meta.get_str("texconv.format"), Some("bc7"));
+/// assert_eq!(meta.get_bytes("audio.loop"), Some(&[1, 2, 3][..]));
+///
+/// // Getters of the wrong type find nothing
+/// assert_eq!(meta.get_u64("texconv.width"), None);
+/// ```
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct MetaMap {
+	entries: Vec<(String, u8, Vec<u8>)>,
+}
+
+impl MetaMap {
+	/// Key of the preview of the file, see [`MemoryEditFile::set_preview`].
+	pub const PREVIEW: &'static str = "paks.preview";
+
+	/// Key of the paths of the files the file depends on, see [`MemoryEditFile::set_dependencies`].
+	pub const DEPENDENCIES: &'static str = "paks.deps";
+
+	/// Key of the tags of the file, see [`MemoryEditor::tag`].
+	pub const TAGS: &'static str = "paks.tags";
+
+	/// Creates an empty key-value store.
+	#[inline]
+	pub fn new() -> MetaMap {
+		MetaMap::default()
+	}
+
+	/// Decodes the key-value store from the contents of a meta section.
+	///
+	/// Decoding stops at the first zero type tag or the end of the data.
+	/// Returns `None` if an entry is truncated or its key is not valid UTF-8.
+	pub fn decode(data: &[u8]) -> Option<MetaMap> {
+		let mut entries = Vec::new();
+		let mut data = data;
+		while let Some((&tag, rest)) = data.split_first() {
+			if tag == TAG_END {
+				break;
+			}
+			let (key_len, rest) = split_array::<2>(rest)?;
+			let (key, rest) = split_at(rest, u16::from_le_bytes(key_len) as usize)?;
+			let (value_len, rest) = split_array::<4>(rest)?;
+			let (value, rest) = split_at(rest, u32::from_le_bytes(value_len) as usize)?;
+			let key = str::from_utf8(key).ok()?;
+			entries.push((String::from(key), tag, value.to_vec()));
+			data = rest;
+		}
+		Some(MetaMap { entries })
+	}
+
+	/// Encodes the key-value store as the contents of a meta section.
+	///
+	/// Keys longer than 65535 bytes and values longer than 4 GiB are truncated.
+	pub fn encode(&self) -> Vec<u8> {
+		let mut data = Vec::new();
+		for (key, tag, value) in &self.entries {
+			let key = &key.as_bytes()[..usize::min(key.len(), u16::MAX as usize)];
+			let value = &value[..usize::min(value.len(), u32::MAX as usize)];
+			data.push(*tag);
+			data.extend_from_slice(&(key.len() as u16).to_le_bytes());
+			data.extend_from_slice(key);
+			data.extend_from_slice(&(value.len() as u32).to_le_bytes());
+			data.extend_from_slice(value);
+		}
+		data
+	}
+
+	/// Returns the number of entries.
+	#[inline]
+	pub fn len(&self) -> usize {
+		self.entries.len()
+	}
+
+	/// Returns if there are no entries.
+	#[inline]
+	pub fn is_empty(&self) -> bool {
+		self.entries.is_empty()
+	}
+
+	/// Returns the keys in the order they were first set.
+	pub fn keys(&self) -> impl Iterator<Item = &str> {
+		self.entries.iter().map(|(key, _, _)| key.as_str())
+	}
+
+	/// Returns if there is an entry with the given key of any type.
+	pub fn contains_key(&self, key: &str) -> bool {
+		self.entries.iter().any(|(entry_key, _, _)| entry_key == key)
+	}
+
+	/// Removes the entry with the given key.
+	///
+	/// Returns `false` if there is no entry with the given key.
+	pub fn remove(&mut self, key: &str) -> bool {
+		let len = self.entries.len();
+		self.entries.retain(|(entry_key, _, _)| entry_key != key);
+		self.entries.len() != len
+	}
+
+	/// Gets the `u32` value of the given key.
+	///
+	/// Returns `None` if there is no entry with the given key or it has a different type.
+	pub fn get_u32(&self, key: &str) -> Option<u32> {
+		let value = self.get(key, TAG_U32)?;
+		Some(u32::from_le_bytes(value.try_into().ok()?))
+	}
+
+	/// Gets the `u64` value of the given key.
+	///
+	/// Returns `None` if there is no entry with the given key or it has a different type.
+	pub fn get_u64(&self, key: &str) -> Option<u64> {
+		let value = self.get(key, TAG_U64)?;
+		Some(u64::from_le_bytes(value.try_into().ok()?))
+	}
+
+	/// Gets the bytes value of the given key.
+	///
+	/// Returns `None` if there is no entry with the given key or it has a different type.
+	pub fn get_bytes(&self, key: &str) -> Option<&[u8]> {
+		self.get(key, TAG_BYTES)
+	}
+
+	/// Gets the string value of the given key.
+	///
+	/// Returns `None` if there is no entry with the given key, it has a different type or is not valid UTF-8.
+	pub fn get_str(&self, key: &str) -> Option<&str> {
+		str::from_utf8(self.get(key, TAG_STR)?).ok()
+	}
+
+	/// Sets the `u32` value of the given key, replacing any previous value.
+	pub fn set_u32(&mut self, key: &str, value: u32) {
+		self.set(key, TAG_U32, &value.to_le_bytes());
+	}
+
+	/// Sets the `u64` value of the given key, replacing any previous value.
+	pub fn set_u64(&mut self, key: &str, value: u64) {
+		self.set(key, TAG_U64, &value.to_le_bytes());
+	}
+
+	/// Sets the bytes value of the given key, replacing any previous value.
+	pub fn set_bytes(&mut self, key: &str, value: &[u8]) {
+		self.set(key, TAG_BYTES, value);
+	}
+
+	/// Sets the string value of the given key, replacing any previous value.
+	pub fn set_str(&mut self, key: &str, value: &str) {
+		self.set(key, TAG_STR, value.as_bytes());
+	}
+
+	/// Gets the paths of the files the file depends on.
+	///
+	/// The paths are stored as the bytes value of the [dependencies](Self::DEPENDENCIES) key, separated by a zero byte.
+	pub fn dependencies(&self) -> Vec<&[u8]> {
+		match self.get_bytes(MetaMap::DEPENDENCIES) {
+			Some(value) if !value.is_empty() => value.split(|&byte| byte == 0).collect(),
+			_ => Vec::new(),
+		}
+	}
+
+	/// Sets the paths of the files the file depends on.
+	///
+	/// Removes the dependencies if there are no paths.
+	pub fn set_dependencies(&mut self, paths: &[&[u8]]) {
+		if paths.is_empty() {
+			self.remove(MetaMap::DEPENDENCIES);
+		}
+		else {
+			self.set_bytes(MetaMap::DEPENDENCIES, &paths.join(&0));
+		}
+	}
+
+	/// Gets the tags of the file.
+	///
+	/// The tags are stored as the string value of the [tags](Self::TAGS) key, separated by a zero byte.
+	pub fn tags(&self) -> Vec<&str> {
+		match self.get_str(MetaMap::TAGS) {
+			Some(value) => value.split('\0').filter(|tag| !tag.is_empty()).collect(),
+			None => Vec::new(),
+		}
+	}
+
+	/// Sets the tags of the file.
+	///
+	/// Removes the tags if there are none.
+	pub fn set_tags(&mut self, tags: &[&str]) {
+		if tags.is_empty() {
+			self.remove(MetaMap::TAGS);
+		}
+		else {
+			self.set_str(MetaMap::TAGS, &tags.join("\0"));
+		}
+	}
+
+	// Adds or removes the tag, returns `false` if the tag was already added or removed
+	pub(crate) fn retag(&mut self, tag: &str, add: bool) -> bool {
+		let mut tags: Vec<String> = self.tags().into_iter().map(String::from).collect();
+		if tags.iter().any(|other| other == tag) == add {
+			return false;
+		}
+		if add {
+			tags.push(String::from(tag));
+		}
+		else {
+			tags.retain(|other| other != tag);
+		}
+		let tags: Vec<&str> = tags.iter().map(String::as_str).collect();
+		self.set_tags(&tags);
+		true
+	}
+
+	fn get(&self, key: &str, tag: u8) -> Option<&[u8]> {
+		self.entries.iter()
+			.find(|(entry_key, entry_tag, _)| entry_key == key && *entry_tag == tag)
+			.map(|(_, _, value)| value.as_slice())
+	}
+
+	// Replaces the value in place to keep the order of the entries stable
+	fn set(&mut self, key: &str, tag: u8, value: &[u8]) {
+		match self.entries.iter_mut().find(|(entry_key, _, _)| entry_key == key) {
+			Some(entry) => {
+				entry.1 = tag;
+				entry.2 = value.to_vec();
+			},
+			None => self.entries.push((String::from(key), tag, value.to_vec())),
+		}
+	}
+}
+
+impl FileReader {
+	/// Decrypts the [metadata](MetaMap) of the file.
+	///
+	/// Returns an empty key-value store if the file has no metadata, see [`Descriptor::meta_section`].
+	///
+	/// # Errors
+	///
+	/// * [`io::ErrorKind::InvalidData`]: The MAC of the meta section is incorrect or its contents are corrupted.
+	/// * [`io::ErrorKind::UnexpectedEof`]: The meta section extends past the end of the PAK file.
+	pub fn read_meta(&self, desc: &Descriptor, key: &Key) -> io::Result<MetaMap> {
+		let section = match desc.meta_section() {
+			Some(section) => section,
+			None => return Ok(MetaMap::new()),
+		};
+		let blocks = self.read_section(&section, key)?;
+		match MetaMap::decode(blocks.as_bytes()) {
+			Some(meta) => Ok(meta),
+			None => Err(io::ErrorKind::InvalidData)?,
+		}
+	}
+
+	/// Reads the preview of the file stored in its metadata.
+	///
+	/// Only the meta section is decrypted, not the file contents.
+	/// Returns `None` if the file has no preview.
+	pub fn read_preview(&self, desc: &Descriptor, key: &Key) -> io::Result<Option<Vec<u8>>> {
+		let meta = self.read_meta(desc, key)?;
+		Ok(meta.get_bytes(MetaMap::PREVIEW).map(|preview| preview.to_vec()))
+	}
+
+	/// Returns the load order of the file at the given path and everything it depends on.
+	///
+	/// The [dependencies](MetaMap::dependencies) are resolved recursively from the metadata of the files.
+	/// Every file is listed once after all the files it depends on, the file at the given path is listed last.
+	///
+	/// # Errors
+	///
+	/// * [`io::ErrorKind::NotFound`]: The file or one of its dependencies does not exist.
+	/// * [`io::ErrorKind::InvalidData`]: The dependencies form a cycle or the metadata of a file is corrupted.
+	pub fn resolve_dependencies(&self, path: &[u8], key: &Key) -> io::Result<Vec<Vec<u8>>> {
+		resolve_dependencies(path, |path| {
+			let desc = match self.find_file(path) {
+				Some(desc) => desc,
+				None => Err(io::Error::new(io::ErrorKind::NotFound, format!("dependency not found: {}", String::from_utf8_lossy(path))))?,
+			};
+			let meta = self.read_meta(desc, key)?;
+			Ok(meta.dependencies().into_iter().map(<[u8]>::to_vec).collect())
+		})
+	}
+}
+
+impl MemoryReader {
+	/// Decrypts the [metadata](MetaMap) of the file.
+	///
+	/// Returns an empty key-value store if the file has no metadata, see [`Descriptor::meta_section`].
+	///
+	/// # Errors
+	///
+	/// * [`io::ErrorKind::InvalidInput`]: The meta section is out of bounds.
+	/// * [`io::ErrorKind::InvalidData`]: The MAC of the meta section is incorrect or its contents are corrupted.
+	pub fn read_meta(&self, desc: &Descriptor, key: &Key) -> Result<MetaMap, io::ErrorKind> {
+		let section = match desc.meta_section() {
+			Some(section) => section,
+			None => return Ok(MetaMap::new()),
+		};
+		let blocks = self.read_section(&section, key)?;
+		MetaMap::decode(blocks.as_bytes()).ok_or(io::ErrorKind::InvalidData)
+	}
+
+	/// Reads the preview of the file stored in its metadata.
+	///
+	/// Only the meta section is decrypted, not the file contents.
+	/// Returns `None` if the file has no preview.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// let ref key = paks::Key::default();
+	/// let mut editor = paks::MemoryEditor::new();
+	/// editor.create_file(b"textures/wall.png", &[0; 4096], key);
+	/// editor.edit_file(b"textures/wall.png").set_preview(b"thumbnail", key).unwrap();
+	/// editor.create_file(b"textures/floor.png", &[0; 4096], key);
+	///
+	/// let (blocks, _) = editor.finish(key);
+	/// let reader = paks::MemoryReader::from_blocks(blocks, key).unwrap();
+	/// let wall = reader.find_file(b"textures/wall.png").unwrap();
+	/// let floor = reader.find_file(b"textures/floor.png").unwrap();
+	/// assert_eq!(reader.read_preview(wall, key).unwrap().unwrap(), b"thumbnail");
+	/// assert_eq!(reader.read_preview(floor, key).unwrap(), None);
+	/// ```
+	pub fn read_preview(&self, desc: &Descriptor, key: &Key) -> Result<Option<Vec<u8>>, io::ErrorKind> {
+		let meta = self.read_meta(desc, key)?;
+		Ok(meta.get_bytes(MetaMap::PREVIEW).map(|preview| preview.to_vec()))
+	}
+
+	/// Returns the load order of the file at the given path and everything it depends on.
+	///
+	/// See [`FileReader::resolve_dependencies`] for more information.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// let ref key = paks::Key::default();
+	/// let mut editor = paks::MemoryEditor::new();
+	/// editor.create_file(b"scene", b"scene", key);
+	/// editor.edit_file(b"scene").set_dependencies(&[b"player", b"level"], key).unwrap();
+	/// editor.create_file(b"player", b"player", key);
+	/// editor.edit_file(b"player").set_dependencies(&[b"texture"], key).unwrap();
+	/// editor.create_file(b"level", b"level", key);
+	/// editor.edit_file(b"level").set_dependencies(&[b"texture"], key).unwrap();
+	/// editor.create_file(b"texture", b"texture", key);
+	///
+	/// let (blocks, _) = editor.finish(key);
+	/// let reader = paks::MemoryReader::from_blocks(blocks, key).unwrap();
+	/// let order = reader.resolve_dependencies(b"scene", key).unwrap();
+	/// assert_eq!(order, [&b"texture"[..], b"player", b"level", b"scene"]);
+	/// ```
+	pub fn resolve_dependencies(&self, path: &[u8], key: &Key) -> Result<Vec<Vec<u8>>, io::ErrorKind> {
+		resolve_dependencies(path, |path| {
+			let desc = self.find_file(path).ok_or(io::ErrorKind::NotFound)?;
+			let meta = self.read_meta(desc, key)?;
+			Ok(meta.dependencies().into_iter().map(<[u8]>::to_vec).collect())
+		})
+	}
+}
+
+// Visits the dependencies of the path depth first and appends the path after its dependencies
+// The state of a path is `false` while its dependencies are visited and `true` once it is ordered
+fn resolve<E, F>(path: &[u8], dependencies: &mut F, state: &mut HashMap<Vec<u8>, bool>, order: &mut Vec<Vec<u8>>) -> Result<(), E>
+	where E: From<io::ErrorKind>, F: FnMut(&[u8]) -> Result<Vec<Vec<u8>>, E>
+{
+	match state.get(path) {
+		Some(true) => return Ok(()),
+		// Found a path while visiting its own dependencies
+		Some(false) => return Err(E::from(io::ErrorKind::InvalidData)),
+		None => (),
+	}
+	state.insert(path.to_vec(), false);
+	for dependency in dependencies(path)? {
+		resolve(&dependency, dependencies, state, order)?;
+	}
+	state.insert(path.to_vec(), true);
+	order.push(path.to_vec());
+	Ok(())
+}
+
+// Returns the load order of the file and its dependencies
+fn resolve_dependencies<E, F>(path: &[u8], mut dependencies: F) -> Result<Vec<Vec<u8>>, E>
+	where E: From<io::ErrorKind>, F: FnMut(&[u8]) -> Result<Vec<Vec<u8>>, E>
+{
+	let mut order = Vec::new();
+	resolve(path, &mut dependencies, &mut HashMap::new(), &mut order)?;
+	Ok(order)
+}
+
+fn split_at(data: &[u8], mid: usize) -> Option<(&[u8], &[u8])> {
+	if mid > data.len() {
+		return None;
+	}
+	Some(data.split_at(mid))
+}
+
+fn split_array<const N: usize>(data: &[u8]) -> Option<([u8; N], &[u8])> {
+	let (head, rest) = split_at(data, N)?;
+	Some((head.try_into().ok()?, rest))
+}
+
+#[test]
+fn test_meta_map() {
+	let mut meta = MetaMap::new();
+	meta.set_u32("a.width", 0x12345678);
+	meta.set_u64("b.time", 0x123456789abcdef0);
+	meta.set_bytes("a.data", b"");
+	meta.set_str("b.name", "héllo");
+	assert_eq!(meta.len(), 4);
+
+	// Setting a key again replaces its value and type in place
+	meta.set_str("a.width", "wide");
+	assert_eq!(meta.get_u32("a.width"), None);
+	assert_eq!(meta.get_str("a.width"), Some("wide"));
+	assert_eq!(meta.keys().collect::<Vec<_>>(), ["a.width", "b.time", "a.data", "b.name"]);
+
+	// Decoding ignores the zero padding of the section
+	let mut data = meta.encode();
+	data.resize(data.len() + 13, 0);
+	let decoded = MetaMap::decode(&data).unwrap();
+	assert_eq!(decoded, meta);
+	assert_eq!(decoded.get_u64("b.time"), Some(0x123456789abcdef0));
+	assert_eq!(decoded.get_bytes("a.data"), Some(&b""[..]));
+	assert_eq!(decoded.get_str("b.name"), Some("héllo"));
+
+	// Unknown types are preserved
+	let mut data = vec![0x7f, 3, 0, b'n', b'e', b'w', 2, 0, 0, 0, 1, 2];
+	data.extend_from_slice(&meta.encode());
+	let mut decoded = MetaMap::decode(&data).unwrap();
+	assert!(decoded.contains_key("new"));
+	assert!(decoded.remove("a.data"));
+	assert!(!decoded.remove("a.data"));
+	assert_eq!(&decoded.encode()[..12], &data[..12]);
+
+	// Truncated entries and invalid keys are rejected
+	let data = meta.encode();
+	assert!(MetaMap::decode(&data[..data.len() - 1]).is_none());
+	assert!(MetaMap::decode(&data[..5]).is_none());
+	assert!(MetaMap::decode(&[TAG_U32, 1, 0, 0xff, 0, 0, 0, 0]).is_none());
+	assert_eq!(MetaMap::decode(&[]), Some(MetaMap::new()));
+}
+
+#[test]
+fn test_resolve_dependencies() {
+	let graph = |path: &[u8]| -> Result<Vec<Vec<u8>>, io::ErrorKind> {
+		let deps: &[&[u8]] = match path {
+			b"a" => &[b"b", b"c"],
+			b"b" => &[b"d"],
+			b"c" => &[b"d", b"b"],
+			b"d" => &[],
+			b"x" => &[b"y"],
+			b"y" => &[b"x"],
+			_ => return Err(io::ErrorKind::NotFound),
+		};
+		Ok(deps.iter().map(|dep| dep.to_vec()).collect())
+	};
+	assert_eq!(resolve_dependencies(b"a", graph).unwrap(), [&b"d"[..], b"b", b"c", b"a"]);
+	assert_eq!(resolve_dependencies(b"d", graph).unwrap(), [b"d"]);
+	assert_eq!(resolve_dependencies(b"x", graph), Err(io::ErrorKind::InvalidData));
+	assert_eq!(resolve_dependencies(b"z", graph), Err(io::ErrorKind::NotFound));
+
+	let mut meta = MetaMap::new();
+	assert!(meta.dependencies().is_empty());
+	meta.set_dependencies(&[b"a/b", b"c"]);
+	assert_eq!(meta.dependencies(), [&b"a/b"[..], b"c"]);
+	meta.set_dependencies(&[]);
+	assert!(meta.is_empty());
+}
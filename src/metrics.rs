@@ -0,0 +1,41 @@
+/*!
+Process-wide counters for monitoring the library.
+*/
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+pub(crate) static NONCES_GENERATED: AtomicU64 = AtomicU64::new(0);
+pub(crate) static NONCE_COLLISIONS: AtomicU64 = AtomicU64::new(0);
+pub(crate) static AUTO_GC_RUNS: AtomicU64 = AtomicU64::new(0);
+pub(crate) static AUTO_GC_BYTES: AtomicU64 = AtomicU64::new(0);
+
+/// Returns the number of random nonces generated for encryption.
+#[inline]
+pub fn nonces_generated() -> u64 {
+	NONCES_GENERATED.load(Ordering::Relaxed)
+}
+
+/// Returns the number of random nonce collisions encountered during encryption.
+///
+/// A collision is detected when the random generator returns the same nonce as the previous encryption on the same thread.
+/// The nonce is regenerated when this happens, any non-zero count indicates a misbehaving random generator.
+///
+/// Use [`Directory::audit_nonces`](crate::Directory::audit_nonces) to find nonce reuse in a PAK file.
+#[inline]
+pub fn nonce_collisions() -> u64 {
+	NONCE_COLLISIONS.load(Ordering::Relaxed)
+}
+
+/// Returns the number of times finishing an editor collected garbage automatically.
+///
+/// See [`MemoryEditor::set_auto_gc_threshold`](crate::MemoryEditor::set_auto_gc_threshold).
+#[inline]
+pub fn auto_gc_runs() -> u64 {
+	AUTO_GC_RUNS.load(Ordering::Relaxed)
+}
+
+/// Returns the number of bytes reclaimed by automatic garbage collection.
+#[inline]
+pub fn auto_gc_bytes() -> u64 {
+	AUTO_GC_BYTES.load(Ordering::Relaxed)
+}
@@ -0,0 +1,354 @@
+use std::{fmt, mem};
+use crate::*;
+
+/// Stable handle to a node in an [`ArchiveModel`].
+///
+/// Handles remain valid across edits until the node is removed, handles of removed nodes are never reused.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct NodeId(u32);
+
+/// Node in an [`ArchiveModel`].
+#[derive(Clone, Debug)]
+pub struct ModelNode {
+	desc: Descriptor,
+	parent: Option<NodeId>,
+	children: Vec<NodeId>,
+}
+
+impl ModelNode {
+	/// Gets the descriptor of this node.
+	///
+	/// The `content_size` of directory descriptors is only updated when the model is converted back to a [`Directory`].
+	#[inline]
+	pub fn descriptor(&self) -> &Descriptor {
+		&self.desc
+	}
+
+	/// Gets the node's name.
+	#[inline]
+	pub fn name(&self) -> &[u8] {
+		self.desc.name()
+	}
+
+	/// Gets the parent node, `None` if the node is at the root.
+	#[inline]
+	pub fn parent(&self) -> Option<NodeId> {
+		self.parent
+	}
+
+	/// Gets the child nodes in order.
+	#[inline]
+	pub fn children(&self) -> &[NodeId] {
+		&self.children
+	}
+}
+
+/// Change notifications emitted by the [`ArchiveModel`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ModelEvent {
+	/// A node was created.
+	Created(NodeId),
+	/// A node was removed.
+	///
+	/// Removing a directory removes all its descendants, only a single event is emitted for the directory.
+	Removed(NodeId),
+	/// A node was moved to a new parent and possibly renamed.
+	Moved {
+		id: NodeId,
+		from: Option<NodeId>,
+		to: Option<NodeId>,
+	},
+	/// The descriptor of a node was updated.
+	Updated(NodeId),
+}
+
+type Listener = Box<dyn FnMut(&ModelEvent)>;
+
+/// Observable tree representation of a [`Directory`].
+///
+/// The directory is decoupled from its TLV representation for embedding in editors and inspectors.
+/// Every node is addressed by a stable [`NodeId`] and every change is reported to the subscribed listeners.
+///
+/// Convert the model back into a directory with [`to_directory`](Self::to_directory).
+#[derive(Default)]
+pub struct ArchiveModel {
+	nodes: Vec<Option<ModelNode>>,
+	root: Vec<NodeId>,
+	listeners: Vec<Listener>,
+}
+
+impl ArchiveModel {
+	/// Creates a new, empty `ArchiveModel` instance.
+	#[inline]
+	pub fn new() -> ArchiveModel {
+		ArchiveModel::default()
+	}
+
+	/// Creates a model from the directory.
+	pub fn from_directory(directory: &Directory) -> ArchiveModel {
+		let mut model = ArchiveModel::new();
+		model.root = model.build(None, directory.as_ref());
+		model
+	}
+	fn build(&mut self, parent: Option<NodeId>, dir: &[Descriptor]) -> Vec<NodeId> {
+		let mut ids = Vec::new();
+		let mut i = 0;
+		while i < dir.len() {
+			let desc = &dir[i];
+			let next_i = dir::next_sibling(desc, i, dir.len());
+			let id = self.alloc(ModelNode { desc: *desc, parent, children: Vec::new() });
+			if desc.is_dir() {
+				let children = self.build(Some(id), &dir[i + 1..next_i]);
+				self.node_mut(id).children = children;
+			}
+			ids.push(id);
+			i = next_i;
+		}
+		ids
+	}
+
+	/// Converts the model to a directory.
+	pub fn to_directory(&self) -> Directory {
+		let mut dir = Vec::new();
+		self.flatten(&self.root, &mut dir);
+		Directory::from(dir)
+	}
+	fn flatten(&self, ids: &[NodeId], dir: &mut Vec<Descriptor>) {
+		for &id in ids {
+			let node = self.node(id);
+			let i = dir.len();
+			dir.push(node.desc);
+			if node.desc.is_dir() {
+				self.flatten(&node.children, dir);
+				dir[i].content_size = (dir.len() - i - 1) as u32;
+			}
+		}
+	}
+
+	/// Subscribes a listener to change notifications.
+	pub fn subscribe<F: 'static + FnMut(&ModelEvent)>(&mut self, listener: F) {
+		self.listeners.push(Box::new(listener));
+	}
+
+	fn notify(&mut self, event: ModelEvent) {
+		for listener in &mut self.listeners {
+			listener(&event);
+		}
+	}
+
+	fn alloc(&mut self, node: ModelNode) -> NodeId {
+		let id = NodeId(self.nodes.len() as u32);
+		self.nodes.push(Some(node));
+		id
+	}
+	fn node(&self, id: NodeId) -> &ModelNode {
+		self.nodes[id.0 as usize].as_ref().expect("invalid node id")
+	}
+	fn node_mut(&mut self, id: NodeId) -> &mut ModelNode {
+		self.nodes[id.0 as usize].as_mut().expect("invalid node id")
+	}
+	fn siblings_mut(&mut self, parent: Option<NodeId>) -> &mut Vec<NodeId> {
+		match parent {
+			Some(parent) => &mut self.node_mut(parent).children,
+			None => &mut self.root,
+		}
+	}
+}
+
+impl ArchiveModel {
+	/// Gets the nodes at the root of the directory.
+	#[inline]
+	pub fn root(&self) -> &[NodeId] {
+		&self.root
+	}
+
+	/// Gets a node by its handle.
+	///
+	/// Returns `None` if the node was removed.
+	#[inline]
+	pub fn get(&self, id: NodeId) -> Option<&ModelNode> {
+		self.nodes.get(id.0 as usize).and_then(Option::as_ref)
+	}
+
+	/// Returns the number of nodes in the model.
+	pub fn len(&self) -> usize {
+		self.nodes.iter().filter(|node| node.is_some()).count()
+	}
+
+	/// Returns if there are no nodes in the model.
+	#[inline]
+	pub fn is_empty(&self) -> bool {
+		self.root.is_empty()
+	}
+
+	/// Finds a node by its path.
+	pub fn find(&self, path: &[u8]) -> Option<NodeId> {
+		let mut siblings = &self.root[..];
+		let normal = dir::normalize(path);
+		let mut path = &normal[..];
+		'outer: while !path.is_empty() {
+			for &id in siblings {
+				let node = self.node(id);
+				if let Some(tail) = dir::name_eq(&node.desc, path) {
+					if tail.is_empty() {
+						return Some(id);
+					}
+					if node.desc.is_dir() {
+						siblings = &node.children;
+						path = tail;
+						continue 'outer;
+					}
+				}
+			}
+			break;
+		}
+		None
+	}
+
+	/// Returns the full path of the node.
+	pub fn path(&self, id: NodeId) -> Vec<u8> {
+		let node = self.node(id);
+		let mut path = match node.parent {
+			Some(parent) => {
+				let mut path = self.path(parent);
+				path.push(b'/');
+				path
+			},
+			None => Vec::new(),
+		};
+		path.extend_from_slice(node.name());
+		path
+	}
+}
+
+impl ArchiveModel {
+	/// Creates a node at the given path with the given descriptor.
+	///
+	/// Any missing parent directories are automatically created.
+	/// If a node already exists at this path its descriptor is updated.
+	///
+	/// The name of the descriptor is replaced with the last component of the path.
+	///
+	/// Returns `None` and does nothing if the path has no components or one of its parents is a file.
+	pub fn create(&mut self, path: &[u8], desc: &Descriptor) -> Option<NodeId> {
+		let mut parent = None;
+		let mut components = path.split(|&c| c == b'/' || c == b'\\').filter(|name| !name.is_empty()).peekable();
+		while let Some(name) = components.next() {
+			let is_last = components.peek().is_none();
+			let siblings = match parent {
+				Some(parent) => &self.node(parent).children,
+				None => &self.root,
+			};
+			let existing = siblings.iter().cloned().find(|&id| self.node(id).name() == name);
+
+			let id = match existing {
+				// A file is in the way of a parent directory
+				Some(id) if !is_last && !self.node(id).desc.is_dir() => return None,
+				Some(id) if is_last => {
+					// Files cannot have children
+					if desc.is_file() {
+						let children = mem::take(&mut self.node_mut(id).children);
+						for child in children {
+							self.free(child);
+						}
+					}
+					let node = self.node_mut(id);
+					node.desc = *desc;
+					node.desc.name = Name::from(name);
+					self.notify(ModelEvent::Updated(id));
+					id
+				},
+				Some(id) => id,
+				None => {
+					let mut new_desc = if is_last { *desc } else { Descriptor::dir(name, 0) };
+					new_desc.name = Name::from(name);
+					let id = self.alloc(ModelNode { desc: new_desc, parent, children: Vec::new() });
+					self.siblings_mut(parent).push(id);
+					self.notify(ModelEvent::Created(id));
+					id
+				},
+			};
+
+			if is_last {
+				return Some(id);
+			}
+			parent = Some(id);
+		}
+		None
+	}
+
+	/// Removes a node and all its descendants.
+	///
+	/// Returns `false` if the node was already removed.
+	pub fn remove(&mut self, id: NodeId) -> bool {
+		let parent = match self.get(id) {
+			Some(node) => node.parent,
+			None => return false,
+		};
+		self.siblings_mut(parent).retain(|&child| child != id);
+		self.free(id);
+		self.notify(ModelEvent::Removed(id));
+		true
+	}
+	fn free(&mut self, id: NodeId) {
+		if let Some(node) = self.nodes[id.0 as usize].take() {
+			for child in node.children {
+				self.free(child);
+			}
+		}
+	}
+
+	/// Moves a node to a new parent directory with a new name.
+	///
+	/// Returns `false` if the node or the new parent was removed, the new parent is not a directory,
+	/// the new parent is the node itself or one of its descendants,
+	/// or the new parent already has another node with the new name.
+	pub fn move_node(&mut self, id: NodeId, to: Option<NodeId>, name: &[u8]) -> bool {
+		let from = match self.get(id) {
+			Some(node) => node.parent,
+			None => return false,
+		};
+
+		// Check that the new parent is a directory outside the moved subtree
+		let mut ancestor = to;
+		while let Some(ancestor_id) = ancestor {
+			let node = match self.get(ancestor_id) {
+				Some(node) if node.desc.is_dir() => node,
+				_ => return false,
+			};
+			if ancestor_id == id {
+				return false;
+			}
+			ancestor = node.parent;
+		}
+
+		// Check that the new name does not clash with a sibling
+		let siblings = match to {
+			Some(to) => &self.node(to).children,
+			None => &self.root,
+		};
+		if siblings.iter().any(|&child| child != id && self.node(child).name() == name) {
+			return false;
+		}
+
+		self.siblings_mut(from).retain(|&child| child != id);
+		self.siblings_mut(to).push(id);
+		let node = self.node_mut(id);
+		node.parent = to;
+		node.desc.name = Name::from(name);
+		self.notify(ModelEvent::Moved { id, from, to });
+		true
+	}
+}
+
+impl fmt::Debug for ArchiveModel {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("ArchiveModel")
+			.field("nodes", &self.nodes)
+			.field("root", &self.root)
+			.finish()
+	}
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,69 @@
+use std::{cell::RefCell, rc::Rc};
+use crate::*;
+
+#[test]
+fn test_model_roundtrip() {
+	let mut directory = Directory::new();
+	directory.create_dir(b"a/b");
+	directory.create_link(b"a/file", &Descriptor::file(b""));
+	directory.create_link(b"top", &Descriptor::file(b""));
+
+	let model = ArchiveModel::from_directory(&directory);
+	assert_eq!(model.len(), 4);
+	assert_eq!(model.to_directory().as_ref(), directory.as_ref());
+}
+
+#[test]
+fn test_model_events() {
+	let events = Rc::new(RefCell::new(Vec::new()));
+	let mut model = ArchiveModel::new();
+	{
+		let events = events.clone();
+		model.subscribe(move |event| events.borrow_mut().push(*event));
+	}
+
+	let file = model.create(b"a/b/file", &Descriptor::file(b"")).unwrap();
+	assert_eq!(model.create(b"//", &Descriptor::file(b"")), None);
+	let a = model.find(b"a").unwrap();
+	let b = model.find(b"a/b").unwrap();
+	assert_eq!(model.find(b"a/b/file"), Some(file));
+	assert_eq!(model.path(file), b"a/b/file");
+
+	assert!(model.move_node(file, None, b"moved"));
+	assert!(!model.move_node(a, Some(b), b"a"));
+	assert!(model.remove(a));
+	assert!(model.get(b).is_none());
+	assert_eq!(model.get(file).unwrap().name(), b"moved");
+
+	assert_eq!(*events.borrow(), [
+		ModelEvent::Created(a),
+		ModelEvent::Created(b),
+		ModelEvent::Created(file),
+		ModelEvent::Moved { id: file, from: Some(b), to: None },
+		ModelEvent::Removed(a),
+	]);
+
+	let directory = model.to_directory();
+	assert_eq!(directory.len(), 1);
+	assert!(directory.find_file(b"moved").is_some());
+}
+
+#[test]
+fn test_model_name_clash() {
+	let mut model = ArchiveModel::new();
+	let file = model.create(b"a/file", &Descriptor::file(b"")).unwrap();
+	let other = model.create(b"a/other", &Descriptor::file(b"")).unwrap();
+	let top = model.create(b"file", &Descriptor::file(b"")).unwrap();
+
+	// Moving or renaming onto an existing name is refused
+	assert!(!model.move_node(other, model.find(b"a"), b"file"));
+	assert!(!model.move_node(file, None, b"file"));
+	assert!(model.move_node(file, model.find(b"a"), b"file"));
+	assert_eq!(model.path(other), b"a/other");
+	assert_eq!(model.path(top), b"file");
+
+	// Files cannot become parent directories
+	assert_eq!(model.create(b"file/child", &Descriptor::file(b"")), None);
+	assert_eq!(model.len(), 4);
+	assert_eq!(model.to_directory().len(), 4);
+}
@@ -0,0 +1,62 @@
+use std::io;
+use crate::*;
+
+impl FileReader {
+	/// Opens the nested PAK file held by the file descriptor.
+	///
+	/// See [`MemoryReader::open_nested`] for more information.
+	pub fn open_nested(&self, desc: &Descriptor, key: &Key, inner_key: &Key) -> io::Result<MemoryReader> {
+		if !desc.is_nested() {
+			Err(io::ErrorKind::InvalidInput)?;
+		}
+		let data = self.read_data(desc, key)?;
+		Ok(MemoryReader::from_bytes(&data, inner_key)?)
+	}
+}
+
+impl MemoryReader {
+	/// Opens the nested PAK file held by the file descriptor.
+	///
+	/// Modular content bundles can be stored as PAK files inside a master PAK file.
+	/// Files holding a nested PAK file have a content type of [`Descriptor::CONTENT_TYPE_PAK`].
+	///
+	/// The contents of the file are decrypted with the key and the nested PAK file is opened with the inner key.
+	/// The nested PAK file is held in memory by the returned reader.
+	///
+	/// # Errors
+	///
+	/// * [`io::ErrorKind::InvalidInput`]: The descriptor is not a file holding a nested PAK file.
+	///
+	/// See [`read_data`](Self::read_data) and [`from_bytes`](Self::from_bytes) for the other errors.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use paks::{Descriptor, MemoryEditor, MemoryReader};
+	///
+	/// let ref key = paks::Key::default();
+	/// let ref inner_key = [1, 2];
+	///
+	/// let mut inner = MemoryEditor::new();
+	/// inner.create_file(b"weapons/rifle", b"rifle", inner_key);
+	/// let (blocks, _) = inner.finish(inner_key);
+	///
+	/// let mut editor = MemoryEditor::new();
+	/// let bytes = paks::as_bytes(&blocks);
+	/// editor.create_file(b"dlc.pak", bytes, key);
+	/// editor.edit_file(b"dlc.pak").set_content(Descriptor::CONTENT_TYPE_PAK, bytes.len() as u32);
+	/// let (blocks, _) = editor.finish(key);
+	///
+	/// let reader = MemoryReader::from_blocks(blocks, key).unwrap();
+	/// let dlc = reader.open_nested(reader.find_file(b"dlc.pak").unwrap(), key, inner_key).unwrap();
+	/// let rifle = dlc.find_file(b"weapons/rifle").unwrap();
+	/// assert_eq!(dlc.read_data(rifle, inner_key).unwrap(), b"rifle");
+	/// ```
+	pub fn open_nested(&self, desc: &Descriptor, key: &Key, inner_key: &Key) -> Result<MemoryReader, io::ErrorKind> {
+		if !desc.is_nested() {
+			return Err(io::ErrorKind::InvalidInput);
+		}
+		let data = self.read_data(desc, key)?;
+		MemoryReader::from_bytes(&data, inner_key)
+	}
+}
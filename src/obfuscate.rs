@@ -0,0 +1,188 @@
+use std::io;
+use crate::*;
+
+/// Options frustrating the fingerprinting of encrypted PAK files, see [`MemoryEditor::set_obfuscation`].
+///
+/// Without the key the files of a PAK file cannot be read, yet the sizes and order of the encrypted sections may identify its contents.
+/// Shuffling the file data and adding decoy files of random size make PAK files with the same contents look different.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct ObfuscationOptions {
+	/// Stores the file data in random order, see [`Layout::Shuffle`].
+	pub shuffle: bool,
+	/// Number of decoy files added to the PAK file.
+	pub decoys: u32,
+	/// Maximum size in bytes of a decoy file, every decoy file has a random size up to this size.
+	pub decoy_max_size: u32,
+}
+
+impl Default for ObfuscationOptions {
+	#[inline]
+	fn default() -> ObfuscationOptions {
+		ObfuscationOptions { shuffle: true, decoys: 16, decoy_max_size: 64 * 1024 }
+	}
+}
+
+impl ObfuscationOptions {
+	fn check(&self) {
+		assert!(self.decoys == 0 || self.decoy_max_size != 0, "decoy files require a maximum size");
+	}
+}
+
+impl Directory {
+	/// Path of the hidden directory holding the decoy files, see [`MemoryEditor::set_obfuscation`].
+	pub const DECOYS: &'static [u8] = b".pak/decoys";
+
+	// Removes the decoy files left behind by earlier edits
+	// Decoys are found by their flag, stripped PAK files do not keep their paths
+	fn remove_decoys(&mut self) {
+		let paths: Vec<Vec<u8>> = self.query(&Filter::Files).filter(|(_, desc)| desc.is_decoy()).map(|(path, _)| path).collect();
+		for path in paths {
+			self.remove_stored(&path);
+		}
+		self.remove(Directory::DECOYS);
+	}
+
+	// Flags the file as a decoy to be removed by the next edit
+	fn mark_decoy(&mut self, path: &[u8]) {
+		if let Some(desc) = self.find(path).and_then(|id| self.get_mut(id)) {
+			desc.set_flags(desc.flags() | Descriptor::FLAG_DECOY);
+		}
+	}
+}
+
+// Random numbers for the obfuscation, not used for cryptography
+fn random_u64s(n: usize) -> Vec<u64> {
+	let mut blocks = vec![Block::default(); n.div_ceil(2)];
+	crypt::random(&mut blocks);
+	blocks.iter().flatten().copied().take(n).collect()
+}
+
+// Shuffles the values in random order
+pub(crate) fn shuffle<T>(values: &mut [T]) {
+	let random = random_u64s(values.len());
+	for i in (1..values.len()).rev() {
+		values.swap(i, (random[i] % (i as u64 + 1)) as usize);
+	}
+}
+
+// Random paths and sizes of the decoy files
+fn decoys(options: &ObfuscationOptions) -> Vec<(Vec<u8>, u32)> {
+	let random = random_u64s(options.decoys as usize * 2);
+	random.chunks_exact(2).map(|pair| {
+		let path = format!("{}/{:016x}", String::from_utf8_lossy(Directory::DECOYS), pair[0]).into_bytes();
+		let size = (pair[1] % options.decoy_max_size as u64) as u32 + 1;
+		(path, size)
+	}).collect()
+}
+
+impl MemoryEditor {
+	/// Obfuscates the file data when finishing the PAK file.
+	///
+	/// The decoy files are created in the hidden directory [`Directory::DECOYS`] with zeroed contents encrypted like any other file.
+	/// Decoys left behind by earlier edits are replaced or removed if disabled, readers with the key can tell the decoy files apart by their [flag](Descriptor::is_decoy).
+	/// Shuffling the file data collects the garbage like [`gc`](Self::gc), [pinned](Descriptor::is_pinned) files stay in place.
+	///
+	/// Disabled if `None`, the default.
+	/// Decoy files are not created past the [maximum size](Self::set_max_size).
+	///
+	/// # Panics
+	///
+	/// Panics if decoy files are requested with a maximum size of zero.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// let ref key = paks::Key::default();
+	/// let mut editor = paks::MemoryEditor::new();
+	/// editor.set_obfuscation(Some(paks::ObfuscationOptions { shuffle: true, decoys: 4, decoy_max_size: 1024 }));
+	/// editor.create_file(b"a", b"a", key);
+	/// editor.create_file(b"b", b"b", key);
+	///
+	/// let (_, directory) = editor.finish(key);
+	/// assert_eq!(directory.query(&paks::Filter::Under(paks::Directory::DECOYS).and(paks::Filter::Files)).count(), 4);
+	/// ```
+	#[inline]
+	pub fn set_obfuscation(&mut self, options: Option<ObfuscationOptions>) {
+		if let Some(options) = &options {
+			options.check();
+		}
+		self.policy.obfuscation = options;
+	}
+
+	/// Returns the obfuscation applied when finishing the PAK file.
+	#[inline]
+	pub fn obfuscation(&self) -> Option<ObfuscationOptions> {
+		self.policy.obfuscation
+	}
+
+	// Adds the decoy files and shuffles the file data, see `set_obfuscation`
+	pub(crate) fn obfuscate(&mut self, key: &Key) {
+		// Decoys left behind by earlier edits are replaced
+		self.remove_decoys();
+		let options = match self.policy.obfuscation {
+			Some(options) => options,
+			None => return,
+		};
+		for (path, size) in decoys(&options) {
+			let mut edit_file = self.edit_file(&path);
+			edit_file.set_content(1, size);
+			match edit_file.allocate_data() {
+				Ok(edit_file) => edit_file.write_data(&vec![0; size as usize], key),
+				Err(_) => {
+					Directory::remove(self, &path);
+					break;
+				},
+			};
+			self.mark_decoy(&path);
+		}
+		if options.shuffle {
+			self.gc_with_layout(Layout::Shuffle);
+		}
+	}
+}
+
+impl<S: Storage> FileEditor<S> {
+	/// Obfuscates the file data when finishing the PAK file.
+	///
+	/// Shuffling copies every section past the end of the PAK file before [collecting the garbage](Self::gc), the PAK file temporarily grows to twice its size.
+	/// See [`MemoryEditor::set_obfuscation`] for more information.
+	///
+	/// # Panics
+	///
+	/// Panics if decoy files are requested with a maximum size of zero.
+	#[inline]
+	pub fn set_obfuscation(&mut self, options: Option<ObfuscationOptions>) {
+		if let Some(options) = &options {
+			options.check();
+		}
+		self.policy.obfuscation = options;
+		// The file data is obfuscated by finish
+		self.dirty = true;
+	}
+
+	/// Returns the obfuscation applied when finishing the PAK file.
+	#[inline]
+	pub fn obfuscation(&self) -> Option<ObfuscationOptions> {
+		self.policy.obfuscation
+	}
+
+	// Adds the decoy files and shuffles the file data, see `set_obfuscation`
+	pub(crate) fn obfuscate(&mut self, key: &Key) -> io::Result<()> {
+		// Decoys left behind by earlier edits are replaced
+		self.remove_decoys();
+		let options = match self.policy.obfuscation {
+			Some(options) => options,
+			None => return Ok(()),
+		};
+		for (path, size) in decoys(&options) {
+			let mut edit_file = self.edit_file(&path);
+			edit_file.set_content(1, size);
+			edit_file.allocate_data()?.write_data(&vec![0; size as usize], key)?;
+			self.mark_decoy(&path);
+		}
+		if options.shuffle {
+			self.shuffle_data()?;
+		}
+		Ok(())
+	}
+}
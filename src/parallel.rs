@@ -0,0 +1,203 @@
+use std::{collections::BTreeMap, fmt, fs, io, path::Path, sync::atomic, sync::mpsc, thread};
+use crate::*;
+
+/// File data encrypted ahead of time.
+///
+/// Encrypting does not depend on where the data ends up in the PAK file,
+/// the expensive encryption can happen on any thread before the data is added to the PAK file.
+///
+/// Add the data to a PAK file with [`FileEditor::create_encrypted`] or [`MemoryEditor::create_encrypted`].
+#[derive(Clone, Debug)]
+pub struct Encrypted {
+	pub(crate) blocks: Vec<Block>,
+	pub(crate) section: Section,
+	pub(crate) block_size: u32,
+	pub(crate) content_size: u32,
+}
+
+impl Encrypted {
+	/// Encrypts the data for a PAK file with the given [block size](Directory::block_size).
+	///
+	/// If the data's len is greater than 4 GiB it is truncated as its size is stored in a `u32`.
+	pub fn new(data: &[u8], block_size: u32, key: &Key) -> Encrypted {
+		let content_size = data.len() as u32;
+		let size = bytes2blocks(content_size, block_size);
+		let mut blocks = vec![Block::default(); size as usize * block_scale(block_size)];
+		let len = usize::min(blocks.as_bytes().len(), data.len());
+		blocks.as_bytes_mut()[..len].copy_from_slice(&data[..len]);
+
+		let mut section = Section { size, ..Section::default() };
+		crypt::encrypt_section(&mut blocks, &mut section, key);
+		Encrypted { blocks, section, block_size, content_size }
+	}
+
+	/// Returns the size of the file in bytes.
+	#[inline]
+	pub fn content_size(&self) -> u32 {
+		self.content_size
+	}
+}
+
+// Loads and encrypts the items on a pool of worker threads, applies the results in order on the calling thread
+// Stops at the first error, returns the number of items applied
+fn encrypt_parallel<T, F, A>(items: &[T], threads: usize, block_size: u32, key: &Key, load: F, mut apply: A) -> io::Result<usize>
+	where T: Sync, F: Fn(&T) -> io::Result<(Vec<u8>, Vec<u8>)> + Sync, A: FnMut(&[u8], &Encrypted) -> io::Result<()>
+{
+	let next = atomic::AtomicUsize::new(0);
+	let abort = atomic::AtomicBool::new(false);
+	thread::scope(|scope| {
+		let threads = usize::max(1, threads);
+		let (sender, receiver) = mpsc::sync_channel(threads * 2);
+		for _ in 0..threads {
+			let sender = sender.clone();
+			let (next, abort, load) = (&next, &abort, &load);
+			scope.spawn(move || {
+				while !abort.load(atomic::Ordering::Relaxed) {
+					let index = next.fetch_add(1, atomic::Ordering::Relaxed);
+					let item = match items.get(index) {
+						Some(item) => item,
+						None => break,
+					};
+					let result = load(item).map(|(path, data)| (path, Encrypted::new(&data, block_size, key)));
+					if sender.send((index, result)).is_err() {
+						break;
+					}
+				}
+			});
+		}
+		drop(sender);
+
+		// Results arrive out of order, hold on to them until their turn
+		let mut pending = BTreeMap::new();
+		let mut applied = 0;
+		for (index, result) in receiver {
+			pending.insert(index, result);
+			while let Some(result) = pending.remove(&applied) {
+				let result = result.and_then(|(path, encrypted)| apply(&path, &encrypted));
+				if let Err(err) = result {
+					abort.store(true, atomic::Ordering::Relaxed);
+					return Err(err);
+				}
+				applied += 1;
+			}
+		}
+		Ok(applied)
+	})
+}
+
+impl<S: Storage> FileEditor<S> {
+	/// Creates many files using a pool of worker threads.
+	///
+	/// The load callback is called on the worker threads for every item and returns the path and contents of its file.
+	/// The files are encrypted on the worker threads and created in the order of the items, the result is the same as creating them one after another.
+	///
+	/// Stops at the first error and returns it, the files created before the error are kept.
+	/// Returns the number of files created.
+	pub fn create_files_parallel<T, F>(&mut self, items: &[T], threads: usize, key: &Key, load: F) -> io::Result<usize>
+		where T: Sync, F: Fn(&T) -> io::Result<(Vec<u8>, Vec<u8>)> + Sync
+	{
+		let block_size = self.block_size();
+		encrypt_parallel(items, threads, block_size, key, load, |path, encrypted| {
+			self.create_encrypted(path, encrypted).map(|_| ())
+		})
+	}
+}
+
+impl MemoryEditor {
+	/// Creates many files using a pool of worker threads.
+	///
+	/// See [`FileEditor::create_files_parallel`] for more information.
+	pub fn create_files_parallel<T, F>(&mut self, items: &[T], threads: usize, key: &Key, load: F) -> io::Result<usize>
+		where T: Sync, F: Fn(&T) -> io::Result<(Vec<u8>, Vec<u8>)> + Sync
+	{
+		let block_size = self.block_size();
+		encrypt_parallel(items, threads, block_size, key, load, |path, encrypted| {
+			self.create_encrypted(path, encrypted).map(|_| ()).map_err(io::Error::from)
+		})
+	}
+}
+
+/// A file which could not be extracted, see [`FileReader::extract_all`].
+#[derive(Debug)]
+pub struct ExtractError {
+	/// Path of the file in the PAK file.
+	pub path: Vec<u8>,
+	/// Why the file could not be extracted.
+	pub error: io::Error,
+}
+
+impl fmt::Display for ExtractError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}: {}", String::from_utf8_lossy(&self.path), self.error)
+	}
+}
+
+impl FileReader {
+	/// Extracts every file at or under the given path to the destination directory using a pool of worker threads.
+	///
+	/// Every file is written to its full path in the PAK file relative to the destination directory, existing files are overwritten.
+	/// Extracting `textures` to `out` writes the file `textures/wall.png` to `out/textures/wall.png`.
+	/// Paths which would escape the destination directory are rejected with [`io::ErrorKind::InvalidData`].
+	///
+	/// The workers decrypt and write one file at the time reusing their buffer, memory use is bounded by the largest files being extracted.
+	/// Extraction continues past files which cannot be extracted, their errors are returned in directory order.
+	/// A path which does not exist fails with [`io::ErrorKind::NotFound`].
+	///
+	/// Returns the number of files extracted.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// let ref key = paks::Key::default();
+	/// let reader = paks::FileReader::open("game.pak", key).unwrap();
+	///
+	/// let threads = std::thread::available_parallelism().map_or(1, usize::from);
+	/// match reader.extract_all(b"textures", "out", threads, key) {
+	///     Ok(count) => println!("extracted {} files", count),
+	///     Err(errors) => for err in &errors {
+	///         eprintln!("{}", err);
+	///     },
+	/// }
+	/// ```
+	pub fn extract_all<P: ?Sized + AsRef<Path>>(&self, path: &[u8], dest: &P, threads: usize, key: &Key) -> Result<usize, Vec<ExtractError>> {
+		let files = match self.files_under(path) {
+			Some(files) => files,
+			None => return Err(vec![ExtractError { path: path.to_vec(), error: io::ErrorKind::NotFound.into() }]),
+		};
+		let dest = dest.as_ref();
+		let extract = |path: &[u8], desc: &Descriptor, scratch: &mut Vec<Block>| -> io::Result<()> {
+			let local = sfx::local_path(dest, path)?;
+			let data = self.read_data_with(desc, key, scratch)?;
+			if let Some(parent) = local.parent() {
+				fs::create_dir_all(parent)?;
+			}
+			fs::write(&local, data)
+		};
+
+		let next = atomic::AtomicUsize::new(0);
+		let mut errors = thread::scope(|scope| {
+			let workers: Vec<_> = (0..usize::max(1, threads)).map(|_| scope.spawn(|| {
+				let mut scratch = Vec::new();
+				let mut errors = Vec::new();
+				loop {
+					let index = next.fetch_add(1, atomic::Ordering::Relaxed);
+					let (path, desc) = match files.get(index) {
+						Some(file) => file,
+						None => break,
+					};
+					if let Err(error) = extract(path, desc, &mut scratch) {
+						errors.push((index, ExtractError { path: path.clone(), error }));
+					}
+				}
+				errors
+			})).collect();
+			workers.into_iter().flat_map(|worker| worker.join().unwrap()).collect::<Vec<_>>()
+		});
+
+		if errors.is_empty() {
+			return Ok(files.len());
+		}
+		errors.sort_by_key(|&(index, _)| index);
+		Err(errors.into_iter().map(|(_, err)| err).collect())
+	}
+}
@@ -0,0 +1,475 @@
+use std::{cmp, convert::TryInto, fs, io, io::prelude::*, path::Path};
+use crate::*;
+
+/// Erasure coding parameters of the parity appended to a PAK file, see [`MemoryEditor::set_parity`].
+///
+/// The file data is split into `data_shards` shards of equal size protected by `parity_shards` Reed-Solomon parity shards.
+/// Up to `parity_shards` damaged data shards can be reconstructed by [`repair`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct ParityOptions {
+	/// Number of shards the file data is split into.
+	pub data_shards: u32,
+	/// Number of parity shards, each as large as a data shard.
+	pub parity_shards: u32,
+}
+
+impl Default for ParityOptions {
+	#[inline]
+	fn default() -> ParityOptions {
+		ParityOptions { data_shards: 32, parity_shards: 4 }
+	}
+}
+
+impl ParityOptions {
+	fn check(&self) {
+		assert!(self.data_shards >= 1 && self.parity_shards >= 1, "parity requires at least one data shard and one parity shard");
+		assert!(self.data_shards + self.parity_shards <= 256, "parity supports at most 256 shards");
+	}
+}
+
+impl Directory {
+	/// Path of the hidden file holding the parity of the file data, see [`MemoryEditor::set_parity`].
+	pub const PARITY: &'static [u8] = b".parity";
+}
+
+//----------------------------------------------------------------
+// Arithmetic in GF(2^8) with the polynomial x^8 + x^4 + x^3 + x^2 + 1
+
+struct Gf {
+	exp: [u8; 512],
+	log: [u8; 256],
+}
+
+const fn gf() -> Gf {
+	let mut exp = [0u8; 512];
+	let mut log = [0u8; 256];
+	let mut x = 1u32;
+	let mut i = 0;
+	while i < 255 {
+		exp[i] = x as u8;
+		exp[i + 255] = x as u8;
+		log[x as usize] = i as u8;
+		x <<= 1;
+		if x & 0x100 != 0 {
+			x ^= 0x11d;
+		}
+		i += 1;
+	}
+	Gf { exp, log }
+}
+
+static GF: Gf = gf();
+
+#[inline]
+fn mul(a: u8, b: u8) -> u8 {
+	if a == 0 || b == 0 { 0 } else { GF.exp[GF.log[a as usize] as usize + GF.log[b as usize] as usize] }
+}
+
+#[inline]
+fn inv(a: u8) -> u8 {
+	GF.exp[255 - GF.log[a as usize] as usize]
+}
+
+// Adds the source multiplied by the coefficient to the destination
+fn mul_acc(dest: &mut [u8], c: u8, src: &[u8]) {
+	if c == 0 {
+		return;
+	}
+	let log_c = GF.log[c as usize] as usize;
+	for (dest, &src) in dest.iter_mut().zip(src) {
+		if src != 0 {
+			*dest ^= GF.exp[log_c + GF.log[src as usize] as usize];
+		}
+	}
+}
+
+// Coefficient of the data shard in the parity shard
+// Every square submatrix of the Cauchy matrix is invertible, any damaged data shards can be solved for with as many parity shards
+#[inline]
+fn coef(data_shards: u32, parity: usize, data: usize) -> u8 {
+	inv((data_shards as usize + parity) as u8 ^ data as u8)
+}
+
+// Inverts the square matrix using Gauss-Jordan elimination
+fn invert(matrix: &mut [u8], n: usize) -> Vec<u8> {
+	let mut inverse = vec![0u8; n * n];
+	for i in 0..n {
+		inverse[i * n + i] = 1;
+	}
+	for col in 0..n {
+		let pivot = (col..n).find(|&row| matrix[row * n + col] != 0).expect("singular matrix");
+		for x in 0..n {
+			matrix.swap(pivot * n + x, col * n + x);
+			inverse.swap(pivot * n + x, col * n + x);
+		}
+		let scale = inv(matrix[col * n + col]);
+		for x in 0..n {
+			matrix[col * n + x] = mul(matrix[col * n + x], scale);
+			inverse[col * n + x] = mul(inverse[col * n + x], scale);
+		}
+		for row in (0..n).filter(|&row| row != col) {
+			let factor = matrix[row * n + col];
+			for x in 0..n {
+				let (m, i) = (mul(factor, matrix[col * n + x]), mul(factor, inverse[col * n + x]));
+				matrix[row * n + x] ^= m;
+				inverse[row * n + x] ^= i;
+			}
+		}
+	}
+	inverse
+}
+
+// FNV-1a locates the damaged shards, the parity file itself is authenticated by its MAC
+fn checksum(data: &[u8]) -> u64 {
+	let mut hash = 0xcbf29ce484222325u64;
+	for &byte in data {
+		hash ^= byte as u64;
+		hash = hash.wrapping_mul(0x100000001b3);
+	}
+	hash
+}
+
+//----------------------------------------------------------------
+
+// Size of the fields preceding the checksums in the parity file
+const HEADER_LEN: usize = 32;
+
+// Parity file contents
+//
+// The header holds the byte range of the file data covered, the shard length and the number of data and parity shards.
+// It is followed by the checksum of every data shard and the parity shards.
+struct Parity<'a> {
+	start: u64,
+	end: u64,
+	shard_len: usize,
+	data_shards: u32,
+	parity_shards: u32,
+	checksums: Vec<u64>,
+	parity: &'a [u8],
+}
+
+impl<'a> Parity<'a> {
+	fn parse(data: &'a [u8]) -> io::Result<Parity<'a>> {
+		let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid parity");
+		let u64_at = |i: usize| u64::from_le_bytes(data[i..i + 8].try_into().unwrap());
+		if data.len() < HEADER_LEN {
+			return Err(invalid());
+		}
+		let (start, end, shard_len) = (u64_at(0), u64_at(8), u64_at(16));
+		let data_shards = u32::from_le_bytes(data[24..28].try_into().unwrap());
+		let parity_shards = u32::from_le_bytes(data[28..32].try_into().unwrap());
+		let options = ParityOptions { data_shards, parity_shards };
+		let parity_start = HEADER_LEN + data_shards as usize * 8;
+		if start > end || data_shards == 0 || data_shards as u64 + parity_shards as u64 > 256
+			|| shard_len != shard_len_of(start, end, &options)
+			|| data.len() as u64 != parity_start as u64 + parity_shards as u64 * shard_len
+		{
+			return Err(invalid());
+		}
+		let checksums = (0..data_shards as usize).map(|j| u64_at(HEADER_LEN + j * 8)).collect();
+		let parity = &data[parity_start..];
+		Ok(Parity { start, end, shard_len: shard_len as usize, data_shards, parity_shards, checksums, parity })
+	}
+
+	// Byte range of the data shard, the last shard is zero padded
+	fn shard_range(&self, j: usize) -> (u64, usize) {
+		let offset = self.start + j as u64 * self.shard_len as u64;
+		(offset, cmp::min(self.shard_len as u64, self.end.saturating_sub(offset)) as usize)
+	}
+
+	fn read_shard<R: FnMut(u64, &mut [u8]) -> io::Result<()>>(&self, j: usize, shard: &mut [u8], read: &mut R) -> io::Result<()> {
+		let (offset, len) = self.shard_range(j);
+		shard[len..].fill(0);
+		read(offset, &mut shard[..len])
+	}
+
+	// Reconstructs the damaged data shards, returns their index and contents
+	fn reconstruct<R: FnMut(u64, &mut [u8]) -> io::Result<()>>(&self, mut read: R) -> io::Result<Vec<(usize, Vec<u8>)>> {
+		let len = self.shard_len;
+
+		// Subtract the intact data shards from the parity leaving the contribution of the damaged shards
+		let mut syndromes = self.parity.to_vec();
+		let mut damaged = Vec::new();
+		let mut shard = vec![0u8; len];
+		for j in 0..self.data_shards as usize {
+			self.read_shard(j, &mut shard, &mut read)?;
+			if checksum(&shard) != self.checksums[j] {
+				damaged.push(j);
+				continue;
+			}
+			for (i, syndrome) in syndromes.chunks_mut(len).enumerate() {
+				mul_acc(syndrome, coef(self.data_shards, i, j), &shard);
+			}
+		}
+		if damaged.is_empty() {
+			return Ok(Vec::new());
+		}
+		let n = damaged.len();
+		if n > self.parity_shards as usize {
+			let msg = format!("{} damaged shards exceed the {} parity shards", n, self.parity_shards);
+			return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+		}
+
+		// Solve for the damaged shards with the first parity shards
+		let mut matrix: Vec<u8> = (0..n).flat_map(|i| damaged.iter().map(move |&j| (i, j))).map(|(i, j)| coef(self.data_shards, i, j)).collect();
+		let inverse = invert(&mut matrix, n);
+		let mut repaired = Vec::with_capacity(n);
+		for (a, &j) in damaged.iter().enumerate() {
+			let mut data = vec![0u8; len];
+			for (b, syndrome) in syndromes.chunks(len).take(n).enumerate() {
+				mul_acc(&mut data, inverse[a * n + b], syndrome);
+			}
+			if checksum(&data) != self.checksums[j] {
+				return Err(io::Error::new(io::ErrorKind::InvalidData, "parity does not match the file data"));
+			}
+			repaired.push((j, data));
+		}
+		Ok(repaired)
+	}
+}
+
+fn shard_len_of(start: u64, end: u64, options: &ParityOptions) -> u64 {
+	u64::max(1, (end - start).div_ceil(options.data_shards as u64))
+}
+
+// Computes the parity file contents of the file data in the byte range
+// The reader is called with the byte offset and the buffer to fill
+pub(crate) fn encode<R: FnMut(u64, &mut [u8]) -> io::Result<()>>(options: &ParityOptions, start: u64, end: u64, mut read: R) -> io::Result<Vec<u8>> {
+	let shard_len = shard_len_of(start, end, options);
+	let size = HEADER_LEN as u64 + options.data_shards as u64 * 8 + options.parity_shards as u64 * shard_len;
+	if size > u32::MAX as u64 {
+		return Err(io::Error::new(io::ErrorKind::OutOfMemory, "parity exceeds the maximum file size"));
+	}
+
+	let mut data = Vec::with_capacity(size as usize);
+	data.extend_from_slice(&start.to_le_bytes());
+	data.extend_from_slice(&end.to_le_bytes());
+	data.extend_from_slice(&shard_len.to_le_bytes());
+	data.extend_from_slice(&options.data_shards.to_le_bytes());
+	data.extend_from_slice(&options.parity_shards.to_le_bytes());
+	data.resize(size as usize, 0);
+
+	let (header, parity) = data.split_at_mut(HEADER_LEN + options.data_shards as usize * 8);
+	let geometry = Parity { start, end, shard_len: shard_len as usize, data_shards: options.data_shards, parity_shards: options.parity_shards, checksums: Vec::new(), parity: &[] };
+	let mut shard = vec![0u8; shard_len as usize];
+	for j in 0..options.data_shards as usize {
+		geometry.read_shard(j, &mut shard, &mut read)?;
+		header[HEADER_LEN + j * 8..HEADER_LEN + j * 8 + 8].copy_from_slice(&checksum(&shard).to_le_bytes());
+		for (i, parity) in parity.chunks_mut(shard_len as usize).enumerate() {
+			mul_acc(parity, coef(options.data_shards, i, j), &shard);
+		}
+	}
+	Ok(data)
+}
+
+// Reads from the storage of the given size in blocks, zero filling past its end
+pub(crate) fn read_storage_padded<S: Storage>(storage: &S, size: u64, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+	let len = cmp::min(buf.len() as u64, (size * BLOCK_SIZE as u64).saturating_sub(offset)) as usize;
+	buf[len..].fill(0);
+	if len > 0 {
+		let first = offset / BLOCK_SIZE as u64;
+		let last = (offset + len as u64).div_ceil(BLOCK_SIZE as u64);
+		let mut blocks = vec![Block::default(); (last - first) as usize];
+		storage.read_blocks(first, &mut blocks)?;
+		let start = (offset % BLOCK_SIZE as u64) as usize;
+		buf[..len].copy_from_slice(&blocks.as_bytes()[start..start + len]);
+	}
+	Ok(())
+}
+
+// Reads from the file, zero filling past its end
+pub(crate) fn read_padded(file: &fs::File, file_len: u64, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+	let len = cmp::min(buf.len() as u64, file_len.saturating_sub(offset)) as usize;
+	buf[len..].fill(0);
+	if len > 0 {
+		file_io::read_exact_at(file, offset, &mut buf[..len])?;
+	}
+	Ok(())
+}
+
+/// Repairs the damaged file data of a PAK file using its parity.
+///
+/// The parity is appended by [`finish`](FileEditor::finish) when enabled with [`set_parity`](FileEditor::set_parity).
+/// Every damaged data shard is reconstructed and overwritten in place, the intact shards are left alone.
+/// The header and the directory are not protected by the parity, the PAK file must still open with the key.
+///
+/// Returns the number of repaired shards.
+///
+/// # Errors
+///
+/// * [`io::ErrorKind::NotFound`]: The PAK file has no parity.
+/// * [`io::ErrorKind::InvalidData`]: The parity is damaged, more shards are damaged than there are parity shards or the file data was changed after the parity was computed.
+/// * [`io::Error`]: An error encountered opening, reading or writing the PAK file.
+///
+/// # Examples
+///
+/// ```no_run
+/// let ref key = paks::Key::default();
+///
+/// let mut editor = paks::FileEditor::create_new("game.pak", key).unwrap();
+/// editor.set_parity(Some(paks::ParityOptions::default()));
+/// editor.create_file(b"music.ogg", &[0; 100000], key).unwrap();
+/// editor.finish(key).unwrap();
+///
+/// // After the PAK file was damaged by unreliable media
+/// let repaired = paks::repair("game.pak", key).unwrap();
+/// println!("repaired {} shards", repaired);
+/// ```
+pub fn repair<P: ?Sized + AsRef<Path>>(path: &P, key: &Key) -> io::Result<usize> {
+	let reader = FileReader::open(path, key)?;
+	let desc = match reader.find_file(Directory::PARITY) {
+		Some(desc) => desc,
+		None => return Err(io::Error::new(io::ErrorKind::NotFound, "PAK file has no parity")),
+	};
+	let data = reader.read_data(desc, key)?;
+	let parity = Parity::parse(&data)?;
+
+	let base = reader.offset();
+	let mut file = fs::OpenOptions::new().read(true).write(true).open(path)?;
+	let file_len = file.metadata()?.len();
+	let repaired = parity.reconstruct(|offset, buf| read_padded(&file, file_len, base + offset, buf))?;
+
+	for (j, data) in &repaired {
+		let (offset, len) = parity.shard_range(*j);
+		file.seek(io::SeekFrom::Start(base + offset))?;
+		file.write_all(&data[..len])?;
+	}
+	if !repaired.is_empty() {
+		file.sync_all()?;
+	}
+	Ok(repaired.len())
+}
+
+impl MemoryEditor {
+	/// Appends Reed-Solomon parity of the file data when finishing the PAK file.
+	///
+	/// The parity is stored in the hidden file [`Directory::PARITY`] and allows [`repair`] to reconstruct damaged file data without a second copy.
+	/// It is computed over the encrypted file data by [`finish`](Self::finish), any existing parity is removed as it no longer matches after editing.
+	/// The parity is omitted if it exceeds the [maximum size](Self::set_max_size).
+	///
+	/// # Panics
+	///
+	/// Panics if there are no data or parity shards, or more than 256 shards in total.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// let ref key = paks::Key::default();
+	/// let mut editor = paks::MemoryEditor::new();
+	/// editor.set_parity(Some(paks::ParityOptions { data_shards: 8, parity_shards: 2 }));
+	/// editor.create_file(b"music.ogg", &[42; 1000], key);
+	///
+	/// let (_, directory) = editor.finish(key);
+	/// assert!(directory.find_file(paks::Directory::PARITY).is_some());
+	/// ```
+	#[inline]
+	pub fn set_parity(&mut self, options: Option<ParityOptions>) {
+		if let Some(options) = &options {
+			options.check();
+		}
+		self.policy.parity = options;
+	}
+
+	/// Returns the parity appended when finishing the PAK file.
+	#[inline]
+	pub fn parity(&self) -> Option<ParityOptions> {
+		self.policy.parity
+	}
+}
+
+impl<S: Storage> FileEditor<S> {
+	/// Appends Reed-Solomon parity of the file data when finishing the PAK file.
+	///
+	/// See [`MemoryEditor::set_parity`] for more information.
+	/// Finishing fails with [`io::ErrorKind::OutOfMemory`] if the parity exceeds the [maximum size](Self::set_max_size).
+	///
+	/// # Panics
+	///
+	/// Panics if there are no data or parity shards, or more than 256 shards in total.
+	#[inline]
+	pub fn set_parity(&mut self, options: Option<ParityOptions>) {
+		if let Some(options) = &options {
+			options.check();
+		}
+		self.policy.parity = options;
+		// The parity is rewritten by finish
+		self.dirty = true;
+	}
+
+	/// Returns the parity appended when finishing the PAK file.
+	#[inline]
+	pub fn parity(&self) -> Option<ParityOptions> {
+		self.policy.parity
+	}
+}
+
+#[test]
+fn test_invert() {
+	for n in 1..=8 {
+		let matrix: Vec<u8> = (0..n).flat_map(|i| (0..n).map(move |j| coef(200, i, j * 3))).collect();
+		let inverse = invert(&mut matrix.clone(), n);
+		for i in 0..n {
+			for j in 0..n {
+				let dot = (0..n).fold(0, |acc, x| acc ^ mul(matrix[i * n + x], inverse[x * n + j]));
+				assert_eq!(dot, (i == j) as u8);
+			}
+		}
+	}
+}
+
+#[test]
+fn test_repair() {
+	let ref key = Key::default();
+	let file_name = "test_repair.pak";
+	let _ = fs::remove_file(file_name);
+
+	let data: Vec<u8> = (0..20000u32).map(|i| (i * 7 + i / 256) as u8).collect();
+	let mut editor = FileEditor::create_new(file_name, key).unwrap();
+	editor.set_parity(Some(ParityOptions { data_shards: 16, parity_shards: 2 }));
+	let desc = *editor.create_file(b"data", &data, key).unwrap();
+	editor.create_file(b"small", b"small", key).unwrap();
+	editor.finish(key).unwrap();
+	let original = fs::read(file_name).unwrap();
+
+	// Nothing to repair
+	assert_eq!(repair(file_name, key).unwrap(), 0);
+
+	// Damage two shards
+	let mut damaged = original.clone();
+	let offset = desc.section.offset as usize * BLOCK_SIZE;
+	damaged[offset + 10] ^= 0xff;
+	damaged[offset + 15000] ^= 0x01;
+	fs::write(file_name, &damaged).unwrap();
+	assert_eq!(repair(file_name, key).unwrap(), 2);
+	assert_eq!(fs::read(file_name).unwrap(), original);
+	let reader = FileReader::open(file_name, key).unwrap();
+	assert_eq!(reader.read_data(&desc, key).unwrap(), data);
+
+	// Too much damage is not repaired
+	damaged[offset + 5000] ^= 0x01;
+	fs::write(file_name, &damaged).unwrap();
+	assert_eq!(repair(file_name, key).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+	// Editing again without parity removes the stale parity
+	let mut editor = FileEditor::open(file_name, key).unwrap();
+	editor.create_file(b"more", &data, key).unwrap();
+	editor.finish(key).unwrap();
+	assert!(FileReader::open(file_name, key).unwrap().find_file(Directory::PARITY).is_none());
+
+	let _ = fs::remove_file(file_name);
+}
+
+#[test]
+fn test_parity_memory() {
+	let ref key = Key::default();
+	let mut editor = MemoryEditor::new();
+	editor.set_parity(Some(ParityOptions::default()));
+	editor.create_file(b"data", &[42; 3000], key).unwrap();
+	let (blocks, directory) = editor.finish(key);
+	let desc = directory.find_file(Directory::PARITY).unwrap();
+	let reader = MemoryReader::from_blocks(blocks, key).unwrap();
+	let data = reader.read_data(desc, key).unwrap();
+	let parity = Parity::parse(&data).unwrap();
+	assert_eq!(parity.start, Header::BLOCKS_LEN as u64 * BLOCK_SIZE as u64);
+	assert_eq!(parity.end, desc.section.offset as u64 * BLOCK_SIZE as u64);
+}
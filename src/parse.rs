@@ -0,0 +1,126 @@
+/*!
+Bounds-checked parsing of untrusted PAK file data.
+
+Every offset and length read from a PAK file is validated before it is used to index into or allocate memory.
+*/
+
+use std::{io, ops};
+use crate::*;
+
+/// Limits on the size of the PAK file checked before allocating memory for it.
+///
+/// The header claims the size of the directory, a corrupted or hostile header can claim several GiB.
+/// The limits are checked right after the header is authenticated, before the directory is read and its MAC is checked.
+/// Used by [`read_with_limits`], [`MemoryReader::from_bytes_with_limits`] and [`ReaderOptions::limits`].
+///
+/// Exceeding a limit fails with [`io::ErrorKind::OutOfMemory`]. Unlimited by default.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct Limits {
+	/// Maximum number of descriptors in the directory.
+	pub max_directory_entries: Option<u32>,
+	/// Maximum size of the PAK file in 16 byte blocks, up to and including the directory.
+	pub max_blocks: Option<u64>,
+}
+
+impl Limits {
+	/// Checks the sizes claimed by the header against the limits.
+	pub(crate) fn check(&self, info: &InfoHeader) -> Result<(), io::ErrorKind> {
+		if matches!(self.max_directory_entries, Some(max) if info.directory.size > max) {
+			return Err(io::ErrorKind::OutOfMemory);
+		}
+		if let Some(max) = self.max_blocks {
+			let dir_range = directory_range(info).ok_or(io::ErrorKind::InvalidData)?;
+			if dir_range.end as u64 > max {
+				return Err(io::ErrorKind::OutOfMemory);
+			}
+		}
+		Ok(())
+	}
+}
+
+/// Decrypts and authenticates the header at the start of the blocks.
+///
+/// Returns `None` if there are not enough blocks or the MAC check fails.
+pub(crate) fn header(blocks: &[Block], key: &Key) -> Option<Header> {
+	let blocks = blocks.get(..Header::BLOCKS_LEN)?;
+
+	let mut header = Header::default();
+	let header_blocks: &mut [Block; Header::BLOCKS_LEN] = header.as_mut();
+	header_blocks.copy_from_slice(blocks);
+
+	if !crypt::decrypt_header(&mut header, key) {
+		return None;
+	}
+	Some(header)
+}
+
+/// Calculates the range of blocks containing the directory.
+///
+/// The range is measured in `Block`s, scaled by the block size of the PAK file.
+/// Returns `None` if the range overflows or a non-empty directory overlaps the header.
+pub(crate) fn directory_range(info: &InfoHeader) -> Option<ops::Range<usize>> {
+	let start = (info.directory.offset as usize).checked_mul(block_scale(info.block_size()))?;
+	let len = (info.directory.size as usize).checked_mul(Descriptor::BLOCKS_LEN)?;
+	let end = start.checked_add(len)?;
+	if len != 0 && start < Header::BLOCKS_LEN {
+		return None;
+	}
+	Some(start..end)
+}
+
+/// Copies the descriptors out of the blocks.
+///
+/// Trailing blocks which do not make up a whole descriptor are ignored.
+pub(crate) fn descriptors(blocks: &[Block]) -> Vec<Descriptor> {
+	blocks.chunks_exact(Descriptor::BLOCKS_LEN).map(|chunk| {
+		let mut desc = Descriptor::default();
+		let desc_blocks: &mut [Block; Descriptor::BLOCKS_LEN] = desc.as_mut();
+		desc_blocks.copy_from_slice(chunk);
+		desc
+	}).collect()
+}
+
+#[test]
+fn test_directory_range() {
+	let mut info = InfoHeader { block_size: 16, directory: Section { offset: 6, size: 2, ..Section::default() }, ..InfoHeader::default() };
+	assert_eq!(directory_range(&info), Some(6..6 + 2 * Descriptor::BLOCKS_LEN));
+
+	info.block_size = 64;
+	assert_eq!(directory_range(&info), Some(24..24 + 2 * Descriptor::BLOCKS_LEN));
+
+	// The directory cannot overlap the header unless it is empty
+	info.block_size = 16;
+	info.directory.offset = 3;
+	assert_eq!(directory_range(&info), None);
+	info.directory.size = 0;
+	assert_eq!(directory_range(&info), Some(3..3));
+
+	info.directory.offset = !0;
+	info.directory.size = !0;
+	if cfg!(target_pointer_width = "32") {
+		assert_eq!(directory_range(&info), None);
+	}
+}
+
+#[test]
+fn test_limits() {
+	let info = InfoHeader { block_size: 16, directory: Section { offset: 100, size: 10, ..Section::default() }, ..InfoHeader::default() };
+	let dir_end = 100 + 10 * Descriptor::BLOCKS_LEN as u64;
+
+	assert_eq!(Limits::default().check(&info), Ok(()));
+	assert_eq!(Limits { max_directory_entries: Some(10), max_blocks: Some(dir_end) }.check(&info), Ok(()));
+	assert_eq!(Limits { max_directory_entries: Some(9), ..Limits::default() }.check(&info), Err(io::ErrorKind::OutOfMemory));
+	assert_eq!(Limits { max_blocks: Some(dir_end - 1), ..Limits::default() }.check(&info), Err(io::ErrorKind::OutOfMemory));
+}
+
+#[test]
+fn test_descriptors() {
+	let dir = [Descriptor::dir(b"a", 1), Descriptor::file(b"b")];
+	let mut blocks = Vec::new();
+	for desc in &dir {
+		let desc_blocks: &[Block; Descriptor::BLOCKS_LEN] = desc.as_ref();
+		blocks.extend_from_slice(desc_blocks);
+	}
+	blocks.push([1, 2]);
+	assert_eq!(descriptors(&blocks), dir);
+}
@@ -0,0 +1,363 @@
+use std::{collections::HashSet, convert::TryFrom, fmt, io, sync::atomic::Ordering};
+use crate::*;
+
+/// Validates a file before it is created, see [`MemoryEditor::set_insert_validator`].
+///
+/// Called with the path and the contents of the file, returns a message describing why the file is rejected.
+pub type InsertValidator = fn(path: &[u8], data: &[u8]) -> Result<(), String>;
+
+/// A file rejected by the insert validator.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Violation {
+	/// Path of the rejected file.
+	pub path: Vec<u8>,
+	/// Message returned by the insert validator.
+	pub message: String,
+}
+
+impl fmt::Display for Violation {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}: {}", String::from_utf8_lossy(&self.path), self.message)
+	}
+}
+
+/// A subtree exceeding its size budget, see [`MemoryEditor::set_budget`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BudgetExceeded {
+	/// Path of the subtree.
+	pub path: Vec<u8>,
+	/// Size budget in bytes.
+	pub budget: u64,
+	/// Bytes used by the subtree.
+	pub used: u64,
+}
+
+impl fmt::Display for BudgetExceeded {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let path = if self.path.is_empty() { String::from(".") } else { String::from_utf8_lossy(&self.path).into_owned() };
+		write!(f, "{}: {} bytes exceeds the budget of {} bytes by {} bytes", path, self.used, self.budget, self.used - self.budget)
+	}
+}
+
+// Pack time policies of an editor
+#[derive(Clone, Default)]
+pub(crate) struct Policy {
+	validator: Option<InsertValidator>,
+	violations: Vec<Violation>,
+	budgets: Vec<(Vec<u8>, u64)>,
+	max_size: Option<u64>,
+	auto_gc: Option<f64>,
+	pub(crate) obfuscation: Option<ObfuscationOptions>,
+	#[cfg(feature = "parity")]
+	pub(crate) parity: Option<ParityOptions>,
+}
+
+impl Policy {
+	// Validates the file and records the violation if it is rejected
+	pub(crate) fn validate(&mut self, path: &[u8], data: &[u8]) -> Result<(), String> {
+		let validator = match self.validator {
+			Some(validator) => validator,
+			None => return Ok(()),
+		};
+		let result = validator(path, data);
+		if let Err(message) = &result {
+			self.violations.push(Violation { path: path.to_vec(), message: message.clone() });
+		}
+		result
+	}
+
+	fn set_budget(&mut self, path: &[u8], max_bytes: u64) {
+		match self.budgets.iter_mut().find(|(budget_path, _)| budget_path == path) {
+			Some(budget) => budget.1 = max_bytes,
+			None => self.budgets.push((path.to_vec(), max_bytes)),
+		}
+	}
+
+	// Highest block index the file data may be allocated up to
+	pub(crate) fn max_mark(&self, block_size: u32) -> u32 {
+		match self.max_size {
+			Some(max_size) => u32::try_from(max_size / block_size as u64).unwrap_or(u32::MAX),
+			None => u32::MAX,
+		}
+	}
+
+	fn set_auto_gc_threshold(&mut self, fraction: Option<f64>) {
+		if let Some(fraction) = fraction {
+			assert!((0.0..=1.0).contains(&fraction), "auto gc threshold must be between 0 and 1: {}", fraction);
+		}
+		self.auto_gc = fraction;
+	}
+
+	// Whether finishing collects the garbage first
+	fn wants_gc(&self, garbage: u32, high_mark: u32) -> bool {
+		match self.auto_gc {
+			Some(fraction) => garbage != 0 && garbage as f64 > fraction * high_mark as f64,
+			None => false,
+		}
+	}
+
+	fn check_budgets(&self, directory: &Directory) -> Result<(), Vec<BudgetExceeded>> {
+		let block_size = directory.block_size() as u64;
+		let mut exceeded = Vec::new();
+		for (path, budget) in &self.budgets {
+			// Links and packed files share their section, count every section once
+			let mut sections = HashSet::new();
+			let used = directory.files_under(path).unwrap_or_default().iter()
+				.filter(|(_, desc)| sections.insert((desc.section.offset, desc.section.size)))
+				.map(|(_, desc)| desc.section.size as u64 * block_size)
+				.sum();
+			if used > *budget {
+				exceeded.push(BudgetExceeded { path: path.clone(), budget: *budget, used });
+			}
+		}
+		if exceeded.is_empty() { Ok(()) } else { Err(exceeded) }
+	}
+}
+
+impl MemoryEditor {
+	/// Sets the validator called by [`create_file`](Self::create_file) before creating a file.
+	///
+	/// Pipelines can enforce naming conventions, size budgets or forbidden formats at pack time.
+	/// Rejected files are not created and recorded as a [violation](Self::violations), the previous file at the path is left alone.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// let ref key = paks::Key::default();
+	/// let mut editor = paks::MemoryEditor::new();
+	/// editor.set_insert_validator(Some(|path, data| {
+	///     if !path.is_ascii() || path.iter().any(u8::is_ascii_uppercase) {
+	///         return Err(String::from("paths must be lowercase ascii"));
+	///     }
+	///     if data.len() > 1000 {
+	///         return Err(format!("{} bytes exceeds the 1000 byte limit", data.len()));
+	///     }
+	///     Ok(())
+	/// }));
+	///
+	/// assert!(editor.create_file(b"Icon.png", b"icon", key).is_none());
+	/// assert!(editor.create_file(b"big.bin", &[0; 2000], key).is_none());
+	/// assert!(editor.create_file(b"icon.png", b"icon", key).is_some());
+	///
+	/// let report: Vec<String> = editor.violations().iter().map(|violation| violation.to_string()).collect();
+	/// assert_eq!(report, ["Icon.png: paths must be lowercase ascii", "big.bin: 2000 bytes exceeds the 1000 byte limit"]);
+	/// ```
+	#[inline]
+	pub fn set_insert_validator(&mut self, validator: Option<InsertValidator>) {
+		self.policy.validator = validator;
+	}
+
+	/// Returns the files rejected by the insert validator, in the order they were rejected.
+	#[inline]
+	pub fn violations(&self) -> &[Violation] {
+		&self.policy.violations
+	}
+
+	/// Returns and clears the files rejected by the insert validator.
+	#[inline]
+	pub fn take_violations(&mut self) -> Vec<Violation> {
+		std::mem::take(&mut self.policy.violations)
+	}
+
+	/// Sets the size budget of the subtree at the given path.
+	///
+	/// The budget limits the bytes stored by the files at or under the path, an empty path budgets the whole PAK file.
+	/// The stored size of a file is its section rounded up to whole blocks, sections shared by links or packed files are counted once.
+	/// Setting the budget of a path again replaces its budget.
+	///
+	/// Budgets are not enforced while editing, check them with [`check_budgets`](Self::check_budgets) before finishing.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// let ref key = paks::Key::default();
+	/// let mut editor = paks::MemoryEditor::new();
+	/// editor.set_budget(b"textures", 64);
+	/// editor.set_budget(b"sounds", 64);
+	///
+	/// editor.create_file(b"textures/wall", &[0; 50], key);
+	/// editor.create_file(b"textures/floor", &[0; 30], key);
+	/// editor.create_file(b"sounds/hit", &[0; 30], key);
+	///
+	/// let report = editor.check_budgets().unwrap_err();
+	/// assert_eq!(report.len(), 1);
+	/// assert_eq!(report[0].to_string(), "textures: 96 bytes exceeds the budget of 64 bytes by 32 bytes");
+	/// ```
+	#[inline]
+	pub fn set_budget(&mut self, path: &[u8], max_bytes: u64) {
+		self.policy.set_budget(path, max_bytes);
+	}
+
+	/// Checks the size budgets.
+	///
+	/// Returns every subtree exceeding its budget in the order the budgets were set.
+	pub fn check_budgets(&self) -> Result<(), Vec<BudgetExceeded>> {
+		self.policy.check_budgets(self)
+	}
+
+	/// Sets the maximum size in bytes of the file data in the PAK file.
+	///
+	/// Allocating file data or metadata past the maximum size fails with [`ErrorKind::OutOfMemory`](std::io::ErrorKind::OutOfMemory) and allocates nothing.
+	/// The header is counted, the directory written by [`finish`](Self::finish) is not.
+	/// Unlike [budgets](Self::set_budget) the maximum size is enforced while editing.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// let ref key = paks::Key::default();
+	/// let mut editor = paks::MemoryEditor::new();
+	/// editor.set_max_size(Some(1024));
+	///
+	/// assert!(editor.create_file(b"small", &[0; 500], key).is_some());
+	/// assert!(editor.create_file(b"big", &[0; 1000], key).is_none());
+	/// assert!(editor.find(b"big").is_none());
+	/// ```
+	#[inline]
+	pub fn set_max_size(&mut self, max_size: Option<u64>) {
+		self.policy.max_size = max_size;
+	}
+
+	/// Returns the maximum size in bytes of the file data in the PAK file.
+	#[inline]
+	pub fn max_size(&self) -> Option<u64> {
+		self.policy.max_size
+	}
+
+	/// Collects garbage automatically when finishing if it exceeds the fraction of the PAK file.
+	///
+	/// The fraction is the number of [garbage](Directory::garbage) blocks over the high mark, eg. `0.25` collects garbage when more than a quarter of the PAK file is unreferenced.
+	/// Disabled if `None`, the default.
+	///
+	/// Automatic garbage collection is counted by [`metrics::auto_gc_runs`] and [`metrics::auto_gc_bytes`] and traced as the `auto_gc` span.
+	///
+	/// # Panics
+	///
+	/// Panics if the fraction is not between 0 and 1.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// let ref key = paks::Key::default();
+	/// let mut editor = paks::MemoryEditor::new();
+	/// editor.set_auto_gc_threshold(Some(0.25));
+	/// editor.create_file(b"old", &[0; 1000], key);
+	/// editor.create_file(b"new", &[0; 1000], key);
+	/// editor.remove(b"old");
+	///
+	/// let (_, directory) = editor.finish(key);
+	/// assert_eq!(directory.find_file(b"new").unwrap().section.offset, 5);
+	/// ```
+	#[inline]
+	pub fn set_auto_gc_threshold(&mut self, fraction: Option<f64>) {
+		self.policy.set_auto_gc_threshold(fraction);
+	}
+
+	/// Returns the garbage fraction above which finishing collects garbage automatically.
+	#[inline]
+	pub fn auto_gc_threshold(&self) -> Option<f64> {
+		self.policy.auto_gc
+	}
+
+	// Collects garbage if it exceeds the automatic garbage collection threshold
+	pub(crate) fn auto_gc(&mut self) {
+		let high_mark = self.high_mark();
+		let garbage = self.garbage(high_mark);
+		if !self.policy.wants_gc(garbage, high_mark) {
+			return;
+		}
+		let span = span!(DEBUG, "auto_gc", garbage, high_mark);
+		self.gc();
+		let bytes = high_mark.saturating_sub(self.high_mark()) as u64 * self.block_size() as u64;
+		metrics::AUTO_GC_RUNS.fetch_add(1, Ordering::Relaxed);
+		metrics::AUTO_GC_BYTES.fetch_add(bytes, Ordering::Relaxed);
+		span.finish(bytes);
+	}
+}
+
+impl<S: Storage> FileEditor<S> {
+	/// Sets the validator called by [`create_file`](Self::create_file) before creating a file.
+	///
+	/// Rejected files fail with [`io::ErrorKind::InvalidInput`](std::io::ErrorKind::InvalidInput) and are recorded as a [violation](Self::violations).
+	/// See [`MemoryEditor::set_insert_validator`] for more information.
+	#[inline]
+	pub fn set_insert_validator(&mut self, validator: Option<InsertValidator>) {
+		self.policy.validator = validator;
+	}
+
+	/// Returns the files rejected by the insert validator, in the order they were rejected.
+	#[inline]
+	pub fn violations(&self) -> &[Violation] {
+		&self.policy.violations
+	}
+
+	/// Returns and clears the files rejected by the insert validator.
+	#[inline]
+	pub fn take_violations(&mut self) -> Vec<Violation> {
+		std::mem::take(&mut self.policy.violations)
+	}
+
+	/// Sets the size budget of the subtree at the given path.
+	///
+	/// See [`MemoryEditor::set_budget`] for more information.
+	#[inline]
+	pub fn set_budget(&mut self, path: &[u8], max_bytes: u64) {
+		self.policy.set_budget(path, max_bytes);
+	}
+
+	/// Checks the size budgets.
+	///
+	/// Returns every subtree exceeding its budget in the order the budgets were set.
+	pub fn check_budgets(&self) -> Result<(), Vec<BudgetExceeded>> {
+		self.policy.check_budgets(self)
+	}
+
+	/// Sets the maximum size in bytes of the file data in the PAK file.
+	///
+	/// Allocating file data or metadata past the maximum size fails with [`io::ErrorKind::OutOfMemory`](std::io::ErrorKind::OutOfMemory).
+	/// See [`MemoryEditor::set_max_size`] for more information.
+	#[inline]
+	pub fn set_max_size(&mut self, max_size: Option<u64>) {
+		self.policy.max_size = max_size;
+	}
+
+	/// Returns the maximum size in bytes of the file data in the PAK file.
+	#[inline]
+	pub fn max_size(&self) -> Option<u64> {
+		self.policy.max_size
+	}
+
+	/// Collects garbage automatically when finishing if it exceeds the fraction of the PAK file.
+	///
+	/// See [`MemoryEditor::set_auto_gc_threshold`] for more information and [`gc`](Self::gc) for its consistency guarantees.
+	/// Finishing an editor without changes does not collect garbage.
+	///
+	/// # Panics
+	///
+	/// Panics if the fraction is not between 0 and 1.
+	#[inline]
+	pub fn set_auto_gc_threshold(&mut self, fraction: Option<f64>) {
+		self.policy.set_auto_gc_threshold(fraction);
+	}
+
+	/// Returns the garbage fraction above which finishing collects garbage automatically.
+	#[inline]
+	pub fn auto_gc_threshold(&self) -> Option<f64> {
+		self.policy.auto_gc
+	}
+
+	// Collects garbage if it exceeds the automatic garbage collection threshold
+	pub(crate) fn auto_gc(&mut self) -> io::Result<()> {
+		let high_mark = self.high_mark();
+		let garbage = self.garbage(high_mark);
+		if !self.policy.wants_gc(garbage, high_mark) {
+			return Ok(());
+		}
+		let span = span!(DEBUG, "auto_gc", garbage, high_mark);
+		self.gc()?;
+		let bytes = high_mark.saturating_sub(self.high_mark()) as u64 * self.block_size() as u64;
+		metrics::AUTO_GC_RUNS.fetch_add(1, Ordering::Relaxed);
+		metrics::AUTO_GC_BYTES.fetch_add(bytes, Ordering::Relaxed);
+		span.finish(bytes);
+		Ok(())
+	}
+}
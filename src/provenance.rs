@@ -0,0 +1,158 @@
+use std::io;
+use crate::*;
+
+impl Directory {
+	/// Path of the hidden file whose [metadata](MetaMap) describes the PAK file itself, see [`FileEditor::set_provenance`].
+	pub const ARCHIVE_META: &'static [u8] = b".pak/meta";
+}
+
+/// Provenance of a build of the PAK file, see [`FileEditor::set_provenance`].
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct BuildInfo {
+	/// Name of the tool which built the PAK file.
+	pub tool: String,
+	/// Version of the tool.
+	pub version: String,
+	/// Commit of the sources the PAK file was built from.
+	pub commit: String,
+	/// Time of the build in seconds since the Unix epoch.
+	pub timestamp: u64,
+}
+
+impl BuildInfo {
+	/// Metadata key of the tool name.
+	pub const TOOL: &'static str = "paks.build.tool";
+	/// Metadata key of the tool version.
+	pub const VERSION: &'static str = "paks.build.version";
+	/// Metadata key of the commit.
+	pub const COMMIT: &'static str = "paks.build.commit";
+	/// Metadata key of the timestamp.
+	pub const TIMESTAMP: &'static str = "paks.build.timestamp";
+
+	/// Stores the build info in the metadata, replacing any previous build info.
+	pub fn write_meta(&self, meta: &mut MetaMap) {
+		meta.set_str(BuildInfo::TOOL, &self.tool);
+		meta.set_str(BuildInfo::VERSION, &self.version);
+		meta.set_str(BuildInfo::COMMIT, &self.commit);
+		meta.set_u64(BuildInfo::TIMESTAMP, self.timestamp);
+	}
+
+	/// Reads the build info from the metadata.
+	///
+	/// Returns `None` if the metadata has no build info, missing fields are left empty.
+	pub fn read_meta(meta: &MetaMap) -> Option<BuildInfo> {
+		if !meta.contains_key(BuildInfo::TOOL) {
+			return None;
+		}
+		let field = |key| meta.get_str(key).unwrap_or_default().to_string();
+		Some(BuildInfo {
+			tool: field(BuildInfo::TOOL),
+			version: field(BuildInfo::VERSION),
+			commit: field(BuildInfo::COMMIT),
+			timestamp: meta.get_u64(BuildInfo::TIMESTAMP).unwrap_or(0),
+		})
+	}
+}
+
+impl<S: Storage> FileEditor<S> {
+	/// Stamps the PAK file with the provenance of its build.
+	///
+	/// The build info is stored in the metadata of the hidden file [`Directory::ARCHIVE_META`], other archive metadata is kept.
+	/// Stamp every build with the tool, version and commit that produced it to audit shipped PAK files for reproducibility.
+	///
+	/// # Errors
+	///
+	/// * [`io::ErrorKind::InvalidData`]: The existing archive metadata is corrupted.
+	/// * [`io::Error`]: An error encountered writing the metadata.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// let ref key = paks::Key::default();
+	/// let mut editor = paks::FileEditor::open("game.pak", key).unwrap();
+	/// let info = paks::BuildInfo {
+	///     tool: String::from("assetc"),
+	///     version: String::from("1.4.2"),
+	///     commit: String::from("9f0c2e1"),
+	///     timestamp: 1700000000,
+	/// };
+	/// editor.set_provenance(&info, key).unwrap();
+	/// editor.finish(key).unwrap();
+	///
+	/// let reader = paks::FileReader::open("game.pak", key).unwrap();
+	/// assert_eq!(reader.provenance(key).unwrap().unwrap().tool, "assetc");
+	/// ```
+	pub fn set_provenance(&mut self, info: &BuildInfo, key: &Key) -> io::Result<()> {
+		let mut edit_file = self.edit_file(Directory::ARCHIVE_META);
+		if edit_file.descriptor().is_dir() {
+			edit_file.set_content(1, 0);
+		}
+		let mut meta = edit_file.read_meta(key)?;
+		info.write_meta(&mut meta);
+		edit_file.set_meta(&meta, key)?;
+		Ok(())
+	}
+}
+
+impl MemoryEditor {
+	/// Stamps the PAK file with the provenance of its build.
+	///
+	/// See [`FileEditor::set_provenance`] for more information.
+	pub fn set_provenance(&mut self, info: &BuildInfo, key: &Key) -> Result<(), io::ErrorKind> {
+		let mut edit_file = self.edit_file(Directory::ARCHIVE_META);
+		if edit_file.descriptor().is_dir() {
+			edit_file.set_content(1, 0);
+		}
+		let mut meta = edit_file.read_meta(key)?;
+		info.write_meta(&mut meta);
+		edit_file.set_meta(&meta, key)?;
+		Ok(())
+	}
+}
+
+impl FileReader {
+	/// Decrypts the provenance of the PAK file, see [`FileEditor::set_provenance`].
+	///
+	/// Returns `None` if the PAK file is not stamped.
+	pub fn provenance(&self, key: &Key) -> io::Result<Option<BuildInfo>> {
+		match self.find_file(Directory::ARCHIVE_META) {
+			Some(desc) => Ok(BuildInfo::read_meta(&self.read_meta(desc, key)?)),
+			None => Ok(None),
+		}
+	}
+}
+
+impl MemoryReader {
+	/// Decrypts the provenance of the PAK file, see [`FileEditor::set_provenance`].
+	///
+	/// Returns `None` if the PAK file is not stamped.
+	pub fn provenance(&self, key: &Key) -> Result<Option<BuildInfo>, io::ErrorKind> {
+		match self.find_file(Directory::ARCHIVE_META) {
+			Some(desc) => Ok(BuildInfo::read_meta(&self.read_meta(desc, key)?)),
+			None => Ok(None),
+		}
+	}
+}
+
+#[test]
+fn test_provenance() {
+	let ref key = Key::default();
+	let info = BuildInfo {
+		tool: String::from("assetc"),
+		version: String::from("1.4.2"),
+		commit: String::from("9f0c2e1"),
+		timestamp: 1700000000,
+	};
+
+	let mut editor = MemoryEditor::new();
+	editor.create_file(b"data.bin", b"contents", key);
+	editor.set_provenance(&BuildInfo::default(), key).unwrap();
+	editor.set_provenance(&info, key).unwrap();
+	let (blocks, _) = editor.finish(key);
+
+	let reader = MemoryReader::from_blocks(blocks, key).unwrap();
+	assert_eq!(reader.provenance(key), Ok(Some(info)));
+
+	let reader = MemoryReader::from_blocks(MemoryEditor::new().finish(key).0, key).unwrap();
+	assert_eq!(reader.provenance(key), Ok(None));
+}
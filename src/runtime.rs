@@ -0,0 +1,452 @@
+/*!
+Minimal runtime loader for engines reading PAK files through an exported index.
+
+The directory of a PAK file is encrypted and its descriptors are walked to find a file by path.
+Engines loading assets by path at startup can skip decrypting and walking the directory by exporting an index with [`Directory::export_index`] at build time.
+
+The index is a header followed by a table of fixed size [`IndexEntry`]s sorted by the [hash](path_hash) of their path.
+Loading an [`Index`] reads the table as-is, looking up a file is a binary search and reading it decrypts its section directly.
+
+The index holds the sections of the files but not their paths nor their contents, the key is still required to read the files.
+The index is stored as plaintext authenticated by a MAC with the key of the PAK file, a tampered index fails to load.
+*/
+
+use std::{convert::TryFrom, fs, io, io::prelude::*, mem};
+use crate::*;
+
+/// Hashes a path for the lookups in an [`Index`].
+///
+/// The path is hashed as stored in the directory, hash with [`NamePolicy::path_hash`] to apply the name policy of the PAK file.
+///
+/// This is the 64-bit FNV-1a hash of the path.
+///
+/// # Examples
+///
+/// ```
+/// assert_eq!(paks::runtime::path_hash(b""), 0xcbf29ce484222325);
+/// assert_ne!(paks::runtime::path_hash(b"a/b"), paks::runtime::path_hash(b"b/a"));
+/// ```
+pub fn path_hash(path: &[u8]) -> u64 {
+	let mut hash = 0xcbf29ce484222325u64;
+	for &byte in path {
+		hash ^= byte as u64;
+		hash = hash.wrapping_mul(0x100000001b3);
+	}
+	hash
+}
+
+/// The index header.
+#[derive(Copy, Clone, Default, Debug, Eq, PartialEq, Hash)]
+#[repr(C)]
+pub struct IndexHeader {
+	/// Version info value, should be equal to [`VERSION`](Self::VERSION).
+	pub version: u32,
+	/// Size in bytes of the blocks of the PAK file, see [`InfoHeader::block_size`].
+	pub block_size: u32,
+	/// Number of entries following the header.
+	pub len: u32,
+	/// Byte offset of the PAK file in the underlying file, see [`FileReader::offset`].
+	pub offset: u32,
+	/// Nonce of the MAC, see [`Section::is_public`].
+	pub nonce: Block,
+	/// MAC of the fields above and the entries.
+	pub mac: Block,
+}
+
+impl IndexHeader {
+	/// Index format version number.
+	///
+	/// Like the PAK file format the index is endian sensitive.
+	pub const VERSION: u32 = u32::from_ne_bytes(*b"PIX1");
+
+	// The fields authenticated by the MAC
+	fn fields(&self) -> Block {
+		let mut fields = Block::default();
+		fields.as_bytes_mut().copy_from_slice(&self.as_bytes()[..BLOCK_SIZE]);
+		fields
+	}
+}
+
+// Computes the MAC of the index over the header fields and the entries as a public section
+fn index_blocks(header: &IndexHeader, entries: &[IndexEntry]) -> Vec<Block> {
+	let mut blocks = vec![Block::default(); 1 + entries.len() * (mem::size_of::<IndexEntry>() / BLOCK_SIZE)];
+	blocks[0] = header.fields();
+	blocks[1..].as_bytes_mut().copy_from_slice(entries.as_bytes());
+	blocks
+}
+
+unsafe impl dataview::Pod for IndexHeader {}
+
+/// The index entry of a file.
+#[derive(Copy, Clone, Default, Debug, Eq, PartialEq, Hash)]
+#[repr(C)]
+pub struct IndexEntry {
+	/// The [hash](path_hash) of the path of the file.
+	pub hash: u64,
+	/// The content type of the file, see [`Descriptor::content_type`].
+	pub content_type: u32,
+	/// The size in bytes of the file contents.
+	pub content_size: u32,
+	/// Byte offset of the file contents in its decrypted section, see [`Descriptor::content_offset`].
+	pub content_offset: u32,
+	/// Reserved, zero.
+	pub reserved: u32,
+	/// The section object of the file contents.
+	pub section: Section,
+}
+
+unsafe impl dataview::Pod for IndexEntry {}
+
+impl IndexEntry {
+	// Extracts the file contents from its decrypted section
+	fn content(&self, data: &[u8]) -> Vec<u8> {
+		let start = usize::min(data.len(), self.content_offset as usize);
+		let end = usize::min(data.len(), start + self.content_size as usize);
+		data[start..end].to_vec()
+	}
+}
+
+impl Directory {
+	/// Exports an index of the files for the [runtime](crate::runtime) loader.
+	///
+	/// Every file outside the [trash](Self::TRASH) is written to the index with the hash of its path, its content type and size and its section.
+	/// The hidden files under `.pak` and the [decoy files](Descriptor::is_decoy) are left out.
+	/// Links are exported once for every path, their entries share the section.
+	///
+	/// The index is authenticated with the key, see [`Index::read`].
+	/// The PAK file is expected at the start of its file, export with [`FileReader::export_index`] for embedded PAK files.
+	///
+	/// Returns the number of entries written.
+	///
+	/// # Errors
+	///
+	/// * [`io::ErrorKind::InvalidData`]: The paths of two files hash to the same value, rename one of them.
+	/// * [`io::Error`]: An error encountered writing the index.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// let ref key = paks::Key::default();
+	/// let reader = paks::FileReader::open("game.pak", key).unwrap();
+	/// let file = std::fs::File::create("game.pix").unwrap();
+	/// reader.export_index(std::io::BufWriter::new(file), key).unwrap();
+	///
+	/// // At runtime
+	/// let index = paks::runtime::Index::read(std::fs::File::open("game.pix").unwrap(), key).unwrap();
+	/// let pak = std::fs::File::open("game.pak").unwrap();
+	/// let entry = index.find(b"levels/1/map.bin").unwrap();
+	/// let data = index.read_data(entry, &pak, key).unwrap();
+	/// ```
+	pub fn export_index<W: Write>(&self, writer: W, key: &Key) -> io::Result<usize> {
+		self.export_index_at(writer, 0, key)
+	}
+	fn export_index_at<W: Write>(&self, mut writer: W, offset: u64, key: &Key) -> io::Result<usize> {
+		let offset = match u32::try_from(offset) {
+			Ok(offset) => offset,
+			Err(_) => Err(io::Error::new(io::ErrorKind::InvalidInput, "PAK file embedded too far into the file"))?,
+		};
+		// Stripped PAK files hide the paths of the hidden files as well
+		let hidden = [Directory::CHANGELOG, Directory::ARCHIVE_META].map(|path| self.name_policy().path_hash(path));
+		let mut entries: Vec<(Vec<u8>, IndexEntry)> = Vec::new();
+		for (path, desc) in self.query(&Filter::Files) {
+			if path.starts_with(Directory::TRASH) && path.get(Directory::TRASH.len()) == Some(&b'/') {
+				continue;
+			}
+			// The plaintext index must not reveal the decoys
+			if desc.is_decoy() || path.starts_with(b".pak/") {
+				continue;
+			}
+			// Stripped names are the path hashes
+			let hash = if self.name_policy().stripped { parse_hashed_name(&path) } else { Some(path_hash(&path)) };
+			let hash = match hash {
+				Some(hash) if !(self.name_policy().stripped && hidden.contains(&hash)) => hash,
+				_ => continue,
+			};
+			let entry = IndexEntry {
+				hash,
+				content_type: desc.content_type,
+				content_size: desc.content_size,
+				content_offset: desc.content_offset(),
+				reserved: 0,
+				section: desc.section,
+			};
+			entries.push((path, entry));
+		}
+		entries.sort_unstable_by_key(|(_, entry)| entry.hash);
+		if let Some(pair) = entries.windows(2).find(|pair| pair[0].1.hash == pair[1].1.hash) {
+			let msg = format!("path hash collision: {} and {}", String::from_utf8_lossy(&pair[0].0), String::from_utf8_lossy(&pair[1].0));
+			Err(io::Error::new(io::ErrorKind::InvalidData, msg))?;
+		}
+
+		let entries: Vec<IndexEntry> = entries.into_iter().map(|(_, entry)| entry).collect();
+		let len = u32::try_from(entries.len()).map_err(|_| io::ErrorKind::InvalidData)?;
+		let mut header = IndexHeader { version: IndexHeader::VERSION, block_size: self.block_size(), len, offset, ..IndexHeader::default() };
+		let mut section = Section { nonce: [Section::PUBLIC_NONCE, 0], ..Section::default() };
+		crypt::encrypt_section(&mut index_blocks(&header, &entries), &mut section, key);
+		header.nonce = section.nonce;
+		header.mac = section.mac;
+		writer.write_all(header.as_bytes())?;
+		writer.write_all(entries.as_bytes())?;
+		writer.flush()?;
+		Ok(entries.len())
+	}
+}
+
+impl FileReader {
+	/// Exports an index of the files for the [runtime](crate::runtime) loader.
+	///
+	/// The [offset](Self::offset) of an embedded PAK file is stored in the index, its files are read from the underlying file.
+	///
+	/// See [`Directory::export_index`] for more information.
+	///
+	/// # Errors
+	///
+	/// * [`io::ErrorKind::InvalidInput`]: The PAK file is embedded 4 GiB or more into the underlying file.
+	pub fn export_index<W: Write>(&self, writer: W, key: &Key) -> io::Result<usize> {
+		self.export_index_at(writer, self.offset(), key)
+	}
+}
+
+/// Index of the files of a PAK file, see [`Directory::export_index`].
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Index {
+	block_size: u32,
+	offset: u32,
+	entries: Vec<IndexEntry>,
+}
+
+impl Index {
+	/// Reads the index.
+	///
+	/// The entries are read as-is into memory and authenticated with the key of the PAK file.
+	///
+	/// # Errors
+	///
+	/// * [`io::ErrorKind::InvalidData`]: The index has the wrong version or block size, fails to authenticate or its entries are not sorted by hash.
+	/// * [`io::ErrorKind::UnexpectedEof`]: The index is shorter than its header claims.
+	/// * [`io::Error`]: An error encountered reading the index.
+	pub fn read<R: Read>(mut reader: R, key: &Key) -> io::Result<Index> {
+		let mut header = IndexHeader::default();
+		reader.read_exact(header.as_bytes_mut())?;
+		if header.version != IndexHeader::VERSION || !InfoHeader::BLOCK_SIZES.contains(&header.block_size) {
+			Err(io::Error::new(io::ErrorKind::InvalidData, "invalid index header"))?;
+		}
+		// The allocation is bounded by the input, not by the length in the header
+		let size = header.len as u64 * mem::size_of::<IndexEntry>() as u64;
+		let mut bytes = Vec::new();
+		reader.take(size).read_to_end(&mut bytes)?;
+		if bytes.len() as u64 != size {
+			Err(io::ErrorKind::UnexpectedEof)?;
+		}
+		let mut entries = vec![IndexEntry::default(); header.len as usize];
+		entries.as_bytes_mut().copy_from_slice(&bytes);
+		drop(bytes);
+
+		let section = Section { nonce: header.nonce, mac: header.mac, ..Section::default() };
+		if !section.is_public() || !crypt::verify_mac(&index_blocks(&header, &entries), &section, key) {
+			Err(io::Error::new(io::ErrorKind::InvalidData, "index fails to authenticate"))?;
+		}
+		if !entries.windows(2).all(|pair| pair[0].hash < pair[1].hash) {
+			Err(io::Error::new(io::ErrorKind::InvalidData, "index entries not sorted"))?;
+		}
+		Ok(Index { block_size: header.block_size, offset: header.offset, entries })
+	}
+
+	/// Reads the index from memory.
+	///
+	/// See [`read`](Self::read) for more information.
+	#[inline]
+	pub fn from_bytes(mut bytes: &[u8], key: &Key) -> io::Result<Index> {
+		Index::read(&mut bytes, key)
+	}
+
+	/// Returns the block size of the PAK file.
+	#[inline]
+	pub fn block_size(&self) -> u32 {
+		self.block_size
+	}
+
+	/// Returns the byte offset of the PAK file in the underlying file.
+	///
+	/// Zero unless the index was exported from an embedded PAK file, see [`FileReader::offset`].
+	#[inline]
+	pub fn offset(&self) -> u32 {
+		self.offset
+	}
+
+	/// Returns the entries in order of their hash.
+	#[inline]
+	pub fn entries(&self) -> &[IndexEntry] {
+		&self.entries
+	}
+
+	/// Finds the entry of the file at the given path.
+	#[inline]
+	pub fn find(&self, path: &[u8]) -> Option<&IndexEntry> {
+		self.find_hash(path_hash(path))
+	}
+
+	/// Finds the entry of the file with the given [path hash](path_hash).
+	pub fn find_hash(&self, hash: u64) -> Option<&IndexEntry> {
+		let i = self.entries.binary_search_by_key(&hash, |entry| entry.hash).ok()?;
+		Some(&self.entries[i])
+	}
+
+	/// Reads and decrypts the contents of the file from the PAK file.
+	///
+	/// The file is the underlying file of an embedded PAK file, its [offset](Self::offset) is added to the position of the section.
+	///
+	/// # Errors
+	///
+	/// * [`io::ErrorKind::InvalidData`]: The file contents fail to decrypt, the key is wrong or the index is stale.
+	/// * [`io::Error`]: An error encountered reading the PAK file.
+	pub fn read_data(&self, entry: &IndexEntry, file: &fs::File, key: &Key) -> io::Result<Vec<u8>> {
+		let scale = block_scale(self.block_size);
+		let mut blocks = vec![Block::default(); entry.section.size as usize * scale];
+		file_io::read_exact_at(file, self.offset as u64 + entry.section.offset as u64 * self.block_size as u64, blocks.as_bytes_mut())?;
+		if !crypt::decrypt_section(&mut blocks, &entry.section, key) {
+			Err(io::ErrorKind::InvalidData)?;
+		}
+		Ok(entry.content(blocks.as_bytes()))
+	}
+
+	/// Decrypts the contents of the file from the bytes of the PAK file, eg. a memory map.
+	///
+	/// The bytes are those of the underlying file of an embedded PAK file, see [`read_data`](Self::read_data).
+	///
+	/// # Errors
+	///
+	/// * [`io::ErrorKind::UnexpectedEof`]: The section extends past the end of the PAK file.
+	/// * [`io::ErrorKind::InvalidData`]: The file contents fail to decrypt, the key is wrong or the index is stale.
+	pub fn read_data_from(&self, entry: &IndexEntry, pak: &[u8], key: &Key) -> io::Result<Vec<u8>> {
+		let start = self.offset as usize + entry.section.offset as usize * self.block_size as usize;
+		let len = entry.section.size as usize * self.block_size as usize;
+		let bytes = match pak.get(start..start.saturating_add(len)) {
+			Some(bytes) => bytes,
+			None => Err(io::ErrorKind::UnexpectedEof)?,
+		};
+		let mut blocks = vec![Block::default(); len / BLOCK_SIZE];
+		blocks.as_bytes_mut().copy_from_slice(bytes);
+		if !crypt::decrypt_section(&mut blocks, &entry.section, key) {
+			Err(io::ErrorKind::InvalidData)?;
+		}
+		Ok(entry.content(blocks.as_bytes()))
+	}
+}
+
+#[test]
+fn test_export_index() {
+	let ref key = Key::default();
+
+	let mut editor = MemoryEditor::new();
+	editor.create_file(b"levels/map", b"map", key);
+	editor.create_file(b"small/a", b"aaaa", key);
+	editor.create_file(b"small/b", b"bbbbbb", key);
+	editor.create_file(b"trashed", b"trashed", key);
+	let map = *editor.find_file(b"levels/map").unwrap();
+	editor.create_link(b"link", &map);
+	editor.soft_remove(b"trashed");
+	editor.pack_files(16, key);
+	let (blocks, _) = editor.finish(key);
+	let reader = MemoryReader::from_blocks(blocks.clone(), key).unwrap();
+
+	let mut bytes = Vec::new();
+	assert_eq!(reader.export_index(&mut bytes, key).unwrap(), 4);
+	let index = Index::from_bytes(&bytes, key).unwrap();
+	assert_eq!(index.block_size(), reader.block_size());
+	assert_eq!(index.entries().len(), 4);
+	assert!(index.find(b"trashed").is_none());
+
+	let pak = as_bytes(&blocks);
+	for (path, data) in [(&b"levels/map"[..], &b"map"[..]), (b"link", b"map"), (b"small/a", b"aaaa"), (b"small/b", b"bbbbbb")] {
+		let entry = index.find(path).unwrap();
+		assert_eq!(index.read_data_from(entry, pak, key).unwrap(), data);
+	}
+
+	assert_eq!(Index::from_bytes(&bytes[1..], key).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+	// The index is authenticated with the key
+	assert_eq!(Index::from_bytes(&bytes, &[1, 2]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+	let mut tampered = bytes.clone();
+	*tampered.last_mut().unwrap() ^= 1;
+	assert_eq!(Index::from_bytes(&tampered, key).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+	// The length in the header is bounded by the input
+	let mut header = IndexHeader::default();
+	header.as_bytes_mut().copy_from_slice(&bytes[..mem::size_of::<IndexHeader>()]);
+	header.len = u32::MAX;
+	assert_eq!(Index::from_bytes(header.as_bytes(), key).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+
+	// Entries out of order are rejected even when authenticated
+	let mut entries = index.entries().to_vec();
+	entries.swap(0, 1);
+	let mut header = IndexHeader { version: IndexHeader::VERSION, block_size: index.block_size(), len: 4, ..IndexHeader::default() };
+	let mut section = Section { nonce: [Section::PUBLIC_NONCE, 0], ..Section::default() };
+	crypt::encrypt_section(&mut index_blocks(&header, &entries), &mut section, key);
+	header.nonce = section.nonce;
+	header.mac = section.mac;
+	let unsorted = [header.as_bytes(), entries.as_bytes()].concat();
+	assert_eq!(Index::from_bytes(&unsorted, key).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+	// Stripped names are exported as the path hashes
+	let mut editor = MemoryEditor::new();
+	editor.set_name_policy(NamePolicy { stripped: true, ..NamePolicy::default() });
+	editor.create_file(b"levels/map", b"map", key);
+	let (blocks, _) = editor.finish(key);
+	let reader = MemoryReader::from_blocks(blocks.clone(), key).unwrap();
+	let mut bytes = Vec::new();
+	assert_eq!(reader.export_index(&mut bytes, key).unwrap(), 1);
+	let index = Index::from_bytes(&bytes, key).unwrap();
+	assert_eq!(index.read_data_from(index.find(b"levels/map").unwrap(), as_bytes(&blocks), key).unwrap(), b"map");
+}
+
+#[test]
+fn test_export_index_embedded() {
+	let ref key = Key::default();
+
+	temp_file!("export_index_embedded");
+
+	let mut editor = MemoryEditor::new();
+	editor.create_file(b"levels/map", b"map", key);
+	let (blocks, _) = editor.finish(key);
+
+	// Append the PAK file to some unaligned host data
+	let host = b"host executable";
+	let mut bytes = host.to_vec();
+	bytes.extend_from_slice(as_bytes(&blocks));
+	std::fs::write("export_index_embedded", &bytes).unwrap();
+
+	let reader = FileReader::open_at_offset("export_index_embedded", host.len() as u64, key).unwrap();
+	let mut index = Vec::new();
+	assert_eq!(reader.export_index(&mut index, key).unwrap(), 1);
+	let index = Index::from_bytes(&index, key).unwrap();
+	assert_eq!(index.offset(), host.len() as u32);
+
+	let entry = index.find(b"levels/map").unwrap();
+	let file = fs::File::open("export_index_embedded").unwrap();
+	assert_eq!(index.read_data(entry, &file, key).unwrap(), b"map");
+	assert_eq!(index.read_data_from(entry, &bytes, key).unwrap(), b"map");
+}
+
+#[test]
+fn test_export_index_decoys() {
+	let ref key = Key::default();
+
+	for &stripped in &[false, true] {
+		let mut editor = MemoryEditor::new();
+		editor.set_name_policy(NamePolicy { stripped, ..NamePolicy::default() });
+		editor.set_obfuscation(Some(ObfuscationOptions { shuffle: true, decoys: 4, decoy_max_size: 1024 }));
+		editor.create_file(b"a", b"a", key);
+		editor.create_file(b"b", b"b", key);
+		editor.log_change("author", "message");
+		let (_, directory) = editor.finish(key);
+		assert_eq!(directory.query(&Filter::Files).filter(|(_, desc)| desc.is_decoy()).count(), 4);
+		assert!(directory.find_file(Directory::CHANGELOG).is_some());
+
+		let mut bytes = Vec::new();
+		assert_eq!(directory.export_index(&mut bytes, key).unwrap(), 2);
+		let index = Index::from_bytes(&bytes, key).unwrap();
+		assert!(index.find(b"a").is_some());
+		assert!(index.find(b"b").is_some());
+	}
+}
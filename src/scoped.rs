@@ -0,0 +1,198 @@
+use std::{io, ops};
+use crate::*;
+
+/// Editor scoped to a subtree of the PAK file.
+///
+/// All paths are relative to the root directory of the scope, descriptors outside of it cannot be found or modified.
+/// Independent steps of an asset pipeline can each be handed a scoped editor of their own subtree.
+///
+/// Created by [`MemoryEditor::chroot`] and [`FileEditor::chroot`].
+///
+/// # Examples
+///
+/// ```
+/// let ref key = paks::Key::default();
+/// let mut editor = paks::MemoryEditor::new();
+/// editor.create_file(b"ui/icon", b"icon", key);
+///
+/// let mut audio = editor.chroot(b"audio").unwrap();
+/// audio.create_file(b"music/theme", b"theme", key);
+/// assert!(audio.find_file(b"ui/icon").is_none());
+/// assert!(audio.remove(b"ui/icon").is_none());
+///
+/// assert!(editor.find_file(b"audio/music/theme").is_some());
+/// assert!(editor.find_file(b"ui/icon").is_some());
+/// ```
+pub struct SubEditor<'a, E> {
+	editor: &'a mut E,
+	root: Vec<u8>,
+}
+
+// Creates the root directory of the scope
+fn chroot<'a, E: ops::DerefMut<Target = Directory>>(editor: &'a mut E, path: &[u8]) -> Option<SubEditor<'a, E>> {
+	if path.is_empty() {
+		return None;
+	}
+	match editor.find_desc(path) {
+		Some(desc) if desc.is_file() => return None,
+		Some(_) => (),
+		None => {
+			editor.create_dir(path)?;
+		},
+	}
+	let mut root = path.to_vec();
+	root.push(b'/');
+	Some(SubEditor { editor, root })
+}
+
+impl MemoryEditor {
+	/// Returns an editor scoped to the directory at the given path.
+	///
+	/// The directory is created if it does not exist.
+	/// Returns `None` if the path is empty, a file exists at the path or the path is [sealed](Directory::seal).
+	pub fn chroot(&mut self, path: &[u8]) -> Option<SubEditor<'_, MemoryEditor>> {
+		chroot(self, path)
+	}
+}
+
+impl<S: Storage> FileEditor<S> {
+	/// Returns an editor scoped to the directory at the given path.
+	///
+	/// The directory is created if it does not exist.
+	/// Returns `None` if the path is empty, a file exists at the path or the path is [sealed](Directory::seal).
+	pub fn chroot(&mut self, path: &[u8]) -> Option<SubEditor<'_, FileEditor<S>>> {
+		chroot(self, path)
+	}
+}
+
+impl<'a, E: ops::DerefMut<Target = Directory>> SubEditor<'a, E> {
+	// Full path of the relative path, `None` for the root itself
+	fn path(&self, path: &[u8]) -> Option<Vec<u8>> {
+		if path.is_empty() {
+			return None;
+		}
+		let mut full = self.root.clone();
+		full.extend_from_slice(path);
+		Some(full)
+	}
+
+	// Descriptors outside the scope are only accepted if a file in the scope references the same section
+	fn in_scope(&self, desc: &Descriptor) -> bool {
+		match self.editor.get_children(self.root()) {
+			Some(children) => children.iter().any(|child| child.is_file() && child.section == desc.section),
+			None => false,
+		}
+	}
+
+	/// Returns the path of the root directory of the scope.
+	#[inline]
+	pub fn root(&self) -> &[u8] {
+		&self.root[..self.root.len() - 1]
+	}
+
+	/// Finds a descriptor by its path relative to the scope.
+	pub fn find_desc(&self, path: &[u8]) -> Option<&Descriptor> {
+		self.editor.find_desc(&self.path(path)?)
+	}
+
+	/// Finds a file descriptor by its path relative to the scope.
+	pub fn find_file(&self, path: &[u8]) -> Option<&Descriptor> {
+		self.editor.find_file(&self.path(path)?)
+	}
+
+	/// Gets the child descriptors of the directory at the given path relative to the scope.
+	///
+	/// An empty path returns the children of the root directory of the scope.
+	pub fn get_children(&self, path: &[u8]) -> Option<&[Descriptor]> {
+		match self.path(path) {
+			Some(path) => self.editor.get_children(&path),
+			None => self.editor.get_children(self.root()),
+		}
+	}
+
+	/// Creates a directory descriptor at the given path relative to the scope.
+	///
+	/// See [`Directory::create_dir`] for more information.
+	pub fn create_dir(&mut self, path: &[u8]) -> Option<DescriptorId> {
+		let path = self.path(path)?;
+		self.editor.create_dir(&path)
+	}
+
+	/// Creates a symbolic link from the path relative to the scope to the given file descriptor.
+	///
+	/// Returns `None` if the file descriptor does not belong to a file in the scope.
+	/// See [`Directory::create_link`] for more information.
+	pub fn create_link(&mut self, path: &[u8], file_desc: &Descriptor) -> Option<DescriptorId> {
+		if !self.in_scope(file_desc) {
+			return None;
+		}
+		let path = self.path(path)?;
+		self.editor.create_link(&path, file_desc)
+	}
+
+	/// Removes a descriptor at the given path relative to the scope.
+	///
+	/// The root directory of the scope cannot be removed.
+	/// See [`Directory::remove`] for more information.
+	pub fn remove(&mut self, path: &[u8]) -> Option<Descriptor> {
+		let path = self.path(path)?;
+		self.editor.remove(&path)
+	}
+
+	/// Moves a file descriptor within the scope.
+	///
+	/// See [`Directory::move_file`] for more information.
+	pub fn move_file(&mut self, src_path: &[u8], dest_path: &[u8]) -> bool {
+		match (self.path(src_path), self.path(dest_path)) {
+			(Some(src_path), Some(dest_path)) => self.editor.move_file(&src_path, &dest_path),
+			_ => false,
+		}
+	}
+}
+
+impl<'a> SubEditor<'a, MemoryEditor> {
+	/// Creates a file at the given path relative to the scope.
+	///
+	/// Returns `None` if the path is empty.
+	/// See [`MemoryEditor::create_file`] for more information.
+	pub fn create_file(&mut self, path: &[u8], data: &[u8], key: &Key) -> Option<&Descriptor> {
+		let path = self.path(path)?;
+		self.editor.create_file(&path, data, key)
+	}
+
+	/// Decrypts the contents of the given file descriptor.
+	///
+	/// Returns [`io::ErrorKind::InvalidInput`] if the file descriptor does not belong to a file in the scope.
+	/// See [`MemoryEditor::read_data`] for more information.
+	pub fn read_data(&self, desc: &Descriptor, key: &Key) -> Result<Vec<u8>, io::ErrorKind> {
+		if !self.in_scope(desc) {
+			return Err(io::ErrorKind::InvalidInput);
+		}
+		self.editor.read_data(desc, key)
+	}
+}
+
+impl<'a, S: Storage> SubEditor<'a, FileEditor<S>> {
+	/// Creates a file at the given path relative to the scope.
+	///
+	/// Returns [`io::ErrorKind::InvalidInput`] if the path is empty.
+	/// See [`FileEditor::create_file`] for more information.
+	pub fn create_file(&mut self, path: &[u8], data: &[u8], key: &Key) -> io::Result<&Descriptor> {
+		let path = match self.path(path) {
+			Some(path) => path,
+			None => Err(io::ErrorKind::InvalidInput)?,
+		};
+		self.editor.create_file(&path, data, key)
+	}
+
+	/// Decrypts the contents of the given file descriptor.
+	///
+	/// Returns [`io::ErrorKind::InvalidInput`] if the file descriptor does not belong to a file in the scope.
+	/// See [`FileEditor::read_data`] for more information.
+	pub fn read_data(&self, desc: &Descriptor, key: &Key) -> io::Result<Vec<u8>> {
+		if !self.in_scope(desc) {
+			Err(io::ErrorKind::InvalidInput)?;
+		}
+		self.editor.read_data(desc, key)
+	}
+}
@@ -0,0 +1,204 @@
+use std::{collections::HashMap, fs, io, io::prelude::*, path::Path, thread, time};
+use crate::*;
+
+/// Options for scrubbing a PAK file, see [`FileReader::scrub`].
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct ScrubOptions {
+	/// Maximum number of bytes verified per second.
+	///
+	/// Scrubbing sleeps between sections to stay below the rate, leaving IO bandwidth for other work.
+	/// Unlimited if `None` or zero, the default.
+	pub max_bytes_per_sec: Option<u64>,
+}
+
+/// A section failing verification found by [`FileReader::scrub`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Damaged {
+	/// Paths of the files referencing the section, in directory order.
+	pub paths: Vec<Vec<u8>>,
+	/// The damaged section.
+	pub section: Section,
+	/// Whether the section is a [meta section](Descriptor::meta_section) of the files.
+	pub meta: bool,
+	/// Why the section failed verification.
+	///
+	/// [`io::ErrorKind::InvalidData`] if its MAC is incorrect, otherwise the section is out of bounds of the PAK file.
+	pub error: io::ErrorKind,
+}
+
+// Verifies every section referenced by the files in order of their offset
+// Errors verifying a section other than damage abort scrubbing
+fn scrub<F, P>(directory: &Directory, options: &ScrubOptions, mut verify: F, mut progress: P) -> io::Result<Vec<Damaged>>
+	where F: FnMut(&Section) -> io::Result<()>, P: FnMut(u64, u64) -> bool
+{
+	// Collect the distinct sections with the paths of the files sharing them
+	let mut sections: Vec<Damaged> = Vec::new();
+	let mut index = HashMap::new();
+	for (path, desc) in directory.query(&Filter::Files) {
+		let meta = desc.meta_section().map(|section| (section, true));
+		for key in Some((desc.section, false)).into_iter().chain(meta) {
+			let i = *index.entry(key).or_insert_with(|| {
+				sections.push(Damaged { paths: Vec::new(), section: key.0, meta: key.1, error: io::ErrorKind::InvalidData });
+				sections.len() - 1
+			});
+			sections[i].paths.push(path.clone());
+		}
+	}
+	// Read the sections sequentially
+	sections.sort_by_key(|damaged| damaged.section.offset);
+
+	let block_size = directory.block_size() as u64;
+	let total = sections.iter().map(|damaged| damaged.section.size as u64 * block_size).sum();
+	let start = time::Instant::now();
+	let mut done = 0;
+	let mut damaged = Vec::new();
+	for mut entry in sections {
+		if !progress(done, total) {
+			return Ok(damaged);
+		}
+		let bytes = entry.section.size as u64 * block_size;
+		match verify(&entry.section) {
+			Ok(()) => (),
+			Err(err) if matches!(err.kind(), io::ErrorKind::InvalidData | io::ErrorKind::InvalidInput | io::ErrorKind::UnexpectedEof) => {
+				entry.error = err.kind();
+				damaged.push(entry);
+			},
+			Err(err) => return Err(err),
+		}
+		done += bytes;
+
+		// Throttle to the maximum rate
+		if let Some(rate) = options.max_bytes_per_sec.filter(|&rate| rate != 0) {
+			let due = time::Duration::from_secs_f64(done as f64 / rate as f64);
+			let elapsed = start.elapsed();
+			if due > elapsed {
+				thread::sleep(due - elapsed);
+			}
+		}
+	}
+	progress(done, total);
+	Ok(damaged)
+}
+
+impl FileReader {
+	/// Verifies the MAC of every file section and meta section.
+	///
+	/// Intended as a background maintenance task, eg. on game servers storing many PAK files.
+	/// The sections are read sequentially in the order they are stored, sections shared by several files are verified once.
+	/// The IO rate is limited by [`ScrubOptions::max_bytes_per_sec`].
+	///
+	/// The progress callback is called with the number of bytes verified and the total number of bytes before every section and once when done.
+	/// Return `false` from the callback to stop scrubbing early, the damage found so far is returned.
+	///
+	/// Returns the damaged sections with the paths of the files referencing them.
+	///
+	/// # Errors
+	///
+	/// * [`io::ErrorKind::Other`]: The reader was opened for metadata only.
+	/// * [`io::ErrorKind::OutOfMemory`]: A section is larger than [`ReaderOptions::max_section_bytes`].
+	/// * [`io::Error`]: An error encountered reading the underlying PAK file.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// let ref key = paks::Key::default();
+	/// let reader = paks::FileReader::open("game.pak", key).unwrap();
+	///
+	/// let options = paks::ScrubOptions { max_bytes_per_sec: Some(16 * 1024 * 1024) };
+	/// let damaged = reader.scrub(key, &options, |done, total| {
+	///     println!("scrubbed {}/{} bytes", done, total);
+	///     true
+	/// }).unwrap();
+	///
+	/// for damaged in &damaged {
+	///     println!("damaged: {}", String::from_utf8_lossy(&damaged.paths[0]));
+	/// }
+	/// ```
+	pub fn scrub<P: FnMut(u64, u64) -> bool>(&self, key: &Key, options: &ScrubOptions, progress: P) -> io::Result<Vec<Damaged>> {
+		scrub(self, options, |section| self.read_section(section, key).map(|_| ()), progress)
+	}
+}
+
+impl MemoryReader {
+	/// Verifies the MAC of every file section and meta section.
+	///
+	/// See [`FileReader::scrub`] for more information.
+	pub fn scrub<P: FnMut(u64, u64) -> bool>(&self, key: &Key, options: &ScrubOptions, progress: P) -> Vec<Damaged> {
+		// Reading memory fails only for damaged sections
+		scrub(self, options, |section| Ok(self.read_section(section, key).map(|_| ())?), progress).unwrap_or_default()
+	}
+}
+
+/// Repairs the damaged sections of a PAK file from a known-good copy.
+///
+/// The primary PAK file is [scrubbed](FileReader::scrub) and every damaged section is overwritten in place with the encrypted contents of the same section in the mirror.
+/// The mirror's section is found by the paths of the files referencing it and must have the same nonce and MAC, otherwise the section at the same offset is tried.
+/// Only the damaged sections are rewritten, the directory and the intact sections are left alone.
+///
+/// Every replacement is verified with the key before the primary PAK file is written to, if any damaged section cannot be repaired nothing is written.
+///
+/// Returns the repaired sections.
+///
+/// # Errors
+///
+/// * [`io::ErrorKind::InvalidData`]: The mirror has no intact copy of a damaged section.
+/// * [`io::Error`]: An error encountered opening, reading or writing the PAK files.
+///
+/// # Examples
+///
+/// ```no_run
+/// let ref key = paks::Key::default();
+/// let repaired = paks::repair_from("game.pak", "backup/game.pak", key).unwrap();
+/// println!("repaired {} sections", repaired.len());
+/// ```
+pub fn repair_from<P: ?Sized + AsRef<Path>, M: ?Sized + AsRef<Path>>(primary: &P, mirror: &M, key: &Key) -> io::Result<Vec<Damaged>> {
+	let reader = FileReader::open(primary, key)?;
+	let damaged = reader.scrub(key, &ScrubOptions::default(), |_, _| true)?;
+	if damaged.is_empty() {
+		return Ok(damaged);
+	}
+	let mirror = FileReader::open(mirror, key)?;
+
+	// Read and verify the replacements before writing anything
+	let mut replacements = Vec::new();
+	for entry in &damaged {
+		let blocks = repair_section(&mirror, entry, key).ok_or_else(|| {
+			let path = String::from_utf8_lossy(&entry.paths[0]);
+			io::Error::new(io::ErrorKind::InvalidData, format!("mirror has no intact copy of {}", path))
+		})?;
+		replacements.push(blocks);
+	}
+
+	let block_size = reader.block_size() as u64;
+	let mut file = fs::OpenOptions::new().write(true).open(primary)?;
+	for (entry, blocks) in damaged.iter().zip(&replacements) {
+		file.seek(io::SeekFrom::Start(reader.offset() + entry.section.offset as u64 * block_size))?;
+		file.write_all(blocks.as_bytes())?;
+	}
+	file.sync_all()?;
+	Ok(damaged)
+}
+
+// Returns the verified encrypted contents of the damaged section in the mirror
+fn repair_section(mirror: &FileReader, entry: &Damaged, key: &Key) -> Option<Vec<Block>> {
+	// Prefer the mirror's section of the same files in case the layout differs
+	let found = entry.paths.iter()
+		.filter_map(|path| mirror.find_file(path))
+		.filter_map(|desc| if entry.meta { desc.meta_section() } else { Some(desc.section) })
+		.find(|section| section.size == entry.section.size && section.nonce == entry.section.nonce && section.mac == entry.section.mac);
+	let offsets = found.map(|section| section.offset).into_iter().chain(Some(entry.section.offset));
+
+	let block_size = mirror.block_size();
+	for offset in offsets {
+		let section = Section { offset, ..entry.section };
+		let mut blocks = vec![Block::default(); section.size as usize * block_scale(block_size)];
+		if mirror.copy_section_raw(&section, blocks.as_bytes_mut()).is_err() {
+			continue;
+		}
+		let mut plain = blocks.clone();
+		if crypt::decrypt_section(&mut plain, &section, key) {
+			return Some(blocks);
+		}
+	}
+	None
+}
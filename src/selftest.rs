@@ -0,0 +1,127 @@
+/*!
+Known-answer tests for the cryptographic primitives.
+
+The test vectors are public for third-party implementations of the PAK file format.
+*/
+
+use std::fmt;
+use crate::*;
+
+/// Test vector for the SPECK128/128 block cipher.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct SpeckVector {
+	pub key: Key,
+	pub plaintext: Block,
+	pub ciphertext: Block,
+}
+
+/// Test vector for the authenticated encryption of a section.
+///
+/// The section is encrypted with SPECK128/128 in CTR mode and authenticated with CBC-MAC over the ciphertext.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct SectionVector {
+	pub key: Key,
+	pub nonce: Block,
+	pub plaintext: &'static [Block],
+	pub ciphertext: &'static [Block],
+	pub mac: Block,
+}
+
+/// SPECK128/128 test vectors from the SIMON and SPECK implementation guide.
+pub const SPECK_VECTORS: &[SpeckVector] = &[
+	SpeckVector {
+		key: [0x0f0e0d0c0b0a0908, 0x0706050403020100],
+		plaintext: [0x6c61766975716520, 0x7469206564616d20],
+		ciphertext: [0xa65d985179783265, 0x7860fedf5c570d18],
+	},
+];
+
+/// Section encryption test vectors.
+///
+/// The first vector encrypts zeroes, its ciphertext is the CTR keystream.
+pub const SECTION_VECTORS: &[SectionVector] = &[
+	SectionVector {
+		key: [0x0706050403020100, 0x0f0e0d0c0b0a0908],
+		nonce: [0x0123456789abcdef, 0xfedcba9876543210],
+		plaintext: &[[0, 0], [0, 0], [0, 0], [0, 0]],
+		ciphertext: &[[0x8283ca8a0ac57a0c, 0x80d4d7d84e914d16], [0xef70555528c64b1a, 0xec753f651afd66f1], [0x1bb21c8c97c6503f, 0x57167cb3671cfe11], [0x7f876d87019f39ad, 0x7236fa604d67b032]],
+		mac: [0x334bc3600a95dfce, 0x35686ef51dd9e7aa],
+	},
+	SectionVector {
+		key: [0x0706050403020100, 0x0f0e0d0c0b0a0908],
+		nonce: [0x0123456789abcdef, 0xfedcba9876543210],
+		plaintext: &[[0x204b415020656854, 0x726f6620656c6966], [0x612073692074616d, 0x772d746867696c20], [0x6e65207468676965, 0x2064657470797263], [0x2e65766968637261, 0x0000000000000000]],
+		ciphertext: &[[0xa2c88bda2aa01258, 0xf2bbb1f82bfd2470], [0x8e50263c08b22a77, 0x9b584b0d7d940ad1], [0x75d73cf8ffa1395a, 0x777219c717658c72], [0x51e21bee69fc4bcc, 0x7236fa604d67b032]],
+		mac: [0x77d4a90c7e82c0e4, 0xb10aede652e0b6b0],
+	},
+];
+
+/// Self-test failures.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SelfTestError {
+	/// The block cipher does not match its test vectors.
+	Speck,
+	/// The CTR encryption does not match its test vectors.
+	Keystream,
+	/// The CBC-MAC does not match its test vectors or failed to detect tampering.
+	Mac,
+}
+
+impl fmt::Display for SelfTestError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(match self {
+			SelfTestError::Speck => "SPECK128/128 known-answer test failed",
+			SelfTestError::Keystream => "CTR keystream known-answer test failed",
+			SelfTestError::Mac => "CBC-MAC known-answer test failed",
+		})
+	}
+}
+
+impl std::error::Error for SelfTestError {}
+
+/// Runs the known-answer tests for the cryptographic primitives.
+///
+/// Checks the SPECK block cipher, the CTR keystream and the CBC-MAC construction against [`SPECK_VECTORS`] and [`SECTION_VECTORS`].
+/// Useful at startup in environments requiring self-tests of the cryptography.
+///
+/// ```
+/// paks::selftest().unwrap();
+/// ```
+pub fn selftest() -> Result<(), SelfTestError> {
+	for vector in SPECK_VECTORS {
+		let rk = cipher::expand(vector.key);
+		if cipher::encrypt(vector.plaintext, &rk) != vector.ciphertext || cipher::decrypt(vector.ciphertext, &rk) != vector.plaintext {
+			return Err(SelfTestError::Speck);
+		}
+	}
+
+	for vector in SECTION_VECTORS {
+		// Encrypt the plaintext
+		let mut blocks = vector.plaintext.to_vec();
+		let mut section = Section { offset: 0, size: blocks.len() as u32, nonce: vector.nonce, mac: Block::default() };
+		crypt::encrypt_section_with_nonce(&mut blocks, &mut section, &vector.key);
+		if blocks != vector.ciphertext {
+			return Err(SelfTestError::Keystream);
+		}
+		if section.mac != vector.mac {
+			return Err(SelfTestError::Mac);
+		}
+
+		// Decrypt and authenticate the ciphertext
+		if !crypt::decrypt_section(&mut blocks, &section, &vector.key) {
+			return Err(SelfTestError::Mac);
+		}
+		if blocks != vector.plaintext {
+			return Err(SelfTestError::Keystream);
+		}
+
+		// Tampering with the ciphertext must be detected
+		let mut blocks = vector.ciphertext.to_vec();
+		blocks[0][0] ^= 1;
+		if crypt::decrypt_section(&mut blocks, &section, &vector.key) {
+			return Err(SelfTestError::Mac);
+		}
+	}
+
+	Ok(())
+}
@@ -0,0 +1,134 @@
+/*!
+Self-extracting executables.
+
+A self-extracting executable is an executable stub with a PAK file appended to it.
+The stub finds the PAK file embedded in itself and extracts its files, see the `sfx_stub` example.
+
+```no_run
+# // Don't actually write files while running tests...
+// Bundle the stub executable and the PAK file
+paks::sfx::build("sfx_stub.exe", "assets.pak", "assets.exe").unwrap();
+
+// Later, inside the stub executable
+let ref key = paks::Key::default();
+paks::sfx::extract("assets", key).unwrap();
+```
+*/
+
+use std::{env, fs, io, path::{Component, Path, PathBuf}, thread};
+use crate::*;
+
+/// Builds a self-extracting executable.
+///
+/// Copies the executable stub to the output and appends the PAK file followed by its [locator](write_locator).
+/// The output keeps the permissions of the stub, on unix it remains executable.
+///
+/// The PAK file is copied as-is, it remains encrypted with its key.
+pub fn build<P: ?Sized + AsRef<Path>, Q: ?Sized + AsRef<Path>, R: ?Sized + AsRef<Path>>(exe_stub: &P, pak: &Q, output: &R) -> io::Result<()> {
+	build_impl(exe_stub.as_ref(), pak.as_ref(), output.as_ref())
+}
+
+#[inline(never)]
+fn build_impl(exe_stub: &Path, pak: &Path, output: &Path) -> io::Result<()> {
+	// Copying the stub preserves its permissions
+	let offset = fs::copy(exe_stub, output)?;
+
+	let mut file = fs::OpenOptions::new().append(true).open(output)?;
+	io::copy(&mut fs::File::open(pak)?, &mut file)?;
+	write_locator(&mut file, offset)?;
+	file.sync_all()
+}
+
+/// Extracts the PAK file embedded in the current executable to the destination directory.
+///
+/// See [`extract_from`] for more information.
+pub fn extract<P: ?Sized + AsRef<Path>>(dest: &P, key: &Key) -> io::Result<usize> {
+	extract_impl(&env::current_exe()?, dest.as_ref(), key)
+}
+
+/// Extracts the PAK file embedded in the executable to the destination directory.
+///
+/// Every file is written to its path in the PAK file relative to the destination directory, existing files are overwritten.
+/// Returns the number of files extracted.
+///
+/// # Errors
+///
+/// * [`io::ErrorKind::NotFound`]: The executable does not contain an embedded PAK file.
+/// * [`io::ErrorKind::InvalidData`]: The key is incorrect, a file is corrupted or a path escapes the destination directory.
+/// * [`io::Error`]: An error encountered reading the executable or writing the files.
+pub fn extract_from<P: ?Sized + AsRef<Path>, Q: ?Sized + AsRef<Path>>(exe: &P, dest: &Q, key: &Key) -> io::Result<usize> {
+	extract_impl(exe.as_ref(), dest.as_ref(), key)
+}
+
+#[inline(never)]
+fn extract_impl(exe: &Path, dest: &Path, key: &Key) -> io::Result<usize> {
+	let offset = match find_embedded(exe)? {
+		Some(offset) => offset,
+		None => Err(io::Error::new(io::ErrorKind::NotFound, "no embedded PAK file"))?,
+	};
+
+	let reader = FileReader::open_at_offset(exe, offset, key)?;
+	let threads = thread::available_parallelism().map_or(1, usize::from);
+	match reader.extract_all(b"", dest, threads, key) {
+		Ok(count) => Ok(count),
+		// Report the first file which could not be extracted
+		Err(mut errors) => Err(errors.swap_remove(0).error),
+	}
+}
+
+// Maps the path in the PAK file to a path in the destination directory
+// Paths which would escape the destination directory are rejected
+// Every component must be a plain name on this platform, eg. `C:` is a drive prefix on Windows
+pub(crate) fn local_path(dest: &Path, path: &[u8]) -> io::Result<PathBuf> {
+	let mut local = dest.to_path_buf();
+	for component in path.split(|&chr| chr == b'/' || chr == b'\\') {
+		let name = String::from_utf8_lossy(component);
+		let mut components = Path::new(name.as_ref()).components();
+		match (components.next(), components.next()) {
+			(Some(Component::Normal(_)), None) => local.push(name.as_ref()),
+			_ => Err(io::Error::new(io::ErrorKind::InvalidData, format!("invalid path: {}", String::from_utf8_lossy(path))))?,
+		}
+	}
+	Ok(local)
+}
+
+#[test]
+fn test_build_extract() {
+	let ref key = [13, 42];
+	let dir = env::temp_dir().join("paks_sfx_test");
+	let _ = fs::remove_dir_all(&dir);
+	fs::create_dir_all(&dir).unwrap();
+
+	let mut edit = MemoryEditor::new();
+	edit.create_file(b"a", b"Hello", key);
+	edit.create_file(b"sub/b", b"World", key);
+	let (blocks, _) = edit.finish(key);
+
+	fs::write(dir.join("stub"), b"#!stub").unwrap();
+	fs::write(dir.join("pak"), as_bytes(&blocks)).unwrap();
+	build(&dir.join("stub"), &dir.join("pak"), &dir.join("sfx")).unwrap();
+
+	assert_eq!(extract_from(&dir.join("sfx"), &dir.join("out"), key).unwrap(), 2);
+	assert_eq!(fs::read(dir.join("out/a")).unwrap(), b"Hello");
+	assert_eq!(fs::read(dir.join("out/sub/b")).unwrap(), b"World");
+
+	assert_eq!(extract_from(&dir.join("stub"), &dir.join("out"), key).unwrap_err().kind(), io::ErrorKind::NotFound);
+	assert_eq!(extract_from(&dir.join("sfx"), &dir.join("out"), &[1, 2]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+	let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_local_path() {
+	let dest = Path::new("out");
+	assert_eq!(local_path(dest, b"sub/a").unwrap(), dest.join("sub").join("a"));
+	for path in [&b""[..], b"/a", b"a//b", b"./a", b"a/../b", b"..\\a"] {
+		assert_eq!(local_path(dest, path).unwrap_err().kind(), io::ErrorKind::InvalidData);
+	}
+	// Drive prefixes replace the destination on Windows
+	if cfg!(windows) {
+		for path in [&b"C:/a"[..], b"C:a", b"a/C:"] {
+			assert_eq!(local_path(dest, path).unwrap_err().kind(), io::ErrorKind::InvalidData);
+		}
+	}
+}
@@ -0,0 +1,258 @@
+use std::{convert::TryFrom, fs, io, io::prelude::*, ops};
+use crate::*;
+
+/// Storage of the blocks of a PAK file, see [`FileEditor::new_in`].
+///
+/// Offsets and lengths are measured in [`Block`]s of [`BLOCK_SIZE`] bytes.
+///
+/// Implemented by files, memory and [fixed-size regions](Region) of other storage.
+pub trait Storage {
+	/// Returns the number of blocks the storage can hold, `None` if it grows on demand.
+	fn capacity(&self) -> Option<u64>;
+
+	/// Returns the number of blocks currently in the storage.
+	fn size(&self) -> io::Result<u64>;
+
+	/// Reads the blocks at the offset.
+	///
+	/// Returns [`io::ErrorKind::UnexpectedEof`] if the blocks extend past the end of the storage.
+	fn read_blocks(&self, offset: u64, blocks: &mut [Block]) -> io::Result<()>;
+
+	/// Writes the blocks at the offset, growing the storage if needed.
+	///
+	/// Returns [`io::ErrorKind::WriteZero`] if the blocks extend past the capacity of the storage.
+	fn write_blocks(&mut self, offset: u64, blocks: &[Block]) -> io::Result<()>;
+
+	/// Flushes the written blocks to the underlying storage.
+	fn flush(&mut self) -> io::Result<()>;
+
+	/// Trims the storage to the given number of blocks.
+	///
+	/// Storage which cannot shrink ignores this.
+	#[inline]
+	fn truncate(&mut self, _len: u64) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+impl Storage for fs::File {
+	#[inline]
+	fn capacity(&self) -> Option<u64> {
+		None
+	}
+	#[inline]
+	fn size(&self) -> io::Result<u64> {
+		Ok(self.metadata()?.len() / BLOCK_SIZE as u64)
+	}
+	fn read_blocks(&self, offset: u64, blocks: &mut [Block]) -> io::Result<()> {
+		file_io::read_exact_at(self, offset * BLOCK_SIZE as u64, blocks.as_bytes_mut())
+	}
+	fn write_blocks(&mut self, offset: u64, blocks: &[Block]) -> io::Result<()> {
+		self.seek(io::SeekFrom::Start(offset * BLOCK_SIZE as u64))?;
+		self.write_all(blocks.as_bytes())
+	}
+	#[inline]
+	fn flush(&mut self) -> io::Result<()> {
+		self.sync_data()
+	}
+	#[inline]
+	fn truncate(&mut self, len: u64) -> io::Result<()> {
+		self.set_len(len * BLOCK_SIZE as u64)
+	}
+}
+
+impl Storage for Vec<Block> {
+	#[inline]
+	fn capacity(&self) -> Option<u64> {
+		None
+	}
+	#[inline]
+	fn size(&self) -> io::Result<u64> {
+		Ok(self.len() as u64)
+	}
+	#[inline]
+	fn read_blocks(&self, offset: u64, blocks: &mut [Block]) -> io::Result<()> {
+		self.as_slice().read_blocks(offset, blocks)
+	}
+	fn write_blocks(&mut self, offset: u64, blocks: &[Block]) -> io::Result<()> {
+		let end = match usize::try_from(offset).ok().and_then(|offset| offset.checked_add(blocks.len())) {
+			Some(end) => end,
+			None => Err(io::ErrorKind::WriteZero)?,
+		};
+		if self.len() < end {
+			self.resize(end, Block::default());
+		}
+		self[end - blocks.len()..end].copy_from_slice(blocks);
+		Ok(())
+	}
+	#[inline]
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+	#[inline]
+	fn truncate(&mut self, len: u64) -> io::Result<()> {
+		Vec::truncate(self, len as usize);
+		Ok(())
+	}
+}
+
+impl Storage for [Block] {
+	#[inline]
+	fn capacity(&self) -> Option<u64> {
+		Some(self.len() as u64)
+	}
+	#[inline]
+	fn size(&self) -> io::Result<u64> {
+		Ok(self.len() as u64)
+	}
+	fn read_blocks(&self, offset: u64, blocks: &mut [Block]) -> io::Result<()> {
+		match slice_range(offset, blocks.len()).and_then(|range| self.get(range)) {
+			Some(src) => blocks.copy_from_slice(src),
+			None => Err(io::ErrorKind::UnexpectedEof)?,
+		}
+		Ok(())
+	}
+	fn write_blocks(&mut self, offset: u64, blocks: &[Block]) -> io::Result<()> {
+		match slice_range(offset, blocks.len()).and_then(|range| self.get_mut(range)) {
+			Some(dest) => dest.copy_from_slice(blocks),
+			None => Err(io::ErrorKind::WriteZero)?,
+		}
+		Ok(())
+	}
+	#[inline]
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+impl<S: ?Sized + Storage> Storage for &mut S {
+	#[inline]
+	fn capacity(&self) -> Option<u64> {
+		(**self).capacity()
+	}
+	#[inline]
+	fn size(&self) -> io::Result<u64> {
+		(**self).size()
+	}
+	#[inline]
+	fn read_blocks(&self, offset: u64, blocks: &mut [Block]) -> io::Result<()> {
+		(**self).read_blocks(offset, blocks)
+	}
+	#[inline]
+	fn write_blocks(&mut self, offset: u64, blocks: &[Block]) -> io::Result<()> {
+		(**self).write_blocks(offset, blocks)
+	}
+	#[inline]
+	fn flush(&mut self) -> io::Result<()> {
+		(**self).flush()
+	}
+	#[inline]
+	fn truncate(&mut self, len: u64) -> io::Result<()> {
+		(**self).truncate(len)
+	}
+}
+
+// Range of the blocks at the offset in a slice
+fn slice_range(offset: u64, len: usize) -> Option<ops::Range<usize>> {
+	let start = usize::try_from(offset).ok()?;
+	Some(start..start.checked_add(len)?)
+}
+
+/// Fixed-size region of another storage.
+///
+/// The region starts at a block offset in the storage and cannot grow past its length,
+/// for example a console partition or a slice of a bigger file.
+#[derive(Clone, Debug)]
+pub struct Region<S> {
+	storage: S,
+	offset: u64,
+	len: u64,
+}
+
+impl<S: Storage> Region<S> {
+	/// Creates a region of `len` blocks starting at the block offset in the storage.
+	#[inline]
+	pub fn new(storage: S, offset: u64, len: u64) -> Region<S> {
+		Region { storage, offset, len }
+	}
+
+	/// Returns the underlying storage.
+	#[inline]
+	pub fn into_inner(self) -> S {
+		self.storage
+	}
+
+	fn check(&self, offset: u64, len: usize) -> Option<u64> {
+		let end = offset.checked_add(len as u64)?;
+		if end > self.len {
+			return None;
+		}
+		self.offset.checked_add(offset)
+	}
+}
+
+impl<S: Storage> Storage for Region<S> {
+	#[inline]
+	fn capacity(&self) -> Option<u64> {
+		Some(self.len)
+	}
+	#[inline]
+	fn size(&self) -> io::Result<u64> {
+		Ok(self.len)
+	}
+	fn read_blocks(&self, offset: u64, blocks: &mut [Block]) -> io::Result<()> {
+		match self.check(offset, blocks.len()) {
+			Some(offset) => self.storage.read_blocks(offset, blocks),
+			None => Err(io::ErrorKind::UnexpectedEof)?,
+		}
+	}
+	fn write_blocks(&mut self, offset: u64, blocks: &[Block]) -> io::Result<()> {
+		match self.check(offset, blocks.len()) {
+			Some(offset) => self.storage.write_blocks(offset, blocks),
+			None => Err(io::ErrorKind::WriteZero)?,
+		}
+	}
+	#[inline]
+	fn flush(&mut self) -> io::Result<()> {
+		self.storage.flush()
+	}
+}
+
+#[test]
+fn test_storage_editor() {
+	let ref key = [5, 6];
+
+	// Memory storage grows on demand and reopens
+	let mut blocks = Vec::new();
+	let mut edit = FileEditor::new_in(&mut blocks);
+	edit.create_file(b"a", b"Hello", key).unwrap();
+	edit.finish(key).unwrap();
+	let mut edit = FileEditor::open_in(&mut blocks, key).unwrap();
+	edit.create_file(b"b", b"World", key).unwrap();
+	let a = *edit.find_file(b"a").unwrap();
+	assert_eq!(edit.read_data(&a, key).unwrap(), b"Hello");
+	edit.finish(key).unwrap();
+	let reader = MemoryReader::from_blocks(blocks, key).unwrap();
+	assert_eq!(reader.read_data(reader.find_file(b"b").unwrap(), key).unwrap(), b"World");
+
+	// The region of a bigger storage cannot grow
+	let mut disk = vec![Block::default(); 64];
+	let mut edit = FileEditor::new_in(Region::new(&mut disk, 8, 32));
+	edit.create_file(b"small", b"small", key).unwrap();
+	let high_mark = edit.high_mark();
+	assert_eq!(edit.create_file(b"big", &[0; 32 * BLOCK_SIZE], key).unwrap_err().kind(), io::ErrorKind::OutOfMemory);
+	assert_eq!(edit.high_mark(), high_mark);
+	assert!(edit.find(b"big").is_none());
+	edit.finish(key).unwrap();
+	assert!(disk[..8].iter().all(|block| *block == Block::default()));
+
+	let mut edit = FileEditor::open_in(Region::new(&mut disk[..], 8, 32), key).unwrap();
+	let small = *edit.find_file(b"small").unwrap();
+	assert_eq!(edit.read_data(&small, key).unwrap(), b"small");
+	// The directory must fit the region as well
+	let fill = vec![0; (32 - edit.high_mark() as usize) * BLOCK_SIZE];
+	edit.create_file(b"fill", &fill, key).unwrap();
+	assert_eq!(edit.finish(key).unwrap_err().kind(), io::ErrorKind::WriteZero);
+	let reader = MemoryReader::from_blocks(disk[8..40].to_vec(), key).unwrap();
+	assert!(reader.find_file(b"fill").is_none());
+}
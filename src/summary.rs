@@ -0,0 +1,54 @@
+/*!
+Summary of readers and editors for their `Debug` and `Display` implementations.
+
+Keys are never stored in readers and editors, only public information about the PAK file is shown.
+*/
+
+use std::fmt;
+use crate::*;
+
+pub(crate) struct Summary<'a> {
+	pub name: &'static str,
+	pub info: Option<&'a InfoHeader>,
+	pub directory: &'a Directory,
+	pub high_mark: u32,
+}
+
+impl<'a> Summary<'a> {
+	fn counts(&self) -> (usize, usize) {
+		let files = self.directory.as_ref().iter().filter(|desc| desc.is_file()).count();
+		(files, self.directory.len() - files)
+	}
+}
+
+impl<'a> fmt::Debug for Summary<'a> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let (files, dirs) = self.counts();
+		let mut s = f.debug_struct(self.name);
+		if let Some(info) = self.info {
+			s.field("info", info);
+		}
+		s.field("entries", &self.directory.len())
+			.field("files", &files)
+			.field("directories", &dirs)
+			.field("high_mark", &self.high_mark)
+			.field("garbage", &self.directory.garbage(self.high_mark))
+			.finish()
+	}
+}
+
+impl<'a> fmt::Display for Summary<'a> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let (files, dirs) = self.counts();
+		writeln!(f, "{}", self.name)?;
+		if let Some(info) = self.info {
+			let version = info.version.to_ne_bytes();
+			writeln!(f, "  version:   {}", String::from_utf8_lossy(&version))?;
+			writeln!(f, "  directory: offset={} size={}", info.directory.offset, info.directory.size)?;
+		}
+		writeln!(f, "  entries:   {} ({} files, {} directories)", self.directory.len(), files, dirs)?;
+		writeln!(f, "  high mark: {} blocks ({} bytes)", self.high_mark, self.high_mark as u64 * self.directory.block_size as u64)?;
+		let garbage = self.directory.garbage(self.high_mark);
+		writeln!(f, "  garbage:   {} blocks ({} bytes)", garbage, garbage as u64 * self.directory.block_size as u64)
+	}
+}
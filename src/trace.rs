@@ -0,0 +1,50 @@
+/*!
+Optional instrumentation with the [tracing](https://docs.rs/tracing) crate.
+
+Enable the `tracing` feature to emit spans around opening, reading, encrypting, decrypting, finishing and garbage collecting PAK files.
+When the instrumented operation succeeds an event is emitted inside its span with the number of bytes processed and the elapsed time.
+
+Without the feature the instrumentation compiles to nothing.
+*/
+
+/// Creates a timed span at the given level, enters it and returns a [`Span`] guard.
+macro_rules! span {
+	($level:ident, $name:literal $($fields:tt)*) => {{
+		#[cfg(feature = "tracing")]
+		let span = crate::trace::Span::new(tracing::span!(tracing::Level::$level, $name $($fields)*), tracing::Level::$level);
+		#[cfg(not(feature = "tracing"))]
+		let span = crate::trace::Span {};
+		span
+	}};
+}
+
+/// Entered span guard.
+pub(crate) struct Span {
+	#[cfg(feature = "tracing")]
+	inner: (tracing::span::EnteredSpan, tracing::Level, std::time::Instant),
+}
+
+impl Span {
+	#[cfg(feature = "tracing")]
+	pub(crate) fn new(span: tracing::Span, level: tracing::Level) -> Span {
+		Span { inner: (span.entered(), level, std::time::Instant::now()) }
+	}
+
+	/// Finishes the span successfully with the number of bytes processed.
+	#[inline(always)]
+	pub(crate) fn finish(self, bytes: u64) {
+		#[cfg(feature = "tracing")]
+		{
+			let (_span, level, start) = self.inner;
+			let elapsed_us = start.elapsed().as_micros() as u64;
+			if level == tracing::Level::TRACE {
+				tracing::trace!(bytes, elapsed_us, "finished");
+			}
+			else {
+				tracing::debug!(bytes, elapsed_us, "finished");
+			}
+		}
+		#[cfg(not(feature = "tracing"))]
+		let _ = bytes;
+	}
+}
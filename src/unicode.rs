@@ -0,0 +1,33 @@
+// Canonical composition of names, see `NamePolicy::nfc`
+
+use unicode_normalization::{is_nfc_quick, IsNormalized, UnicodeNormalization};
+
+// Returns true if the string is known to be in NFC already
+pub(crate) fn is_composed(s: &str) -> bool {
+	is_nfc_quick(s.chars()) == IsNormalized::Yes
+}
+
+// Normalizes the string to NFC
+// The string is fully decomposed, its marks canonically reordered and composed again
+pub(crate) fn compose(s: &str) -> String {
+	s.nfc().collect()
+}
+
+#[test]
+fn test_compose() {
+	assert_eq!(compose("cafe\u{301}"), "caf\u{e9}");
+	assert_eq!(compose("caf\u{e9}"), "caf\u{e9}");
+	// Multiple marks compose in canonical order
+	assert_eq!(compose("e\u{323}\u{302}"), "\u{1ec7}");
+	assert_eq!(compose("A\u{30a}ngstr\u{f6}m"), "\u{c5}ngstr\u{f6}m");
+	// Hangul syllables
+	assert_eq!(compose("\u{1100}\u{1161}\u{11a8}"), "\u{ac01}");
+	// Marks without composition are left alone
+	assert_eq!(compose("x\u{301}\u{301}"), "x\u{301}\u{301}");
+	// Precomposed characters are decomposed and reordered before composing
+	assert_eq!(compose("\u{e9}\u{323}"), compose("e\u{323}\u{301}"));
+	assert_eq!(compose("\u{e9}\u{323}"), "\u{1eb9}\u{301}");
+	assert_eq!(compose("a\u{301}\u{328}"), compose("a\u{328}\u{301}"));
+	assert_eq!(compose("a\u{328}\u{301}"), "\u{105}\u{301}");
+	assert!(is_composed("caf\u{e9}") && !is_composed("cafe\u{301}"));
+}